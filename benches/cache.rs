@@ -0,0 +1,112 @@
+//! Benchmarks of the cache backends, run with `cargo bench --features bench`
+//!
+//! Each backend is measured getting, storing, and choosing a random image among the fixture images,
+//! so regressions in the backends and their locks show up as slower operations.
+
+use std::{hint::black_box, path::PathBuf};
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use random_image_server::{
+    cache::{
+        CacheBackend, CacheKey, CacheValue, FileSystemCache, InMemoryCache, TieredCache, Validators,
+    },
+    config::Compression,
+};
+
+/// The number of images each cache holds while it's measured
+const IMAGES: usize = 100;
+
+/// The image every cached image is a copy of
+fn fixture() -> CacheValue {
+    CacheValue {
+        data: std::fs::read("assets/blank.jpg").expect("the fixture image exists"),
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    }
+}
+
+fn key(index: usize) -> CacheKey {
+    CacheKey::ImagePath(PathBuf::from(format!("/bench/{index}.jpg")))
+}
+
+/// The backends measured, by name, each holding `IMAGES` copies of the fixture
+fn backends() -> Vec<(&'static str, Box<dyn CacheBackend>)> {
+    #[cfg_attr(not(feature = "sled"), allow(unused_mut))]
+    let mut backends: Vec<(&'static str, Box<dyn CacheBackend>)> = vec![
+        ("in_memory", Box::new(InMemoryCache::new())),
+        ("file_system", Box::new(FileSystemCache::new())),
+        (
+            "file_system_no_mmap",
+            Box::new(FileSystemCache::new().with_mmap(false)),
+        ),
+        (
+            "file_system_zstd",
+            Box::new(FileSystemCache::new().with_compression(Compression::Zstd)),
+        ),
+        (
+            "tiered",
+            Box::new(TieredCache::with_disk(
+                FileSystemCache::new(),
+                TieredCache::DEFAULT_CAPACITY,
+            )),
+        ),
+    ];
+    #[cfg(feature = "sled")]
+    backends.push((
+        "sled",
+        Box::new(random_image_server::cache::SledCache::new()),
+    ));
+
+    let image = fixture();
+    for (_, cache) in &backends {
+        for index in 0..IMAGES {
+            cache
+                .set(key(index), image.clone())
+                .expect("the image is stored");
+        }
+    }
+    backends
+}
+
+fn bench_get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get");
+    for (name, cache) in backends() {
+        let mut index = 0;
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                index = (index + 1) % IMAGES;
+                black_box(cache.get(key(index)))
+            });
+        });
+    }
+    group.finish();
+}
+
+fn bench_set(c: &mut Criterion) {
+    let mut group = c.benchmark_group("set");
+    let image = fixture();
+    for (name, cache) in backends() {
+        let mut index = 0;
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            // images are replaced, so the cache doesn't grow while it's measured
+            b.iter(|| {
+                index = (index + 1) % IMAGES;
+                cache.set(key(index), black_box(image.clone()))
+            });
+        });
+    }
+    group.finish();
+}
+
+fn bench_get_random(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_random");
+    for (name, cache) in backends() {
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| black_box(cache.get_random()));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_get, bench_set, bench_get_random);
+criterion_main!(benches);
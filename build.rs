@@ -0,0 +1,49 @@
+use std::{path::Path, process::Command};
+
+/// Run a command and return its trimmed stdout, if it succeeded
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_string())
+        .filter(|output| !output.is_empty())
+}
+
+fn main() {
+    // Embed build metadata, exposed by the `/version` endpoint
+    let git_commit = command_output("git", &["rev-parse", "HEAD"]);
+    println!(
+        "cargo:rustc-env=RANDOM_IMAGE_SERVER_GIT_COMMIT={}",
+        git_commit.as_deref().unwrap_or("unknown")
+    );
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = command_output(&rustc, &["--version"]);
+    println!(
+        "cargo:rustc-env=RANDOM_IMAGE_SERVER_RUSTC_VERSION={}",
+        rustc_version.as_deref().unwrap_or("unknown")
+    );
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!(
+        "cargo:rustc-env=RANDOM_IMAGE_SERVER_FEATURES={}",
+        features.join(",")
+    );
+
+    // Only re-run when the checked out commit changes
+    for path in [".git/HEAD", ".git/refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,327 @@
+//! Network-level access control, allowing or denying requests by the address of the client.
+
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use hyper::{Request, header::FORWARDED};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AccessConfig, ServerConfig};
+use crate::public_url::RemoteAddr;
+
+/// A range of IP addresses, written in CIDR notation (e.g. `10.0.0.0/8` or `2001:db8::/32`),
+/// or as a single address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Whether the given address is in the range
+    ///
+    /// IPv4 addresses mapped to IPv6 (`::ffff:a.b.c.d`) are compared as IPv4 addresses.
+    #[must_use]
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                prefix_matches(range.to_bits().into(), ip.to_bits().into(), 32, self.prefix)
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                prefix_matches(range.to_bits(), ip.to_bits(), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether the first `prefix` of the `bits` most significant bits of two addresses are equal
+fn prefix_matches(range: u128, ip: u128, bits: u8, prefix: u8) -> bool {
+    let ignored = u32::from(bits - prefix);
+    range.checked_shr(ignored).unwrap_or(0) == ip.checked_shr(ignored).unwrap_or(0)
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid IP address range: {s}");
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr = IpAddr::from_str(addr).map_err(|_| invalid())?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => u8::from_str(prefix)
+                .ok()
+                .filter(|prefix| *prefix <= bits)
+                .ok_or_else(invalid)?,
+            None => bits,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(cidr: Cidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+/// Whether the peer with the given address is one of the configured trusted proxies
+#[must_use]
+pub fn is_trusted_proxy(config: &ServerConfig, ip: IpAddr) -> bool {
+    config
+        .trusted_proxies
+        .iter()
+        .any(|range| range.contains(ip))
+}
+
+/// The address of the client that sent a request
+///
+/// This is the address of the peer, unless it's a trusted proxy, in which case the forwarded
+/// addresses are followed from the right, past every trusted proxy, to the address that forwarded
+/// the request to them. They are taken from the `Forwarded` header if the request has one, and from
+/// `X-Forwarded-For` otherwise. Returns `None` if the peer address is unknown, e.g. when a request
+/// is handled outside of the server.
+#[must_use]
+pub fn client_ip<B>(req: &Request<B>, config: &ServerConfig) -> Option<IpAddr> {
+    let RemoteAddr(peer) = req.extensions().get::<RemoteAddr>()?;
+    let mut client = peer.ip();
+    if !is_trusted_proxy(config, client) {
+        return Some(client);
+    }
+    let hops = forwarded_hops(req).unwrap_or_else(|| x_forwarded_for_hops(req));
+    for hop in hops.into_iter().rev() {
+        match hop {
+            Some(ip) => {
+                client = ip;
+                if !is_trusted_proxy(config, ip) {
+                    break;
+                }
+            }
+            // the rest of the chain can't be trusted
+            None => break,
+        }
+    }
+    Some(client)
+}
+
+/// The addresses of the `X-Forwarded-For` header, from the first hop to the last,
+/// `None` for those that aren't valid addresses
+fn x_forwarded_for_hops<B>(req: &Request<B>) -> Vec<Option<IpAddr>> {
+    req.headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|hop| IpAddr::from_str(hop.trim()).ok())
+        .collect()
+}
+
+/// The `for` addresses of the `Forwarded` header (RFC 7239), from the first hop to the last,
+/// `None` for hops without one or whose node is obfuscated or `unknown`
+///
+/// Returns `None` if the request doesn't have the header.
+fn forwarded_hops<B>(req: &Request<B>) -> Option<Vec<Option<IpAddr>>> {
+    let values = req.headers().get_all(FORWARDED);
+    values.iter().next()?;
+    let hops = values
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, node)| parse_forwarded_node(node))
+        })
+        .collect();
+    Some(hops)
+}
+
+/// The address of a node of the `Forwarded` header, e.g. `192.0.2.60`, `"192.0.2.60:8080"`,
+/// or `"[2001:db8::1]:4711"`, ignoring its port
+fn parse_forwarded_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(bracketed) = node.strip_prefix('[') {
+        let (ip, _) = bracketed.split_once(']')?;
+        return ip.parse::<std::net::Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+    let ip = node.split_once(':').map_or(node, |(ip, _)| ip);
+    ip.parse::<std::net::Ipv4Addr>().ok().map(IpAddr::V4)
+}
+
+/// Whether a request from the given client address may be served
+///
+/// Addresses in a denied range are always refused. If any ranges are allowed, only addresses in
+/// them are served, so requests from an unknown address are refused too.
+#[must_use]
+pub fn is_allowed(access: &AccessConfig, client: Option<IpAddr>) -> bool {
+    let Some(client) = client else {
+        return access.allow.is_empty();
+    };
+    !access.deny.iter().any(|range| range.contains(client))
+        && (access.allow.is_empty() || access.allow.iter().any(|range| range.contains(client)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    fn cidrs(ranges: &[&str]) -> Vec<Cidr> {
+        ranges.iter().map(|range| range.parse().unwrap()).collect()
+    }
+
+    #[rstest]
+    #[case::v4("10.0.0.0/8", "10.1.2.3", true)]
+    #[case::v4_outside("10.0.0.0/8", "11.0.0.1", false)]
+    #[case::v4_single("192.168.1.1", "192.168.1.1", true)]
+    #[case::v4_single_outside("192.168.1.1", "192.168.1.2", false)]
+    #[case::v4_any("0.0.0.0/0", "203.0.113.7", true)]
+    #[case::v4_mapped("10.0.0.0/8", "::ffff:10.0.0.1", true)]
+    #[case::v6("2001:db8::/32", "2001:db8::1", true)]
+    #[case::v6_outside("2001:db8::/32", "2001:db9::1", false)]
+    #[case::v6_any("::/0", "::1", true)]
+    #[case::mixed("10.0.0.0/8", "2001:db8::1", false)]
+    fn test_cidr_contains(#[case] range: &str, #[case] ip: &str, #[case] expected: bool) {
+        let range: Cidr = range.parse().unwrap();
+        assert_eq!(range.contains(ip.parse().unwrap()), expected);
+    }
+
+    #[rstest]
+    #[case::prefix_too_long("10.0.0.0/33")]
+    #[case::not_an_address("example.com/8")]
+    #[case::not_a_prefix("10.0.0.0/x")]
+    fn test_cidr_invalid(#[case] range: &str) {
+        assert!(range.parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_cidr_display() {
+        assert_eq!(
+            "10.0.0.0/8".parse::<Cidr>().unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+        assert_eq!("::1".parse::<Cidr>().unwrap().to_string(), "::1/128");
+    }
+
+    #[rstest]
+    #[case::no_lists(&[], &[], Some("203.0.113.7"), true)]
+    #[case::unknown_client(&[], &["10.0.0.0/8"], None, true)]
+    #[case::unknown_client_allowlist(&["10.0.0.0/8"], &[], None, false)]
+    #[case::allowed(&["10.0.0.0/8"], &[], Some("10.0.0.1"), true)]
+    #[case::not_allowed(&["10.0.0.0/8"], &[], Some("203.0.113.7"), false)]
+    #[case::denied(&[], &["203.0.113.0/24"], Some("203.0.113.7"), false)]
+    #[case::deny_wins(&["10.0.0.0/8"], &["10.0.0.1"], Some("10.0.0.1"), false)]
+    fn test_is_allowed(
+        #[case] allow: &[&str],
+        #[case] deny: &[&str],
+        #[case] client: Option<&str>,
+        #[case] expected: bool,
+    ) {
+        let access = AccessConfig {
+            allow: cidrs(allow),
+            deny: cidrs(deny),
+        };
+        assert_eq!(
+            is_allowed(&access, client.map(|ip| ip.parse().unwrap())),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::no_peer(None, None, None)]
+    #[case::peer(Some("203.0.113.7:1234"), Some("10.0.0.9"), Some("203.0.113.7"))]
+    #[case::trusted_proxy(
+        Some("10.0.0.1:1234"),
+        Some("198.51.100.1, 203.0.113.7"),
+        Some("203.0.113.7")
+    )]
+    #[case::trusted_chain(
+        Some("10.0.0.1:1234"),
+        Some("203.0.113.7, 10.0.0.2"),
+        Some("203.0.113.7")
+    )]
+    #[case::trusted_no_header(Some("10.0.0.1:1234"), None, Some("10.0.0.1"))]
+    #[case::trusted_malformed(
+        Some("10.0.0.1:1234"),
+        Some("203.0.113.7, unknown"),
+        Some("10.0.0.1")
+    )]
+    #[case::trusted_range(
+        Some("172.16.5.4:1234"),
+        Some("203.0.113.7, 172.16.0.9"),
+        Some("203.0.113.7")
+    )]
+    fn test_client_ip(
+        #[case] peer: Option<&str>,
+        #[case] forwarded_for: Option<&str>,
+        #[case] expected: Option<&str>,
+    ) {
+        let config = ServerConfig {
+            trusted_proxies: cidrs(&["10.0.0.1", "10.0.0.2", "172.16.0.0/12"]),
+            ..ServerConfig::default()
+        };
+        let mut req = Request::builder();
+        if let Some(forwarded_for) = forwarded_for {
+            req = req.header("x-forwarded-for", forwarded_for);
+        }
+        let mut req = req.body(()).unwrap();
+        if let Some(peer) = peer {
+            req.extensions_mut()
+                .insert(RemoteAddr(peer.parse().unwrap()));
+        }
+
+        assert_eq!(
+            client_ip(&req, &config),
+            expected.map(|ip| ip.parse().unwrap())
+        );
+    }
+
+    #[rstest]
+    #[case::address("for=203.0.113.7", Some("203.0.113.7"))]
+    #[case::quoted_port(r#"for="203.0.113.7:4711";proto=https"#, Some("203.0.113.7"))]
+    #[case::v6(r#"For="[2001:db8::1]:4711""#, Some("2001:db8::1"))]
+    #[case::trusted_chain("for=203.0.113.7, for=10.0.0.2;by=10.0.0.1", Some("203.0.113.7"))]
+    #[case::untrusted_hop("for=198.51.100.1, for=203.0.113.7", Some("203.0.113.7"))]
+    #[case::obfuscated("for=203.0.113.7, for=_hidden", Some("10.0.0.1"))]
+    #[case::no_for("proto=https", Some("10.0.0.1"))]
+    fn test_client_ip_forwarded(#[case] forwarded: &str, #[case] expected: Option<&str>) {
+        let config = ServerConfig {
+            trusted_proxies: cidrs(&["10.0.0.0/8"]),
+            ..ServerConfig::default()
+        };
+        // the standard header is preferred to X-Forwarded-For
+        let mut req = Request::builder()
+            .header(FORWARDED, forwarded)
+            .header("x-forwarded-for", "192.0.2.1")
+            .body(())
+            .unwrap();
+        req.extensions_mut()
+            .insert(RemoteAddr("10.0.0.1:1234".parse().unwrap()));
+
+        assert_eq!(
+            client_ip(&req, &config),
+            expected.map(|ip| ip.parse().unwrap())
+        );
+    }
+}
@@ -0,0 +1,906 @@
+//! Obtaining and renewing certificates automatically from an ACME certificate authority, e.g. Let's Encrypt.
+//!
+//! Domains are validated with the `tls-alpn-01` challenge (RFC 8737), answered by the HTTPS listener itself
+//! with a certificate made for the challenge, so no other port or web server is needed.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::Path,
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, anyhow};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ring::{
+    digest::{SHA256, digest},
+    rand::SystemRandom,
+    signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair},
+};
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::config::{AcmeConfig, HttpConfig, TlsConfig};
+
+/// The protocol ACME validators negotiate with ALPN to request the certificate of a `tls-alpn-01` challenge
+pub const ALPN_PROTOCOL: &[u8] = b"acme-tls/1";
+
+/// The files the account key, the certificate chain, and its private key are stored in, in `cache_dir`
+const ACCOUNT_KEY_FILE: &str = "account.pk8";
+const CERT_FILE: &str = "cert.pem";
+const KEY_FILE: &str = "key.pem";
+
+/// How long failing to obtain a certificate is retried after
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// The longest the certificate goes without being checked for renewal
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How often pending authorizations and orders are polled, and how many times
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 30;
+
+/// Chooses the certificate of each TLS handshake: the one obtained from the certificate authority,
+/// or the one of a pending challenge for the handshakes of ACME validators
+#[derive(Debug, Default)]
+pub struct CertResolver {
+    certificate: RwLock<Option<Arc<CertifiedKey>>>,
+    challenges: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl CertResolver {
+    /// Serve the given certificate from now on
+    pub fn set_certificate(&self, certificate: Arc<CertifiedKey>) {
+        *self
+            .certificate
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(certificate);
+    }
+
+    /// Answer the challenges of ACME validators for the given domain with the given certificate
+    fn set_challenge(&self, domain: &str, certificate: Arc<CertifiedKey>) {
+        self.challenges
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(domain.to_string(), certificate);
+    }
+
+    /// Stop answering the challenges of ACME validators for the given domain
+    fn remove_challenge(&self, domain: &str) {
+        self.challenges
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(domain);
+    }
+
+    /// The certificate served to a client, which is an ACME validator if `challenge` is set
+    fn certificate(&self, server_name: Option<&str>, challenge: bool) -> Option<Arc<CertifiedKey>> {
+        if challenge {
+            let challenges = self
+                .challenges
+                .read()
+                .unwrap_or_else(PoisonError::into_inner);
+            return server_name.and_then(|name| challenges.get(name).cloned());
+        }
+        self.certificate
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let challenge = client_hello
+            .alpn()
+            .is_some_and(|mut protocols| protocols.any(|protocol| protocol == ALPN_PROTOCOL));
+        self.certificate(client_hello.server_name(), challenge)
+    }
+}
+
+/// Keeps the certificate of the server obtained from an ACME certificate authority, and renews it before it expires
+#[derive(Debug)]
+pub struct CertificateManager {
+    config: AcmeConfig,
+    client: reqwest::Client,
+    resolver: Arc<CertResolver>,
+    /// When the current certificate is due for renewal, if there is one
+    renew_at: Option<SystemTime>,
+}
+
+impl CertificateManager {
+    /// Prepare to manage the certificate described by the given configuration,
+    /// serving the one stored in `cache_dir` by a previous run meanwhile, if any
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no domains are configured, or the HTTP client can't be built.
+    pub fn new(config: &AcmeConfig, http: &HttpConfig) -> Result<Self> {
+        if config.domains.is_empty() {
+            return Err(anyhow!("tls.acme.domains must list at least one domain"));
+        }
+        let manager = Self {
+            config: config.clone(),
+            client: http.build_client()?,
+            resolver: Arc::new(CertResolver::default()),
+            renew_at: None,
+        };
+        match manager.load_certificate() {
+            Ok(renew_at) => Ok(Self {
+                renew_at,
+                ..manager
+            }),
+            Err(e) => {
+                tracing::warn!("Failed to load the stored ACME certificate: {e:#}");
+                Ok(manager)
+            }
+        }
+    }
+
+    /// The TLS configuration serving the managed certificate, and answering ACME challenges
+    ///
+    /// As ACME validators don't authenticate with a certificate, clients without one complete the handshake
+    /// even if `tls.client_ca` is set, and their requests are refused instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the certificate authorities of `tls.client_ca` can't be loaded.
+    pub fn server_config(&self, tls: &TlsConfig) -> Result<rustls::ServerConfig> {
+        let mut config =
+            crate::tls::config_builder(tls, true)?.with_cert_resolver(self.resolver.clone());
+        config.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        Ok(config)
+    }
+
+    /// Keep the certificate up to date, obtaining a new one whenever it's due for renewal
+    pub async fn run(mut self) {
+        loop {
+            let wait = match self.renew_if_due().await {
+                Ok(renew_at) => renew_at
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default()
+                    .min(CHECK_INTERVAL),
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to obtain a certificate from {}: {e:#}",
+                        self.config.directory
+                    );
+                    RETRY_INTERVAL
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Obtain a new certificate if there is none yet or it's due for renewal, returning when the next one is due
+    async fn renew_if_due(&mut self) -> Result<SystemTime> {
+        if let Some(renew_at) = self.renew_at
+            && renew_at > SystemTime::now()
+        {
+            return Ok(renew_at);
+        }
+        tracing::info!(
+            "Obtaining a certificate for {} from {}",
+            self.config.domains.join(", "),
+            self.config.directory
+        );
+        let (chain, key) = Session::new(self).await?.order_certificate().await?;
+
+        fs::create_dir_all(&self.config.cache_dir)?;
+        write_private(&self.config.cache_dir.join(KEY_FILE), key.as_bytes())?;
+        fs::write(self.config.cache_dir.join(CERT_FILE), &chain)?;
+        let renew_at = self
+            .load_certificate()?
+            .ok_or_else(|| anyhow!("The certificate obtained was not stored"))?;
+        tracing::info!(
+            "Obtained a certificate for {}",
+            self.config.domains.join(", ")
+        );
+        self.renew_at = Some(renew_at);
+        Ok(renew_at)
+    }
+
+    /// Serve the certificate stored in `cache_dir`, returning when it's due for renewal.
+    /// It's due right away if it isn't for the configured domains
+    fn load_certificate(&self) -> Result<Option<SystemTime>> {
+        let (cert_path, key_path) = (
+            self.config.cache_dir.join(CERT_FILE),
+            self.config.cache_dir.join(KEY_FILE),
+        );
+        if !cert_path.exists() || !key_path.exists() {
+            return Ok(None);
+        }
+        let (certs, key) = (
+            crate::tls::load_certs(&cert_path)?,
+            crate::tls::load_key(&key_path)?,
+        );
+        let (renew_at, domains) = certificate_details(&certs[0])?;
+        let certified = certified_key(certs, &key)?;
+        self.resolver.set_certificate(certified);
+
+        let configured = self.config.domains.iter().map(String::as_str).collect();
+        if domains.iter().map(String::as_str).collect::<BTreeSet<_>>() != configured {
+            tracing::info!("The stored certificate isn't for the configured domains, renewing it");
+            return Ok(Some(UNIX_EPOCH));
+        }
+        Ok(Some(renew_at))
+    }
+}
+
+/// Build the certified key served in TLS handshakes from a certificate chain and its private key
+fn certified_key(
+    certs: Vec<CertificateDer<'static>>,
+    key: &PrivateKeyDer<'static>,
+) -> Result<Arc<CertifiedKey>> {
+    let key = rustls::crypto::ring::sign::any_supported_type(key)
+        .context("The type of the private key isn't supported")?;
+    Ok(Arc::new(CertifiedKey::new(certs, key)))
+}
+
+/// When a certificate is due for renewal, once two thirds of its validity have passed, and the domains it's for
+fn certificate_details(cert: &CertificateDer<'_>) -> Result<(SystemTime, Vec<String>)> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert)
+        .map_err(|e| anyhow!("Failed to parse the certificate: {e}"))?;
+    let validity = cert.validity();
+    let (not_before, not_after) = (
+        validity.not_before.timestamp(),
+        validity.not_after.timestamp(),
+    );
+    let renew_at = not_after - (not_after - not_before) / 3;
+    let renew_at = UNIX_EPOCH + Duration::from_secs(u64::try_from(renew_at).unwrap_or_default());
+
+    let domains = cert
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .map(|names| {
+            names
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    x509_parser::extensions::GeneralName::DNSName(name) => Some(name.to_string()),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok((renew_at, domains))
+}
+
+/// Write a file only its owner can read, for private keys
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(&mut options.open(path)?, contents)?;
+    Ok(())
+}
+
+/// The URLs of the operations of a certificate authority
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+    error: Option<Problem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+    error: Option<Problem>,
+}
+
+/// An error reported by the certificate authority
+#[derive(Debug, Default, Deserialize)]
+struct Problem {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    detail: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.detail, self.kind)
+    }
+}
+
+/// The account key requests to the certificate authority are signed with
+struct AccountKey {
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+}
+
+impl AccountKey {
+    /// Load the account key stored at the given path, generating and storing a new one if there is none
+    fn load_or_generate(path: &Path) -> Result<Self> {
+        let rng = SystemRandom::new();
+        let pkcs8 = if path.exists() {
+            fs::read(path)?
+        } else {
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                .map_err(|_| anyhow!("Failed to generate an ACME account key"))?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            write_private(path, pkcs8.as_ref())?;
+            pkcs8.as_ref().to_vec()
+        };
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng)
+            .map_err(|_| anyhow!("Invalid ACME account key {}", path.display()))?;
+        Ok(Self { key, rng })
+    }
+
+    /// The public key, as a JSON web key
+    fn jwk(&self) -> Value {
+        let (x, y) = self.key.public_key().as_ref()[1..].split_at(32);
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": URL_SAFE_NO_PAD.encode(x),
+            "y": URL_SAFE_NO_PAD.encode(y),
+        })
+    }
+
+    /// The thumbprint of the public key (RFC 7638), the second half of the key authorizations of challenges
+    fn thumbprint(&self) -> String {
+        let jwk = self.jwk();
+        // the members of the key are hashed in lexicographic order, without whitespace
+        let canonical = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":{},"y":{}}}"#,
+            jwk["x"], jwk["y"]
+        );
+        URL_SAFE_NO_PAD.encode(digest(&SHA256, canonical.as_bytes()))
+    }
+
+    /// Sign a request to the given URL, identifying the account by its URL if known, or by its public key
+    fn sign(
+        &self,
+        url: &str,
+        nonce: &str,
+        kid: Option<&str>,
+        payload: Option<&Value>,
+    ) -> Result<Value> {
+        let protected = match kid {
+            Some(kid) => json!({ "alg": "ES256", "kid": kid, "nonce": nonce, "url": url }),
+            None => json!({ "alg": "ES256", "jwk": self.jwk(), "nonce": nonce, "url": url }),
+        };
+        let protected = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&protected)?);
+        // requests without a payload are POST-as-GET requests, with an empty one
+        let payload = payload
+            .map(|payload| {
+                serde_json::to_vec(payload).map(|payload| URL_SAFE_NO_PAD.encode(payload))
+            })
+            .transpose()?
+            .unwrap_or_default();
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+            .map_err(|_| anyhow!("Failed to sign an ACME request"))?;
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": URL_SAFE_NO_PAD.encode(signature),
+        }))
+    }
+}
+
+/// An exchange with the certificate authority to obtain a certificate
+struct Session<'a> {
+    manager: &'a CertificateManager,
+    directory: Directory,
+    key: AccountKey,
+    /// The URL of the account, once it's created or found
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl<'a> Session<'a> {
+    /// Fetch the directory of the certificate authority, and register the account with it
+    async fn new(manager: &'a CertificateManager) -> Result<Self> {
+        let response = manager
+            .client
+            .get(manager.config.directory.as_str())
+            .send()
+            .await?
+            .error_for_status()?;
+        let directory = json_body(response)
+            .await
+            .context("Invalid ACME directory")?;
+        let key = AccountKey::load_or_generate(&manager.config.cache_dir.join(ACCOUNT_KEY_FILE))?;
+        let mut session = Self {
+            manager,
+            directory,
+            key,
+            kid: None,
+            nonce: None,
+        };
+
+        let mut account = json!({ "termsOfServiceAgreed": true });
+        if let Some(email) = &manager.config.email {
+            account["contact"] = json!([format!("mailto:{email}")]);
+        }
+        let url = session.directory.new_account.clone();
+        let response = session.post(&url, Some(&account)).await?;
+        session.kid = Some(location(&response)?);
+        Ok(session)
+    }
+
+    /// Order a certificate for the configured domains, returning its chain and its private key, in PEM
+    async fn order_certificate(mut self) -> Result<(String, String)> {
+        let domains = &self.manager.config.domains;
+        let identifiers: Vec<_> = domains
+            .iter()
+            .map(|domain| json!({ "type": "dns", "value": domain }))
+            .collect();
+        let url = self.directory.new_order.clone();
+        let response = self
+            .post(&url, Some(&json!({ "identifiers": identifiers })))
+            .await?;
+        let order_url = location(&response)?;
+        let order: Order = json_body(response).await?;
+
+        for authorization in &order.authorizations {
+            self.authorize(authorization).await?;
+        }
+
+        let key = rcgen::KeyPair::generate()?;
+        let csr = rcgen::CertificateParams::new(domains.clone())?.serialize_request(&key)?;
+        self.post(
+            &order.finalize,
+            Some(&json!({ "csr": URL_SAFE_NO_PAD.encode(csr.der()) })),
+        )
+        .await?;
+
+        let mut order = order;
+        for _ in 0..POLL_ATTEMPTS {
+            order = json_body(self.post(&order_url, None).await?).await?;
+            match order.status.as_str() {
+                "valid" => break,
+                "invalid" => {
+                    return Err(anyhow!(
+                        "The order was refused: {}",
+                        order.error.unwrap_or_default()
+                    ));
+                }
+                _ => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+        let certificate = order
+            .certificate
+            .ok_or_else(|| anyhow!("The order wasn't fulfilled in time"))?;
+        let chain = self.post(&certificate, None).await?.text().await?;
+        Ok((chain, key.serialize_pem()))
+    }
+
+    /// Prove control of the domain of an authorization, with the `tls-alpn-01` challenge
+    async fn authorize(&mut self, url: &str) -> Result<()> {
+        let authorization: Authorization = json_body(self.post(url, None).await?).await?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let domain = authorization.identifier.value;
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.kind == "tls-alpn-01")
+            .ok_or_else(|| anyhow!("{domain} can't be validated with the tls-alpn-01 challenge"))?;
+
+        // the certificate of the challenge holds the digest of its key authorization
+        let key_authorization = format!("{}.{}", challenge.token, self.key.thumbprint());
+        let mut params = rcgen::CertificateParams::new(vec![domain.clone()])?;
+        params.custom_extensions = vec![rcgen::CustomExtension::new_acme_identifier(
+            digest(&SHA256, key_authorization.as_bytes()).as_ref(),
+        )];
+        let key = rcgen::KeyPair::generate()?;
+        let cert = params.self_signed(&key)?;
+        let resolver = &self.manager.resolver;
+        resolver.set_challenge(
+            &domain,
+            certified_key(
+                vec![cert.der().clone()],
+                &PrivateKeyDer::try_from(key.serialize_der()).map_err(|e| anyhow!(e))?,
+            )?,
+        );
+
+        let validated = self.validate(url, &challenge.url, &domain).await;
+        resolver.remove_challenge(&domain);
+        validated
+    }
+
+    /// Tell the certificate authority a challenge is ready, and wait for it to validate the authorization
+    async fn validate(
+        &mut self,
+        authorization_url: &str,
+        challenge_url: &str,
+        domain: &str,
+    ) -> Result<()> {
+        self.post(challenge_url, Some(&json!({}))).await?;
+        for _ in 0..POLL_ATTEMPTS {
+            let authorization: Authorization =
+                json_body(self.post(authorization_url, None).await?).await?;
+            match authorization.status.as_str() {
+                "valid" => return Ok(()),
+                "pending" => tokio::time::sleep(POLL_INTERVAL).await,
+                _ => {
+                    let error = authorization
+                        .challenges
+                        .into_iter()
+                        .find_map(|challenge| challenge.error)
+                        .unwrap_or_default();
+                    return Err(anyhow!("{domain} failed to be validated: {error}"));
+                }
+            }
+        }
+        Err(anyhow!("{domain} wasn't validated in time"))
+    }
+
+    /// Send a signed request, retrying it once if the certificate authority refuses its nonce
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<reqwest::Response> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.new_nonce().await?,
+            };
+            let body = self.key.sign(url, &nonce, self.kid.as_deref(), payload)?;
+            let response = self
+                .manager
+                .client
+                .post(url)
+                .header(reqwest::header::CONTENT_TYPE, "application/jose+json")
+                .body(serde_json::to_vec(&body)?)
+                .send()
+                .await?;
+            self.nonce = replay_nonce(&response);
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let problem: Problem = json_body(response).await.unwrap_or_default();
+            if problem.kind == "urn:ietf:params:acme:error:badNonce" && !retried {
+                retried = true;
+                continue;
+            }
+            return Err(anyhow!("ACME request to {url} failed: {problem}"));
+        }
+    }
+
+    /// Get a fresh nonce to sign a request with
+    async fn new_nonce(&self) -> Result<String> {
+        let response = self
+            .manager
+            .client
+            .head(&self.directory.new_nonce)
+            .send()
+            .await?
+            .error_for_status()?;
+        replay_nonce(&response).ok_or_else(|| anyhow!("The certificate authority sent no nonce"))
+    }
+}
+
+async fn json_body<T: serde::de::DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    Ok(serde_json::from_slice(&response.bytes().await?)?)
+}
+
+fn replay_nonce(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get("replay-nonce")
+        .and_then(|nonce| nonce.to_str().ok())
+        .map(ToString::to_string)
+}
+
+fn location(response: &reqwest::Response) -> Result<String> {
+    response
+        .headers()
+        .get(reqwest::header::LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .map(ToString::to_string)
+        .ok_or_else(|| anyhow!("The certificate authority sent no Location"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    };
+
+    use http_body_util::{BodyExt, Full};
+    use hyper::{Response, body::Bytes, service::service_fn};
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto,
+    };
+    use pretty_assertions::assert_eq;
+    use ring::signature::{ECDSA_P256_SHA256_FIXED, UnparsedPublicKey};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn decode(value: &Value) -> Value {
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(value.as_str().unwrap()).unwrap())
+            .unwrap_or(Value::Null)
+    }
+
+    #[test]
+    fn test_account_key_sign() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("account.pk8");
+        let key = AccountKey::load_or_generate(&path).unwrap();
+        // the stored key is loaded again
+        let loaded = AccountKey::load_or_generate(&path).unwrap();
+        assert_eq!(key.jwk(), loaded.jwk());
+        assert_eq!(key.thumbprint(), loaded.thumbprint());
+        assert_eq!(key.thumbprint().len(), 43);
+
+        let payload = json!({ "identifiers": [] });
+        let body = key
+            .sign("https://ca.example/order", "nonce", None, Some(&payload))
+            .unwrap();
+        let protected = decode(&body["protected"]);
+        assert_eq!(protected["alg"], "ES256");
+        assert_eq!(protected["nonce"], "nonce");
+        assert_eq!(protected["url"], "https://ca.example/order");
+        assert_eq!(protected["jwk"], key.jwk());
+        assert_eq!(decode(&body["payload"]), payload);
+        let signed = format!(
+            "{}.{}",
+            body["protected"].as_str().unwrap(),
+            body["payload"].as_str().unwrap()
+        );
+        let signature = URL_SAFE_NO_PAD
+            .decode(body["signature"].as_str().unwrap())
+            .unwrap();
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, key.key.public_key().as_ref())
+            .verify(signed.as_bytes(), &signature)
+            .unwrap();
+
+        // once the account is known, it's identified by its URL, and POST-as-GET requests have no payload
+        let body = key
+            .sign(
+                "https://ca.example/order/1",
+                "nonce",
+                Some("https://ca.example/account/1"),
+                None,
+            )
+            .unwrap();
+        let protected = decode(&body["protected"]);
+        assert_eq!(protected["kid"], "https://ca.example/account/1");
+        assert!(protected.get("jwk").is_none());
+        assert_eq!(body["payload"], "");
+    }
+
+    #[test]
+    fn test_certificate_details() {
+        let mut params =
+            rcgen::CertificateParams::new(vec!["a.example".to_string(), "b.example".to_string()])
+                .unwrap();
+        params.not_before = rcgen::date_time_ymd(2025, 1, 1);
+        params.not_after = rcgen::date_time_ymd(2025, 4, 1);
+        let cert = params
+            .self_signed(&rcgen::KeyPair::generate().unwrap())
+            .unwrap();
+
+        let (renew_at, domains) = certificate_details(cert.der()).unwrap();
+        // two thirds into the 90 days the certificate is valid for
+        let not_after = rcgen::date_time_ymd(2025, 4, 1).unix_timestamp();
+        assert_eq!(
+            renew_at,
+            UNIX_EPOCH + Duration::from_secs(u64::try_from(not_after).unwrap() - 30 * 24 * 60 * 60)
+        );
+        assert_eq!(domains, ["a.example", "b.example"]);
+    }
+
+    #[test]
+    fn test_cert_resolver() {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let certified = |domain: &str| {
+            let cert = rcgen::CertificateParams::new(vec![domain.to_string()])
+                .unwrap()
+                .self_signed(&key)
+                .unwrap();
+            certified_key(
+                vec![cert.der().clone()],
+                &PrivateKeyDer::try_from(key.serialize_der()).unwrap(),
+            )
+            .unwrap()
+        };
+        let resolver = CertResolver::default();
+        assert!(resolver.certificate(Some("a.example"), false).is_none());
+
+        let (certificate, challenge) = (certified("a.example"), certified("a.example"));
+        resolver.set_certificate(certificate.clone());
+        resolver.set_challenge("a.example", challenge.clone());
+        // validators get the certificate of the challenge for its domain, and other clients the certificate
+        let resolved = resolver.certificate(Some("a.example"), true).unwrap();
+        assert!(Arc::ptr_eq(&resolved, &challenge));
+        assert!(resolver.certificate(Some("b.example"), true).is_none());
+        let resolved = resolver.certificate(Some("a.example"), false).unwrap();
+        assert!(Arc::ptr_eq(&resolved, &certificate));
+
+        resolver.remove_challenge("a.example");
+        assert!(resolver.certificate(Some("a.example"), true).is_none());
+    }
+
+    /// Serve a certificate authority that validates challenges as soon as they're ready,
+    /// checking that the resolver answers them, and issues a self-signed certificate for `localhost`
+    async fn serve_certificate_authority(
+        resolver: Arc<Mutex<Option<Arc<CertResolver>>>>,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let validated = Arc::new(AtomicBool::new(false));
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+            .unwrap()
+            .cert
+            .pem();
+        let url = base.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (base, resolver, validated, certificate) = (
+                    url.clone(),
+                    resolver.clone(),
+                    validated.clone(),
+                    certificate.clone(),
+                );
+                let service = service_fn(move |req: hyper::Request<hyper::body::Incoming>| {
+                    let (base, resolver, validated, certificate) = (
+                        base.clone(),
+                        resolver.clone(),
+                        validated.clone(),
+                        certificate.clone(),
+                    );
+                    async move {
+                        let path = req.uri().path().to_string();
+                        let body = req.into_body().collect().await.unwrap().to_bytes();
+                        if !body.is_empty() {
+                            // every request is signed for its URL, with a nonce
+                            let jws: Value = serde_json::from_slice(&body).unwrap();
+                            let protected = decode(&jws["protected"]);
+                            assert_eq!(protected["url"], format!("{base}{path}"));
+                            assert_eq!(protected["nonce"], "nonce");
+                            assert_eq!(protected.get("jwk").is_some(), path == "/account");
+                        }
+                        let order = || {
+                            json!({
+                                "status": if validated.load(Ordering::SeqCst) { "valid" } else { "pending" },
+                                "authorizations": [format!("{base}/authz/1")],
+                                "finalize": format!("{base}/finalize"),
+                                "certificate": format!("{base}/cert"),
+                            })
+                        };
+                        let (status, location, body) = match path.as_str() {
+                            "/directory" => (
+                                200,
+                                None,
+                                json!({
+                                    "newNonce": format!("{base}/nonce"),
+                                    "newAccount": format!("{base}/account"),
+                                    "newOrder": format!("{base}/order"),
+                                })
+                                .to_string(),
+                            ),
+                            "/nonce" => (200, None, String::new()),
+                            "/account" => (201, Some(format!("{base}/account/1")), "{}".to_string()),
+                            "/order" => (201, Some(format!("{base}/order/1")), order().to_string()),
+                            "/order/1" | "/finalize" => (200, None, order().to_string()),
+                            "/authz/1" => (
+                                200,
+                                None,
+                                json!({
+                                    "status": if validated.load(Ordering::SeqCst) { "valid" } else { "pending" },
+                                    "identifier": { "type": "dns", "value": "localhost" },
+                                    "challenges": [
+                                        { "type": "http-01", "url": format!("{base}/challenge/0"), "token": "other" },
+                                        { "type": "tls-alpn-01", "url": format!("{base}/challenge/1"), "token": "token" },
+                                    ],
+                                })
+                                .to_string(),
+                            ),
+                            "/challenge/1" => {
+                                let resolver = resolver.lock().unwrap().clone().unwrap();
+                                assert!(resolver.certificate(Some("localhost"), true).is_some());
+                                validated.store(true, Ordering::SeqCst);
+                                (200, None, "{}".to_string())
+                            }
+                            "/cert" => (200, None, certificate),
+                            _ => (404, None, "{}".to_string()),
+                        };
+                        let mut response = Response::builder()
+                            .status(status)
+                            .header("replay-nonce", "nonce");
+                        if let Some(location) = location {
+                            response = response.header("location", location);
+                        }
+                        Ok::<_, std::convert::Infallible>(
+                            response.body(Full::new(Bytes::from(body))).unwrap(),
+                        )
+                    }
+                });
+                tokio::spawn(async move {
+                    let _ = auto::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        base
+    }
+
+    #[tokio::test]
+    async fn test_certificate_manager() {
+        let resolver = Arc::new(Mutex::new(None));
+        let base = serve_certificate_authority(resolver.clone()).await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = AcmeConfig {
+            domains: vec!["localhost".to_string()],
+            email: Some("admin@example.com".to_string()),
+            cache_dir: dir.path().join("acme"),
+            directory: format!("{base}/directory").parse().unwrap(),
+        };
+
+        assert!(CertificateManager::new(&AcmeConfig::default(), &HttpConfig::default()).is_err());
+        let mut manager = CertificateManager::new(&config, &HttpConfig::default()).unwrap();
+        *resolver.lock().unwrap() = Some(manager.resolver.clone());
+        assert!(manager.resolver.certificate(None, false).is_none());
+
+        let renew_at = manager.renew_if_due().await.unwrap();
+        assert!(renew_at > SystemTime::now());
+        assert!(manager.resolver.certificate(None, false).is_some());
+        assert!(
+            manager
+                .resolver
+                .certificate(Some("localhost"), true)
+                .is_none()
+        );
+        for file in [ACCOUNT_KEY_FILE, CERT_FILE, KEY_FILE] {
+            assert!(config.cache_dir.join(file).exists(), "{file}");
+        }
+        // the certificate isn't renewed until it's due
+        assert_eq!(manager.renew_if_due().await.unwrap(), renew_at);
+
+        // the stored certificate is served right away after a restart, and renewed if the domains changed
+        let manager = CertificateManager::new(&config, &HttpConfig::default()).unwrap();
+        assert_eq!(manager.renew_at, Some(renew_at));
+        assert!(manager.resolver.certificate(None, false).is_some());
+        let config = AcmeConfig {
+            domains: vec!["localhost".to_string(), "example.com".to_string()],
+            ..config
+        };
+        let manager = CertificateManager::new(&config, &HttpConfig::default()).unwrap();
+        assert_eq!(manager.renew_at, Some(UNIX_EPOCH));
+    }
+}
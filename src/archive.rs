@@ -0,0 +1,341 @@
+//! Archive sources: zip and tar files of images, loaded without unpacking them to disk.
+//!
+//! Archives are recognised by their extension: `.zip`, `.tar`, `.tar.gz` or `.tgz`.
+//! Entries are read one at a time, so only the images that are loaded are held in memory.
+
+#[cfg(feature = "remote-sources")]
+use std::collections::BTreeMap;
+use std::{
+    io::{Read, Seek},
+    ops::ControlFlow,
+    path::Path,
+};
+
+use anyhow::{Result, anyhow};
+#[cfg(feature = "remote-sources")]
+use url::Url;
+
+use crate::{
+    cache::{CacheValue, Validators},
+    filter::FileFilter,
+    has_allowed_extension,
+};
+
+/// The most bytes allocated up front for an archive entry, by the size in its header, before it's read
+const MAX_PREALLOCATED_SIZE: u64 = 1024 * 1024;
+
+/// The formats of archives that can be used as sources
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// The format of an archive with the given file name or path, if it is one
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        ARCHIVE_SUFFIXES
+            .iter()
+            .find(|(suffix, _)| name.ends_with(suffix))
+            .map(|&(_, format)| format)
+    }
+}
+
+/// The file name suffixes of archives, and their formats
+const ARCHIVE_SUFFIXES: [(&str, ArchiveFormat); 4] = [
+    (".zip", ArchiveFormat::Zip),
+    (".tar", ArchiveFormat::Tar),
+    (".tar.gz", ArchiveFormat::TarGz),
+    (".tgz", ArchiveFormat::TarGz),
+];
+
+/// Visit the images in an archive that pass the filter and have one of the allowed extensions
+///
+/// `visit` is given the path of each image within the archive, its size in bytes, and a reader
+/// for its contents, and can stop the iteration early by returning `ControlFlow::Break`.
+/// Entries with paths escaping the archive (e.g. `../a.jpg`) are skipped.
+///
+/// # Errors
+///
+/// Returns an error if the archive is corrupt.
+pub fn for_each_image<R: Read + Seek>(
+    reader: R,
+    format: ArchiveFormat,
+    filter: &FileFilter,
+    allowed_extensions: &[impl AsRef<str>],
+    mut visit: impl FnMut(&Path, u64, &mut dyn Read) -> ControlFlow<()>,
+) -> Result<()> {
+    let is_image =
+        |path: &Path| has_allowed_extension(path, allowed_extensions) && filter.allows_file(path);
+
+    match format {
+        ArchiveFormat::Zip => {
+            let mut archive =
+                zip::ZipArchive::new(reader).map_err(|e| anyhow!("Invalid zip archive: {e}"))?;
+            for index in 0..archive.len() {
+                let mut file = archive
+                    .by_index(index)
+                    .map_err(|e| anyhow!("Invalid zip archive entry: {e}"))?;
+                let Some(path) = file.enclosed_name() else {
+                    continue;
+                };
+                if file.is_file()
+                    && is_image(&path)
+                    && visit(&path, file.size(), &mut file).is_break()
+                {
+                    break;
+                }
+            }
+        }
+        ArchiveFormat::Tar => for_each_tar_image(reader, is_image, visit)?,
+        ArchiveFormat::TarGz => {
+            for_each_tar_image(flate2::read::GzDecoder::new(reader), is_image, visit)?;
+        }
+    }
+    Ok(())
+}
+
+fn for_each_tar_image(
+    reader: impl Read,
+    is_image: impl Fn(&Path) -> bool,
+    mut visit: impl FnMut(&Path, u64, &mut dyn Read) -> ControlFlow<()>,
+) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    let entries = archive
+        .entries()
+        .map_err(|e| anyhow!("Invalid tar archive: {e}"))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| anyhow!("Invalid tar archive entry: {e}"))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry
+            .path()
+            .map_err(|e| anyhow!("Invalid tar archive entry: {e}"))?
+            .into_owned();
+        let escapes = path
+            .components()
+            .any(|component| !matches!(component, std::path::Component::Normal(_)));
+        if !escapes && is_image(&path) && visit(&path, entry.size(), &mut entry).is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Read an image entry of an archive, of the given size in bytes according to its header
+///
+/// At most `max_size` bytes are read, if set, whatever size the header claims.
+///
+/// # Errors
+///
+/// Returns an error if the entry cannot be read, is larger than `max_size`, or its content type
+/// cannot be determined.
+pub fn read_image(
+    path: &Path,
+    size: u64,
+    max_size: Option<u64>,
+    reader: &mut dyn Read,
+) -> Result<CacheValue> {
+    let max_size = max_size.unwrap_or(u64::MAX);
+    let too_large = || {
+        anyhow!(
+            "{} is larger than the limit of {max_size} bytes",
+            path.display()
+        )
+    };
+    if size > max_size {
+        return Err(too_large());
+    }
+    let content_type = mime_guess::from_path(path)
+        .first()
+        .ok_or_else(|| anyhow!("Failed to determine content type for {}", path.display()))?
+        .to_string();
+    // the size in the header is only trusted so far, as it may be forged
+    let capacity = size.min(max_size).min(MAX_PREALLOCATED_SIZE);
+    let mut data = Vec::with_capacity(usize::try_from(capacity).unwrap_or_default());
+    reader
+        .take(max_size.saturating_add(1))
+        .read_to_end(&mut data)
+        .map_err(|e| anyhow!("Failed to read {} from archive: {e}", path.display()))?;
+    if data.len() as u64 > max_size {
+        return Err(too_large());
+    }
+    Ok(CacheValue {
+        data,
+        content_type,
+        validators: Validators::default(),
+    })
+}
+
+/// Fetch an archive with the given client, sending the given HTTP headers
+///
+/// The download is aborted once it's larger than `max_size` bytes.
+///
+/// # Errors
+///
+/// Returns an error if the archive cannot be fetched, or is larger than `max_size`.
+#[cfg(feature = "remote-sources")]
+#[tracing::instrument(level = "debug", skip_all, fields(url = %url))]
+pub async fn fetch_archive(
+    client: &reqwest::Client,
+    url: &Url,
+    headers: &BTreeMap<String, String>,
+    max_size: u64,
+) -> Result<Vec<u8>> {
+    let response = headers
+        .iter()
+        .fold(client.get(url.as_str()), |request, (name, value)| {
+            request.header(name, value)
+        })
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to fetch archive: {e}"))?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Failed to fetch archive, status: {}",
+            response.status()
+        ));
+    }
+
+    crate::http::read_body(response, Some(max_size))
+        .await
+        .map_err(|e| anyhow!("Failed to read archive: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use std::io::{Cursor, Write};
+
+    const FILES: [(&str, &[u8]); 4] = [
+        ("cats/cat.jpg", b"cat"),
+        ("dog.PNG", b"dog"),
+        ("notes.txt", b"notes"),
+        ("cats/cat_thumb.jpg", b"thumb"),
+    ];
+
+    fn zip_archive() -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .add_directory("cats/", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        for (name, data) in FILES {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn tar_archive() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in FILES {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn tar_gz_archive() -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&tar_archive()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[rstest]
+    #[case::zip("images.zip", Some(ArchiveFormat::Zip))]
+    #[case::tar("images.tar", Some(ArchiveFormat::Tar))]
+    #[case::tar_gz("/srv/Images.TAR.GZ", Some(ArchiveFormat::TarGz))]
+    #[case::tgz("images.tgz", Some(ArchiveFormat::TarGz))]
+    #[case::gz("image.jpg.gz", None)]
+    #[case::image("image.jpg", None)]
+    fn test_archive_format(#[case] name: &str, #[case] expected: Option<ArchiveFormat>) {
+        assert_eq!(ArchiveFormat::from_name(name), expected);
+    }
+
+    #[rstest]
+    #[case::zip(zip_archive(), ArchiveFormat::Zip)]
+    #[case::tar(tar_archive(), ArchiveFormat::Tar)]
+    #[case::tar_gz(tar_gz_archive(), ArchiveFormat::TarGz)]
+    fn test_for_each_image(#[case] archive: Vec<u8>, #[case] format: ArchiveFormat) {
+        let filter = FileFilter::new(&[], &["*_thumb.jpg".to_string()]).unwrap();
+        let mut images = vec![];
+        for_each_image(
+            Cursor::new(archive),
+            format,
+            &filter,
+            &["jpg", "png"],
+            |path, size, reader| {
+                let image = read_image(path, size, None, reader).unwrap();
+                images.push((path.to_path_buf(), image));
+                ControlFlow::Continue(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].0, Path::new("cats/cat.jpg"));
+        assert_eq!(images[0].1.data, b"cat");
+        assert_eq!(images[0].1.content_type, "image/jpeg");
+        assert_eq!(images[1].0, Path::new("dog.PNG"));
+        assert_eq!(images[1].1.content_type, "image/png");
+    }
+
+    #[test]
+    fn test_for_each_image_break() {
+        let mut visited = 0;
+        for_each_image(
+            Cursor::new(zip_archive()),
+            ArchiveFormat::Zip,
+            &FileFilter::default(),
+            &["jpg", "png"],
+            |_, _, _| {
+                visited += 1;
+                ControlFlow::Break(())
+            },
+        )
+        .unwrap();
+        assert_eq!(visited, 1);
+    }
+
+    #[rstest]
+    #[case::within_limit(3, Some(3), true)]
+    #[case::no_limit(3, None, true)]
+    #[case::header_too_large(4, Some(3), false)]
+    // the header claims the entry is smaller than it is
+    #[case::content_too_large(2, Some(2), false)]
+    // the header claims the entry is far larger than it is, without a limit
+    #[case::forged_header(u64::MAX, None, true)]
+    fn test_read_image_max_size(
+        #[case] size: u64,
+        #[case] max_size: Option<u64>,
+        #[case] expected: bool,
+    ) {
+        let image = read_image(Path::new("cat.jpg"), size, max_size, &mut &b"cat"[..]);
+        assert_eq!(image.is_ok(), expected);
+    }
+
+    #[rstest]
+    #[case::zip(ArchiveFormat::Zip)]
+    #[case::tar_gz(ArchiveFormat::TarGz)]
+    fn test_for_each_image_invalid(#[case] format: ArchiveFormat) {
+        let result = for_each_image(
+            Cursor::new(b"not an archive".to_vec()),
+            format,
+            &FileFilter::default(),
+            &["jpg"],
+            |_, _, _| ControlFlow::Continue(()),
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! HTTP basic authentication of requests, against a bcrypt or argon2 password hash.
+//!
+//! Those hashes are deliberately slow to check, so they're checked on a blocking thread, and only once for each
+//! client: credentials that were verified before are remembered, as digests keyed with a random key.
+
+use std::{
+    collections::HashSet,
+    sync::{Mutex, PoisonError},
+};
+
+use anyhow::{Result, anyhow};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use hyper::{Request, header};
+
+use crate::config::HashedBasicAuth;
+
+/// The `WWW-Authenticate` header of responses to unauthenticated requests
+pub const WWW_AUTHENTICATE: &str = "Basic realm=\"random-image-server\", charset=\"UTF-8\"";
+
+/// The most verified credentials remembered, which are forgotten all at once when more are verified
+const MAX_VERIFIED_CREDENTIALS: usize = 64;
+
+/// Check a password against a bcrypt (`$2b$...`) or argon2 (`$argon2id$...`) hash
+///
+/// # Errors
+///
+/// Returns an error if the hash is neither a bcrypt nor an argon2 hash.
+pub fn verify_password(password: &str, hash: &str) -> Result<bool> {
+    if hash.starts_with("$argon2") {
+        let hash = PasswordHash::new(hash).map_err(|e| anyhow!("Invalid argon2 hash: {e}"))?;
+        if hash.hash.is_none() {
+            return Err(anyhow!("Invalid argon2 hash: the hash is missing"));
+        }
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok())
+    } else if hash.starts_with("$2") {
+        bcrypt::verify(password, hash).map_err(|e| anyhow!("Invalid bcrypt hash: {e}"))
+    } else {
+        Err(anyhow!(
+            "The password hash is neither a bcrypt nor an argon2 hash"
+        ))
+    }
+}
+
+/// Check that the password hash is a bcrypt or argon2 hash
+///
+/// # Errors
+///
+/// Returns an error if the hash can't be used to check passwords.
+pub fn validate_password_hash(hash: &str) -> Result<()> {
+    verify_password("", hash).map(|_| ())
+}
+
+/// Compare two byte strings in time independent of their contents
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// The credentials that passed verification before
+///
+/// Only digests of them are kept, keyed with a random key along with the configured username and hash, so that
+/// credentials verified against a replaced hash aren't accepted, and the passwords can't be recovered from memory.
+#[derive(Debug)]
+pub struct VerifiedCredentials {
+    key: [u8; blake3::KEY_LEN],
+    digests: Mutex<HashSet<blake3::Hash>>,
+}
+
+impl Default for VerifiedCredentials {
+    fn default() -> Self {
+        Self {
+            key: rand::random(),
+            digests: Mutex::default(),
+        }
+    }
+}
+
+impl VerifiedCredentials {
+    /// The digest of the decoded credentials of a request, checked against the given configuration
+    fn digest(&self, credentials: &str, auth: &HashedBasicAuth) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new_keyed(&self.key);
+        for part in [&auth.username, &auth.password_hash, credentials] {
+            hasher.update(&(part.len() as u64).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+        hasher.finalize()
+    }
+
+    fn contains(&self, digest: &blake3::Hash) -> bool {
+        self.digests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains(digest)
+    }
+
+    fn insert(&self, digest: blake3::Hash) {
+        let mut digests = self.digests.lock().unwrap_or_else(PoisonError::into_inner);
+        if digests.len() >= MAX_VERIFIED_CREDENTIALS {
+            digests.clear();
+        }
+        digests.insert(digest);
+    }
+}
+
+/// Whether a request carries the configured basic authentication credentials
+///
+/// The password is checked against its hash on a blocking thread, unless the same credentials were verified before.
+pub async fn is_authenticated<B>(
+    req: &Request<B>,
+    auth: &HashedBasicAuth,
+    verified: &VerifiedCredentials,
+) -> bool {
+    let Some(credentials) = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+    else {
+        return false;
+    };
+    let digest = verified.digest(&credentials, auth);
+    if verified.contains(&digest) {
+        return true;
+    }
+    let Some((username, password)) = credentials.split_once(':') else {
+        return false;
+    };
+    // the password is checked even if the username is wrong, so both take as long
+    let username_matches = constant_time_eq(username.as_bytes(), auth.username.as_bytes());
+    let (password, hash) = (password.to_string(), auth.password_hash.clone());
+    let verify = tokio::task::spawn_blocking(move || verify_password(&password, &hash));
+    match verify.await.map_err(anyhow::Error::from).flatten() {
+        Ok(password_matches) => {
+            let authenticated = username_matches && password_matches;
+            if authenticated {
+                verified.insert(digest);
+            }
+            authenticated
+        }
+        Err(e) => {
+            tracing::error!("Failed to check basic authentication credentials: {e}");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    /// `secret`, hashed with the lowest bcrypt cost so that tests are fast
+    const BCRYPT_HASH: &str = "$2b$04$tXSlArtfTOTXHXfydJFsoOYAS5ytxSUx6yTwYpMDPQoFvOAqr9pmy";
+    /// `secret`, hashed with argon2id and small parameters so that tests are fast
+    const ARGON2_HASH: &str =
+        "$argon2id$v=19$m=16,t=2,p=1$c2FsdHNhbHQ$865AUeURr6n8HtD+KV9954Buy8pBRcZagkPkr9xKlao";
+
+    fn request(authorization: Option<&str>) -> Request<()> {
+        let mut builder = Request::builder();
+        if let Some(authorization) = authorization {
+            builder = builder.header(header::AUTHORIZATION, authorization);
+        }
+        builder.body(()).unwrap()
+    }
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", STANDARD.encode(credentials))
+    }
+
+    #[rstest]
+    #[case::bcrypt(BCRYPT_HASH)]
+    #[case::argon2(ARGON2_HASH)]
+    fn test_verify_password(#[case] hash: &str) {
+        assert!(verify_password("secret", hash).unwrap());
+        assert!(!verify_password("wrong", hash).unwrap());
+        assert!(validate_password_hash(hash).is_ok());
+    }
+
+    #[rstest]
+    #[case::plain_text("secret")]
+    #[case::truncated_bcrypt("$2b$04$2DCc")]
+    #[case::truncated_argon2("$argon2id$v=19")]
+    fn test_invalid_password_hash(#[case] hash: &str) {
+        assert!(validate_password_hash(hash).is_err());
+    }
+
+    #[rstest]
+    #[case::valid(Some(basic("admin:secret")), true)]
+    #[case::missing(None, false)]
+    #[case::wrong_password(Some(basic("admin:wrong")), false)]
+    #[case::wrong_username(Some(basic("root:secret")), false)]
+    #[case::no_colon(Some(basic("admin")), false)]
+    #[case::not_basic(Some("Bearer token".to_string()), false)]
+    #[case::not_base64(Some("Basic !!!".to_string()), false)]
+    #[tokio::test]
+    async fn test_is_authenticated(#[case] authorization: Option<String>, #[case] expected: bool) {
+        let auth = HashedBasicAuth {
+            username: "admin".to_string(),
+            password_hash: BCRYPT_HASH.to_string(),
+        };
+        let verified = VerifiedCredentials::default();
+        let req = request(authorization.as_deref());
+        // the second time, from the verified credentials
+        for _ in 0..2 {
+            assert_eq!(is_authenticated(&req, &auth, &verified).await, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verified_credentials_replaced_hash() {
+        let auth = HashedBasicAuth {
+            username: "admin".to_string(),
+            password_hash: BCRYPT_HASH.to_string(),
+        };
+        let verified = VerifiedCredentials::default();
+        let req = request(Some(&basic("admin:secret")));
+        assert!(is_authenticated(&req, &auth, &verified).await);
+
+        // `secret` is no longer the password
+        let auth = HashedBasicAuth {
+            password_hash: bcrypt::hash("changed", 4).unwrap(),
+            ..auth
+        };
+        assert!(!is_authenticated(&req, &auth, &verified).await);
+    }
+}
@@ -0,0 +1,24 @@
+use random_image_server::load_test::{LoadTestArgs, run};
+
+use anyhow::Result;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args();
+    let program = args.next().unwrap_or_default();
+    let args = match LoadTestArgs::parse(args) {
+        Ok(args) if !args.help => args,
+        Ok(_) => {
+            eprintln!("{}", LoadTestArgs::usage(&program));
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{}", LoadTestArgs::usage(&program));
+            std::process::exit(2);
+        }
+    };
+
+    let report = run(&args).await?;
+    println!("{report}");
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! The bodies of the responses sent by the server.
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use http_body_util::{BodyExt, Full, combinators::BoxBody};
+use hyper::body::{Bytes, Frame, SizeHint};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// The body of a response, either held in memory or streamed from a file
+pub type Body = BoxBody<Bytes, io::Error>;
+
+/// A body holding the given bytes in memory
+#[must_use]
+pub fn full(data: impl Into<Bytes>) -> Body {
+    Full::new(data.into())
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+/// An empty body
+#[must_use]
+pub fn empty() -> Body {
+    full(Bytes::new())
+}
+
+/// A body serving a file mapped into memory straight from the page cache, without copying it
+#[must_use]
+pub fn mapped(map: memmap2::Mmap) -> Body {
+    full(Bytes::from_owner(map))
+}
+
+/// A body streaming the first `len` bytes of the given file
+#[must_use]
+pub fn file(file: std::fs::File, len: u64) -> Body {
+    FileBody::new(file, len).boxed()
+}
+
+/// A body that streams a file in chunks, so that it is never read into memory as a whole
+#[derive(Debug)]
+pub struct FileBody {
+    file: tokio::fs::File,
+    /// The number of bytes of the file that are yet to be sent
+    remaining: u64,
+    buffer: Vec<u8>,
+}
+
+impl FileBody {
+    /// The most bytes read from the file at once
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    /// Stream the first `len` bytes of the given file
+    #[must_use]
+    pub fn new(file: std::fs::File, len: u64) -> Self {
+        Self {
+            file: tokio::fs::File::from_std(file),
+            remaining: len,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl hyper::body::Body for FileBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        if this.remaining == 0 {
+            return Poll::Ready(None);
+        }
+        let chunk = usize::try_from(this.remaining).map_or(Self::CHUNK_SIZE, |remaining| {
+            remaining.min(Self::CHUNK_SIZE)
+        });
+        this.buffer.resize(chunk, 0);
+        let mut buffer = ReadBuf::new(&mut this.buffer);
+        ready!(Pin::new(&mut this.file).poll_read(cx, &mut buffer))?;
+        let read = buffer.filled();
+        if read.is_empty() {
+            return Poll::Ready(Some(Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The file was truncated while it was being sent",
+            ))));
+        }
+        this.remaining -= read.len() as u64;
+        Poll::Ready(Some(Ok(Frame::data(Bytes::copy_from_slice(read)))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::body::Body as _;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn test_file_body_streams_file() {
+        let data: Vec<u8> = (0..=255)
+            .cycle()
+            .take(FileBody::CHUNK_SIZE * 2 + 10)
+            .collect();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&data).unwrap();
+        std::io::Seek::rewind(&mut file).unwrap();
+
+        let body = FileBody::new(file, data.len() as u64);
+        assert_eq!(body.size_hint().exact(), Some(data.len() as u64));
+        let collected = body.collect().await.unwrap().to_bytes();
+        assert_eq!(collected.as_ref(), data.as_slice());
+    }
+
+    #[tokio::test]
+    async fn test_file_body_truncated_file() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&[1, 2, 3]).unwrap();
+        std::io::Seek::rewind(&mut file).unwrap();
+
+        let body = FileBody::new(file, 10);
+        assert!(body.collect().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_full_body() {
+        let body = full("hello");
+        assert_eq!(body.collect().await.unwrap().to_bytes(), "hello");
+        assert!(empty().is_end_stream());
+    }
+}
@@ -0,0 +1,295 @@
+//! Negative caching and circuit breaking for remote sources, so that a dead host isn't hammered with requests.
+//!
+//! Each URL that fails to be fetched is backed off exponentially, with jitter, up to `max_backoff` seconds.
+//! Once `failure_threshold` consecutive fetches from a host have failed, its circuit opens and every
+//! request to it is skipped, except for a probe every `max_backoff` seconds. A successful fetch closes it.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow};
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::Serialize;
+use url::Url;
+
+use crate::config::HttpConfig;
+
+/// The backoff after the first failure, doubled after each consecutive failure
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The state of the circuit breaker of a host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Requests are sent, the host has failed fewer than `failure_threshold` times in a row
+    Closed,
+    /// Requests are skipped until the host is probed again
+    Open,
+    /// The next request is a probe, which closes the circuit if it succeeds
+    HalfOpen,
+}
+
+/// The circuit breaker of a host that recently failed, reported by `/health`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct HostHealth {
+    /// The origin of the host, e.g. `https://example.com`
+    pub host: String,
+    pub state: BreakerState,
+    /// The number of fetches from the host that failed in a row
+    pub consecutive_failures: u32,
+    /// How long until the host is probed again, in seconds, if its circuit is open
+    pub retry_in: Option<u64>,
+}
+
+/// Consecutive failures of a URL or host
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    consecutive: u32,
+    retry_at: Instant,
+}
+
+/// The fetch failures of every URL and host, used to decide which requests to skip
+#[derive(Debug)]
+pub struct CircuitBreakers {
+    failure_threshold: u32,
+    max_backoff: Duration,
+    urls: HashMap<Url, Failures>,
+    hosts: HashMap<String, Failures>,
+}
+
+impl Default for CircuitBreakers {
+    fn default() -> Self {
+        Self::new(&HttpConfig::default())
+    }
+}
+
+impl CircuitBreakers {
+    #[must_use]
+    pub fn new(config: &HttpConfig) -> Self {
+        Self {
+            failure_threshold: config.failure_threshold,
+            max_backoff: Duration::from_secs(config.max_backoff),
+            urls: HashMap::new(),
+            hosts: HashMap::new(),
+        }
+    }
+
+    /// Check whether the URL may be fetched now
+    ///
+    /// # Errors
+    ///
+    /// Returns an error explaining why the fetch is skipped, if the URL is backed off or its host's circuit is open.
+    pub fn check(&self, url: &Url, now: Instant) -> Result<()> {
+        let host = host(url);
+        if let Some(failures) = self.hosts.get(&host)
+            && self.is_tripped(failures)
+            && now < failures.retry_at
+        {
+            return Err(anyhow!(
+                "Circuit open for {host} after {} consecutive failures, retrying in {}s",
+                failures.consecutive,
+                seconds_until(failures.retry_at, now)
+            ));
+        }
+        if let Some(failures) = self.urls.get(url)
+            && now < failures.retry_at
+        {
+            return Err(anyhow!(
+                "Backing off after {} consecutive failures, retrying in {}s",
+                failures.consecutive,
+                seconds_until(failures.retry_at, now)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Record that the URL was fetched, closing its host's circuit
+    pub fn record_success(&mut self, url: &Url) {
+        self.urls.remove(url);
+        if self.hosts.remove(&host(url)).is_some() {
+            tracing::info!("Circuit closed for {}", host(url));
+        }
+    }
+
+    /// Record that the URL failed to be fetched, backing it off and opening its host's circuit if it keeps failing
+    pub fn record_failure(&mut self, url: &Url, now: Instant) {
+        let failures = self.urls.entry(url.clone()).or_insert(Failures {
+            consecutive: 0,
+            retry_at: now,
+        });
+        failures.consecutive += 1;
+        failures.retry_at = now + jitter(backoff(failures.consecutive, self.max_backoff));
+
+        let host = host(url);
+        let failures = self.hosts.entry(host.clone()).or_insert(Failures {
+            consecutive: 0,
+            retry_at: now,
+        });
+        failures.consecutive += 1;
+        if self.failure_threshold > 0 && failures.consecutive >= self.failure_threshold {
+            if failures.consecutive == self.failure_threshold {
+                tracing::warn!(
+                    "Circuit open for {host} after {} consecutive failures",
+                    failures.consecutive
+                );
+            }
+            failures.retry_at = now + jitter(self.max_backoff);
+        }
+    }
+
+    /// The circuit breakers of the hosts that recently failed, ordered by host
+    #[must_use]
+    pub fn hosts(&self, now: Instant) -> Vec<HostHealth> {
+        let mut hosts: Vec<HostHealth> = self
+            .hosts
+            .iter()
+            .map(|(host, failures)| {
+                let state = match (self.is_tripped(failures), now < failures.retry_at) {
+                    (false, _) => BreakerState::Closed,
+                    (true, true) => BreakerState::Open,
+                    (true, false) => BreakerState::HalfOpen,
+                };
+                HostHealth {
+                    host: host.clone(),
+                    state,
+                    consecutive_failures: failures.consecutive,
+                    retry_in: (state == BreakerState::Open)
+                        .then(|| seconds_until(failures.retry_at, now)),
+                }
+            })
+            .collect();
+        hosts.sort_by(|a, b| a.host.cmp(&b.host));
+        hosts
+    }
+
+    const fn is_tripped(&self, failures: &Failures) -> bool {
+        self.failure_threshold > 0 && failures.consecutive >= self.failure_threshold
+    }
+}
+
+/// The host a URL is fetched from, as its origin
+fn host(url: &Url) -> String {
+    url.origin().ascii_serialization()
+}
+
+/// The backoff after the given number of consecutive failures, doubling from `BASE_BACKOFF` up to `max_backoff`
+fn backoff(consecutive: u32, max_backoff: Duration) -> Duration {
+    BASE_BACKOFF
+        .checked_mul(2u32.saturating_pow(consecutive.saturating_sub(1)))
+        .map_or(max_backoff, |backoff| backoff.min(max_backoff))
+}
+
+/// A random duration between half of and the whole given duration, so that retries are spread out
+fn jitter(duration: Duration) -> Duration {
+    duration.mul_f64(rand::rng().random_range(0.5..=1.0))
+}
+
+fn seconds_until(instant: Instant, now: Instant) -> u64 {
+    instant.saturating_duration_since(now).as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    fn breakers(failure_threshold: u32) -> CircuitBreakers {
+        CircuitBreakers::new(&HttpConfig {
+            failure_threshold,
+            max_backoff: 60,
+            ..HttpConfig::default()
+        })
+    }
+
+    #[rstest]
+    #[case::first(1, 1)]
+    #[case::second(2, 2)]
+    #[case::fourth(4, 8)]
+    #[case::capped(10, 60)]
+    #[case::overflow(u32::MAX, 60)]
+    fn test_backoff(#[case] consecutive: u32, #[case] expected: u64) {
+        assert_eq!(
+            backoff(consecutive, Duration::from_secs(60)),
+            Duration::from_secs(expected)
+        );
+    }
+
+    #[test]
+    fn test_url_backoff() {
+        let mut breakers = breakers(5);
+        let url = Url::parse("https://example.com/a.jpg").unwrap();
+        let other = Url::parse("https://example.com/b.jpg").unwrap();
+        let now = Instant::now();
+
+        assert!(breakers.check(&url, now).is_ok());
+        breakers.record_failure(&url, now);
+        assert!(breakers.check(&url, now).is_err());
+        // other URLs on the same host are still fetched, until the circuit opens
+        assert!(breakers.check(&other, now).is_ok());
+        assert!(breakers.check(&url, now + Duration::from_secs(2)).is_ok());
+
+        breakers.record_success(&url);
+        assert!(breakers.check(&url, now).is_ok());
+        assert!(breakers.hosts(now).is_empty());
+    }
+
+    #[test]
+    fn test_circuit_opens() {
+        let mut breakers = breakers(2);
+        let url = Url::parse("https://example.com/a.jpg").unwrap();
+        let other = Url::parse("https://example.com/b.jpg").unwrap();
+        let now = Instant::now();
+
+        breakers.record_failure(&url, now);
+        assert_eq!(breakers.hosts(now)[0].state, BreakerState::Closed);
+        breakers.record_failure(&other, now);
+
+        let hosts = breakers.hosts(now);
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].host, "https://example.com");
+        assert_eq!(hosts[0].state, BreakerState::Open);
+        assert_eq!(hosts[0].consecutive_failures, 2);
+        assert!(hosts[0].retry_in.is_some_and(|retry_in| retry_in <= 60));
+        let error = breakers
+            .check(&Url::parse("https://example.com/c.jpg").unwrap(), now)
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("Circuit open for https://example.com")
+        );
+        // other hosts are unaffected
+        assert!(
+            breakers
+                .check(&Url::parse("https://example.org/a.jpg").unwrap(), now)
+                .is_ok()
+        );
+
+        // the host is probed again after the maximum backoff
+        let later = now + Duration::from_secs(61);
+        assert_eq!(breakers.hosts(later)[0].state, BreakerState::HalfOpen);
+        assert!(breakers.check(&url, later).is_ok());
+        breakers.record_success(&url);
+        assert!(breakers.hosts(later).is_empty());
+    }
+
+    #[test]
+    fn test_circuit_disabled() {
+        let mut breakers = breakers(0);
+        let url = Url::parse("https://example.com/a.jpg").unwrap();
+        let now = Instant::now();
+        for _ in 0..10 {
+            breakers.record_failure(&url, now);
+        }
+        assert_eq!(breakers.hosts(now)[0].state, BreakerState::Closed);
+        assert!(
+            breakers
+                .check(&Url::parse("https://example.com/b.jpg").unwrap(), now)
+                .is_ok()
+        );
+    }
+}
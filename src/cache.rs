@@ -1,9 +1,26 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, LazyLock, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+};
 
 use rand::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use tempfile::TempDir;
 use url::Url;
 
+use crate::config::{CacheConfig, Compression, EvictionPolicy, HashAlgorithm};
+
+pub mod testing;
+
+/// A cache of images
+///
+/// Backends synchronize themselves, so that images can be read while others are being stored.
 pub trait CacheBackend: std::fmt::Debug + Send + Sync {
     /// report the type of the cache backend
     fn backend_type(&self) -> &'static str;
@@ -16,56 +33,547 @@ pub trait CacheBackend: std::fmt::Debug + Send + Sync {
     /// Get an image from the cache by its key
     fn get(&self, key: CacheKey) -> Option<CacheValue>;
 
-    /// Get a random image from the cache
-    fn get_random(&self) -> Option<CacheValue>;
+    /// Get a random image from the cache, along with its key
+    fn get_random(&self) -> Option<(CacheKey, CacheValue)>;
+
+    /// Open the file an image is stored in, so that it can be streamed instead of read into memory
+    ///
+    /// Only backends that store images as they are, in files, support this. Backends that check the
+    /// content of their files against its hash, as `get` does, check it the first time a file is opened.
+    fn open(&self, _key: &CacheKey) -> Option<CacheFile> {
+        None
+    }
+
+    /// Get the HTTP validators stored with an image, without reading the image itself
+    fn validators(&self, key: &CacheKey) -> Option<Validators> {
+        self.get(key.clone()).map(|image| image.validators)
+    }
+
+    /// Get the content type of an image, without reading the image itself
+    fn content_type(&self, key: &CacheKey) -> Option<String> {
+        self.get(key.clone()).map(|image| image.content_type)
+    }
+
+    /// Get an image fetched from a URL by a previous run, that was spooled to disk, even if it isn't in the cache
+    fn spooled(&self, _key: &CacheKey) -> Option<CacheValue> {
+        None
+    }
 
     /// Store an image in the cache with its key
     ///
     /// # Errors
     ///
     /// Returns an error if the image cannot be stored (e.g. due to size limits), or if the image is invalid
-    fn set(&mut self, key: CacheKey, image: CacheValue) -> Result<(), String>;
+    fn set(&self, key: CacheKey, image: CacheValue) -> Result<(), String>;
+
+    /// Store an image in the cache with its key, returning the keys of the images evicted to make room for it
+    ///
+    /// Backends that never evict images only need to implement `set`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image cannot be stored, as `set` does
+    fn set_evicting(&self, key: CacheKey, image: CacheValue) -> Result<Vec<CacheKey>, String> {
+        self.set(key, image).map(|()| Vec::new())
+    }
 
     /// Remove an image from the cache by its key
-    fn remove(&mut self, key: &CacheKey) -> Option<CacheValue>;
+    fn remove(&self, key: &CacheKey) -> Option<CacheValue>;
 
     /// Get the size of the cache
     fn size(&self) -> usize;
 
+    /// Get the total size of the images in the cache, in bytes
+    fn bytes(&self) -> u64 {
+        self.keys()
+            .filter_map(|key| self.get(key))
+            .map(|image| image.data.len() as u64)
+            .sum()
+    }
+
     /// Check if the cache is empty
     fn is_empty(&self) -> bool {
         self.size() == 0
     }
 
-    /// Retrieve the keys in the cache
-    fn keys(&self) -> &[CacheKey];
+    /// Iterate over the keys of the images in the cache, in the order they were stored, with their metadata
+    ///
+    /// The images themselves are not read. Images stored or removed while iterating may be missed.
+    fn iter(&self) -> Box<dyn Iterator<Item = (CacheKey, CacheMetadata)> + '_>;
+
+    /// Iterate over the keys of the images in the cache, in the order they were stored
+    fn keys(&self) -> Box<dyn Iterator<Item = CacheKey> + '_> {
+        Box::new(self.iter().map(|(key, _)| key))
+    }
+
+    /// Iterate over the keys of the images in the cache with one of the given content types,
+    /// grouped by content type, each in the order they were stored
+    ///
+    /// Backends that index their images by content type find them without visiting every image.
+    fn keys_of_types(&self, content_types: &[String]) -> Box<dyn Iterator<Item = CacheKey> + '_> {
+        let mut keys: Vec<(usize, CacheKey)> = self
+            .iter()
+            .filter_map(|(key, metadata)| {
+                let position = content_types
+                    .iter()
+                    .position(|content_type| *content_type == metadata.content_type)?;
+                Some((position, key))
+            })
+            .collect();
+        keys.sort_by_key(|(position, _)| *position);
+        Box::new(keys.into_iter().map(|(_, key)| key))
+    }
 
     /// Clear the cache
     ///
     /// # Errors
     ///
     /// Returns an error if the cache cannot be cleared.
-    fn clear(&mut self) -> Result<(), String>;
+    fn clear(&self) -> Result<(), String>;
+
+    /// Counters of the lookups, failed integrity checks, and evictions of the cache
+    fn stats(&self) -> CacheStats {
+        CacheStats::default()
+    }
+
+    /// Check that a stored image hasn't been corrupted at rest, removing it from the cache if it has
+    ///
+    /// Returns `false` if the image was removed. Backends that don't check the integrity of
+    /// what they store consider every image valid.
+    fn revalidate(&self, _key: &CacheKey) -> bool {
+        true
+    }
+
+    /// Write every image in the cache, with its key and metadata, to a snapshot archive,
+    /// returning how many images were written
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive cannot be written.
+    fn export(&self, path: &Path) -> Result<usize, String> {
+        crate::snapshot::export(self, path)
+    }
+
+    /// Store every image of a snapshot archive written by `export` in the cache,
+    /// returning how many images were stored
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive cannot be read or is malformed.
+    fn import(&self, path: &Path) -> Result<usize, String> {
+        crate::snapshot::import(self, path)
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// The type-erased form of a cache backend, chosen at runtime from the configuration
+///
+/// New caches are in memory, the default backend.
+impl CacheBackend for Box<dyn CacheBackend> {
+    fn backend_type(&self) -> &'static str {
+        (**self).backend_type()
+    }
+
+    fn new() -> Self {
+        Box::new(InMemoryCache::new())
+    }
+
+    fn get(&self, key: CacheKey) -> Option<CacheValue> {
+        (**self).get(key)
+    }
+
+    fn get_random(&self) -> Option<(CacheKey, CacheValue)> {
+        (**self).get_random()
+    }
+
+    fn open(&self, key: &CacheKey) -> Option<CacheFile> {
+        (**self).open(key)
+    }
+
+    fn validators(&self, key: &CacheKey) -> Option<Validators> {
+        (**self).validators(key)
+    }
+
+    fn content_type(&self, key: &CacheKey) -> Option<String> {
+        (**self).content_type(key)
+    }
+
+    fn spooled(&self, key: &CacheKey) -> Option<CacheValue> {
+        (**self).spooled(key)
+    }
+
+    fn set(&self, key: CacheKey, image: CacheValue) -> Result<(), String> {
+        (**self).set(key, image)
+    }
+
+    fn set_evicting(&self, key: CacheKey, image: CacheValue) -> Result<Vec<CacheKey>, String> {
+        (**self).set_evicting(key, image)
+    }
+
+    fn remove(&self, key: &CacheKey) -> Option<CacheValue> {
+        (**self).remove(key)
+    }
+
+    fn size(&self) -> usize {
+        (**self).size()
+    }
+
+    fn bytes(&self) -> u64 {
+        (**self).bytes()
+    }
+
+    fn is_empty(&self) -> bool {
+        (**self).is_empty()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (CacheKey, CacheMetadata)> + '_> {
+        (**self).iter()
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = CacheKey> + '_> {
+        (**self).keys()
+    }
+
+    fn keys_of_types(&self, content_types: &[String]) -> Box<dyn Iterator<Item = CacheKey> + '_> {
+        (**self).keys_of_types(content_types)
+    }
+
+    fn clear(&self) -> Result<(), String> {
+        (**self).clear()
+    }
+
+    fn stats(&self) -> CacheStats {
+        (**self).stats()
+    }
+
+    fn revalidate(&self, key: &CacheKey) -> bool {
+        (**self).revalidate(key)
+    }
+
+    fn export(&self, path: &Path) -> Result<usize, String> {
+        (**self).export(path)
+    }
+
+    fn import(&self, path: &Path) -> Result<usize, String> {
+        (**self).import(path)
+    }
+}
+
+/// Creates a cache backend registered with `register_backend`, from the cache settings of the configuration
+pub type BackendFactory = Arc<dyn Fn(&CacheConfig) -> Box<dyn CacheBackend> + Send + Sync>;
+
+/// The cache backends registered by name, selected with `cache.backend = "<name>"`
+static BACKENDS: LazyLock<RwLock<HashMap<String, BackendFactory>>> = LazyLock::new(RwLock::default);
+
+/// Register a cache backend under a name, so that it can be selected from the configuration
+/// with `cache.backend = "<name>"`, replacing any backend previously registered under that name
+///
+/// Backends must be registered before the server state is created from the configuration.
+pub fn register_backend(
+    name: impl Into<String>,
+    factory: impl Fn(&CacheConfig) -> Box<dyn CacheBackend> + Send + Sync + 'static,
+) {
+    write(&BACKENDS).insert(name.into(), Arc::new(factory));
+}
+
+/// The factory of the cache backend registered under the given name, if any
+#[must_use]
+pub fn registered_backend(name: &str) -> Option<BackendFactory> {
+    read(&BACKENDS).get(name).cloned()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum CacheKey {
     /// Cache key for an image URL
     ImageUrl(Url),
     /// Cache key for an image path
     ImagePath(PathBuf),
+    /// Cache key for an image generated by the server, e.g. a placeholder, named after what it was generated from
+    Generated(String),
+}
+
+impl std::fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ImageUrl(url) => write!(f, "{url}"),
+            Self::ImagePath(path) => write!(f, "{}", path.display()),
+            Self::Generated(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl CacheKey {
+    /// An opaque, stable identifier for this key that is safe to expose publicly
+    #[must_use]
+    pub fn id(&self) -> String {
+        format!("{:x}", md5::compute(self.to_string()))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CacheValue {
     pub data: Vec<u8>,
     pub content_type: String,
+    /// The HTTP validators of an image fetched from a URL, used to refresh it conditionally
+    pub validators: Validators,
+}
+
+/// A cached image stored as it is in a file, opened to be streamed
+#[derive(Debug)]
+pub struct CacheFile {
+    pub file: fs::File,
+    /// The file mapped into memory, served from the page cache rather than streamed, if the backend maps its files
+    pub map: Option<memmap2::Mmap>,
+    /// The size of the file, in bytes
+    pub len: u64,
+    pub content_type: String,
+    /// The hash of the content of the file, with the hash algorithm of the cache
+    pub hash: String,
+}
+
+/// What is known about a cached image without reading it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheMetadata {
+    pub content_type: String,
+    pub validators: Validators,
+}
+
+impl From<&CacheValue> for CacheMetadata {
+    fn from(image: &CacheValue) -> Self {
+        Self {
+            content_type: image.content_type.clone(),
+            validators: image.validators.clone(),
+        }
+    }
+}
+
+/// The `ETag` and `Last-Modified` headers an image was served with
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// Whether there are no validators to make a conditional request with
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Counters of how a cache has been used, reported by `/metrics` and `/stats/cache`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct CacheStats {
+    /// Lookups that found the image
+    pub hits: u64,
+    /// Lookups that didn't find the image
+    pub misses: u64,
+    /// Images that failed their integrity check when read, and were dropped
+    pub validation_failures: u64,
+    /// Images removed to make room for others
+    pub evictions: u64,
+}
+
+/// The counters behind `CacheStats`, updated by a backend as it is used
+#[derive(Debug, Default)]
+pub struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    validation_failures: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl CacheCounters {
+    /// Record the result of a lookup, as a hit if it found an image
+    pub fn record_lookup<T>(&self, found: Option<T>) -> Option<T> {
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    /// Record that an image failed its integrity check
+    pub fn record_validation_failure(&self) {
+        self.validation_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that an image was evicted
+    pub fn record_eviction(&self) {
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The current value of the counters
+    #[must_use]
+    pub fn snapshot(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            validation_failures: self.validation_failures.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The keys of a cache, in the order they were stored
+///
+/// Iterators hold a snapshot of the keys, which is copied if the cache changes while they are alive,
+/// so that iterating doesn't hold the lock of the cache.
+#[derive(Debug, Default)]
+struct KeyList(Arc<Vec<CacheKey>>);
+
+impl KeyList {
+    fn push(&mut self, key: CacheKey) {
+        Arc::make_mut(&mut self.0).push(key);
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if self.0.contains(key) {
+            Arc::make_mut(&mut self.0).retain(|k| k != key);
+        }
+    }
+
+    fn contains(&self, key: &CacheKey) -> bool {
+        self.0.contains(key)
+    }
+
+    fn first_except(&self, key: &CacheKey) -> Option<&CacheKey> {
+        self.0.iter().find(|k| *k != key)
+    }
+
+    fn clear(&mut self) {
+        self.0 = Arc::default();
+    }
+
+    fn choose(&self) -> Option<CacheKey> {
+        self.0.choose(&mut rand::rng()).cloned()
+    }
+
+    fn snapshot(&self) -> Keys {
+        Keys {
+            keys: Arc::clone(&self.0),
+            next: 0,
+        }
+    }
+}
+
+/// An iterator over a snapshot of the keys of a cache
+#[derive(Debug)]
+struct Keys {
+    keys: Arc<Vec<CacheKey>>,
+    next: usize,
+}
+
+impl Iterator for Keys {
+    type Item = CacheKey;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.keys.get(self.next)?.clone();
+        self.next += 1;
+        Some(key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.keys.len().saturating_sub(self.next);
+        (remaining, Some(remaining))
+    }
+}
+
+/// The keys of a cache by the content type of their images, each in the order they were stored,
+/// so that the images of some types can be found without visiting every image
+#[derive(Debug, Default)]
+struct ContentTypeIndex(HashMap<String, KeyList>);
+
+impl ContentTypeIndex {
+    /// Index a key under the content type of its image, instead of the type it was indexed under, if any
+    fn insert(&mut self, key: &CacheKey, content_type: &str, previous: Option<&str>) {
+        if let Some(previous) = previous {
+            self.remove(key, previous);
+        }
+        let keys = self.0.entry(content_type.to_string()).or_default();
+        if !keys.contains(key) {
+            keys.push(key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &CacheKey, content_type: &str) {
+        if let Some(keys) = self.0.get_mut(content_type) {
+            keys.remove(key);
+            if keys.0.is_empty() {
+                self.0.remove(content_type);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// A snapshot of the keys of the images of the given content types, grouped by content type
+    fn snapshot(&self, content_types: &[String]) -> impl Iterator<Item = CacheKey> + use<> {
+        content_types
+            .iter()
+            .filter_map(|content_type| self.0.get(content_type))
+            .map(KeyList::snapshot)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flatten()
+    }
+}
+
+/// Acquire a read lock, even if another thread panicked while holding it
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Acquire a write lock, even if another thread panicked while holding it
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The images of an `InMemoryCache`
+#[derive(Debug, Default)]
+struct InMemoryImages {
+    keys: KeyList,
+    by_type: ContentTypeIndex,
+    images: HashMap<CacheKey, CacheValue>,
+    /// The total size of the cached images, in bytes
+    bytes: u64,
+}
+
+impl InMemoryImages {
+    fn remove(&mut self, key: &CacheKey) -> Option<CacheValue> {
+        self.keys.remove(key);
+        let image = self.images.remove(key)?;
+        self.by_type.remove(key, &image.content_type);
+        self.bytes -= image.data.len() as u64;
+        Some(image)
+    }
 }
 
 #[derive(Debug)]
 pub struct InMemoryCache {
-    keys: Vec<CacheKey>,
-    cache: HashMap<CacheKey, CacheValue>,
+    images: RwLock<InMemoryImages>,
+    /// The most bytes of images the cache may hold
+    max_bytes: u64,
+    /// What to do when storing an image would exceed `max_bytes`
+    eviction: EvictionPolicy,
+    counters: CacheCounters,
+}
+
+impl InMemoryCache {
+    /// Create a new in-memory cache that holds at most `max_bytes` of images,
+    /// rejecting images or evicting the oldest ones when it is full, depending on the policy
+    #[must_use]
+    pub fn with_max_bytes(max_bytes: u64, eviction: EvictionPolicy) -> Self {
+        Self {
+            max_bytes,
+            eviction,
+            ..Self::new()
+        }
+    }
 }
 
 // Implement Default for InMemoryCache specifically
@@ -82,179 +590,1053 @@ impl CacheBackend for InMemoryCache {
 
     fn new() -> Self {
         Self {
-            cache: HashMap::new(),
-            keys: Vec::new(),
+            images: RwLock::default(),
+            max_bytes: u64::MAX,
+            eviction: EvictionPolicy::default(),
+            counters: CacheCounters::default(),
         }
     }
 
+    #[tracing::instrument(name = "cache_get", level = "debug", skip_all, fields(backend = "InMemory", key = %key))]
     fn get(&self, key: CacheKey) -> Option<CacheValue> {
-        self.cache.get(&key).cloned()
+        let image = read(&self.images).images.get(&key).cloned();
+        self.counters.record_lookup(image)
+    }
+
+    fn get_random(&self) -> Option<(CacheKey, CacheValue)> {
+        let images = read(&self.images);
+        let random = images
+            .keys
+            .choose()
+            .and_then(|key| images.images.get(&key).cloned().map(|image| (key, image)));
+        drop(images);
+        self.counters.record_lookup(random)
+    }
+
+    fn validators(&self, key: &CacheKey) -> Option<Validators> {
+        read(&self.images)
+            .images
+            .get(key)
+            .map(|image| image.validators.clone())
+    }
+
+    fn content_type(&self, key: &CacheKey) -> Option<String> {
+        read(&self.images)
+            .images
+            .get(key)
+            .map(|image| image.content_type.clone())
     }
 
-    fn get_random(&self) -> Option<CacheValue> {
-        let keys: Vec<&CacheKey> = self.cache.keys().collect();
-        keys.choose(&mut rand::rng())
-            .and_then(|&random_key| self.cache.get(random_key).cloned())
+    fn set(&self, key: CacheKey, image: CacheValue) -> Result<(), String> {
+        self.set_evicting(key, image).map(drop)
     }
 
-    fn set(&mut self, key: CacheKey, image: CacheValue) -> Result<(), String> {
-        if !self.keys.contains(&key) {
-            self.keys.push(key.clone());
+    #[tracing::instrument(name = "cache_set", level = "debug", skip_all, fields(backend = "InMemory", key = %key))]
+    fn set_evicting(&self, key: CacheKey, image: CacheValue) -> Result<Vec<CacheKey>, String> {
+        let size = image.data.len() as u64;
+        if size > self.max_bytes {
+            return Err(format!(
+                "Image of {size} bytes is larger than the cache's budget of {} bytes",
+                self.max_bytes
+            ));
         }
-        self.cache.insert(key, image);
-        Ok(())
+        let mut images = write(&self.images);
+        let replaced = images
+            .images
+            .get(&key)
+            .map_or(0, |image| image.data.len() as u64);
+        let needed = (images.bytes - replaced).saturating_add(size);
+        let mut evicted = Vec::new();
+        if needed > self.max_bytes {
+            match self.eviction {
+                EvictionPolicy::Reject => {
+                    return Err(format!(
+                        "Cache is full, storing {size} more bytes would exceed its budget of {} bytes",
+                        self.max_bytes
+                    ));
+                }
+                EvictionPolicy::EvictOldest => {
+                    while images.bytes - replaced + size > self.max_bytes {
+                        let Some(oldest) = images.keys.first_except(&key).cloned() else {
+                            break;
+                        };
+                        tracing::debug!("Evicting image from the cache to make room: {oldest}");
+                        images.remove(&oldest);
+                        self.counters.record_eviction();
+                        evicted.push(oldest);
+                    }
+                }
+            }
+        }
+
+        if !images.keys.contains(&key) {
+            images.keys.push(key.clone());
+        }
+        let previous = images
+            .images
+            .get(&key)
+            .map(|image| image.content_type.clone());
+        images
+            .by_type
+            .insert(&key, &image.content_type, previous.as_deref());
+        images.bytes = images.bytes - replaced + size;
+        images.images.insert(key, image);
+        drop(images);
+        Ok(evicted)
     }
 
-    fn remove(&mut self, key: &CacheKey) -> Option<CacheValue> {
-        self.keys.retain(|k| k != key);
-        self.cache.remove(key)
+    fn remove(&self, key: &CacheKey) -> Option<CacheValue> {
+        write(&self.images).remove(key)
+    }
+
+    fn bytes(&self) -> u64 {
+        read(&self.images).bytes
     }
 
     fn size(&self) -> usize {
-        self.cache.len()
+        read(&self.images).images.len()
     }
 
-    fn clear(&mut self) -> Result<(), String> {
-        self.cache.clear();
+    fn clear(&self) -> Result<(), String> {
+        *write(&self.images) = InMemoryImages::default();
         Ok(())
     }
 
-    fn keys(&self) -> &[CacheKey] {
-        debug_assert!(
-            self.keys.len() == self.cache.len(),
-            "Keys and cache size mismatch: {} != {}",
-            self.keys.len(),
-            self.cache.len()
-        );
-        &self.keys
+    fn keys(&self) -> Box<dyn Iterator<Item = CacheKey> + '_> {
+        Box::new(read(&self.images).keys.snapshot())
+    }
+
+    fn keys_of_types(&self, content_types: &[String]) -> Box<dyn Iterator<Item = CacheKey> + '_> {
+        Box::new(read(&self.images).by_type.snapshot(content_types))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (CacheKey, CacheMetadata)> + '_> {
+        Box::new(self.keys().filter_map(|key| {
+            let metadata = read(&self.images)
+                .images
+                .get(&key)
+                .map(CacheMetadata::from)?;
+            Some((key, metadata))
+        }))
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.counters.snapshot()
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FileSystemCacheValue {
     pub path: PathBuf,
     pub hash: String,
     pub content_type: String,
+    pub validators: Validators,
 }
 
+/// An image fetched from a URL, spooled to disk so that it can be served after a restart without network
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SpoolEntry {
+    /// The name of the file in the spool directory
+    file: String,
+    hash: String,
+    /// The algorithm of `hash`, md5 for images spooled before it was configurable
+    #[serde(default = "legacy_hash_algorithm")]
+    hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    compression: Compression,
+    content_type: String,
+    #[serde(default)]
+    validators: Validators,
+}
+
+const fn legacy_hash_algorithm() -> HashAlgorithm {
+    HashAlgorithm::Md5
+}
+
+/// The directory, within the cache directory, that images fetched from URLs are spooled to
+const SPOOL_DIRECTORY: &str = "spool";
+/// The file, within the spool directory, that maps the URL of each spooled image to its file
+const SPOOL_INDEX: &str = "index.json";
+
+/// The directory a `FileSystemCache` stores its files in
 #[derive(Debug)]
-pub struct FileSystemCache {
-    tempdir: TempDir,
-    keys: Vec<CacheKey>,
+enum CacheDirectory {
+    /// A temporary directory, removed when the cache is dropped
+    Temporary(TempDir),
+    /// A directory that outlives the cache
+    Persistent(PathBuf),
+}
+
+impl CacheDirectory {
+    fn path(&self) -> &Path {
+        match self {
+            Self::Temporary(tempdir) => tempdir.path(),
+            Self::Persistent(path) => path,
+        }
+    }
+}
+
+/// The files of a `FileSystemCache`
+#[derive(Debug, Default)]
+struct CachedFiles {
+    keys: KeyList,
+    by_type: ContentTypeIndex,
     // map of keys to file paths and the hash of the file content
-    pub cache: HashMap<CacheKey, FileSystemCacheValue>,
+    cache: HashMap<CacheKey, FileSystemCacheValue>,
+    /// The images fetched from URLs that are spooled to disk, empty unless the directory is persistent
+    spool: HashMap<Url, SpoolEntry>,
+    /// The images whose file was opened, and found to match its hash, so it isn't hashed whenever it's opened
+    verified: HashSet<CacheKey>,
 }
 
-impl CacheBackend for FileSystemCache {
-    fn backend_type(&self) -> &'static str {
-        "FileSystem"
+#[derive(Debug)]
+pub struct FileSystemCache {
+    directory: CacheDirectory,
+    files: RwLock<CachedFiles>,
+    /// The hash used to check the integrity of cached files
+    hash_algorithm: HashAlgorithm,
+    /// How cached files are compressed
+    compression: Compression,
+    /// Whether cached files are memory-mapped to be read, rather than copied into memory
+    mmap: bool,
+    counters: CacheCounters,
+}
+
+impl FileSystemCache {
+    /// Create a new filesystem cache that stores its files in the given directory,
+    /// which is created if it doesn't exist and is not removed when the cache is dropped.
+    ///
+    /// Cached files left over from a previous run are removed, except for images fetched from URLs,
+    /// which are spooled to the `spool` subdirectory so they can still be served if the network is unavailable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created or read.
+    pub fn with_directory(directory: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(directory.join(SPOOL_DIRECTORY))?;
+        for entry in fs::read_dir(&directory)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "cache") {
+                fs::remove_file(path)?;
+            }
+        }
+        let spool = Self::load_spool(&directory.join(SPOOL_DIRECTORY));
+        Ok(Self {
+            directory: CacheDirectory::Persistent(directory),
+            files: RwLock::new(CachedFiles {
+                spool,
+                ..CachedFiles::default()
+            }),
+            hash_algorithm: HashAlgorithm::default(),
+            compression: Compression::default(),
+            mmap: true,
+            counters: CacheCounters::default(),
+        })
     }
 
-    fn new() -> Self {
-        let tempdir = TempDir::new().expect("Failed to create temp dir");
-        Self {
-            tempdir,
-            keys: Vec::new(),
-            cache: HashMap::new(),
+    /// Use the given hash to check the integrity of cached files
+    #[must_use]
+    pub const fn with_hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    /// Compress cached files with the given compression
+    #[must_use]
+    pub const fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Whether to memory-map cached files to read them, so they're read from the page cache rather than copied
+    /// into memory, which is the default
+    #[must_use]
+    pub const fn with_mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Map a cached file into memory, if the cache maps its files and the file can be mapped
+    fn map(&self, file: &fs::File) -> Option<memmap2::Mmap> {
+        if !self.mmap {
+            return None;
         }
+        // SAFETY: the files of the cache are written once, under a name of their own, and never modified
+        // afterwards, only removed, which leaves the mapping intact
+        unsafe { memmap2::Mmap::map(file) }
+            .inspect_err(|e| tracing::debug!("Failed to map cached file, reading it instead: {e}"))
+            .ok()
     }
 
-    fn get(&self, key: CacheKey) -> Option<CacheValue> {
-        let compute_hash = |data: &[u8]| format!("{:x}", md5::compute(data));
+    /// Read the index of the images spooled by a previous run, skipping any whose file is missing
+    fn load_spool(spool_directory: &Path) -> HashMap<Url, SpoolEntry> {
+        let index = spool_directory.join(SPOOL_INDEX);
+        let Ok(content) = fs::read(&index) else {
+            return HashMap::new();
+        };
+        let spool: HashMap<Url, SpoolEntry> =
+            serde_json::from_slice(&content).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring invalid spool index {}: {e}", index.display());
+                HashMap::new()
+            });
+        spool
+            .into_iter()
+            .filter(|(_, entry)| spool_directory.join(&entry.file).exists())
+            .collect()
+    }
+
+    /// The directory images fetched from URLs are spooled to, if the cache directory is persistent
+    fn spool_directory(&self) -> Option<PathBuf> {
+        match &self.directory {
+            CacheDirectory::Temporary(_) => None,
+            CacheDirectory::Persistent(directory) => Some(directory.join(SPOOL_DIRECTORY)),
+        }
+    }
+
+    /// Persist the index of the spooled images
+    fn save_spool(spool: &HashMap<Url, SpoolEntry>, spool_directory: &Path) {
+        let index = spool_directory.join(SPOOL_INDEX);
+        let result = serde_json::to_vec(spool)
+            .map_err(|e| e.to_string())
+            .and_then(|content| fs::write(&index, content).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            tracing::error!("Failed to save spool index {}: {e}", index.display());
+        }
+    }
+
+    /// The directory the cache stores its files in
+    #[must_use]
+    pub fn directory(&self) -> &Path {
+        self.directory.path()
+    }
+
+    /// The file an image is stored in, and the hash of its content
+    #[must_use]
+    pub fn entry(&self, key: &CacheKey) -> Option<FileSystemCacheValue> {
+        read(&self.files).cache.get(key).cloned()
+    }
 
+    /// Read an image from its file, checking the integrity of its content
+    fn read_file(&self, key: &CacheKey) -> Option<CacheValue> {
         if let Some(FileSystemCacheValue {
             path,
             hash,
             content_type,
-        }) = self.cache.get(&key)
+            validators,
+        }) = self.entry(key)
             && path.exists()
         {
-            let data = std::fs::read(path).ok()?;
-            // Validate the content type based on the file extension
-            if hash != &compute_hash(&data) {
-                tracing::warn!("Hash mismatch for cached file: {}", path.display());
-                fs::remove_file(path).ok()?;
+            let mut file = fs::File::open(&path).ok()?;
+            let map = self.map(&file);
+            let mut read = Vec::new();
+            if map.is_none() {
+                std::io::Read::read_to_end(&mut file, &mut read).ok()?;
+            }
+            if hash != self.hash_algorithm.digest(map.as_deref().unwrap_or(&read)) {
+                self.discard_corrupted(&path);
                 return None;
             }
+            // a mapped file is decompressed, or copied, straight from the page cache
+            let data = match map {
+                Some(map) => self.compression.decompress_slice(&map),
+                None => self.compression.decompress(read),
+            }
+            .inspect_err(|e| {
+                tracing::warn!("Failed to decompress cached file {}: {e}", path.display());
+                self.counters.record_validation_failure();
+            })
+            .ok()?;
 
             return Some(CacheValue {
                 data,
-                content_type: content_type.clone(),
+                content_type,
+                validators,
             });
         }
         None
     }
 
-    fn get_random(&self) -> Option<CacheValue> {
-        let keys: Vec<&CacheKey> = self.cache.keys().collect();
-        keys.choose(&mut rand::rng())
-            .copied()
-            .and_then(|random_key| self.get(random_key.clone()))
+    /// Remove a cached file that doesn't match its hash
+    fn discard_corrupted(&self, path: &Path) {
+        tracing::warn!("Hash mismatch for cached file: {}", path.display());
+        self.counters.record_validation_failure();
+        fs::remove_file(path).ok();
     }
+}
 
-    fn set(&mut self, key: CacheKey, image: CacheValue) -> Result<(), String> {
-        let file_path = self
-            .tempdir
-            .path()
-            .join(format!("{}.cache", uuid::Uuid::new_v4()));
-        std::fs::write(&file_path, &image.data).map_err(|e| e.to_string())?;
+impl CacheBackend for FileSystemCache {
+    fn backend_type(&self) -> &'static str {
+        "FileSystem"
+    }
+
+    fn new() -> Self {
+        let tempdir = TempDir::new().expect("Failed to create temp dir");
+        Self {
+            directory: CacheDirectory::Temporary(tempdir),
+            files: RwLock::default(),
+            hash_algorithm: HashAlgorithm::default(),
+            compression: Compression::default(),
+            mmap: true,
+            counters: CacheCounters::default(),
+        }
+    }
+
+    #[tracing::instrument(name = "cache_get", level = "debug", skip_all, fields(backend = "FileSystem", key = %key))]
+    fn get(&self, key: CacheKey) -> Option<CacheValue> {
+        let image = self.read_file(&key);
+        self.counters.record_lookup(image)
+    }
+
+    #[tracing::instrument(name = "cache_open", level = "debug", skip_all, fields(backend = "FileSystem", key = %key))]
+    fn open(&self, key: &CacheKey) -> Option<CacheFile> {
+        if self.compression != Compression::None {
+            return None;
+        }
+        let FileSystemCacheValue {
+            path,
+            hash,
+            content_type,
+            ..
+        } = self.entry(key)?;
+        let mut file = fs::File::open(&path).ok()?;
+        let len = file.metadata().ok()?.len();
+        let map = self.map(&file);
+        if !read(&self.files).verified.contains(key) {
+            let mut content = Vec::new();
+            if map.is_none() {
+                std::io::Read::read_to_end(&mut file, &mut content).ok()?;
+                std::io::Seek::rewind(&mut file).ok()?;
+            }
+            if hash
+                != self
+                    .hash_algorithm
+                    .digest(map.as_deref().unwrap_or(&content))
+            {
+                self.discard_corrupted(&path);
+                return self.counters.record_lookup(None);
+            }
+            write(&self.files).verified.insert(key.clone());
+        }
+        self.counters.record_lookup(Some(CacheFile {
+            map,
+            file,
+            len,
+            content_type,
+            hash,
+        }))
+    }
 
-        if self.keys.contains(&key) {
+    fn validators(&self, key: &CacheKey) -> Option<Validators> {
+        self.entry(key)
+            .filter(|value| value.path.exists())
+            .map(|value| value.validators)
+    }
+
+    fn content_type(&self, key: &CacheKey) -> Option<String> {
+        self.entry(key)
+            .filter(|value| value.path.exists())
+            .map(|value| value.content_type)
+    }
+
+    fn spooled(&self, key: &CacheKey) -> Option<CacheValue> {
+        let CacheKey::ImageUrl(url) = key else {
+            return None;
+        };
+        let entry = read(&self.files).spool.get(url).cloned()?;
+        let data = fs::read(self.spool_directory()?.join(&entry.file)).ok()?;
+        if entry.hash != entry.hash_algorithm.digest(&data) {
+            tracing::warn!("Hash mismatch for spooled file: {}", entry.file);
+            return None;
+        }
+        let data = entry.compression.decompress(data).ok()?;
+        Some(CacheValue {
+            data,
+            content_type: entry.content_type,
+            validators: entry.validators,
+        })
+    }
+
+    fn get_random(&self) -> Option<(CacheKey, CacheValue)> {
+        let random_key = read(&self.files).keys.choose()?;
+        self.get(random_key.clone())
+            .map(|image| (random_key, image))
+    }
+
+    #[tracing::instrument(name = "cache_set", level = "debug", skip_all, fields(backend = "FileSystem", key = %key))]
+    fn set(&self, key: CacheKey, image: CacheValue) -> Result<(), String> {
+        // images fetched from URLs are spooled, so they outlive the cache
+        let spool_directory = match &key {
+            CacheKey::ImageUrl(_) => self.spool_directory(),
+            CacheKey::ImagePath(_) | CacheKey::Generated(_) => None,
+        };
+        let file_name = format!("{}.cache", uuid::Uuid::new_v4());
+        let file_path = spool_directory
+            .as_deref()
+            .unwrap_or_else(|| self.directory.path())
+            .join(&file_name);
+        let stored = self
+            .compression
+            .compress(&image.data)
+            .map_err(|e| e.to_string())?;
+        std::fs::write(&file_path, &stored).map_err(|e| e.to_string())?;
+
+        let hash_str = self.hash_algorithm.digest(&stored);
+        drop(stored);
+
+        let content_type = image.content_type;
+
+        let mut files = write(&self.files);
+        if files.keys.contains(&key) {
             tracing::warn!("Key already exists in cache: {key:?}");
-            if let Some(FileSystemCacheValue { path, .. }) = self.cache.get(&key) {
+            if let Some(FileSystemCacheValue { path, .. }) = files.cache.get(&key) {
                 fs::remove_file(path).ok();
             }
         } else {
-            self.keys.push(key.clone());
+            files.keys.push(key.clone());
         }
+        files.verified.remove(&key);
 
-        let hash = md5::compute(&image.data);
-        let hash_str = format!("{hash:x}");
-
-        let content_type = image.content_type;
+        if let (Some(spool_directory), CacheKey::ImageUrl(url)) = (&spool_directory, &key) {
+            let entry = SpoolEntry {
+                file: file_name,
+                hash: hash_str.clone(),
+                hash_algorithm: self.hash_algorithm,
+                compression: self.compression,
+                content_type: content_type.clone(),
+                validators: image.validators.clone(),
+            };
+            if let Some(previous) = files.spool.insert(url.clone(), entry) {
+                fs::remove_file(spool_directory.join(previous.file)).ok();
+            }
+            Self::save_spool(&files.spool, spool_directory);
+        }
 
-        self.cache.insert(
+        let previous = files
+            .cache
+            .get(&key)
+            .map(|value| value.content_type.clone());
+        files
+            .by_type
+            .insert(&key, &content_type, previous.as_deref());
+        files.cache.insert(
             key,
             FileSystemCacheValue {
                 path: file_path,
                 hash: hash_str,
                 content_type,
+                validators: image.validators,
             },
         );
+        drop(files);
         Ok(())
     }
 
-    fn remove(&mut self, key: &CacheKey) -> Option<CacheValue> {
-        if let Some(FileSystemCacheValue { path, .. }) = self.cache.remove(key)
-            && path.exists()
+    fn remove(&self, key: &CacheKey) -> Option<CacheValue> {
+        // the image is read before its file is deleted, so that it can be returned
+        let removed = self.read_file(key);
+        let mut files = write(&self.files);
+        if let (Some(spool_directory), CacheKey::ImageUrl(url)) = (self.spool_directory(), key)
+            && files.spool.remove(url).is_some()
         {
-            let content_type = mime_guess::from_path(&path)
-                .first_or_octet_stream()
-                .to_string();
-            fs::remove_file(&path).ok()?;
+            Self::save_spool(&files.spool, &spool_directory);
+        }
+        files.keys.remove(key);
+        files.verified.remove(key);
+        let entry = files.cache.remove(key);
+        if let Some(entry) = &entry {
+            files.by_type.remove(key, &entry.content_type);
+        }
+        drop(files);
+        if let Some(FileSystemCacheValue { path, .. }) = entry {
+            fs::remove_file(path).ok();
+        }
+        removed
+    }
+
+    fn size(&self) -> usize {
+        read(&self.files).cache.len()
+    }
+
+    fn bytes(&self) -> u64 {
+        let paths: Vec<PathBuf> = read(&self.files)
+            .cache
+            .values()
+            .map(|value| value.path.clone())
+            .collect();
+        paths
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
 
-            let data = std::fs::read(path).ok()?;
-            return Some(CacheValue { data, content_type });
+    fn clear(&self) -> Result<(), String> {
+        let mut files = write(&self.files);
+        files.keys.clear();
+        files.by_type.clear();
+        files.cache.clear();
+        files.verified.clear();
+        drop(files);
+        Ok(())
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = CacheKey> + '_> {
+        Box::new(read(&self.files).keys.snapshot())
+    }
+
+    fn keys_of_types(&self, content_types: &[String]) -> Box<dyn Iterator<Item = CacheKey> + '_> {
+        Box::new(read(&self.files).by_type.snapshot(content_types))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (CacheKey, CacheMetadata)> + '_> {
+        Box::new(self.keys().filter_map(|key| {
+            self.entry(&key).map(|value| {
+                let metadata = CacheMetadata {
+                    content_type: value.content_type,
+                    validators: value.validators,
+                };
+                (key, metadata)
+            })
+        }))
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.counters.snapshot()
+    }
+
+    fn revalidate(&self, key: &CacheKey) -> bool {
+        if self.entry(key).is_none() || self.read_file(key).is_some() {
+            return true;
         }
-        None
+        self.remove(key);
+        false
+    }
+}
+
+/// The most recently served images of a `TieredCache`, kept in memory
+#[derive(Debug, Default)]
+struct HotImages {
+    /// The keys of the hot images, least recently served first
+    order: VecDeque<CacheKey>,
+    images: HashMap<CacheKey, CacheValue>,
+}
+
+impl HotImages {
+    /// Mark an image as the most recently served
+    fn touch(&mut self, key: &CacheKey) {
+        if let Some(position) = self.order.iter().position(|k| k == key) {
+            self.order.remove(position);
+        }
+        self.order.push_back(key.clone());
+    }
+
+    /// Keep an image in memory, evicting the least recently served ones beyond `capacity`,
+    /// returning how many were evicted
+    fn insert(&mut self, key: CacheKey, image: CacheValue, capacity: usize) -> usize {
+        if capacity == 0 {
+            return 0;
+        }
+        self.touch(&key);
+        self.images.insert(key, image);
+        let mut evicted = 0;
+        while self.order.len() > capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.images.remove(&oldest);
+                evicted += 1;
+            }
+        }
+        evicted
+    }
+
+    /// Get a hot image, marking it as the most recently served
+    fn get(&mut self, key: &CacheKey) -> Option<CacheValue> {
+        let image = self.images.get(key).cloned()?;
+        self.touch(key);
+        Some(image)
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        self.order.retain(|k| k != key);
+        self.images.remove(key);
+    }
+}
+
+/// A cache that stores every image on disk, and keeps the most recently served ones in memory
+#[derive(Debug)]
+pub struct TieredCache {
+    disk: FileSystemCache,
+    /// How many images are kept in memory
+    capacity: usize,
+    hot: Mutex<HotImages>,
+    /// Lookups, and evictions from memory, of the cache as a whole
+    counters: CacheCounters,
+}
+
+impl TieredCache {
+    /// The number of images kept in memory by default
+    pub const DEFAULT_CAPACITY: usize = 100;
+
+    /// Create a new tiered cache, storing images in the given filesystem cache and keeping the
+    /// `capacity` most recently served ones in memory
+    #[must_use]
+    pub fn with_disk(disk: FileSystemCache, capacity: usize) -> Self {
+        Self {
+            disk,
+            capacity,
+            hot: Mutex::new(HotImages::default()),
+            counters: CacheCounters::default(),
+        }
+    }
+
+    /// The filesystem cache every image is stored in
+    #[must_use]
+    pub const fn disk(&self) -> &FileSystemCache {
+        &self.disk
+    }
+
+    /// The number of images currently kept in memory
+    #[must_use]
+    pub fn hot_size(&self) -> usize {
+        self.hot_images().images.len()
+    }
+
+    fn hot_images(&self) -> std::sync::MutexGuard<'_, HotImages> {
+        self.hot.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl CacheBackend for TieredCache {
+    fn backend_type(&self) -> &'static str {
+        "Tiered"
+    }
+
+    fn new() -> Self {
+        Self::with_disk(FileSystemCache::new(), Self::DEFAULT_CAPACITY)
+    }
+
+    #[tracing::instrument(name = "cache_get", level = "debug", skip_all, fields(backend = "Tiered", key = %key))]
+    fn get(&self, key: CacheKey) -> Option<CacheValue> {
+        let hot = self.hot_images().get(&key);
+        if hot.is_some() {
+            return self.counters.record_lookup(hot);
+        }
+        let Some(image) = self.disk.get(key.clone()) else {
+            return self.counters.record_lookup(None);
+        };
+        let evicted = self.hot_images().insert(key, image.clone(), self.capacity);
+        for _ in 0..evicted {
+            self.counters.record_eviction();
+        }
+        self.counters.record_lookup(Some(image))
+    }
+
+    fn get_random(&self) -> Option<(CacheKey, CacheValue)> {
+        let key = read(&self.disk.files).keys.choose()?;
+        self.get(key.clone()).map(|image| (key, image))
+    }
+
+    fn validators(&self, key: &CacheKey) -> Option<Validators> {
+        self.disk.validators(key)
+    }
+
+    fn content_type(&self, key: &CacheKey) -> Option<String> {
+        self.disk.content_type(key)
+    }
+
+    fn spooled(&self, key: &CacheKey) -> Option<CacheValue> {
+        self.disk.spooled(key)
+    }
+
+    #[tracing::instrument(name = "cache_set", level = "debug", skip_all, fields(backend = "Tiered", key = %key))]
+    fn set(&self, key: CacheKey, image: CacheValue) -> Result<(), String> {
+        self.hot_images().remove(&key);
+        self.disk.set(key, image)
+    }
+
+    fn remove(&self, key: &CacheKey) -> Option<CacheValue> {
+        self.hot_images().remove(key);
+        self.disk.remove(key)
     }
 
     fn size(&self) -> usize {
-        self.cache.len()
+        self.disk.size()
+    }
+
+    fn bytes(&self) -> u64 {
+        self.disk.bytes()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (CacheKey, CacheMetadata)> + '_> {
+        self.disk.iter()
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = CacheKey> + '_> {
+        self.disk.keys()
+    }
+
+    fn keys_of_types(&self, content_types: &[String]) -> Box<dyn Iterator<Item = CacheKey> + '_> {
+        self.disk.keys_of_types(content_types)
     }
 
-    fn clear(&mut self) -> Result<(), String> {
-        self.cache.clear();
+    fn clear(&self) -> Result<(), String> {
+        *self.hot_images() = HotImages::default();
+        self.disk.clear()
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            validation_failures: self.disk.stats().validation_failures,
+            ..self.counters.snapshot()
+        }
+    }
+
+    fn revalidate(&self, key: &CacheKey) -> bool {
+        if self.disk.revalidate(key) {
+            return true;
+        }
+        self.hot_images().remove(key);
+        false
+    }
+}
+
+/// The metadata of an image stored in a `SledCache`, alongside its bytes
+#[cfg(feature = "sled")]
+#[derive(Debug, Serialize, Deserialize)]
+struct SledMetadata {
+    content_type: String,
+    #[serde(default)]
+    validators: Validators,
+    #[serde(default)]
+    compression: Compression,
+}
+
+/// The images a `SledCache` loaded during this run
+#[cfg(feature = "sled")]
+#[derive(Debug, Default)]
+struct LoadedImages {
+    keys: KeyList,
+    loaded: std::collections::HashSet<CacheKey>,
+}
+
+/// A cache that stores images and their metadata in a single embedded sled database
+///
+/// Images loaded from paths or generated are removed when the database is opened again, while images
+/// fetched from URLs are kept, and can be loaded with `spooled` if they can't be fetched.
+#[cfg(feature = "sled")]
+#[derive(Debug)]
+pub struct SledCache {
+    /// The bytes of each image, by its serialized key
+    data: sled::Tree,
+    /// The metadata of each image, by its serialized key
+    metadata: sled::Tree,
+    loaded: RwLock<LoadedImages>,
+    /// How the bytes of images are compressed
+    compression: Compression,
+    counters: CacheCounters,
+}
+
+#[cfg(feature = "sled")]
+impl SledCache {
+    /// Open (or create) a sled database at the given path to store images in
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened, e.g. because another process is using it.
+    pub fn with_path(path: impl AsRef<Path>) -> Result<Self, String> {
+        let db = sled::open(path.as_ref()).map_err(|e| e.to_string())?;
+        Self::with_db(&db)
+    }
+
+    fn with_db(db: &sled::Db) -> Result<Self, String> {
+        let cache = Self {
+            data: db.open_tree("data").map_err(|e| e.to_string())?,
+            metadata: db.open_tree("metadata").map_err(|e| e.to_string())?,
+            loaded: RwLock::default(),
+            compression: Compression::default(),
+            counters: CacheCounters::default(),
+        };
+        for entry in &cache.metadata {
+            let (id, _) = entry.map_err(|e| e.to_string())?;
+            if !matches!(
+                serde_json::from_slice::<CacheKey>(&id),
+                Ok(CacheKey::ImageUrl(_))
+            ) {
+                cache.data.remove(&id).map_err(|e| e.to_string())?;
+                cache.metadata.remove(&id).map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(cache)
+    }
+
+    /// Compress the bytes of images stored from now on with the given compression
+    #[must_use]
+    pub const fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// The key an image is stored under in the database
+    fn id(key: &CacheKey) -> Vec<u8> {
+        serde_json::to_vec(key).unwrap_or_else(|_| key.to_string().into_bytes())
+    }
+
+    /// Whether the image was loaded by this run
+    fn is_loaded(&self, key: &CacheKey) -> bool {
+        read(&self.loaded).loaded.contains(key)
+    }
+
+    /// Read an image from the database, whether or not it was loaded by this run
+    fn read(&self, key: &CacheKey) -> Option<CacheValue> {
+        let id = Self::id(key);
+        let metadata: SledMetadata = serde_json::from_slice(&self.metadata.get(&id).ok()??).ok()?;
+        let data = self.data.get(&id).ok()??;
+        let data = metadata
+            .compression
+            .decompress(data.to_vec())
+            .inspect_err(|e| {
+                tracing::warn!("Failed to decompress image {key} from the sled database: {e}");
+                self.counters.record_validation_failure();
+            })
+            .ok()?;
+        Some(CacheValue {
+            data,
+            content_type: metadata.content_type,
+            validators: metadata.validators,
+        })
+    }
+}
+
+#[cfg(feature = "sled")]
+impl CacheBackend for SledCache {
+    fn backend_type(&self) -> &'static str {
+        "Sled"
+    }
+
+    fn new() -> Self {
+        let db = sled::Config::new()
+            .temporary(true)
+            .open()
+            .expect("Failed to create temporary sled database");
+        Self::with_db(&db).expect("Failed to open temporary sled database")
+    }
+
+    #[tracing::instrument(name = "cache_get", level = "debug", skip_all, fields(backend = "Sled", key = %key))]
+    fn get(&self, key: CacheKey) -> Option<CacheValue> {
+        let image = self.is_loaded(&key).then(|| self.read(&key)).flatten();
+        self.counters.record_lookup(image)
+    }
+
+    fn get_random(&self) -> Option<(CacheKey, CacheValue)> {
+        let key = read(&self.loaded).keys.choose();
+        let random = key.and_then(|key| self.read(&key).map(|image| (key, image)));
+        self.counters.record_lookup(random)
+    }
+
+    fn validators(&self, key: &CacheKey) -> Option<Validators> {
+        if !self.is_loaded(key) {
+            return None;
+        }
+        let metadata = self.metadata.get(Self::id(key)).ok()??;
+        serde_json::from_slice::<SledMetadata>(&metadata)
+            .ok()
+            .map(|metadata| metadata.validators)
+    }
+
+    fn content_type(&self, key: &CacheKey) -> Option<String> {
+        if !self.is_loaded(key) {
+            return None;
+        }
+        let metadata = self.metadata.get(Self::id(key)).ok()??;
+        serde_json::from_slice::<SledMetadata>(&metadata)
+            .ok()
+            .map(|metadata| metadata.content_type)
+    }
+
+    fn spooled(&self, key: &CacheKey) -> Option<CacheValue> {
+        match key {
+            CacheKey::ImageUrl(_) => self.read(key),
+            CacheKey::ImagePath(_) | CacheKey::Generated(_) => None,
+        }
+    }
+
+    #[tracing::instrument(name = "cache_set", level = "debug", skip_all, fields(backend = "Sled", key = %key))]
+    fn set(&self, key: CacheKey, image: CacheValue) -> Result<(), String> {
+        let id = Self::id(&key);
+        let metadata = serde_json::to_vec(&SledMetadata {
+            content_type: image.content_type,
+            validators: image.validators,
+            compression: self.compression,
+        })
+        .map_err(|e| e.to_string())?;
+        let data = self
+            .compression
+            .compress(&image.data)
+            .map_err(|e| e.to_string())?;
+        self.data
+            .insert(&id, data.as_ref())
+            .map_err(|e| e.to_string())?;
+        self.metadata
+            .insert(&id, metadata)
+            .map_err(|e| e.to_string())?;
+
+        let mut loaded = write(&self.loaded);
+        if loaded.loaded.insert(key.clone()) {
+            loaded.keys.push(key);
+        }
+        drop(loaded);
         Ok(())
     }
 
-    fn keys(&self) -> &[CacheKey] {
-        debug_assert!(
-            self.keys.len() == self.cache.len(),
-            "Keys and cache size mismatch: {} != {}",
-            self.keys.len(),
-            self.cache.len()
-        );
-        &self.keys
+    fn remove(&self, key: &CacheKey) -> Option<CacheValue> {
+        let mut loaded = write(&self.loaded);
+        if !loaded.loaded.remove(key) {
+            return None;
+        }
+        loaded.keys.remove(key);
+        drop(loaded);
+        let image = self.read(key);
+        let id = Self::id(key);
+        self.data.remove(&id).ok();
+        self.metadata.remove(&id).ok();
+        image
+    }
+
+    fn size(&self) -> usize {
+        read(&self.loaded).loaded.len()
+    }
+
+    fn bytes(&self) -> u64 {
+        self.keys()
+            .filter_map(|key| self.data.get(Self::id(&key)).ok().flatten())
+            .map(|data| data.len() as u64)
+            .sum()
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = CacheKey> + '_> {
+        Box::new(read(&self.loaded).keys.snapshot())
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (CacheKey, CacheMetadata)> + '_> {
+        Box::new(self.keys().filter_map(|key| {
+            let metadata = self.metadata.get(Self::id(&key)).ok()??;
+            let metadata: SledMetadata = serde_json::from_slice(&metadata).ok()?;
+            let metadata = CacheMetadata {
+                content_type: metadata.content_type,
+                validators: metadata.validators,
+            };
+            Some((key, metadata))
+        }))
+    }
+
+    fn clear(&self) -> Result<(), String> {
+        self.data.clear().map_err(|e| e.to_string())?;
+        self.metadata.clear().map_err(|e| e.to_string())?;
+        *write(&self.loaded) = LoadedImages::default();
+        Ok(())
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.counters.snapshot()
     }
 }
@@ -0,0 +1,314 @@
+//! A conformance test suite for cache backends, so that every backend (including ones implemented
+//! outside this crate) behaves the way the server expects.
+//!
+//! Call [`cache_backend_conformance`] from a test, with a function creating an empty cache:
+//!
+//! ```
+//! use random_image_server::cache::{CacheBackend, InMemoryCache, testing::cache_backend_conformance};
+//!
+//! cache_backend_conformance(InMemoryCache::new);
+//! ```
+//!
+//! or generate a test for each check of the suite with [`cache_backend_tests!`](crate::cache_backend_tests),
+//! so that failures are reported by the check that failed:
+//!
+//! ```
+//! mod in_memory_cache {
+//!     use random_image_server::cache::{CacheBackend, InMemoryCache};
+//!
+//!     random_image_server::cache_backend_tests!(InMemoryCache::new);
+//! }
+//! ```
+//!
+//! Backends that hold a bounded number of bytes are also checked with [`check_eviction`] or
+//! [`check_rejection`], depending on what they do when they're full.
+
+use std::path::PathBuf;
+
+use url::Url;
+
+use crate::cache::{CacheBackend, CacheKey, CacheMetadata, CacheValue, Validators};
+
+/// Run every check of the suite against caches created by `new_cache`, panicking on the first failure
+///
+/// Each check is given a new, empty cache.
+pub fn cache_backend_conformance<C: CacheBackend>(new_cache: impl Fn() -> C) {
+    check_empty(&new_cache());
+    check_set_and_get(&new_cache());
+    check_replace(&new_cache());
+    check_remove(&new_cache());
+    check_iter(&new_cache());
+    check_keys_of_types(&new_cache());
+    check_get_random(&new_cache());
+    check_clear(&new_cache());
+}
+
+/// Generate a test for each check of the conformance suite, in the current module, given an expression
+/// of a function creating an empty cache
+///
+/// Each test is given a new, empty cache.
+#[macro_export]
+macro_rules! cache_backend_tests {
+    ($new_cache:expr) => {
+        $crate::cache_backend_tests!(
+            $new_cache;
+            check_empty,
+            check_set_and_get,
+            check_replace,
+            check_remove,
+            check_iter,
+            check_keys_of_types,
+            check_get_random,
+            check_clear
+        );
+    };
+    ($new_cache:expr; $($check:ident),+) => {
+        $(
+            #[test]
+            fn $check() {
+                $crate::cache::testing::$check(&($new_cache)());
+            }
+        )+
+    };
+}
+
+fn image(data: &[u8]) -> CacheValue {
+    CacheValue {
+        data: data.to_vec(),
+        content_type: "image/png".to_string(),
+        validators: Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+        },
+    }
+}
+
+fn path_key(name: &str) -> CacheKey {
+    CacheKey::ImagePath(PathBuf::from(format!("/conformance/{name}.png")))
+}
+
+fn url_key(name: &str) -> CacheKey {
+    CacheKey::ImageUrl(
+        Url::parse(&format!("https://example.com/conformance/{name}.png"))
+            .expect("the URL is valid"),
+    )
+}
+
+/// A new cache holds no images
+pub fn check_empty<C: CacheBackend>(cache: &C) {
+    assert!(cache.is_empty(), "a new cache should be empty");
+    assert_eq!(cache.size(), 0);
+    assert_eq!(cache.bytes(), 0);
+    assert_eq!(cache.get(path_key("missing")), None);
+    assert_eq!(cache.get_random(), None);
+    assert_eq!(cache.validators(&path_key("missing")), None);
+    assert_eq!(cache.keys().count(), 0);
+}
+
+/// Stored images are returned as they were stored, whatever their key
+pub fn check_set_and_get<C: CacheBackend>(cache: &C) {
+    for (key, value) in [(path_key("a"), image(b"a")), (url_key("b"), image(b"bb"))] {
+        cache
+            .set(key.clone(), value.clone())
+            .expect("the image should be stored");
+        assert_eq!(cache.get(key.clone()), Some(value.clone()));
+        assert_eq!(cache.validators(&key), Some(value.validators));
+    }
+    assert_eq!(cache.size(), 2);
+    assert!(!cache.is_empty());
+    // backends compressing images may count the bytes they store rather than the bytes of the images
+    assert!(cache.bytes() > 0);
+}
+
+/// Storing an image with the key of a stored image replaces it
+pub fn check_replace<C: CacheBackend>(cache: &C) {
+    cache
+        .set(path_key("a"), image(b"old"))
+        .expect("the image should be stored");
+    cache
+        .set(path_key("a"), image(b"new"))
+        .expect("the image should be stored");
+    assert_eq!(cache.size(), 1);
+    assert_eq!(cache.get(path_key("a")), Some(image(b"new")));
+    assert_eq!(cache.keys().collect::<Vec<_>>(), [path_key("a")]);
+}
+
+/// Removing an image returns it, and it is no longer found
+pub fn check_remove<C: CacheBackend>(cache: &C) {
+    cache
+        .set(path_key("a"), image(b"a"))
+        .expect("the image should be stored");
+    cache
+        .set(url_key("b"), image(b"b"))
+        .expect("the image should be stored");
+
+    assert_eq!(cache.remove(&path_key("a")), Some(image(b"a")));
+    assert_eq!(cache.remove(&path_key("a")), None);
+    assert_eq!(cache.get(path_key("a")), None);
+    assert_eq!(cache.size(), 1);
+    assert_eq!(cache.keys().collect::<Vec<_>>(), [url_key("b")]);
+
+    assert_eq!(cache.remove(&url_key("b")), Some(image(b"b")));
+    assert!(cache.is_empty());
+    assert_eq!(cache.remove(&path_key("missing")), None);
+}
+
+/// Images are iterated over in the order they were stored, with their metadata
+pub fn check_iter<C: CacheBackend>(cache: &C) {
+    let keys = [path_key("a"), url_key("b"), path_key("c")];
+    for key in &keys {
+        cache
+            .set(key.clone(), image(b"data"))
+            .expect("the image should be stored");
+    }
+    assert_eq!(cache.keys().collect::<Vec<_>>(), keys);
+    let metadata = CacheMetadata::from(&image(b"data"));
+    assert_eq!(
+        cache.iter().collect::<Vec<_>>(),
+        keys.map(|key| (key, metadata.clone()))
+    );
+}
+
+/// Images are found by their content type, grouped by the types asked for, each in the order they were stored,
+/// including after they're replaced with an image of another type, removed, or cleared
+pub fn check_keys_of_types<C: CacheBackend>(cache: &C) {
+    let typed = |data: &[u8], content_type: &str| CacheValue {
+        content_type: content_type.to_string(),
+        ..image(data)
+    };
+    cache
+        .set(path_key("a"), typed(b"a", "image/png"))
+        .expect("the image should be stored");
+    cache
+        .set(url_key("b"), typed(b"b", "image/jpeg"))
+        .expect("the image should be stored");
+    cache
+        .set(path_key("c"), typed(b"c", "image/png"))
+        .expect("the image should be stored");
+    let keys_of_types = |content_types: &[&str]| {
+        let content_types: Vec<String> = content_types.iter().map(ToString::to_string).collect();
+        cache.keys_of_types(&content_types).collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        keys_of_types(&["image/png"]),
+        [path_key("a"), path_key("c")]
+    );
+    assert_eq!(
+        keys_of_types(&["image/jpeg", "image/png"]),
+        [url_key("b"), path_key("a"), path_key("c")]
+    );
+    assert_eq!(keys_of_types(&["image/webp"]), []);
+    assert_eq!(keys_of_types(&[]), []);
+
+    cache
+        .set(path_key("a"), typed(b"a", "image/webp"))
+        .expect("the image should be replaced");
+    assert_eq!(keys_of_types(&["image/png"]), [path_key("c")]);
+    assert_eq!(keys_of_types(&["image/webp"]), [path_key("a")]);
+
+    cache.remove(&path_key("c"));
+    assert_eq!(keys_of_types(&["image/png"]), []);
+    cache.clear().expect("the cache should be cleared");
+    assert_eq!(keys_of_types(&["image/webp", "image/jpeg"]), []);
+}
+
+/// Random images are among the stored images, and returned with their key
+pub fn check_get_random<C: CacheBackend>(cache: &C) {
+    cache
+        .set(path_key("a"), image(b"a"))
+        .expect("the image should be stored");
+    cache
+        .set(path_key("b"), image(b"b"))
+        .expect("the image should be stored");
+    for _ in 0..10 {
+        let (key, value) = cache.get_random().expect("the cache isn't empty");
+        assert_eq!(cache.get(key), Some(value));
+    }
+}
+
+/// Clearing the cache removes every image
+pub fn check_clear<C: CacheBackend>(cache: &C) {
+    cache
+        .set(path_key("a"), image(b"a"))
+        .expect("the image should be stored");
+    cache
+        .set(url_key("b"), image(b"b"))
+        .expect("the image should be stored");
+    cache.clear().expect("the cache should be cleared");
+    assert!(cache.is_empty());
+    assert_eq!(cache.get(path_key("a")), None);
+    assert_eq!(cache.keys().count(), 0);
+    assert_eq!(cache.get_random(), None);
+}
+
+/// Storing an image that would take a cache over its budget of `max_bytes` evicts the oldest images and reports them,
+/// and storing one larger than the whole budget fails
+///
+/// For backends evicting their oldest images when they're full, given an empty cache with a budget of at least 2 bytes.
+pub fn check_eviction<C: CacheBackend>(cache: &C, max_bytes: u64) {
+    let half = vec![0; usize::try_from(max_bytes / 2).expect("the budget fits in memory")];
+    for name in ["a", "b"] {
+        cache
+            .set(path_key(name), image(&half))
+            .expect("the image fits in the budget");
+    }
+    assert_eq!(cache.stats().evictions, 0);
+
+    // replacing an image only makes room for itself
+    let evicted = cache
+        .set_evicting(path_key("a"), image(&half))
+        .expect("the image should be replaced");
+    assert_eq!(evicted, []);
+    assert_eq!(
+        cache.keys().collect::<Vec<_>>(),
+        [path_key("a"), path_key("b")]
+    );
+
+    // the evicted images are reported, so that what is known about them can be forgotten too
+    let evicted = cache
+        .set_evicting(path_key("c"), image(&half))
+        .expect("the oldest image should be evicted to make room");
+    assert_eq!(evicted, [path_key("a")]);
+    assert_eq!(
+        cache.keys().collect::<Vec<_>>(),
+        [path_key("b"), path_key("c")]
+    );
+    assert_eq!(cache.size(), 2);
+    assert_eq!(cache.get(path_key("a")), None);
+    assert!(cache.bytes() <= max_bytes);
+    assert_eq!(cache.stats().evictions, 1);
+
+    let too_large = vec![0; usize::try_from(max_bytes + 1).expect("the budget fits in memory")];
+    assert!(cache.set(path_key("d"), image(&too_large)).is_err());
+    assert_eq!(
+        cache.keys().collect::<Vec<_>>(),
+        [path_key("b"), path_key("c")]
+    );
+}
+
+/// Storing an image that would take a cache over its budget of `max_bytes` fails, and keeps the stored images
+///
+/// For backends rejecting images when they're full, given an empty cache with a budget of at least 2 bytes.
+pub fn check_rejection<C: CacheBackend>(cache: &C, max_bytes: u64) {
+    let half = vec![0; usize::try_from(max_bytes / 2).expect("the budget fits in memory")];
+    for name in ["a", "b"] {
+        cache
+            .set(path_key(name), image(&half))
+            .expect("the image fits in the budget");
+    }
+
+    assert!(cache.set(path_key("c"), image(&half)).is_err());
+    assert_eq!(
+        cache.keys().collect::<Vec<_>>(),
+        [path_key("a"), path_key("b")]
+    );
+    assert_eq!(cache.get(path_key("c")), None);
+    assert_eq!(cache.stats().evictions, 0);
+
+    // replacing an image doesn't need more room
+    cache
+        .set(path_key("a"), image(&half))
+        .expect("the image should be replaced");
+    assert!(cache.bytes() <= max_bytes);
+}
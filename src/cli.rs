@@ -0,0 +1,676 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+
+use crate::{
+    completions::Shell,
+    env::{DotEnvBackend, EnvBackend},
+};
+
+/// The environment variable that sets the path of the config file
+pub const CONFIG_ENV_VAR: &str = "RANDOM_IMAGE_SERVER_CONFIG";
+
+/// The config file used if none is specified
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// The env file loaded at startup, if it exists and none is specified
+pub const DEFAULT_ENV_FILE: &str = ".env";
+
+/// What the server was asked to do
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Serve images
+    #[default]
+    Serve,
+    /// Validate the configuration and its sources, then exit
+    Validate,
+    /// Print a commented default config file, then exit
+    PrintDefaultConfig,
+    /// Print the JSON Schema of config files, then exit
+    PrintConfigSchema,
+    /// Populate the cache, write a snapshot of it to `Args::snapshot`, then exit
+    ExportCache,
+    /// Load the snapshot at `Args::snapshot` into the cache, then serve images
+    ImportCache,
+    /// Populate the persistent cache, print a summary of it, then exit
+    Prefetch,
+    /// Populate the cache, print its images as a table (or JSON, with `Args::json`), then exit
+    ListImages,
+    /// Print a signed link to the image `Args::image_id`, valid for `Args::expires_in`, then exit
+    SignUrl,
+    /// Print the completion script of `Args::shell`, then exit
+    Completions,
+    /// Print the man page, then exit
+    Man,
+    /// Register the Windows service, to serve images with the config and env files passed, then exit
+    InstallService,
+    /// Stop and remove the Windows service, then exit
+    UninstallService,
+}
+
+impl Command {
+    /// Whether the command serves images until it's interrupted
+    #[must_use]
+    pub const fn serves(self) -> bool {
+        matches!(self, Self::Serve | Self::ImportCache)
+    }
+}
+
+/// What the shell completions of an argument, or the value of an option, offer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueHint {
+    /// Paths of files
+    File,
+    /// The names of the shells completion scripts are generated for
+    Shell,
+    /// Nothing, the value is free-form
+    Any,
+}
+
+/// An argument of a command, or the value of an option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArgInfo {
+    pub name: &'static str,
+    pub hint: ValueHint,
+}
+
+/// A command, as documented by the usage information, the man page, and the shell completions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub args: &'static [ArgInfo],
+    /// What the command does, in lines short enough for the usage information
+    pub help: &'static [&'static str],
+}
+
+/// An option, as documented by the usage information, the man page, and the shell completions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptionInfo {
+    pub short: Option<char>,
+    pub long: &'static str,
+    pub value: Option<ArgInfo>,
+    /// What the option does, in lines short enough for the usage information
+    pub help: &'static [&'static str],
+}
+
+const fn arg(name: &'static str, hint: ValueHint) -> ArgInfo {
+    ArgInfo { name, hint }
+}
+
+/// The commands understood by `Args::parse`, besides serving images
+pub const COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        name: "validate",
+        args: &[],
+        help: &[
+            "Check the configuration and its sources, then exit.",
+            "Exits with a non-zero status if any problems are found",
+        ],
+    },
+    CommandInfo {
+        name: "print-default-config",
+        args: &[],
+        help: &["Print a commented config file with the default values"],
+    },
+    CommandInfo {
+        name: "print-config-schema",
+        args: &[],
+        help: &["Print the JSON Schema of config files"],
+    },
+    CommandInfo {
+        name: "export-cache",
+        args: &[arg("archive", ValueHint::File)],
+        help: &["Populate the cache, then write a snapshot of it to the archive"],
+    },
+    CommandInfo {
+        name: "import-cache",
+        args: &[arg("archive", ValueHint::File)],
+        help: &[
+            "Load a snapshot written by `export-cache` into the cache, then serve images.",
+            "Images fetched from URLs are only downloaded again if they changed",
+        ],
+    },
+    CommandInfo {
+        name: "prefetch",
+        args: &[],
+        help: &[
+            "Populate the persistent cache (`cache.directory` or `cache.sled_path`),",
+            "print a summary, then exit. Exits with a non-zero status if any source failed",
+        ],
+    },
+    CommandInfo {
+        name: "list-images",
+        args: &[],
+        help: &[
+            "Load the images from the configured sources, then print their sources,",
+            "keys, ids, sizes, and content types, as a table or JSON (with `--json`)",
+        ],
+    },
+    CommandInfo {
+        name: "sign-url",
+        args: &[
+            arg("image_id", ValueHint::Any),
+            arg("expires_in", ValueHint::Any),
+        ],
+        help: &["Print a link to the image, signed with `signing.secret`, valid for e.g. `1h`"],
+    },
+    CommandInfo {
+        name: "completions",
+        args: &[arg("shell", ValueHint::Shell)],
+        help: &["Print the completion script of the shell: bash, zsh, or fish"],
+    },
+    CommandInfo {
+        name: "man",
+        args: &[],
+        help: &["Print the man page"],
+    },
+    CommandInfo {
+        name: "install-service",
+        args: &[],
+        help: &[
+            "Register a Windows service serving images with the config and env files passed,",
+            "started at boot",
+        ],
+    },
+    CommandInfo {
+        name: "uninstall-service",
+        args: &[],
+        help: &["Stop and remove the Windows service"],
+    },
+];
+
+/// The options understood by `Args::parse`
+pub const OPTIONS: &[OptionInfo] = &[
+    OptionInfo {
+        short: Some('c'),
+        long: "config",
+        value: Some(arg("config_file", ValueHint::File)),
+        help: &[
+            "The config file to use (TOML, YAML, or JSON), also accepted as a positional argument.",
+            "Defaults to $RANDOM_IMAGE_SERVER_CONFIG, then ./config.toml",
+        ],
+    },
+    OptionInfo {
+        short: None,
+        long: "env-file",
+        value: Some(arg("env_file", ValueHint::File)),
+        help: &[
+            "A file to read environment variables from.",
+            "Defaults to ./.env, if it exists",
+        ],
+    },
+    OptionInfo {
+        short: None,
+        long: "json",
+        value: None,
+        help: &["Print JSON rather than a table, with `list-images`"],
+    },
+    OptionInfo {
+        short: None,
+        long: "dry-run",
+        value: None,
+        help: &["Same as `validate`"],
+    },
+    OptionInfo {
+        short: None,
+        long: "config-schema",
+        value: None,
+        help: &["Same as `print-config-schema`"],
+    },
+    OptionInfo {
+        short: None,
+        long: "daemon",
+        value: None,
+        help: &[
+            "Serve images in the background, detached from the terminal (Unix only).",
+            "Nothing is printed, so set `server.log_file`",
+        ],
+    },
+    OptionInfo {
+        short: None,
+        long: "pidfile",
+        value: Some(arg("pidfile", ValueHint::File)),
+        help: &[
+            "Write the process id to the file while serving images (Unix only).",
+            "Fails if the file names a process that's still running",
+        ],
+    },
+    OptionInfo {
+        short: None,
+        long: "service",
+        value: None,
+        help: &["Serve images as the Windows service, as started by the service control manager"],
+    },
+    OptionInfo {
+        short: Some('h'),
+        long: "help",
+        value: None,
+        help: &["Print this help message"],
+    },
+];
+
+impl CommandInfo {
+    /// The command followed by its arguments, e.g. `export-cache <archive>`
+    #[must_use]
+    pub fn synopsis(&self) -> String {
+        self.args
+            .iter()
+            .fold(self.name.to_string(), |synopsis, arg| {
+                format!("{synopsis} <{}>", arg.name)
+            })
+    }
+}
+
+impl OptionInfo {
+    /// The spellings of the option followed by its value, e.g. `-c, --config <config_file>`
+    #[must_use]
+    pub fn synopsis(&self) -> String {
+        let short = self
+            .short
+            .map_or_else(|| "    ".to_string(), |short| format!("-{short}, "));
+        let value = self
+            .value
+            .map(|value| format!(" <{}>", value.name))
+            .unwrap_or_default();
+        format!("{short}--{}{value}", self.long)
+    }
+}
+
+/// Command line arguments of the server
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Args {
+    /// What the server was asked to do
+    pub command: Command,
+    /// The config file passed with `--config` (or as a positional argument)
+    pub config: Option<PathBuf>,
+    /// The env file passed with `--env-file`
+    pub env_file: Option<PathBuf>,
+    /// The cache snapshot archive passed to `export-cache` or `import-cache`
+    pub snapshot: Option<PathBuf>,
+    /// The id of the image passed to `sign-url`
+    pub image_id: Option<String>,
+    /// How long the link printed by `sign-url` is valid for
+    pub expires_in: Option<Duration>,
+    /// Whether `list-images` prints JSON rather than a table
+    pub json: bool,
+    /// The shell passed to `completions`
+    pub shell: Option<Shell>,
+    /// Whether to serve images in the background, detached from the terminal, on Unix
+    pub daemon: bool,
+    /// The file the process id is written to while serving images, on Unix
+    pub pidfile: Option<PathBuf>,
+    /// Whether to serve images as the Windows service, as started by the service control manager
+    pub service: bool,
+    /// Whether usage information was requested
+    pub help: bool,
+}
+
+impl Args {
+    /// Parse the command line arguments, excluding the program name
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an argument is unknown, missing its value, or the config file is given more than once.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let config = match arg.as_str() {
+                "-h" | "--help" => {
+                    parsed.help = true;
+                    continue;
+                }
+                "validate" | "--dry-run" if parsed.command == Command::Serve => {
+                    parsed.command = Command::Validate;
+                    continue;
+                }
+                "print-default-config" if parsed.command == Command::Serve => {
+                    parsed.command = Command::PrintDefaultConfig;
+                    continue;
+                }
+                "print-config-schema" | "--config-schema" if parsed.command == Command::Serve => {
+                    parsed.command = Command::PrintConfigSchema;
+                    continue;
+                }
+                "export-cache" | "import-cache" if parsed.command == Command::Serve => {
+                    let snapshot = args
+                        .next()
+                        .ok_or_else(|| anyhow!("Missing snapshot archive for {arg}"))?;
+                    parsed.command = if arg == "export-cache" {
+                        Command::ExportCache
+                    } else {
+                        Command::ImportCache
+                    };
+                    parsed.snapshot = Some(PathBuf::from(snapshot));
+                    continue;
+                }
+                "list-images" if parsed.command == Command::Serve => {
+                    parsed.command = Command::ListImages;
+                    continue;
+                }
+                "--json" => {
+                    parsed.json = true;
+                    continue;
+                }
+                "completions" if parsed.command == Command::Serve => {
+                    let shell = args
+                        .next()
+                        .ok_or_else(|| anyhow!("Missing shell for {arg}"))?;
+                    parsed.command = Command::Completions;
+                    parsed.shell = Some(shell.parse()?);
+                    continue;
+                }
+                "man" if parsed.command == Command::Serve => {
+                    parsed.command = Command::Man;
+                    continue;
+                }
+                "install-service" if parsed.command == Command::Serve => {
+                    parsed.command = Command::InstallService;
+                    continue;
+                }
+                "uninstall-service" if parsed.command == Command::Serve => {
+                    parsed.command = Command::UninstallService;
+                    continue;
+                }
+                "--daemon" => {
+                    parsed.daemon = true;
+                    continue;
+                }
+                "--service" => {
+                    parsed.service = true;
+                    continue;
+                }
+                "--pidfile" => {
+                    let pidfile = args
+                        .next()
+                        .ok_or_else(|| anyhow!("Missing value for {arg}"))?;
+                    parsed.pidfile = Some(PathBuf::from(pidfile));
+                    continue;
+                }
+                flag if flag.starts_with("--pidfile=") => {
+                    parsed.pidfile = Some(PathBuf::from(flag.trim_start_matches("--pidfile=")));
+                    continue;
+                }
+                "prefetch" if parsed.command == Command::Serve => {
+                    parsed.command = Command::Prefetch;
+                    continue;
+                }
+                "sign-url" if parsed.command == Command::Serve => {
+                    let image_id = args
+                        .next()
+                        .ok_or_else(|| anyhow!("Missing image id for {arg}"))?;
+                    let expires_in = args
+                        .next()
+                        .ok_or_else(|| anyhow!("Missing expiry duration for {arg}"))?;
+                    parsed.command = Command::SignUrl;
+                    parsed.image_id = Some(image_id);
+                    parsed.expires_in = Some(crate::config::parse_duration(&expires_in)?);
+                    continue;
+                }
+                "-c" | "--config" => args
+                    .next()
+                    .ok_or_else(|| anyhow!("Missing value for {arg}"))?,
+                flag if flag.starts_with("--config=") => {
+                    flag.trim_start_matches("--config=").to_string()
+                }
+                "--env-file" => {
+                    let env_file = args
+                        .next()
+                        .ok_or_else(|| anyhow!("Missing value for {arg}"))?;
+                    parsed.env_file = Some(PathBuf::from(env_file));
+                    continue;
+                }
+                flag if flag.starts_with("--env-file=") => {
+                    parsed.env_file = Some(PathBuf::from(flag.trim_start_matches("--env-file=")));
+                    continue;
+                }
+                flag if flag.starts_with('-') => return Err(anyhow!("Unknown argument: {flag}")),
+                _ => arg,
+            };
+            if parsed.config.replace(PathBuf::from(config)).is_some() {
+                return Err(anyhow!("The config file can only be specified once"));
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The config file that was explicitly requested, if any
+    ///
+    /// In order of precedence, the config file is taken from:
+    /// 1. the `--config` flag (or positional argument)
+    /// 2. the `RANDOM_IMAGE_SERVER_CONFIG` environment variable
+    ///
+    /// If neither is set, `config.toml` in the working directory is used if it exists.
+    #[must_use]
+    pub fn config_file(&self) -> Option<PathBuf> {
+        self.config_file_with_env_backend(&crate::env::StdEnvBackend)
+    }
+
+    /// The config file that was explicitly requested, if any.
+    ///
+    /// Same as `config_file`, but allows passing a custom environment backend (e.g., for testing).
+    #[must_use]
+    pub fn config_file_with_env_backend(&self, env: &impl EnvBackend) -> Option<PathBuf> {
+        self.config.clone().or_else(|| {
+            env.var(CONFIG_ENV_VAR)
+                .ok()
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+        })
+    }
+
+    /// Wrap an environment backend with the variables of the env file loaded at startup
+    ///
+    /// This is the file passed with `--env-file`, or `.env` in the working directory if it exists.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the env file cannot be read or parsed.
+    pub fn env_backend<'a, E: EnvBackend>(&self, env: &'a E) -> Result<DotEnvBackend<'a, E>> {
+        match &self.env_file {
+            Some(env_file) => DotEnvBackend::from_file(env, env_file),
+            None if Path::new(DEFAULT_ENV_FILE).is_file() => {
+                DotEnvBackend::from_file(env, Path::new(DEFAULT_ENV_FILE))
+            }
+            None => Ok(DotEnvBackend::new(env)),
+        }
+    }
+
+    /// Usage information for the given program name
+    #[must_use]
+    pub fn usage(program: &str) -> String {
+        /// The width of the column of commands and options, after which their help is aligned
+        const COLUMN: usize = 26;
+        let mut usage = format!(
+            "Usage: {program} [{}] [--config <config_file>] [--env-file <env_file>]\n\nCommands:\n",
+            COMMANDS
+                .iter()
+                .map(CommandInfo::synopsis)
+                .collect::<Vec<_>>()
+                .join(" | ")
+        );
+        let entries = COMMANDS
+            .iter()
+            .map(|command| (command.synopsis(), command.help))
+            .chain(std::iter::once((String::new(), &[][..])))
+            .chain(
+                OPTIONS
+                    .iter()
+                    .map(|option| (option.synopsis(), option.help)),
+            );
+        for (index, (synopsis, help)) in entries.enumerate() {
+            if index == COMMANDS.len() {
+                usage.push_str("\nOptions:\n");
+                continue;
+            }
+            let mut lines = help.iter();
+            if synopsis.len() > COLUMN {
+                usage.push_str(&format!("  {synopsis}\n"));
+            } else if let Some(line) = lines.next() {
+                usage.push_str(&format!("  {synopsis:COLUMN$}  {line}\n"));
+            }
+            for line in lines {
+                usage.push_str(&format!("{:width$}{line}\n", "", width = COLUMN + 4));
+            }
+        }
+        usage.trim_end().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::MockEnvBackend;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    fn args(args: &[&str]) -> Result<Args> {
+        Args::parse(args.iter().map(ToString::to_string))
+    }
+
+    #[rstest]
+    #[case::none(&[], Args::default())]
+    #[case::help(&["--help"], Args { help: true, ..Args::default() })]
+    #[case::short_help(&["-h"], Args { help: true, ..Args::default() })]
+    #[case::positional(&["config.yaml"], Args { config: Some("config.yaml".into()), ..Args::default() })]
+    #[case::flag(&["--config", "config.yaml"], Args { config: Some("config.yaml".into()), ..Args::default() })]
+    #[case::short_flag(&["-c", "config.yaml"], Args { config: Some("config.yaml".into()), ..Args::default() })]
+    #[case::flag_equals(&["--config=config.yaml"], Args { config: Some("config.yaml".into()), ..Args::default() })]
+    #[case::env_file(&["--env-file", "dev.env", "config.yaml"], Args { config: Some("config.yaml".into()), env_file: Some("dev.env".into()), ..Args::default() })]
+    #[case::validate(&["validate", "config.yaml"], Args { command: Command::Validate, config: Some("config.yaml".into()), ..Args::default() })]
+    #[case::dry_run(&["--config", "config.yaml", "--dry-run"], Args { command: Command::Validate, config: Some("config.yaml".into()), ..Args::default() })]
+    #[case::print_default_config(&["print-default-config"], Args { command: Command::PrintDefaultConfig, ..Args::default() })]
+    #[case::print_config_schema(&["print-config-schema"], Args { command: Command::PrintConfigSchema, ..Args::default() })]
+    #[case::config_schema(&["--config-schema"], Args { command: Command::PrintConfigSchema, ..Args::default() })]
+    #[case::env_file_equals(&["--env-file=dev.env"], Args { env_file: Some("dev.env".into()), ..Args::default() })]
+    #[case::export_cache(&["export-cache", "cache.tar", "config.yaml"], Args { command: Command::ExportCache, snapshot: Some("cache.tar".into()), config: Some("config.yaml".into()), ..Args::default() })]
+    #[case::import_cache(&["--config", "config.yaml", "import-cache", "cache.tar"], Args { command: Command::ImportCache, snapshot: Some("cache.tar".into()), config: Some("config.yaml".into()), ..Args::default() })]
+    #[case::prefetch(&["prefetch", "--config", "config.yaml"], Args { command: Command::Prefetch, config: Some("config.yaml".into()), ..Args::default() })]
+    #[case::list_images(&["list-images"], Args { command: Command::ListImages, ..Args::default() })]
+    #[case::list_images_json(&["list-images", "--json"], Args { command: Command::ListImages, json: true, ..Args::default() })]
+    #[case::completions(&["completions", "zsh"], Args { command: Command::Completions, shell: Some(Shell::Zsh), ..Args::default() })]
+    #[case::man(&["man"], Args { command: Command::Man, ..Args::default() })]
+    #[case::daemon(&["--daemon", "--pidfile", "/run/server.pid"], Args { daemon: true, pidfile: Some("/run/server.pid".into()), ..Args::default() })]
+    #[case::pidfile_equals(&["--pidfile=/run/server.pid"], Args { pidfile: Some("/run/server.pid".into()), ..Args::default() })]
+    #[case::install_service(&["install-service", "--config", "config.yaml"], Args { command: Command::InstallService, config: Some("config.yaml".into()), ..Args::default() })]
+    #[case::uninstall_service(&["uninstall-service"], Args { command: Command::UninstallService, ..Args::default() })]
+    #[case::service(&["--service", "config.yaml"], Args { service: true, config: Some("config.yaml".into()), ..Args::default() })]
+    #[case::sign_url(&["sign-url", "abc", "1h"], Args { command: Command::SignUrl, image_id: Some("abc".into()), expires_in: Some(Duration::from_secs(3600)), ..Args::default() })]
+    fn test_parse_args(#[case] input: &[&str], #[case] expected: Args) {
+        assert_eq!(args(input).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case::missing_value(&["--config"])]
+    #[case::missing_env_file(&["--env-file"])]
+    #[case::unknown_flag(&["--verbose"])]
+    #[case::config_twice(&["--config", "a.toml", "b.toml"])]
+    #[case::missing_snapshot(&["export-cache"])]
+    #[case::missing_expiry(&["sign-url", "abc"])]
+    #[case::invalid_expiry(&["sign-url", "abc", "soon"])]
+    #[case::missing_shell(&["completions"])]
+    #[case::missing_pidfile(&["--daemon", "--pidfile"])]
+    #[case::unknown_shell(&["completions", "tcsh"])]
+    fn test_parse_args_invalid(#[case] input: &[&str]) {
+        assert!(args(input).is_err());
+    }
+
+    /// Every documented command and option is understood, so the documentation doesn't drift from the parser
+    #[test]
+    fn test_documented_arguments_parse() {
+        let value = |arg: &ArgInfo| match arg.hint {
+            ValueHint::Shell => "bash".to_string(),
+            ValueHint::File | ValueHint::Any => "1h".to_string(),
+        };
+        for command in COMMANDS {
+            let input =
+                std::iter::once(command.name.to_string()).chain(command.args.iter().map(value));
+            let parsed = Args::parse(input).unwrap();
+            assert_ne!(parsed.command, Command::Serve, "{}", command.name);
+        }
+        for option in OPTIONS {
+            let spellings = std::iter::once(format!("--{}", option.long))
+                .chain(option.short.map(|short| format!("-{short}")));
+            for spelling in spellings {
+                let input = std::iter::once(spelling).chain(option.value.as_ref().map(value));
+                assert!(Args::parse(input).is_ok(), "{}", option.long);
+            }
+        }
+    }
+
+    #[test]
+    fn test_usage() {
+        let usage = Args::usage("random-image-server");
+        assert!(usage.starts_with(
+            "Usage: random-image-server [validate | print-default-config | print-config-schema | export-cache <archive> |"
+        ));
+        assert!(usage.contains(
+            "\n  validate                    Check the configuration and its sources, then exit.\n                              Exits with"
+        ));
+        assert!(usage.contains(
+            "\n  sign-url <image_id> <expires_in>\n                              Print a link"
+        ));
+        assert!(usage.contains("\n\nOptions:\n  -c, --config <config_file>  The config file"));
+        assert!(usage.contains("\n      --dry-run               Same as `validate`\n"));
+        assert!(usage.ends_with("  -h, --help                  Print this help message"));
+        // the defaults documented are the ones used
+        assert!(usage.contains(&format!("${CONFIG_ENV_VAR}, then ./{DEFAULT_CONFIG_FILE}")));
+        assert!(usage.contains(&format!("./{DEFAULT_ENV_FILE}, if it exists")));
+    }
+
+    #[rstest]
+    #[case::flag(Some("flag.toml"), Some("env.toml"), Some("flag.toml"))]
+    #[case::env(None, Some("env.toml"), Some("env.toml"))]
+    #[case::empty_env(None, Some(""), None)]
+    #[case::neither(None, None, None)]
+    fn test_config_file_precedence(
+        #[case] flag: Option<&str>,
+        #[case] env_var: Option<&str>,
+        #[case] expected: Option<&str>,
+    ) {
+        let mut env = MockEnvBackend::default();
+        if let Some(value) = env_var {
+            env.set_var(CONFIG_ENV_VAR, value);
+        }
+        let args = Args {
+            config: flag.map(PathBuf::from),
+            ..Args::default()
+        };
+
+        assert_eq!(
+            args.config_file_with_env_backend(&env),
+            expected.map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_env_backend_from_env_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let env_file = temp_dir.path().join("dev.env");
+        std::fs::write(&env_file, format!("{CONFIG_ENV_VAR}=dev.yaml\n")).unwrap();
+        let args = Args {
+            env_file: Some(env_file),
+            ..Args::default()
+        };
+
+        let env = MockEnvBackend::default();
+        let env = args.env_backend(&env).unwrap();
+        assert_eq!(
+            args.config_file_with_env_backend(&env),
+            Some(PathBuf::from("dev.yaml"))
+        );
+    }
+
+    #[test]
+    fn test_env_backend_missing_env_file() {
+        let args = Args {
+            env_file: Some(PathBuf::from("/nonexistent/dev.env")),
+            ..Args::default()
+        };
+
+        assert!(args.env_backend(&MockEnvBackend::default()).is_err());
+    }
+}
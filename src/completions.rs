@@ -0,0 +1,405 @@
+//! Shell completion scripts, generated from the commands and options documented in [`cli`](crate::cli).
+
+use std::{fmt::Write, str::FromStr};
+
+use anyhow::anyhow;
+
+use crate::cli::{COMMANDS, OPTIONS, ValueHint};
+
+/// A shell completion scripts are generated for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    /// Every shell completion scripts are generated for
+    pub const ALL: [Self; 3] = [Self::Bash, Self::Zsh, Self::Fish];
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Bash => "bash",
+            Self::Zsh => "zsh",
+            Self::Fish => "fish",
+        }
+    }
+}
+
+impl FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|shell| shell.name() == s)
+            .ok_or_else(|| anyhow!("Unsupported shell {s:?}, expected one of: bash, zsh, fish"))
+    }
+}
+
+/// The completion script of the given shell for the program with the given name
+#[must_use]
+pub fn script(shell: Shell, program: &str) -> String {
+    match shell {
+        Shell::Bash => bash(program),
+        Shell::Zsh => zsh(program),
+        Shell::Fish => fish(program),
+    }
+}
+
+/// The name of the shell function completing the program
+fn function_name(program: &str) -> String {
+    format!(
+        "_{}",
+        program.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    )
+}
+
+fn shell_names() -> String {
+    Shell::ALL.map(Shell::name).join(" ")
+}
+
+/// The commands whose first argument is completed with the given hint, as alternatives of a `case` pattern
+fn commands_taking(hint: ValueHint) -> String {
+    COMMANDS
+        .iter()
+        .filter(|command| command.args.first().is_some_and(|arg| arg.hint == hint))
+        .map(|command| command.name)
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// The commands and options whose value is completed with the given hint, as alternatives of a `case` pattern
+fn taking(hint: ValueHint) -> String {
+    let options = OPTIONS
+        .iter()
+        .filter(|option| option.value.is_some_and(|value| value.hint == hint))
+        .flat_map(|option| {
+            std::iter::once(format!("--{}", option.long))
+                .chain(option.short.map(|short| format!("-{short}")))
+        });
+    std::iter::once(commands_taking(hint))
+        .chain(options)
+        .filter(|pattern| !pattern.is_empty())
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+fn bash(program: &str) -> String {
+    let function = function_name(program);
+    let commands = COMMANDS
+        .iter()
+        .map(|command| command.name)
+        .collect::<Vec<_>>();
+    let value_arms = [
+        (
+            ValueHint::File,
+            r#"COMPREPLY=($(compgen -f -- "$cur"))"#.to_string(),
+        ),
+        (
+            ValueHint::Shell,
+            format!(r#"COMPREPLY=($(compgen -W "{}" -- "$cur"))"#, shell_names()),
+        ),
+        (ValueHint::Any, "COMPREPLY=()".to_string()),
+    ]
+    .into_iter()
+    .filter_map(|(hint, completion)| {
+        let pattern = taking(hint);
+        (!pattern.is_empty()).then(|| {
+            format!(
+                "        {pattern})\n            {completion}\n            return\n            ;;\n"
+            )
+        })
+    })
+    .collect::<String>();
+    let options = OPTIONS
+        .iter()
+        .flat_map(|option| {
+            option
+                .short
+                .map(|short| format!("-{short}"))
+                .into_iter()
+                .chain(std::iter::once(format!("--{}", option.long)))
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        r#"# bash completion for {program}, generated by `{program} completions bash`
+{function}() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" prev="${{COMP_WORDS[COMP_CWORD-1]}}" word
+    case "$prev" in
+{value_arms}    esac
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "{options}" -- "$cur"))
+        return
+    fi
+    # a config file may follow the command
+    for word in "${{COMP_WORDS[@]:1:COMP_CWORD-1}}"; do
+        case "$word" in
+            {command_pattern})
+                COMPREPLY=($(compgen -f -- "$cur"))
+                return
+                ;;
+        esac
+    done
+    COMPREPLY=($(compgen -W "{commands}" -- "$cur") $(compgen -f -- "$cur"))
+}}
+complete -o filenames -F {function} {program}
+"#,
+        command_pattern = commands.join("|"),
+        commands = commands.join(" "),
+    )
+}
+
+/// Escape text for a single-quoted zsh string
+fn zsh_quote(text: &str) -> String {
+    text.replace('\'', r"'\''")
+}
+
+/// Escape the help of an option for the brackets of an `_arguments` spec, where backslashes
+/// escape, brackets delimit the help, and colons delimit the value
+fn zsh_option_help(text: &str) -> String {
+    text.replace('\\', r"\\")
+        .replace('[', r"\[")
+        .replace(']', r"\]")
+        .replace(':', r"\:")
+}
+
+fn zsh(program: &str) -> String {
+    let function = function_name(program);
+    let mut commands = String::new();
+    for command in COMMANDS {
+        let help = command.help.join(" ");
+        let _ = writeln!(commands, "        '{}:{}'", command.name, zsh_quote(&help));
+    }
+    let mut options = String::new();
+    for option in OPTIONS {
+        let help = zsh_option_help(&option.help.join(" "));
+        let spellings = match option.short {
+            Some(short) => format!(
+                "'(-{short} --{long})'{{-{short},--{long}}}'",
+                long = option.long
+            ),
+            None => format!("'--{}", option.long),
+        };
+        let value = match option.value {
+            Some(value) if value.hint == ValueHint::File => format!(":{}:_files", value.name),
+            Some(value) => format!(":{}: ", value.name),
+            None => String::new(),
+        };
+        let _ = writeln!(
+            options,
+            "        {spellings}[{}]{value}' \\",
+            zsh_quote(&help)
+        );
+    }
+    let arg_arms = [
+        (ValueHint::File, "_files".to_string()),
+        (ValueHint::Shell, format!("_values shell {}", shell_names())),
+        (ValueHint::Any, "_message value".to_string()),
+    ]
+    .into_iter()
+    .filter_map(|(hint, completion)| {
+        let pattern = commands_taking(hint);
+        (!pattern.is_empty()).then(|| format!("                {pattern}) {completion} ;;\n"))
+    })
+    .collect::<String>();
+    format!(
+        r#"#compdef {program}
+
+# zsh completion for {program}, generated by `{program} completions zsh`
+{function}() {{
+    local -a commands
+    commands=(
+{commands}    )
+    local state
+    _arguments -s \
+{options}        '1: :->command' \
+        '*:: :->args'
+    case $state in
+        command)
+            _describe -t commands command commands
+            _files
+            ;;
+        args)
+            case $words[1] in
+{arg_arms}                *) _files ;;
+            esac
+            ;;
+    esac
+}}
+
+{function} "$@"
+"#,
+    )
+}
+
+/// Escape text for a single-quoted fish string
+fn fish_quote(text: &str) -> String {
+    text.replace('\\', r"\\").replace('\'', r"\'")
+}
+
+fn fish(program: &str) -> String {
+    let mut script = format!(
+        "# fish completion for {program}, generated by `{program} completions fish`\ncomplete -c {program} -f\n"
+    );
+    let names = COMMANDS
+        .iter()
+        .map(|command| command.name)
+        .collect::<Vec<_>>()
+        .join(" ");
+    for command in COMMANDS {
+        let help = command.help.join(" ");
+        let _ = writeln!(
+            script,
+            "complete -c {program} -n 'not __fish_seen_subcommand_from {names}' -a {} -d '{}'",
+            command.name,
+            fish_quote(&help)
+        );
+    }
+    // a config file may be given instead of, or after, a command
+    let _ = writeln!(script, "complete -c {program} -F");
+    for option in OPTIONS {
+        let help = option.help.join(" ");
+        let short = option
+            .short
+            .map(|short| format!(" -s {short}"))
+            .unwrap_or_default();
+        let value = match option.value {
+            Some(value) if value.hint == ValueHint::File => " -r -F",
+            Some(_) => " -r",
+            None => "",
+        };
+        let _ = writeln!(
+            script,
+            "complete -c {program}{short} -l {}{value} -d '{}'",
+            option.long,
+            fish_quote(&help)
+        );
+    }
+    let _ = writeln!(
+        script,
+        "complete -c {program} -n '__fish_seen_subcommand_from {}' -a '{}'",
+        commands_taking(ValueHint::Shell).replace('|', " "),
+        shell_names()
+    );
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::bash("bash", Shell::Bash)]
+    #[case::zsh("zsh", Shell::Zsh)]
+    #[case::fish("fish", Shell::Fish)]
+    fn test_parse_shell(#[case] name: &str, #[case] expected: Shell) {
+        assert_eq!(name.parse::<Shell>().unwrap(), expected);
+        assert_eq!(expected.name(), name);
+    }
+
+    #[test]
+    fn test_parse_unknown_shell() {
+        assert!("powershell".parse::<Shell>().is_err());
+    }
+
+    #[test]
+    fn test_bash() {
+        let script = script(Shell::Bash, "random-image-server");
+        assert!(script.contains("\n_random_image_server() {\n"));
+        assert!(
+            script.ends_with("complete -o filenames -F _random_image_server random-image-server\n")
+        );
+        assert!(
+            script.contains(
+                "\n        export-cache|import-cache|--config|-c|--env-file|--pidfile)\n"
+            )
+        );
+        assert!(script.contains(
+            "\n        completions)\n            COMPREPLY=($(compgen -W \"bash zsh fish\""
+        ));
+        assert!(script.contains(
+            "compgen -W \"-c --config --env-file --json --dry-run --config-schema --daemon --pidfile --service -h --help\""
+        ));
+    }
+
+    #[test]
+    fn test_zsh() {
+        let script = script(Shell::Zsh, "random-image-server");
+        assert!(script.starts_with("#compdef random-image-server\n"));
+        assert!(script.contains("\n        'man:Print the man page'\n"));
+        assert!(script.contains(
+            "\n        '(-c --config)'{-c,--config}'[The config file to use (TOML, YAML, or JSON), also accepted as a positional argument. Defaults to $RANDOM_IMAGE_SERVER_CONFIG, then ./config.toml]:config_file:_files' \\\n"
+        ));
+        assert!(script.contains(
+            "\n        '--json[Print JSON rather than a table, with `list-images`]' \\\n"
+        ));
+        assert!(script.contains("\n                completions) _values shell bash zsh fish ;;\n"));
+        assert!(script.ends_with("\n_random_image_server \"$@\"\n"));
+    }
+
+    #[test]
+    fn test_fish() {
+        let script = script(Shell::Fish, "random-image-server");
+        assert!(script.contains(
+            "\ncomplete -c random-image-server -n 'not __fish_seen_subcommand_from validate print-default-config print-config-schema export-cache import-cache prefetch list-images sign-url completions man install-service uninstall-service' -a man -d 'Print the man page'\n"
+        ));
+        assert!(script.contains(
+            "\ncomplete -c random-image-server -s c -l config -r -F -d 'The config file"
+        ));
+        assert!(
+            script
+                .contains("\ncomplete -c random-image-server -l dry-run -d 'Same as `validate`'\n")
+        );
+        assert!(script.ends_with(
+            "\ncomplete -c random-image-server -n '__fish_seen_subcommand_from completions' -a 'bash zsh fish'\n"
+        ));
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(zsh_quote("it's"), r"it'\''s");
+        assert_eq!(fish_quote(r"it's a \"), r"it\'s a \\");
+        assert_eq!(
+            zsh_quote(&zsh_option_help(r"it's [a:b] \")),
+            r"it'\''s \[a\:b\] \\"
+        );
+    }
+
+    /// Check the syntax of every script with its shell, skipping the shells that aren't installed
+    #[rstest]
+    #[case::bash(Shell::Bash, &["-n"])]
+    #[case::zsh(Shell::Zsh, &["-n"])]
+    #[case::fish(Shell::Fish, &["--no-execute"])]
+    fn test_syntax(#[case] shell: Shell, #[case] check: &[&str]) {
+        use std::io::Write as _;
+        use std::process::{Command, Stdio};
+
+        let Ok(mut child) = Command::new(shell.name())
+            .args(check)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        else {
+            eprintln!("{} isn't installed, skipping", shell.name());
+            return;
+        };
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(script(shell, "random-image-server").as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
@@ -1,91 +1,1760 @@
 use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
     net::{Ipv4Addr, SocketAddr},
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 
 use anyhow::{Result, anyhow};
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use tracing::Level;
 use url::Url;
 
+use crate::{
+    ALLOWED_IMAGE_EXTENSIONS,
+    access::Cidr,
+    archive::ArchiveFormat,
+    cache::CacheKey,
+    filter::{FileFilter, validate_glob},
+};
+
 const DEFAULT_PORT: u16 = 3000;
 const DEFAULT_HOST: url::Host = url::Host::Ipv4(Ipv4Addr::LOCALHOST);
 const DEFAULT_LOG_LEVEL: Level = Level::INFO;
+const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_STATSD_PORT: u16 = 8125;
+const DEFAULT_STATSD_PREFIX: &str = "random_image_server";
+const DEFAULT_STATSD_FLUSH_INTERVAL: u64 = 10;
+const DEFAULT_HTTP_TIMEOUT: u64 = 30;
+const DEFAULT_HTTP_MAX_REDIRECTS: usize = 10;
+const DEFAULT_HTTP_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_HTTP_MAX_BACKOFF: u64 = 300;
+const DEFAULT_HTTP_MAX_ARCHIVE_SIZE: u64 = 1024 * 1024 * 1024;
+const DEFAULT_HTTP_USER_AGENT: &str = concat!("random-image-server/", env!("CARGO_PKG_VERSION"));
+const DEFAULT_PLACEHOLDER_MAX_SIZE: u32 = 4096;
+
+/// Configuration structure for the server
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// Settings for the HTTP server
+    pub server: ServerConfig,
+    /// Settings for the image cache
+    #[serde(default)]
+    pub cache: CacheConfig,
+    /// Settings for error reporting
+    #[serde(default)]
+    pub observability: ObservabilityConfig,
+    /// Settings for exporting metrics
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    /// Settings for the HTTP client used to fetch images
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// Settings for signed, expiring links to images
+    #[serde(default)]
+    pub signing: SigningConfig,
+    /// Settings for serving HTTPS and HTTP/3
+    #[serde(default)]
+    pub tls: TlsConfig,
+    /// Settings for notifying other services of lifecycle events
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    /// Settings for proxying remote images through the cache
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    /// Settings for generating placeholder images
+    #[serde(default)]
+    pub placeholder: PlaceholderConfig,
+    /// Settings for transforming served images, e.g. drawing captions onto them
+    #[serde(default)]
+    pub transforms: TransformsConfig,
+    /// Additional named sets of images, each served under its own path, e.g. `/cats/random`
+    #[serde(default, deserialize_with = "deserialize_collections")]
+    pub collections: BTreeMap<String, CollectionConfig>,
+    /// Curated orders of images, each served one after the other at `/sequential/{name}`
+    #[serde(default, deserialize_with = "deserialize_playlists")]
+    pub playlists: BTreeMap<String, PlaylistConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// The port the server will listen on
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// The host the server will bind to
+    #[schemars(with = "String")]
+    #[serde(
+        deserialize_with = "deserialize_host",
+        serialize_with = "serialize_display",
+        default = "default_host"
+    )]
+    pub host: url::Host,
+    /// The log level, one of `error`, `warn`, `info`, `debug`, or `trace`
+    #[schemars(with = "String")]
+    #[serde(
+        deserialize_with = "deserialize_log_level",
+        serialize_with = "serialize_log_level",
+        default = "default_log_level"
+    )]
+    pub log_level: Level,
+    /// File to write logs to instead of stdout
+    #[serde(default)]
+    pub log_file: Option<PathBuf>,
+    /// When to rotate the log file
+    #[serde(default)]
+    pub log_rotation: LogRotation,
+    /// The size in bytes at which the log file is rotated, when `log_rotation = "size"`
+    #[serde(default = "default_log_max_size")]
+    pub log_max_size: u64,
+    /// The images to serve: paths to image files or directories, or URLs of images.
+    /// Either as plain strings, or as `[[server.source]]` tables with per-source settings.
+    #[schemars(with = "Vec<SourceEntry>", length(min = 1))]
+    #[serde(
+        alias = "source",
+        deserialize_with = "deserialize_sources",
+        serialize_with = "serialize_sources"
+    )]
+    pub sources: Vec<SourceConfig>,
+    /// Prefix that all routes are mounted under, e.g. `/images`
+    #[serde(deserialize_with = "deserialize_base_path", default)]
+    pub base_path: String,
+    /// The externally visible URL of the server, used when generating absolute links
+    #[serde(default)]
+    pub public_url: Option<Url>,
+    /// The proxies, as addresses or ranges like `10.0.0.0/8`, whose forwarding headers are trusted:
+    /// `X-Forwarded-For`/`Forwarded` for the client address, and `X-Forwarded-Proto`/`X-Forwarded-Host`
+    /// for the public URL
+    #[schemars(with = "Vec<String>")]
+    #[serde(default)]
+    pub trusted_proxies: Vec<Cidr>,
+    /// The domains images may be embedded on. If set, images are only served to requests whose
+    /// `Origin` (or `Referer`) is one of these domains or their subdomains, or that send neither
+    #[serde(default)]
+    pub allowed_referers: Vec<String>,
+    /// An image served instead of the requested one to requests from other domains,
+    /// which are refused with 403 Forbidden if unset
+    #[serde(default)]
+    pub hotlink_placeholder: Option<PathBuf>,
+    /// The client addresses allowed or denied access to the server
+    #[serde(default)]
+    pub access: AccessConfig,
+    /// The authentication required to access the server
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// Limits on the size of requests
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    /// A `.env` file to read `RANDOM_IMAGE_SERVER_*` variables from, in addition to the environment
+    #[serde(default)]
+    pub env_file: Option<PathBuf>,
+    /// Glob patterns of the files to load from directory sources, all files are loaded if empty
+    #[serde(deserialize_with = "deserialize_globs", default)]
+    pub include: Vec<String>,
+    /// Glob patterns of the files (or folders) to skip in directory sources, e.g. `*_thumb.jpg` or `.*`
+    #[serde(deserialize_with = "deserialize_globs", default)]
+    pub exclude: Vec<String>,
+    /// The file extensions of the images that are loaded, each must be known to be an image type
+    #[serde(
+        deserialize_with = "deserialize_extensions",
+        default = "default_allowed_extensions"
+    )]
+    pub allowed_extensions: Vec<String>,
+    /// The size in bytes below which images are skipped, e.g. to skip tiny icons
+    #[serde(default)]
+    pub min_file_size: Option<u64>,
+    /// The size in bytes above which images are skipped, e.g. to skip giant raw scans
+    #[serde(default)]
+    pub max_file_size: Option<u64>,
+    /// Collapse near-duplicate images, whose perceptual hashes differ in at most this many of their 64 bits.
+    /// Requires the `perceptual-hash` feature
+    #[serde(default)]
+    pub dedup_threshold: Option<u32>,
+    /// Rotate JPEGs upright as they're loaded, according to their EXIF orientation, so photos taken
+    /// with phones aren't served sideways. Requires the `transforms` feature
+    #[serde(default)]
+    pub auto_orient: bool,
+    /// How often every source is re-scanned for added and removed images, e.g. `"10m"` or `"1h30m"`.
+    /// Sources with their own `refresh_interval` are refreshed on that schedule instead
+    #[schemars(with = "Option<String>")]
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        default
+    )]
+    pub rescan_interval: Option<Duration>,
+    /// Skip every remote source (URLs, manifests, feeds, and remote archives), and never fetch anything over the network
+    #[serde(default)]
+    pub offline: bool,
+    /// Accept requests right away, while the cache is populated in the background, instead of once it is populated.
+    /// `/readyz` responds with 503 Service Unavailable until then, so slow remote sources don't delay liveness checks
+    #[serde(default)]
+    pub start_before_populate: bool,
+    /// What the server does if no images are found once the cache is populated: `fail` to exit with an error,
+    /// `wait` to keep running until images appear in the sources, or `placeholder` to also serve a generated
+    /// placeholder from `/random` and `/sequential` meanwhile
+    #[serde(default)]
+    pub on_empty: EmptyCachePolicy,
+    /// Serve a Swagger UI page rendering `/openapi.json` at `/docs`
+    #[serde(default)]
+    pub swagger_ui: bool,
+    /// Send the path or URL of each image served by `/random` and `/sequential` in the `X-Image-Source` header,
+    /// besides its opaque id in `X-Image-Id`
+    #[serde(default)]
+    pub expose_sources: bool,
+    /// How many of the most recently served images `/random` avoids, so the same image isn't shown
+    /// twice in a row, as long as there are others to choose from
+    #[serde(default)]
+    pub no_repeat_window: Option<usize>,
+    /// How long the image `/random` chooses for a client is pinned to it, e.g. `"5m"`, so dashboards that
+    /// refresh often don't flicker between images, for at most a week. Requests may set their own with `?sticky={seconds}`
+    #[schemars(with = "Option<String>")]
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        default
+    )]
+    pub sticky: Option<Duration>,
+    /// An icon served at `/favicon.ico` instead of the built-in one
+    #[serde(default)]
+    pub favicon: Option<PathBuf>,
+    /// A directory of static files, e.g. the CSS and scripts of a gallery, served under `/static/`
+    #[serde(default)]
+    pub static_dir: Option<PathBuf>,
+    /// A file created once the server is ready, as reported by `/readyz`, and removed when it shuts down,
+    /// so container health checks can test for it without an HTTP client
+    #[serde(default)]
+    pub ready_file: Option<PathBuf>,
+}
+
+const fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+const fn default_host() -> url::Host {
+    DEFAULT_HOST
+}
+const fn default_log_level() -> Level {
+    DEFAULT_LOG_LEVEL
+}
+const fn default_log_max_size() -> u64 {
+    DEFAULT_LOG_MAX_SIZE
+}
+fn default_allowed_extensions() -> Vec<String> {
+    ALLOWED_IMAGE_EXTENSIONS
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn deserialize_host<'de, D>(deserializer: D) -> Result<url::Host, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    url::Host::parse(&s).map_err(serde::de::Error::custom)
+}
+
+fn serialize_display<S>(value: &impl std::fmt::Display, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_str(value)
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)] // signature required by `serialize_with`
+fn serialize_log_level<S>(level: &Level, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&level.as_str().to_lowercase())
+}
+
+fn deserialize_log_level<'de, D>(deserializer: D) -> Result<Level, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let level: String = Deserialize::deserialize(deserializer)?;
+    Level::from_str(&level).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let duration: Option<String> = Deserialize::deserialize(deserializer)?;
+    duration
+        .as_deref()
+        .map(parse_duration)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_quality<'de, D>(deserializer: D) -> Result<Option<u8>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let quality: Option<u64> = Deserialize::deserialize(deserializer)?;
+    quality
+        .map(|quality| parse_quality(&quality.to_string()))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+#[allow(clippy::ref_option)] // signature required by `serialize_with`
+fn serialize_duration<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match duration {
+        Some(duration) => serializer.serialize_str(&format_duration(*duration)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Parse the quality JPEG images are encoded at, a whole number from 1 to 100
+///
+/// # Errors
+///
+/// Returns an error if the quality isn't a whole number from 1 to 100.
+pub fn parse_quality(quality: &str) -> Result<u8> {
+    match u8::from_str(quality.trim()) {
+        Ok(quality @ 1..=100) => Ok(quality),
+        _ => Err(anyhow!(
+            "Invalid quality {quality:?}, expected a whole number from 1 to 100"
+        )),
+    }
+}
+
+/// The units a duration can be given in, largest first
+const DURATION_UNITS: [(char, u64); 4] = [('d', 86_400), ('h', 3_600), ('m', 60), ('s', 1)];
+
+/// Parse a duration made of whole numbers of days, hours, minutes and seconds, e.g. `"10m"` or `"1h30m"`.
+/// A plain number is a number of seconds
+///
+/// # Errors
+///
+/// Returns an error if the duration is empty, zero, or not made of numbers followed by `d`, `h`, `m`, or `s`.
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let invalid = || anyhow!("Invalid duration {duration:?}, expected e.g. \"10m\" or \"1h30m\"");
+    let trimmed = duration.trim();
+    let seconds = if let Ok(seconds) = u64::from_str(trimmed) {
+        seconds
+    } else {
+        let (mut seconds, mut rest) = (0u64, trimmed);
+        while !rest.is_empty() {
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .ok_or_else(invalid)?;
+            let value = u64::from_str(&rest[..digits]).map_err(|_| invalid())?;
+            let unit = rest[digits..].chars().next().ok_or_else(invalid)?;
+            let (_, scale) = DURATION_UNITS
+                .iter()
+                .find(|(name, _)| *name == unit)
+                .ok_or_else(invalid)?;
+            seconds = value
+                .checked_mul(*scale)
+                .and_then(|value| seconds.checked_add(value))
+                .ok_or_else(invalid)?;
+            rest = &rest[digits + unit.len_utf8()..];
+        }
+        seconds
+    };
+    if seconds == 0 {
+        return Err(anyhow!("Duration must not be zero"));
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Format a duration the way it is written in the config, e.g. `"1h30m"`
+#[must_use]
+pub fn format_duration(duration: Duration) -> String {
+    let mut seconds = duration.as_secs();
+    let mut formatted = String::new();
+    for (name, scale) in DURATION_UNITS {
+        if seconds >= scale {
+            let _ = write!(formatted, "{}{name}", seconds / scale);
+            seconds %= scale;
+        }
+    }
+    if formatted.is_empty() {
+        formatted.push_str("0s");
+    }
+    formatted
+}
+
+fn deserialize_base_path<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let base_path: String = Deserialize::deserialize(deserializer)?;
+    normalize_base_path(&base_path).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_collections<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, CollectionConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let collections: BTreeMap<String, CollectionConfig> = Deserialize::deserialize(deserializer)?;
+    for name in collections.keys() {
+        check_collection_name(name).map_err(serde::de::Error::custom)?;
+    }
+    Ok(collections)
+}
+
+fn deserialize_playlists<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, PlaylistConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let playlists: BTreeMap<String, PlaylistConfig> = Deserialize::deserialize(deserializer)?;
+    for name in playlists.keys() {
+        check_playlist_name(name).map_err(serde::de::Error::custom)?;
+    }
+    Ok(playlists)
+}
+
+/// Check that a playlist name can be used as the last segment of `/sequential/{name}`
+///
+/// # Errors
+///
+/// Returns an error if the name is empty, or isn't made of ASCII letters, digits, `-`, and `_`.
+pub fn check_playlist_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        return Err(anyhow!(
+            "Invalid playlist name {name:?}: only ASCII letters, digits, `-`, and `_` are allowed"
+        ));
+    }
+    Ok(())
+}
+
+fn deserialize_playlist_items<'de, D>(deserializer: D) -> Result<Vec<ImageSource>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let items: Vec<String> = Deserialize::deserialize(deserializer)?;
+    if items.is_empty() {
+        return Err(serde::de::Error::custom(
+            "A playlist must have at least one item",
+        ));
+    }
+    items
+        .iter()
+        .map(|item| ImageSource::from_str(item).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn serialize_playlist_items<S>(items: &[ImageSource], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_seq(items.iter().map(ToString::to_string))
+}
+
+/// Check that a collection name can be used as the first segment of the paths of its routes
+///
+/// # Errors
+///
+/// Returns an error if the name is empty, isn't made of ASCII letters, digits, `-`, and `_`,
+/// or is the first segment of a route of the server, e.g. `image` or `stats`.
+pub fn check_collection_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        return Err(anyhow!(
+            "Invalid collection name {name:?}, only ASCII letters, digits, `-`, and `_` are allowed"
+        ));
+    }
+    if crate::openapi::is_route_segment(name) {
+        return Err(anyhow!(
+            "Invalid collection name {name:?}, it is already used by a route of the server"
+        ));
+    }
+    Ok(())
+}
+
+fn deserialize_globs<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let patterns: Vec<String> = Deserialize::deserialize(deserializer)?;
+    parse_globs(patterns).map_err(serde::de::Error::custom)
+}
+
+/// Check that every pattern is a valid glob
+fn parse_globs(patterns: impl IntoIterator<Item = String>) -> Result<Vec<String>> {
+    patterns
+        .into_iter()
+        .map(|pattern| validate_glob(&pattern).map(|()| pattern))
+        .collect()
+}
+
+/// Parse a comma-separated list of address ranges
+fn parse_cidrs(ranges: &str) -> Result<Vec<Cidr>, String> {
+    ranges
+        .split(',')
+        .map(str::trim)
+        .filter(|range| !range.is_empty())
+        .map(Cidr::from_str)
+        .collect()
+}
+
+fn deserialize_extensions<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let extensions: Vec<String> = Deserialize::deserialize(deserializer)?;
+    parse_extensions(extensions.iter().map(String::as_str)).map_err(serde::de::Error::custom)
+}
+
+/// Normalize file extensions to lowercase without a leading `.`, checking that each is an image type
+///
+/// # Errors
+///
+/// Returns an error if there are no extensions, or an extension isn't known to be an image type.
+fn parse_extensions<'a>(extensions: impl IntoIterator<Item = &'a str>) -> Result<Vec<String>> {
+    let extensions = extensions
+        .into_iter()
+        .map(|extension| {
+            let extension = extension
+                .trim()
+                .trim_start_matches('.')
+                .to_ascii_lowercase();
+            match mime_guess::from_ext(&extension).first() {
+                Some(mime) if mime.type_() == mime_guess::mime::IMAGE => Ok(extension),
+                Some(mime) => Err(anyhow!(
+                    "Extension {extension:?} is not an image type, but {mime}"
+                )),
+                None => Err(anyhow!("Extension {extension:?} is not a known image type")),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if extensions.is_empty() {
+        return Err(anyhow!("At least one allowed extension is required"));
+    }
+    Ok(extensions)
+}
+
+/// Normalize a base path so that it is either empty or starts with a `/` and has no trailing `/`
+///
+/// # Errors
+///
+/// Returns an error if the base path contains a query string, fragment, or whitespace.
+pub fn normalize_base_path(base_path: &str) -> Result<String> {
+    let trimmed = base_path.trim().trim_end_matches('/');
+    if trimmed.contains(['?', '#']) || trimmed.contains(char::is_whitespace) {
+        return Err(anyhow!("Invalid base path: {base_path}"));
+    }
+    if trimmed.is_empty() {
+        Ok(String::new())
+    } else if trimmed.starts_with('/') {
+        Ok(trimmed.to_string())
+    } else {
+        Ok(format!("/{trimmed}"))
+    }
+}
+
+/// Configuration for error reporting
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct ObservabilityConfig {
+    /// The Sentry DSN that handler errors, fetch failures, and panics are reported to.
+    /// Requires the `sentry` feature.
+    #[serde(default)]
+    pub sentry_dsn: Option<String>,
+    /// The environment reported to Sentry, e.g. `production`
+    #[serde(default)]
+    pub sentry_environment: Option<String>,
+}
+
+/// Configuration for exporting metrics
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct MetricsConfig {
+    /// The host of a statsd (or Datadog) agent to push metrics to
+    #[serde(default)]
+    pub statsd_host: Option<String>,
+    /// The port of the statsd agent
+    #[serde(default = "default_statsd_port")]
+    pub statsd_port: u16,
+    /// The prefix of the names of metrics pushed to statsd
+    #[serde(default = "default_statsd_prefix")]
+    pub statsd_prefix: String,
+    /// How often metrics are pushed to statsd, in seconds
+    #[serde(default = "default_statsd_flush_interval")]
+    pub statsd_flush_interval: u64,
+}
+
+const fn default_statsd_port() -> u16 {
+    DEFAULT_STATSD_PORT
+}
+fn default_statsd_prefix() -> String {
+    DEFAULT_STATSD_PREFIX.to_string()
+}
+const fn default_statsd_flush_interval() -> u64 {
+    DEFAULT_STATSD_FLUSH_INTERVAL
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            statsd_host: None,
+            statsd_port: DEFAULT_STATSD_PORT,
+            statsd_prefix: DEFAULT_STATSD_PREFIX.to_string(),
+            statsd_flush_interval: DEFAULT_STATSD_FLUSH_INTERVAL,
+        }
+    }
+}
+
+/// Configuration for the HTTP client used to fetch images, manifests, feeds, and archives
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct HttpConfig {
+    /// A proxy all requests are sent through. If unset, the system proxy settings are used
+    #[serde(default)]
+    pub proxy: Option<Url>,
+    /// How long a request may take, in seconds
+    #[serde(default = "default_http_timeout")]
+    pub timeout: u64,
+    /// How many redirects are followed, 0 to not follow redirects
+    #[serde(default = "default_http_max_redirects")]
+    pub max_redirects: usize,
+    /// The `User-Agent` header sent with requests
+    #[serde(default = "default_http_user_agent")]
+    pub user_agent: String,
+    /// A PEM file of CA certificates to trust, in addition to the built-in ones
+    #[serde(default)]
+    pub ca_bundle: Option<PathBuf>,
+    /// Whether TLS certificates are verified. Disabling this is insecure
+    #[serde(default = "default_tls_verify")]
+    pub tls_verify: bool,
+    /// How many fetches from a host may fail in a row before requests to it are skipped, 0 to never skip them
+    #[serde(default = "default_http_failure_threshold")]
+    pub failure_threshold: u32,
+    /// The longest a failing URL is backed off for, in seconds, and how often a failing host is probed again
+    #[serde(default = "default_http_max_backoff")]
+    pub max_backoff: u64,
+    /// The largest archive fetched from a URL, in bytes, as it's held in memory while its images are read
+    #[serde(default = "default_http_max_archive_size")]
+    pub max_archive_size: u64,
+}
+
+const fn default_http_timeout() -> u64 {
+    DEFAULT_HTTP_TIMEOUT
+}
+const fn default_http_max_redirects() -> usize {
+    DEFAULT_HTTP_MAX_REDIRECTS
+}
+fn default_http_user_agent() -> String {
+    DEFAULT_HTTP_USER_AGENT.to_string()
+}
+const fn default_tls_verify() -> bool {
+    true
+}
+const fn default_http_failure_threshold() -> u32 {
+    DEFAULT_HTTP_FAILURE_THRESHOLD
+}
+const fn default_http_max_backoff() -> u64 {
+    DEFAULT_HTTP_MAX_BACKOFF
+}
+const fn default_http_max_archive_size() -> u64 {
+    DEFAULT_HTTP_MAX_ARCHIVE_SIZE
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            timeout: DEFAULT_HTTP_TIMEOUT,
+            max_redirects: DEFAULT_HTTP_MAX_REDIRECTS,
+            user_agent: DEFAULT_HTTP_USER_AGENT.to_string(),
+            ca_bundle: None,
+            tls_verify: true,
+            failure_threshold: DEFAULT_HTTP_FAILURE_THRESHOLD,
+            max_backoff: DEFAULT_HTTP_MAX_BACKOFF,
+            max_archive_size: DEFAULT_HTTP_MAX_ARCHIVE_SIZE,
+        }
+    }
+}
+
+/// Which client addresses may access the server, checked before requests are routed
+///
+/// The client address is the peer address, or the one a trusted proxy forwarded the request for
+/// in the `Forwarded` or `X-Forwarded-For` header.
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct AccessConfig {
+    /// The address ranges, e.g. `10.0.0.0/8`, that may access the server. Any address may if empty
+    #[schemars(with = "Vec<String>")]
+    #[serde(default)]
+    pub allow: Vec<Cidr>,
+    /// The address ranges that may not access the server, even if they're allowed
+    #[schemars(with = "Vec<String>")]
+    #[serde(default)]
+    pub deny: Vec<Cidr>,
+}
+
+/// The authentication required to access the server
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct AuthConfig {
+    /// The credentials of HTTP basic authentication, required by every route except the health checks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub basic: Option<HashedBasicAuth>,
+}
+
+/// The smallest `max_header_size` the HTTP/1 server can parse requests with
+pub const MIN_MAX_HEADER_SIZE: usize = 8192;
+const DEFAULT_MAX_URI_LENGTH: usize = 8192;
+const DEFAULT_MAX_HEADER_SIZE: usize = 16 * 1024;
+const DEFAULT_MAX_HEADERS: usize = 100;
+
+/// Limits on the size of requests, which protect the server from requests that would take up
+/// too much of its memory
+///
+/// The limits apply to every route alike. No route accepts a request body, so requests with one are always refused
+/// with 400 Bad Request.
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+pub struct LimitsConfig {
+    /// The longest path and query of a request, in bytes. Longer ones are refused with 414 URI Too Long
+    #[serde(default = "default_max_uri_length")]
+    pub max_uri_length: usize,
+    /// The largest request line and headers of a request, in bytes, at least 8192.
+    /// Larger ones are refused with 431 Request Header Fields Too Large
+    #[schemars(range(min = 8192))]
+    #[serde(
+        deserialize_with = "deserialize_max_header_size",
+        default = "default_max_header_size"
+    )]
+    pub max_header_size: usize,
+    /// The most headers a request may have. Requests with more are refused with 431 Request Header Fields Too Large
+    #[serde(default = "default_max_headers")]
+    pub max_headers: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_uri_length: DEFAULT_MAX_URI_LENGTH,
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_headers: DEFAULT_MAX_HEADERS,
+        }
+    }
+}
+
+const fn default_max_uri_length() -> usize {
+    DEFAULT_MAX_URI_LENGTH
+}
+const fn default_max_header_size() -> usize {
+    DEFAULT_MAX_HEADER_SIZE
+}
+const fn default_max_headers() -> usize {
+    DEFAULT_MAX_HEADERS
+}
+
+/// Parse the largest size of the request line and headers, which can't be below [`MIN_MAX_HEADER_SIZE`]
+fn parse_max_header_size(s: &str) -> Result<usize, String> {
+    let size = usize::from_str(s.trim()).map_err(|e| e.to_string())?;
+    check_max_header_size(size)
+}
+
+fn check_max_header_size(size: usize) -> Result<usize, String> {
+    if size < MIN_MAX_HEADER_SIZE {
+        return Err(format!(
+            "The max header size must be at least {MIN_MAX_HEADER_SIZE} bytes, got {size}"
+        ));
+    }
+    Ok(size)
+}
+
+fn deserialize_max_header_size<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    check_max_header_size(usize::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// Credentials for HTTP basic authentication, with the password stored as a hash
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct HashedBasicAuth {
+    pub username: String,
+    /// A bcrypt (`$2b$...`) or argon2 (`$argon2id$...`) hash of the password
+    pub password_hash: String,
+}
+
+/// Configuration for signed, expiring links to images, of the form `/image/{id}?expires=...&sig=...`
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct SigningConfig {
+    /// The secret links are signed with. Links to images are only checked if it's set
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Whether images are only served through signed links, so that `/random`, `/sequential`,
+    /// `/list`, and unsigned links to images respond with 403 Forbidden
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// Configuration for serving HTTPS, and HTTP/3 with the `http3` feature
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// A PEM file of the certificate chain of the server. HTTPS is served instead of HTTP if it's set, along with `key`
+    #[serde(default)]
+    pub cert: Option<PathBuf>,
+    /// A PEM file of the private key of the certificate
+    #[serde(default)]
+    pub key: Option<PathBuf>,
+    /// Certificates served instead of `cert` to the clients of the hostnames they're keyed by, as sent with SNI.
+    /// Hostnames may start with a `*.` wildcard, matching any single label
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub certificates: BTreeMap<String, CertificateFiles>,
+    /// Whether HTTP/3 is also served, over QUIC on the UDP port of the same number,
+    /// and advertised to HTTPS clients with the `Alt-Svc` header. Requires the `http3` feature
+    #[serde(default)]
+    pub http3: bool,
+    /// A PEM file of the certificate authorities client certificates are verified against.
+    /// If set, clients must authenticate with a certificate signed by one of them
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+    /// Obtain and renew the certificate automatically from an ACME certificate authority, e.g. Let's Encrypt,
+    /// instead of loading it from `cert` and `key`. Requires the `acme` feature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acme: Option<AcmeConfig>,
+}
+
+/// A certificate chain and its private key, both in PEM files
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CertificateFiles {
+    /// A PEM file of the certificate chain
+    pub cert: PathBuf,
+    /// A PEM file of the private key of the certificate
+    pub key: PathBuf,
+}
+
+const DEFAULT_ACME_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+const DEFAULT_ACME_CACHE_DIR: &str = "acme";
+
+/// Configuration for obtaining certificates from an ACME certificate authority
+///
+/// Certificates are validated with the `tls-alpn-01` challenge, which the server answers on its own port,
+/// so the domains must resolve to the server and it must be reachable on port 443.
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct AcmeConfig {
+    /// The domains the certificate is obtained for
+    pub domains: Vec<String>,
+    /// The contact address of the ACME account, e.g. for notices about expiring certificates
+    #[serde(default)]
+    pub email: Option<String>,
+    /// The directory the account key and the certificate are stored in, so they're reused across restarts
+    #[serde(default = "default_acme_cache_dir")]
+    pub cache_dir: PathBuf,
+    /// The directory URL of the certificate authority, Let's Encrypt by default
+    #[serde(default = "default_acme_directory")]
+    pub directory: Url,
+}
+
+fn default_acme_cache_dir() -> PathBuf {
+    PathBuf::from(DEFAULT_ACME_CACHE_DIR)
+}
+
+fn default_acme_directory() -> Url {
+    Url::parse(DEFAULT_ACME_DIRECTORY).expect("the default ACME directory is a valid URL")
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        Self {
+            domains: Vec::new(),
+            email: None,
+            cache_dir: default_acme_cache_dir(),
+            directory: default_acme_directory(),
+        }
+    }
+}
+
+/// Configuration for notifying other services of lifecycle events, e.g. to post them to Slack
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct NotificationsConfig {
+    /// A URL that events (startup, populating the cache, failing sources, and shutdown) are POSTed to as JSON
+    #[serde(default)]
+    pub webhook_url: Option<Url>,
+}
+
+/// Configuration for proxying remote images through the cache, at `/proxy?url=...`
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// The domains images may be proxied from, including their subdomains. The proxy is disabled if empty
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    /// The cache proxied images are stored in, in the same form as `[cache]`.
+    /// If unset, an in-memory cache of up to 256 MiB, evicting the oldest images first
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
+}
+
+/// Configuration for generating placeholder images, at `/placeholder/{width}x{height}`
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct PlaceholderConfig {
+    /// Whether placeholder images are generated
+    #[serde(default)]
+    pub enabled: bool,
+    /// The widest placeholder generated, in pixels
+    #[serde(default = "default_placeholder_max_size")]
+    pub max_width: u32,
+    /// The tallest placeholder generated, in pixels
+    #[serde(default = "default_placeholder_max_size")]
+    pub max_height: u32,
+    /// The cache generated placeholders are stored in, in the same form as `[cache]`.
+    /// If unset, an in-memory cache of up to 64 MiB, evicting the oldest images first
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
+}
+
+const fn default_placeholder_max_size() -> u32 {
+    DEFAULT_PLACEHOLDER_MAX_SIZE
+}
+
+impl Default for PlaceholderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_width: DEFAULT_PLACEHOLDER_MAX_SIZE,
+            max_height: DEFAULT_PLACEHOLDER_MAX_SIZE,
+            cache: None,
+        }
+    }
+}
+
+/// Configuration for transforming served images, e.g. `/random?caption=...`,
+/// which requires the `transforms` feature
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct TransformsConfig {
+    /// The cache transformed variants of images are stored in, in the same form as `[cache]`.
+    /// If unset, an in-memory cache of up to 128 MiB, evicting the oldest images first
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
+    /// The quality, from 1 to 100, JPEG images are re-encoded at unless a request sets `?quality=`,
+    /// e.g. to serve smaller files than the originals. If unset, JPEGs are served as they are
+    #[serde(default, deserialize_with = "deserialize_quality")]
+    #[schemars(range(min = 1, max = 100))]
+    pub quality: Option<u8>,
+    /// The formats JPEG and PNG images are pre-encoded in as they're loaded, in order of preference,
+    /// e.g. `["avif", "webp"]`. Clients whose `Accept` header lists one of them are served it instead
+    /// of the original, if it's smaller. Requires the `variants` feature
+    #[serde(default)]
+    pub formats: Vec<VariantFormat>,
+}
+
+/// A format images are pre-encoded in, for the clients that accept it
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum VariantFormat {
+    /// AVIF, a lossy format that's much smaller than JPEG at the same quality
+    Avif,
+    /// Lossless WebP, usually smaller than PNG
+    Webp,
+}
+
+impl FromStr for VariantFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "avif" => Ok(Self::Avif),
+            "webp" => Ok(Self::Webp),
+            _ => Err(format!("Unknown variant format: {s}")),
+        }
+    }
+}
+
+/// Configuration for a collection, a named set of images served under `/{name}/`,
+/// e.g. `/cats/random` and `/cats/list`, with a cache of its own
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct CollectionConfig {
+    /// The images of the collection, in the same forms as `server.sources`
+    #[schemars(with = "Vec<SourceEntry>", length(min = 1))]
+    #[serde(
+        alias = "source",
+        deserialize_with = "deserialize_sources",
+        serialize_with = "serialize_sources"
+    )]
+    pub sources: Vec<SourceConfig>,
+    /// The cache the images of the collection are stored in, in the same form as `[cache]`,
+    /// e.g. to keep a small collection in memory and a large one on disk.
+    /// If unset, the settings of `[cache]` are used, with a cache of the collection's own.
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
+}
+
+/// Configuration for a playlist, a curated order of images served one after the other at `/sequential/{name}`,
+/// with a position of its own
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct PlaylistConfig {
+    /// The images of the playlist, in order: paths of images or directories, URLs of images,
+    /// or any of `server.sources`, whose images are played in the order they were loaded.
+    /// Only images loaded from `server.sources` are played.
+    #[schemars(with = "Vec<String>", length(min = 1))]
+    #[serde(
+        deserialize_with = "deserialize_playlist_items",
+        serialize_with = "serialize_playlist_items"
+    )]
+    pub items: Vec<ImageSource>,
+}
+
+/// When the log file should be rotated
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    /// Start a new log file every day
+    #[default]
+    Daily,
+    /// Start a new log file every hour
+    Hourly,
+    /// Start a new log file once the current one exceeds `log_max_size`
+    Size,
+}
+
+impl FromStr for LogRotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "daily" => Ok(Self::Daily),
+            "hourly" => Ok(Self::Hourly),
+            "size" => Ok(Self::Size),
+            _ => Err(format!("Unknown log rotation: {s}")),
+        }
+    }
+}
+
+/// What the server does if no images are found once the cache is populated
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyCachePolicy {
+    /// Exit with an error
+    #[default]
+    Fail,
+    /// Keep running, and re-scan the sources until images appear in them.
+    /// Image routes respond with 404 Not Found meanwhile
+    Wait,
+    /// Keep running like `wait`, serving a generated placeholder from `/random` and `/sequential` meanwhile
+    Placeholder,
+}
+
+impl FromStr for EmptyCachePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fail" => Ok(Self::Fail),
+            "wait" => Ok(Self::Wait),
+            "placeholder" => Ok(Self::Placeholder),
+            _ => Err(format!("Unknown empty cache policy: {s}")),
+        }
+    }
+}
+
+/// An aspect ratio, written as `width:height`, e.g. `16:9`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct AspectRatio {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AspectRatio {
+    /// Compare the aspect ratio of an image with the given dimensions to this one
+    #[must_use]
+    pub fn compare(&self, width: u64, height: u64) -> std::cmp::Ordering {
+        (u128::from(width) * u128::from(self.height))
+            .cmp(&(u128::from(height) * u128::from(self.width)))
+    }
+}
+
+impl FromStr for AspectRatio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Aspect ratio must be written as width:height: {s}"))?;
+        let parse = |part: &str| {
+            part.trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|part| *part > 0)
+                .ok_or_else(|| format!("Invalid aspect ratio: {s}"))
+        };
+        Ok(Self {
+            width: parse(width)?,
+            height: parse(height)?,
+        })
+    }
+}
+
+impl TryFrom<String> for AspectRatio {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<AspectRatio> for String {
+    fn from(ratio: AspectRatio) -> Self {
+        format!("{}:{}", ratio.width, ratio.height)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageSource {
+    Url(Url),
+    Path(PathBuf),
+    /// A remote file listing the URLs of images, written as `manifest:<url>`
+    Manifest(Url),
+    /// An RSS or Atom feed linking to images, written as `feed:<url>`
+    Feed(Url),
+}
+
+/// The prefix of manifest sources written as strings
+const MANIFEST_PREFIX: &str = "manifest:";
+/// The prefix of feed sources written as strings
+const FEED_PREFIX: &str = "feed:";
+
+/// A configured image source, along with its per-source settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceConfig {
+    /// Where the images are loaded from
+    pub location: ImageSource,
+    /// How likely each image of this source is to be chosen at random,
+    /// relative to images of sources with a weight of 1
+    pub weight: u32,
+    /// Tags describing the images of this source
+    pub tags: Vec<String>,
+    /// How often the source is reloaded, in seconds
+    pub refresh_interval: Option<u64>,
+    /// Whether subdirectories of directory sources are searched for images
+    pub recursive: bool,
+    /// How many levels of subdirectories of directory sources are searched, unlimited if unset
+    pub max_depth: Option<usize>,
+    /// Whether symlinks in directory sources are followed, rather than skipped
+    pub follow_symlinks: bool,
+    /// The maximum number of images loaded from this source
+    pub max_images: Option<usize>,
+    /// The minimum width in pixels of the images loaded from this source
+    pub min_width: Option<u32>,
+    /// The minimum height in pixels of the images loaded from this source
+    pub min_height: Option<u32>,
+    /// The minimum aspect ratio of the images loaded from this source, e.g. `1:1` for landscape images
+    pub min_aspect_ratio: Option<AspectRatio>,
+    /// The maximum aspect ratio of the images loaded from this source, e.g. `1:1` for portrait images
+    pub max_aspect_ratio: Option<AspectRatio>,
+    /// HTTP headers sent when fetching images from URL sources
+    pub headers: BTreeMap<String, String>,
+    /// Credentials for HTTP basic authentication, sent when fetching images from URL sources
+    pub basic_auth: Option<BasicAuth>,
+    /// A bearer token, sent when fetching images from URL sources
+    pub bearer_token: Option<String>,
+    /// A caption drawn onto the images of this source when they're served, unless a request sets its own
+    pub caption: Option<String>,
+}
+
+/// Credentials for HTTP basic authentication
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BasicAuth {
+    pub username: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+impl From<ImageSource> for SourceConfig {
+    fn from(location: ImageSource) -> Self {
+        Self {
+            location,
+            weight: 1,
+            tags: vec![],
+            refresh_interval: None,
+            recursive: true,
+            max_depth: None,
+            follow_symlinks: false,
+            max_images: None,
+            min_width: None,
+            min_height: None,
+            min_aspect_ratio: None,
+            max_aspect_ratio: None,
+            headers: BTreeMap::new(),
+            basic_auth: None,
+            bearer_token: None,
+            caption: None,
+        }
+    }
+}
+
+impl SourceConfig {
+    /// How many levels of subdirectories of a directory source are searched, unlimited if `None`
+    #[must_use]
+    pub const fn search_depth(&self) -> Option<usize> {
+        if self.recursive {
+            self.max_depth
+        } else {
+            Some(0)
+        }
+    }
+
+    /// Whether images of this source are filtered by their dimensions
+    #[must_use]
+    pub const fn has_dimension_limits(&self) -> bool {
+        self.min_width.is_some()
+            || self.min_height.is_some()
+            || self.min_aspect_ratio.is_some()
+            || self.max_aspect_ratio.is_some()
+    }
+
+    /// Whether an image with the given dimensions in pixels is within this source's dimension limits
+    #[must_use]
+    pub fn allows_dimensions(&self, width: u64, height: u64) -> bool {
+        self.min_width.is_none_or(|min| width >= u64::from(min))
+            && self.min_height.is_none_or(|min| height >= u64::from(min))
+            && self
+                .min_aspect_ratio
+                .is_none_or(|min| min.compare(width, height).is_ge())
+            && self
+                .max_aspect_ratio
+                .is_none_or(|max| max.compare(width, height).is_le())
+    }
+
+    /// The HTTP headers sent when fetching from this source, including the `Authorization`
+    /// header for its basic auth credentials or bearer token
+    #[must_use]
+    pub fn request_headers(&self) -> BTreeMap<String, String> {
+        use base64::Engine;
+
+        let mut headers = self.headers.clone();
+        if let Some(BasicAuth { username, password }) = &self.basic_auth {
+            let credentials = format!("{username}:{}", password.as_deref().unwrap_or_default());
+            let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+            headers.insert("Authorization".to_string(), format!("Basic {encoded}"));
+        } else if let Some(token) = &self.bearer_token {
+            headers.insert("Authorization".to_string(), format!("Bearer {token}"));
+        }
+        headers
+    }
+
+    /// Override the credentials of this source, at the given position in `sources`, from
+    /// `RANDOM_IMAGE_SERVER_SOURCE_<N>_USERNAME`, `_PASSWORD`, and `_BEARER_TOKEN`
+    fn apply_env(&mut self, index: usize, env: &impl crate::env::EnvBackend) -> Result<()> {
+        let var = |name: &str| env.var(&format!("RANDOM_IMAGE_SERVER_SOURCE_{index}_{name}"));
+        if let Ok(username) = var("USERNAME") {
+            self.basic_auth = Some(BasicAuth {
+                username,
+                password: self.basic_auth.take().and_then(|auth| auth.password),
+            });
+        }
+        if let Ok(password) = var("PASSWORD") {
+            let auth = self.basic_auth.as_mut().ok_or_else(|| {
+                anyhow!(
+                    "Failed to parse environment variable 'SOURCE_{index}_PASSWORD': source has no username"
+                )
+            })?;
+            auth.password = Some(password);
+        }
+        if let Ok(token) = var("BEARER_TOKEN") {
+            self.bearer_token = Some(token);
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for SourceConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.location.fmt(f)
+    }
+}
+
+/// A source as written in a config file, either a plain string or a table
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[serde(untagged)]
+enum SourceEntry {
+    /// A path to an image file or directory, the URL of an image, or `manifest:` or `feed:` followed by the URL of a manifest or feed
+    Location(String),
+    Table(Box<SourceTable>),
+}
+
+/// A source with per-source settings, exactly one of `path`, `url`, `manifest`, or `feed` must be set
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct SourceTable {
+    /// A path to an image file or directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+    /// The URL of an image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<Url>,
+    /// The URL of a manifest, a text or JSON file listing the URLs of images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    manifest: Option<Url>,
+    /// The URL of an RSS or Atom feed linking to images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    feed: Option<Url>,
+    /// How likely each image of this source is to be chosen at random,
+    /// relative to images of sources with a weight of 1
+    #[serde(default = "default_weight")]
+    weight: u32,
+    /// Tags describing the images of this source
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// How often the source is reloaded, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refresh_interval: Option<u64>,
+    /// Whether subdirectories of directory sources are searched for images
+    #[serde(default = "default_recursive")]
+    recursive: bool,
+    /// How many levels of subdirectories of directory sources are searched, unlimited if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_depth: Option<usize>,
+    /// Whether symlinks in directory sources are followed, rather than skipped
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    follow_symlinks: bool,
+    /// The maximum number of images loaded from this source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_images: Option<usize>,
+    /// The minimum width in pixels of the images loaded from this source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_width: Option<u32>,
+    /// The minimum height in pixels of the images loaded from this source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_height: Option<u32>,
+    /// The minimum aspect ratio of the images loaded from this source, as `width:height`
+    #[schemars(with = "Option<String>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_aspect_ratio: Option<AspectRatio>,
+    /// The maximum aspect ratio of the images loaded from this source, as `width:height`
+    #[schemars(with = "Option<String>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_aspect_ratio: Option<AspectRatio>,
+    /// HTTP headers sent when fetching images from URL sources
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+    /// Credentials for HTTP basic authentication, sent when fetching images from URL sources
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    basic_auth: Option<BasicAuth>,
+    /// A bearer token, sent when fetching images from URL sources
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bearer_token: Option<String>,
+    /// A caption drawn onto the images of this source when they're served, unless a request sets its own.
+    /// Requires the `transforms` feature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    caption: Option<String>,
+}
+
+const fn default_weight() -> u32 {
+    1
+}
+const fn default_recursive() -> bool {
+    true
+}
+
+impl TryFrom<SourceEntry> for SourceConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: SourceEntry) -> Result<Self> {
+        let table = match entry {
+            SourceEntry::Location(location) => return Ok(ImageSource::from_str(&location)?.into()),
+            SourceEntry::Table(table) => *table,
+        };
+        let location = match (table.path, table.url, table.manifest, table.feed) {
+            (Some(path), None, None, None) if path.exists() => {
+                ImageSource::Path(path.canonicalize()?)
+            }
+            (Some(path), None, None, None) => {
+                return Err(anyhow!("Image source doesn't exist: {}", path.display()));
+            }
+            (None, Some(url), None, None) => ImageSource::Url(url),
+            (None, None, Some(manifest), None) => ImageSource::Manifest(manifest),
+            (None, None, None, Some(feed)) => ImageSource::Feed(feed),
+            _ => {
+                return Err(anyhow!(
+                    "Exactly one of `path`, `url`, `manifest`, or `feed` must be set"
+                ));
+            }
+        };
+        if table.basic_auth.is_some() && table.bearer_token.is_some() {
+            return Err(anyhow!(
+                "At most one of `basic_auth` or `bearer_token` can be set"
+            ));
+        }
+        Ok(Self {
+            location,
+            weight: table.weight,
+            tags: table.tags,
+            refresh_interval: table.refresh_interval,
+            recursive: table.recursive,
+            max_depth: table.max_depth,
+            follow_symlinks: table.follow_symlinks,
+            max_images: table.max_images,
+            min_width: table.min_width,
+            min_height: table.min_height,
+            min_aspect_ratio: table.min_aspect_ratio,
+            max_aspect_ratio: table.max_aspect_ratio,
+            headers: table.headers,
+            basic_auth: table.basic_auth,
+            bearer_token: table.bearer_token,
+            caption: table.caption,
+        })
+    }
+}
+
+impl From<&SourceConfig> for SourceEntry {
+    fn from(source: &SourceConfig) -> Self {
+        if *source == SourceConfig::from(source.location.clone()) {
+            return Self::Location(source.to_string());
+        }
+        let (path, url, manifest, feed) = match &source.location {
+            ImageSource::Path(path) => (Some(path.clone()), None, None, None),
+            ImageSource::Url(url) => (None, Some(url.clone()), None, None),
+            ImageSource::Manifest(manifest) => (None, None, Some(manifest.clone()), None),
+            ImageSource::Feed(feed) => (None, None, None, Some(feed.clone())),
+        };
+        Self::Table(Box::new(SourceTable {
+            path,
+            url,
+            manifest,
+            feed,
+            weight: source.weight,
+            tags: source.tags.clone(),
+            refresh_interval: source.refresh_interval,
+            recursive: source.recursive,
+            max_depth: source.max_depth,
+            follow_symlinks: source.follow_symlinks,
+            max_images: source.max_images,
+            min_width: source.min_width,
+            min_height: source.min_height,
+            min_aspect_ratio: source.min_aspect_ratio,
+            max_aspect_ratio: source.max_aspect_ratio,
+            headers: source.headers.clone(),
+            basic_auth: source.basic_auth.clone(),
+            bearer_token: source.bearer_token.clone(),
+            caption: source.caption.clone(),
+        }))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// The cache backend to use
+    pub backend: CacheBackendType,
+    /// Directory the filesystem backend stores its data in, persisted across restarts.
+    /// If unset, a temporary directory is used and removed on shutdown.
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+    /// The most bytes of images the in-memory backend may hold, unlimited if unset
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// What the in-memory backend does when storing an image would exceed `max_bytes`
+    #[serde(default)]
+    pub eviction: EvictionPolicy,
+    /// How many of the most recently served images the tiered backend keeps in memory
+    #[serde(default = "default_hot_images")]
+    pub hot_images: usize,
+    /// The database the sled backend stores images in.
+    /// If unset, `images.sled` in `directory` is used, or a temporary database if that is unset too.
+    #[serde(default)]
+    pub sled_path: Option<PathBuf>,
+    /// The hash used to check the integrity of cached files, also sent as the `ETag` of images and used as their id
+    #[serde(default)]
+    pub hash: HashAlgorithm,
+    /// How the filesystem, tiered, and sled backends compress the images they store
+    #[serde(default)]
+    pub compression: Compression,
+    /// Whether the filesystem and tiered backends memory-map the files they store to read them, so images are
+    /// served from the page cache rather than copied into memory. Disable it where memory maps are undesirable,
+    /// e.g. on network filesystems, whose files may change or vanish under the map
+    #[serde(default = "default_mmap")]
+    pub mmap: bool,
+    /// How often every file stored by the filesystem and tiered backends is re-verified against its hash,
+    /// e.g. `"1h"`. Corrupted images are removed, and loaded again from their source
+    #[schemars(with = "Option<String>")]
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        default
+    )]
+    pub revalidate_interval: Option<Duration>,
+    /// The most stored files re-verified per second, so that revalidation doesn't starve clients of disk bandwidth
+    #[serde(default = "default_revalidate_rate")]
+    pub revalidate_rate: u32,
+}
+
+const fn default_hot_images() -> usize {
+    crate::cache::TieredCache::DEFAULT_CAPACITY
+}
+
+const fn default_mmap() -> bool {
+    true
+}
+
+const fn default_revalidate_rate() -> u32 {
+    10
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            backend: CacheBackendType::default(),
+            directory: None,
+            max_bytes: None,
+            eviction: EvictionPolicy::default(),
+            hot_images: default_hot_images(),
+            sled_path: None,
+            hash: HashAlgorithm::default(),
+            compression: Compression::default(),
+            mmap: default_mmap(),
+            revalidate_interval: None,
+            revalidate_rate: default_revalidate_rate(),
+        }
+    }
+}
 
-/// Configuration structure for the server
-#[derive(Debug, Default, Deserialize, Clone, PartialEq, Eq)]
-pub struct Config {
-    pub server: ServerConfig,
-    #[serde(default)]
-    pub cache: CacheConfig,
+/// What the in-memory cache does when storing an image would exceed its memory budget
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// Refuse to store the image
+    #[default]
+    Reject,
+    /// Evict the images that were stored first until the image fits
+    EvictOldest,
 }
 
-#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
-pub struct ServerConfig {
-    #[serde(default = "default_port")]
-    pub port: u16,
-    #[serde(deserialize_with = "deserialize_host", default = "default_host")]
-    pub host: url::Host,
-    #[serde(
-        deserialize_with = "deserialize_log_level",
-        default = "default_log_level"
-    )]
-    pub log_level: Level,
-    #[serde(deserialize_with = "deserialize_sources")]
-    pub sources: Vec<ImageSource>,
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(Self::Reject),
+            "evict_oldest" => Ok(Self::EvictOldest),
+            _ => Err(format!("Unknown eviction policy: {s}")),
+        }
+    }
 }
 
-const fn default_port() -> u16 {
-    DEFAULT_PORT
+/// The hash used to check the integrity of cached files, and to generate `ETag`s
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    /// BLAKE3, a fast cryptographic hash
+    #[default]
+    Blake3,
+    /// XXH3, a faster non-cryptographic hash
+    Xxh3,
+    /// MD5, as used before the hash was configurable
+    Md5,
 }
-const fn default_host() -> url::Host {
-    DEFAULT_HOST
+
+impl HashAlgorithm {
+    /// Hash the given data, as lowercase hexadecimal digits
+    #[must_use]
+    pub fn digest(self, data: &[u8]) -> String {
+        match self {
+            Self::Blake3 => blake3::hash(data).to_hex().to_string(),
+            Self::Xxh3 => format!("{:032x}", xxhash_rust::xxh3::xxh3_128(data)),
+            Self::Md5 => format!("{:x}", md5::compute(data)),
+        }
+    }
 }
-const fn default_log_level() -> Level {
-    DEFAULT_LOG_LEVEL
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "blake3" => Ok(Self::Blake3),
+            "xxh3" => Ok(Self::Xxh3),
+            "md5" => Ok(Self::Md5),
+            _ => Err(format!("Unknown hash algorithm: {s}")),
+        }
+    }
 }
 
-fn deserialize_host<'de, D>(deserializer: D) -> Result<url::Host, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let s: String = Deserialize::deserialize(deserializer)?;
-    url::Host::parse(&s).map_err(serde::de::Error::custom)
+/// How cached images are compressed at rest
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// Store images as they are
+    #[default]
+    None,
+    /// Compress images with zstd, decompressing them when they are read
+    Zstd,
 }
 
-fn deserialize_log_level<'de, D>(deserializer: D) -> Result<Level, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let level: String = Deserialize::deserialize(deserializer)?;
-    Level::from_str(&level).map_err(serde::de::Error::custom)
+impl Compression {
+    /// The zstd compression level, favoring speed since images are compressed as they are loaded
+    const ZSTD_LEVEL: i32 = 3;
+
+    /// Compress the given data
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be compressed.
+    pub fn compress(self, data: &[u8]) -> std::io::Result<std::borrow::Cow<'_, [u8]>> {
+        match self {
+            Self::None => Ok(std::borrow::Cow::Borrowed(data)),
+            Self::Zstd => zstd::encode_all(data, Self::ZSTD_LEVEL).map(std::borrow::Cow::Owned),
+        }
+    }
+
+    /// Decompress data compressed by `compress`, copying it if it isn't compressed
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be decompressed.
+    pub fn decompress_slice(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Zstd => zstd::decode_all(data),
+        }
+    }
+
+    /// Decompress data compressed by `compress`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be decompressed.
+    pub fn decompress(self, data: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data),
+            Self::Zstd => zstd::decode_all(data.as_slice()),
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum ImageSource {
-    Url(Url),
-    Path(PathBuf),
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(format!("Unknown compression: {s}")),
+        }
+    }
 }
 
-#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
-pub struct CacheConfig {
-    pub backend: CacheBackendType,
+impl CacheConfig {
+    /// Whether the images the cache stores outlive the server, so it can be warmed by `prefetch` before serving
+    ///
+    /// Backends registered by a program embedding the server are assumed to be persistent.
+    #[must_use]
+    pub fn is_persistent(&self) -> bool {
+        match self.backend {
+            CacheBackendType::InMemory => false,
+            CacheBackendType::FileSystem | CacheBackendType::Tiered => self.directory.is_some(),
+            CacheBackendType::Sled => self.sled_path().is_some(),
+            CacheBackendType::Custom(_) => true,
+        }
+    }
+
+    /// The database the sled backend stores images in, if the cache is persistent
+    #[must_use]
+    pub fn sled_path(&self) -> Option<PathBuf> {
+        self.sled_path.clone().or_else(|| {
+            self.directory
+                .as_ref()
+                .map(|directory| directory.join("images.sled"))
+        })
+    }
+
+    /// The file per-image serve counters are persisted to, if the cache is persistent
+    #[must_use]
+    pub fn serve_counts_path(&self) -> Option<PathBuf> {
+        match (&self.backend, &self.directory) {
+            (
+                CacheBackendType::FileSystem | CacheBackendType::Tiered | CacheBackendType::Sled,
+                Some(directory),
+            ) => Some(directory.join("serve_counts.json")),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum CacheBackendType {
+    /// Keep images in memory
     #[default]
     InMemory,
+    /// Keep images on disk
     FileSystem,
+    /// Keep images on disk, and the most recently served ones in memory
+    Tiered,
+    /// Keep images in an embedded sled database. Requires the `sled` feature
+    Sled,
+    /// A backend registered under this name with `cache::register_backend`, by a program embedding the server
+    #[serde(untagged)]
+    Custom(String),
+}
+
+impl std::fmt::Display for ImageSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Url(url) => write!(f, "{url}"),
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Manifest(url) => write!(f, "{MANIFEST_PREFIX}{url}"),
+            Self::Feed(url) => write!(f, "{FEED_PREFIX}{url}"),
+        }
+    }
+}
+
+impl ImageSource {
+    /// Whether this source, as an item of a playlist, includes the image with the given key,
+    /// loaded from the given source: all of the images of the source it is, or of the directory it is the path of,
+    /// or the image it is the path or URL of
+    #[must_use]
+    pub fn includes(&self, key: &CacheKey, source: Option<&Self>) -> bool {
+        if source == Some(self) {
+            return true;
+        }
+        match (self, key) {
+            (Self::Path(path), CacheKey::ImagePath(image)) => image.starts_with(path),
+            (Self::Url(url), CacheKey::ImageUrl(image)) => url == image,
+            _ => false,
+        }
+    }
+
+    /// The format of the archive this source points to, if it is a zip or tar file
+    #[must_use]
+    pub fn archive_format(&self) -> Option<ArchiveFormat> {
+        match self {
+            Self::Url(url) => ArchiveFormat::from_name(url.path()),
+            Self::Path(path) => ArchiveFormat::from_name(&path.to_string_lossy()),
+            Self::Manifest(_) | Self::Feed(_) => None,
+        }
+    }
+
+    /// Whether images are fetched from this source over the network
+    #[must_use]
+    pub const fn is_remote(&self) -> bool {
+        !matches!(self, Self::Path(_))
+    }
 }
 
 impl FromStr for ImageSource {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if let Ok(url) = Url::parse(s) {
+        if let Some(manifest) = s.strip_prefix(MANIFEST_PREFIX) {
+            Ok(Self::Manifest(Url::parse(manifest)?))
+        } else if let Some(feed) = s.strip_prefix(FEED_PREFIX) {
+            Ok(Self::Feed(Url::parse(feed)?))
+        } else if let Ok(url) = Url::parse(s) {
             Ok(Self::Url(url))
         } else if PathBuf::from(s).exists() {
             Ok(Self::Path(PathBuf::from(s).canonicalize()?))
@@ -104,22 +1773,31 @@ impl FromStr for CacheBackendType {
         match s.to_lowercase().as_str() {
             "in_memory" => Ok(Self::InMemory),
             "file_system" => Ok(Self::FileSystem),
-            _ => Err(format!("Unknown cache backend type: {s}")),
+            "tiered" => Ok(Self::Tiered),
+            "sled" => Ok(Self::Sled),
+            _ => Ok(Self::Custom(s.to_string())),
         }
     }
 }
 
-fn deserialize_sources<'de, D>(deserializer: D) -> Result<Vec<ImageSource>, D::Error>
+fn serialize_sources<S>(sources: &[SourceConfig], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_seq(sources.iter().map(SourceEntry::from))
+}
+
+fn deserialize_sources<'de, D>(deserializer: D) -> Result<Vec<SourceConfig>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
-    let sources: Vec<String> = Deserialize::deserialize(deserializer)?;
+    let sources: Vec<SourceEntry> = Deserialize::deserialize(deserializer)?;
     let mut image_sources = Vec::new();
 
     for source in sources {
-        match ImageSource::from_str(&source) {
+        match SourceConfig::try_from(source) {
             Ok(image_source) => image_sources.push(image_source),
-            Err(e) => tracing::warn!("Invalid image source '{source}': {e}"),
+            Err(e) => tracing::warn!("Invalid image source: {e}"),
         }
     }
 
@@ -136,23 +1814,288 @@ impl Default for ServerConfig {
             port: DEFAULT_PORT,
             host: DEFAULT_HOST,
             log_level: DEFAULT_LOG_LEVEL,
+            log_file: None,
+            log_rotation: LogRotation::default(),
+            log_max_size: DEFAULT_LOG_MAX_SIZE,
             sources: vec![],
+            base_path: String::new(),
+            public_url: None,
+            trusted_proxies: vec![],
+            allowed_referers: vec![],
+            hotlink_placeholder: None,
+            access: AccessConfig::default(),
+            auth: AuthConfig::default(),
+            limits: LimitsConfig::default(),
+            env_file: None,
+            include: vec![],
+            exclude: vec![],
+            allowed_extensions: default_allowed_extensions(),
+            min_file_size: None,
+            max_file_size: None,
+            dedup_threshold: None,
+            auto_orient: false,
+            rescan_interval: None,
+            offline: false,
+            start_before_populate: false,
+            on_empty: EmptyCachePolicy::default(),
+            swagger_ui: false,
+            expose_sources: false,
+            no_repeat_window: None,
+            sticky: None,
+            favicon: None,
+            static_dir: None,
+            ready_file: None,
+        }
+    }
+}
+
+/// Set a field from the `RANDOM_IMAGE_SERVER_`-prefixed environment variable, if it is set
+macro_rules! set_from_env {
+    ($env:expr, $field:expr, $var:literal, $parse_fn:expr) => {
+        if let Ok(value) = $env.var(concat!("RANDOM_IMAGE_SERVER_", $var)) {
+            $field = $parse_fn(&value)
+                .map_err(|e| anyhow!("Failed to parse environment variable '{}': {}", $var, e))?
+        }
+    };
+}
+
+impl ServerConfig {
+    /// The filter deciding which files of directory sources are loaded
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the include or exclude patterns is not a valid glob.
+    pub fn file_filter(&self) -> Result<FileFilter> {
+        FileFilter::new(&self.include, &self.exclude)
+    }
+
+    /// Check that, in offline mode, at least one source can be loaded without the network
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if offline mode is enabled and every configured source is remote.
+    pub fn check_offline(&self) -> Result<()> {
+        if self.offline
+            && !self.sources.is_empty()
+            && self
+                .sources
+                .iter()
+                .all(|source| source.location.is_remote())
+        {
+            return Err(anyhow!(
+                "Offline mode is enabled, but only remote sources are configured"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Update the server settings from the variables of the given environment backend
+    fn apply_env(&mut self, env: &impl crate::env::EnvBackend) -> Result<()> {
+        set_from_env!(env, self.port, "PORT", u16::from_str);
+        set_from_env!(env, self.host, "HOST", url::Host::parse);
+        set_from_env!(env, self.log_level, "LOG_LEVEL", Level::from_str);
+        set_from_env!(env, self.log_file, "LOG_FILE", |s: &str| {
+            Ok::<_, std::convert::Infallible>(Some(PathBuf::from(s)))
+        });
+        set_from_env!(
+            env,
+            self.log_rotation,
+            "LOG_ROTATION",
+            LogRotation::from_str
+        );
+        set_from_env!(env, self.log_max_size, "LOG_MAX_SIZE", u64::from_str);
+        set_from_env!(env, self.sources, "SOURCES", |s: &str| {
+            s.split(',')
+                .map(|source| ImageSource::from_str(source).map(SourceConfig::from))
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(|sources| {
+                    if sources.is_empty() {
+                        Err(anyhow!("No valid image sources found"))
+                    } else {
+                        Ok(sources)
+                    }
+                })
+        });
+        set_from_env!(env, self.base_path, "BASE_PATH", normalize_base_path);
+        set_from_env!(env, self.public_url, "PUBLIC_URL", |s: &str| Url::parse(s)
+            .map(Some));
+        set_from_env!(env, self.trusted_proxies, "TRUSTED_PROXIES", parse_cidrs);
+        set_from_env!(env, self.allowed_referers, "ALLOWED_REFERERS", |s: &str| {
+            Ok::<_, std::convert::Infallible>(
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|domain| !domain.is_empty())
+                    .map(ToString::to_string)
+                    .collect(),
+            )
+        });
+        if let Ok(username) = env.var("RANDOM_IMAGE_SERVER_AUTH_USERNAME") {
+            self.auth.basic.get_or_insert_default().username = username;
+        }
+        if let Ok(password_hash) = env.var("RANDOM_IMAGE_SERVER_AUTH_PASSWORD_HASH") {
+            let auth = self.auth.basic.as_mut().ok_or_else(|| {
+                anyhow!(
+                    "Failed to parse environment variable 'AUTH_PASSWORD_HASH': basic authentication has no username"
+                )
+            })?;
+            auth.password_hash = password_hash;
+        }
+        set_from_env!(env, self.access.allow, "ACCESS_ALLOW", parse_cidrs);
+        set_from_env!(env, self.access.deny, "ACCESS_DENY", parse_cidrs);
+        set_from_env!(
+            env,
+            self.limits.max_uri_length,
+            "LIMITS_MAX_URI_LENGTH",
+            usize::from_str
+        );
+        set_from_env!(
+            env,
+            self.limits.max_header_size,
+            "LIMITS_MAX_HEADER_SIZE",
+            parse_max_header_size
+        );
+        set_from_env!(
+            env,
+            self.limits.max_headers,
+            "LIMITS_MAX_HEADERS",
+            usize::from_str
+        );
+        set_from_env!(
+            env,
+            self.hotlink_placeholder,
+            "HOTLINK_PLACEHOLDER",
+            |s: &str| Ok::<_, std::convert::Infallible>(Some(PathBuf::from(s)))
+        );
+        set_from_env!(env, self.include, "INCLUDE", |s: &str| {
+            parse_globs(
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(ToString::to_string),
+            )
+        });
+        set_from_env!(env, self.exclude, "EXCLUDE", |s: &str| {
+            parse_globs(
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(ToString::to_string),
+            )
+        });
+        set_from_env!(
+            env,
+            self.allowed_extensions,
+            "ALLOWED_EXTENSIONS",
+            |s: &str| parse_extensions(s.split(',').filter(|ext| !ext.trim().is_empty()))
+        );
+        set_from_env!(env, self.min_file_size, "MIN_FILE_SIZE", |s: &str| {
+            u64::from_str(s).map(Some)
+        });
+        set_from_env!(env, self.max_file_size, "MAX_FILE_SIZE", |s: &str| {
+            u64::from_str(s).map(Some)
+        });
+        set_from_env!(env, self.dedup_threshold, "DEDUP_THRESHOLD", |s: &str| {
+            u32::from_str(s).map(Some)
+        });
+        set_from_env!(env, self.rescan_interval, "RESCAN_INTERVAL", |s: &str| {
+            parse_duration(s).map(Some)
+        });
+        set_from_env!(env, self.auto_orient, "AUTO_ORIENT", bool::from_str);
+        set_from_env!(env, self.offline, "OFFLINE", bool::from_str);
+        set_from_env!(
+            env,
+            self.start_before_populate,
+            "START_BEFORE_POPULATE",
+            bool::from_str
+        );
+        set_from_env!(env, self.on_empty, "ON_EMPTY", EmptyCachePolicy::from_str);
+        set_from_env!(env, self.swagger_ui, "SWAGGER_UI", bool::from_str);
+        set_from_env!(env, self.expose_sources, "EXPOSE_SOURCES", bool::from_str);
+        set_from_env!(env, self.no_repeat_window, "NO_REPEAT_WINDOW", |s: &str| {
+            usize::from_str(s).map(Some)
+        });
+        set_from_env!(env, self.sticky, "STICKY", |s: &str| parse_duration(s)
+            .map(Some));
+        set_from_env!(env, self.favicon, "FAVICON", |s: &str| {
+            Ok::<_, std::convert::Infallible>(Some(PathBuf::from(s)))
+        });
+        set_from_env!(env, self.static_dir, "STATIC_DIR", |s: &str| {
+            Ok::<_, std::convert::Infallible>(Some(PathBuf::from(s)))
+        });
+        set_from_env!(env, self.ready_file, "READY_FILE", |s: &str| {
+            Ok::<_, std::convert::Infallible>(Some(PathBuf::from(s)))
+        });
+        for (index, source) in self.sources.iter_mut().enumerate() {
+            source.apply_env(index, env)?;
+        }
+        Ok(())
+    }
+
+    /// Whether an image of the given size in bytes is within `min_file_size` and `max_file_size`
+    #[must_use]
+    pub fn allows_file_size(&self, size: u64) -> bool {
+        self.min_file_size.is_none_or(|min| size >= min)
+            && self.max_file_size.is_none_or(|max| size <= max)
+    }
+}
+
+/// The formats configuration files can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Determine the format of a configuration file from its extension
+    ///
+    /// Returns `None` if the extension is not `.toml`, `.yaml`, `.yml`, or `.json`.
+    #[must_use]
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "json" => Some(Self::Json),
+            _ => None,
         }
     }
 }
 
 impl Config {
-    /// Load configuration from a TOML file
+    /// Load configuration from a TOML, YAML, or JSON file, based on its extension
     ///
     /// # Errors
     ///
-    /// Returns an error if the file cannot be read or parsed.
+    /// Returns an error if the file cannot be read or parsed, or has an unsupported extension.
     pub fn from_file(path: &str) -> Result<Self> {
+        let format = ConfigFormat::from_path(Path::new(path))
+            .ok_or_else(|| anyhow!("Unsupported config file extension: {path}"))?;
         let content = std::fs::read_to_string(path)?;
-        let config: Self = toml::from_str(&content)?;
+        Self::parse(&content, format)
+    }
+
+    /// Parse configuration in the given format
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the content cannot be parsed.
+    pub fn parse(content: &str, format: ConfigFormat) -> Result<Self> {
+        let config: Self = match format {
+            ConfigFormat::Toml => toml::from_str(content)?,
+            ConfigFormat::Yaml => serde_yaml::from_str(content)?,
+            ConfigFormat::Json => serde_json::from_str(content)?,
+        };
         Ok(config)
     }
 
+    /// The JSON Schema of configuration files
+    #[must_use]
+    pub fn json_schema() -> serde_json::Value {
+        schemars::schema_for!(Self).to_value()
+    }
+
     /// Create a new configuration, with it's values updated from environment variables
     ///
     /// This function reads environment variables prefixed with `RANDOM_IMAGE_SERVER_`
@@ -160,8 +2103,65 @@ impl Config {
     /// - `RANDOM_IMAGE_SERVER_PORT`: The port for the server
     /// - `RANDOM_IMAGE_SERVER_HOST`: The host for the server
     /// - `RANDOM_IMAGE_SERVER_LOG_LEVEL`: The log level for the server
+    /// - `RANDOM_IMAGE_SERVER_LOG_FILE`: The file to write logs to
+    /// - `RANDOM_IMAGE_SERVER_LOG_ROTATION`: When to rotate the log file, either `daily`, `hourly`, or `size`
+    /// - `RANDOM_IMAGE_SERVER_LOG_MAX_SIZE`: The size in bytes at which the log file is rotated
     /// - `RANDOM_IMAGE_SERVER_SOURCES`: A comma-separated list of image sources (URLs or paths)
-    /// - `RANDOM_IMAGE_SERVER_CACHE_BACKEND`: The cache backend type, either `in_memory` or `file_system`
+    /// - `RANDOM_IMAGE_SERVER_BASE_PATH`: The prefix that all routes are mounted under
+    /// - `RANDOM_IMAGE_SERVER_PUBLIC_URL`: The externally visible URL of the server
+    /// - `RANDOM_IMAGE_SERVER_TRUSTED_PROXIES`: A comma-separated list of the address ranges of trusted proxies
+    /// - `RANDOM_IMAGE_SERVER_ALLOWED_REFERERS`: A comma-separated list of the domains images may be embedded on
+    /// - `RANDOM_IMAGE_SERVER_HOTLINK_PLACEHOLDER`: An image served to requests from other domains
+    /// - `RANDOM_IMAGE_SERVER_ACCESS_ALLOW`: A comma-separated list of the address ranges that may access the server
+    /// - `RANDOM_IMAGE_SERVER_ACCESS_DENY`: A comma-separated list of the address ranges that may not access the server
+    /// - `RANDOM_IMAGE_SERVER_AUTH_USERNAME`: The username of HTTP basic authentication
+    /// - `RANDOM_IMAGE_SERVER_AUTH_PASSWORD_HASH`: The bcrypt or argon2 hash of the password of HTTP basic authentication
+    /// - `RANDOM_IMAGE_SERVER_LIMITS_MAX_URI_LENGTH`: The longest path and query of a request, in bytes
+    /// - `RANDOM_IMAGE_SERVER_LIMITS_MAX_HEADER_SIZE`: The largest request line and headers of a request, in bytes
+    /// - `RANDOM_IMAGE_SERVER_LIMITS_MAX_HEADERS`: The most headers a request may have
+    /// - `RANDOM_IMAGE_SERVER_START_BEFORE_POPULATE`: Whether requests are accepted while the cache is populated
+    /// - `RANDOM_IMAGE_SERVER_ON_EMPTY`: What the server does if no images are found, one of `fail`, `wait`, or `placeholder`
+    /// - `RANDOM_IMAGE_SERVER_SWAGGER_UI`: Whether a Swagger UI page rendering `/openapi.json` is served at `/docs`
+    /// - `RANDOM_IMAGE_SERVER_EXPOSE_SOURCES`: Whether the path or URL of served images is sent in the `X-Image-Source` header
+    /// - `RANDOM_IMAGE_SERVER_NO_REPEAT_WINDOW`: How many of the most recently served images `/random` avoids
+    /// - `RANDOM_IMAGE_SERVER_STICKY`: How long the image `/random` chooses for a client is pinned to it, e.g. `5m`
+    /// - `RANDOM_IMAGE_SERVER_FAVICON`: An icon served at `/favicon.ico` instead of the built-in one
+    /// - `RANDOM_IMAGE_SERVER_STATIC_DIR`: A directory of static files served under `/static/`
+    /// - `RANDOM_IMAGE_SERVER_READY_FILE`: A file created once the server is ready, and removed when it shuts down
+    /// - `RANDOM_IMAGE_SERVER_CACHE_BACKEND`: The cache backend type, one of `in_memory`, `file_system`, `tiered`, `sled`, or the name of a registered backend
+    /// - `RANDOM_IMAGE_SERVER_CACHE_DIRECTORY`: The directory the filesystem backend stores its data in
+    /// - `RANDOM_IMAGE_SERVER_CACHE_MAX_BYTES`: The most bytes of images the in-memory backend may hold
+    /// - `RANDOM_IMAGE_SERVER_CACHE_EVICTION`: What happens when `max_bytes` is exceeded, either `reject` or `evict_oldest`
+    /// - `RANDOM_IMAGE_SERVER_CACHE_HOT_IMAGES`: How many recently served images the tiered backend keeps in memory
+    /// - `RANDOM_IMAGE_SERVER_CACHE_SLED_PATH`: The database the sled backend stores images in
+    /// - `RANDOM_IMAGE_SERVER_CACHE_HASH`: The hash of cached files and `ETag`s, one of `blake3`, `xxh3`, or `md5`
+    /// - `RANDOM_IMAGE_SERVER_CACHE_COMPRESSION`: How cached images are compressed at rest, either `none` or `zstd`
+    /// - `RANDOM_IMAGE_SERVER_CACHE_MMAP`: Whether files stored by the filesystem and tiered backends are memory-mapped
+    /// - `RANDOM_IMAGE_SERVER_CACHE_REVALIDATE_INTERVAL`: How often stored files are re-verified against their hashes, e.g. `1h`
+    /// - `RANDOM_IMAGE_SERVER_CACHE_REVALIDATE_RATE`: The most stored files re-verified per second
+    /// - `RANDOM_IMAGE_SERVER_SENTRY_DSN`: The Sentry DSN to report errors to
+    /// - `RANDOM_IMAGE_SERVER_SENTRY_ENVIRONMENT`: The environment reported to Sentry
+    /// - `RANDOM_IMAGE_SERVER_STATSD_HOST`: The host of a statsd agent to push metrics to
+    /// - `RANDOM_IMAGE_SERVER_STATSD_PORT`: The port of the statsd agent
+    /// - `RANDOM_IMAGE_SERVER_STATSD_PREFIX`: The prefix of the names of metrics pushed to statsd
+    /// - `RANDOM_IMAGE_SERVER_STATSD_FLUSH_INTERVAL`: How often metrics are pushed to statsd, in seconds
+    /// - `RANDOM_IMAGE_SERVER_SIGNING_SECRET`: The secret links to images are signed with
+    /// - `RANDOM_IMAGE_SERVER_SIGNING_REQUIRED`: Whether images are only served through signed links
+    /// - `RANDOM_IMAGE_SERVER_TLS_CERT`: A PEM file of the certificate chain HTTPS is served with
+    /// - `RANDOM_IMAGE_SERVER_TLS_KEY`: A PEM file of the private key of the certificate
+    /// - `RANDOM_IMAGE_SERVER_TLS_HTTP3`: Whether HTTP/3 is also served, over QUIC
+    /// - `RANDOM_IMAGE_SERVER_TLS_CLIENT_CA`: A PEM file of the certificate authorities client certificates must be signed by
+    /// - `RANDOM_IMAGE_SERVER_TLS_ACME_DOMAINS`: A comma-separated list of the domains a certificate is obtained for with ACME
+    /// - `RANDOM_IMAGE_SERVER_TLS_ACME_EMAIL`: The contact address of the ACME account
+    /// - `RANDOM_IMAGE_SERVER_TLS_ACME_CACHE_DIR`: The directory the ACME account key and certificate are stored in
+    /// - `RANDOM_IMAGE_SERVER_TLS_ACME_DIRECTORY`: The directory URL of the ACME certificate authority
+    /// - `RANDOM_IMAGE_SERVER_WEBHOOK_URL`: A URL that lifecycle events are POSTed to as JSON
+    /// - `RANDOM_IMAGE_SERVER_PROXY_ALLOWED_DOMAINS`: A comma-separated list of the domains images may be proxied from
+    /// - `RANDOM_IMAGE_SERVER_PLACEHOLDER_ENABLED`: Whether placeholder images are generated at `/placeholder/{width}x{height}`
+    /// - `RANDOM_IMAGE_SERVER_PLACEHOLDER_MAX_WIDTH`: The widest placeholder generated, in pixels
+    /// - `RANDOM_IMAGE_SERVER_PLACEHOLDER_MAX_HEIGHT`: The tallest placeholder generated, in pixels
+    /// - `RANDOM_IMAGE_SERVER_TRANSFORMS_QUALITY`: The quality JPEG images are re-encoded at, from 1 to 100
+    /// - `RANDOM_IMAGE_SERVER_TRANSFORMS_FORMATS`: A comma-separated list of the formats JPEG and PNG images are pre-encoded in
     ///
     /// # Errors
     ///
@@ -173,45 +2173,265 @@ impl Config {
     /// Create a new configuration, with it's values updated from environment variables.
     ///
     /// Same as `with_env`, but allows passing a custom environment backend (e.g., for testing).
+    /// If `server.env_file` is configured, variables missing from the environment are read from it.
     ///
     /// # Errors
     ///
-    /// Returns an error if any environment variable is invalid or cannot be parsed.
-    pub fn with_env_backend(mut self, env: &impl crate::env::EnvBackend) -> Result<Self> {
-        macro_rules! set_from_env {
-            ($field:expr, $var:literal,  $parse_fn:expr) => {
-                if let Ok(value) = env.var(concat!("RANDOM_IMAGE_SERVER_", $var)) {
-                    $field = $parse_fn(&value).map_err(|e| {
-                        anyhow!("Failed to parse environment variable '{}': {}", $var, e)
-                    })?
-                }
-            };
-        }
-
-        set_from_env!(self.server.port, "PORT", u16::from_str);
-        set_from_env!(self.server.host, "HOST", url::Host::parse);
-        set_from_env!(self.server.log_level, "LOG_LEVEL", Level::from_str);
-        set_from_env!(self.server.sources, "SOURCES", |s: &str| {
-            s.split(',')
-                .map(ImageSource::from_str)
-                .collect::<Result<Vec<_>, _>>()
-                .and_then(|sources| {
-                    if sources.is_empty() {
-                        Err(anyhow!("No valid image sources found"))
-                    } else {
-                        Ok(sources)
-                    }
-                })
-        });
+    /// Returns an error if any environment variable is invalid or cannot be parsed,
+    /// or the configured env file cannot be loaded.
+    pub fn with_env_backend(self, env: &impl crate::env::EnvBackend) -> Result<Self> {
+        match self.server.env_file.clone() {
+            Some(env_file) => {
+                let env = crate::env::DotEnvBackend::from_file(env, &env_file)?;
+                self.apply_env(&env)
+            }
+            None => self.apply_env(env),
+        }
+    }
+
+    /// Update the configuration from the variables of the given environment backend
+    fn apply_env(mut self, env: &impl crate::env::EnvBackend) -> Result<Self> {
+        self.server.apply_env(env)?;
         set_from_env!(
+            env,
             self.cache.backend,
             "CACHE_BACKEND",
             CacheBackendType::from_str
         );
+        set_from_env!(env, self.cache.directory, "CACHE_DIRECTORY", |s: &str| {
+            Ok::<_, std::convert::Infallible>(Some(PathBuf::from(s)))
+        });
+        set_from_env!(env, self.cache.max_bytes, "CACHE_MAX_BYTES", |s: &str| {
+            u64::from_str(s).map(Some)
+        });
+        set_from_env!(
+            env,
+            self.cache.eviction,
+            "CACHE_EVICTION",
+            EvictionPolicy::from_str
+        );
+        set_from_env!(
+            env,
+            self.cache.hot_images,
+            "CACHE_HOT_IMAGES",
+            usize::from_str
+        );
+        set_from_env!(env, self.cache.sled_path, "CACHE_SLED_PATH", |s: &str| {
+            Ok::<_, std::convert::Infallible>(Some(PathBuf::from(s)))
+        });
+        set_from_env!(env, self.cache.hash, "CACHE_HASH", HashAlgorithm::from_str);
+        set_from_env!(
+            env,
+            self.cache.compression,
+            "CACHE_COMPRESSION",
+            Compression::from_str
+        );
+        set_from_env!(env, self.cache.mmap, "CACHE_MMAP", bool::from_str);
+        set_from_env!(
+            env,
+            self.cache.revalidate_interval,
+            "CACHE_REVALIDATE_INTERVAL",
+            |s: &str| parse_duration(s).map(Some)
+        );
+        set_from_env!(
+            env,
+            self.cache.revalidate_rate,
+            "CACHE_REVALIDATE_RATE",
+            u32::from_str
+        );
+        set_from_env!(
+            env,
+            self.observability.sentry_dsn,
+            "SENTRY_DSN",
+            |s: &str| { Ok::<_, std::convert::Infallible>(Some(s.to_string())) }
+        );
+        set_from_env!(
+            env,
+            self.observability.sentry_environment,
+            "SENTRY_ENVIRONMENT",
+            |s: &str| Ok::<_, std::convert::Infallible>(Some(s.to_string()))
+        );
+        set_from_env!(env, self.metrics.statsd_host, "STATSD_HOST", |s: &str| {
+            Ok::<_, std::convert::Infallible>(Some(s.to_string()))
+        });
+        set_from_env!(env, self.metrics.statsd_port, "STATSD_PORT", u16::from_str);
+        set_from_env!(
+            env,
+            self.metrics.statsd_prefix,
+            "STATSD_PREFIX",
+            |s: &str| { Ok::<_, std::convert::Infallible>(s.to_string()) }
+        );
+        set_from_env!(
+            env,
+            self.metrics.statsd_flush_interval,
+            "STATSD_FLUSH_INTERVAL",
+            u64::from_str
+        );
+        set_from_env!(env, self.http.proxy, "HTTP_PROXY", |s: &str| Url::parse(s)
+            .map(Some));
+        set_from_env!(env, self.http.timeout, "HTTP_TIMEOUT", u64::from_str);
+        set_from_env!(
+            env,
+            self.http.max_redirects,
+            "HTTP_MAX_REDIRECTS",
+            usize::from_str
+        );
+        set_from_env!(env, self.http.user_agent, "HTTP_USER_AGENT", |s: &str| {
+            Ok::<_, std::convert::Infallible>(s.to_string())
+        });
+        set_from_env!(env, self.http.ca_bundle, "HTTP_CA_BUNDLE", |s: &str| {
+            Ok::<_, std::convert::Infallible>(Some(PathBuf::from(s)))
+        });
+        set_from_env!(env, self.http.tls_verify, "HTTP_TLS_VERIFY", bool::from_str);
+        set_from_env!(
+            env,
+            self.http.failure_threshold,
+            "HTTP_FAILURE_THRESHOLD",
+            u32::from_str
+        );
+        set_from_env!(
+            env,
+            self.http.max_backoff,
+            "HTTP_MAX_BACKOFF",
+            u64::from_str
+        );
+        set_from_env!(
+            env,
+            self.http.max_archive_size,
+            "HTTP_MAX_ARCHIVE_SIZE",
+            u64::from_str
+        );
+        set_from_env!(env, self.signing.secret, "SIGNING_SECRET", |s: &str| {
+            Ok::<_, std::convert::Infallible>(Some(s.to_string()))
+        });
+        set_from_env!(
+            env,
+            self.signing.required,
+            "SIGNING_REQUIRED",
+            bool::from_str
+        );
+        set_from_env!(env, self.tls.cert, "TLS_CERT", |s: &str| {
+            Ok::<_, std::convert::Infallible>(Some(PathBuf::from(s)))
+        });
+        set_from_env!(env, self.tls.key, "TLS_KEY", |s: &str| {
+            Ok::<_, std::convert::Infallible>(Some(PathBuf::from(s)))
+        });
+        set_from_env!(env, self.tls.http3, "TLS_HTTP3", bool::from_str);
+        set_from_env!(env, self.tls.client_ca, "TLS_CLIENT_CA", |s: &str| {
+            Ok::<_, std::convert::Infallible>(Some(PathBuf::from(s)))
+        });
+        if let Ok(domains) = env.var("RANDOM_IMAGE_SERVER_TLS_ACME_DOMAINS") {
+            self.tls.acme.get_or_insert_default().domains = domains
+                .split(',')
+                .map(str::trim)
+                .filter(|domain| !domain.is_empty())
+                .map(ToString::to_string)
+                .collect();
+        }
+        if let Ok(email) = env.var("RANDOM_IMAGE_SERVER_TLS_ACME_EMAIL") {
+            self.tls.acme.get_or_insert_default().email = Some(email);
+        }
+        if let Ok(cache_dir) = env.var("RANDOM_IMAGE_SERVER_TLS_ACME_CACHE_DIR") {
+            self.tls.acme.get_or_insert_default().cache_dir = PathBuf::from(cache_dir);
+        }
+        if let Ok(directory) = env.var("RANDOM_IMAGE_SERVER_TLS_ACME_DIRECTORY") {
+            self.tls.acme.get_or_insert_default().directory =
+                Url::parse(&directory).map_err(|e| {
+                    anyhow!("Failed to parse environment variable 'TLS_ACME_DIRECTORY': {e}")
+                })?;
+        }
+        set_from_env!(
+            env,
+            self.notifications.webhook_url,
+            "WEBHOOK_URL",
+            |s: &str| Url::parse(s).map(Some)
+        );
+        set_from_env!(
+            env,
+            self.proxy.allowed_domains,
+            "PROXY_ALLOWED_DOMAINS",
+            |s: &str| {
+                Ok::<_, std::convert::Infallible>(
+                    s.split(',')
+                        .map(str::trim)
+                        .filter(|domain| !domain.is_empty())
+                        .map(ToString::to_string)
+                        .collect(),
+                )
+            }
+        );
+        set_from_env!(
+            env,
+            self.placeholder.enabled,
+            "PLACEHOLDER_ENABLED",
+            bool::from_str
+        );
+        set_from_env!(
+            env,
+            self.placeholder.max_width,
+            "PLACEHOLDER_MAX_WIDTH",
+            u32::from_str
+        );
+        set_from_env!(
+            env,
+            self.placeholder.max_height,
+            "PLACEHOLDER_MAX_HEIGHT",
+            u32::from_str
+        );
+        set_from_env!(env, self.transforms.quality, "TRANSFORMS_QUALITY", |s| {
+            parse_quality(s).map(Some)
+        });
+        set_from_env!(
+            env,
+            self.transforms.formats,
+            "TRANSFORMS_FORMATS",
+            |s: &str| {
+                s.split(',')
+                    .filter(|format| !format.trim().is_empty())
+                    .map(VariantFormat::from_str)
+                    .collect::<Result<Vec<_>, _>>()
+            }
+        );
 
         Ok(self)
     }
 
+    /// The configuration a collection is served with, if it is configured
+    ///
+    /// It is this configuration with the sources and cache settings of the collection,
+    /// mounted under `/{name}` of the base path, without the proxy and placeholders.
+    /// Collections without cache settings of their own keep persistent caches in a `collections/{name}`
+    /// subdirectory of `cache.directory`, and an explicit `cache.sled_path` is suffixed with `-{name}`,
+    /// so collections never share stored images.
+    #[must_use]
+    pub fn collection(&self, name: &str) -> Option<Self> {
+        let collection = self.collections.get(name)?;
+        let mut config = Self {
+            proxy: ProxyConfig::default(),
+            placeholder: PlaceholderConfig::default(),
+            collections: BTreeMap::new(),
+            playlists: BTreeMap::new(),
+            ..self.clone()
+        };
+        config.server.sources.clone_from(&collection.sources);
+        config.server.base_path = format!("{}/{name}", self.server.base_path);
+        if let Some(cache) = &collection.cache {
+            config.cache = cache.clone();
+            return Some(config);
+        }
+        config.cache.directory = self
+            .cache
+            .directory
+            .as_ref()
+            .map(|directory| directory.join("collections").join(name));
+        config.cache.sled_path = self.cache.sled_path.as_ref().map(|path| {
+            let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+            file_name.push(format!("-{name}"));
+            path.with_file_name(file_name)
+        });
+        Some(config)
+    }
+
     /// Get the socket address for the server
     ///
     /// # Errors
@@ -0,0 +1,172 @@
+//! Running the server in the background on Unix, detached from the terminal that started it, with `--daemon`,
+//! and recording its process id in a pidfile, with `--pidfile`.
+//!
+//! Daemonizing must happen before the async runtime is started, since only the thread that forks survives.
+//! The working directory is kept, so relative paths in the config file still resolve.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow};
+use nix::{
+    errno::Errno,
+    sys::signal::kill,
+    unistd::{ForkResult, Pid, dup2_stderr, dup2_stdin, dup2_stdout, fork, getpid, setsid},
+};
+
+/// Detach the process from its terminal, continuing in a grandchild in a new session,
+/// with standard input and output redirected to `/dev/null`
+///
+/// The original process exits once the grandchild is forked, so this must be called before any threads are spawned.
+/// Since nothing is printed anymore, the server should log to `server.log_file`.
+///
+/// # Errors
+///
+/// Returns an error if the process can't be forked, or its standard input and output can't be redirected.
+pub fn daemonize() -> Result<()> {
+    // SAFETY: no other threads are running, so the child can safely run arbitrary code
+    if let ForkResult::Parent { .. } =
+        unsafe { fork() }.map_err(|e| anyhow!("Failed to fork: {e}"))?
+    {
+        std::process::exit(0);
+    }
+    setsid().map_err(|e| anyhow!("Failed to start a new session: {e}"))?;
+    // fork again, so the daemon isn't a session leader and can never acquire a controlling terminal
+    // SAFETY: as above, the process is still single-threaded
+    if let ForkResult::Parent { .. } =
+        unsafe { fork() }.map_err(|e| anyhow!("Failed to fork: {e}"))?
+    {
+        std::process::exit(0);
+    }
+
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .map_err(|e| anyhow!("Failed to open /dev/null: {e}"))?;
+    dup2_stdin(&null)
+        .and_then(|()| dup2_stdout(&null))
+        .and_then(|()| dup2_stderr(&null))
+        .map_err(|e| anyhow!("Failed to redirect standard input and output: {e}"))?;
+    Ok(())
+}
+
+/// A file holding the process id of the running server, removed when dropped
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the id of the current process to the file at the given path
+    ///
+    /// A pidfile left behind by a process that's no longer running is replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file names a process that's still running, or can't be written.
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut file = match Self::create_new(&path) {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                if let Some(pid) = Self::running_pid(&path) {
+                    return Err(anyhow!(
+                        "The server is already running with pid {pid}, according to {}",
+                        path.display()
+                    ));
+                }
+                tracing::warn!("Replacing the stale pidfile {}", path.display());
+                fs::remove_file(&path)
+                    .and_then(|()| Self::create_new(&path))
+                    .map_err(|e| anyhow!("Failed to replace pidfile {}: {e}", path.display()))?
+            }
+            result => {
+                result.map_err(|e| anyhow!("Failed to create pidfile {}: {e}", path.display()))?
+            }
+        };
+        writeln!(file, "{}", getpid())
+            .map_err(|e| anyhow!("Failed to write pidfile {}: {e}", path.display()))?;
+        Ok(Self { path })
+    }
+
+    fn create_new(path: &Path) -> std::io::Result<File> {
+        OpenOptions::new().write(true).create_new(true).open(path)
+    }
+
+    /// The process id in the pidfile at the given path, if the process is still running
+    fn running_pid(path: &Path) -> Option<i32> {
+        let pid = fs::read_to_string(path).ok()?.trim().parse().ok()?;
+        // a process we aren't allowed to signal is still running
+        match kill(Pid::from_raw(pid), None) {
+            Ok(()) | Err(Errno::EPERM) => Some(pid),
+            Err(_) => None,
+        }
+    }
+
+    /// The path of the pidfile
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            tracing::warn!("Failed to remove pidfile {}: {e}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_pidfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.pid");
+
+        let pidfile = PidFile::create(&path).unwrap();
+        assert_eq!(pidfile.path(), path);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        // this process is still running
+        assert!(PidFile::create(&path).is_err());
+        assert!(path.exists());
+
+        drop(pidfile);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_stale_pidfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.pid");
+        let mut exited = std::process::Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        fs::write(&path, format!("{}\n", exited.id())).unwrap();
+
+        let _pidfile = PidFile::create(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+    }
+
+    #[test]
+    fn test_invalid_pidfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.pid");
+        fs::write(&path, "not a pid").unwrap();
+        assert!(PidFile::create(&path).is_ok());
+
+        assert!(PidFile::create(dir.path().join("missing/server.pid")).is_err());
+    }
+}
@@ -0,0 +1,825 @@
+//! Generation of a fully commented default config file, for `print-default-config`.
+//!
+//! The values are serialized from `Config::default()`, so they can't drift from the code.
+//! Every field must be documented in `SECTIONS`, which is enforced by the tests below.
+
+use std::fmt::Write;
+
+use anyhow::{Result, anyhow};
+
+use crate::config::Config;
+
+/// Documentation of a section of the config file
+struct Section {
+    name: &'static str,
+    doc: &'static str,
+    fields: &'static [Field],
+}
+
+/// Documentation of a field of the config file
+struct Field {
+    key: &'static str,
+    doc: &'static str,
+    /// An example value, shown commented out for fields that are unset by default
+    example: Option<&'static str>,
+}
+
+const fn field(key: &'static str, doc: &'static str) -> Field {
+    Field {
+        key,
+        doc,
+        example: None,
+    }
+}
+
+const fn optional(key: &'static str, doc: &'static str, example: &'static str) -> Field {
+    Field {
+        key,
+        doc,
+        example: Some(example),
+    }
+}
+
+const SECTIONS: &[Section] = &[
+    Section {
+        name: "server",
+        doc: "Settings for the HTTP server",
+        fields: &[
+            field("port", "The port the server will listen on"),
+            field("host", "The host the server will bind to"),
+            field(
+                "log_level",
+                "The log level, one of \"error\", \"warn\", \"info\", \"debug\", or \"trace\"",
+            ),
+            optional(
+                "log_file",
+                "File to write logs to instead of stdout",
+                "\"/var/log/random-image-server/server.log\"",
+            ),
+            field(
+                "log_rotation",
+                "When to rotate the log file, one of \"daily\", \"hourly\", or \"size\"",
+            ),
+            field(
+                "log_max_size",
+                "The size in bytes at which the log file is rotated, when log_rotation = \"size\"",
+            ),
+            field(
+                "sources",
+                "The images to serve (required): paths to image files or directories, or URLs of images.\n\
+                 Prefix a URL with \"manifest:\" to load the images listed in a text (one URL per line) or JSON file.\n\
+                 Prefix a URL with \"feed:\" to load the images linked from an RSS or Atom feed, e.g. a picture of the day.\n\
+                 Paths and URLs of .zip, .tar, .tar.gz or .tgz archives load the images inside them.\n\
+                 Sources can also be tables (or `[[server.source]]` entries) with per-source settings:\n\
+                 { path = \"/path/to/cats\", weight = 3, tags = [\"cats\"], max_depth = 2, follow_symlinks = true, max_images = 100 }\n\
+                 { path = \"/path/to/wallpapers\", min_width = 1920, min_height = 1080, min_aspect_ratio = \"1:1\" }\n\
+                 { path = \"/path/to/memes\", caption = \"Meme of the day\" }, drawn onto its images with the `transforms` feature\n\
+                 { url = \"https://example.com/image.jpg\", refresh_interval = 3600, headers = { X-Api-Key = \"key\" } }\n\
+                 { url = \"https://example.com/private.jpg\", bearer_token = \"token\" } or basic_auth = { username = \"user\", password = \"secret\" }\n\
+                 Credentials can be overridden by RANDOM_IMAGE_SERVER_SOURCE_<N>_USERNAME, _PASSWORD, and _BEARER_TOKEN, where N counts sources from 0",
+            ),
+            field(
+                "base_path",
+                "Prefix that all routes are mounted under, e.g. \"/images\"",
+            ),
+            optional(
+                "public_url",
+                "The externally visible URL of the server, used when generating links",
+                "\"https://images.example.com\"",
+            ),
+            field(
+                "trusted_proxies",
+                "The proxies, as addresses or CIDR ranges, e.g. [\"10.0.0.0/8\"], whose forwarding headers are trusted:\n\
+                 the client address is taken from their Forwarded or X-Forwarded-For header, and the public URL from X-Forwarded-Proto and X-Forwarded-Host",
+            ),
+            field(
+                "allowed_referers",
+                "The domains images may be embedded on, e.g. [\"example.com\"], images may be embedded anywhere if empty.\n\
+                 Requests whose Origin (or Referer) is another domain are refused with 403 Forbidden,\n\
+                 requests sending neither (e.g. visiting an image directly) are always served",
+            ),
+            optional(
+                "hotlink_placeholder",
+                "An image served instead of the requested one to requests from other domains",
+                "\"/path/to/placeholder.png\"",
+            ),
+            field(
+                "access",
+                "The client addresses that may access the server, as CIDR ranges, e.g. { allow = [\"10.0.0.0/8\"], deny = [\"10.0.0.1\"] }.\n\
+                 Denied addresses are refused with 403 Forbidden, and so are addresses that aren't allowed, if any are.\n\
+                 Behind a trusted proxy, the client address is taken from the Forwarded or X-Forwarded-For header",
+            ),
+            field(
+                "auth",
+                "The HTTP basic authentication required by every route except /health, /livez, and /readyz, e.g.\n\
+                 { basic = { username = \"admin\", password_hash = \"$2b$12$...\" } }\n\
+                 The password hash is a bcrypt or argon2 hash, e.g. from `htpasswd -nbB admin <password>`",
+            ),
+            field(
+                "limits",
+                "Limits on the size of requests: the longest path and query (max_uri_length, refused with 414 URI Too Long),\n\
+                 and the largest request line and headers (max_header_size, at least 8192) and most headers (max_headers),\n\
+                 refused with 431 Request Header Fields Too Large. Requests with a body are refused with 400 Bad Request.\n\
+                 The limits are the same for every route",
+            ),
+            optional(
+                "env_file",
+                "A .env file to read RANDOM_IMAGE_SERVER_* variables from",
+                "\"/etc/random-image-server/server.env\"",
+            ),
+            field(
+                "include",
+                "Glob patterns of the files to load from directory sources, e.g. [\"*.png\"].\n\
+                 A pattern matches a file's path relative to its source, or the name of the file or any folder it is in.\n\
+                 If empty, every image is loaded",
+            ),
+            field(
+                "exclude",
+                "Glob patterns of the files or folders to skip in directory sources, e.g. [\"*_thumb.jpg\", \".*\"]",
+            ),
+            field(
+                "allowed_extensions",
+                "The file extensions of the images to load, e.g. add \"heic\", \"tiff\", or \"bmp\".\n\
+                 Each must be a known image type, and URL sources must respond with a matching content type",
+            ),
+            optional(
+                "min_file_size",
+                "The size in bytes below which images are skipped, e.g. to skip tiny icons",
+                "1024",
+            ),
+            optional(
+                "max_file_size",
+                "The size in bytes above which images are skipped, e.g. to skip giant raw scans",
+                "52428800",
+            ),
+            optional(
+                "dedup_threshold",
+                "Collapse near-duplicate images, whose perceptual hashes differ in at most this many of their 64 bits.\n\
+                 Requires the server to be built with the `perceptual-hash` feature",
+                "4",
+            ),
+            field(
+                "auto_orient",
+                "Whether JPEGs are rotated upright as they're loaded, according to their EXIF orientation,\n\
+                 so photos taken with phones aren't served sideways. Requires the `transforms` feature",
+            ),
+            optional(
+                "rescan_interval",
+                "How often every source is re-scanned, adding new images and dropping removed ones, e.g. \"10m\" or \"1h30m\".\n\
+                 Images already loaded from a directory are not read again. Sources with a `refresh_interval` are skipped",
+                "\"10m\"",
+            ),
+            optional(
+                "offline",
+                "Skip every remote source (URLs, manifests, feeds, and remote archives), e.g. for air-gapped deployments.\n\
+                 The server refuses to start if every source is remote",
+                "true",
+            ),
+            field(
+                "start_before_populate",
+                "Whether requests are accepted right away, while the cache is populated in the background,\n\
+                 instead of once it is populated. /readyz responds with 503 Service Unavailable until then",
+            ),
+            field(
+                "on_empty",
+                "What the server does if no images are found once the cache is populated, one of \"fail\" (exit with an error),\n\
+                 \"wait\" (keep running, re-scanning the sources until images appear), or \"placeholder\"\n\
+                 (wait, serving a generated placeholder from /random and /sequential meanwhile)",
+            ),
+            field(
+                "swagger_ui",
+                "Whether a Swagger UI page rendering the API description at /openapi.json is served at /docs",
+            ),
+            field(
+                "expose_sources",
+                "Whether the path or URL of each image served by /random and /sequential is sent in the X-Image-Source header,\n\
+                 besides its opaque id in X-Image-Id",
+            ),
+            optional(
+                "no_repeat_window",
+                "How many of the most recently served images /random avoids, so the same image isn't shown twice in a row,\n\
+                 as long as there are others to choose from",
+                "10",
+            ),
+            optional(
+                "sticky",
+                "How long the image /random chooses for a client is pinned to it, so dashboards that refresh often\n\
+                 don't flicker between images, for at most a week. Clients are identified by a cookie, or by their address\n\
+                 if they don't keep it. Requests may set their own with ?sticky={seconds}",
+                "\"5m\"",
+            ),
+            optional(
+                "favicon",
+                "An icon served at /favicon.ico instead of the built-in one",
+                "\"/path/to/favicon.ico\"",
+            ),
+            optional(
+                "static_dir",
+                "A directory of static files, e.g. the CSS and scripts of a gallery, served under /static/",
+                "\"/path/to/static\"",
+            ),
+            optional(
+                "ready_file",
+                "A file created once the server is ready, as reported by /readyz, and removed when it shuts down,\n\
+                 so container health checks can test for it, e.g. with `test -f`",
+                "\"/tmp/random-image-server.ready\"",
+            ),
+        ],
+    },
+    Section {
+        name: "cache",
+        doc: "Settings for the image cache",
+        fields: &[
+            field(
+                "backend",
+                "The cache backend to use, one of \"in_memory\", \"file_system\", \"tiered\"\n\
+                 (images on disk, with the most recently served ones kept in memory), or \"sled\"\n\
+                 (images in an embedded database, requires the `sled` feature).\n\
+                 Programs embedding the server can also select a backend they registered by its name",
+            ),
+            optional(
+                "directory",
+                "Directory the file_system and tiered backends store their data in, persisted across restarts.\n\
+                 Images fetched from URLs are spooled to its `spool` subdirectory, and loaded from there\n\
+                 if they can't be fetched after a restart. If unset, a temporary directory is used",
+                "\"/var/cache/random-image-server\"",
+            ),
+            optional(
+                "max_bytes",
+                "The most bytes of images the in_memory backend may hold, unlimited if unset",
+                "536870912",
+            ),
+            field(
+                "eviction",
+                "What the in_memory backend does when storing an image would exceed `max_bytes`,\n\
+                 one of \"reject\" (skip the image) or \"evict_oldest\" (evict the images stored first)",
+            ),
+            field(
+                "hot_images",
+                "How many of the most recently served images the tiered backend keeps in memory",
+            ),
+            optional(
+                "sled_path",
+                "The database the sled backend stores images in.\n\
+                 If unset, `images.sled` in `directory` is used, or a temporary database if that is unset too",
+                "\"/var/cache/random-image-server/images.sled\"",
+            ),
+            field(
+                "hash",
+                "The hash used to check the integrity of cached files, also sent as the `ETag` of images and used as their id,\n\
+                 one of \"blake3\", \"xxh3\" (faster, but not cryptographic), or \"md5\"",
+            ),
+            field(
+                "compression",
+                "How the file_system, tiered, and sled backends compress the images they store,\n\
+                 either \"none\" or \"zstd\" (smaller on disk, at the cost of decompressing each image served)",
+            ),
+            field(
+                "mmap",
+                "Whether the file_system and tiered backends memory-map the files they store, serving images from the page cache\n\
+                 rather than copying them into memory. Disable it where memory maps are undesirable, e.g. on network filesystems",
+            ),
+            optional(
+                "revalidate_interval",
+                "How often every file stored by the file_system and tiered backends is re-verified against its hash,\n\
+                 e.g. \"1h\". Corrupted images are removed from the cache, and loaded again from their source",
+                "\"1h\"",
+            ),
+            field(
+                "revalidate_rate",
+                "The most stored files re-verified per second, so revalidation doesn't hog the disk",
+            ),
+        ],
+    },
+    Section {
+        name: "observability",
+        doc: "Error reporting, requires the server to be built with the `sentry` feature",
+        fields: &[
+            optional(
+                "sentry_dsn",
+                "The Sentry DSN to report handler errors, fetch failures, and panics to",
+                "\"https://key@sentry.example.com/1\"",
+            ),
+            optional(
+                "sentry_environment",
+                "The environment reported to Sentry",
+                "\"production\"",
+            ),
+        ],
+    },
+    Section {
+        name: "metrics",
+        doc: "Metrics are exposed at /metrics, and optionally pushed to a statsd (or Datadog) agent",
+        fields: &[
+            optional(
+                "statsd_host",
+                "The host of the statsd agent, metrics are only pushed if set",
+                "\"127.0.0.1\"",
+            ),
+            field("statsd_port", "The port of the statsd agent"),
+            field("statsd_prefix", "The prefix of the names of pushed metrics"),
+            field(
+                "statsd_flush_interval",
+                "How often metrics are pushed, in seconds",
+            ),
+        ],
+    },
+    Section {
+        name: "http",
+        doc: "The HTTP client used to fetch images, manifests, feeds, and archives from URL sources",
+        fields: &[
+            optional(
+                "proxy",
+                "A proxy all requests are sent through, by default the system proxy settings (HTTPS_PROXY etc.) are used",
+                "\"http://proxy.example.com:8080\"",
+            ),
+            field("timeout", "How long a request may take, in seconds"),
+            field(
+                "max_redirects",
+                "How many redirects are followed, 0 to not follow redirects",
+            ),
+            field("user_agent", "The User-Agent header sent with requests"),
+            optional(
+                "ca_bundle",
+                "A PEM file of CA certificates to trust, in addition to the built-in ones",
+                "\"/etc/ssl/certs/internal-ca.pem\"",
+            ),
+            field(
+                "tls_verify",
+                "Whether TLS certificates are verified, disabling this is insecure",
+            ),
+            field(
+                "failure_threshold",
+                "How many fetches from a host may fail in a row before requests to it are skipped, 0 to never skip them.\n\
+                 Failing URLs are backed off exponentially, and a failing host is probed again every `max_backoff` seconds",
+            ),
+            field(
+                "max_backoff",
+                "The longest a failing URL is backed off for, in seconds, and how often a failing host is probed again",
+            ),
+            field(
+                "max_archive_size",
+                "The largest archive fetched from a URL, in bytes, as it's held in memory while its images are read",
+            ),
+        ],
+    },
+    Section {
+        name: "signing",
+        doc: "Signed, expiring links to images, of the form /image/{id}?expires=...&sig=...\n\
+              Create them with `random-image-server sign-url <image_id> <expires_in>`",
+        fields: &[
+            optional(
+                "secret",
+                "The secret links are signed with, links to images are only checked if it's set",
+                "\"change-me\"",
+            ),
+            field(
+                "required",
+                "Whether images are only served through signed links.\n\
+                 If set, /random, /sequential, /list, and unsigned links to images respond with 403 Forbidden",
+            ),
+        ],
+    },
+    Section {
+        name: "tls",
+        doc: "Serving HTTPS instead of HTTP, with the certificate and private key in the given PEM files",
+        fields: &[
+            optional(
+                "cert",
+                "The certificate chain of the server, HTTPS is served if it's set along with `key`",
+                "\"/etc/random-image-server/cert.pem\"",
+            ),
+            optional(
+                "key",
+                "The private key of the certificate",
+                "\"/etc/random-image-server/key.pem\"",
+            ),
+            optional(
+                "certificates",
+                "Certificates served instead of cert to the clients of the hostnames they're keyed by, as sent with SNI,\n\
+                 so one server can serve several domains. Hostnames may start with a `*.` wildcard",
+                "{ \"cdn.example.org\" = { cert = \"/etc/random-image-server/cdn.pem\", key = \"/etc/random-image-server/cdn.key\" } }",
+            ),
+            field(
+                "http3",
+                "Whether HTTP/3 is also served, over QUIC on the UDP port of the same number.\n\
+                 It's advertised to HTTPS clients with the Alt-Svc header. Requires the `http3` feature",
+            ),
+            optional(
+                "client_ca",
+                "The certificate authorities client certificates are verified against.\n\
+                 If set, clients must authenticate with a certificate signed by one of them,\n\
+                 and the common name of their certificate is logged with their requests",
+                "\"/etc/random-image-server/clients.pem\"",
+            ),
+            optional(
+                "acme",
+                "Obtain and renew the certificate automatically from an ACME certificate authority, instead of cert and key.\n\
+                 Domains are validated with the tls-alpn-01 challenge, so they must resolve to the server, listening on port 443.\n\
+                 The account key and certificate are stored in cache_dir, and `directory` sets the URL of the certificate authority,\n\
+                 Let's Encrypt by default. Requires the `acme` feature",
+                "{ domains = [\"images.example.com\"], email = \"admin@example.com\", cache_dir = \"/var/lib/random-image-server/acme\" }",
+            ),
+        ],
+    },
+    Section {
+        name: "notifications",
+        doc: "Notifications of lifecycle events, e.g. to post them to Slack",
+        fields: &[optional(
+            "webhook_url",
+            "A URL that events (startup, populating the cache, failing sources, and shutdown) are POSTed to as JSON,\n\
+             with a `text` field describing them",
+            "\"https://hooks.slack.com/services/...\"",
+        )],
+    },
+    Section {
+        name: "proxy",
+        doc: "Proxying remote images through a cache of their own, at `/proxy?url=...`",
+        fields: &[
+            field(
+                "allowed_domains",
+                "The domains images may be proxied from, including their subdomains. The proxy is disabled if empty",
+            ),
+            optional(
+                "cache",
+                "The cache proxied images are stored in, with the same settings as `[cache]`.\n\
+                 By default, an in-memory cache of up to 256 MiB, evicting the oldest images first",
+                "{ backend = \"file_system\", directory = \"/var/cache/random-image-server/proxy\" }",
+            ),
+        ],
+    },
+    Section {
+        name: "placeholder",
+        doc: "Generating placeholder images, at `/placeholder/{width}x{height}`, e.g. `/placeholder/640x480?color=ccc&to=333`",
+        fields: &[
+            field("enabled", "Whether placeholder images are generated"),
+            field("max_width", "The widest placeholder generated, in pixels"),
+            field("max_height", "The tallest placeholder generated, in pixels"),
+            optional(
+                "cache",
+                "The cache generated placeholders are stored in, with the same settings as `[cache]`.\n\
+                 By default, an in-memory cache of up to 64 MiB, evicting the oldest images first",
+                "{ backend = \"in_memory\", max_bytes = 16777216 }",
+            ),
+        ],
+    },
+    Section {
+        name: "transforms",
+        doc: "Transforming served images, e.g. `/random?caption=Hello`, which requires the `transforms` feature",
+        fields: &[
+            optional(
+                "cache",
+                "The cache transformed variants of images are stored in, with the same settings as `[cache]`.\n\
+                 By default, an in-memory cache of up to 128 MiB, evicting the oldest variants first",
+                "{ backend = \"file_system\", directory = \"/var/cache/random-image-server/variants\" }",
+            ),
+            optional(
+                "quality",
+                "The quality, from 1 to 100, JPEG images are re-encoded at unless a request sets `?quality=`,\n\
+                 e.g. to serve smaller files than the originals. JPEGs are served as they are if unset",
+                "70",
+            ),
+            field(
+                "formats",
+                "The formats, of \"avif\" and \"webp\", JPEG and PNG images are pre-encoded in as they're loaded, in order of preference.\n\
+                 Clients whose Accept header lists one of them are served it instead of the original, if it's smaller. Requires the `variants` feature",
+            ),
+        ],
+    },
+];
+
+/// Documentation of a collection, shown as a commented out example since none are configured by default
+const COLLECTION: Section = Section {
+    name: "collections.cats",
+    doc: "A named set of images, served under its own path, e.g. `/cats/random`, `/cats/sequential`, and `/cats/list`.\n\
+          The name can't be the first segment of another route, e.g. `image` or `stats`",
+    fields: &[
+        optional(
+            "sources",
+            "The images of the collection, in the same forms as `server.sources`",
+            "[\"/path/to/cats\"]",
+        ),
+        optional(
+            "cache",
+            "The cache the images of the collection are stored in, with the same settings as `[cache]`,\n\
+         e.g. to keep a small collection in memory and a large one on disk. By default, the settings of `[cache]` are used",
+            "{ backend = \"in_memory\", max_bytes = 268435456 }",
+        ),
+    ],
+};
+
+/// Documentation of a playlist, shown as a commented out example since none are configured by default
+const PLAYLIST: Section = Section {
+    name: "playlists.lobby",
+    doc: "A curated order of images, served one after the other at `/sequential/lobby`,\n\
+          with a position of its own",
+    fields: &[optional(
+        "items",
+        "The images of the playlist, in order: paths of images or directories, URLs of images,\n\
+         or any of `server.sources`, whose images are played in the order they were loaded.\n\
+         Only images loaded from `server.sources` are played",
+        "[\"/path/to/welcome.jpg\", \"/path/to/announcements\"]",
+    )],
+};
+
+/// Render the default configuration as a fully commented TOML config file
+///
+/// # Errors
+///
+/// Returns an error if the default configuration cannot be serialized.
+pub fn default_config_toml() -> Result<String> {
+    let defaults = toml::Table::try_from(Config::default())?;
+
+    let mut output = String::from(
+        "# Configuration for random-image-server\n\
+         # Generated with `random-image-server print-default-config`, values shown are the defaults\n",
+    );
+    for section in SECTIONS {
+        let values = defaults
+            .get(section.name)
+            .and_then(toml::Value::as_table)
+            .ok_or_else(|| anyhow!("Missing config section: {}", section.name))?;
+
+        let _ = writeln!(output, "\n[{}]", section.name);
+        comment(&mut output, section.doc);
+        for field in section.fields {
+            comment(&mut output, field.doc);
+            match (values.get(field.key), field.example) {
+                (Some(value), _) => {
+                    let _ = writeln!(output, "{} = {value}", field.key);
+                }
+                (None, Some(example)) => {
+                    let _ = writeln!(output, "# {} = {example}", field.key);
+                }
+                (None, None) => return Err(anyhow!("Missing default for {}", field.key)),
+            }
+        }
+    }
+
+    for section in [&COLLECTION, &PLAYLIST] {
+        let _ = writeln!(output, "\n# [{}]", section.name);
+        comment(&mut output, section.doc);
+        for field in section.fields {
+            comment(&mut output, field.doc);
+            let _ = writeln!(
+                output,
+                "# {} = {}",
+                field.key,
+                field.example.unwrap_or_default()
+            );
+        }
+    }
+    Ok(output)
+}
+
+/// Append the given text as `#` comments
+fn comment(output: &mut String, text: &str) {
+    for line in text.lines() {
+        let _ = writeln!(output, "# {line}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        path::PathBuf,
+    };
+
+    use super::*;
+    use crate::config::{
+        AccessConfig, AcmeConfig, AuthConfig, CacheBackendType, CacheConfig, CertificateFiles,
+        CollectionConfig, Compression, EmptyCachePolicy, EvictionPolicy, HashAlgorithm,
+        HashedBasicAuth, HttpConfig, ImageSource, LimitsConfig, LogRotation, MetricsConfig,
+        NotificationsConfig, ObservabilityConfig, PlaceholderConfig, PlaylistConfig, ProxyConfig,
+        ServerConfig, SigningConfig, TlsConfig, TransformsConfig, VariantFormat,
+    };
+    use pretty_assertions::assert_eq;
+
+    /// A configuration with every field set, so that every field is serialized
+    ///
+    /// Intentionally doesn't use `..Default::default()`, so that new fields must be added here.
+    fn full_config() -> Config {
+        Config {
+            server: ServerConfig {
+                port: 3000,
+                host: url::Host::Domain("localhost".to_string()),
+                log_level: tracing::Level::INFO,
+                log_file: Some(PathBuf::from("server.log")),
+                log_rotation: LogRotation::Daily,
+                log_max_size: 1024,
+                sources: vec![ImageSource::Path(PathBuf::from("assets")).into()],
+                base_path: String::new(),
+                public_url: Some("https://images.example.com".parse().unwrap()),
+                trusted_proxies: vec![],
+                allowed_referers: vec!["example.com".to_string()],
+                hotlink_placeholder: Some(PathBuf::from("assets/blank.jpg")),
+                access: AccessConfig {
+                    allow: vec!["10.0.0.0/8".parse().unwrap()],
+                    deny: vec!["10.0.0.1".parse().unwrap()],
+                },
+                auth: AuthConfig {
+                    basic: Some(HashedBasicAuth {
+                        username: "admin".to_string(),
+                        password_hash: "$2b$12$hash".to_string(),
+                    }),
+                },
+                limits: LimitsConfig {
+                    max_uri_length: 4096,
+                    max_header_size: 32 * 1024,
+                    max_headers: 50,
+                },
+                env_file: Some(PathBuf::from(".env")),
+                include: vec!["*.jpg".to_string()],
+                exclude: vec![".*".to_string()],
+                allowed_extensions: vec!["jpg".to_string()],
+                min_file_size: Some(1024),
+                max_file_size: Some(50 * 1024 * 1024),
+                dedup_threshold: Some(4),
+                auto_orient: true,
+                rescan_interval: Some(std::time::Duration::from_secs(600)),
+                offline: false,
+                start_before_populate: true,
+                on_empty: EmptyCachePolicy::Wait,
+                swagger_ui: true,
+                expose_sources: false,
+                no_repeat_window: Some(10),
+                sticky: Some(std::time::Duration::from_secs(300)),
+                favicon: Some(PathBuf::from("static/favicon.ico")),
+                static_dir: Some(PathBuf::from("static")),
+                ready_file: Some(PathBuf::from("/tmp/ready")),
+            },
+            cache: CacheConfig {
+                backend: CacheBackendType::InMemory,
+                directory: Some(PathBuf::from("cache")),
+                max_bytes: Some(512 * 1024 * 1024),
+                eviction: EvictionPolicy::EvictOldest,
+                hot_images: 100,
+                sled_path: Some(PathBuf::from("images.sled")),
+                hash: HashAlgorithm::Xxh3,
+                compression: Compression::Zstd,
+                mmap: false,
+                revalidate_interval: Some(std::time::Duration::from_secs(3600)),
+                revalidate_rate: 20,
+            },
+            observability: ObservabilityConfig {
+                sentry_dsn: Some("https://key@sentry.example.com/1".to_string()),
+                sentry_environment: Some("production".to_string()),
+            },
+            metrics: MetricsConfig {
+                statsd_host: Some("localhost".to_string()),
+                statsd_port: 8125,
+                statsd_prefix: "images".to_string(),
+                statsd_flush_interval: 10,
+            },
+            http: HttpConfig {
+                proxy: Some("http://proxy.example.com:8080".parse().unwrap()),
+                timeout: 30,
+                max_redirects: 10,
+                user_agent: "random-image-server".to_string(),
+                ca_bundle: Some(PathBuf::from("ca.pem")),
+                tls_verify: true,
+                failure_threshold: 3,
+                max_backoff: 600,
+                max_archive_size: 1024,
+            },
+            signing: SigningConfig {
+                secret: Some("secret".to_string()),
+                required: true,
+            },
+            tls: TlsConfig {
+                cert: Some(PathBuf::from("/etc/random-image-server/cert.pem")),
+                key: Some(PathBuf::from("/etc/random-image-server/key.pem")),
+                certificates: [(
+                    "cdn.example.org".to_string(),
+                    CertificateFiles {
+                        cert: PathBuf::from("/etc/random-image-server/cdn.pem"),
+                        key: PathBuf::from("/etc/random-image-server/cdn.key"),
+                    },
+                )]
+                .into(),
+                http3: true,
+                client_ca: Some(PathBuf::from("/etc/random-image-server/clients.pem")),
+                acme: Some(AcmeConfig {
+                    domains: vec!["images.example.com".to_string()],
+                    email: Some("admin@example.com".to_string()),
+                    cache_dir: PathBuf::from("/var/lib/random-image-server/acme"),
+                    ..AcmeConfig::default()
+                }),
+            },
+            notifications: NotificationsConfig {
+                webhook_url: Some("https://hooks.example.com/events".parse().unwrap()),
+            },
+            proxy: ProxyConfig {
+                allowed_domains: vec!["example.com".to_string()],
+                cache: Some(CacheConfig {
+                    backend: CacheBackendType::FileSystem,
+                    ..CacheConfig::default()
+                }),
+            },
+            placeholder: PlaceholderConfig {
+                enabled: true,
+                max_width: 1920,
+                max_height: 1080,
+                cache: Some(CacheConfig {
+                    backend: CacheBackendType::InMemory,
+                    max_bytes: Some(16_777_216),
+                    ..CacheConfig::default()
+                }),
+            },
+            transforms: TransformsConfig {
+                cache: Some(CacheConfig {
+                    backend: CacheBackendType::FileSystem,
+                    directory: Some(PathBuf::from("/var/cache/random-image-server/variants")),
+                    ..CacheConfig::default()
+                }),
+                quality: Some(70),
+                formats: vec![VariantFormat::Avif, VariantFormat::Webp],
+            },
+            collections: BTreeMap::from([(
+                "cats".to_string(),
+                CollectionConfig {
+                    sources: vec![ImageSource::Path(PathBuf::from("/path/to/cats")).into()],
+                    cache: Some(CacheConfig {
+                        backend: CacheBackendType::InMemory,
+                        max_bytes: Some(268_435_456),
+                        ..CacheConfig::default()
+                    }),
+                },
+            )]),
+            playlists: BTreeMap::from([(
+                "lobby".to_string(),
+                PlaylistConfig {
+                    items: vec![ImageSource::Path(PathBuf::from("/path/to/welcome.jpg"))],
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn test_every_field_is_documented() {
+        let full = toml::Table::try_from(full_config()).unwrap();
+        let serialized: BTreeSet<String> = full
+            .iter()
+            .flat_map(|(section, values)| {
+                let values = values.as_table().unwrap();
+                // collections and playlists are tables of their own, named by their key
+                if section == "collections" || section == "playlists" {
+                    values
+                        .iter()
+                        .flat_map(|(name, collection)| {
+                            collection
+                                .as_table()
+                                .unwrap()
+                                .keys()
+                                .map(move |key| format!("{section}.{name}.{key}"))
+                        })
+                        .collect::<Vec<_>>()
+                } else {
+                    values
+                        .keys()
+                        .map(|key| format!("{section}.{key}"))
+                        .collect()
+                }
+            })
+            .collect();
+        let documented: BTreeSet<String> = SECTIONS
+            .iter()
+            .chain([&COLLECTION, &PLAYLIST])
+            .flat_map(|section| {
+                section
+                    .fields
+                    .iter()
+                    .map(|field| format!("{}.{}", section.name, field.key))
+            })
+            .collect();
+
+        assert_eq!(serialized, documented);
+    }
+
+    #[test]
+    fn test_default_config_roundtrip() {
+        let output = default_config_toml().unwrap();
+        assert!(output.contains("\n[server]\n"));
+        assert!(output.contains("\nport = 3000\n"));
+        assert!(output.contains("\n# log_file = \"/var/log/random-image-server/server.log\"\n"));
+        assert!(output.contains("\n# [collections.cats]\n"));
+        assert!(output.contains("\n# [playlists.lobby]\n"));
+
+        // sources are required, so the default config only loads once some are added
+        let output = output.replace("sources = []", "sources = [\"assets\"]");
+        let config: Config = toml::from_str(&output).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                server: ServerConfig {
+                    sources: vec![
+                        ImageSource::Path(PathBuf::from("assets").canonicalize().unwrap()).into()
+                    ],
+                    ..ServerConfig::default()
+                },
+                ..Config::default()
+            }
+        );
+    }
+}
@@ -1,4 +1,6 @@
-use std::env::VarError;
+use std::{collections::HashMap, env::VarError, path::Path};
+
+use anyhow::{Result, anyhow};
 
 pub trait EnvBackend {
     /// Read an environment variable
@@ -15,7 +17,8 @@ pub trait EnvBackend {
     fn remove(&mut self, var: &str);
 }
 
-pub(crate) struct StdEnvBackend;
+/// The environment of the current process
+pub struct StdEnvBackend;
 impl EnvBackend for StdEnvBackend {
     fn var(&self, var: &str) -> Result<String, VarError> {
         std::env::var(var)
@@ -48,3 +51,83 @@ impl EnvBackend for MockEnvBackend {
         self.vars.remove(var);
     }
 }
+
+/// An environment backend that falls back to the variables of a `.env` file
+///
+/// Variables set in the underlying environment take precedence over the ones in the file.
+pub struct DotEnvBackend<'a, E: EnvBackend> {
+    env: &'a E,
+    vars: HashMap<String, String>,
+}
+
+impl<'a, E: EnvBackend> DotEnvBackend<'a, E> {
+    /// Wrap an environment backend, without any variables from a `.env` file
+    pub fn new(env: &'a E) -> Self {
+        Self {
+            env,
+            vars: HashMap::new(),
+        }
+    }
+
+    /// Wrap an environment backend, falling back to the variables in the given `.env` file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed.
+    pub fn from_file(env: &'a E, path: &Path) -> Result<Self> {
+        let vars = dotenvy::from_path_iter(path)
+            .and_then(Iterator::collect)
+            .map_err(|e| anyhow!("Failed to load env file {}: {e}", path.display()))?;
+        Ok(Self { env, vars })
+    }
+}
+
+impl<E: EnvBackend> EnvBackend for DotEnvBackend<'_, E> {
+    fn var(&self, var: &str) -> Result<String, VarError> {
+        self.env
+            .var(var)
+            .or_else(|_| self.vars.get(var).cloned().ok_or(VarError::NotPresent))
+    }
+
+    fn set_var(&mut self, var: &str, value: &str) {
+        self.vars.insert(var.to_string(), value.to_string());
+    }
+
+    fn remove(&mut self, var: &str) {
+        self.vars.remove(var);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_dotenv_backend() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(".env");
+        std::fs::write(
+            &path,
+            "# comment\nRANDOM_IMAGE_SERVER_PORT=8080\nexport RANDOM_IMAGE_SERVER_HOST=\"example.com\"\n",
+        )
+        .unwrap();
+        let mut env = MockEnvBackend::default();
+        env.set_var("RANDOM_IMAGE_SERVER_PORT", "9090");
+
+        let dotenv = DotEnvBackend::from_file(&env, &path).unwrap();
+        assert_eq!(dotenv.var("RANDOM_IMAGE_SERVER_PORT").unwrap(), "9090");
+        assert_eq!(
+            dotenv.var("RANDOM_IMAGE_SERVER_HOST").unwrap(),
+            "example.com"
+        );
+        assert!(dotenv.var("RANDOM_IMAGE_SERVER_LOG_LEVEL").is_err());
+    }
+
+    #[test]
+    fn test_dotenv_backend_missing_file() {
+        let env = MockEnvBackend::default();
+        assert!(DotEnvBackend::from_file(&env, Path::new("/nonexistent/.env")).is_err());
+    }
+}
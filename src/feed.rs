@@ -0,0 +1,173 @@
+//! Feed sources: RSS or Atom feeds whose entries link to images, e.g. "picture of the day" feeds.
+//!
+//! Images are taken from RSS `<enclosure>` elements, Media RSS `<media:content>` and
+//! `<media:thumbnail>` elements, and Atom `<link rel="enclosure">` elements. Links with a
+//! `type` (or `medium`) that isn't an image are skipped. Relative URLs are resolved against
+//! the URL of the feed.
+
+#[cfg(feature = "remote-sources")]
+use std::collections::BTreeMap;
+
+use anyhow::{Result, anyhow};
+use quick_xml::events::{BytesStart, Event};
+use url::Url;
+
+/// Parse the image URLs linked from a feed fetched from `base`, in the order they appear
+///
+/// # Errors
+///
+/// Returns an error if the feed is not valid XML, or links to an invalid URL.
+pub fn parse_feed(base: &Url, body: &str) -> Result<Vec<Url>> {
+    let mut reader = quick_xml::Reader::from_str(body);
+    let mut urls = Vec::new();
+    loop {
+        match reader
+            .read_event()
+            .map_err(|e| anyhow!("Invalid feed: {e}"))?
+        {
+            Event::Start(element) | Event::Empty(element) => {
+                if let Some(link) = image_link(&element)? {
+                    let url = base
+                        .join(&link)
+                        .map_err(|e| anyhow!("Invalid URL in feed: {link}: {e}"))?;
+                    if !urls.contains(&url) {
+                        urls.push(url);
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(urls)
+}
+
+/// The image an element links to, if it is an enclosure or media element linking to an image
+fn image_link(element: &BytesStart<'_>) -> Result<Option<String>> {
+    let link_attribute: &[u8] = match element.local_name().as_ref() {
+        b"enclosure" | b"content" | b"thumbnail" => b"url",
+        b"link" => b"href",
+        _ => return Ok(None),
+    };
+
+    let mut link = None;
+    let mut is_enclosure = element.local_name().as_ref() != b"link";
+    let mut is_image = true;
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| anyhow!("Invalid feed: {e}"))?;
+        let value = attribute
+            .unescape_value()
+            .map_err(|e| anyhow!("Invalid feed: {e}"))?;
+        match attribute.key.local_name().as_ref() {
+            key if key == link_attribute => link = Some(value.into_owned()),
+            b"rel" => is_enclosure = value == "enclosure",
+            b"type" => is_image &= value.starts_with("image/"),
+            b"medium" => is_image &= value == "image",
+            _ => {}
+        }
+    }
+    Ok(link.filter(|_| is_enclosure && is_image))
+}
+
+/// Fetch a feed with the given client, sending the given HTTP headers, and return the image URLs it links to
+///
+/// # Errors
+///
+/// Returns an error if the feed cannot be fetched or parsed.
+#[cfg(feature = "remote-sources")]
+#[tracing::instrument(level = "debug", skip_all, fields(url = %url))]
+pub async fn fetch_feed(
+    client: &reqwest::Client,
+    url: &Url,
+    headers: &BTreeMap<String, String>,
+) -> Result<Vec<Url>> {
+    let response = headers
+        .iter()
+        .fold(client.get(url.as_str()), |request, (name, value)| {
+            request.header(name, value)
+        })
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to fetch feed: {e}"))?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Failed to fetch feed, status: {}",
+            response.status()
+        ));
+    }
+
+    let body = response
+        .text()
+        .await
+        .map_err(|e| anyhow!("Failed to read feed: {e}"))?;
+
+    parse_feed(url, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    const RSS: &str = r#"<?xml version="1.0"?>
+<rss version="2.0" xmlns:media="http://search.yahoo.com/mrss/">
+  <channel>
+    <title>Picture of the day</title>
+    <link>https://example.com/</link>
+    <image><url>https://example.com/logo.png</url></image>
+    <item>
+      <title>Today</title>
+      <enclosure url="https://example.com/today.jpg" length="1000" type="image/jpeg"/>
+      <media:content url="https://example.com/today.jpg" medium="image"/>
+    </item>
+    <item>
+      <title>Yesterday</title>
+      <media:thumbnail url="/images/yesterday.png"/>
+      <enclosure url="https://example.com/podcast.mp3" type="audio/mpeg"/>
+      <media:content url="https://example.com/clip.mp4" medium="video"/>
+    </item>
+  </channel>
+</rss>"#;
+
+    const ATOM: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Picture of the day</title>
+  <link href="https://example.com/"/>
+  <entry>
+    <title>Today</title>
+    <link rel="alternate" href="https://example.com/today"/>
+    <link rel="enclosure" type="image/jpeg" href="https://example.com/today.jpg"/>
+    <content type="html">&lt;p&gt;Today&lt;/p&gt;</content>
+  </entry>
+  <entry>
+    <title>Yesterday</title>
+    <link rel="enclosure" href="/images/yesterday.png"/>
+  </entry>
+</feed>"#;
+
+    #[rstest]
+    #[case::rss(RSS)]
+    #[case::atom(ATOM)]
+    fn test_parse_feed(#[case] body: &str) {
+        let base = Url::parse("https://example.com/feed.xml").unwrap();
+        let urls = parse_feed(&base, body).unwrap();
+
+        assert_eq!(
+            urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+            vec![
+                "https://example.com/today.jpg",
+                "https://example.com/images/yesterday.png"
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case::invalid_xml("<rss><channel></rss>")]
+    #[case::invalid_url(r#"<rss><enclosure url="https://[invalid" type="image/jpeg"/></rss>"#)]
+    fn test_parse_feed_invalid(#[case] body: &str) {
+        let base = Url::parse("https://example.com/feed.xml").unwrap();
+        assert!(parse_feed(&base, body).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! Include/exclude filename filters, applied to the files found in directory sources.
+
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+/// Glob filters deciding which files of a directory source are loaded
+///
+/// A pattern matches a file if it matches the file's path relative to the source directory,
+/// or the name of the file or of any directory it is in. So `*_thumb.jpg` skips thumbnails,
+/// and `.*` skips hidden files and folders.
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl FileFilter {
+    /// Build a filter from the given include and exclude patterns
+    ///
+    /// If there are no include patterns, every file that isn't excluded is loaded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the patterns is not a valid glob.
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: if include.is_empty() {
+                None
+            } else {
+                Some(glob_set(include)?)
+            },
+            exclude: glob_set(exclude)?,
+        })
+    }
+
+    /// Whether the directory at the given path (relative to the source directory) should be searched
+    #[must_use]
+    pub fn allows_directory(&self, relative: &Path) -> bool {
+        !matches_path_or_name(&self.exclude, relative)
+    }
+
+    /// Whether the file at the given path (relative to the source directory) should be loaded
+    #[must_use]
+    pub fn allows_file(&self, relative: &Path) -> bool {
+        self.allows_directory(relative)
+            && self
+                .include
+                .as_ref()
+                .is_none_or(|include| matches_any_component(include, relative))
+    }
+}
+
+/// Check that a glob pattern is valid
+///
+/// # Errors
+///
+/// Returns an error if the pattern is not a valid glob.
+pub fn validate_glob(pattern: &str) -> Result<()> {
+    parse_glob(pattern).map(|_| ())
+}
+
+fn parse_glob(pattern: &str) -> Result<Glob> {
+    Glob::new(pattern).map_err(|e| anyhow!("Invalid glob pattern {pattern:?}: {e}"))
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(parse_glob(pattern)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Whether the set matches the path, or its final component
+///
+/// Used while walking a directory, where the parents of the path have already been checked.
+fn matches_path_or_name(set: &GlobSet, relative: &Path) -> bool {
+    set.is_match(relative) || relative.file_name().is_some_and(|name| set.is_match(name))
+}
+
+/// Whether the set matches the path, or any of its components
+fn matches_any_component(set: &GlobSet, relative: &Path) -> bool {
+    set.is_match(relative)
+        || relative
+            .components()
+            .any(|component| set.is_match(component.as_os_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(ToString::to_string).collect()
+    }
+
+    #[rstest]
+    #[case::no_patterns(&[], &[], "cats/cat.jpg", true)]
+    #[case::excluded_name(&[], &["*_thumb.jpg"], "cat_thumb.jpg", false)]
+    #[case::excluded_nested_name(&[], &["*_thumb.jpg"], "cats/cat_thumb.jpg", false)]
+    #[case::not_excluded(&[], &["*_thumb.jpg"], "cats/cat.jpg", true)]
+    #[case::hidden_file(&[], &[".*"], ".cat.jpg", false)]
+    #[case::excluded_path(&[], &["raw/*"], "raw/cat.jpg", false)]
+    #[case::included_name(&["*.png"], &[], "cats/cat.png", true)]
+    #[case::not_included(&["*.png"], &[], "cats/cat.jpg", false)]
+    #[case::included_directory(&["cats"], &[], "cats/cat.jpg", true)]
+    #[case::excluded_overrides_included(&["*.png"], &["cat*"], "cat.png", false)]
+    fn test_allows_file(
+        #[case] include: &[&str],
+        #[case] exclude: &[&str],
+        #[case] path: &str,
+        #[case] expected: bool,
+    ) {
+        let filter = FileFilter::new(&patterns(include), &patterns(exclude)).unwrap();
+        assert_eq!(filter.allows_file(Path::new(path)), expected);
+    }
+
+    #[test]
+    fn test_allows_directory() {
+        let filter = FileFilter::new(&patterns(&["*.png"]), &patterns(&[".*"])).unwrap();
+        assert!(filter.allows_directory(Path::new("cats")));
+        assert!(!filter.allows_directory(Path::new(".thumbnails")));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(FileFilter::new(&patterns(&["[a-"]), &[]).is_err());
+        assert!(validate_glob("[a-").is_err());
+        assert!(validate_glob("*.jpg").is_ok());
+    }
+}
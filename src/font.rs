@@ -0,0 +1,273 @@
+//! The font text is drawn onto images with, e.g. the dimensions of placeholders and captions:
+//! a 5x7 pixel bitmap font of the printable ASCII characters, scaled up by whole pixels.
+
+/// The width of a glyph, in font pixels
+pub const GLYPH_WIDTH: usize = 5;
+/// The height of a glyph, in font pixels
+pub const GLYPH_HEIGHT: usize = 7;
+/// The space between glyphs, in font pixels
+const SPACING: usize = 1;
+
+/// The glyphs of the printable ASCII characters, from space to `~`, as rows of bits,
+/// the leftmost pixel being the most significant bit
+#[rustfmt::skip]
+const GLYPHS: [[u8; GLYPH_HEIGHT]; 95] = [
+    [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000], // space
+    [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000, 0b00100], // !
+    [0b01010, 0b01010, 0b01010, 0b00000, 0b00000, 0b00000, 0b00000], // "
+    [0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010], // #
+    [0b00100, 0b01111, 0b10100, 0b01110, 0b00101, 0b11110, 0b00100], // $
+    [0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011], // %
+    [0b01100, 0b10010, 0b10100, 0b01000, 0b10101, 0b10010, 0b01101], // &
+    [0b01100, 0b00100, 0b01000, 0b00000, 0b00000, 0b00000, 0b00000], // '
+    [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010], // (
+    [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000], // )
+    [0b00000, 0b00100, 0b10101, 0b01110, 0b10101, 0b00100, 0b00000], // *
+    [0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000], // +
+    [0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000], // ,
+    [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000], // -
+    [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100], // .
+    [0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000], // /
+    [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110], // 0
+    [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110], // 1
+    [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111], // 2
+    [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110], // 3
+    [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010], // 4
+    [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110], // 5
+    [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110], // 6
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000], // 7
+    [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110], // 8
+    [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100], // 9
+    [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000], // :
+    [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b00100, 0b01000], // ;
+    [0b00010, 0b00100, 0b01000, 0b10000, 0b01000, 0b00100, 0b00010], // <
+    [0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000], // =
+    [0b01000, 0b00100, 0b00010, 0b00001, 0b00010, 0b00100, 0b01000], // >
+    [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100], // ?
+    [0b01110, 0b10001, 0b00001, 0b01101, 0b10101, 0b10101, 0b01110], // @
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001], // A
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110], // B
+    [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110], // C
+    [0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100], // D
+    [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111], // E
+    [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000], // F
+    [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111], // G
+    [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001], // H
+    [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110], // I
+    [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100], // J
+    [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001], // K
+    [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111], // L
+    [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001], // M
+    [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001], // N
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110], // O
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000], // P
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101], // Q
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001], // R
+    [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110], // S
+    [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100], // T
+    [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110], // U
+    [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100], // V
+    [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010], // W
+    [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001], // X
+    [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100], // Y
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111], // Z
+    [0b01110, 0b01000, 0b01000, 0b01000, 0b01000, 0b01000, 0b01110], // [
+    [0b00000, 0b10000, 0b01000, 0b00100, 0b00010, 0b00001, 0b00000], // backslash
+    [0b01110, 0b00010, 0b00010, 0b00010, 0b00010, 0b00010, 0b01110], // ]
+    [0b00100, 0b01010, 0b10001, 0b00000, 0b00000, 0b00000, 0b00000], // ^
+    [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111], // _
+    [0b01000, 0b00100, 0b00010, 0b00000, 0b00000, 0b00000, 0b00000], // `
+    [0b00000, 0b00000, 0b01110, 0b00001, 0b01111, 0b10001, 0b01111], // a
+    [0b10000, 0b10000, 0b10110, 0b11001, 0b10001, 0b10001, 0b11110], // b
+    [0b00000, 0b00000, 0b01110, 0b10000, 0b10000, 0b10001, 0b01110], // c
+    [0b00001, 0b00001, 0b01101, 0b10011, 0b10001, 0b10001, 0b01111], // d
+    [0b00000, 0b00000, 0b01110, 0b10001, 0b11111, 0b10000, 0b01110], // e
+    [0b00110, 0b01001, 0b01000, 0b11100, 0b01000, 0b01000, 0b01000], // f
+    [0b00000, 0b01111, 0b10001, 0b10001, 0b01111, 0b00001, 0b01110], // g
+    [0b10000, 0b10000, 0b10110, 0b11001, 0b10001, 0b10001, 0b10001], // h
+    [0b00100, 0b00000, 0b01100, 0b00100, 0b00100, 0b00100, 0b01110], // i
+    [0b00010, 0b00000, 0b00110, 0b00010, 0b00010, 0b10010, 0b01100], // j
+    [0b10000, 0b10000, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010], // k
+    [0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110], // l
+    [0b00000, 0b00000, 0b11010, 0b10101, 0b10101, 0b10001, 0b10001], // m
+    [0b00000, 0b00000, 0b10110, 0b11001, 0b10001, 0b10001, 0b10001], // n
+    [0b00000, 0b00000, 0b01110, 0b10001, 0b10001, 0b10001, 0b01110], // o
+    [0b00000, 0b00000, 0b11110, 0b10001, 0b11110, 0b10000, 0b10000], // p
+    [0b00000, 0b00000, 0b01101, 0b10011, 0b01111, 0b00001, 0b00001], // q
+    [0b00000, 0b00000, 0b10110, 0b11001, 0b10000, 0b10000, 0b10000], // r
+    [0b00000, 0b00000, 0b01110, 0b10000, 0b01110, 0b00001, 0b11110], // s
+    [0b01000, 0b01000, 0b11100, 0b01000, 0b01000, 0b01001, 0b00110], // t
+    [0b00000, 0b00000, 0b10001, 0b10001, 0b10001, 0b10011, 0b01101], // u
+    [0b00000, 0b00000, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100], // v
+    [0b00000, 0b00000, 0b10001, 0b10001, 0b10101, 0b10101, 0b01010], // w
+    [0b00000, 0b00000, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001], // x
+    [0b00000, 0b00000, 0b10001, 0b10001, 0b01111, 0b00001, 0b01110], // y
+    [0b00000, 0b00000, 0b11111, 0b00010, 0b00100, 0b01000, 0b11111], // z
+    [0b00010, 0b00100, 0b00100, 0b01000, 0b00100, 0b00100, 0b00010], // {
+    [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100], // |
+    [0b01000, 0b00100, 0b00100, 0b00010, 0b00100, 0b00100, 0b01000], // }
+    [0b00000, 0b00000, 0b01000, 0b10101, 0b00010, 0b00000, 0b00000], // ~
+];
+
+/// The glyph of a character, `?` for characters outside printable ASCII
+#[must_use]
+pub fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    let printable = if c == ' ' || c.is_ascii_graphic() {
+        c
+    } else {
+        '?'
+    };
+    GLYPHS[printable as usize - ' ' as usize]
+}
+
+/// The width of a line of text, in font pixels
+#[must_use]
+pub fn text_width(text: &str) -> usize {
+    (text.chars().count() * (GLYPH_WIDTH + SPACING)).saturating_sub(SPACING)
+}
+
+/// The most characters a line of text the given number of font pixels wide can hold
+#[must_use]
+pub const fn max_chars(width: usize) -> usize {
+    (width + SPACING) / (GLYPH_WIDTH + SPACING)
+}
+
+/// A buffer of pixels, row by row, that text is drawn onto
+#[derive(Debug)]
+pub struct Canvas<'a> {
+    pub pixels: &'a mut [u8],
+    /// The width of the buffer, in pixels
+    pub width: usize,
+    /// The number of bytes of each pixel, 3 for RGB or 4 for RGBA
+    pub channels: usize,
+}
+
+impl Canvas<'_> {
+    /// The height of the buffer, in pixels
+    #[must_use]
+    pub fn height(&self) -> usize {
+        self.pixels.len() / (self.width * self.channels).max(1)
+    }
+
+    /// Draw a line of text with its top-left corner at the given position, each font pixel
+    /// drawn as a square of `scale` by `scale` pixels, clipped to the buffer
+    pub fn draw_text(
+        &mut self,
+        text: &str,
+        (left, top): (usize, usize),
+        scale: usize,
+        color: [u8; 3],
+    ) {
+        for (index, c) in text.chars().enumerate() {
+            let glyph_left = left + index * (GLYPH_WIDTH + SPACING) * scale;
+            for (row, bits) in glyph(c).into_iter().enumerate() {
+                for column in
+                    (0..GLYPH_WIDTH).filter(|column| bits >> (GLYPH_WIDTH - 1 - column) & 1 == 1)
+                {
+                    let position = (glyph_left + column * scale, top + row * scale);
+                    self.blend(position, (scale, scale), color, u8::MAX);
+                }
+            }
+        }
+    }
+
+    /// Blend a rectangle of the buffer with the given color, at the given opacity (`u8::MAX` to paint over it),
+    /// clipped to the buffer
+    ///
+    /// Transparent pixels of RGBA buffers are made as opaque as the color.
+    pub fn blend(
+        &mut self,
+        (left, top): (usize, usize),
+        (width, height): (usize, usize),
+        color: [u8; 3],
+        opacity: u8,
+    ) {
+        let (left, right) = (left.min(self.width), (left + width).min(self.width));
+        let opacity = u32::from(opacity);
+        let mix = |from: u8, to: u8| {
+            let mixed = (u32::from(from) * (255 - opacity) + u32::from(to) * opacity) / 255;
+            u8::try_from(mixed).unwrap_or(u8::MAX)
+        };
+        for y in top..(top + height).min(self.height()) {
+            let row = y * self.width;
+            let pixels =
+                &mut self.pixels[(row + left) * self.channels..(row + right) * self.channels];
+            for pixel in pixels.chunks_exact_mut(self.channels) {
+                for (channel, to) in pixel.iter_mut().zip(color) {
+                    *channel = mix(*channel, to);
+                }
+                if let Some(alpha) = pixel.get_mut(3) {
+                    *alpha = mix(*alpha, u8::MAX);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_glyph() {
+        assert_eq!(glyph(' '), [0; GLYPH_HEIGHT]);
+        assert_eq!(
+            glyph('1'),
+            [
+                0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110
+            ]
+        );
+        // glyphs fit in their width, and every visible character has some pixels
+        for c in ' '..='~' {
+            assert!(glyph(c).iter().all(|row| *row < 1 << GLYPH_WIDTH), "{c}");
+            assert_eq!(glyph(c) == [0; GLYPH_HEIGHT], c == ' ', "{c}");
+        }
+        assert_eq!(glyph('é'), glyph('?'));
+        assert_eq!(glyph('\n'), glyph('?'));
+    }
+
+    #[test]
+    fn test_text_width() {
+        assert_eq!(text_width(""), 0);
+        assert_eq!(text_width("a"), GLYPH_WIDTH);
+        assert_eq!(text_width("640x480"), 7 * GLYPH_WIDTH + 6);
+        for chars in 0..10 {
+            let text = "a".repeat(chars);
+            assert_eq!(max_chars(text_width(&text)), chars);
+        }
+    }
+
+    #[test]
+    fn test_canvas_draw_text() {
+        let mut pixels = vec![0; 8 * 9 * 3];
+        let mut canvas = Canvas {
+            pixels: &mut pixels,
+            width: 8,
+            channels: 3,
+        };
+        assert_eq!(canvas.height(), 9);
+        // the glyph of `|` is a single column, drawn 2 pixels wide from (2, 1), clipped at the bottom
+        canvas.draw_text("|", (0, 1), 2, [0xff; 3]);
+        let lit: Vec<(usize, usize)> = pixels
+            .chunks_exact(3)
+            .enumerate()
+            .filter(|(_, pixel)| pixel == &[0xff; 3])
+            .map(|(index, _)| (index % 8, index / 8))
+            .collect();
+        let expected: Vec<(usize, usize)> = (1..9).flat_map(|y| [(4, y), (5, y)]).collect();
+        assert_eq!(lit, expected);
+    }
+
+    #[test]
+    fn test_canvas_blend() {
+        let mut pixels = vec![0xff, 0xff, 0xff, 0, 0x00, 0x00, 0x00, 0xff];
+        let mut canvas = Canvas {
+            pixels: &mut pixels,
+            width: 2,
+            channels: 4,
+        };
+        canvas.blend((0, 0), (3, 3), [0, 0, 0], 0x80);
+        assert_eq!(pixels, [0x7f, 0x7f, 0x7f, 0x80, 0, 0, 0, 0xff]);
+    }
+}
@@ -0,0 +1,150 @@
+//! The HTTP clients used to fetch images, manifests, feeds, and archives from remote sources,
+//! and images to proxy.
+
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use reqwest::redirect::Policy;
+
+use crate::config::{HttpConfig, ProxyConfig};
+
+impl HttpConfig {
+    /// Build the HTTP client described by this configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the proxy URL is invalid, or the CA bundle cannot be read or parsed.
+    pub fn build_client(&self) -> Result<reqwest::Client> {
+        let redirect = if self.max_redirects == 0 {
+            Policy::none()
+        } else {
+            Policy::limited(self.max_redirects)
+        };
+        self.build_client_with(redirect)
+    }
+
+    /// Build the HTTP client images are proxied with, which only follows redirects to URLs the proxy allows,
+    /// so a redirect from an allowed domain can't reach any other host
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the proxy URL is invalid, or the CA bundle cannot be read or parsed.
+    pub fn build_proxy_client(&self, proxy: &ProxyConfig) -> Result<reqwest::Client> {
+        let max_redirects = self.max_redirects;
+        let proxy = proxy.clone();
+        let redirect = Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                attempt.error(anyhow!("too many redirects"))
+            } else if proxy.allows(attempt.url()) {
+                attempt.follow()
+            } else {
+                let error = anyhow!("redirected to {}, which is not allowed", attempt.url());
+                attempt.error(error)
+            }
+        });
+        self.build_client_with(redirect)
+    }
+
+    /// Build an HTTP client with these settings, following redirects by the given policy
+    fn build_client_with(&self, redirect: Policy) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .timeout(Duration::from_secs(self.timeout))
+            .redirect(redirect)
+            .user_agent(&self.user_agent)
+            .danger_accept_invalid_certs(!self.tls_verify);
+
+        if let Some(proxy) = &self.proxy {
+            let proxy =
+                reqwest::Proxy::all(proxy.as_str()).map_err(|e| anyhow!("Invalid proxy: {e}"))?;
+            builder = builder.proxy(proxy);
+        }
+        if let Some(path) = &self.ca_bundle {
+            let pem = std::fs::read(path)
+                .map_err(|e| anyhow!("Failed to read CA bundle {}: {e}", path.display()))?;
+            let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| anyhow!("Invalid CA bundle {}: {e}", path.display()))?;
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        builder
+            .build()
+            .map_err(|e| anyhow!("Failed to build HTTP client: {e}"))
+    }
+}
+
+/// Read the body of a response, failing as soon as more than `max_size` bytes of it, if set, are received,
+/// rather than once all of it is buffered
+///
+/// # Errors
+///
+/// Returns an error if the body can't be read, or is larger than `max_size`.
+pub(crate) async fn read_body(
+    mut response: reqwest::Response,
+    max_size: Option<u64>,
+) -> Result<Vec<u8>> {
+    let too_large = |size: u64| max_size.is_some_and(|max| size > max);
+    if response.content_length().is_some_and(too_large) {
+        return Err(anyhow!(
+            "The body of {} bytes is larger than the limit of {} bytes",
+            response.content_length().unwrap_or_default(),
+            max_size.unwrap_or_default()
+        ));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| anyhow!("Failed to read the body of the response: {e}"))?
+    {
+        if too_large((body.len() + chunk.len()) as u64) {
+            return Err(anyhow!(
+                "The body is larger than the limit of {} bytes",
+                max_size.unwrap_or_default()
+            ));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_build_default_client() {
+        assert!(HttpConfig::default().build_client().is_ok());
+    }
+
+    #[test]
+    fn test_build_client_with_settings() {
+        let config = HttpConfig {
+            proxy: Some("http://proxy.example.com:8080".parse().unwrap()),
+            timeout: 5,
+            max_redirects: 0,
+            user_agent: "test".to_string(),
+            ca_bundle: None,
+            tls_verify: false,
+            ..HttpConfig::default()
+        };
+        assert!(config.build_client().is_ok());
+    }
+
+    #[test]
+    fn test_build_client_invalid_ca_bundle() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let invalid = temp_dir.path().join("ca.pem");
+        std::fs::write(&invalid, "-----BEGIN CERTIFICATE-----\nnot base64\n").unwrap();
+
+        for ca_bundle in [invalid, PathBuf::from("/nonexistent/ca.pem")] {
+            let config = HttpConfig {
+                ca_bundle: Some(ca_bundle),
+                ..HttpConfig::default()
+            };
+            assert!(config.build_client().is_err());
+        }
+    }
+}
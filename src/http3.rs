@@ -0,0 +1,246 @@
+//! Serving HTTP/3 over QUIC, on the UDP port of the same number as the TCP listener.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::{Context, Result};
+use http_body_util::BodyExt;
+use hyper::{Method, Response, body::Bytes};
+use rustls::pki_types::CertificateDer;
+use tokio::task::JoinHandle;
+
+use crate::cache::CacheBackend;
+use crate::public_url::RemoteAddr;
+use crate::state::ServerState;
+use crate::tls::{ClientCertificate, Secure};
+
+/// The protocol negotiated with ALPN for HTTP/3
+const ALPN_PROTOCOL: &[u8] = b"h3";
+
+/// The `H3_NO_ERROR` code connections are closed with when the server shuts down
+const NO_ERROR: u32 = 0x100;
+
+type RequestResolver = h3::server::RequestResolver<h3_quinn::Connection, Bytes>;
+
+/// A QUIC endpoint serving requests over HTTP/3, handled like the ones received over TCP
+#[derive(Debug)]
+pub struct Http3Listener {
+    endpoint: quinn::Endpoint,
+    accepting: JoinHandle<()>,
+}
+
+impl Http3Listener {
+    /// Start accepting HTTP/3 connections on the given address, secured with the given TLS configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the TLS configuration can't be used for QUIC, or the UDP socket can't be bound.
+    pub fn bind<C: CacheBackend + 'static>(
+        addr: SocketAddr,
+        mut tls: rustls::ServerConfig,
+        state: Arc<ServerState<C>>,
+    ) -> Result<Self> {
+        tls.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(tls)
+            .context("The TLS configuration can't be used for HTTP/3")?;
+        let endpoint =
+            quinn::Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(crypto)), addr)
+                .with_context(|| format!("Failed to bind the HTTP/3 listener to udp://{addr}"))?;
+
+        let accepting = tokio::spawn({
+            let endpoint = endpoint.clone();
+            async move {
+                while let Some(incoming) = endpoint.accept().await {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve_connection(incoming, state).await {
+                            tracing::error!("Failed to serve HTTP/3 connection: {e:#}");
+                        }
+                    });
+                }
+            }
+        });
+        Ok(Self {
+            endpoint,
+            accepting,
+        })
+    }
+
+    /// The address the listener is bound to
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address of the UDP socket can't be determined.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
+
+    /// Stop accepting connections, and close the open ones
+    pub async fn shutdown(self) {
+        self.accepting.abort();
+        self.endpoint
+            .close(quinn::VarInt::from_u32(NO_ERROR), b"shutting down");
+        self.endpoint.wait_idle().await;
+    }
+}
+
+/// Serve the requests of a QUIC connection, each in its own task
+async fn serve_connection<C: CacheBackend + 'static>(
+    incoming: quinn::Incoming,
+    state: Arc<ServerState<C>>,
+) -> Result<()> {
+    let connection = incoming.await?;
+    let addr = connection.remote_address();
+    let client = connection
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+        .and_then(|chain| ClientCertificate::from_chain(Some(&chain)));
+    let mut connection: h3::server::Connection<_, Bytes> =
+        h3::server::Connection::new(h3_quinn::Connection::new(connection)).await?;
+
+    loop {
+        match connection.accept().await {
+            Ok(Some(resolver)) => {
+                let (state, client) = (state.clone(), client.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = serve_request(resolver, addr, client, state).await {
+                        tracing::warn!("Failed to answer HTTP/3 request: {e:#}");
+                    }
+                });
+            }
+            Ok(None) => return Ok(()),
+            Err(e) if e.is_h3_no_error() => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Answer a request of a QUIC connection, streaming the body of the response
+async fn serve_request<C: CacheBackend>(
+    resolver: RequestResolver,
+    addr: SocketAddr,
+    client: Option<ClientCertificate>,
+    state: Arc<ServerState<C>>,
+) -> Result<()> {
+    let (mut req, mut stream) = resolver.resolve_request().await?;
+    req.extensions_mut().insert(RemoteAddr(addr));
+    req.extensions_mut().insert(Secure);
+    if let Some(client) = client {
+        req.extensions_mut().insert(client);
+    }
+    let head = req.method() == Method::HEAD;
+
+    let Ok(response) = crate::handle_request(req, state).await;
+    let (parts, mut body) = response.into_parts();
+    stream
+        .send_response(Response::from_parts(parts, ()))
+        .await?;
+    // unlike hyper, h3 leaves it to servers to not send the bodies of responses to HEAD requests
+    while !head && let Some(frame) = body.frame().await {
+        if let Ok(data) = frame?.into_data() {
+            stream.send_data(data).await?;
+        }
+    }
+    stream.finish().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use hyper::body::Buf;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::config::{Config, ImageSource};
+
+    /// Send a request to the given HTTP/3 server, trusting the given certificate
+    async fn get(
+        addr: SocketAddr,
+        cert: &rcgen::Certificate,
+        method: Method,
+        path: &str,
+    ) -> (Response<()>, Vec<u8>) {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert.der().clone()).unwrap();
+        let mut tls = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        tls.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(tls).unwrap();
+
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+        let connection = endpoint.connect(addr, "localhost").unwrap().await.unwrap();
+        let (mut driver, mut sender) = h3::client::new(h3_quinn::Connection::new(connection))
+            .await
+            .unwrap();
+        let driving =
+            tokio::spawn(async move { std::future::poll_fn(|cx| driver.poll_close(cx)).await });
+
+        let request = hyper::Request::builder()
+            .method(method)
+            .uri(format!("https://localhost:{}{path}", addr.port()))
+            .body(())
+            .unwrap();
+        let mut stream = sender.send_request(request).await.unwrap();
+        stream.finish().await.unwrap();
+        let response = stream.recv_response().await.unwrap();
+        let mut body = Vec::new();
+        while let Some(mut chunk) = stream.recv_data().await.unwrap() {
+            body.extend(chunk.copy_to_bytes(chunk.remaining()));
+        }
+
+        drop(sender);
+        driving.abort();
+        endpoint.close(quinn::VarInt::from_u32(NO_ERROR), b"done");
+        (response, body)
+    }
+
+    #[tokio::test]
+    async fn test_http3_listener() {
+        let rcgen::CertifiedKey { cert, signing_key } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let tls = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![cert.der().clone()],
+            rustls::pki_types::PrivateKeyDer::try_from(signing_key.serialize_der()).unwrap(),
+        )
+        .unwrap();
+
+        let mut config = Config::default();
+        config.server.sources = vec![ImageSource::Path(PathBuf::from("assets")).into()];
+        let state = Arc::new(ServerState::with_config(&config));
+        let listener =
+            Http3Listener::bind("127.0.0.1:0".parse().unwrap(), tls, state.clone()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (response, body) = get(addr, &cert, Method::GET, "/livez").await;
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(body, b"OK");
+
+        // the bodies of responses to HEAD requests aren't sent
+        let (response, body) = get(addr, &cert, Method::HEAD, "/livez").await;
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert!(body.is_empty());
+
+        let (response, body) = get(addr, &cert, Method::GET, "/unknown").await;
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        assert!(!body.is_empty());
+
+        listener.shutdown().await;
+    }
+}
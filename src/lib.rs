@@ -1,36 +1,127 @@
-use std::{convert::Infallible, fs, path::PathBuf, sync::Arc};
+#[cfg(feature = "remote-sources")]
+use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    convert::Infallible,
+    fs,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
 
 use anyhow::{Result, anyhow};
-use http_body_util::Full;
-use hyper::{Request, Response, body::Bytes, service::service_fn};
+use hyper::{Request, Response, service::service_fn};
 use hyper_util::{
     rt::{TokioExecutor, TokioIo},
     server::conn::auto,
 };
-use tokio::{
-    net::TcpListener,
-    sync::{RwLock, broadcast::Receiver},
-};
+use rand::distr::Distribution;
+use rand::seq::IndexedRandom;
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::{net::TcpListener, sync::broadcast::Receiver};
+use tracing::Instrument;
+#[cfg(feature = "remote-sources")]
 use url::Url;
 
-use crate::config::{Config, ImageSource};
-use crate::state::ServerState;
+use crate::filter::FileFilter;
+
+use crate::body::Body;
+use crate::cache::CacheBackend;
+use crate::config::{Config, ImageSource, ServerConfig, SourceConfig};
+use crate::notify::Event;
+use crate::placeholder::Placeholder;
+use crate::populate::{PopulateReport, SourcePopulateReport};
+use crate::proxy::ProxyError;
+use crate::public_url::{RemoteAddr, public_base_url};
+use crate::query::{
+    ListQuery, ListSort, PlaceholderQuery, ProxyQuery, RandomOrder, RandomQuery, SequentialQuery,
+    TransformQuery,
+};
+use crate::state::{SelectableImage, ServedImage, ServerState};
 use crate::termination::Interrupted;
 
+pub mod access;
+#[cfg(feature = "acme")]
+pub mod acme;
 pub mod cache;
+pub mod cli;
+pub mod completions;
 pub mod config;
+#[cfg(unix)]
+pub mod daemon;
+pub mod default_config;
 mod logging;
+pub mod observability;
 pub mod state;
 pub use logging::init_logging;
+pub mod archive;
+pub mod auth;
+pub mod body;
+pub mod breaker;
 pub mod env;
+pub mod feed;
+pub mod filter;
+pub mod font;
+#[cfg(feature = "remote-sources")]
+pub mod http;
+#[cfg(feature = "http3")]
+pub mod http3;
+pub mod listing;
+#[cfg(feature = "bench")]
+pub mod load_test;
+pub mod man;
+pub mod manifest;
+pub mod metrics;
+pub mod notify;
+pub mod openapi;
+pub mod phash;
+pub mod placeholder;
+pub mod populate;
+pub mod preview;
+pub mod proxy;
+pub mod public_url;
+pub mod query;
+pub mod ready_file;
+pub mod referer;
+#[cfg(windows)]
+pub mod service;
+pub mod signing;
+pub mod snapshot;
+pub mod static_files;
+pub mod stats;
+pub mod sticky;
 pub mod termination;
+pub mod tls;
+pub mod transform;
+pub mod validate;
+pub mod variants;
+pub mod version;
 
+/// Why remote sources fail to load when the server is built without the `remote-sources` feature
+#[cfg(not(feature = "remote-sources"))]
+pub const REMOTE_SOURCES_DISABLED: &str =
+    "Remote sources require the server to be built with the `remote-sources` feature";
+
+/// The file extensions of the images that are loaded, unless overridden by `server.allowed_extensions`
 pub const ALLOWED_IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif"];
 
+const MEBIBYTE: u64 = 1024 * 1024;
+/// How often the sources are re-scanned while no images are found in them, unless `server.rescan_interval` is set
+const EMPTY_RESCAN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+/// The size of an unlimited in-memory cache above which a warning is logged after populating it
+const LARGE_IN_MEMORY_CACHE: u64 = 1024 * MEBIBYTE;
+
 /// The main server structure
-pub struct ImageServer {
+///
+/// Generic over the cache backend of its state, which is chosen at runtime from the configuration by default.
+pub struct ImageServer<C = Box<dyn CacheBackend>> {
     pub config: Config,
-    pub state: Arc<RwLock<ServerState>>,
+    pub state: Arc<ServerState<C>>,
 }
 
 impl ImageServer {
@@ -39,7 +130,7 @@ impl ImageServer {
     pub fn new() -> Self {
         Self {
             config: Config::default(),
-            state: Arc::new(RwLock::new(ServerState::default())),
+            state: Arc::new(ServerState::default()),
         }
     }
 
@@ -47,150 +138,270 @@ impl ImageServer {
     #[must_use]
     pub fn with_config(config: Config) -> Self {
         Self {
-            state: Arc::new(RwLock::new(ServerState::with_config(&config))),
+            state: Arc::new(ServerState::with_config(&config)),
             config,
         }
     }
+}
 
-    /// Populate the cache with the configured images
+impl<C: CacheBackend + 'static> ImageServer<C> {
+    /// Create a new `ImageServer` instance with custom configuration, storing images in the given cache
     ///
-    /// # Errors
+    /// The cache settings of the configuration are not used to create a backend.
+    #[must_use]
+    pub fn with_cache(config: Config, cache: C) -> Self {
+        Self {
+            state: Arc::new(ServerState::with_cache(&config, cache)),
+            config,
+        }
+    }
+
+    /// Register a callback invoked after each image is served, including from collections,
+    /// with its key and what is known about it
     ///
-    /// Returns an error if the image file does not exist, is not a file, or has an unsupported extension.
-    pub async fn populate_cache(&self) {
-        // This method can be implemented to load images from configured sources
-        // and populate the cache. For now, it is a placeholder.
-        tracing::info!("Populating cache with configured images...");
+    /// Callbacks run on the thread serving the request, so they should return quickly,
+    /// e.g. by sending what they need to a channel.
+    #[must_use]
+    pub fn on_image_served(
+        self,
+        hook: impl Fn(&cache::CacheKey, &ServedImage) + Send + Sync + 'static,
+    ) -> Self {
+        let hook = Arc::new(hook);
+        for collection in self.state.collections.values() {
+            let hook = hook.clone();
+            collection
+                .serve_hooks
+                .push(move |key, image| hook(key, image));
+        }
+        self.state
+            .serve_hooks
+            .push(move |key, image| hook(key, image));
+        self
+    }
 
-        for source in &self.config.server.sources {
-            match source {
-                ImageSource::Url(url) => {
-                    tracing::info!("Loading image from URL: {url}");
-                    let key = cache::CacheKey::ImageUrl(url.clone());
-                    // fetch the image from the URL and store it in the cache
-                    match read_image_from_url(url).await {
-                        Ok(image) => {
-                            let set_result = self.state.write().await.cache.set(key, image);
-                            if let Err(err) = set_result {
-                                tracing::error!("Failed to store image in cache: {err}");
-                            }
-                        }
-                        Err(e) => {
-                            tracing::error!("Failed to read image from URL {url}: {e}");
-                        }
-                    }
-                }
-                ImageSource::Path(path) if path.is_file() => {
-                    let path = path.canonicalize().unwrap_or_else(|_| {
-                        tracing::warn!("Failed to canonicalize path: {}", path.display());
-                        path.clone()
-                    });
-                    if path.extension().is_some_and(|ext| {
-                        ALLOWED_IMAGE_EXTENSIONS.contains(&ext.to_string_lossy().as_ref())
-                    }) {
-                        tracing::info!("Loading image from file path: {}", path.display());
-                        // read the image file from the path and store it in the cache
-                        let Ok(image) = read_image_from_path(&path) else {
-                            tracing::error!("Failed to read image file: {}", path.display());
-                            continue;
-                        };
-                        let key = cache::CacheKey::ImagePath(path.clone());
-                        let set_result = self.state.write().await.cache.set(key, image);
-                        if let Err(err) = set_result {
-                            tracing::error!("Failed to store image in cache: {err}");
-                        }
-                    } else {
-                        tracing::warn!("Unsupported image file extension: {}", path.display());
-                    }
-                }
-                ImageSource::Path(path) if path.is_dir() => {
-                    let path = path.canonicalize().unwrap_or_else(|_| {
-                        tracing::warn!("Failed to canonicalize path: {}", path.display());
-                        path.clone()
-                    });
+    /// Populate the cache with the configured images, and the cache of each collection with its images
+    ///
+    /// The outcome of loading each source is recorded in the server state, and reported by `/health`,
+    /// and returned along with the time it took, with the sources of collections prefixed by their name, after a one-line summary of it is logged
+    /// and sent to the webhook, along with the sources that failed.
+    pub async fn populate_cache(&self) -> PopulateReport {
+        tracing::info!("Populating cache with configured images...");
+        let start = Instant::now();
+        #[cfg(not(feature = "perceptual-hash"))]
+        if self.config.server.dedup_threshold.is_some() {
+            tracing::warn!(
+                "A dedup threshold is configured, but the server was built without the `perceptual-hash` feature"
+            );
+        }
+        #[cfg(not(feature = "transforms"))]
+        if self
+            .config
+            .server
+            .sources
+            .iter()
+            .chain(self.config.collections.values().flat_map(|c| &c.sources))
+            .any(|source| source.caption.is_some())
+        {
+            tracing::warn!(
+                "Captions are configured for sources, but the server was built without the `transforms` feature"
+            );
+        }
+        #[cfg(not(feature = "transforms"))]
+        if self.config.server.auto_orient {
+            tracing::warn!(
+                "Auto-orientation is enabled, but the server was built without the `transforms` feature"
+            );
+        }
+        #[cfg(not(feature = "transforms"))]
+        if self.config.transforms.quality.is_some() {
+            tracing::warn!(
+                "A JPEG quality is configured, but the server was built without the `transforms` feature"
+            );
+        }
+        #[cfg(not(feature = "variants"))]
+        if !self.config.transforms.formats.is_empty() {
+            tracing::warn!(
+                "Variant formats are configured, but the server was built without the `variants` feature"
+            );
+        }
 
-                    tracing::info!("Loading images from directory: {}", path.display());
-                    // Read all image files in the directory and store them in the cache
-                    let mut state = self.state.write().await;
-                    walkdir::WalkDir::new(&path)
-                        .into_iter()
-                        .filter_map(Result::ok)
-                        .filter(|e| e.file_type().is_file())
-                        .filter(|e| {
-                            e.path()
-                                .extension()
-                                .and_then(|ext| ext.to_str())
-                                .is_some_and(|ext| ALLOWED_IMAGE_EXTENSIONS.contains(&ext))
-                        })
-                        .for_each(|entry| {
-                            let path = entry.path().to_path_buf();
-                            tracing::info!("Loading image from file: {}", path.display());
-                            // read the image file and store it in the cache
-                            match read_image_from_path(&path) {
-                                Ok(image) => {
-                                    let key = cache::CacheKey::ImagePath(path.clone());
-                                    let set_result = state.cache.set(key, image);
-                                    if let Err(err) = set_result {
-                                        tracing::error!("Failed to store image in cache: {err}");
-                                    }
-                                }
-                                Err(e) => {
-                                    tracing::error!(
-                                        "Failed to read image from path {}: {e}",
-                                        path.display(),
-                                    );
-                                }
-                            }
-                        });
-                }
-                ImageSource::Path(path) => {
-                    tracing::warn!("Unsupported image path: {}", path.display());
-                }
+        let mut report = PopulateReport {
+            sources: populate_sources(&self.state, &self.config.server.sources).await,
+            ..PopulateReport::default()
+        };
+        for (name, collection) in &self.state.collections {
+            for mut source in populate_sources(collection, &collection.config.server.sources).await
+            {
+                source.source = format!("{name}: {}", source.source);
+                report.sources.push(source);
             }
+            collection.populated.store(true, Ordering::Release);
+        }
+        report.elapsed = start.elapsed();
+        tracing::info!("{report}");
+        self.state.notify(&Event::populated(&report)).await;
+        for event in report.sources.iter().filter_map(Event::source_failed) {
+            self.state.notify(&event).await;
+        }
+
+        log_cache_size(&self.state, &self.config.cache, "cache");
+        for (name, collection) in &self.state.collections {
+            log_cache_size(
+                collection,
+                &collection.config.cache,
+                &format!("cache of the {name} collection"),
+            );
         }
+        self.state.populated.store(true, Ordering::Release);
+        report
     }
 
     /// Start the server
     ///
+    /// Requests are accepted once the cache is populated, or right away if `server.start_before_populate` is set,
+    /// while it is populated in the background.
+    ///
     /// # Errors
     ///
     /// Returns an error if the server fails to start or encounters an unexpected error.
     pub async fn start(&self, mut interrupt_rx: Receiver<Interrupted>) -> Result<()> {
         let addr = self.config.socket_addr()?;
-        let listener = TcpListener::bind(addr).await?;
-        tracing::info!("Server running on http://{addr}");
+        let (tls, renewing) = self.tls()?;
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        if let Some(path) = &self.config.server.ready_file {
+            ready_file::ReadyFile::clear(path)?;
+        }
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| StartupError::Bind(addr, e))?;
+        tracing::info!(
+            "Server running on {scheme}://{addr}{}",
+            self.config.server.base_path
+        );
         tracing::debug!("Configuration: {:?}", self.config);
 
-        // Populate the cache with images from configured sources
-        self.populate_cache().await;
-        if self.state.read().await.cache.size() == 0 {
-            tracing::error!("No images found in cache, please check your configuration");
-            return Err(anyhow!(
-                "No images found in cache, please check your configuration"
-            ));
+        self.config.server.check_offline()?;
+
+        // Populate the cache with images from configured sources, then keep it up to date
+        let mut source_tasks = Vec::new();
+        let mut populating = None;
+        if self.config.server.start_before_populate {
+            tracing::info!("Accepting requests while the cache is populated in the background");
+            let server = Self {
+                config: self.config.clone(),
+                state: self.state.clone(),
+            };
+            populating = Some(tokio::spawn(async move {
+                // without images, the server keeps running but isn't ready, and the sources are still refreshed
+                let _ = server.populate_and_check().await;
+                server.spawn_source_tasks()
+            }));
+        } else {
+            self.populate_and_check().await?;
+            source_tasks = self.spawn_source_tasks();
         }
+        let ready_file = self.config.server.ready_file.clone().map(|path| {
+            let state = self.state.clone();
+            tokio::spawn(async move {
+                ready_file::ReadyFile::create_when_ready(state, path)
+                    .await
+                    .inspect_err(|e| tracing::error!("{e}"))
+                    .ok()
+            })
+        });
+
+        let statsd_exporter = tokio::spawn({
+            let (config, state) = (self.config.metrics.clone(), self.state.clone());
+            async move {
+                if let Err(e) = metrics::run_statsd_exporter(config, state).await {
+                    tracing::error!("Failed to push metrics to StatsD: {e}");
+                }
+            }
+        });
 
-        let executor = auto::Builder::new(TokioExecutor::new());
+        self.state
+            .notify(&Event::Started {
+                address: format!("{scheme}://{addr}{}", self.config.server.base_path),
+                images: self.state.image_count(),
+            })
+            .await;
+
+        let mut executor = auto::Builder::new(TokioExecutor::new());
+        // larger request lines and headers are refused by hyper, with 414 or 431
+        let limits = &self.config.server.limits;
+        executor
+            .http1()
+            .max_buf_size(limits.max_header_size)
+            .max_headers(limits.max_headers);
+        executor
+            .http2()
+            .max_header_list_size(u32::try_from(limits.max_header_size).unwrap_or(u32::MAX));
         let graceful = hyper_util::server::graceful::GracefulShutdown::new();
 
+        // HTTP/3 is served on the UDP port of the same number, and advertised to clients connecting over TCP
+        #[cfg(feature = "http3")]
+        let http3 = self.bind_http3(listener.local_addr()?, tls.as_ref())?;
+        #[cfg(feature = "http3")]
+        let alt_svc = http3
+            .as_ref()
+            .map(|_| listener.local_addr().map(|addr| tls::alt_svc(addr.port())))
+            .transpose()?;
+        #[cfg(not(feature = "http3"))]
+        let alt_svc: Option<hyper::header::HeaderValue> = {
+            if self.config.tls.http3 {
+                tracing::warn!(
+                    "tls.http3 is set, but the server was built without the `http3` feature"
+                );
+            }
+            None
+        };
+        let acceptor = tls.map(tls::tcp_acceptor);
+
         loop {
             tokio::select! {
-                Ok((stream, _addr)) = listener.accept() => {
-                    let io = TokioIo::new(stream);
-
-                    // Clone state for the handler
-                    let state = self.state.clone();
-                    let service = service_fn(move |req| {
-                        handle_request(req, state.clone())
+                Ok((stream, addr)) = listener.accept() => {
+                    // Clone state for the handler, which attaches what's known of the connection to its requests
+                    let (state, alt_svc) = (self.state.clone(), alt_svc.clone());
+                    let service = move |extensions: hyper::http::Extensions| service_fn(move |mut req: Request<hyper::body::Incoming>| {
+                        req.extensions_mut().extend(extensions.clone());
+                        let (state, alt_svc) = (state.clone(), alt_svc.clone());
+                        async move {
+                            let mut response = handle_request(req, state).await?;
+                            if let Some(alt_svc) = alt_svc {
+                                response.headers_mut().insert(hyper::header::ALT_SVC, alt_svc);
+                            }
+                            Ok::<_, Infallible>(response)
+                        }
                     });
 
-                    // watch this connection
-                    let conn = executor.serve_connection(io, service);
-                    let fut = graceful.watch(conn.into_owned());
-
-                    // Spawn a new task to handle the connection
+                    // Spawn a new task to handle the connection, watched so it's closed gracefully,
+                    // after the TLS handshake if HTTPS is served
+                    let (executor, watcher, acceptor) =
+                        (executor.clone(), graceful.watcher(), acceptor.clone());
                     tokio::spawn(async move {
-                        if let Err(e) = fut.await {
+                        let mut extensions = hyper::http::Extensions::new();
+                        extensions.insert(RemoteAddr(addr));
+                        let served = match acceptor {
+                            Some(acceptor) => match acceptor.accept(stream).await {
+                                Ok(stream) => {
+                                    extensions.insert(tls::Secure);
+                                    if let Some(client) = tls::ClientCertificate::from_chain(stream.get_ref().1.peer_certificates()) {
+                                        extensions.insert(client);
+                                    }
+                                    let conn = executor.serve_connection(TokioIo::new(stream), service(extensions));
+                                    watcher.watch(conn.into_owned()).await
+                                }
+                                Err(e) => {
+                                    tracing::debug!("Failed the TLS handshake with {addr}: {e}");
+                                    return;
+                                }
+                            },
+                            None => {
+                                let conn = executor.serve_connection(TokioIo::new(stream), service(extensions));
+                                watcher.watch(conn.into_owned()).await
+                            }
+                        };
+                        if let Err(e) = served {
                             tracing::error!("Failed to serve connection: {e}");
                         }
                     });
@@ -204,9 +415,23 @@ impl ImageServer {
             };
         }
 
-        // Start the shutdown and wait for any existing connections to close
+        // the server is no longer ready once it shuts down, and dropping the ready file removes it
+        if let Some(ready_file) = ready_file {
+            ready_file.abort();
+            drop(ready_file.await);
+        }
+
+        // Start the shutdown and wait for any existing connections to close,
+        // then close the HTTP/3 connections
+        let closing = async {
+            graceful.shutdown().await;
+            #[cfg(feature = "http3")]
+            if let Some(http3) = http3 {
+                http3.shutdown().await;
+            }
+        };
         tokio::select! {
-            () = graceful.shutdown() => {
+            () = closing => {
                 tracing::info!("All connections gracefully closed");
             }
             () = tokio::time::sleep(std::time::Duration::from_secs(5)) => {
@@ -214,8 +439,167 @@ impl ImageServer {
             }
         }
 
+        statsd_exporter.abort();
+        if let Some(renewing) = renewing {
+            renewing.abort();
+        }
+        if let Some(populating) = populating {
+            // the tasks keeping the cache up to date only exist if it finished being populated
+            populating.abort();
+            if let Ok(tasks) = populating.await {
+                source_tasks.extend(tasks);
+            }
+        }
+        source_tasks.iter().for_each(tokio::task::JoinHandle::abort);
+        self.save_serve_counts();
+        self.state.notify(&Event::Shutdown).await;
+
+        Ok(())
+    }
+
+    /// The TLS configuration HTTPS is served with, if it is, and the task renewing the certificate obtained with ACME
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the certificate can't be loaded, or ACME is configured without the `acme` feature.
+    fn tls(
+        &self,
+    ) -> Result<(
+        Option<rustls::ServerConfig>,
+        Option<tokio::task::JoinHandle<()>>,
+    )> {
+        let config = &self.config.tls;
+        let Some(acme) = &config.acme else {
+            return Ok((tls::server_config(config)?, None));
+        };
+        #[cfg(feature = "acme")]
+        {
+            tls::server_config(config)?;
+            let manager = acme::CertificateManager::new(acme, &self.config.http)?;
+            let server_config = manager.server_config(config)?;
+            Ok((Some(server_config), Some(tokio::spawn(manager.run()))))
+        }
+        #[cfg(not(feature = "acme"))]
+        {
+            let _ = acme;
+            Err(anyhow!(
+                "tls.acme requires the server to be built with the `acme` feature"
+            ))
+        }
+    }
+
+    /// Start serving HTTP/3 on the given UDP address, if `tls.http3` is set and HTTPS is served
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP/3 listener can't be bound.
+    #[cfg(feature = "http3")]
+    fn bind_http3(
+        &self,
+        addr: std::net::SocketAddr,
+        tls: Option<&rustls::ServerConfig>,
+    ) -> Result<Option<http3::Http3Listener>> {
+        if !self.config.tls.http3 {
+            return Ok(None);
+        }
+        let Some(tls) = tls else {
+            tracing::warn!(
+                "tls.http3 is set, but HTTP/3 is only served along with HTTPS, with tls.cert and tls.key"
+            );
+            return Ok(None);
+        };
+        let listener = http3::Http3Listener::bind(addr, tls.clone(), self.state.clone())?;
+        tracing::info!("Serving HTTP/3 on udp://{addr}");
+        Ok(Some(listener))
+    }
+
+    /// Populate the caches with the configured images, checking that at least one image was found
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no images were found and `server.on_empty = "fail"`, and warns about collections
+    /// without images.
+    async fn populate_and_check(&self) -> Result<()> {
+        self.populate_cache().await;
+        for (name, collection) in &self.state.collections {
+            if collection.cache.size() == 0 {
+                tracing::warn!(
+                    "No images found in the {name} collection, please check its sources"
+                );
+            }
+        }
+        if self.state.cache.size() == 0 {
+            if self.config.server.on_empty != config::EmptyCachePolicy::Fail {
+                tracing::warn!(
+                    "No images found in cache, waiting for images to appear in the sources"
+                );
+                return Ok(());
+            }
+            tracing::error!("{}", StartupError::EmptyCache);
+            return Err(StartupError::EmptyCache.into());
+        }
         Ok(())
     }
+
+    /// Spawn the tasks that keep the caches of the server and each collection up to date with their sources
+    fn spawn_source_tasks(&self) -> Vec<tokio::task::JoinHandle<()>> {
+        let mut source_tasks = spawn_source_tasks(&self.state, &self.config);
+        for collection in self.state.collections.values() {
+            source_tasks.extend(spawn_source_tasks(collection, &collection.config));
+        }
+        source_tasks
+    }
+
+    /// Persist the per-image serve counters, of the server and each collection, if their cache is persistent
+    pub fn save_serve_counts(&self) {
+        self.state.save_serve_counts();
+        for collection in self.state.collections.values() {
+            collection.save_serve_counts();
+        }
+    }
+
+    /// Write a snapshot of the cache to an archive at the given path, returning how many images were written
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive cannot be written.
+    pub fn export_cache(&self, path: &Path) -> Result<usize> {
+        let count = self
+            .state
+            .cache
+            .export(path)
+            .map_err(|e| anyhow!("Failed to export the cache to {}: {e}", path.display()))?;
+        tracing::info!("Exported {count} image(s) to {}", path.display());
+        Ok(count)
+    }
+
+    /// Load a snapshot written by `export_cache` into the cache, returning how many images were loaded
+    ///
+    /// This is meant to be called before `start`, so that images fetched from URLs are only
+    /// downloaded again if they changed since the snapshot was taken.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive cannot be read or is malformed.
+    pub fn import_cache(&self, path: &Path) -> Result<usize> {
+        let count = self
+            .state
+            .cache
+            .import(path)
+            .map_err(|e| anyhow!("Failed to import the cache from {}: {e}", path.display()))?;
+        tracing::info!("Imported {count} image(s) from {}", path.display());
+        Ok(count)
+    }
+
+    /// The images in the cache, then in the cache of each collection, in the order they were stored
+    #[must_use]
+    pub fn list_images(&self) -> Vec<listing::ListedImage> {
+        let mut images = listing::list_images(&self.state, None);
+        for (name, collection) in &self.state.collections {
+            images.extend(listing::list_images(collection, Some(name)));
+        }
+        images
+    }
 }
 
 impl Default for ImageServer {
@@ -224,182 +608,2937 @@ impl Default for ImageServer {
     }
 }
 
-/// Read an image file from the given path and return it as a `CacheValue`
-///
-/// # Errors
-///
-/// Returns an error if the file does not exist, is not a file, or has an unsupported extension.
-pub fn read_image_from_path(path: &PathBuf) -> Result<cache::CacheValue> {
-    let path_display = path.display();
-    if !path.exists() || !path.is_file() {
-        return Err(anyhow!("Image file does not exist: {path_display}"));
-    }
-    let Some(ext) = path.extension().and_then(|ext| ext.to_str()) else {
-        return Err(anyhow!("Image file has no extension: {path_display}"));
-    };
-    if !ALLOWED_IMAGE_EXTENSIONS.contains(&ext) {
-        return Err(anyhow!(
-            "Unsupported image file extension: {}",
-            path.display()
-        ));
+/// Log how many bytes of images a cache holds once populated,
+/// and warn if an unlimited in-memory cache holds a lot of them
+fn log_cache_size<C: CacheBackend>(
+    state: &ServerState<C>,
+    config: &config::CacheConfig,
+    cache: &str,
+) {
+    let bytes = state.cache.bytes();
+    tracing::info!(
+        "The {} {cache} holds {} MiB of images",
+        state.cache.backend_type(),
+        bytes / MEBIBYTE
+    );
+    if config.backend == config::CacheBackendType::InMemory
+        && config.max_bytes.is_none()
+        && bytes >= LARGE_IN_MEMORY_CACHE
+    {
+        tracing::warn!(
+            "The in-memory {cache} holds {} MiB of images, all kept in memory. \
+             Set `max_bytes` to limit it, or use the `file_system` backend",
+            bytes / MEBIBYTE
+        );
     }
+}
 
-    let image_data = fs::read(path).map_err(|e| anyhow!("Failed to read image file: {e}"))?;
-    let content_type = mime_guess::from_path(path)
-        .first()
-        .ok_or_else(|| anyhow!("Failed to determine content type for image file: {path_display}"))?
-        .to_string();
-    Ok(cache::CacheValue {
-        data: image_data,
-        content_type,
-    })
+/// Load the images from the given sources into the cache, in order
+async fn populate_sources<C: CacheBackend>(
+    state: &ServerState<C>,
+    sources: &[SourceConfig],
+) -> Vec<SourcePopulateReport> {
+    let mut reports = Vec::new();
+    for (index, source) in sources.iter().enumerate() {
+        let outcome = populate_source(state, index, source, false).await;
+        reports.push(outcome.report(source));
+    }
+    reports
 }
 
-/// Fetch an image from a URL and return it as a `CacheValue`
-///
-/// # Errors
+/// Load the images from a single configured source into the cache
 ///
-/// Returns an error if the image cannot be fetched or if the content type is unsupported.
-pub async fn read_image_from_url(url: &Url) -> Result<cache::CacheValue> {
-    let response = reqwest::get(url.as_str())
-        .await
-        .map_err(|e| anyhow!("Failed to fetch image from URL: {e}"))?;
+/// The outcome is recorded in the server state, and reported by `/health`.
+async fn populate_source<C: CacheBackend>(
+    state: &ServerState<C>,
+    index: usize,
+    source: &SourceConfig,
+    incremental: bool,
+) -> SourceOutcome {
+    if state.config.server.offline && source.location.is_remote() {
+        tracing::info!("Skipping remote source in offline mode: {source}");
+        state.mark_source_skipped(&source.location);
+        return SourceOutcome {
+            offline: true,
+            ..SourceOutcome::default()
+        };
+    }
+    state.mark_source_refreshing(&source.location);
+    let server_config = state.config.server.clone();
 
-    if !response.status().is_success() {
-        return Err(anyhow!(
-            "Failed to fetch image, status: {}",
-            response.status()
-        ));
+    let mut outcome = SourceOutcome::default();
+    match &source.location {
+        #[cfg(not(feature = "remote-sources"))]
+        ImageSource::Url(_) | ImageSource::Manifest(_) | ImageSource::Feed(_) => {
+            tracing::error!(
+                "Skipping remote source, the server was built without the `remote-sources` feature: {source}"
+            );
+            outcome.record_error(REMOTE_SOURCES_DISABLED.to_string());
+        }
+        location if location.archive_format().is_some() => {
+            populate_archive(state, index, source, &mut outcome).await;
+        }
+        #[cfg(feature = "remote-sources")]
+        ImageSource::Url(url) => {
+            populate_url(state, index, url, source, &server_config, &mut outcome).await;
+        }
+        #[cfg(feature = "remote-sources")]
+        ImageSource::Manifest(manifest) => {
+            tracing::info!("Loading images from manifest: {manifest}");
+            let headers = source.request_headers();
+            let fetch = manifest::fetch_manifest(&state.http_client, manifest, &headers);
+            let urls = fetch_with_breaker(state, manifest, fetch).await;
+            populate_url_list(
+                state,
+                index,
+                "manifest",
+                urls,
+                source,
+                &server_config,
+                &mut outcome,
+            )
+            .await;
+        }
+        #[cfg(feature = "remote-sources")]
+        ImageSource::Feed(feed) => {
+            tracing::info!("Loading images from feed: {feed}");
+            let headers = source.request_headers();
+            let fetch = feed::fetch_feed(&state.http_client, feed, &headers);
+            let urls = fetch_with_breaker(state, feed, fetch).await;
+            populate_url_list(
+                state,
+                index,
+                "feed",
+                urls,
+                source,
+                &server_config,
+                &mut outcome,
+            )
+            .await;
+        }
+        ImageSource::Path(path) if path.is_file() => {
+            let path = path.canonicalize().unwrap_or_else(|_| {
+                tracing::warn!("Failed to canonicalize path: {}", path.display());
+                path.clone()
+            });
+            populate_file(state, index, &path, source, &server_config, &mut outcome).await;
+        }
+        ImageSource::Path(path) if path.is_dir() => {
+            let path = path.canonicalize().unwrap_or_else(|_| {
+                tracing::warn!("Failed to canonicalize path: {}", path.display());
+                path.clone()
+            });
+
+            tracing::info!("Loading images from directory: {}", path.display());
+            populate_directory(
+                state,
+                index,
+                &path,
+                source,
+                &server_config,
+                incremental,
+                &mut outcome,
+            )
+            .await;
+
+            if outcome.keys.is_empty() && outcome.last_error.is_none() {
+                outcome.last_error = Some("No images found in directory".to_string());
+            }
+        }
+        ImageSource::Path(path) => {
+            tracing::warn!("Unsupported image path: {}", path.display());
+            outcome.record_error("Unsupported image path".to_string());
+        }
     }
 
-    let content_type = response
-        .headers()
-        .get("Content-Type")
-        .and_then(|v| v.to_str().ok())
-        .ok_or_else(|| anyhow!("Failed to get Content-Type header from response"))?
-        .to_string();
+    state.record_source_outcome(
+        &source.location,
+        outcome.keys.len(),
+        outcome.last_error.clone(),
+    );
+    outcome
+}
 
-    if !ALLOWED_IMAGE_EXTENSIONS.contains(&content_type.split('/').next_back().unwrap_or("")) {
-        return Err(anyhow!("Unsupported image content type: {content_type}"));
+/// Load the image at a URL, of the given source, into the cache
+#[cfg(feature = "remote-sources")]
+async fn populate_url<C: CacheBackend>(
+    state: &ServerState<C>,
+    index: usize,
+    url: &Url,
+    source: &SourceConfig,
+    server_config: &ServerConfig,
+    outcome: &mut SourceOutcome,
+) {
+    tracing::debug!("Loading image from URL: {url}");
+    let key = cache::CacheKey::ImageUrl(url.clone());
+    // when refreshing, only download the image again if it changed since it was cached
+    let (client, validators) = {
+        let validators = state.cache.validators(&key).unwrap_or_default();
+        (state.http_client.clone(), validators)
+    };
+    // fetch the image from the URL and store it in the cache
+    let headers = source.request_headers();
+    let fetch = read_image_from_url_if_modified(
+        &client,
+        url,
+        &headers,
+        &server_config.allowed_extensions,
+        &validators,
+        None,
+    );
+    match fetch_with_breaker(state, url, fetch).await {
+        Ok(Some(image)) => {
+            if let Some(reason) = image_limits_violation(server_config, source, &image.data) {
+                outcome.record_skip(url, &reason);
+            } else {
+                store_loaded_image(state, index, key, image, outcome).await;
+            }
+        }
+        Ok(None) => {
+            tracing::debug!("Image at URL {url} is not modified, keeping the cached copy");
+            state.mark_fresh(&key);
+            outcome.keys.push(key);
+        }
+        Err(e) => {
+            if state.image_source_index(&key).is_some() {
+                // serve the copy cached before until a refresh succeeds, rather than dropping the image
+                tracing::warn!(
+                    "Failed to refresh image from URL {url}, keeping the stale cached copy: {e}"
+                );
+                state.mark_stale(&key);
+                outcome.keys.push(key);
+                outcome.record_error(format!("Failed to read image from URL: {e}"));
+            } else if let Some(image) = state.cache.spooled(&key) {
+                tracing::warn!(
+                    "Failed to read image from URL {url}, loading it from the spool: {e}"
+                );
+                store_loaded_image(state, index, key, image, outcome).await;
+            } else {
+                tracing::error!("Failed to read image from URL {url}: {e}");
+                observability::capture_message(&format!(
+                    "Failed to read image from URL {url}: {e}"
+                ));
+                outcome.record_error(format!("Failed to read image from URL: {e}"));
+            }
+        }
     }
+}
 
-    let data = response
-        .bytes()
-        .await
-        .map_err(|e| anyhow!("Failed to read image bytes from response: {e}"))?;
+/// Fetch from a URL, unless it is backed off or its host's circuit is open, recording whether the fetch failed
+#[cfg(feature = "remote-sources")]
+async fn fetch_with_breaker<T, C: CacheBackend>(
+    state: &ServerState<C>,
+    url: &Url,
+    fetch: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    state.breakers().check(url, std::time::Instant::now())?;
+    let result = fetch.await;
+    match &result {
+        Ok(_) => state.breakers().record_success(url),
+        Err(_) => state
+            .breakers()
+            .record_failure(url, std::time::Instant::now()),
+    }
+    result
+}
 
-    Ok(cache::CacheValue {
-        data: data.to_vec(),
-        content_type,
-    })
+/// Load the images listed by a manifest or feed source into the cache, up to the source's `max_images`
+#[cfg(feature = "remote-sources")]
+async fn populate_url_list<C: CacheBackend>(
+    state: &ServerState<C>,
+    index: usize,
+    kind: &str,
+    urls: Result<Vec<Url>>,
+    source: &SourceConfig,
+    server_config: &ServerConfig,
+    outcome: &mut SourceOutcome,
+) {
+    match urls {
+        Ok(urls) => {
+            for url in urls.iter().take(source.max_images.unwrap_or(usize::MAX)) {
+                populate_url(state, index, url, source, server_config, outcome).await;
+            }
+            if outcome.keys.is_empty() && outcome.last_error.is_none() {
+                outcome.last_error = Some(format!("No images found in {kind}"));
+            }
+        }
+        Err(e) => {
+            let location = &source.location;
+            tracing::error!("Failed to load {kind} {location}: {e}");
+            observability::capture_message(&format!("Failed to load {kind} {location}: {e}"));
+            outcome.record_error(format!("Failed to load {kind}: {e}"));
+        }
+    }
 }
 
-/// Handle incoming HTTP requests
-///
-/// # Errors
-///
-/// should be Infallible
-pub async fn handle_request(
-    req: Request<hyper::body::Incoming>,
-    state: Arc<RwLock<ServerState>>,
-) -> Result<Response<Full<Bytes>>, Infallible> {
-    match req.uri().path() {
-        "/" => Ok(Response::new(Full::new(Bytes::from(
-            "Welcome to the Random Image Server!",
-        )))),
-        "/health" => Ok(Response::new(Full::new(Bytes::from("OK")))),
-        "/random" => match handle_random_image(state).await {
-            Ok(response) => Ok(response),
-            Err(err) => {
-                tracing::error!("Failed to get random image: {err}");
-                let mut not_found = Response::new(Full::new(Bytes::from("Not Found")));
-                *not_found.status_mut() = hyper::StatusCode::NOT_FOUND;
-                Ok(not_found)
+/// Load the image file of a file source into the cache
+async fn populate_file<C: CacheBackend>(
+    state: &ServerState<C>,
+    index: usize,
+    path: &PathBuf,
+    source: &SourceConfig,
+    server_config: &ServerConfig,
+    outcome: &mut SourceOutcome,
+) {
+    let allowed_extensions = &server_config.allowed_extensions;
+    if !has_allowed_extension(path, allowed_extensions) {
+        tracing::warn!("Unsupported image file extension: {}", path.display());
+        outcome.record_error("Unsupported image file extension".to_string());
+    } else if let Some(reason) = file_limits_violation(server_config, source, path) {
+        outcome.record_skip(path.display(), &reason);
+    } else {
+        tracing::debug!("Loading image from file path: {}", path.display());
+        // read the image file from the path and store it in the cache
+        match read_image_from_path_with_extensions(path, allowed_extensions) {
+            Ok(image) => {
+                let key = cache::CacheKey::ImagePath(path.clone());
+                store_loaded_image(state, index, key, image, outcome).await;
             }
-        },
-        "/sequential" => match handle_sequential_image(state).await {
-            Ok(response) => Ok(response),
-            Err(err) => {
-                tracing::error!("Failed to get sequential image: {err}");
-                let mut not_found = Response::new(Full::new(Bytes::from("Not Found")));
-                *not_found.status_mut() = hyper::StatusCode::NOT_FOUND;
-                Ok(not_found)
+            Err(e) => {
+                tracing::error!("Failed to read image file: {}", path.display());
+                outcome.record_error(format!("Failed to read image file: {e}"));
             }
-        },
-        _ => {
-            let mut not_found = Response::new(Full::new(Bytes::from("Not Found")));
-            *not_found.status_mut() = hyper::StatusCode::NOT_FOUND;
-            Ok(not_found)
         }
     }
 }
 
-/// Handle random image serving
-///
-/// # Errors
+/// The most image files read from a directory source at once, before they are stored in the cache
+const DIRECTORY_BATCH_SIZE: usize = 64;
+
+/// Load the images in a directory source into the cache
 ///
-/// Returns an error if no images are configured or if the image cannot be found in the cache.
-pub async fn handle_random_image(state: Arc<RwLock<ServerState>>) -> Result<Response<Full<Bytes>>> {
-    let state = state.read().await;
+/// The directory is walked, and its images read in batches, on blocking threads, so that a large
+/// directory doesn't stall request handling. Each image is then stored on its own, so the cache is
+/// only ever locked for a single insert.
+async fn populate_directory<C: CacheBackend>(
+    state: &ServerState<C>,
+    index: usize,
+    path: &Path,
+    source: &SourceConfig,
+    server_config: &ServerConfig,
+    incremental: bool,
+    outcome: &mut SourceOutcome,
+) {
+    let filter = file_filter(server_config, outcome);
+    let walk = {
+        let (path, source) = (path.to_path_buf(), source.clone());
+        let server_config = server_config.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut skipped = Vec::new();
+            let allowed_extensions = &server_config.allowed_extensions;
+            let paths: Vec<PathBuf> =
+                image_files_in_directory(&path, &source, &filter, allowed_extensions)
+                    .filter(|path| {
+                        let violation = file_limits_violation(&server_config, &source, path);
+                        if let Some(reason) = violation.clone() {
+                            skipped.push((path.clone(), reason));
+                        }
+                        violation.is_none()
+                    })
+                    .take(source.max_images.unwrap_or(usize::MAX))
+                    .collect();
+            (paths, skipped)
+        })
+    };
+    let paths = match walk.await {
+        Ok((paths, skipped)) => {
+            for (path, reason) in skipped {
+                outcome.record_skip(path.display(), &reason);
+            }
+            paths
+        }
+        Err(e) => {
+            tracing::error!("Failed to walk directory {}: {e}", path.display());
+            outcome.record_error(format!("Failed to walk directory: {e}"));
+            return;
+        }
+    };
 
-    // get a random image from the cache
-    state.cache.get_random().map_or_else(
-        || {
-            Err(anyhow!(
-                "Failed to retrieve a random image, perhaps no images are configured"
-            ))
-        },
-        |image| {
-            let body = Full::new(Bytes::from(image.data));
-            let mut response = Response::new(body);
-            *response.status_mut() = hyper::StatusCode::OK;
-            response
-                .headers_mut()
-                .insert(hyper::header::CONTENT_TYPE, image.content_type.parse()?);
-            Ok(response)
-        },
-    )
-}
+    // the images new to the cache are withheld at once, if the directory is being reloaded
+    state.withhold_new(
+        &paths
+            .iter()
+            .map(|path| cache::CacheKey::ImagePath(path.clone()))
+            .collect::<Vec<_>>(),
+    );
 
-/// Handle sequential image serving
-///
-/// # Errors
-///
-/// Returns an error if no images are configured or if the image cannot be found in the cache.
-pub async fn handle_sequential_image(
-    state: Arc<RwLock<ServerState>>,
-) -> Result<Response<Full<Bytes>>> {
-    let mut state = state.write().await;
+    let mut paths = paths.into_iter().peekable();
+    while paths.peek().is_some() {
+        let mut batch = Vec::with_capacity(DIRECTORY_BATCH_SIZE);
+        for path in paths.by_ref() {
+            let key = cache::CacheKey::ImagePath(path.clone());
+            if incremental && state.image_source_index(&key) == Some(index) {
+                // already loaded by a previous scan
+                outcome.keys.push(key);
+                continue;
+            }
+            batch.push(path);
+            if batch.len() == DIRECTORY_BATCH_SIZE {
+                break;
+            }
+        }
 
-    if state.cache.is_empty() {
-        return Err(anyhow!("No image sources configured"));
+        // Read the image files of the batch, then store them in the cache
+        let allowed_extensions = server_config.allowed_extensions.clone();
+        let read = tokio::task::spawn_blocking(move || {
+            batch
+                .into_iter()
+                .map(|path| {
+                    tracing::debug!("Loading image from file: {}", path.display());
+                    let image = read_image_from_path_with_extensions(&path, &allowed_extensions);
+                    (path, image)
+                })
+                .collect::<Vec<_>>()
+        });
+        let images = match read.await {
+            Ok(images) => images,
+            Err(e) => {
+                tracing::error!("Failed to read images from {}: {e}", path.display());
+                outcome.record_error(format!("Failed to read images: {e}"));
+                continue;
+            }
+        };
+        for (path, image) in images {
+            match image {
+                Ok(image) => {
+                    let key = cache::CacheKey::ImagePath(path);
+                    store_loaded_image(state, index, key, image, outcome).await;
+                }
+                Err(e) => {
+                    tracing::error!("Failed to read image from path {}: {e}", path.display());
+                    outcome.record_error(format!(
+                        "Failed to read image from path {}: {e}",
+                        path.display()
+                    ));
+                }
+            }
+        }
     }
+}
 
-    let current_index = state.current_index % state.cache.size();
-    let source = state.cache.keys()[current_index].clone();
-    state.current_index = (current_index + 1) % state.cache.size();
+/// Load the images in an archive source, a local or remote zip or tar file, into the cache
+async fn populate_archive<C: CacheBackend>(
+    state: &ServerState<C>,
+    index: usize,
+    source: &SourceConfig,
+    outcome: &mut SourceOutcome,
+) {
+    let Some(format) = source.location.archive_format() else {
+        return;
+    };
+    tracing::info!("Loading images from archive: {}", source.location);
+    let result = match &source.location {
+        #[cfg(feature = "remote-sources")]
+        ImageSource::Url(url) => {
+            let headers = source.request_headers();
+            let max_size = state.config.http.max_archive_size;
+            let fetch = archive::fetch_archive(&state.http_client, url, &headers, max_size);
+            match fetch_with_breaker(state, url, fetch).await {
+                Ok(data) => {
+                    let url = url.clone();
+                    let key = move |entry: &Path| {
+                        let mut url = url.clone();
+                        url.set_fragment(Some(&entry.to_string_lossy()));
+                        cache::CacheKey::ImageUrl(url)
+                    };
+                    let reader = std::io::Cursor::new(data);
+                    extract_archive(state, index, reader, format, key, source, outcome).await
+                }
+                Err(e) => Err(e),
+            }
+        }
+        ImageSource::Path(path) => {
+            let path = path.canonicalize().unwrap_or_else(|_| path.clone());
+            match fs::File::open(&path) {
+                Ok(file) => {
+                    let key = move |entry: &Path| cache::CacheKey::ImagePath(path.join(entry));
+                    let reader = std::io::BufReader::new(file);
+                    extract_archive(state, index, reader, format, key, source, outcome).await
+                }
+                Err(e) => Err(anyhow!("Failed to open archive: {e}")),
+            }
+        }
+        _ => Ok(()),
+    };
 
-    // Fetch the image from the cache or source
-    if let Some(image) = state.cache.get(source.clone()) {
-        let body = Full::new(Bytes::from(image.data));
-        let mut response = Response::new(body);
-        *response.status_mut() = hyper::StatusCode::OK;
-        response
-            .headers_mut()
-            .insert(hyper::header::CONTENT_TYPE, image.content_type.parse()?);
-        Ok(response)
-    } else {
-        state.cache.remove(&source);
-        drop(state);
-        Err(anyhow!("Image not found in cache"))
+    match result {
+        Err(e) => {
+            let location = &source.location;
+            tracing::error!("Failed to load archive {location}: {e}");
+            observability::capture_message(&format!("Failed to load archive {location}: {e}"));
+            outcome.record_error(format!("Failed to load archive: {e}"));
+        }
+        Ok(()) if outcome.keys.is_empty() && outcome.last_error.is_none() => {
+            outcome.last_error = Some("No images found in archive".to_string());
+        }
+        Ok(()) => {}
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::termination::create_termination;
-    use pretty_assertions::assert_eq;
+/// What became of an image in an archive, as it was read
+enum ArchiveImage {
+    Loaded(cache::CacheKey, cache::CacheValue),
+    Skipped(cache::CacheKey, String),
+    Failed(cache::CacheKey, anyhow::Error),
+}
+
+/// Read the images in an archive into the cache, keyed by their path within the archive
+///
+/// The archive is read on a blocking thread, which hands each image over to be stored as soon as it's read,
+/// so at most `DIRECTORY_BATCH_SIZE` of them are held in memory at once.
+async fn extract_archive<C: CacheBackend>(
+    state: &ServerState<C>,
+    index: usize,
+    reader: impl std::io::Read + std::io::Seek + Send + 'static,
+    format: archive::ArchiveFormat,
+    key: impl Fn(&Path) -> cache::CacheKey + Send + 'static,
+    source: &SourceConfig,
+    outcome: &mut SourceOutcome,
+) -> Result<()> {
+    let server_config = state.config.server.clone();
+    let filter = file_filter(&server_config, outcome);
+    let source = source.clone();
+    let (sender, mut images) = tokio::sync::mpsc::channel(DIRECTORY_BATCH_SIZE);
+    let read = tokio::task::spawn_blocking(move || {
+        let max_images = source.max_images.unwrap_or(usize::MAX);
+        let mut loaded = 0;
+        archive::for_each_image(
+            reader,
+            format,
+            &filter,
+            &server_config.allowed_extensions,
+            |path, size, reader| {
+                if loaded >= max_images {
+                    return ControlFlow::Break(());
+                }
+                let key = key(path);
+                let image = if server_config.allows_file_size(size) {
+                    match archive::read_image(path, size, server_config.max_file_size, reader) {
+                        Ok(image) => {
+                            match image_limits_violation(&server_config, &source, &image.data) {
+                                Some(reason) => ArchiveImage::Skipped(key, reason),
+                                None => {
+                                    loaded += 1;
+                                    ArchiveImage::Loaded(key, image)
+                                }
+                            }
+                        }
+                        Err(e) => ArchiveImage::Failed(key, e),
+                    }
+                } else {
+                    // skipped by the size in its header, without reading it
+                    let reason =
+                        format!("file size of {size} bytes is outside the configured limits");
+                    ArchiveImage::Skipped(key, reason)
+                };
+                if sender.blocking_send(image).is_err() {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            },
+        )
+    });
+
+    let mut batch = Vec::with_capacity(DIRECTORY_BATCH_SIZE);
+    while images.recv_many(&mut batch, DIRECTORY_BATCH_SIZE).await > 0 {
+        // the images new to the cache are withheld a batch at a time, if the archive is being reloaded
+        let keys: Vec<cache::CacheKey> = batch
+            .iter()
+            .filter_map(|image| match image {
+                ArchiveImage::Loaded(key, _) => Some(key.clone()),
+                _ => None,
+            })
+            .collect();
+        state.withhold_new(&keys);
+        for image in batch.drain(..) {
+            match image {
+                ArchiveImage::Loaded(key, image) => {
+                    tracing::debug!("Loading image from archive: {key}");
+                    store_loaded_image(state, index, key, image, outcome).await;
+                }
+                ArchiveImage::Skipped(key, reason) => outcome.record_skip(&key, &reason),
+                ArchiveImage::Failed(key, e) => {
+                    tracing::error!("Failed to read image {key}: {e}");
+                    outcome.record_error(e.to_string());
+                }
+            }
+        }
+    }
+    read.await
+        .map_err(|e| anyhow!("Failed to read archive: {e}"))?
+}
+
+/// The include/exclude filter of the server, or one allowing every file if it's invalid
+fn file_filter(server_config: &ServerConfig, outcome: &mut SourceOutcome) -> FileFilter {
+    match server_config.file_filter() {
+        Ok(filter) => filter,
+        Err(e) => {
+            tracing::error!("Invalid file filter: {e}");
+            outcome.record_error(e.to_string());
+            FileFilter::default()
+        }
+    }
+}
+
+/// Periodically reload a configured source, every `refresh_interval` seconds
+///
+/// Images that were previously loaded from the source, but are no longer found in it, are removed from the cache.
+/// Runs until the task is aborted.
+async fn refresh_source<C: CacheBackend>(
+    state: Arc<ServerState<C>>,
+    index: usize,
+    source: SourceConfig,
+    refresh_interval: u64,
+) {
+    let period = std::time::Duration::from_secs(refresh_interval.max(1));
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+        tracing::info!("Refreshing image source: {source}");
+        reload_sources(&state, &[(index, &source)], false).await;
+    }
+}
+
+/// Spawn the tasks that keep the cache up to date with the configured sources: refreshing the sources
+/// with a `refresh_interval`, re-scanning the others, and revalidating the cache, as configured
+fn spawn_source_tasks<C: CacheBackend + 'static>(
+    state: &Arc<ServerState<C>>,
+    config: &Config,
+) -> Vec<tokio::task::JoinHandle<()>> {
+    let mut tasks: Vec<_> = config
+        .server
+        .sources
+        .iter()
+        .enumerate()
+        .filter(|(_, source)| !(config.server.offline && source.location.is_remote()))
+        .filter_map(|(index, source)| {
+            let refresh_interval = source.refresh_interval?;
+            Some(tokio::spawn(refresh_source(
+                state.clone(),
+                index,
+                source.clone(),
+                refresh_interval,
+            )))
+        })
+        .collect();
+
+    if let Some(rescan_interval) = config.server.rescan_interval {
+        tasks.push(tokio::spawn(rescan_sources(
+            state.clone(),
+            config.server.sources.clone(),
+            rescan_interval,
+        )));
+    }
+    if config.server.rescan_interval.is_none()
+        && config.server.on_empty != config::EmptyCachePolicy::Fail
+        && state.image_count() == 0
+    {
+        tasks.push(tokio::spawn(wait_for_images(
+            state.clone(),
+            config.server.sources.clone(),
+        )));
+    }
+    if let Some(revalidate_interval) = config.cache.revalidate_interval {
+        tasks.push(tokio::spawn(revalidate_cache(
+            state.clone(),
+            revalidate_interval,
+            config.cache.revalidate_rate,
+        )));
+    }
+    tasks
+}
+
+/// Periodically re-scan the configured sources that have no `refresh_interval` of their own, every `rescan_interval`
+///
+/// Re-scans are incremental: images already loaded from a directory are kept without being read again,
+/// new images are added, and images no longer found are removed from the cache.
+/// Runs until the task is aborted.
+async fn rescan_sources<C: CacheBackend>(
+    state: Arc<ServerState<C>>,
+    sources: Vec<SourceConfig>,
+    rescan_interval: std::time::Duration,
+) {
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + rescan_interval,
+        rescan_interval,
+    );
+    loop {
+        interval.tick().await;
+        tracing::info!("Re-scanning image sources");
+        let sources: Vec<(usize, &SourceConfig)> = sources
+            .iter()
+            .enumerate()
+            .filter(|(_, source)| source.refresh_interval.is_none())
+            .collect();
+        reload_sources(&state, &sources, true).await;
+    }
+}
+
+/// Re-scan the configured sources every [`EMPTY_RESCAN_INTERVAL`] until images are found in them,
+/// for servers that started without images, and have no `rescan_interval` to find them with
+async fn wait_for_images<C: CacheBackend>(state: Arc<ServerState<C>>, sources: Vec<SourceConfig>) {
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + EMPTY_RESCAN_INTERVAL,
+        EMPTY_RESCAN_INTERVAL,
+    );
+    while state.image_count() == 0 {
+        interval.tick().await;
+        tracing::info!("Re-scanning image sources for images");
+        let sources: Vec<(usize, &SourceConfig)> = sources.iter().enumerate().collect();
+        reload_sources(&state, &sources, true).await;
+    }
+    tracing::info!("Found {} image(s), serving them", state.image_count());
+}
+
+/// Periodically re-verify every cached image against its hash, every `revalidate_interval`,
+/// checking at most `rate` images per second
+///
+/// Corrupted images are removed from the cache, and the sources they were loaded from are loaded again
+/// at the end of each pass, so they are replaced before a client requests them.
+/// Runs until the task is aborted.
+async fn revalidate_cache<C: CacheBackend>(
+    state: Arc<ServerState<C>>,
+    revalidate_interval: std::time::Duration,
+    rate: u32,
+) {
+    let delay = std::time::Duration::from_secs(1) / rate.max(1);
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + revalidate_interval,
+        revalidate_interval,
+    );
+    loop {
+        interval.tick().await;
+        let corrupted = revalidate_images(&state, delay).await;
+        let sources: Vec<(usize, &SourceConfig)> = corrupted
+            .into_iter()
+            .filter_map(|index| {
+                let source = state.config.server.sources.get(index)?;
+                tracing::info!("Reloading image source with corrupted images: {source}");
+                Some((index, source))
+            })
+            .collect();
+        reload_sources(&state, &sources, true).await;
+    }
+}
+
+/// Re-verify every cached image against its hash, waiting `delay` between images,
+/// and remove the corrupted ones, returning the indices of the sources they were loaded from
+async fn revalidate_images<C: CacheBackend>(
+    state: &ServerState<C>,
+    delay: std::time::Duration,
+) -> BTreeSet<usize> {
+    let mut corrupted = BTreeSet::new();
+    let keys: Vec<cache::CacheKey> = state.cache.keys().collect();
+    tracing::debug!("Revalidating {} cached images", keys.len());
+    for key in keys {
+        tokio::time::sleep(delay).await;
+        if state.cache.revalidate(&key) {
+            continue;
+        }
+        tracing::warn!("Cached image failed its integrity check: {key}");
+        if let Some(index) = state.remove_image(&key) {
+            corrupted.insert(index);
+        }
+    }
+    corrupted
+}
+
+/// Load sources again, removing the images that were previously loaded from them but are no longer found in them
+///
+/// The new images are only served once every source is loaded, at the same time as the removed images stop
+/// being served, so requests never see the sources half-reloaded.
+async fn reload_sources<C: CacheBackend>(
+    state: &ServerState<C>,
+    sources: &[(usize, &SourceConfig)],
+    incremental: bool,
+) {
+    let mut reload = state.begin_reload();
+    for &(index, source) in sources {
+        let outcome = populate_source(state, index, source, incremental).await;
+        reload.record(index, &outcome.keys);
+    }
+    reload.publish();
+}
+
+/// The outcome of loading the images from a single source
+#[derive(Debug, Default)]
+struct SourceOutcome {
+    /// The keys of the images loaded into the cache
+    keys: Vec<cache::CacheKey>,
+    /// The most recent error encountered while loading the source
+    last_error: Option<String>,
+    /// The number of errors encountered while loading the source
+    errors: usize,
+    /// The number of images skipped for being outside the configured limits, or near-duplicates
+    skipped: usize,
+    /// Whether the source was skipped, because it's remote and the server is offline
+    offline: bool,
+}
+
+impl SourceOutcome {
+    /// Record an error encountered while loading an image
+    fn record_error(&mut self, err: String) {
+        self.last_error = Some(err);
+        self.errors += 1;
+    }
+
+    /// Summarize the outcome of loading the given source
+    fn report(&self, source: &SourceConfig) -> SourcePopulateReport {
+        SourcePopulateReport {
+            source: source.to_string(),
+            images: self.keys.len(),
+            skipped: self.skipped,
+            errors: self.errors,
+            last_error: self.last_error.clone(),
+            offline: self.offline,
+        }
+    }
+
+    /// Record that an image was skipped for being outside the configured limits, or a near-duplicate
+    fn record_skip(&mut self, image: impl std::fmt::Display, reason: &str) {
+        tracing::debug!("Skipping image {image}: {reason}");
+        self.skipped += 1;
+    }
+
+    /// Record the result of storing a single image in the cache
+    fn record_store(&mut self, key: cache::CacheKey, result: Result<(), String>) {
+        match result {
+            Ok(()) => self.keys.push(key),
+            Err(err) => {
+                tracing::error!("Failed to store image in cache: {err}");
+                self.record_error(format!("Failed to store image in cache: {err}"));
+            }
+        }
+    }
+}
+
+/// Store a loaded image in the cache, unless it's a near-duplicate of an image already in it
+///
+/// Near-duplicates are only detected if `server.dedup_threshold` is set, and the server was
+/// built with the `perceptual-hash` feature. The preview of the image is recorded if the server
+/// was built with the `blurhash` feature. JPEGs are rotated upright first if `server.auto_orient` is set,
+/// and the server was built with the `transforms` feature. Variants of JPEGs and PNGs are encoded in each of
+/// `transforms.formats` and stored in the variant cache, if the server was built with the `variants` feature.
+///
+/// All of that decoding and encoding is done on blocking threads, so it doesn't hold up the async workers.
+async fn store_loaded_image<C: CacheBackend>(
+    state: &ServerState<C>,
+    index: usize,
+    key: cache::CacheKey,
+    image: cache::CacheValue,
+    outcome: &mut SourceOutcome,
+) {
+    let auto_orient = cfg!(feature = "transforms") && state.config.server.auto_orient;
+    let image_key = key.clone();
+    let hashed = tokio::task::spawn_blocking(move || {
+        let image = if auto_orient {
+            match transform::auto_orient(&image) {
+                Ok(oriented) => oriented.unwrap_or(image),
+                Err(err) => {
+                    tracing::warn!("Failed to orient {image_key}, storing it as it is: {err}");
+                    image
+                }
+            }
+        } else {
+            image
+        };
+        let hash = phash::perceptual_hash(&image.data);
+        (image, hash)
+    });
+    let Some((image, hash)) = processed(hashed.await, &key, outcome) else {
+        return;
+    };
+    if let (Some(hash), Some(threshold)) = (hash, state.config.server.dedup_threshold)
+        && let Some(original) = state.near_duplicate(&key, hash, threshold)
+    {
+        outcome.record_skip(&key, &format!("near-duplicate of {original}"));
+        return;
+    }
+
+    let formats = state.config.transforms.formats.clone();
+    let image_key = key.clone();
+    let encoded = tokio::task::spawn_blocking(move || {
+        let preview = preview::preview(&image.data);
+        let variants = encode_variants(&formats, &image_key, &image);
+        (image, preview, variants)
+    });
+    let Some((image, preview, variants)) = processed(encoded.await, &key, outcome) else {
+        return;
+    };
+    let set_result = state.store_image(index, key.clone(), image);
+    if set_result.is_ok() {
+        let id = state.image_id(&key);
+        for (format, variant) in variants {
+            if let Err(e) = state
+                .variant_cache
+                .set(variants::variant_key(&key, &id, format), variant)
+            {
+                tracing::warn!(
+                    "Failed to store the {} variant of {key}: {e}",
+                    format.extension()
+                );
+            }
+        }
+        if let Some(hash) = hash {
+            state.set_image_hash(key.clone(), hash);
+        }
+        if let Some(preview) = preview {
+            state.set_image_preview(key.clone(), preview);
+        }
+    }
+    outcome.record_store(key, set_result);
+}
+
+/// The result of processing a loaded image on a blocking thread, recording an error if the processing panicked
+fn processed<T>(
+    result: Result<T, tokio::task::JoinError>,
+    key: &cache::CacheKey,
+    outcome: &mut SourceOutcome,
+) -> Option<T> {
+    result
+        .inspect_err(|e| {
+            tracing::error!("Failed to process image {key}: {e}");
+            outcome.record_error(format!("Failed to process image: {e}"));
+        })
+        .ok()
+}
+
+/// Encode the variants of an image in each of the given formats (`transforms.formats`) it has one in,
+/// if the server was built with the `variants` feature
+fn encode_variants(
+    formats: &[config::VariantFormat],
+    key: &cache::CacheKey,
+    image: &cache::CacheValue,
+) -> Vec<(config::VariantFormat, cache::CacheValue)> {
+    if !cfg!(feature = "variants") {
+        return Vec::new();
+    }
+    formats
+        .iter()
+        .filter_map(|&format| match variants::encode(image, format) {
+            Ok(variant) => variant.map(|variant| (format, variant)),
+            Err(err) => {
+                tracing::warn!(
+                    "Failed to encode the {} variant of {key}: {err}",
+                    format.extension()
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+/// Why an image should be skipped for being outside the configured size or dimension limits, if it should be
+///
+/// The dimensions are only determined if the source has dimension limits.
+fn limits_violation(
+    server_config: &ServerConfig,
+    source: &SourceConfig,
+    size: u64,
+    dimensions: impl FnOnce() -> imagesize::ImageResult<imagesize::ImageSize>,
+) -> Option<String> {
+    if !server_config.allows_file_size(size) {
+        return Some(format!(
+            "file size of {size} bytes is outside the configured limits"
+        ));
+    }
+    if !source.has_dimension_limits() {
+        return None;
+    }
+    match dimensions() {
+        Ok(dimensions) => {
+            let width = u64::try_from(dimensions.width).unwrap_or(u64::MAX);
+            let height = u64::try_from(dimensions.height).unwrap_or(u64::MAX);
+            (!source.allows_dimensions(width, height)).then(|| {
+                format!("dimensions of {width}x{height} are outside the configured limits")
+            })
+        }
+        Err(e) => Some(format!("dimensions could not be determined: {e}")),
+    }
+}
+
+/// Same as `limits_violation`, for an image already read into memory
+pub(crate) fn image_limits_violation(
+    server_config: &ServerConfig,
+    source: &SourceConfig,
+    data: &[u8],
+) -> Option<String> {
+    limits_violation(server_config, source, data.len() as u64, || {
+        imagesize::blob_size(data)
+    })
+}
+
+/// Same as `limits_violation`, for an image file, reading only its metadata and header
+pub(crate) fn file_limits_violation(
+    server_config: &ServerConfig,
+    source: &SourceConfig,
+    path: &Path,
+) -> Option<String> {
+    let size = fs::metadata(path).map_or(0, |metadata| metadata.len());
+    limits_violation(server_config, source, size, || imagesize::size(path))
+}
+
+/// Read an image file from the given path and return it as a `CacheValue`
+///
+/// # Errors
+///
+/// Returns an error if the file does not exist, is not a file, or has an unsupported extension.
+pub fn read_image_from_path(path: &PathBuf) -> Result<cache::CacheValue> {
+    read_image_from_path_with_extensions(path, ALLOWED_IMAGE_EXTENSIONS)
+}
+
+/// Read an image file from the given path, if it has one of the given extensions
+///
+/// # Errors
+///
+/// Returns an error if the file does not exist, is not a file, or has an unsupported extension.
+pub fn read_image_from_path_with_extensions(
+    path: &PathBuf,
+    allowed_extensions: &[impl AsRef<str>],
+) -> Result<cache::CacheValue> {
+    let path_display = path.display();
+    if !path.exists() || !path.is_file() {
+        return Err(anyhow!("Image file does not exist: {path_display}"));
+    }
+    if path.extension().is_none() {
+        return Err(anyhow!("Image file has no extension: {path_display}"));
+    }
+    if !has_allowed_extension(path, allowed_extensions) {
+        return Err(anyhow!(
+            "Unsupported image file extension: {}",
+            path.display()
+        ));
+    }
+
+    let image_data = fs::read(path).map_err(|e| anyhow!("Failed to read image file: {e}"))?;
+    let content_type = mime_guess::from_path(path)
+        .first()
+        .ok_or_else(|| anyhow!("Failed to determine content type for image file: {path_display}"))?
+        .to_string();
+    Ok(cache::CacheValue {
+        data: image_data,
+        content_type,
+        validators: cache::Validators::default(),
+    })
+}
+
+/// Whether the given path has one of the allowed extensions, ignoring case
+#[must_use]
+pub fn has_allowed_extension(path: &Path, allowed_extensions: &[impl AsRef<str>]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            allowed_extensions
+                .iter()
+                .any(|allowed| allowed.as_ref().eq_ignore_ascii_case(ext))
+        })
+}
+
+/// Whether the given content type is an image type with one of the allowed extensions
+#[must_use]
+pub fn has_allowed_content_type(
+    content_type: &str,
+    allowed_extensions: &[impl AsRef<str>],
+) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    essence.starts_with("image/")
+        && mime_guess::get_mime_extensions_str(essence).is_some_and(|extensions| {
+            extensions.iter().any(|ext| {
+                allowed_extensions
+                    .iter()
+                    .any(|allowed| allowed.as_ref().eq_ignore_ascii_case(ext))
+            })
+        })
+}
+
+/// Find the image files in a directory that pass the filter and have one of the allowed extensions
+///
+/// Subdirectories are searched up to the source's `search_depth`, and symlinks are skipped
+/// unless the source has `follow_symlinks` set.
+pub fn image_files_in_directory(
+    path: &Path,
+    source: &SourceConfig,
+    filter: &FileFilter,
+    allowed_extensions: &[String],
+) -> impl Iterator<Item = PathBuf> {
+    let walker = walkdir::WalkDir::new(path).follow_links(source.follow_symlinks);
+    let walker = match source.search_depth() {
+        Some(depth) => walker.max_depth(depth.saturating_add(1)),
+        None => walker,
+    };
+    walker
+        .into_iter()
+        .filter_entry(move |e| {
+            // skip excluded directories entirely, rather than filtering out each of their files
+            e.depth() == 0
+                || !e.file_type().is_dir()
+                || e.path()
+                    .strip_prefix(path)
+                    .is_ok_and(|relative| filter.allows_directory(relative))
+        })
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| has_allowed_extension(e.path(), allowed_extensions))
+        .filter(move |e| {
+            e.path()
+                .strip_prefix(path)
+                .is_ok_and(|relative| filter.allows_file(relative))
+        })
+        .map(walkdir::DirEntry::into_path)
+}
+
+/// Fetch an image from a URL and return it as a `CacheValue`
+///
+/// # Errors
+///
+/// Returns an error if the image cannot be fetched or if the content type is unsupported.
+#[cfg(feature = "remote-sources")]
+pub async fn read_image_from_url(url: &Url) -> Result<cache::CacheValue> {
+    let client = config::HttpConfig::default().build_client()?;
+    read_image_from_url_with_headers(
+        &client,
+        url,
+        &BTreeMap::new(),
+        ALLOWED_IMAGE_EXTENSIONS,
+        None,
+    )
+    .await
+}
+
+/// Fetch an image from a URL with the given client, sending the given HTTP headers, and return it as a `CacheValue`
+///
+/// The download is aborted once it's larger than `max_size` bytes, if set.
+///
+/// # Errors
+///
+/// Returns an error if the image cannot be fetched, is larger than `max_size`, or if the content type
+/// is not an image type with one of the allowed extensions.
+#[cfg(feature = "remote-sources")]
+pub async fn read_image_from_url_with_headers(
+    client: &reqwest::Client,
+    url: &Url,
+    headers: &BTreeMap<String, String>,
+    allowed_extensions: &[impl AsRef<str> + Sync],
+    max_size: Option<u64>,
+) -> Result<cache::CacheValue> {
+    read_image_from_url_if_modified(
+        client,
+        url,
+        headers,
+        allowed_extensions,
+        &cache::Validators::default(),
+        max_size,
+    )
+    .await?
+    .ok_or_else(|| anyhow!("Failed to fetch image, status: 304 Not Modified"))
+}
+
+/// Fetch an image from a URL, unless it hasn't changed since it was served with the given validators
+///
+/// The validators are sent as `If-None-Match` and `If-Modified-Since` headers, and `None` is
+/// returned if the server responds with `304 Not Modified`. The download is aborted once it's larger than
+/// `max_size` bytes, if set.
+///
+/// # Errors
+///
+/// Returns an error if the image cannot be fetched, is larger than `max_size`, or if the content type
+/// is not an image type with one of the allowed extensions.
+#[cfg(feature = "remote-sources")]
+#[tracing::instrument(
+    name = "fetch_image",
+    level = "debug",
+    skip_all,
+    fields(url = %url, status = tracing::field::Empty)
+)]
+pub async fn read_image_from_url_if_modified(
+    client: &reqwest::Client,
+    url: &Url,
+    headers: &BTreeMap<String, String>,
+    allowed_extensions: &[impl AsRef<str> + Sync],
+    validators: &cache::Validators,
+    max_size: Option<u64>,
+) -> Result<Option<cache::CacheValue>> {
+    let mut request = headers
+        .iter()
+        .fold(client.get(url.as_str()), |request, (name, value)| {
+            request.header(name, value)
+        });
+    if let Some(etag) = &validators.etag {
+        request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to fetch image from URL: {e}"))?;
+    tracing::Span::current().record("status", response.status().as_u16());
+
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED && !validators.is_empty() {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Failed to fetch image, status: {}",
+            response.status()
+        ));
+    }
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string)
+    };
+    let content_type = header(reqwest::header::CONTENT_TYPE)
+        .ok_or_else(|| anyhow!("Failed to get Content-Type header from response"))?;
+    let validators = cache::Validators {
+        etag: header(reqwest::header::ETAG),
+        last_modified: header(reqwest::header::LAST_MODIFIED),
+    };
+
+    if !has_allowed_content_type(&content_type, allowed_extensions) {
+        return Err(anyhow!("Unsupported image content type: {content_type}"));
+    }
+
+    let data = http::read_body(response, max_size)
+        .await
+        .map_err(|e| anyhow!("Failed to read image bytes from response: {e}"))?;
+
+    Ok(Some(cache::CacheValue {
+        data,
+        content_type,
+        validators,
+    }))
+}
+
+/// The header used to propagate request IDs
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// The header identifying the image served by `/random` and `/sequential`, by its public id
+pub const IMAGE_ID_HEADER: &str = "x-image-id";
+/// The header naming the path or URL of the image served by `/random` and `/sequential`,
+/// if `server.expose_sources` is set
+pub const IMAGE_SOURCE_HEADER: &str = "x-image-source";
+
+/// Handle incoming HTTP requests
+///
+/// Each request is assigned a request ID, taken from the `X-Request-Id` header if present and
+/// otherwise generated, which is attached to all logs emitted while handling the request,
+/// along with its method and route, and echoed back in the response headers.
+///
+/// # Errors
+///
+/// should be Infallible
+pub async fn handle_request<B: Send + Sync, C: CacheBackend>(
+    req: Request<B>,
+    state: Arc<ServerState<C>>,
+) -> Result<Response<Body>, Infallible> {
+    let request_id = request_id(&req);
+    // logged as the client the request was forwarded for, if the peer is a trusted proxy
+    let client = access::client_ip(&req, &state.config.server);
+    // the name of the certificate the client authenticated with, if `tls.client_ca` is set
+    let client_cn = req
+        .extensions()
+        .get::<tls::ClientCertificate>()
+        .and_then(|client| client.common_name.clone());
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        route = tracing::field::Empty,
+        client = client.map(tracing::field::display),
+        client_cn = client_cn.as_deref()
+    );
+    let (method, path) = (req.method().clone(), req.uri().path().to_string());
+
+    let mut response = observability::with_request_context(
+        &request_id,
+        &method,
+        &path,
+        route_request(&req, &state).instrument(span),
+    )
+    .await;
+    state.metrics.record_response(response.status());
+    if let Ok(value) = hyper::header::HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(response)
+}
+
+/// Get the ID of a request from its `X-Request-Id` header, or generate a new one
+///
+/// Incoming IDs are only honored if they are reasonably short and made of visible ASCII characters.
+#[must_use]
+pub fn request_id<B>(req: &Request<B>) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic()))
+        .map_or_else(|| uuid::Uuid::new_v4().to_string(), ToString::to_string)
+}
+
+/// Route a request to the handler for its path
+async fn route_request<B: Sync, C: CacheBackend>(
+    req: &Request<B>,
+    state: &ServerState<C>,
+) -> Response<Body> {
+    let client = access::client_ip(req, &state.config.server);
+    if !access::is_allowed(&state.config.server.access, client) {
+        tracing::warn!(
+            "Refused a request from {}",
+            client.map_or_else(|| "an unknown address".to_string(), |ip| ip.to_string())
+        );
+        return status_response(hyper::StatusCode::FORBIDDEN);
+    }
+
+    // clients without a certificate only get through the handshake when ACME validators have to
+    if state.config.tls.client_ca.is_some()
+        && req.extensions().get::<tls::ClientCertificate>().is_none()
+    {
+        tracing::warn!("Refused a request without a client certificate");
+        return status_response(hyper::StatusCode::FORBIDDEN);
+    }
+
+    if let Some(response) = oversized_request_response(req, &state.config.server.limits) {
+        return response;
+    }
+
+    let Some(path) = strip_base_path(req.uri().path(), &state.config.server.base_path) else {
+        return unknown_route_response();
+    };
+
+    if let Some(basic) = &state.config.server.auth.basic
+        && !openapi::is_public(path)
+        && !auth::is_authenticated(req, basic, &state.verified_credentials).await
+    {
+        return unauthorized_response();
+    }
+
+    let span = tracing::Span::current();
+    if let Some((name, _)) = path.trim_start_matches('/').split_once('/')
+        && let Some(collection) = state.collections.get(name)
+    {
+        let path = &path[name.len() + 1..];
+        return if openapi::is_collection_route(path) {
+            if let Some(route) = openapi::route_path(path) {
+                span.record("route", format!("/{name}{route}"));
+            }
+            route(req, path, collection.as_ref()).await
+        } else {
+            unknown_route_response()
+        };
+    }
+    if let Some(route) = openapi::route_path(path) {
+        span.record("route", route);
+    }
+    route(req, path, state).await
+}
+
+/// Route a request to the handler for its path, relative to the base path of the state
+async fn route<B: Sync, C: CacheBackend>(
+    req: &Request<B>,
+    path: &str,
+    state: &ServerState<C>,
+) -> Response<Body> {
+    let response = match path {
+        "/random" | "/random.json" | "/sequential" | "/list" if state.config.signing.required => {
+            status_response(hyper::StatusCode::FORBIDDEN)
+        }
+        playlist if playlist.starts_with("/sequential/") && state.config.signing.required => {
+            status_response(hyper::StatusCode::FORBIDDEN)
+        }
+        image
+            if is_image_route(image)
+                && !referer::is_allowed(req, &state.config.server.allowed_referers) =>
+        {
+            hotlink_response(state)
+        }
+        "/" => Response::new(body::full("Welcome to the Random Image Server!")),
+        "/health" => or_problem(handle_health(state), "Failed to check health"),
+        "/livez" => Response::new(body::full("OK")),
+        "/readyz" => or_problem(handle_readiness(state), "Failed to check readiness"),
+        "/admin/config/schema" => or_problem(
+            json_response(&Config::json_schema()),
+            "Failed to get config schema",
+        ),
+        "/openapi.json" => or_problem(
+            json_response(&openapi::document(&state.config)),
+            "Failed to get OpenAPI document",
+        ),
+        "/docs" if state.config.server.swagger_ui => {
+            let mut response = Response::new(body::full(openapi::swagger_ui(&format!(
+                "{}/openapi.json",
+                state.config.server.base_path
+            ))));
+            response.headers_mut().insert(
+                hyper::header::CONTENT_TYPE,
+                hyper::header::HeaderValue::from_static("text/html; charset=utf-8"),
+            );
+            response
+        }
+        "/version" => or_problem(handle_version(), "Failed to get version"),
+        "/random" | "/sequential"
+            if state.empty_placeholder.is_some() && state.image_count() == 0 =>
+        {
+            empty_placeholder_response(state)
+        }
+        "/random.json" | "/random" => {
+            let mut response = match RandomQuery::parse(req.uri().query()).map(|mut query| {
+                query.transforms.format = accepted_format(req, state);
+                query
+            }) {
+                Ok(query) if path == "/random.json" || accepts_json(req) => or_problem(
+                    handle_random_image_json(req, state, &query),
+                    "Failed to describe a random image",
+                ),
+                Ok(query) => or_problem(
+                    handle_random_image(req, state, &query).await,
+                    "Failed to get random image",
+                ),
+                Err(err) => {
+                    tracing::warn!("Invalid query for random image: {err}");
+                    status_response(hyper::StatusCode::BAD_REQUEST)
+                }
+            };
+            if path == "/random" {
+                // whether an image or its description is sent depends on the `Accept` header
+                response.headers_mut().insert(
+                    hyper::header::VARY,
+                    hyper::header::HeaderValue::from_static("Accept"),
+                );
+            }
+            response
+        }
+        "/proxy" if state.proxy_cache.is_some() => match ProxyQuery::parse(req.uri().query()) {
+            Ok(query) => handle_proxy_image(state, &query)
+                .await
+                .unwrap_or_else(|err| {
+                    tracing::warn!("Failed to proxy {}: {err}", query.url);
+                    status_response(err.status())
+                }),
+            Err(err) => {
+                tracing::warn!("Invalid query for proxied image: {err}");
+                status_response(hyper::StatusCode::BAD_REQUEST)
+            }
+        },
+        size if size.starts_with("/placeholder/") && state.placeholder_cache.is_some() => {
+            let size = size.trim_start_matches("/placeholder/");
+            match PlaceholderQuery::parse(req.uri().query())
+                .and_then(|style| Placeholder::new(size, style, &state.config.placeholder))
+            {
+                Ok(placeholder) => or_problem(
+                    handle_placeholder_image(state, &placeholder),
+                    "Failed to generate placeholder",
+                ),
+                Err(err) => {
+                    tracing::warn!("Invalid placeholder: {err}");
+                    status_response(hyper::StatusCode::BAD_REQUEST)
+                }
+            }
+        }
+        "/sequential" => match SequentialQuery::parse(req.uri().query()).map(|mut query| {
+            query.transforms.format = accepted_format(req, state);
+            query
+        }) {
+            Ok(query) => or_problem(
+                handle_sequential_image(state, &query).await,
+                "Failed to get sequential image",
+            ),
+            Err(err) => {
+                tracing::warn!("Invalid query for sequential image: {err}");
+                status_response(hyper::StatusCode::BAD_REQUEST)
+            }
+        },
+        playlist if playlist.starts_with("/sequential/") => {
+            let name = playlist.trim_start_matches("/sequential/");
+            match SequentialQuery::parse(req.uri().query()).map(|mut query| {
+                query.transforms.format = accepted_format(req, state);
+                query
+            }) {
+                Ok(query) => or_problem(
+                    handle_playlist_image(state, name, &query).await,
+                    "Failed to get playlist image",
+                ),
+                Err(err) => {
+                    tracing::warn!("Invalid query for playlist {name}: {err}");
+                    status_response(hyper::StatusCode::BAD_REQUEST)
+                }
+            }
+        }
+        "/favicon.ico" => or_problem(handle_favicon(state), "Failed to get favicon"),
+        file if file.starts_with("/static/") => match &state.config.server.static_dir {
+            Some(dir) => or_problem(
+                handle_static_file(dir, file.trim_start_matches("/static/")),
+                "Failed to get static file",
+            ),
+            None => unknown_route_response(),
+        },
+        "/metrics" => handle_metrics(state),
+        "/stats/images" => or_problem(handle_image_stats(state), "Failed to get image stats"),
+        "/stats/cache" => or_problem(handle_cache_stats(state), "Failed to get cache stats"),
+        "/list" => match ListQuery::parse(req.uri().query()) {
+            Ok(query) => or_problem(
+                handle_list_images(req, state, &query),
+                "Failed to list images",
+            ),
+            Err(err) => {
+                tracing::warn!("Invalid query for image list: {err}");
+                status_response(hyper::StatusCode::BAD_REQUEST)
+            }
+        },
+        info if info.starts_with("/image/") && info.ends_with("/info") => {
+            let id = info.trim_start_matches("/image/").trim_end_matches("/info");
+            match check_signature(req, id, state) {
+                Ok(()) => or_problem(
+                    handle_image_info(req, id, state),
+                    "Failed to describe image",
+                ),
+                Err(err) => {
+                    tracing::warn!("Refused a link to image {id}: {err}");
+                    status_response(hyper::StatusCode::FORBIDDEN)
+                }
+            }
+        }
+        image if image.starts_with("/image/") => {
+            let id = image.trim_start_matches("/image/");
+            match (
+                check_signature(req, id, state),
+                TransformQuery::parse(req.uri().query()).map(|mut transforms| {
+                    transforms.format = accepted_format(req, state);
+                    transforms
+                }),
+            ) {
+                (Ok(()), Ok(transforms)) => or_problem(
+                    handle_image_by_id(id, state, &transforms).await,
+                    "Failed to get image",
+                ),
+                (Err(err), _) => {
+                    tracing::warn!("Refused a link to image {id}: {err}");
+                    status_response(hyper::StatusCode::FORBIDDEN)
+                }
+                (_, Err(err)) => {
+                    tracing::warn!("Invalid query for image {id}: {err}");
+                    status_response(hyper::StatusCode::BAD_REQUEST)
+                }
+            }
+        }
+        _ => unknown_route_response(),
+    };
+    not_modified(req, response)
+}
+
+/// The response to a request that exceeds the configured limits, if it does
+///
+/// Every route is a `GET` endpoint, so requests with a body are refused as malformed, whatever its size.
+/// The limits are the same for every route.
+fn oversized_request_response<B>(
+    req: &Request<B>,
+    limits: &config::LimitsConfig,
+) -> Option<Response<Body>> {
+    let uri_length = req
+        .uri()
+        .path_and_query()
+        .map_or(0, |path_and_query| path_and_query.as_str().len());
+    if uri_length > limits.max_uri_length {
+        return Some(problem_response(
+            hyper::StatusCode::URI_TOO_LONG,
+            &format!(
+                "The path and query of the request are longer than {} bytes",
+                limits.max_uri_length
+            ),
+        ));
+    }
+    let headers = req.headers();
+    let has_body = headers.contains_key(hyper::header::TRANSFER_ENCODING)
+        || headers
+            .get(hyper::header::CONTENT_LENGTH)
+            .is_some_and(|length| length != "0");
+    has_body.then(|| {
+        problem_response(
+            hyper::StatusCode::BAD_REQUEST,
+            "No route accepts a request body",
+        )
+    })
+}
+
+/// The response to a request for a path no route matches
+fn unknown_route_response() -> Response<Body> {
+    problem_response(hyper::StatusCode::NOT_FOUND, "No route matches the path")
+}
+
+/// The response to a request without the configured basic authentication credentials
+fn unauthorized_response() -> Response<Body> {
+    let mut response = status_response(hyper::StatusCode::UNAUTHORIZED);
+    response.headers_mut().insert(
+        hyper::header::WWW_AUTHENTICATE,
+        hyper::header::HeaderValue::from_static(auth::WWW_AUTHENTICATE),
+    );
+    response
+}
+
+/// Whether the route responds with images, and so is subject to hotlink protection
+fn is_image_route(path: &str) -> bool {
+    matches!(path, "/random" | "/sequential" | "/proxy")
+        || path.starts_with("/sequential/")
+        || path.starts_with("/image/")
+}
+
+/// The response to a request for an image from a domain images may not be embedded on
+///
+/// The configured placeholder image if there is one, and 403 Forbidden otherwise.
+fn hotlink_response<C: CacheBackend>(state: &ServerState<C>) -> Response<Body> {
+    // the placeholder must not be cached in place of the image, for pages that may embed it
+    state.hotlink_placeholder.as_ref().map_or_else(
+        || status_response(hyper::StatusCode::FORBIDDEN),
+        uncached_image_response,
+    )
+}
+
+/// The response to a request for an image while no images are available, with `server.on_empty = "placeholder"`
+///
+/// The generated placeholder, which must not be cached in place of the images to come.
+fn empty_placeholder_response<C: CacheBackend>(state: &ServerState<C>) -> Response<Body> {
+    state.empty_placeholder.as_ref().map_or_else(
+        || status_response(hyper::StatusCode::NOT_FOUND),
+        uncached_image_response,
+    )
+}
+
+/// The response serving an image that must not be cached, e.g. a placeholder served in place of another image
+fn uncached_image_response(image: &cache::CacheValue) -> Response<Body> {
+    let mut response = Response::new(body::full(image.data.clone()));
+    let headers = response.headers_mut();
+    if let Ok(content_type) = hyper::header::HeaderValue::from_str(&image.content_type) {
+        headers.insert(hyper::header::CONTENT_TYPE, content_type);
+    }
+    headers.insert(
+        hyper::header::CACHE_CONTROL,
+        hyper::header::HeaderValue::from_static("no-store"),
+    );
+    response
+}
+
+/// Check the signature of a request for the image with the given id
+///
+/// Unsigned requests are only refused if `signing.required` is set, but a signed request is always
+/// checked if a secret is configured, so that expired links are refused.
+fn check_signature<B, C: CacheBackend>(
+    req: &Request<B>,
+    id: &str,
+    state: &ServerState<C>,
+) -> Result<(), signing::SignatureError> {
+    let signing = &state.config.signing;
+    let query = req.uri().query();
+    let signed = query.is_some_and(|query| {
+        url::form_urlencoded::parse(query.as_bytes()).any(|(key, _)| key == "sig")
+    });
+    match signing.secret.as_deref() {
+        Some(secret) if signing.required || signed => {
+            signing::verify(secret, id, query, std::time::SystemTime::now())
+        }
+        None if signing.required => Err(signing::SignatureError::NoSecret),
+        _ => Ok(()),
+    }
+}
+
+/// Unwrap the result of a handler, responding with a problem on failure
+///
+/// The status of the problem is that of the [`RequestError`] the handler failed with, if any.
+/// Any other error is an internal one, logged and reported, and answered with `500 Internal Server Error`.
+fn or_problem(result: Result<Response<Body>>, context: &str) -> Response<Body> {
+    result.unwrap_or_else(|err| {
+        if let Some(request_err) = err.downcast_ref::<RequestError>() {
+            tracing::warn!("{context}: {request_err}");
+            return problem_response(request_err.status(), &request_err.to_string());
+        }
+        tracing::error!("{context}: {err}");
+        observability::capture_error(&err.context(context.to_string()));
+        problem_response(hyper::StatusCode::INTERNAL_SERVER_ERROR, context)
+    })
+}
+
+/// Strip the configured base path from a request path
+///
+/// Returns `None` if the request path is not mounted under the base path.
+#[must_use]
+pub fn strip_base_path<'a>(path: &'a str, base_path: &str) -> Option<&'a str> {
+    if base_path.is_empty() {
+        return Some(path);
+    }
+    match path.strip_prefix(base_path)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
+
+/// Handle random image serving
+///
+/// # Errors
+///
+/// Returns an error if no images are configured or if the image cannot be found in the cache.
+pub async fn handle_random_image<B: Sync, C: CacheBackend>(
+    req: &Request<B>,
+    state: &ServerState<C>,
+    query: &RandomQuery,
+) -> Result<Response<Body>> {
+    let (key, cookie) = choose_sticky_image(req, state, query)?;
+    let mut response = cached_image_response(state, &key, &query.transforms).await?;
+    identify_image(state, &key, &mut response);
+    if let Some(cookie) = cookie {
+        response
+            .headers_mut()
+            .insert(hyper::header::SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// A random image, as described by `/random.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct RandomImage {
+    /// The opaque identifier of the image
+    pub id: String,
+    /// The absolute URL the image can be retrieved from
+    pub url: String,
+    pub content_type: String,
+    /// The width of the image in pixels, if it could be determined
+    pub width: Option<usize>,
+    /// The height of the image in pixels, if it could be determined
+    pub height: Option<usize>,
+    /// The size of the image in bytes
+    pub size: u64,
+}
+
+/// Handle describing a random image as JSON, with a link to it, instead of serving it
+///
+/// The image isn't counted as served until it's retrieved from its link, which carries the transforms
+/// of the query, while the description is of the image as it is cached.
+///
+/// # Errors
+///
+/// Returns an error if no images are configured or if the image cannot be found in the cache.
+pub fn handle_random_image_json<B: Sync, C: CacheBackend>(
+    req: &Request<B>,
+    state: &ServerState<C>,
+    query: &RandomQuery,
+) -> Result<Response<Body>> {
+    let (key, cookie) = choose_sticky_image(req, state, query)?;
+    let (content_type, size, dimensions) = describe_image(state, &key)?;
+    let id = state.image_id(&key);
+
+    let transforms = query.transforms.query_string();
+    let query = if transforms.is_empty() {
+        String::new()
+    } else {
+        format!("?{transforms}")
+    };
+    let mut response = json_response(&RandomImage {
+        url: format!(
+            "{}/image/{id}{query}",
+            public_base_url(req, &state.config.server)
+        ),
+        id,
+        content_type,
+        width: dimensions.map(|dimensions| dimensions.width),
+        height: dimensions.map(|dimensions| dimensions.height),
+        size,
+    })?;
+    if let Some(cookie) = cookie {
+        response
+            .headers_mut()
+            .insert(hyper::header::SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// The content type, size in bytes, and dimensions (if they can be determined) of a cached image
+///
+/// # Errors
+///
+/// Returns an error if the image is not in the cache.
+fn describe_image<C: CacheBackend>(
+    state: &ServerState<C>,
+    key: &cache::CacheKey,
+) -> Result<(String, u64, Option<imagesize::ImageSize>)> {
+    let (content_type, size, dimensions) = if let Some(file) = state.cache.open(key) {
+        let dimensions = imagesize::reader_size(std::io::BufReader::new(file.file));
+        (file.content_type, file.len, dimensions)
+    } else {
+        let image = state
+            .cache
+            .get(key.clone())
+            .ok_or_else(|| anyhow!("Image not found in cache"))?;
+        let dimensions = imagesize::blob_size(&image.data);
+        (image.content_type, image.data.len() as u64, dimensions)
+    };
+    Ok((content_type, size, dimensions.ok()))
+}
+
+/// An image, as described by `/image/{id}/info`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct ImageInfo {
+    /// The opaque identifier of the image
+    pub id: String,
+    /// The absolute URL the image can be retrieved from
+    pub url: String,
+    pub content_type: String,
+    /// The width of the image in pixels, if it could be determined
+    pub width: Option<usize>,
+    /// The height of the image in pixels, if it could be determined
+    pub height: Option<usize>,
+    /// The size of the image in bytes
+    pub size: u64,
+    /// The perceptual hash of the image as 16 hexadecimal digits, if computed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// The most common color of the image, as a CSS hex color, if computed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dominant_color: Option<String>,
+    /// The blurhash of the image, if computed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+}
+
+/// Handle describing the image with the given id as JSON, e.g. to show its preview while it loads
+///
+/// The image isn't counted as served.
+///
+/// # Errors
+///
+/// Returns an error if no image has the given id.
+pub fn handle_image_info<B: Sync, C: CacheBackend>(
+    req: &Request<B>,
+    id: &str,
+    state: &ServerState<C>,
+) -> Result<Response<Body>> {
+    let key = state
+        .find_image(id)
+        .ok_or_else(|| RequestError::NotFound(format!("No image with id {id}")))?;
+    let (content_type, size, dimensions) = describe_image(state, &key)?;
+    let preview = state.image_preview(&key);
+
+    json_response(&ImageInfo {
+        url: format!("{}/image/{id}", public_base_url(req, &state.config.server)),
+        id: id.to_string(),
+        content_type,
+        width: dimensions.map(|dimensions| dimensions.width),
+        height: dimensions.map(|dimensions| dimensions.height),
+        size,
+        hash: state.image_hash(&key).map(phash::format_hash),
+        dominant_color: preview
+            .as_ref()
+            .map(|preview| preview::format_color(preview.dominant_color)),
+        blurhash: preview.map(|preview| preview.blurhash),
+    })
+}
+
+/// The pre-encoded format of images the request accepts best, of `transforms.formats`,
+/// if the server was built with the `variants` feature
+fn accepted_format<B, C: CacheBackend>(
+    req: &Request<B>,
+    state: &ServerState<C>,
+) -> Option<config::VariantFormat> {
+    if !cfg!(feature = "variants") {
+        return None;
+    }
+    variants::negotiate(req.headers(), &state.config.transforms.formats)
+}
+
+/// Whether the request prefers a JSON description of an image to the image itself,
+/// i.e. its `Accept` header explicitly lists `application/json`
+fn accepts_json<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get_all(hyper::header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|range| {
+            let mut params = range.split(';').map(str::trim);
+            params
+                .next()
+                .is_some_and(|media_type| media_type.eq_ignore_ascii_case("application/json"))
+                && !params.any(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .is_some_and(|q| q <= 0.0)
+                })
+        })
+}
+
+/// Choose a random image for the client that sent a request, or the image pinned to it
+///
+/// With `?sticky=` or `server.sticky`, the image chosen for a client is pinned to it for that long,
+/// and the `Set-Cookie` header identifying new clients is returned along with it.
+///
+/// # Errors
+///
+/// Returns an error if the cache is empty.
+fn choose_sticky_image<B, C: CacheBackend>(
+    req: &Request<B>,
+    state: &ServerState<C>,
+    query: &RandomQuery,
+) -> Result<(cache::CacheKey, Option<hyper::header::HeaderValue>)> {
+    let Some(duration) = query
+        .sticky
+        .or(state.config.server.sticky)
+        .filter(|duration| !duration.is_zero())
+        .map(|duration| duration.min(std::time::Duration::from_secs(query::MAX_STICKY_SECONDS)))
+    else {
+        return Ok((choose_random_image(state, query)?, None));
+    };
+
+    let client = sticky::Client::identify(req, &state.config.server);
+    let now = std::time::Instant::now();
+    // images removed from the cache since they were pinned, or that don't fit the query, are chosen again
+    let pinned = state
+        .sticky_images()
+        .get(&client, now)
+        .filter(|pinned| is_selectable(state, pinned, query));
+    let key = match pinned {
+        Some(key) => key,
+        None => {
+            let key = choose_random_image(state, query)?;
+            state.sticky_images().pin(&client, &key, duration, now);
+            key
+        }
+    };
+    Ok((
+        key,
+        client.set_cookie(&state.config.server.base_path, duration),
+    ))
+}
+
+/// Whether `/random` may choose the cached image with the given key: it isn't withheld from requests,
+/// and has the format, orientation, and dimensions of the query
+fn is_selectable<C: CacheBackend>(
+    state: &ServerState<C>,
+    key: &cache::CacheKey,
+    query: &RandomQuery,
+) -> bool {
+    !state.is_withheld(key)
+        && state.cache.content_type(key).is_some_and(|content_type| {
+            query.formats.is_empty() || query.formats.contains(&content_type)
+        })
+        && query.fits(state.image_dimensions(key))
+}
+
+/// How many times an image is drawn from the weights of every image, while the drawn images were served recently,
+/// before the images that weren't are chosen among instead
+const MAX_RANDOM_DRAWS: usize = 16;
+
+/// Choose a random image from the cache, among those with the format, orientation, and dimensions of the query,
+/// weighted by the weight of its source, avoiding the images served most recently if `server.no_repeat_window` is set
+///
+/// # Errors
+///
+/// Returns an error if the cache is empty, or no image fits the query.
+fn choose_random_image<C: CacheBackend>(
+    state: &ServerState<C>,
+    query: &RandomQuery,
+) -> Result<cache::CacheKey> {
+    if state.image_count() == 0 {
+        return Err(no_images_error(state).into());
+    }
+    let selection = state.random_selection();
+    // how recently each of the images served most recently was served, the latest first
+    let recently_served = state.recently_served();
+    let mut recent: HashMap<&cache::CacheKey, usize> = HashMap::new();
+    for (position, key) in recently_served.iter().enumerate() {
+        recent.entry(key).or_insert(position);
+    }
+    if query.order == RandomOrder::Uniform
+        && query.formats.is_empty()
+        && !query.filters_dimensions()
+    {
+        // without filters, an image is drawn from the weights of every image directly
+        let mut rng = rand::rng();
+        let drawn = selection.weights.as_ref().and_then(|weights| {
+            std::iter::repeat_with(|| &selection.images[weights.sample(&mut rng)].key)
+                .take(MAX_RANDOM_DRAWS)
+                .find(|key| !recent.contains_key(key))
+        });
+        if let Some(key) = drawn {
+            return Ok(key.clone());
+        }
+    }
+
+    let images: Vec<&SelectableImage> = selection
+        .images
+        .iter()
+        .filter(|image| {
+            (query.formats.is_empty() || query.formats.contains(&image.content_type))
+                && query.fits(image.dimensions)
+        })
+        .collect();
+    if images.is_empty() && (query.filters_dimensions() || !query.formats.is_empty()) {
+        return Err(RequestError::NotFound(
+            "No image has the format, orientation, and dimensions of the query".to_string(),
+        )
+        .into());
+    }
+
+    let candidates: Vec<&SelectableImage> = match query.order {
+        RandomOrder::Uniform => images,
+        RandomOrder::LeastServed => {
+            let least_served = images
+                .iter()
+                .map(|image| state.serve_counts.get(&image.key))
+                .min();
+            images
+                .into_iter()
+                .filter(|image| Some(state.serve_counts.get(&image.key)) == least_served)
+                .collect()
+        }
+    };
+    // the most recently served images are avoided, as long as there are others to choose from,
+    // and otherwise the one served least recently is chosen
+    let (fresh, served): (Vec<&SelectableImage>, Vec<&SelectableImage>) = candidates
+        .into_iter()
+        .partition(|image| !recent.contains_key(&image.key));
+    let candidates = if fresh.is_empty() {
+        served
+            .into_iter()
+            .max_by_key(|image| recent[&image.key])
+            .into_iter()
+            .collect()
+    } else {
+        fresh
+    };
+
+    candidates
+        .choose_weighted(&mut rand::rng(), |image| image.weight)
+        .map_err(|e| anyhow!("Failed to retrieve a random image: {e}"))
+        .map(|image| image.key.clone())
+}
+
+/// Handle sequential image serving
+///
+/// # Errors
+///
+/// Returns an error if no images are configured or if the image cannot be found in the cache.
+pub async fn handle_sequential_image<C: CacheBackend>(
+    state: &ServerState<C>,
+    query: &SequentialQuery,
+) -> Result<Response<Body>> {
+    // the images of the formats of the query are looked up in the index of the cache
+    let formats: Option<Vec<cache::CacheKey>> =
+        (!query.formats.is_empty()).then(|| state.image_keys_of_types(&query.formats));
+    let size = formats
+        .as_ref()
+        .map_or_else(|| state.image_count(), Vec::len);
+    if size == 0 {
+        return Err(if formats.is_some() && state.image_count() > 0 {
+            RequestError::NotFound("No image has the format of the query".to_string())
+        } else {
+            no_images_error(state)
+        }
+        .into());
+    }
+
+    let current_index = advance(&state.current_index, size);
+    let source = match formats {
+        Some(keys) => keys.into_iter().nth(current_index),
+        None => state.image_keys().nth(current_index),
+    }
+    .ok_or_else(|| anyhow!("Image not found in cache"))?;
+
+    // Fetch the image from the cache, dropping it if it's no longer there
+    let mut response = cached_image_response(state, &source, &query.transforms)
+        .await
+        .inspect_err(|e| remove_if_missing(state, &source, e))?;
+    identify_image(state, &source, &mut response);
+    Ok(response)
+}
+
+/// Handle serving the next image of a playlist
+///
+/// # Errors
+///
+/// Returns an error if no playlist has the given name, if none of its images are cached,
+/// or if the image cannot be found in the cache.
+pub async fn handle_playlist_image<C: CacheBackend>(
+    state: &ServerState<C>,
+    name: &str,
+    query: &SequentialQuery,
+) -> Result<Response<Body>> {
+    let (Some(index), Some(mut keys)) = (
+        state.playlist_indices.get(name),
+        state.playlist_images(name),
+    ) else {
+        return Err(RequestError::NotFound(format!("No playlist is named {name}")).into());
+    };
+    if !query.formats.is_empty() {
+        let formats: HashSet<cache::CacheKey> = state
+            .image_keys_of_types(&query.formats)
+            .into_iter()
+            .collect();
+        keys.retain(|key| formats.contains(key));
+    }
+    if keys.is_empty() {
+        return Err(if state.image_count() == 0 {
+            no_images_error(state)
+        } else {
+            RequestError::NotFound(format!("No image of playlist {name} is available"))
+        }
+        .into());
+    }
+
+    let source = &keys[advance(index, keys.len())];
+    let mut response = cached_image_response(state, source, &query.transforms)
+        .await
+        .inspect_err(|e| remove_if_missing(state, source, e))?;
+    identify_image(state, source, &mut response);
+    Ok(response)
+}
+
+/// Drop an image, along with everything known about it, if serving it failed because it's no longer in the cache,
+/// e.g. its file was deleted, so it isn't chosen again
+///
+/// Images that fail to be transformed are kept: the failure is the request's, not the image's.
+fn remove_if_missing<C: CacheBackend>(
+    state: &ServerState<C>,
+    key: &cache::CacheKey,
+    error: &anyhow::Error,
+) {
+    if error.is::<CacheMiss>() {
+        state.remove_image(key);
+    }
+}
+
+/// Move a sequential position on to the next of `size` images, returning the position it was at
+///
+/// Only the index is updated, so sequential requests don't block each other, or writers to the cache.
+fn advance(index: &AtomicUsize, size: usize) -> usize {
+    index
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |index| {
+            Some((index % size + 1) % size)
+        })
+        .unwrap_or_default()
+        % size
+}
+
+/// An entry in the response of the `/list` endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct ImageListEntry {
+    /// The opaque identifier of the image
+    pub id: String,
+    /// The absolute URL the image can be retrieved from
+    pub url: String,
+    /// The perceptual hash of the image as 16 hexadecimal digits, if computed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// The size of the image in bytes, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    /// The most common color of the image, as a CSS hex color, if computed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dominant_color: Option<String>,
+    /// The blurhash of the image, if computed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+}
+
+/// The response of the `/list` endpoint, a page of the cached images
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct ImageList {
+    pub images: Vec<ImageListEntry>,
+    /// The number of images listed across every page
+    pub total: usize,
+    /// The page, counting from 1
+    pub page: usize,
+    pub per_page: usize,
+    /// The absolute URL of the next page, if any
+    pub next: Option<String>,
+    /// The absolute URL of the previous page, if any
+    pub previous: Option<String>,
+}
+
+/// Handle listing a page of the images in the cache, with absolute links to each image and to the adjacent pages
+///
+/// # Errors
+///
+/// Returns an error if the response cannot be serialized.
+pub fn handle_list_images<B: Sync, C: CacheBackend>(
+    req: &Request<B>,
+    state: &ServerState<C>,
+    query: &ListQuery,
+) -> Result<Response<Body>> {
+    let base_url = public_base_url(req, &state.config.server);
+    let mut keys: Vec<cache::CacheKey> = state
+        .image_keys()
+        .filter(|key| {
+            query.tag.as_ref().is_none_or(|tag| {
+                state
+                    .image_source(key)
+                    .is_some_and(|source| source.tags.contains(tag))
+            })
+        })
+        .collect();
+    match query.sort {
+        ListSort::Added => {}
+        ListSort::Name => keys.sort_by_cached_key(ToString::to_string),
+        ListSort::Size => keys.sort_by_cached_key(|key| state.image_size(key)),
+    }
+
+    let total = keys.len();
+    let start = (query.page - 1).saturating_mul(query.per_page);
+    let images = keys
+        .iter()
+        .skip(start)
+        .take(query.per_page)
+        .map(|key| {
+            let id = state.image_id(key);
+            let preview = state.image_preview(key);
+            ImageListEntry {
+                url: format!("{base_url}/image/{id}"),
+                id,
+                hash: state.image_hash(key).map(phash::format_hash),
+                bytes: state.image_size(key),
+                dominant_color: preview
+                    .as_ref()
+                    .map(|preview| preview::format_color(preview.dominant_color)),
+                blurhash: preview.map(|preview| preview.blurhash),
+            }
+        })
+        .collect();
+    let page_url = |page: usize| format!("{base_url}/list?{}", query.page_query(page));
+
+    json_response(&ImageList {
+        images,
+        total,
+        page: query.page,
+        per_page: query.per_page,
+        next: (start.saturating_add(query.per_page) < total).then(|| page_url(query.page + 1)),
+        // past the last page, the previous page is the last one
+        previous: (query.page > 1)
+            .then(|| page_url((query.page - 1).min(total.div_ceil(query.per_page).max(1)))),
+    })
+}
+
+/// How many times a single image has been served, reported by `/stats/images`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct ImageStats {
+    /// The opaque identifier of the image
+    pub id: String,
+    /// The source of the image, as a URL or path
+    pub source: String,
+    /// The number of times the image has been served
+    pub served: u64,
+    /// When the image became stale, in seconds since the Unix epoch, if it failed to be refreshed from its
+    /// source and the copy cached before is served instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale_since: Option<u64>,
+}
+
+/// The response of the `/stats/images` endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct ImageStatsReport {
+    /// The cached images, most served first
+    pub images: Vec<ImageStats>,
+}
+
+/// Handle reporting how many times each image has been served
+///
+/// # Errors
+///
+/// Returns an error if the response cannot be serialized.
+pub fn handle_image_stats<C: CacheBackend>(state: &ServerState<C>) -> Result<Response<Body>> {
+    let mut images: Vec<ImageStats> = state
+        .cache
+        .keys()
+        .map(|key| ImageStats {
+            id: state.image_id(&key),
+            source: key.to_string(),
+            served: state.serve_counts.get(&key),
+            stale_since: state.image_stale_since(&key),
+        })
+        .collect();
+    images.sort_by(|a, b| {
+        b.served
+            .cmp(&a.served)
+            .then_with(|| a.source.cmp(&b.source))
+    });
+
+    json_response(&ImageStatsReport { images })
+}
+
+/// The response of the `/stats/cache` endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct CacheStatsReport {
+    /// The type of the cache backend
+    pub backend: &'static str,
+    /// The number of images in the cache
+    pub images: usize,
+    /// The total size of the images in the cache, in bytes
+    pub bytes: u64,
+    /// The number of images in the cache that are stale, served after failing to be refreshed from their source
+    pub stale: usize,
+    #[serde(flatten)]
+    pub stats: cache::CacheStats,
+}
+
+/// Handle reporting the lookups, failed integrity checks, and evictions of the cache
+///
+/// # Errors
+///
+/// Returns an error if the response cannot be serialized.
+pub fn handle_cache_stats<C: CacheBackend>(state: &ServerState<C>) -> Result<Response<Body>> {
+    json_response(&CacheStatsReport {
+        backend: state.cache.backend_type(),
+        images: state.cache.size(),
+        bytes: state.cache.bytes(),
+        stale: state.stale_count(),
+        stats: state.cache.stats(),
+    })
+}
+
+/// Handle proxying a remote image through the proxy cache
+///
+/// Images proxied before are served from the cache. Others are fetched, checked against the
+/// configured extensions and file size limits, stored in the cache, and served.
+/// Proxied images are kept apart from the configured ones, so they are never served by `/random`.
+///
+/// # Errors
+///
+/// Returns an error if the proxy is disabled, the URL is not on an allowed domain,
+/// or the image can't be fetched or is outside the limits.
+pub async fn handle_proxy_image<C: CacheBackend>(
+    state: &ServerState<C>,
+    query: &ProxyQuery,
+) -> Result<Response<Body>, ProxyError> {
+    let Some(cache) = &state.proxy_cache else {
+        return Err(ProxyError::Disabled);
+    };
+    if !state.config.proxy.allows(&query.url) {
+        return Err(ProxyError::NotAllowed);
+    }
+    let hash = state.config.proxy.cache_config().hash;
+    let key = cache::CacheKey::ImageUrl(query.url.clone());
+    if let Ok((body, served)) = read_cached_image(cache, hash, &key) {
+        return image_response(body, &served.content_type, &served.hash)
+            .map_err(ProxyError::Response);
+    }
+
+    let image = fetch_proxied_image(state, &query.url)
+        .await
+        .map_err(ProxyError::Fetch)?;
+    let size = image.data.len() as u64;
+    if !state.config.server.allows_file_size(size) {
+        return Err(ProxyError::OutsideLimits(size));
+    }
+    let digest = hash.digest(&image.data);
+    if let Err(e) = cache.set(key, image.clone()) {
+        tracing::warn!("Failed to store proxied image {}: {e}", query.url);
+    }
+    image_response(body::full(image.data), &image.content_type, &digest)
+        .map_err(ProxyError::Response)
+}
+
+/// Fetch an image to proxy, unless its URL is backed off or its host's circuit is open
+///
+/// Redirects are only followed to URLs the proxy allows, and the download is aborted once it's larger
+/// than `server.max_file_size`.
+#[cfg(feature = "remote-sources")]
+async fn fetch_proxied_image<C: CacheBackend>(
+    state: &ServerState<C>,
+    url: &Url,
+) -> Result<cache::CacheValue> {
+    let client = state
+        .proxy_client
+        .as_ref()
+        .ok_or_else(|| anyhow!("The HTTP client of the proxy could not be built"))?;
+    let headers = BTreeMap::new();
+    let fetch = read_image_from_url_with_headers(
+        client,
+        url,
+        &headers,
+        &state.config.server.allowed_extensions,
+        state.config.server.max_file_size,
+    );
+    fetch_with_breaker(state, url, fetch).await
+}
+
+/// Fetch an image to proxy, which always fails without the `remote-sources` feature
+#[cfg(not(feature = "remote-sources"))]
+#[allow(clippy::unused_async)]
+async fn fetch_proxied_image<C: CacheBackend>(
+    _state: &ServerState<C>,
+    _url: &url::Url,
+) -> Result<cache::CacheValue> {
+    Err(anyhow!(REMOTE_SOURCES_DISABLED))
+}
+
+/// Handle generating a placeholder image
+///
+/// Placeholders generated before are served from the placeholder cache, under the synthetic key
+/// of their size and style. Others are drawn, stored in the cache, and served.
+///
+/// # Errors
+///
+/// Returns an error if placeholders are disabled, or the placeholder can't be drawn.
+pub fn handle_placeholder_image<C: CacheBackend>(
+    state: &ServerState<C>,
+    placeholder: &Placeholder,
+) -> Result<Response<Body>> {
+    let Some(cache) = &state.placeholder_cache else {
+        return Err(RequestError::NotFound("Placeholders are disabled".to_string()).into());
+    };
+    let hash = state.config.placeholder.cache_config().hash;
+    let key = placeholder.key();
+    if let Ok((body, served)) = read_cached_image(cache, hash, &key) {
+        return image_response(body, &served.content_type, &served.hash);
+    }
+
+    let image = placeholder.render()?;
+    let digest = hash.digest(&image.data);
+    if let Err(e) = cache.set(key.clone(), image.clone()) {
+        tracing::warn!("Failed to store placeholder {key}: {e}");
+    }
+    image_response(body::full(image.data), &image.content_type, &digest)
+}
+
+/// How long browsers may cache the favicon and static files, in seconds
+const STATIC_MAX_AGE: u32 = 24 * 60 * 60;
+/// How long browsers and CDNs may cache images served under the id of their content, in seconds
+const IMMUTABLE_MAX_AGE: u32 = 365 * 24 * 60 * 60;
+
+/// Handle serving the favicon
+///
+/// # Errors
+///
+/// Returns an error if the content type of the favicon is invalid.
+pub fn handle_favicon<C: CacheBackend>(state: &ServerState<C>) -> Result<Response<Body>> {
+    let favicon = &state.favicon;
+    let mut response = image_response(
+        body::full(favicon.data.clone()),
+        &favicon.content_type,
+        &state.config.cache.hash.digest(&favicon.data),
+    )?;
+    response.headers_mut().insert(
+        hyper::header::CACHE_CONTROL,
+        format!("public, max-age={STATIC_MAX_AGE}").parse()?,
+    );
+    Ok(response)
+}
+
+/// Handle serving a file from the static directory, at the given path relative to it
+///
+/// # Errors
+///
+/// Returns an error if no file is found at the path, or it leads out of the directory.
+pub fn handle_static_file(dir: &Path, path: &str) -> Result<Response<Body>> {
+    let file_path = static_files::resolve(dir, path)
+        .ok_or_else(|| RequestError::NotFound(format!("No static file at {path}")))?;
+    let file = fs::File::open(&file_path)?;
+    let len = file.metadata()?.len();
+    let mut response = Response::new(body::file(file, len));
+    let headers = response.headers_mut();
+    headers.insert(
+        hyper::header::CONTENT_TYPE,
+        static_files::content_type(&file_path).parse()?,
+    );
+    headers.insert(
+        hyper::header::CACHE_CONTROL,
+        format!("public, max-age={STATIC_MAX_AGE}").parse()?,
+    );
+    Ok(response)
+}
+
+/// Handle exposing metrics in the Prometheus text format
+pub fn handle_metrics<C: CacheBackend>(state: &ServerState<C>) -> Response<Body> {
+    let snapshot = metrics::MetricsSnapshot::from_state(state);
+    let mut response = Response::new(body::full(snapshot.to_prometheus()));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    response
+}
+
+/// The response of the `/health` endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct Health {
+    /// `ok` if every source loaded successfully, `degraded` otherwise
+    pub status: &'static str,
+    /// The number of images available to be served
+    pub images: usize,
+    /// The status of each configured source
+    pub sources: Vec<state::SourceHealth>,
+    /// The circuit breakers of the remote hosts that recently failed
+    pub hosts: Vec<breaker::HostHealth>,
+}
+
+/// Handle detailed health checks, reporting the status of each configured source
+///
+/// # Errors
+///
+/// Returns an error if the response cannot be serialized.
+pub fn handle_health<C: CacheBackend>(state: &ServerState<C>) -> Result<Response<Body>> {
+    let sources = state.sources();
+    let health = Health {
+        status: if sources.iter().all(|source| {
+            matches!(
+                source.status,
+                state::SourceStatus::Loaded | state::SourceStatus::Skipped
+            )
+        }) {
+            "ok"
+        } else {
+            "degraded"
+        },
+        images: state.image_count(),
+        sources,
+        hosts: state.breakers().hosts(std::time::Instant::now()),
+    };
+
+    json_response(&health)
+}
+
+/// The response of the `/readyz` endpoint when the server is ready
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct Readiness {
+    pub status: &'static str,
+    /// The number of images available to be served
+    pub images: usize,
+}
+
+/// A problem details body, as described by RFC 9457
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+}
+
+/// Why a request could not be handled, as opposed to an internal error of the server,
+/// which determines the status of the problem it's answered with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+    /// No image, playlist, or file has the requested id or name, or no image fits the query
+    NotFound(String),
+    /// No images can be served yet: the cache is empty, or hasn't finished being populated
+    Unavailable(String),
+}
+
+impl RequestError {
+    /// The status of the response to a request that failed with this error
+    #[must_use]
+    pub const fn status(&self) -> hyper::StatusCode {
+        match self {
+            Self::NotFound(_) => hyper::StatusCode::NOT_FOUND,
+            Self::Unavailable(_) => hyper::StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(detail) | Self::Unavailable(detail) => f.write_str(detail),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// Why the server failed to start, each with its own exit code, so container orchestrators and init systems
+/// can tell a misconfiguration, which restarting won't fix, from a port that's still in use
+#[derive(Debug)]
+pub enum StartupError {
+    /// The command line arguments are invalid, described along with the usage
+    Usage(String),
+    /// The config file, or the env file, can't be loaded or is invalid
+    Config(String),
+    /// The server can't listen on its address, e.g. because another process already does
+    Bind(std::net::SocketAddr, std::io::Error),
+    /// No images were found once the cache was populated, and `server.on_empty` is `fail`
+    EmptyCache,
+}
+
+impl StartupError {
+    /// The code the process exits with after this error, following the conventions of `sysexits.h`
+    #[must_use]
+    pub const fn exit_code(&self) -> u8 {
+        match self {
+            // EX_USAGE
+            Self::Usage(_) => 64,
+            // EX_CONFIG
+            Self::Config(_) => 78,
+            // EX_UNAVAILABLE
+            Self::Bind(..) => 69,
+            // EX_NOINPUT
+            Self::EmptyCache => 66,
+        }
+    }
+
+    /// The code the process exits with after the given error: that of a startup error, or 1 for any other error
+    #[must_use]
+    pub fn exit_code_of(error: &anyhow::Error) -> u8 {
+        error.downcast_ref::<Self>().map_or(1, Self::exit_code)
+    }
+}
+
+impl std::fmt::Display for StartupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Usage(detail) | Self::Config(detail) => f.write_str(detail),
+            Self::Bind(addr, e) => write!(f, "Failed to listen on {addr}: {e}"),
+            Self::EmptyCache => {
+                f.write_str("No images found in cache, please check your configuration")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StartupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Bind(_, e) => Some(e),
+            Self::Usage(_) | Self::Config(_) | Self::EmptyCache => None,
+        }
+    }
+}
+
+/// The error of a request for an image while no images can be served
+fn no_images_error<C: CacheBackend>(state: &ServerState<C>) -> RequestError {
+    RequestError::Unavailable(if state.populated.load(Ordering::Acquire) {
+        "No images are available, the configured sources may be unreachable".to_string()
+    } else {
+        "The image cache has not finished being populated".to_string()
+    })
+}
+
+/// Handle readiness checks
+///
+/// The server is ready once the cache has been populated and contains at least one image, unless every source
+/// failed to load, otherwise a `503 Service Unavailable` problem response is returned.
+///
+/// # Errors
+///
+/// Returns an error if the response cannot be serialized.
+pub fn handle_readiness<C: CacheBackend>(state: &ServerState<C>) -> Result<Response<Body>> {
+    if state.sources_failed() {
+        return Ok(problem_response(
+            hyper::StatusCode::SERVICE_UNAVAILABLE,
+            "Every image source failed to load",
+        ));
+    }
+    if !state.is_ready() {
+        return Ok(problem_response(
+            hyper::StatusCode::SERVICE_UNAVAILABLE,
+            &no_images_error(state).to_string(),
+        ));
+    }
+
+    json_response(&Readiness {
+        status: "ready",
+        images: state.image_count(),
+    })
+}
+
+/// Handle reporting the build metadata of the server
+///
+/// # Errors
+///
+/// Returns an error if the response cannot be serialized.
+pub fn handle_version() -> Result<Response<Body>> {
+    json_response(&version::build_info())
+}
+
+/// Handle serving a specific image by its identifier
+///
+/// # Errors
+///
+/// Returns an error if no image with the given identifier is in the cache.
+pub async fn handle_image_by_id<C: CacheBackend>(
+    id: &str,
+    state: &ServerState<C>,
+    transforms: &TransformQuery,
+) -> Result<Response<Body>> {
+    let key = state
+        .find_image(id)
+        .ok_or_else(|| RequestError::NotFound(format!("No image with id {id}")))?;
+
+    let mut response = cached_image_response(state, &key, transforms).await?;
+    // the content of an image never changes under the id of its content
+    if state.image_id(&key) == id {
+        response.headers_mut().insert(
+            hyper::header::CACHE_CONTROL,
+            format!("public, max-age={IMMUTABLE_MAX_AGE}, immutable").parse()?,
+        );
+    }
+    Ok(response)
+}
+
+/// Build a plain text response with the given status, using its canonical reason as the body
+fn status_response(status: hyper::StatusCode) -> Response<Body> {
+    let reason = status.canonical_reason().unwrap_or_default();
+    let mut response = Response::new(body::full(reason));
+    *response.status_mut() = status;
+    response
+}
+
+/// Build a JSON response from the given value
+fn json_response(value: &impl Serialize) -> Result<Response<Body>> {
+    let json = serde_json::to_vec(value)?;
+    let mut response = Response::new(body::full(json));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    Ok(response)
+}
+
+/// Build an `application/problem+json` response with the given status and detail
+///
+/// Falls back to a plain text response with the status, if the problem can't be serialized.
+fn problem_response(status: hyper::StatusCode, detail: &str) -> Response<Body> {
+    let problem = Problem {
+        kind: "about:blank".to_string(),
+        title: status.canonical_reason().unwrap_or_default().to_string(),
+        status: status.as_u16(),
+        detail: detail.to_string(),
+    };
+    let Ok(mut response) = json_response(&problem) else {
+        return status_response(status);
+    };
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/problem+json"),
+    );
+    response
+}
+
+/// Build a response serving the cached image with the given key, with its hash as the `ETag`,
+/// and record that it was served
+///
+/// Images the cache stores in files are served from a memory map of the file, or streamed from disk, rather than read into memory.
+/// Transformed images are served from the variant cache, and rendered and stored in it the first time.
+/// Images that aren't transformed, other than re-encoding a JPEG at another quality, are served in their
+/// pre-encoded format instead, if they have a variant in it.
+///
+/// # Errors
+///
+/// Returns an error if the image is not in the cache, or can't be transformed.
+async fn cached_image_response<C: CacheBackend>(
+    state: &ServerState<C>,
+    key: &cache::CacheKey,
+    transforms: &TransformQuery,
+) -> Result<Response<Body>> {
+    let transforms = image_transforms(state, key, transforms);
+    let encoded = transforms
+        .format
+        .filter(|_| {
+            TransformQuery {
+                quality: None,
+                ..transforms.clone()
+            }
+            .is_empty()
+        })
+        .and_then(|format| read_encoded_variant(state, key, format));
+    let (body, served) = match encoded {
+        Some(encoded) => encoded,
+        None if transforms.is_empty() => {
+            read_cached_image(&state.cache, state.config.cache.hash, key)?
+        }
+        None => read_image_variant(state, key, &transforms).await?,
+    };
+    let mut response = image_response(body, &served.content_type, &served.hash)?;
+    if cfg!(feature = "variants") && !state.config.transforms.formats.is_empty() {
+        // which format is sent depends on the `Accept` header
+        response.headers_mut().insert(
+            hyper::header::VARY,
+            hyper::header::HeaderValue::from_static("Accept"),
+        );
+    }
+    state.record_served(key, &served);
+    Ok(response)
+}
+
+/// Read the variant of a cached image pre-encoded in the given format from the variant cache, if it's there
+fn read_encoded_variant<C: CacheBackend>(
+    state: &ServerState<C>,
+    key: &cache::CacheKey,
+    format: config::VariantFormat,
+) -> Option<(Body, ServedImage)> {
+    let variant_key = variants::variant_key(key, &state.image_id(key), format);
+    let hash = state.config.transforms.cache_config().hash;
+    read_cached_image(&state.variant_cache, hash, &variant_key).ok()
+}
+
+/// Add the headers identifying the image with the given key to the response serving it:
+/// its public id, and its path or URL if `server.expose_sources` is set
+fn identify_image<C: CacheBackend>(
+    state: &ServerState<C>,
+    key: &cache::CacheKey,
+    response: &mut Response<Body>,
+) {
+    let headers = response.headers_mut();
+    if let Ok(id) = hyper::header::HeaderValue::from_str(&state.image_id(key)) {
+        headers.insert(IMAGE_ID_HEADER, id);
+    }
+    if state.config.server.expose_sources {
+        // paths that aren't visible ASCII can't be sent as a header
+        match hyper::header::HeaderValue::from_str(&key.to_string()) {
+            Ok(source) => {
+                headers.insert(IMAGE_SOURCE_HEADER, source);
+            }
+            Err(_) => tracing::debug!("The source of {key} can't be sent as a header"),
+        }
+    }
+}
+
+/// The transforms applied to the image with the given key: those of the request,
+/// with the caption of the image's source and the configured quality unless the request sets its own
+fn image_transforms<C: CacheBackend>(
+    state: &ServerState<C>,
+    key: &cache::CacheKey,
+    requested: &TransformQuery,
+) -> TransformQuery {
+    let mut transforms = requested.clone();
+    if cfg!(feature = "transforms") {
+        if transforms.caption.is_none() {
+            transforms.caption = state
+                .image_source(key)
+                .and_then(|source| source.caption.clone());
+        }
+        transforms.quality = transforms.quality.or(state.config.transforms.quality);
+    }
+    transforms
+}
+
+/// Read the variant of a cached image with the given transforms from the variant cache,
+/// rendering and storing it if it isn't there
+///
+/// Variants are keyed by the id of the content of the image, so the image is only read when its variant is rendered,
+/// on a blocking thread.
+async fn read_image_variant<C: CacheBackend>(
+    state: &ServerState<C>,
+    key: &cache::CacheKey,
+    transforms: &TransformQuery,
+) -> Result<(Body, ServedImage)> {
+    let content_type = state.cache.content_type(key).ok_or(CacheMiss)?;
+    // only JPEGs are re-encoded at another quality
+    let mut transforms = transforms.clone();
+    if content_type != "image/jpeg" {
+        transforms.quality = None;
+        if transforms.is_empty() {
+            return read_cached_image(&state.cache, state.config.cache.hash, key);
+        }
+    }
+    let variant_key = transform::variant_key(key, &state.image_id(key), &transforms);
+    let hash = state.config.transforms.cache_config().hash;
+    if let Ok(variant) = read_cached_image(&state.variant_cache, hash, &variant_key) {
+        return Ok(variant);
+    }
+
+    let original = state.cache.get(key.clone()).ok_or(CacheMiss)?;
+    let render = tokio::task::spawn_blocking(move || transform::apply(&original, &transforms));
+    let variant = render.await.map_err(anyhow::Error::from).flatten()?;
+    let served = ServedImage {
+        content_type: variant.content_type.clone(),
+        bytes: variant.data.len() as u64,
+        hash: hash.digest(&variant.data),
+    };
+    if let Err(e) = state.variant_cache.set(variant_key, variant.clone()) {
+        tracing::warn!("Failed to store a variant of {key}: {e}");
+    }
+    Ok((body::full(variant.data), served))
+}
+
+/// The error of reading an image that's no longer in the cache, or can no longer be read from it,
+/// as opposed to failing to transform it
+#[derive(Debug)]
+struct CacheMiss;
+
+impl std::fmt::Display for CacheMiss {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Image not found in cache")
+    }
+}
+
+impl std::error::Error for CacheMiss {}
+
+/// Read an image from a cache, serving it from its mapped file or streaming it from disk if the backend stores it uncompressed,
+/// along with what is known about it
+fn read_cached_image(
+    cache: &impl CacheBackend,
+    hash: config::HashAlgorithm,
+    key: &cache::CacheKey,
+) -> Result<(Body, ServedImage)> {
+    if let Some(file) = cache.open(key) {
+        let served = ServedImage {
+            content_type: file.content_type,
+            bytes: file.len,
+            hash: file.hash,
+        };
+        let body = match file.map {
+            Some(map) => body::mapped(map),
+            None => body::file(file.file, file.len),
+        };
+        return Ok((body, served));
+    }
+    let image = cache.get(key.clone()).ok_or(CacheMiss)?;
+    let served = ServedImage {
+        content_type: image.content_type,
+        bytes: image.data.len() as u64,
+        hash: hash.digest(&image.data),
+    };
+    Ok((body::full(image.data), served))
+}
+
+/// Build a response serving an image with the given body, with its hash as the `ETag`
+fn image_response(body: Body, content_type: &str, hash: &str) -> Result<Response<Body>> {
+    let mut response = Response::new(body);
+    *response.status_mut() = hyper::StatusCode::OK;
+    response
+        .headers_mut()
+        .insert(hyper::header::CONTENT_TYPE, content_type.parse()?);
+    response
+        .headers_mut()
+        .insert(hyper::header::ETAG, format!("\"{hash}\"").parse()?);
+    Ok(response)
+}
+
+/// Replace the response with a `304 Not Modified` if its `ETag` matches the `If-None-Match` of the request
+#[must_use]
+pub fn not_modified<B>(req: &Request<B>, response: Response<Body>) -> Response<Body> {
+    let Some(etag) = response.headers().get(hyper::header::ETAG) else {
+        return response;
+    };
+    let matches =
+        req.headers()
+            .get(hyper::header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| {
+                value.split(',').map(str::trim).any(|tag| {
+                    tag == "*" || tag.trim_start_matches("W/").as_bytes() == etag.as_bytes()
+                })
+            });
+    if !matches {
+        return response;
+    }
+    let mut not_modified = Response::new(body::empty());
+    *not_modified.status_mut() = hyper::StatusCode::NOT_MODIFIED;
+    not_modified
+        .headers_mut()
+        .insert(hyper::header::ETAG, etag.clone());
+    not_modified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::termination::create_termination;
+    use http_body_util::BodyExt;
+    use pretty_assertions::assert_eq;
     use rstest::rstest;
 
     #[test]
@@ -412,6 +3551,608 @@ mod tests {
         assert_eq!(ALLOWED_IMAGE_EXTENSIONS.len(), 5);
     }
 
+    #[rstest]
+    #[case::allowed("image.jpg", true)]
+    #[case::uppercase("image.JPG", true)]
+    #[case::not_allowed("image.heic", false)]
+    #[case::no_extension("image", false)]
+    fn test_has_allowed_extension(#[case] path: &str, #[case] expected: bool) {
+        assert_eq!(
+            has_allowed_extension(Path::new(path), ALLOWED_IMAGE_EXTENSIONS),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::jpeg("image/jpeg", &["jpg"], true)]
+    #[case::parameters("image/png; charset=binary", &["png"], true)]
+    #[case::heic("image/heic", &["heic"], true)]
+    #[case::not_allowed("image/tiff", &["jpg", "png"], false)]
+    #[case::not_an_image("text/plain", &["txt"], false)]
+    fn test_has_allowed_content_type(
+        #[case] content_type: &str,
+        #[case] allowed: &[&str],
+        #[case] expected: bool,
+    ) {
+        assert_eq!(has_allowed_content_type(content_type, allowed), expected);
+    }
+
+    #[rstest]
+    #[case::no_base_path("/random", "", Some("/random"))]
+    #[case::root("/images", "/images", Some("/"))]
+    #[case::root_slash("/images/", "/images", Some("/"))]
+    #[case::route("/images/random", "/images", Some("/random"))]
+    #[case::missing_prefix("/random", "/images", None)]
+    #[case::partial_segment("/imagesrandom", "/images", None)]
+    fn test_strip_base_path(
+        #[case] path: &str,
+        #[case] base_path: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        assert_eq!(strip_base_path(path, base_path), expected);
+    }
+
+    #[rstest]
+    #[case::missing(None, None)]
+    #[case::honored(Some("abc-123"), Some("abc-123"))]
+    #[case::too_long(Some(&*"a".repeat(129)), None)]
+    #[case::whitespace(Some("abc 123"), None)]
+    fn test_request_id(#[case] incoming: Option<&str>, #[case] expected: Option<&str>) {
+        let mut builder = Request::builder();
+        if let Some(incoming) = incoming {
+            builder = builder.header(REQUEST_ID_HEADER, incoming);
+        }
+        let req = builder.body(()).unwrap();
+
+        let id = request_id(&req);
+        match expected {
+            Some(expected) => assert_eq!(id, expected),
+            None => assert!(uuid::Uuid::parse_str(&id).is_ok()),
+        }
+    }
+
+    #[rstest]
+    #[case::allowed("10.1.2.3:1234", &[], hyper::StatusCode::OK)]
+    #[case::not_allowed("192.168.1.1:1234", &[], hyper::StatusCode::FORBIDDEN)]
+    #[case::denied("10.0.0.1:1234", &[], hyper::StatusCode::FORBIDDEN)]
+    #[case::forwarded("10.0.0.2:1234", &[("x-forwarded-for", "10.1.2.3")], hyper::StatusCode::OK)]
+    #[case::forwarded_denied("10.0.0.2:1234", &[("x-forwarded-for", "10.0.0.1")], hyper::StatusCode::FORBIDDEN)]
+    #[tokio::test]
+    async fn test_route_request_access(
+        #[case] peer: &str,
+        #[case] headers: &[(&str, &str)],
+        #[case] expected: hyper::StatusCode,
+    ) {
+        let mut config = Config::default();
+        config.server.trusted_proxies = vec!["10.0.0.2".parse().unwrap()];
+        config.server.access.allow = vec!["10.0.0.0/8".parse().unwrap()];
+        config.server.access.deny = vec!["10.0.0.1".parse().unwrap()];
+        let state = ServerState::with_config(&config);
+
+        let mut builder = Request::builder().uri("/livez");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let mut req = builder.body(()).unwrap();
+        req.extensions_mut()
+            .insert(RemoteAddr(peer.parse().unwrap()));
+
+        assert_eq!(route_request(&req, &state).await.status(), expected);
+    }
+
+    #[rstest]
+    #[case::json(&["application/json"], true)]
+    #[case::among_others(&["text/html, application/json;q=0.9"], true)]
+    #[case::case_insensitive(&["Application/JSON"], true)]
+    #[case::second_header(&["image/*", "application/json"], true)]
+    #[case::refused(&["application/json;q=0"], false)]
+    #[case::image(&["image/avif,image/webp,*/*"], false)]
+    #[case::none(&[], false)]
+    fn test_accepts_json(#[case] accept: &[&str], #[case] expected: bool) {
+        let mut builder = Request::builder().uri("/random");
+        for value in accept {
+            builder = builder.header(hyper::header::ACCEPT, *value);
+        }
+        assert_eq!(accepts_json(&builder.body(()).unwrap()), expected);
+    }
+
+    #[tokio::test]
+    async fn test_route_request_documented_routes() {
+        let mut config = Config::default();
+        config.server.swagger_ui = true;
+        let state = ServerState::with_config(&config);
+        let key = cache::CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
+        let value = cache::CacheValue {
+            data: vec![1, 2, 3, 4],
+            content_type: "image/jpeg".to_string(),
+            validators: cache::Validators::default(),
+        };
+        state.store_image(0, key.clone(), value).unwrap();
+        state.populated.store(true, Ordering::Release);
+
+        for route in openapi::routes(&config) {
+            let path = route.path.replace("{id}", &key.id());
+            let req = Request::builder().uri(&path).body(()).unwrap();
+            let response = route_request(&req, &state).await;
+            assert_eq!(response.status(), hyper::StatusCode::OK, "{path}");
+        }
+
+        config.server.swagger_ui = false;
+        let state = ServerState::with_config(&config);
+        let req = Request::builder().uri("/docs").body(()).unwrap();
+        assert_eq!(
+            route_request(&req, &state).await.status(),
+            hyper::StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_route_request_empty_placeholder() {
+        let mut config = Config::default();
+        config.server.on_empty = config::EmptyCachePolicy::Placeholder;
+        let state = ServerState::with_config(&config);
+
+        // the placeholder is served in place of the images to come
+        for path in ["/random", "/sequential"] {
+            let req = Request::builder().uri(path).body(()).unwrap();
+            let response = route_request(&req, &state).await;
+            assert_eq!(response.status(), hyper::StatusCode::OK, "{path}");
+            assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], "image/png");
+            assert_eq!(response.headers()[hyper::header::CACHE_CONTROL], "no-store");
+        }
+        let req = Request::builder().uri("/random.json").body(()).unwrap();
+        assert_eq!(
+            route_request(&req, &state).await.status(),
+            hyper::StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        // and images once they arrive
+        let value = cache::CacheValue {
+            data: vec![1, 2, 3, 4],
+            content_type: "image/jpeg".to_string(),
+            validators: cache::Validators::default(),
+        };
+        state
+            .store_image(
+                0,
+                cache::CacheKey::ImagePath("/test/image.jpg".into()),
+                value,
+            )
+            .unwrap();
+        let req = Request::builder().uri("/random").body(()).unwrap();
+        let response = route_request(&req, &state).await;
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "image/jpeg"
+        );
+
+        // without the policy, there is no placeholder
+        let state = ServerState::with_config(&Config::default());
+        let req = Request::builder().uri("/random").body(()).unwrap();
+        assert_eq!(
+            route_request(&req, &state).await.status(),
+            hyper::StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[cfg(all(feature = "variants", feature = "transforms"))]
+    #[tokio::test]
+    async fn test_route_request_variants() {
+        let mut config = Config::default();
+        config.transforms.formats = vec![config::VariantFormat::Avif, config::VariantFormat::Webp];
+        let state = ServerState::with_config(&config);
+        let mut png = Vec::new();
+        image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * y) as u8, ((x ^ y) * 4) as u8, ((x + y) * 2) as u8])
+        })
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+        let key = cache::CacheKey::ImagePath("/test/image.png".into());
+        let image = cache::CacheValue {
+            data: png,
+            content_type: "image/png".to_string(),
+            validators: cache::Validators::default(),
+        };
+        let mut outcome = SourceOutcome::default();
+        store_loaded_image(&state, 0, key.clone(), image, &mut outcome).await;
+        assert_eq!(outcome.keys, vec![key.clone()]);
+        let id = state.image_id(&key);
+
+        for (path, accept, expected) in [
+            ("/random", "image/avif,image/webp,*/*", "image/avif"),
+            ("/sequential", "image/webp,*/*", "image/webp"),
+            (
+                &format!("/image/{id}"),
+                "image/avif;q=0.5,image/webp",
+                "image/webp",
+            ),
+            (&format!("/image/{id}"), "*/*", "image/png"),
+            // the variants of transformed images aren't served
+            ("/random?rotate=90", "image/avif", "image/png"),
+        ] {
+            let req = Request::builder()
+                .uri(path)
+                .header(hyper::header::ACCEPT, accept)
+                .body(())
+                .unwrap();
+            let response = route_request(&req, &state).await;
+            assert_eq!(response.status(), hyper::StatusCode::OK, "{path}");
+            assert_eq!(
+                response.headers()[hyper::header::CONTENT_TYPE],
+                expected,
+                "{path} {accept}"
+            );
+            assert_eq!(response.headers()[hyper::header::VARY], "Accept", "{path}");
+        }
+    }
+
+    #[rstest]
+    #[case::not_populated("/random", false, hyper::StatusCode::SERVICE_UNAVAILABLE)]
+    #[case::empty("/sequential", true, hyper::StatusCode::SERVICE_UNAVAILABLE)]
+    #[case::unknown_image("/image/unknown", true, hyper::StatusCode::NOT_FOUND)]
+    #[case::unknown_playlist("/sequential/unknown", true, hyper::StatusCode::NOT_FOUND)]
+    #[case::unknown_route("/unknown", true, hyper::StatusCode::NOT_FOUND)]
+    #[tokio::test]
+    async fn test_route_request_problems(
+        #[case] path: &str,
+        #[case] populated: bool,
+        #[case] expected: hyper::StatusCode,
+    ) {
+        let state = ServerState::with_config(&Config::default());
+        state.populated.store(populated, Ordering::Release);
+
+        let req = Request::builder().uri(path).body(()).unwrap();
+        let response = route_request(&req, &state).await;
+        assert_eq!(response.status(), expected);
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem["status"], expected.as_u16());
+        assert!(!problem["detail"].as_str().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_or_problem() {
+        let response = or_problem(
+            Err(RequestError::NotFound("No image with id 1".to_string()).into()),
+            "Failed to get image",
+        );
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+
+        // other errors are internal ones, whose details aren't sent
+        let response = or_problem(Err(anyhow!("disk on fire")), "Failed to get image");
+        assert_eq!(response.status(), hyper::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    #[timeout(std::time::Duration::from_secs(5))]
+    async fn test_start_on_empty_wait() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().canonicalize().unwrap();
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut config = config::Config::default();
+        config.server.port = port;
+        config.server.on_empty = config::EmptyCachePolicy::Wait;
+        config.server.rescan_interval = Some(std::time::Duration::from_secs(1));
+        config.server.sources = vec![ImageSource::Path(dir_path.clone()).into()];
+        let server = ImageServer::with_config(config);
+        let (mut terminator, interrupt_rx) = create_termination();
+        let running = tokio::spawn(async move { server.start(interrupt_rx).await });
+
+        // the server starts without images, and isn't ready
+        let client = reqwest::Client::new();
+        let readiness =
+            |client: &reqwest::Client| client.get(format!("http://127.0.0.1:{port}/readyz")).send();
+        let status = loop {
+            match readiness(&client).await {
+                Ok(response) => break response.status(),
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+        assert_eq!(status, hyper::StatusCode::SERVICE_UNAVAILABLE);
+
+        // until images appear in its sources
+        fs::copy("assets/blank.jpg", dir_path.join("a.jpg")).unwrap();
+        while readiness(&client).await.unwrap().status() != hyper::StatusCode::OK {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        drop(client);
+
+        terminator.terminate(Interrupted::UserInt).unwrap();
+        running.await.unwrap().unwrap();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    #[timeout(std::time::Duration::from_secs(5))]
+    async fn test_start_ready_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let ready_file = dir.path().join("ready");
+        // left behind by a server that didn't shut down gracefully
+        fs::write(&ready_file, b"").unwrap();
+        let mut config = config::Config::default();
+        config.server.port = 0;
+        config.server.start_before_populate = true;
+        config.server.ready_file = Some(ready_file.clone());
+        config.server.sources = vec![ImageSource::Path(PathBuf::from("assets")).into()];
+        let server = ImageServer::with_config(config);
+        let (mut terminator, interrupt_rx) = create_termination();
+        let running = tokio::spawn(async move { server.start(interrupt_rx).await });
+
+        while !ready_file.exists() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        terminator.terminate(Interrupted::UserInt).unwrap();
+        running.await.unwrap().unwrap();
+        assert!(!ready_file.exists());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    #[timeout(std::time::Duration::from_secs(5))]
+    async fn test_start_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config::Config::default();
+        config.server.port = 0;
+        config.server.sources = vec![ImageSource::Path(dir.path().to_path_buf()).into()];
+        let (_terminator, interrupt_rx) = create_termination();
+
+        // no images are found
+        let error = ImageServer::with_config(config.clone())
+            .start(interrupt_rx.resubscribe())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<StartupError>(),
+            Some(StartupError::EmptyCache)
+        ));
+        assert_eq!(StartupError::exit_code_of(&error), 66);
+
+        // the port is already in use
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        config.server.port = listener.local_addr().unwrap().port();
+        let error = ImageServer::with_config(config)
+            .start(interrupt_rx)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<StartupError>(),
+            Some(StartupError::Bind(..))
+        ));
+        assert_eq!(StartupError::exit_code_of(&error), 69);
+
+        assert_eq!(StartupError::exit_code_of(&anyhow!("disk on fire")), 1);
+        assert_eq!(
+            StartupError::exit_code_of(&StartupError::Config("invalid".to_string()).into()),
+            78
+        );
+        assert_eq!(
+            StartupError::exit_code_of(&StartupError::Usage("--bogus".to_string()).into()),
+            64
+        );
+    }
+
+    #[rstest]
+    #[case::short_uri("/random?format=jpeg", &[], hyper::StatusCode::OK)]
+    #[case::long_uri("/random?format=jpeg,png,webp,gif", &[], hyper::StatusCode::URI_TOO_LONG)]
+    #[case::body("/random", &[(hyper::header::CONTENT_LENGTH, "3")], hyper::StatusCode::BAD_REQUEST)]
+    #[case::empty_body("/random", &[(hyper::header::CONTENT_LENGTH, "0")], hyper::StatusCode::OK)]
+    #[case::chunked_body(
+        "/random",
+        &[(hyper::header::TRANSFER_ENCODING, "chunked")],
+        hyper::StatusCode::BAD_REQUEST
+    )]
+    fn test_oversized_request_response(
+        #[case] uri: &str,
+        #[case] headers: &[(hyper::header::HeaderName, &str)],
+        #[case] expected: hyper::StatusCode,
+    ) {
+        let limits = config::LimitsConfig {
+            max_uri_length: 20,
+            ..config::LimitsConfig::default()
+        };
+        let mut req = Request::builder().uri(uri);
+        for (name, value) in headers {
+            req = req.header(name, *value);
+        }
+        let req = req.body(()).unwrap();
+        assert_eq!(
+            oversized_request_response(&req, &limits)
+                .map_or(hyper::StatusCode::OK, |response| response.status()),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    #[timeout(std::time::Duration::from_secs(5))]
+    async fn test_start_header_limits() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut config = config::Config::default();
+        config.server.port = port;
+        config.server.sources = vec![ImageSource::Path(PathBuf::from("assets")).into()];
+        config.server.limits.max_header_size = config::MIN_MAX_HEADER_SIZE;
+        config.server.limits.max_headers = 20;
+        let server = ImageServer::with_config(config);
+        let (mut terminator, interrupt_rx) = create_termination();
+        let running = tokio::spawn(async move { server.start(interrupt_rx).await });
+
+        let client = reqwest::Client::new();
+        let get = || client.get(format!("http://127.0.0.1:{port}/livez"));
+        let status = loop {
+            match get().send().await {
+                Ok(response) => break response.status(),
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+        assert_eq!(status, hyper::StatusCode::OK);
+
+        // too large headers, and too many of them, are refused. hyper only checks the size of requests
+        // it hasn't read in full yet, so a request a little over the limit may still get through
+        let large = get().header("x-large", "a".repeat(4 * config::MIN_MAX_HEADER_SIZE));
+        let mut many = get();
+        for i in 0..30 {
+            many = many.header(format!("x-header-{i}"), "value");
+        }
+        for request in [large, many] {
+            assert_eq!(
+                request.send().await.unwrap().status(),
+                hyper::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+            );
+        }
+        drop(client);
+
+        terminator.terminate(Interrupted::UserInt).unwrap();
+        running.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_start_https() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let dir = tempfile::tempdir().unwrap();
+        let rcgen::CertifiedKey { cert, signing_key } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(dir.path().join("cert.pem"), cert.pem()).unwrap();
+        std::fs::write(dir.path().join("key.pem"), signing_key.serialize_pem()).unwrap();
+
+        let mut config = config::Config::default();
+        config.server.port = port;
+        config.server.sources = vec![ImageSource::Path(PathBuf::from("assets")).into()];
+        config.tls.cert = Some(dir.path().join("cert.pem"));
+        config.tls.key = Some(dir.path().join("key.pem"));
+        config.tls.http3 = true;
+        let server = ImageServer::with_config(config);
+        let (mut terminator, interrupt_rx) = create_termination();
+        let running = tokio::spawn(async move { server.start(interrupt_rx).await });
+
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(cert.pem().as_bytes()).unwrap())
+            .resolve("localhost", ([127, 0, 0, 1], port).into())
+            .build()
+            .unwrap();
+        let response = loop {
+            match client
+                .get(format!("https://localhost:{port}/livez"))
+                .send()
+                .await
+            {
+                Ok(response) => break response,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        // HTTP/3 is only advertised if it's served
+        assert_eq!(
+            response
+                .headers()
+                .get(hyper::header::ALT_SVC)
+                .map(|value| value.to_str().unwrap().to_string()),
+            cfg!(feature = "http3").then(|| format!("h3=\":{port}\"; ma=86400"))
+        );
+        drop(client);
+
+        terminator.terminate(Interrupted::UserInt).unwrap();
+        running.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_start_mutual_tls() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let dir = tempfile::tempdir().unwrap();
+        let server = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(dir.path().join("cert.pem"), server.cert.pem()).unwrap();
+        std::fs::write(
+            dir.path().join("key.pem"),
+            server.signing_key.serialize_pem(),
+        )
+        .unwrap();
+
+        // a certificate authority, and a client certificate it signed
+        let mut ca_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        std::fs::write(
+            dir.path().join("ca.pem"),
+            ca_params.self_signed(&ca_key).unwrap().pem(),
+        )
+        .unwrap();
+        let ca = rcgen::Issuer::new(ca_params, ca_key);
+        let mut client_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        client_params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "photo-frame");
+        client_params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+        let client_key = rcgen::KeyPair::generate().unwrap();
+        let client_cert = client_params.signed_by(&client_key, &ca).unwrap();
+
+        let mut config = config::Config::default();
+        config.server.port = port;
+        config.server.sources = vec![ImageSource::Path(PathBuf::from("assets")).into()];
+        config.tls.cert = Some(dir.path().join("cert.pem"));
+        config.tls.key = Some(dir.path().join("key.pem"));
+        config.tls.client_ca = Some(dir.path().join("ca.pem"));
+        let server_cert = server.cert.pem();
+        let server = ImageServer::with_config(config);
+        let (mut terminator, interrupt_rx) = create_termination();
+        let running = tokio::spawn(async move { server.start(interrupt_rx).await });
+
+        let client = |identity: Option<reqwest::Identity>| {
+            let mut builder = reqwest::Client::builder()
+                .add_root_certificate(
+                    reqwest::Certificate::from_pem(server_cert.as_bytes()).unwrap(),
+                )
+                .resolve("localhost", ([127, 0, 0, 1], port).into());
+            if let Some(identity) = identity {
+                builder = builder.identity(identity);
+            }
+            builder.build().unwrap()
+        };
+        let authenticated = client(Some(
+            reqwest::Identity::from_pem(
+                format!("{}{}", client_cert.pem(), client_key.serialize_pem()).as_bytes(),
+            )
+            .unwrap(),
+        ));
+        let url = format!("https://localhost:{port}/livez");
+        let status = loop {
+            match authenticated.get(&url).send().await {
+                Ok(response) => break response.status(),
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+        assert_eq!(status, hyper::StatusCode::OK);
+        // clients without a certificate can't connect
+        assert!(client(None).get(&url).send().await.is_err());
+        drop(authenticated);
+
+        terminator.terminate(Interrupted::UserInt).unwrap();
+        running.await.unwrap().unwrap();
+    }
+
     #[rstest]
     #[tokio::test]
     #[timeout(std::time::Duration::from_secs(2))]
@@ -419,10 +4160,134 @@ mod tests {
         let mut server = ImageServer::default();
         let port = 0;
         server.config.server.port = port;
-        server.config.server.sources = vec![ImageSource::Path(PathBuf::from("assets"))];
+        server.config.server.sources = vec![ImageSource::Path(PathBuf::from("assets")).into()];
 
         let (mut terminator, interrupt_rx) = create_termination();
         terminator.terminate(Interrupted::UserInt).unwrap();
         server.start(interrupt_rx).await.unwrap();
     }
+
+    #[cfg(feature = "remote-sources")]
+    #[rstest]
+    #[tokio::test]
+    #[timeout(std::time::Duration::from_secs(5))]
+    async fn test_start_before_populate() {
+        // a source that accepts connections, but never responds
+        let stalled = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut config = config::Config::default();
+        config.server.port = port;
+        config.server.start_before_populate = true;
+        config.server.sources = vec![
+            ImageSource::Url(
+                format!("http://{}/image.jpg", stalled.local_addr().unwrap())
+                    .parse()
+                    .unwrap(),
+            )
+            .into(),
+        ];
+        let server = ImageServer::with_config(config);
+        let (mut terminator, interrupt_rx) = create_termination();
+        let running = tokio::spawn(async move { server.start(interrupt_rx).await });
+
+        // requests are accepted while the source is still loading, but the server isn't ready
+        let client = reqwest::Client::new();
+        let readiness = loop {
+            match client
+                .get(format!("http://127.0.0.1:{port}/readyz"))
+                .send()
+                .await
+            {
+                Ok(response) => break response,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+        assert_eq!(readiness.status(), hyper::StatusCode::SERVICE_UNAVAILABLE);
+        let health = client
+            .get(format!("http://127.0.0.1:{port}/health"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(health.status(), hyper::StatusCode::OK);
+        let health: serde_json::Value =
+            serde_json::from_slice(&health.bytes().await.unwrap()).unwrap();
+        assert_eq!(health["images"], 0);
+        drop(client);
+
+        // and stops without waiting for the source
+        terminator.terminate(Interrupted::UserInt).unwrap();
+        running.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_source_incremental() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().canonicalize().unwrap();
+        fs::copy("assets/blank.jpg", dir_path.join("a.jpg")).unwrap();
+
+        let mut config = config::Config::default();
+        config.server.sources = vec![ImageSource::Path(dir_path.clone()).into()];
+        let server = ImageServer::with_config(config);
+        server.populate_cache().await;
+
+        // a sync job adds one image, and deletes the other
+        fs::copy("assets/blank.jpg", dir_path.join("b.jpg")).unwrap();
+        fs::remove_file(dir_path.join("a.jpg")).unwrap();
+        let source = server.config.server.sources[0].clone();
+        reload_sources(&server.state, &[(0, &source)], true).await;
+
+        assert_eq!(
+            server.state.cache.keys().collect::<Vec<_>>(),
+            [cache::CacheKey::ImagePath(dir_path.join("b.jpg"))]
+        );
+        assert_eq!(server.state.sources()[0].images, 1);
+    }
+
+    #[tokio::test]
+    async fn test_revalidate_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().canonicalize().unwrap();
+        fs::copy("assets/blank.jpg", dir_path.join("a.jpg")).unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let key = cache::CacheKey::ImagePath(dir_path.join("a.jpg"));
+
+        let mut config = config::Config::default();
+        config.server.sources = vec![ImageSource::Path(dir_path.clone()).into()];
+        config.cache.backend = config::CacheBackendType::FileSystem;
+        config.cache.directory = Some(cache_dir.path().to_path_buf());
+        let server = ImageServer::with_config(config);
+        server.populate_cache().await;
+        let delay = std::time::Duration::ZERO;
+        assert!(revalidate_images(&server.state, delay).await.is_empty());
+
+        // the stored file is corrupted at rest
+        for entry in fs::read_dir(cache_dir.path()).unwrap() {
+            let path = entry.unwrap().path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "cache")
+            {
+                fs::write(path, b"corrupted").unwrap();
+            }
+        }
+        assert_eq!(
+            revalidate_images(&server.state, delay).await,
+            BTreeSet::from([0])
+        );
+        assert!(server.state.cache.is_empty());
+        assert_eq!(server.state.image_source_index(&key), None);
+
+        // so it is loaded again from its source
+        let source = server.config.server.sources[0].clone();
+        reload_sources(&server.state, &[(0, &source)], true).await;
+        assert_eq!(
+            server.state.cache.get(key).unwrap().data,
+            fs::read("assets/blank.jpg").unwrap()
+        );
+    }
 }
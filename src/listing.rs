@@ -0,0 +1,152 @@
+//! The images of the cache, as listed by the `list-images` command.
+
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::{cache::CacheBackend, state::ServerState};
+
+/// An image in the cache, along with where it was loaded from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListedImage {
+    /// The configured source the image was loaded from, prefixed by the name of its collection if it belongs to one
+    pub source: Option<String>,
+    /// The path or URL the image was loaded from
+    pub key: String,
+    /// The opaque identifier of the image, as served by `/image/{id}`
+    pub id: String,
+    /// The size of the image in bytes
+    pub size: u64,
+    pub content_type: String,
+}
+
+/// The images in the cache of the given state, in the order they were stored,
+/// with their sources prefixed by the name of the collection the state belongs to, if any
+pub fn list_images<C: CacheBackend>(
+    state: &ServerState<C>,
+    collection: Option<&str>,
+) -> Vec<ListedImage> {
+    state
+        .cache
+        .keys()
+        .filter_map(|key| {
+            let (content_type, size) = match state.cache.open(&key) {
+                Some(file) => (file.content_type, file.len),
+                None => {
+                    let image = state.cache.get(key.clone())?;
+                    (image.content_type, image.data.len() as u64)
+                }
+            };
+            let source = state.image_source(&key).map(|source| match collection {
+                Some(name) => format!("{name}: {source}"),
+                None => source.to_string(),
+            });
+            Some(ListedImage {
+                source,
+                id: state.image_id(&key),
+                key: key.to_string(),
+                size,
+                content_type,
+            })
+        })
+        .collect()
+}
+
+/// Format images as a table with a header row, and a column for each of their fields
+#[must_use]
+pub fn format_table(images: &[ListedImage]) -> String {
+    let rows: Vec<[String; 5]> =
+        std::iter::once(["SOURCE", "KEY", "ID", "SIZE", "CONTENT TYPE"].map(ToString::to_string))
+            .chain(images.iter().map(|image| {
+                [
+                    image.source.clone().unwrap_or_else(|| "-".to_string()),
+                    image.key.clone(),
+                    image.id.clone(),
+                    image.size.to_string(),
+                    image.content_type.clone(),
+                ]
+            }))
+            .collect();
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in &rows {
+        let line = row
+            .iter()
+            .zip(widths)
+            .fold(String::new(), |mut line, (cell, width)| {
+                let _ = write!(line, "{cell:width$}  ");
+                line
+            });
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ImageServer, config::Config};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_format_table() {
+        let images = [
+            ListedImage {
+                source: Some("/images".to_string()),
+                key: "/images/cat.jpg".to_string(),
+                id: "abc".to_string(),
+                size: 1234,
+                content_type: "image/jpeg".to_string(),
+            },
+            ListedImage {
+                source: None,
+                key: "https://example.com/dog.png".to_string(),
+                id: "def".to_string(),
+                size: 56,
+                content_type: "image/png".to_string(),
+            },
+        ];
+        assert_eq!(
+            format_table(&images),
+            "SOURCE   KEY                          ID   SIZE  CONTENT TYPE\n\
+             /images  /images/cat.jpg              abc  1234  image/jpeg\n\
+             -        https://example.com/dog.png  def  56    image/png\n"
+        );
+        assert_eq!(format_table(&[]), "SOURCE  KEY  ID  SIZE  CONTENT TYPE\n");
+    }
+
+    #[tokio::test]
+    async fn test_list_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().canonicalize().unwrap();
+        std::fs::copy("assets/blank.jpg", dir_path.join("a.jpg")).unwrap();
+
+        let mut config = Config::default();
+        config.server.sources = vec![crate::config::ImageSource::Path(dir_path.clone()).into()];
+        let server = ImageServer::with_config(config);
+        assert_eq!(server.list_images(), []);
+        server.populate_cache().await;
+
+        let key = dir_path.join("a.jpg");
+        let images = server.list_images();
+        assert_eq!(
+            images,
+            [ListedImage {
+                source: Some(dir_path.display().to_string()),
+                key: key.display().to_string(),
+                id: server
+                    .state
+                    .image_id(&crate::cache::CacheKey::ImagePath(key)),
+                size: std::fs::metadata("assets/blank.jpg").unwrap().len(),
+                content_type: "image/jpeg".to_string(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,381 @@
+//! Load testing a server, enabled by the `bench` feature.
+//!
+//! Requests are sent by a number of concurrent clients, at a target rate if one is set, and the throughput
+//! and latencies achieved are reported, so performance regressions (e.g. in the cache and its locks) are measurable.
+//! Without a URL, an in-process server is started as the target, serving the fixture images of `assets`.
+
+use std::{
+    fmt,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, anyhow};
+use url::Url;
+
+use crate::{
+    ImageServer,
+    config::{Config, ImageSource},
+    termination::{Interrupted, create_termination},
+};
+
+/// The number of concurrent clients, unless `--concurrency` is given
+pub const DEFAULT_CONCURRENCY: usize = 16;
+
+/// How long requests are sent for, unless `--duration` is given
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+
+/// The path requested from the in-process server, unless `--path` is given
+pub const DEFAULT_PATH: &str = "/random";
+
+/// The directory of images the in-process server serves, unless `--config` is given
+pub const FIXTURE_DIRECTORY: &str = "assets";
+
+/// Command line arguments of the load test
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadTestArgs {
+    /// The URL requested, or `None` to start an in-process server and request it
+    pub url: Option<Url>,
+    /// The path requested from the in-process server
+    pub path: String,
+    /// The config of the in-process server, which serves `assets` from memory if unset
+    pub config: Option<PathBuf>,
+    /// The target number of requests per second, across every client. Requests are sent as fast as possible if unset
+    pub rps: Option<u32>,
+    /// The number of clients sending requests at the same time
+    pub concurrency: usize,
+    /// How long requests are sent for
+    pub duration: Duration,
+    /// Whether usage information was requested
+    pub help: bool,
+}
+
+impl Default for LoadTestArgs {
+    fn default() -> Self {
+        Self {
+            url: None,
+            path: DEFAULT_PATH.to_string(),
+            config: None,
+            rps: None,
+            concurrency: DEFAULT_CONCURRENCY,
+            duration: DEFAULT_DURATION,
+            help: false,
+        }
+    }
+}
+
+impl LoadTestArgs {
+    /// Parse the command line arguments, excluding the program name
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an argument is unknown, missing its value, or has an invalid one.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| anyhow!("Missing value for {arg}"))
+            };
+            match arg.as_str() {
+                "-h" | "--help" => parsed.help = true,
+                "--path" => parsed.path = value()?,
+                "-c" | "--config" => parsed.config = Some(PathBuf::from(value()?)),
+                "--rps" => {
+                    parsed.rps = match value()?.parse()? {
+                        0 => {
+                            return Err(anyhow!(
+                                "The target rate must be at least 1 request per second"
+                            ));
+                        }
+                        rps => Some(rps),
+                    };
+                }
+                "--concurrency" => {
+                    parsed.concurrency = match value()?.parse()? {
+                        0 => return Err(anyhow!("The concurrency must be at least 1")),
+                        concurrency => concurrency,
+                    };
+                }
+                "--duration" => parsed.duration = crate::config::parse_duration(&value()?)?,
+                flag if flag.starts_with('-') => return Err(anyhow!("Unknown argument: {flag}")),
+                url => {
+                    let url = Url::parse(url).with_context(|| format!("Invalid URL: {url}"))?;
+                    if parsed.url.replace(url).is_some() {
+                        return Err(anyhow!("The URL can only be specified once"));
+                    }
+                }
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Usage information for the given program name
+    #[must_use]
+    pub fn usage(program: &str) -> String {
+        format!(
+            "Usage: {program} [<url>] [--rps <rate>] [--concurrency <clients>] [--duration <duration>] [--path <path>] [--config <config_file>]\n\n\
+             Sends requests to the URL, or to a server started in-process if none is given, and reports the throughput and latencies achieved.\n\n\
+             Options:\n      \
+             --rps <rate>            The target number of requests per second, across every client.\n                              \
+             Requests are sent as fast as possible if unset\n      \
+             --concurrency <clients> The number of clients sending requests at the same time. Defaults to {DEFAULT_CONCURRENCY}\n      \
+             --duration <duration>   How long requests are sent for, e.g. `30s`. Defaults to 10s\n      \
+             --path <path>           The path requested from the in-process server. Defaults to {DEFAULT_PATH}\n  \
+             -c, --config <config_file>  The config of the in-process server.\n                              \
+             Defaults to serving ./{FIXTURE_DIRECTORY} from memory\n  \
+             -h, --help                  Print this help message"
+        )
+    }
+}
+
+/// What a load test achieved
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadTestReport {
+    /// The latencies of the requests that succeeded, from the fastest to the slowest
+    pub latencies: Vec<Duration>,
+    /// The number of requests that failed, or were answered with an error status
+    pub failures: usize,
+    /// How long requests were sent for
+    pub elapsed: Duration,
+}
+
+impl LoadTestReport {
+    /// The number of requests sent
+    #[must_use]
+    pub const fn requests(&self) -> usize {
+        self.latencies.len() + self.failures
+    }
+
+    /// The number of requests that succeeded per second
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn throughput(&self) -> f64 {
+        let elapsed = self.elapsed.as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+        self.latencies.len() as f64 / elapsed
+    }
+
+    /// The latency under which the given percentage of successful requests were answered, if any succeeded
+    #[must_use]
+    pub fn percentile(&self, percent: u8) -> Option<Duration> {
+        let rank = (self.latencies.len() * usize::from(percent.min(100))).div_ceil(100);
+        self.latencies.get(rank.saturating_sub(1)).copied()
+    }
+}
+
+impl fmt::Display for LoadTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} requests in {:.1?}, {} failed, {:.1} requests per second",
+            self.requests(),
+            self.elapsed,
+            self.failures,
+            self.throughput()
+        )?;
+        for (name, percent) in [("p50", 50), ("p90", 90), ("p99", 99), ("max", 100)] {
+            if let Some(latency) = self.percentile(percent) {
+                write!(f, "\n  {name}: {latency:.2?}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run a load test against the URL of the arguments, or against a server started in-process
+///
+/// # Errors
+///
+/// Returns an error if the in-process server can't be configured, started, or stopped.
+pub async fn run(args: &LoadTestArgs) -> Result<LoadTestReport> {
+    if let Some(url) = &args.url {
+        return Ok(load(url, args).await);
+    }
+
+    let mut config = match &args.config {
+        Some(path) => Config::from_file(&path.to_string_lossy())?,
+        None => fixture_config(),
+    };
+    // the port is only free once the listener is dropped, but nothing else should take it meanwhile
+    config.server.port = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    config.server.host = url::Host::Ipv4(std::net::Ipv4Addr::LOCALHOST);
+    let base = Url::parse(&format!("http://127.0.0.1:{}", config.server.port))?;
+    let server = ImageServer::with_config(config);
+    let (mut terminator, interrupt_rx) = create_termination();
+    let running = tokio::spawn(async move { server.start(interrupt_rx).await });
+
+    let client = reqwest::Client::new();
+    let ready = base.join("/readyz")?;
+    while !client
+        .get(ready.clone())
+        .send()
+        .await
+        .is_ok_and(|response| response.status().is_success())
+    {
+        if running.is_finished() {
+            return Err(running
+                .await?
+                .err()
+                .unwrap_or_else(|| anyhow!("The server stopped before it was ready")));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let report = load(&base.join(&args.path)?, args).await;
+    terminator.terminate(Interrupted::UserInt)?;
+    running.await??;
+    Ok(report)
+}
+
+/// The configuration of the in-process server, serving the fixture images from memory
+#[must_use]
+pub fn fixture_config() -> Config {
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(PathBuf::from(FIXTURE_DIRECTORY)).into()];
+    config
+}
+
+/// Send requests to the URL with the concurrency, rate, and duration of the arguments
+async fn load(url: &Url, args: &LoadTestArgs) -> LoadTestReport {
+    let client = reqwest::Client::new();
+    let start = Instant::now();
+    let deadline = start + args.duration;
+    // each client sends its share of the target rate
+    let period = args
+        .rps
+        .map(|rps| Duration::from_secs(u64::try_from(args.concurrency).unwrap_or(u64::MAX)) / rps);
+
+    let clients: Vec<_> = (0..args.concurrency)
+        .map(|_| {
+            let (client, url) = (client.clone(), url.clone());
+            tokio::spawn(async move {
+                let mut interval = period.map(|period| {
+                    let mut interval = tokio::time::interval(period);
+                    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                    interval
+                });
+                let mut report = LoadTestReport::default();
+                while Instant::now() < deadline {
+                    if let Some(interval) = &mut interval {
+                        interval.tick().await;
+                    }
+                    let sent = Instant::now();
+                    let answered = match client.get(url.clone()).send().await {
+                        Ok(response) if response.status().is_success() => {
+                            response.bytes().await.is_ok()
+                        }
+                        Ok(_) | Err(_) => false,
+                    };
+                    if answered {
+                        report.latencies.push(sent.elapsed());
+                    } else {
+                        report.failures += 1;
+                    }
+                }
+                report
+            })
+        })
+        .collect();
+
+    let mut report = LoadTestReport::default();
+    for client in clients {
+        match client.await {
+            Ok(client) => {
+                report.latencies.extend(client.latencies);
+                report.failures += client.failures;
+            }
+            Err(e) => tracing::error!("A load test client failed: {e}"),
+        }
+    }
+    report.latencies.sort_unstable();
+    report.elapsed = start.elapsed();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    fn args(args: &[&str]) -> Result<LoadTestArgs> {
+        LoadTestArgs::parse(args.iter().map(ToString::to_string))
+    }
+
+    #[rstest]
+    #[case::none(&[], LoadTestArgs::default())]
+    #[case::help(&["--help"], LoadTestArgs { help: true, ..LoadTestArgs::default() })]
+    #[case::url(&["http://localhost:8080/random"], LoadTestArgs { url: Some("http://localhost:8080/random".parse().unwrap()), ..LoadTestArgs::default() })]
+    #[case::options(
+        &["--rps", "200", "--concurrency", "4", "--duration", "1m", "--path", "/sequential", "--config", "config.toml"],
+        LoadTestArgs {
+            path: "/sequential".to_string(),
+            config: Some("config.toml".into()),
+            rps: Some(200),
+            concurrency: 4,
+            duration: Duration::from_secs(60),
+            ..LoadTestArgs::default()
+        }
+    )]
+    fn test_parse_args(#[case] input: &[&str], #[case] expected: LoadTestArgs) {
+        assert_eq!(args(input).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case::missing_value(&["--rps"])]
+    #[case::zero_rps(&["--rps", "0"])]
+    #[case::zero_concurrency(&["--concurrency", "0"])]
+    #[case::invalid_duration(&["--duration", "soon"])]
+    #[case::unknown_flag(&["--verbose"])]
+    #[case::invalid_url(&["localhost"])]
+    #[case::url_twice(&["http://a.example", "http://b.example"])]
+    fn test_parse_args_invalid(#[case] input: &[&str]) {
+        assert!(args(input).is_err());
+    }
+
+    #[test]
+    fn test_report() {
+        let report = LoadTestReport {
+            latencies: (1..=10).map(Duration::from_millis).collect(),
+            failures: 2,
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(report.requests(), 12);
+        assert!((report.throughput() - 5.0).abs() < f64::EPSILON);
+        assert_eq!(report.percentile(50), Some(Duration::from_millis(5)));
+        assert_eq!(report.percentile(90), Some(Duration::from_millis(9)));
+        assert_eq!(report.percentile(100), Some(Duration::from_millis(10)));
+        assert_eq!(report.percentile(0), Some(Duration::from_millis(1)));
+        assert!(
+            report
+                .to_string()
+                .starts_with("12 requests in 2.0s, 2 failed, 5.0 requests per second")
+        );
+
+        let empty = LoadTestReport::default();
+        assert_eq!(empty.percentile(50), None);
+        assert!((empty.throughput()).abs() < f64::EPSILON);
+    }
+
+    #[tokio::test]
+    async fn test_run_fixture() {
+        let report = run(&LoadTestArgs {
+            rps: Some(100),
+            concurrency: 2,
+            duration: Duration::from_millis(300),
+            ..LoadTestArgs::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(report.failures, 0);
+        assert!(!report.latencies.is_empty());
+        // the target rate is never exceeded
+        assert!(report.requests() <= 40, "{report}");
+    }
+}
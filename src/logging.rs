@@ -1,25 +1,226 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
 use anyhow::{Result, anyhow};
-use tracing::Level;
-use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{EnvFilter, filter::LevelFilter, fmt::format::FmtSpan};
+
+use crate::config::{LogRotation, ServerConfig};
+
+/// The number of rotated log files kept when rotating by size
+const MAX_LOG_BACKUPS: usize = 5;
 
 /// Initialize the global tracing subscriber based on configuration
 ///
+/// Logs are written to stdout, or to `log_file` (rotated according to `log_rotation`) if configured.
+/// The `RUST_LOG` environment variable, if set, overrides `log_level`, e.g. `RUST_LOG=random_image_server=debug`.
+/// Once debug logs are enabled, the time spent in each span (requests, cache lookups, and fetches) is logged
+/// as it closes.
+///
+/// When logging to a file, the returned guard must be held for as long as logs should be written.
+///
 /// # Errors
-/// Returns an error if the subscriber cannot be initialized.
-pub fn init_logging(level: Level) -> Result<()> {
-    // Simple stdout-only logging using tracing-subscriber
-    tracing_subscriber::fmt()
-        .with_max_level(level)
-        .with_span_events(FmtSpan::NONE)
+/// Returns an error if the log file cannot be opened, or the subscriber cannot be initialized.
+pub fn init_logging(config: &ServerConfig) -> Result<Option<WorkerGuard>> {
+    let level = config.log_level;
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::from_level(level).into())
+        .from_env_lossy();
+    let span_events = if filter
+        .max_level_hint()
+        .is_none_or(|max| max >= LevelFilter::DEBUG)
+    {
+        FmtSpan::CLOSE
+    } else {
+        FmtSpan::NONE
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(span_events)
         .with_target(true)
         .with_thread_ids(false)
         .with_thread_names(false)
         .with_file(true)
-        .with_line_number(true)
-        .try_init()
-        .map_err(|e| anyhow!("Failed to initialize tracing subscriber: {e}"))?;
+        .with_line_number(true);
+
+    let guard = if let Some(log_file) = &config.log_file {
+        let writer = log_file_writer(log_file, config.log_rotation, config.log_max_size)?;
+        let (writer, guard) = tracing_appender::non_blocking(writer);
+        builder
+            .with_writer(writer)
+            .with_ansi(false)
+            .try_init()
+            .map_err(|e| anyhow!("Failed to initialize tracing subscriber: {e}"))?;
+        Some(guard)
+    } else {
+        // Simple stdout-only logging using tracing-subscriber
+        builder
+            .try_init()
+            .map_err(|e| anyhow!("Failed to initialize tracing subscriber: {e}"))?;
+        None
+    };
+
+    tracing::info!(
+        "Logging initialized: level={level:?}, file={:?}, rotation={:?}",
+        config.log_file,
+        config.log_rotation
+    );
+
+    Ok(guard)
+}
+
+/// Create a writer for the given log file that rotates according to the given policy
+fn log_file_writer(
+    log_file: &Path,
+    rotation: LogRotation,
+    max_size: u64,
+) -> Result<Box<dyn Write + Send>> {
+    let directory = log_file
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let file_name = log_file
+        .file_name()
+        .ok_or_else(|| anyhow!("Log file has no file name: {}", log_file.display()))?;
+    fs::create_dir_all(directory).map_err(|e| {
+        anyhow!(
+            "Failed to create log directory {}: {e}",
+            directory.display()
+        )
+    })?;
+
+    Ok(match rotation {
+        LogRotation::Daily => Box::new(tracing_appender::rolling::daily(directory, file_name)),
+        LogRotation::Hourly => Box::new(tracing_appender::rolling::hourly(directory, file_name)),
+        LogRotation::Size => Box::new(
+            SizeRollingWriter::new(log_file.to_path_buf(), max_size)
+                .map_err(|e| anyhow!("Failed to open log file {}: {e}", log_file.display()))?,
+        ),
+    })
+}
+
+/// A writer that rotates the underlying file once it exceeds a maximum size
+///
+/// Rotated files are renamed to `<file>.1`, `<file>.2`, ..., keeping at most `MAX_LOG_BACKUPS`.
+#[derive(Debug)]
+pub struct SizeRollingWriter {
+    path: PathBuf,
+    max_size: u64,
+    file: File,
+    written: u64,
+}
+
+impl SizeRollingWriter {
+    /// Open (or create) the log file at the given path
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened.
+    pub fn new(path: PathBuf, max_size: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            file,
+            written,
+        })
+    }
+
+    /// The path of the `index`th rotated log file
+    fn backup_path(&self, index: usize) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(format!(".{index}"));
+        PathBuf::from(path)
+    }
+
+    /// Shift the rotated log files, and start a new log file
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        for index in (1..MAX_LOG_BACKUPS).rev() {
+            let from = self.backup_path(index);
+            if from.exists() {
+                fs::rename(from, self.backup_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.backup_path(1))?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRollingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_size_rolling_writer_rotates() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("server.log");
+
+        let mut writer = SizeRollingWriter::new(path.clone(), 10).unwrap();
+        writer.write_all(b"0123456789").unwrap();
+        writer.write_all(b"abcdef").unwrap();
+        writer.write_all(b"ghijklmnop").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ghijklmnop");
+        assert_eq!(fs::read_to_string(writer.backup_path(1)).unwrap(), "abcdef");
+        assert_eq!(
+            fs::read_to_string(writer.backup_path(2)).unwrap(),
+            "0123456789"
+        );
+    }
+
+    #[test]
+    fn test_size_rolling_writer_keeps_max_backups() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("server.log");
+
+        let mut writer = SizeRollingWriter::new(path, 1).unwrap();
+        for _ in 0..=MAX_LOG_BACKUPS + 2 {
+            writer.write_all(b"log").unwrap();
+        }
+
+        assert!(writer.backup_path(MAX_LOG_BACKUPS).exists());
+        assert!(!writer.backup_path(MAX_LOG_BACKUPS + 1).exists());
+    }
+
+    #[test]
+    fn test_size_rolling_writer_appends_to_existing_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("server.log");
+        fs::write(&path, "existing").unwrap();
 
-    tracing::info!("Logging initialized: level={level:?}");
+        let mut writer = SizeRollingWriter::new(path.clone(), 100).unwrap();
+        writer.write_all(b" more").unwrap();
 
-    Ok(())
+        assert_eq!(fs::read_to_string(&path).unwrap(), "existing more");
+        assert_eq!(writer.written, 13);
+    }
 }
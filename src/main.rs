@@ -1,61 +1,259 @@
+use std::{path::PathBuf, process::ExitCode};
+
 use random_image_server::{
-    ImageServer,
-    config::Config,
-    termination::{Interrupted, create_termination},
+    ImageServer, StartupError,
+    cli::{Args, Command, DEFAULT_CONFIG_FILE},
+    completions,
+    config::{Config, ConfigFormat},
+    default_config::default_config_toml,
+    env::StdEnvBackend,
+    listing::format_table,
+    man,
+    signing::signed_link,
+    termination::{Interrupted, Terminator, create_termination},
+    validate::validate_config,
 };
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+#[cfg(unix)]
+use random_image_server::daemon;
+#[cfg(windows)]
+use random_image_server::service;
+use tokio::sync::broadcast;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // parse command line arguments
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() > 2 {
-        eprintln!("Usage: {} [config_file]", args[0]);
-        return Ok(());
+fn main() -> ExitCode {
+    match try_main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(StartupError::exit_code_of(&e))
+        }
     }
-    let config_file = if args.len() == 2 {
-        if args[1] == "--help" || args[1] == "-h" {
-            eprintln!("Usage: {} [config_file]", args[0]);
+}
+
+fn try_main() -> Result<()> {
+    // parse command line arguments
+    let mut args = std::env::args();
+    let program = args.next().unwrap_or_default();
+    let args = match Args::parse(args) {
+        Ok(args) if !args.help => args,
+        Ok(_) => {
+            eprintln!("{}", Args::usage(&program));
             return Ok(());
         }
-        let path = std::path::Path::new(&args[1]);
-        if !path.exists() {
-            eprintln!("Config file does not exist: {}", args[1]);
+        Err(e) => {
+            return Err(StartupError::Usage(format!("{e}\n\n{}", Args::usage(&program))).into());
+        }
+    };
+    match args.command {
+        Command::PrintDefaultConfig => {
+            print!("{}", default_config_toml()?);
             return Ok(());
         }
-        if !path.is_file() {
-            eprintln!("Config file must be a regular file: {}", args[1]);
+        Command::PrintConfigSchema => {
+            println!("{}", serde_json::to_string_pretty(&Config::json_schema())?);
+            return Ok(());
+        }
+        Command::Completions => {
+            if let Some(shell) = args.shell {
+                print!("{}", completions::script(shell, env!("CARGO_PKG_NAME")));
+            }
             return Ok(());
         }
-        if path
-            .extension()
-            .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"))
-        {
-            &args[1]
-        } else {
-            eprintln!("Config file must be a .toml file");
+        Command::Man => {
+            print!("{}", man::page(env!("CARGO_PKG_NAME")));
+            return Ok(());
+        }
+        Command::Serve
+        | Command::Validate
+        | Command::ExportCache
+        | Command::ImportCache
+        | Command::Prefetch
+        | Command::ListImages
+        | Command::SignUrl
+        | Command::InstallService
+        | Command::UninstallService => {}
+    }
+
+    #[cfg(windows)]
+    match args.command {
+        Command::InstallService => {
+            service::install(args.config.as_deref(), args.env_file.as_deref())?;
+            println!("Installed the {} service", service::SERVICE_NAME);
             return Ok(());
         }
+        Command::UninstallService => {
+            service::uninstall()?;
+            println!("Uninstalled the {} service", service::SERVICE_NAME);
+            return Ok(());
+        }
+        _ if args.service => {
+            return service::run(Box::new(move |terminator, interrupt_rx| {
+                Box::pin(run(args, Some((terminator, interrupt_rx))))
+            }));
+        }
+        _ => {}
+    }
+    #[cfg(not(windows))]
+    if args.service
+        || matches!(
+            args.command,
+            Command::InstallService | Command::UninstallService
+        )
+    {
+        return Err(anyhow!("Windows services are only supported on Windows"));
+    }
+
+    #[cfg(unix)]
+    if args.daemon && args.command.serves() {
+        daemon::daemonize()?;
+    }
+    #[cfg(not(unix))]
+    if args.daemon || args.pidfile.is_some() {
+        return Err(anyhow!("--daemon and --pidfile are only supported on Unix"));
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(args, None))
+}
+
+/// Load the configuration, then do what the arguments ask
+///
+/// Images are served until the termination broadcast is sent, by signals unless another broadcast is given,
+/// e.g. that of the Windows service.
+async fn run(
+    args: Args,
+    termination: Option<(Terminator, broadcast::Receiver<Interrupted>)>,
+) -> Result<()> {
+    let std_env = StdEnvBackend;
+    let env = args.env_backend(&std_env).map_err(config_error)?;
+    let config_file = if let Some(path) = args.config_file_with_env_backend(&env) {
+        if !path.exists() {
+            return Err(StartupError::Config(format!(
+                "Config file does not exist: {}",
+                path.display()
+            ))
+            .into());
+        }
+        if !path.is_file() {
+            return Err(StartupError::Config(format!(
+                "Config file must be a regular file: {}",
+                path.display()
+            ))
+            .into());
+        }
+        if ConfigFormat::from_path(&path).is_none() {
+            return Err(StartupError::Config(
+                "Config file must be a .toml, .yaml, .yml, or .json file".to_string(),
+            )
+            .into());
+        }
+        path
     } else {
-        "config.toml"
+        PathBuf::from(DEFAULT_CONFIG_FILE)
     };
 
     // Try to load config from file, fall back to default if not found
-    let config = Config::from_file(config_file).unwrap_or_else(|e| {
-        eprintln!("Warning: Could not load config.toml ({e}), using defaults");
-        Config::default()
-    });
-    let config = config.with_env()?;
+    let config = match Config::from_file(&config_file.to_string_lossy()) {
+        Ok(config) => config,
+        // a config file that exists but is invalid is an error, rather than silently ignored
+        Err(e) if args.command == Command::Validate || config_file.exists() => {
+            return Err(StartupError::Config(format!(
+                "Could not load {} ({e})",
+                config_file.display()
+            ))
+            .into());
+        }
+        Err(e) => {
+            eprintln!(
+                "Warning: Could not load {} ({e}), using defaults",
+                config_file.display()
+            );
+            Config::default()
+        }
+    };
+    let config = config.with_env_backend(&env).map_err(config_error)?;
+
+    if args.command == Command::Validate {
+        let report = validate_config(&config).await;
+        println!("{report}");
+        if !report.is_valid() {
+            return Err(StartupError::Config("The configuration is invalid".to_string()).into());
+        }
+        return Ok(());
+    }
+
+    if args.command == Command::ListImages {
+        return list_images(config, args.json).await;
+    }
+
+    if let (Command::SignUrl, Some(image_id), Some(expires_in)) =
+        (args.command, &args.image_id, args.expires_in)
+    {
+        let link = signed_link(&config, image_id, expires_in)
+            .map_err(|e| StartupError::Config(format!("Could not sign a link: {e}")))?;
+        println!("{link}");
+        return Ok(());
+    }
+
+    if args.command == Command::Prefetch && !config.cache.is_persistent() {
+        return Err(StartupError::Config(
+            "Prefetching needs a persistent cache: set `cache.directory` with the file_system, tiered, or sled backend, or `cache.sled_path`".to_string(),
+        )
+        .into());
+    }
 
     // Initialize logging based on config
-    random_image_server::init_logging(config.server.log_level)?;
+    let _log_guard = random_image_server::init_logging(&config.server)?;
+    let _observability_guard = random_image_server::observability::init(&config.observability);
+    #[cfg(unix)]
+    let _pidfile = match &args.pidfile {
+        Some(path) if args.command.serves() => {
+            Some(daemon::PidFile::create(path).inspect_err(|e| tracing::error!("{e}"))?)
+        }
+        _ => None,
+    };
 
     // Create and start the server
     let server = ImageServer::with_config(config);
 
+    match (args.command, &args.snapshot) {
+        (Command::ExportCache, Some(snapshot)) => {
+            server.populate_cache().await;
+            let count = server.export_cache(snapshot)?;
+            println!("Exported {count} image(s) to {}", snapshot.display());
+            return Ok(());
+        }
+        (Command::ImportCache, Some(snapshot)) => {
+            server.import_cache(snapshot)?;
+        }
+        (Command::Prefetch, _) => {
+            let report = server.populate_cache().await;
+            println!("{report}");
+            for source in report.sources.iter().filter(|source| source.errors > 0) {
+                eprintln!(
+                    "{}: {} error(s), the last: {}",
+                    source.source,
+                    source.errors,
+                    source.last_error.as_deref().unwrap_or("unknown")
+                );
+            }
+            // returning, rather than exiting, lets the cache flush what it stored
+            if report.failed_sources() > 0 {
+                return Err(anyhow!(
+                    "{} source(s) failed to prefetch",
+                    report.failed_sources()
+                ));
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
     // Create a termination handler to gracefully shut down the server
-    let (_terminator, mut interrupt_rx) = create_termination();
+    let (_terminator, mut interrupt_rx) = termination.unwrap_or_else(create_termination);
 
     if let Err(e) = server.start(interrupt_rx.resubscribe()).await {
         tracing::error!("Server encountered an unexpected error: {e}");
@@ -69,6 +267,7 @@ async fn main() -> Result<()> {
             Interrupted::OsSigInt => tracing::info!("exited because of an os sig int"),
             Interrupted::OsSigTerm => tracing::info!("exited because of an os sig term"),
             Interrupted::OsSigQuit => tracing::info!("exited because of an os sig quit"),
+            Interrupted::ServiceStop => tracing::info!("exited because the service was stopped"),
         }
     } else {
         tracing::error!("exited because of an unexpected error");
@@ -76,3 +275,34 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Load the images from the configured sources, then print them as a table or JSON
+///
+/// Logging isn't initialized, so that the output isn't interleaved with logs, and webhooks aren't notified.
+/// A summary of populating the cache is printed to stderr.
+async fn list_images(mut config: Config, json: bool) -> Result<()> {
+    config.notifications.webhook_url = None;
+    let server = ImageServer::with_config(config);
+    let report = server.populate_cache().await;
+    let images = server.list_images();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&images)?);
+    } else {
+        print!("{}", format_table(&images));
+    }
+    eprintln!("{report}");
+    for source in report.sources.iter().filter(|source| source.errors > 0) {
+        eprintln!(
+            "{}: {} error(s), the last: {}",
+            source.source,
+            source.errors,
+            source.last_error.as_deref().unwrap_or("unknown")
+        );
+    }
+    Ok(())
+}
+
+/// Mark an error loading the configuration as such, so the process exits with the code of config errors
+fn config_error(error: anyhow::Error) -> anyhow::Error {
+    StartupError::Config(error.to_string()).into()
+}
@@ -0,0 +1,127 @@
+//! The man page of the server, generated from the commands and options documented in [`cli`](crate::cli).
+
+use std::fmt::Write;
+
+use crate::cli::{COMMANDS, CONFIG_ENV_VAR, DEFAULT_CONFIG_FILE, DEFAULT_ENV_FILE, OPTIONS};
+
+/// Escape text for roff, so that it's printed as it is
+fn escape(text: &str) -> String {
+    let escaped = text.replace('\\', r"\e").replace('-', r"\-");
+    if escaped.starts_with(['.', '\'']) {
+        format!(r"\&{escaped}")
+    } else {
+        escaped
+    }
+}
+
+/// The man page, in roff, of the program with the given name
+#[must_use]
+pub fn page(program: &str) -> String {
+    let name = escape(program);
+    let mut page = format!(
+        ".TH {} 1 \"\" \"{name} {}\" \"User Commands\"\n\
+         .SH NAME\n\
+         {name} \\- {}\n\
+         .SH SYNOPSIS\n\
+         .B {name}\n\
+         [\\fIcommand\\fR] [\\fIoptions\\fR] [\\fIconfig_file\\fR]\n\
+         .SH DESCRIPTION\n\
+         Without a command, {name} loads the images of the sources of its config file, \
+         then serves them over HTTP until it's interrupted.\n\
+         .SH COMMANDS\n",
+        escape(&program.to_uppercase()),
+        env!("CARGO_PKG_VERSION"),
+        escape(env!("CARGO_PKG_DESCRIPTION")),
+    );
+    for command in COMMANDS {
+        let args = command
+            .args
+            .iter()
+            .map(|arg| format!(" \\fI{}\\fR", escape(arg.name)))
+            .collect::<String>();
+        let _ = writeln!(
+            page,
+            ".TP\n\\fB{}\\fR{args}\n{}",
+            escape(command.name),
+            escape(&command.help.join(" "))
+        );
+    }
+    page.push_str(".SH OPTIONS\n");
+    for option in OPTIONS {
+        let short = option
+            .short
+            .map(|short| format!("\\fB\\-{short}\\fR, "))
+            .unwrap_or_default();
+        let value = option
+            .value
+            .map(|value| format!(" \\fI{}\\fR", escape(value.name)))
+            .unwrap_or_default();
+        let _ = writeln!(
+            page,
+            ".TP\n{short}\\fB\\-\\-{}\\fR{value}\n{}",
+            escape(option.long),
+            escape(&option.help.join(" "))
+        );
+    }
+    let _ = write!(
+        page,
+        ".SH ENVIRONMENT\n\
+         .TP\n\
+         {}\n\
+         The config file to use, unless one is passed as an argument.\n\
+         .TP\n\
+         RANDOM_IMAGE_SERVER_*\n\
+         Override settings of the config file, e.g. RANDOM_IMAGE_SERVER_PORT. \
+         Run \\fB{name} print\\-default\\-config\\fR for every setting.\n\
+         .SH FILES\n\
+         .TP\n\
+         {}\n\
+         The config file used if none is passed, or set in the environment.\n\
+         .TP\n\
+         {}\n\
+         Environment variables loaded at startup, unless another file is passed with \\fB\\-\\-env\\-file\\fR.\n",
+        escape(CONFIG_ENV_VAR),
+        escape(DEFAULT_CONFIG_FILE),
+        escape(DEFAULT_ENV_FILE),
+    );
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("print-default-config"), r"print\-default\-config");
+        assert_eq!(escape(r"a\b"), r"a\eb");
+        assert_eq!(escape(".env"), r"\&.env");
+    }
+
+    #[test]
+    fn test_page() {
+        let page = page("random-image-server");
+        assert!(page.starts_with(&format!(
+            ".TH RANDOM\\-IMAGE\\-SERVER 1 \"\" \"random\\-image\\-server {}\" \"User Commands\"\n.SH NAME\n",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(page.contains(
+            "\n.TP\n\\fBexport\\-cache\\fR \\fIarchive\\fR\nPopulate the cache, then write a snapshot of it to the archive\n"
+        ));
+        assert!(page.contains(
+            "\n.TP\n\\fB\\-c\\fR, \\fB\\-\\-config\\fR \\fIconfig_file\\fR\nThe config file to use"
+        ));
+        assert!(page.contains("\n.TP\n\\fB\\-\\-json\\fR\nPrint JSON rather than a table"));
+        // every line is either a request or text, never text mistaken for a request
+        for line in page.lines() {
+            assert!(!line.starts_with('\''), "{line}");
+            if let Some(request) = line.strip_prefix('.') {
+                assert!(
+                    ["TH", "SH", "TP", "B"].contains(&request.split(' ').next().unwrap()),
+                    "{line}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Manifest sources: remote files listing the URLs of images to serve.
+//!
+//! A manifest is either a JSON array of URLs (or an object with an `images` array of URLs),
+//! or a text file with one URL per line, ignoring blank lines and lines starting with `#`.
+//! Relative URLs are resolved against the URL of the manifest.
+
+#[cfg(feature = "remote-sources")]
+use std::collections::BTreeMap;
+
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use url::Url;
+
+/// A manifest in the JSON format
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonManifest {
+    List(Vec<String>),
+    Object { images: Vec<String> },
+}
+
+/// Parse the URLs listed in a manifest fetched from `base`
+///
+/// The manifest is parsed as JSON if its content type says so, or it looks like JSON.
+///
+/// # Errors
+///
+/// Returns an error if the manifest is invalid JSON, or lists an invalid URL.
+pub fn parse_manifest(base: &Url, content_type: Option<&str>, body: &str) -> Result<Vec<Url>> {
+    let trimmed = body.trim_start();
+    let is_json = content_type.is_some_and(|content_type| content_type.contains("json"))
+        || trimmed.starts_with('[')
+        || trimmed.starts_with('{');
+
+    let entries = if is_json {
+        match serde_json::from_str(body).map_err(|e| anyhow!("Invalid JSON manifest: {e}"))? {
+            JsonManifest::List(images) | JsonManifest::Object { images } => images,
+        }
+    } else {
+        body.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(ToString::to_string)
+            .collect()
+    };
+
+    entries
+        .iter()
+        .map(|entry| {
+            base.join(entry)
+                .map_err(|e| anyhow!("Invalid URL in manifest: {entry}: {e}"))
+        })
+        .collect()
+}
+
+/// Fetch a manifest with the given client, sending the given HTTP headers, and return the URLs it lists
+///
+/// # Errors
+///
+/// Returns an error if the manifest cannot be fetched or parsed.
+#[cfg(feature = "remote-sources")]
+#[tracing::instrument(level = "debug", skip_all, fields(url = %url))]
+pub async fn fetch_manifest(
+    client: &reqwest::Client,
+    url: &Url,
+    headers: &BTreeMap<String, String>,
+) -> Result<Vec<Url>> {
+    let response = headers
+        .iter()
+        .fold(client.get(url.as_str()), |request, (name, value)| {
+            request.header(name, value)
+        })
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to fetch manifest: {e}"))?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Failed to fetch manifest, status: {}",
+            response.status()
+        ));
+    }
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+    let body = response
+        .text()
+        .await
+        .map_err(|e| anyhow!("Failed to read manifest: {e}"))?;
+
+    parse_manifest(url, content_type.as_deref(), &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::text(None, "https://example.com/a.jpg\n\n# comment\n  b.png  \n")]
+    #[case::json_list(None, r#"["https://example.com/a.jpg", "b.png"]"#)]
+    #[case::json_object(None, r#"{"images": ["https://example.com/a.jpg", "/images/b.png"]}"#)]
+    #[case::json_content_type(Some("application/json"), r#" ["a.jpg", "b.png"]"#)]
+    fn test_parse_manifest(#[case] content_type: Option<&str>, #[case] body: &str) {
+        let base = Url::parse("https://example.com/images/manifest.txt").unwrap();
+        let urls = parse_manifest(&base, content_type, body).unwrap();
+
+        assert_eq!(urls.len(), 2);
+        assert_eq!(urls[0].host_str(), Some("example.com"));
+        assert_eq!(urls[1].as_str(), "https://example.com/images/b.png");
+    }
+
+    #[rstest]
+    #[case::invalid_json(Some("application/json"), "not json")]
+    #[case::invalid_url(None, "https://[invalid")]
+    fn test_parse_manifest_invalid(#[case] content_type: Option<&str>, #[case] body: &str) {
+        let base = Url::parse("https://example.com/manifest.txt").unwrap();
+        assert!(parse_manifest(&base, content_type, body).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+//! Request and cache metrics, exposed in the Prometheus text format at `/metrics`,
+//! and optionally pushed to a statsd (or Datadog) agent.
+
+use std::{
+    fmt::Write,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use hyper::StatusCode;
+use tokio::net::UdpSocket;
+
+use crate::{
+    cache::{CacheBackend, CacheStats},
+    config::MetricsConfig,
+    state::ServerState,
+};
+
+/// The status classes responses are counted by
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+/// Counters of the responses sent by the server
+#[derive(Debug, Default)]
+pub struct RequestMetrics {
+    responses: [AtomicU64; 5],
+}
+
+impl RequestMetrics {
+    /// Record that a response with the given status was sent
+    pub fn record_response(&self, status: StatusCode) {
+        let class = usize::from(status.as_u16() / 100).clamp(1, 5) - 1;
+        self.responses[class].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of responses sent, by status class
+    #[must_use]
+    pub fn responses(&self) -> [u64; 5] {
+        std::array::from_fn(|class| self.responses[class].load(Ordering::Relaxed))
+    }
+}
+
+/// A point-in-time view of the server's metrics
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// The number of responses sent, by status class (1xx through 5xx)
+    pub responses: [u64; 5],
+    /// The number of images served
+    pub images_served: u64,
+    /// The number of images in the cache
+    pub cache_images: usize,
+    /// The type of the cache backend, that `cache` is labeled with
+    pub cache_backend: &'static str,
+    /// The lookups, failed integrity checks, and evictions of the cache
+    pub cache: CacheStats,
+}
+
+impl MetricsSnapshot {
+    /// Take a snapshot of the metrics of the given server state
+    #[must_use]
+    pub fn from_state<C: CacheBackend>(state: &ServerState<C>) -> Self {
+        Self {
+            responses: state.metrics.responses(),
+            images_served: state.serve_counts.total(),
+            cache_images: state.cache.size(),
+            cache_backend: state.cache.backend_type(),
+            cache: state.cache.stats(),
+        }
+    }
+
+    /// The cache counters, with their names and descriptions
+    fn cache_counters(&self) -> [(&'static str, &'static str, u64); 4] {
+        [
+            (
+                "hits",
+                "Cache lookups that found the image",
+                self.cache.hits,
+            ),
+            (
+                "misses",
+                "Cache lookups that didn't find the image",
+                self.cache.misses,
+            ),
+            (
+                "validation_failures",
+                "Cached images that failed their integrity check, and were dropped",
+                self.cache.validation_failures,
+            ),
+            (
+                "evictions",
+                "Cached images removed to make room for others",
+                self.cache.evictions,
+            ),
+        ]
+    }
+
+    /// Render the metrics in the Prometheus text exposition format
+    #[must_use]
+    pub fn to_prometheus(&self) -> String {
+        let mut output = String::new();
+        output.push_str(
+            "# HELP random_image_server_responses_total Responses sent, by status class\n",
+        );
+        output.push_str("# TYPE random_image_server_responses_total counter\n");
+        for (class, count) in STATUS_CLASSES.iter().zip(self.responses) {
+            let _ = writeln!(
+                output,
+                "random_image_server_responses_total{{status=\"{class}\"}} {count}"
+            );
+        }
+        let _ = writeln!(
+            output,
+            "# HELP random_image_server_images_served_total Images served\n\
+             # TYPE random_image_server_images_served_total counter\n\
+             random_image_server_images_served_total {}",
+            self.images_served
+        );
+        let _ = writeln!(
+            output,
+            "# HELP random_image_server_cache_images Images in the cache\n\
+             # TYPE random_image_server_cache_images gauge\n\
+             random_image_server_cache_images {}",
+            self.cache_images
+        );
+        for (name, help, count) in self.cache_counters() {
+            let _ = writeln!(
+                output,
+                "# HELP random_image_server_cache_{name}_total {help}\n\
+                 # TYPE random_image_server_cache_{name}_total counter\n\
+                 random_image_server_cache_{name}_total{{backend=\"{}\"}} {count}",
+                self.cache_backend
+            );
+        }
+        output
+    }
+
+    /// Render the change in metrics since `previous` as statsd lines
+    ///
+    /// Counters are sent as the increase since the previous flush, gauges as their current value.
+    #[must_use]
+    pub fn to_statsd(&self, prefix: &str, previous: &Self) -> Vec<String> {
+        let prefix = if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{prefix}.")
+        };
+
+        let mut lines: Vec<String> = STATUS_CLASSES
+            .iter()
+            .zip(self.responses.iter().zip(previous.responses))
+            .map(|(class, (count, previous))| {
+                format!(
+                    "{prefix}responses.{class}:{}|c",
+                    count.saturating_sub(previous)
+                )
+            })
+            .collect();
+        lines.push(format!(
+            "{prefix}images_served:{}|c",
+            self.images_served.saturating_sub(previous.images_served)
+        ));
+        lines.push(format!("{prefix}cache.images:{}|g", self.cache_images));
+        for ((name, _, count), (_, _, previous)) in self
+            .cache_counters()
+            .into_iter()
+            .zip(previous.cache_counters())
+        {
+            lines.push(format!(
+                "{prefix}cache.{name}:{}|c",
+                count.saturating_sub(previous)
+            ));
+        }
+        lines
+    }
+}
+
+/// Periodically push the server's metrics to the configured statsd agent
+///
+/// Runs until the task is aborted. Does nothing if no statsd host is configured.
+///
+/// # Errors
+///
+/// Returns an error if the socket cannot be bound, or the statsd host cannot be resolved.
+pub async fn run_statsd_exporter<C: CacheBackend>(
+    config: MetricsConfig,
+    state: Arc<ServerState<C>>,
+) -> Result<()> {
+    let Some(host) = config.statsd_host else {
+        return Ok(());
+    };
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect((host.as_str(), config.statsd_port)).await?;
+    tracing::info!(
+        "Pushing metrics to StatsD at {host}:{} every {}s",
+        config.statsd_port,
+        config.statsd_flush_interval
+    );
+
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(config.statsd_flush_interval.max(1)));
+    let mut previous = MetricsSnapshot::default();
+    loop {
+        interval.tick().await;
+        let snapshot = MetricsSnapshot::from_state(&state);
+        let payload = snapshot
+            .to_statsd(&config.statsd_prefix, &previous)
+            .join("\n");
+        if let Err(e) = socket.send(payload.as_bytes()).await {
+            tracing::warn!("Failed to push metrics to StatsD: {e}");
+        }
+        previous = snapshot;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_record_response() {
+        let metrics = RequestMetrics::default();
+        metrics.record_response(StatusCode::OK);
+        metrics.record_response(StatusCode::NO_CONTENT);
+        metrics.record_response(StatusCode::NOT_FOUND);
+        metrics.record_response(StatusCode::SERVICE_UNAVAILABLE);
+
+        assert_eq!(metrics.responses(), [0, 2, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_to_prometheus() {
+        let snapshot = MetricsSnapshot {
+            responses: [0, 3, 0, 1, 0],
+            images_served: 2,
+            cache_images: 5,
+            cache_backend: "FileSystem",
+            cache: CacheStats {
+                hits: 7,
+                misses: 1,
+                validation_failures: 1,
+                evictions: 0,
+            },
+        };
+
+        let output = snapshot.to_prometheus();
+        assert!(output.contains("random_image_server_responses_total{status=\"2xx\"} 3\n"));
+        assert!(output.contains("random_image_server_responses_total{status=\"4xx\"} 1\n"));
+        assert!(output.contains("random_image_server_images_served_total 2\n"));
+        assert!(output.contains("random_image_server_cache_images 5\n"));
+        assert!(
+            output.contains("random_image_server_cache_hits_total{backend=\"FileSystem\"} 7\n")
+        );
+        assert!(output.contains(
+            "random_image_server_cache_validation_failures_total{backend=\"FileSystem\"} 1\n"
+        ));
+    }
+
+    #[test]
+    fn test_to_statsd() {
+        let previous = MetricsSnapshot {
+            responses: [0, 3, 0, 1, 0],
+            images_served: 2,
+            cache_images: 5,
+            cache: CacheStats {
+                hits: 2,
+                ..CacheStats::default()
+            },
+            ..MetricsSnapshot::default()
+        };
+        let snapshot = MetricsSnapshot {
+            responses: [0, 5, 0, 1, 1],
+            images_served: 4,
+            cache_images: 6,
+            cache: CacheStats {
+                hits: 4,
+                misses: 1,
+                ..CacheStats::default()
+            },
+            ..MetricsSnapshot::default()
+        };
+
+        assert_eq!(
+            snapshot.to_statsd("images", &previous),
+            vec![
+                "images.responses.1xx:0|c",
+                "images.responses.2xx:2|c",
+                "images.responses.3xx:0|c",
+                "images.responses.4xx:0|c",
+                "images.responses.5xx:1|c",
+                "images.images_served:2|c",
+                "images.cache.images:6|g",
+                "images.cache.hits:2|c",
+                "images.cache.misses:1|c",
+                "images.cache.validation_failures:0|c",
+                "images.cache.evictions:0|c",
+            ]
+        );
+        assert_eq!(
+            snapshot.to_statsd("", &previous).last().unwrap(),
+            "cache.evictions:0|c"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_statsd_exporter_pushes_metrics() {
+        let agent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = MetricsConfig {
+            statsd_host: Some("127.0.0.1".to_string()),
+            statsd_port: agent.local_addr().unwrap().port(),
+            statsd_prefix: "test".to_string(),
+            statsd_flush_interval: 1,
+        };
+        let state = Arc::new(ServerState::default());
+        state.metrics.record_response(StatusCode::OK);
+
+        let exporter = tokio::spawn(run_statsd_exporter(config, state));
+        let mut buf = [0; 1024];
+        let len = tokio::time::timeout(Duration::from_secs(5), agent.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        exporter.abort();
+
+        let payload = String::from_utf8_lossy(&buf[..len]);
+        assert!(payload.lines().any(|line| line == "test.responses.2xx:1|c"));
+        assert!(payload.lines().any(|line| line == "test.cache.images:0|g"));
+    }
+}
@@ -0,0 +1,297 @@
+//! Notifications of lifecycle events, POSTed as JSON to `notifications.webhook_url`.
+//!
+//! Each event is sent as an object with an `event` field naming it, its details, and a `text` field
+//! describing it, so that it can be posted to Slack-compatible incoming webhooks as is.
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::{
+    cache::CacheBackend,
+    populate::{PopulateReport, SourcePopulateReport},
+    state::ServerState,
+};
+
+/// A lifecycle event of the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The cache was populated, and the server started accepting connections
+    Started {
+        /// The address the server listens on
+        address: String,
+        /// The number of images in the cache
+        images: usize,
+    },
+    /// The cache finished being populated with the configured images
+    Populated {
+        /// The number of images loaded into the cache
+        images: usize,
+        /// The number of images skipped for being outside the configured limits, or near-duplicates
+        skipped: usize,
+        /// The number of errors encountered while loading the sources
+        errors: usize,
+        /// The number of configured sources
+        sources: usize,
+        /// The number of sources that encountered at least one error
+        failed_sources: usize,
+        /// How long populating the cache took, in milliseconds
+        elapsed_ms: u64,
+    },
+    /// Loading a source encountered errors
+    SourceFailed {
+        /// The configured source
+        source: String,
+        /// The number of errors encountered while loading the source
+        errors: usize,
+        /// The most recent error encountered while loading the source
+        error: Option<String>,
+    },
+    /// The server is shutting down
+    Shutdown,
+}
+
+impl Event {
+    /// The event of populating the cache
+    #[must_use]
+    pub fn populated(report: &PopulateReport) -> Self {
+        Self::Populated {
+            images: report.images(),
+            skipped: report.skipped(),
+            errors: report.errors(),
+            sources: report.sources.len(),
+            failed_sources: report.failed_sources(),
+            elapsed_ms: u64::try_from(report.elapsed.as_millis()).unwrap_or(u64::MAX),
+        }
+    }
+
+    /// The event of a source failing to load, if it encountered any errors
+    #[must_use]
+    pub fn source_failed(report: &SourcePopulateReport) -> Option<Self> {
+        (report.errors > 0).then(|| Self::SourceFailed {
+            source: report.source.clone(),
+            errors: report.errors,
+            error: report.last_error.clone(),
+        })
+    }
+
+    /// The name of the event, as sent in its `event` field
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Started { .. } => "started",
+            Self::Populated { .. } => "populated",
+            Self::SourceFailed { .. } => "source_failed",
+            Self::Shutdown => "shutdown",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Started { address, images } => {
+                write!(f, "Serving {images} image(s) on {address}")
+            }
+            Self::Populated {
+                images,
+                skipped,
+                errors,
+                sources,
+                failed_sources,
+                elapsed_ms,
+            } => write!(
+                f,
+                "Loaded {images} image(s) from {sources} source(s) in {elapsed_ms}ms, \
+                 skipped {skipped}, {errors} error(s) from {failed_sources} source(s)"
+            ),
+            Self::SourceFailed {
+                source,
+                errors,
+                error,
+            } => {
+                write!(f, "{errors} error(s) loading {source}")?;
+                if let Some(error) = error {
+                    write!(f, ", the last was: {error}")?;
+                }
+                Ok(())
+            }
+            Self::Shutdown => f.write_str("Shutting down"),
+        }
+    }
+}
+
+/// The body of a webhook request
+#[derive(Serialize)]
+#[cfg_attr(not(feature = "remote-sources"), allow(dead_code))]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a Event,
+    text: String,
+}
+
+#[cfg_attr(not(feature = "remote-sources"), allow(dead_code))]
+impl<'a> Payload<'a> {
+    fn new(event: &'a Event) -> Self {
+        Self {
+            event,
+            text: format!("random-image-server: {event}"),
+        }
+    }
+}
+
+impl<C: CacheBackend> ServerState<C> {
+    /// POST an event to the configured webhook, if any
+    ///
+    /// Failures are logged rather than returned, so that an unreachable webhook doesn't stop the server.
+    pub async fn notify(&self, event: &Event) {
+        let Some(url) = &self.config.notifications.webhook_url else {
+            return;
+        };
+        // webhook URLs often embed a token, so only their host is logged
+        let host = url.host_str().unwrap_or_default();
+
+        #[cfg(feature = "remote-sources")]
+        {
+            let body = match serde_json::to_vec(&Payload::new(event)) {
+                Ok(body) => body,
+                Err(e) => {
+                    tracing::error!("Failed to serialize the {} event: {e}", event.name());
+                    return;
+                }
+            };
+            let result = self
+                .http_client
+                .post(url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status);
+            match result {
+                Ok(_) => {
+                    tracing::debug!("Sent the {} event to the webhook at {host}", event.name());
+                }
+                Err(e) => tracing::warn!(
+                    "Failed to send the {} event to the webhook at {host}: {}",
+                    event.name(),
+                    e.without_url()
+                ),
+            }
+        }
+
+        #[cfg(not(feature = "remote-sources"))]
+        tracing::warn!(
+            "Not sending the {} event to the webhook at {host}: {}",
+            event.name(),
+            crate::REMOTE_SOURCES_DISABLED
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn test_payload() {
+        let report = PopulateReport {
+            sources: vec![
+                SourcePopulateReport {
+                    source: "assets".to_string(),
+                    images: 2,
+                    ..SourcePopulateReport::default()
+                },
+                SourcePopulateReport {
+                    source: "https://example.com/image.jpg".to_string(),
+                    errors: 1,
+                    last_error: Some("404 Not Found".to_string()),
+                    ..SourcePopulateReport::default()
+                },
+            ],
+            elapsed: Duration::from_millis(1500),
+        };
+
+        let event = Event::populated(&report);
+        assert_eq!(
+            serde_json::to_value(Payload::new(&event)).unwrap(),
+            serde_json::json!({
+                "event": "populated",
+                "images": 2,
+                "skipped": 0,
+                "errors": 1,
+                "sources": 2,
+                "failed_sources": 1,
+                "elapsed_ms": 1500,
+                "text": "random-image-server: Loaded 2 image(s) from 2 source(s) in 1500ms, skipped 0, 1 error(s) from 1 source(s)",
+            })
+        );
+
+        assert_eq!(Event::source_failed(&report.sources[0]), None);
+        let event = Event::source_failed(&report.sources[1]).unwrap();
+        assert_eq!(
+            serde_json::to_value(Payload::new(&event)).unwrap(),
+            serde_json::json!({
+                "event": "source_failed",
+                "source": "https://example.com/image.jpg",
+                "errors": 1,
+                "error": "404 Not Found",
+                "text": "random-image-server: 1 error(s) loading https://example.com/image.jpg, the last was: 404 Not Found",
+            })
+        );
+
+        assert_eq!(
+            serde_json::to_value(Payload::new(&Event::Shutdown)).unwrap(),
+            serde_json::json!({
+                "event": "shutdown",
+                "text": "random-image-server: Shutting down",
+            })
+        );
+    }
+
+    #[cfg(feature = "remote-sources")]
+    #[tokio::test]
+    async fn test_notify_posts_to_webhook() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = crate::config::Config::default();
+        config.notifications.webhook_url = Some(
+            format!("http://{}/hooks/token", listener.local_addr().unwrap())
+                .parse()
+                .unwrap(),
+        );
+        let state = ServerState::with_config(&config);
+
+        let webhook = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            // read until the whole body, which ends with the closing brace of the JSON object, is received
+            while !request.ends_with(b"}") {
+                let len = stream.read(&mut buf).await.unwrap();
+                assert_ne!(len, 0, "the connection closed before the body was received");
+                request.extend_from_slice(&buf[..len]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        tokio::time::timeout(Duration::from_secs(5), state.notify(&Event::Shutdown))
+            .await
+            .unwrap();
+        let request = webhook.await.unwrap();
+
+        assert!(request.starts_with("POST /hooks/token HTTP/1.1\r\n"));
+        assert!(request.contains("content-type: application/json\r\n"));
+        assert!(
+            request
+                .ends_with(r#"{"event":"shutdown","text":"random-image-server: Shutting down"}"#)
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! Error reporting to Sentry, enabled by the `sentry` feature.
+//!
+//! Without the feature, every function in this module is a no-op.
+
+use std::future::Future;
+
+use crate::config::ObservabilityConfig;
+
+/// Keeps error reporting alive, and flushes pending events when dropped
+#[must_use]
+pub struct ObservabilityGuard {
+    #[cfg(feature = "sentry")]
+    _sentry: Option<sentry::ClientInitGuard>,
+}
+
+/// Initialize error reporting based on configuration
+///
+/// Panics are reported automatically once initialized.
+pub fn init(config: &ObservabilityConfig) -> ObservabilityGuard {
+    #[cfg(feature = "sentry")]
+    {
+        let sentry = config.sentry_dsn.as_deref().map(|dsn| {
+            tracing::info!("Reporting errors to Sentry");
+            let mut options = sentry::ClientOptions::new();
+            options.release = sentry::release_name!();
+            options.environment = config.sentry_environment.clone().map(Into::into);
+            sentry::init((dsn, options))
+        });
+        ObservabilityGuard { _sentry: sentry }
+    }
+
+    #[cfg(not(feature = "sentry"))]
+    {
+        if config.sentry_dsn.is_some() {
+            tracing::warn!(
+                "A Sentry DSN is configured, but the server was built without the `sentry` feature"
+            );
+        }
+        ObservabilityGuard {}
+    }
+}
+
+/// Run a request handler so that any errors it reports carry the context of the request
+pub async fn with_request_context<F: Future>(
+    request_id: &str,
+    method: &hyper::Method,
+    path: &str,
+    handler: F,
+) -> F::Output {
+    #[cfg(feature = "sentry")]
+    {
+        use sentry::SentryFutureExt;
+
+        let hub = std::sync::Arc::new(sentry::Hub::new_from_top(sentry::Hub::current()));
+        hub.configure_scope(|scope| {
+            scope.set_tag("request_id", request_id);
+            scope.set_tag("method", method);
+            scope.set_tag("path", path);
+        });
+        handler.bind_hub(hub).await
+    }
+
+    #[cfg(not(feature = "sentry"))]
+    {
+        let _ = (request_id, method, path);
+        handler.await
+    }
+}
+
+/// Report an error
+#[cfg_attr(not(feature = "sentry"), allow(clippy::missing_const_for_fn))]
+pub fn capture_error(err: &anyhow::Error) {
+    #[cfg(feature = "sentry")]
+    sentry::integrations::anyhow::capture_anyhow(err);
+
+    #[cfg(not(feature = "sentry"))]
+    let _ = err;
+}
+
+/// Report an error message
+#[cfg_attr(not(feature = "sentry"), allow(clippy::missing_const_for_fn))]
+pub fn capture_message(message: &str) {
+    #[cfg(feature = "sentry")]
+    sentry::capture_message(message, sentry::Level::Error);
+
+    #[cfg(not(feature = "sentry"))]
+    let _ = message;
+}
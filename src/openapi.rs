@@ -0,0 +1,997 @@
+//! The OpenAPI 3 description of the routes of the server, served at `/openapi.json`,
+//! and the optional Swagger UI page rendering it, served at `/docs`.
+//!
+//! Routes are described by [`ROUTES`], and the schemas of their JSON responses are generated from
+//! the types they're serialized from, so the description can't drift from what is sent.
+
+use schemars::{JsonSchema, Schema, SchemaGenerator, generate::SchemaSettings, json_schema};
+use serde_json::{Map, Value, json};
+
+use crate::config::Config;
+
+/// A route of the server, as described in the OpenAPI document
+#[derive(Debug, Clone, Copy)]
+pub struct Route {
+    /// The path of the route, relative to `server.base_path`, with parameters in braces
+    pub path: &'static str,
+    pub summary: &'static str,
+    pub description: &'static str,
+    pub parameters: &'static [Parameter],
+    pub responses: &'static [ApiResponse],
+    /// Whether the route is exempt from basic authentication
+    pub public: bool,
+}
+
+/// A parameter of a route
+#[derive(Debug, Clone, Copy)]
+pub struct Parameter {
+    pub name: &'static str,
+    /// Where the parameter is sent, `path` or `query`
+    pub location: &'static str,
+    pub description: &'static str,
+    /// The values the parameter may take, any string if empty
+    pub values: &'static [&'static str],
+}
+
+/// A response a route may send
+#[derive(Debug, Clone, Copy)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub description: &'static str,
+    pub content: Content,
+}
+
+/// The body of a response
+#[derive(Debug, Clone, Copy)]
+pub enum Content {
+    /// No meaningful body
+    None,
+    /// A plain text body
+    Text,
+    /// An image, of whatever type it was stored as
+    Image,
+    /// A JSON body of the given media type, described by the given schema
+    Json(&'static str, fn(&mut SchemaGenerator) -> Schema),
+    /// An HTML page
+    Html,
+    /// A file, of whatever type it is
+    File,
+}
+
+fn schema<T: JsonSchema>(generator: &mut SchemaGenerator) -> Schema {
+    generator.subschema_for::<T>()
+}
+
+fn any_object(_: &mut SchemaGenerator) -> Schema {
+    json_schema!({ "type": "object" })
+}
+
+const fn response(status: u16, description: &'static str, content: Content) -> ApiResponse {
+    ApiResponse {
+        status,
+        description,
+        content,
+    }
+}
+
+const fn json(
+    status: u16,
+    description: &'static str,
+    schema: fn(&mut SchemaGenerator) -> Schema,
+) -> ApiResponse {
+    response(
+        status,
+        description,
+        Content::Json("application/json", schema),
+    )
+}
+
+const IMAGE: ApiResponse = response(200, "The image", Content::Image);
+/// A response with an `application/problem+json` body, describing why the request failed
+const fn problem(status: u16, description: &'static str) -> ApiResponse {
+    response(
+        status,
+        description,
+        Content::Json("application/problem+json", schema::<crate::Problem>),
+    )
+}
+
+const IDENTIFIED_IMAGE: ApiResponse = response(
+    200,
+    "The image, identified by its id in the `X-Image-Id` header, \
+     and by its path or URL in the `X-Image-Source` header if `server.expose_sources` is set",
+    Content::Image,
+);
+const NOT_MODIFIED: ApiResponse = response(
+    304,
+    "The image matches the `If-None-Match` or `If-Modified-Since` header of the request",
+    Content::None,
+);
+const FORBIDDEN: ApiResponse = response(
+    403,
+    "The request is from an address, or embeds the image on a domain, that isn't allowed, \
+     or images are only served through signed links",
+    Content::Text,
+);
+const ORDER: Parameter = Parameter {
+    name: "order",
+    location: "query",
+    description: "How the image is chosen: `uniform` picks any image, `least_served` only the images served the fewest times",
+    values: &["uniform", "least_served"],
+};
+const FORMAT: Parameter = Parameter {
+    name: "format",
+    location: "query",
+    description: "The comma-separated formats of the images to choose among, \
+                  by extension (e.g. `png,webp`) or content type (e.g. `image/png`)",
+    values: &[],
+};
+const ORIENTATION: Parameter = Parameter {
+    name: "orientation",
+    location: "query",
+    description: "The shape of the images to choose among, wider or taller than they are tall or wide, or square",
+    values: &["landscape", "portrait", "square"],
+};
+const MIN_WIDTH: Parameter = Parameter {
+    name: "min_width",
+    location: "query",
+    description: "The width in pixels below which images aren't chosen",
+    values: &[],
+};
+const MIN_HEIGHT: Parameter = Parameter {
+    name: "min_height",
+    location: "query",
+    description: "The height in pixels below which images aren't chosen",
+    values: &[],
+};
+const STICKY: Parameter = Parameter {
+    name: "sticky",
+    location: "query",
+    description: "How long, in seconds, the chosen image is pinned to the client, instead of `server.sticky`, \
+                  at most a week. Clients are identified by the `random_image_client` cookie, set by their first \
+                  sticky request, or by their address if they don't send it. `0` doesn't pin the image",
+    values: &[],
+};
+const CAPTION: Parameter = Parameter {
+    name: "caption",
+    location: "query",
+    description: "Text drawn across the bottom of the image, at most 200 characters, \
+                  instead of the caption of its source, which an empty caption leaves out. \
+                  Requires the `transforms` feature",
+    values: &[],
+};
+const ROTATE: Parameter = Parameter {
+    name: "rotate",
+    location: "query",
+    description: "How far the image is rotated clockwise, in degrees. Requires the `transforms` feature",
+    values: &["90", "180", "270"],
+};
+const FLIP: Parameter = Parameter {
+    name: "flip",
+    location: "query",
+    description: "Whether the image is mirrored horizontally or vertically, after it's rotated. \
+                  Requires the `transforms` feature",
+    values: &["h", "v"],
+};
+const FILTER: Parameter = Parameter {
+    name: "filter",
+    location: "query",
+    description: "A filter applied to the colors of the image: `grayscale`, `sepia`, or `blur`, \
+                  optionally with its strength as `blur:{sigma}`, from 1 to 50 pixels (5 by default). \
+                  Requires the `transforms` feature",
+    values: &[],
+};
+const QUALITY: Parameter = Parameter {
+    name: "quality",
+    location: "query",
+    description: "The quality, from 1 to 100, a JPEG image is re-encoded at, \
+                  instead of the configured `transforms.quality`. Requires the `transforms` feature",
+    values: &[],
+};
+const INVALID_QUERY: ApiResponse = response(
+    400,
+    "The query is invalid, or transforms the image without the `transforms` feature",
+    Content::Text,
+);
+const NO_MATCH: ApiResponse = problem(404, "No image fits the query");
+const NO_IMAGES: ApiResponse = problem(
+    503,
+    "No images are available yet, or the cache hasn't finished being populated",
+);
+
+/// The routes of the server, except `/docs` and `/static/{path}`, which are only served if configured
+pub const ROUTES: &[Route] = &[
+    Route {
+        path: "/",
+        summary: "Welcome message",
+        description: "A plain text greeting, to check the server is reachable",
+        parameters: &[],
+        responses: &[response(200, "The greeting", Content::Text)],
+        public: false,
+    },
+    Route {
+        path: "/random",
+        summary: "A random image",
+        description: "A random image from the cache, chosen among the images of each source according to its weight",
+        parameters: &[
+            ORDER,
+            FORMAT,
+            ORIENTATION,
+            MIN_WIDTH,
+            MIN_HEIGHT,
+            STICKY,
+            CAPTION,
+            ROTATE,
+            FLIP,
+            FILTER,
+            QUALITY,
+        ],
+        responses: &[
+            IDENTIFIED_IMAGE,
+            NOT_MODIFIED,
+            INVALID_QUERY,
+            FORBIDDEN,
+            NO_MATCH,
+            NO_IMAGES,
+        ],
+        public: false,
+    },
+    Route {
+        path: "/random.json",
+        summary: "A random image, described as JSON",
+        description: "Describes a random image, with a link to it, instead of serving it. \
+                      Also sent by `/random` to requests that accept `application/json`",
+        parameters: &[
+            ORDER,
+            FORMAT,
+            ORIENTATION,
+            MIN_WIDTH,
+            MIN_HEIGHT,
+            STICKY,
+            CAPTION,
+            ROTATE,
+            FLIP,
+            FILTER,
+            QUALITY,
+        ],
+        responses: &[
+            json(200, "The image", schema::<crate::RandomImage>),
+            INVALID_QUERY,
+            FORBIDDEN,
+            NO_MATCH,
+            NO_IMAGES,
+        ],
+        public: false,
+    },
+    Route {
+        path: "/sequential",
+        summary: "The next image",
+        description: "The images of the cache, one after the other, starting over after the last one",
+        parameters: &[FORMAT, CAPTION, ROTATE, FLIP, FILTER, QUALITY],
+        responses: &[
+            IDENTIFIED_IMAGE,
+            NOT_MODIFIED,
+            INVALID_QUERY,
+            FORBIDDEN,
+            NO_MATCH,
+            NO_IMAGES,
+        ],
+        public: false,
+    },
+    Route {
+        path: "/image/{id}",
+        summary: "An image by id",
+        description: "The image with the given id, as listed by `/list`. \
+                      Images are identified by the hash of their content, so identical images share a URL, \
+                      and are served with `Cache-Control: immutable`. \
+                      If `signing.secret` is set, the link must be signed, as printed by `random-image-server sign-url`",
+        parameters: &[
+            Parameter {
+                name: "id",
+                location: "path",
+                description: "The opaque identifier of the image",
+                values: &[],
+            },
+            Parameter {
+                name: "expires",
+                location: "query",
+                description: "When the signed link expires, in seconds since the unix epoch",
+                values: &[],
+            },
+            Parameter {
+                name: "sig",
+                location: "query",
+                description: "The signature of the link",
+                values: &[],
+            },
+            CAPTION,
+            ROTATE,
+            FLIP,
+            FILTER,
+            QUALITY,
+        ],
+        responses: &[
+            IMAGE,
+            NOT_MODIFIED,
+            INVALID_QUERY,
+            response(
+                403,
+                "The link is not correctly signed, or expired, or the request isn't allowed",
+                Content::Text,
+            ),
+            problem(404, "No image has the given id"),
+        ],
+        public: false,
+    },
+    Route {
+        path: "/image/{id}/info",
+        summary: "An image by id, described as JSON",
+        description: "Describes the image with the given id, including its dominant color and blurhash \
+                      if the server was built with the `blurhash` feature, e.g. to show a preview while it loads. \
+                      Signed like links to the image itself",
+        parameters: &[
+            Parameter {
+                name: "id",
+                location: "path",
+                description: "The opaque identifier of the image",
+                values: &[],
+            },
+            Parameter {
+                name: "expires",
+                location: "query",
+                description: "When the signed link expires, in seconds since the unix epoch",
+                values: &[],
+            },
+            Parameter {
+                name: "sig",
+                location: "query",
+                description: "The signature of the link",
+                values: &[],
+            },
+        ],
+        responses: &[
+            json(200, "The image", schema::<crate::ImageInfo>),
+            response(
+                403,
+                "The link is not correctly signed, or expired, or the request isn't allowed",
+                Content::Text,
+            ),
+            problem(404, "No image has the given id"),
+        ],
+        public: false,
+    },
+    Route {
+        path: "/list",
+        summary: "A page of the images",
+        description: "The id of each image in the cache, with an absolute link to it, a page at a time",
+        parameters: &[
+            Parameter {
+                name: "page",
+                location: "query",
+                description: "The page, counting from 1",
+                values: &[],
+            },
+            Parameter {
+                name: "per_page",
+                location: "query",
+                description: "The number of images on each page, 100 by default and at most 1000",
+                values: &[],
+            },
+            Parameter {
+                name: "sort",
+                location: "query",
+                description: "How the images are sorted: in the order they were `added`, by `name`, or by `size`",
+                values: &["added", "name", "size"],
+            },
+            Parameter {
+                name: "tag",
+                location: "query",
+                description: "Only list the images of the sources with the given tag",
+                values: &[],
+            },
+        ],
+        responses: &[
+            json(200, "The page of images", schema::<crate::ImageList>),
+            response(400, "The query is invalid", Content::Text),
+            FORBIDDEN,
+        ],
+        public: false,
+    },
+    Route {
+        path: "/health",
+        summary: "Detailed health",
+        description: "The status of each configured source, and of the remote hosts that recently failed",
+        parameters: &[],
+        responses: &[json(
+            200,
+            "The health of the server",
+            schema::<crate::Health>,
+        )],
+        public: true,
+    },
+    Route {
+        path: "/livez",
+        summary: "Liveness",
+        description: "Responds as long as the server is running",
+        parameters: &[],
+        responses: &[response(200, "`OK`", Content::Text)],
+        public: true,
+    },
+    Route {
+        path: "/readyz",
+        summary: "Readiness",
+        description: "Whether the cache has been populated, and holds at least one image",
+        parameters: &[],
+        responses: &[
+            json(200, "The server is ready", schema::<crate::Readiness>),
+            problem(503, "The server isn't ready"),
+        ],
+        public: true,
+    },
+    Route {
+        path: "/version",
+        summary: "Build metadata",
+        description: "The version, commit, compiler, and features the server was built with",
+        parameters: &[],
+        responses: &[json(
+            200,
+            "The build metadata",
+            schema::<crate::version::BuildInfo>,
+        )],
+        public: false,
+    },
+    Route {
+        path: "/favicon.ico",
+        summary: "Favicon",
+        description: "The configured `server.favicon`, or the built-in icon",
+        parameters: &[],
+        responses: &[IMAGE, NOT_MODIFIED],
+        public: false,
+    },
+    Route {
+        path: "/metrics",
+        summary: "Metrics",
+        description: "Counters of responses and of the cache, in the Prometheus text format",
+        parameters: &[],
+        responses: &[response(200, "The metrics", Content::Text)],
+        public: false,
+    },
+    Route {
+        path: "/stats/images",
+        summary: "Image statistics",
+        description: "How many times each image has been served, most served first",
+        parameters: &[],
+        responses: &[json(
+            200,
+            "The statistics",
+            schema::<crate::ImageStatsReport>,
+        )],
+        public: false,
+    },
+    Route {
+        path: "/stats/cache",
+        summary: "Cache statistics",
+        description: "The size of the cache, and how it has been used",
+        parameters: &[],
+        responses: &[json(
+            200,
+            "The statistics",
+            schema::<crate::CacheStatsReport>,
+        )],
+        public: false,
+    },
+    Route {
+        path: "/admin/config/schema",
+        summary: "Configuration schema",
+        description: "The JSON schema of the configuration file",
+        parameters: &[],
+        responses: &[json(200, "The JSON schema", any_object)],
+        public: false,
+    },
+    Route {
+        path: "/openapi.json",
+        summary: "API description",
+        description: "This OpenAPI document",
+        parameters: &[],
+        responses: &[json(200, "The OpenAPI document", any_object)],
+        public: false,
+    },
+];
+
+/// The Swagger UI page, served at `/docs` if `server.swagger_ui` is set
+pub const DOCS_ROUTE: Route = Route {
+    path: "/docs",
+    summary: "API documentation",
+    description: "A Swagger UI page rendering this OpenAPI document",
+    parameters: &[],
+    responses: &[response(200, "The page", Content::Html)],
+    public: false,
+};
+
+/// Whether the route at the given path is exempt from basic authentication, like the health checks
+#[must_use]
+pub fn is_public(path: &str) -> bool {
+    ROUTES
+        .iter()
+        .any(|route| route.public && route.path == path)
+}
+
+/// Whether the route at the given path is also served for each collection, under `/{name}`
+#[must_use]
+pub fn is_collection_route(path: &str) -> bool {
+    matches!(
+        path,
+        "/random" | "/random.json" | "/sequential" | "/list" | "/stats/images" | "/stats/cache"
+    ) || path.starts_with("/image/")
+}
+
+/// The path of the route matching the given path, relative to `server.base_path`, with its parameters in braces,
+/// e.g. `/image/{id}` for `/image/1a2b`, so requests can be grouped by route in logs
+///
+/// The last parameter of a route may span several segments, like the path of a static file,
+/// unless another route matches the path segment by segment.
+#[must_use]
+pub fn route_path(path: &str) -> Option<&'static str> {
+    let routes = || {
+        ROUTES
+            .iter()
+            .chain([
+                &DOCS_ROUTE,
+                &STATIC_ROUTE,
+                &PROXY_ROUTE,
+                &PLAYLIST_ROUTE,
+                &PLACEHOLDER_ROUTE,
+            ])
+            .map(|route| route.path)
+    };
+    routes()
+        .find(|route| matches_route(route, path, false))
+        .or_else(|| routes().find(|route| matches_route(route, path, true)))
+}
+
+/// Whether the path matches the path of a route, whose parameters match any non-empty segment,
+/// and whose last parameter matches the rest of the path if `greedy` is set
+fn matches_route(route: &str, path: &str, greedy: bool) -> bool {
+    let mut segments = path.split('/');
+    let mut expected = route.split('/').peekable();
+    while let Some(expected_segment) = expected.next() {
+        let Some(segment) = segments.next() else {
+            return false;
+        };
+        if expected_segment.starts_with('{') {
+            if segment.is_empty() {
+                return false;
+            }
+            if greedy && expected.peek().is_none() {
+                return true;
+            }
+        } else if expected_segment != segment {
+            return false;
+        }
+    }
+    segments.next().is_none()
+}
+
+/// Whether the given path segment is the first segment of the path of a route,
+/// and so can't be the name of a collection
+#[must_use]
+pub fn is_route_segment(segment: &str) -> bool {
+    ROUTES
+        .iter()
+        .chain([&DOCS_ROUTE, &STATIC_ROUTE, &PROXY_ROUTE, &PLACEHOLDER_ROUTE])
+        .filter_map(|route| route.path.split('/').nth(1))
+        .any(|first| first == segment)
+}
+
+/// The files of the static directory, served under `/static/` if `server.static_dir` is set
+pub const STATIC_ROUTE: Route = Route {
+    path: "/static/{path}",
+    summary: "A static file",
+    description: "A file of the configured static directory, e.g. the CSS and scripts of a gallery",
+    parameters: &[Parameter {
+        name: "path",
+        location: "path",
+        description: "The path of the file, relative to the static directory",
+        values: &[],
+    }],
+    responses: &[
+        response(200, "The file", Content::File),
+        problem(404, "No file is found at the path"),
+    ],
+    public: false,
+};
+
+/// Remote images proxied through the cache, served at `/proxy` if `proxy.allowed_domains` is set
+pub const PROXY_ROUTE: Route = Route {
+    path: "/proxy",
+    summary: "A proxied image",
+    description: "An image fetched from a URL on one of the allowed domains, and served from the proxy cache \
+                  once it was fetched",
+    parameters: &[Parameter {
+        name: "url",
+        location: "query",
+        description: "The URL of the image",
+        values: &[],
+    }],
+    responses: &[
+        response(200, "The image", Content::Image),
+        response(400, "The URL is missing or invalid", Content::Text),
+        response(
+            403,
+            "The URL is not on one of the allowed domains",
+            Content::Text,
+        ),
+        response(
+            502,
+            "The image could not be fetched, or is outside the configured limits",
+            Content::Text,
+        ),
+    ],
+    public: false,
+};
+
+/// The configured playlists, served at `/sequential/{name}` if `[playlists]` has any
+pub const PLAYLIST_ROUTE: Route = Route {
+    path: "/sequential/{playlist}",
+    summary: "The next image of a playlist",
+    description: "The images of the playlist, in the configured order, one after the other, \
+                  starting over after the last one. Each playlist keeps a position of its own",
+    parameters: &[
+        Parameter {
+            name: "playlist",
+            location: "path",
+            description: "The name of the playlist, as configured in `[playlists]`",
+            values: &[],
+        },
+        FORMAT,
+        CAPTION,
+        ROTATE,
+        FLIP,
+        FILTER,
+        QUALITY,
+    ],
+    responses: &[
+        IDENTIFIED_IMAGE,
+        NOT_MODIFIED,
+        INVALID_QUERY,
+        FORBIDDEN,
+        problem(
+            404,
+            "No playlist has the name, or none of its images are available",
+        ),
+        NO_IMAGES,
+    ],
+    public: false,
+};
+
+/// Generated placeholder images, served at `/placeholder/{size}` if `placeholder.enabled` is set
+pub const PLACEHOLDER_ROUTE: Route = Route {
+    path: "/placeholder/{size}",
+    summary: "A placeholder image",
+    description: "A PNG of the given size, of a solid color or a gradient, optionally labelled with its \
+                  dimensions, generated once and then served from the placeholder cache",
+    parameters: &[
+        Parameter {
+            name: "size",
+            location: "path",
+            description: "The width and height of the image in pixels, e.g. `640x480`, \
+                          at most `placeholder.max_width` by `placeholder.max_height`",
+            values: &[],
+        },
+        Parameter {
+            name: "color",
+            location: "query",
+            description: "The hex color of the background, or where its gradient starts, `cccccc` by default",
+            values: &[],
+        },
+        Parameter {
+            name: "to",
+            location: "query",
+            description: "The hex color the gradient ends at, for a gradient background",
+            values: &[],
+        },
+        Parameter {
+            name: "direction",
+            location: "query",
+            description: "Which way the gradient runs",
+            values: &["horizontal", "vertical"],
+        },
+        Parameter {
+            name: "text",
+            location: "query",
+            description: "Whether the dimensions are drawn at the middle of the image, `true` by default",
+            values: &["true", "false"],
+        },
+    ],
+    responses: &[
+        response(200, "The image", Content::Image),
+        response(
+            400,
+            "The size or a parameter is invalid, or the size is larger than the limits",
+            Content::Text,
+        ),
+    ],
+    public: false,
+};
+
+/// The routes served with the given configuration
+pub fn routes(config: &Config) -> impl Iterator<Item = &'static Route> {
+    ROUTES
+        .iter()
+        .chain(config.server.swagger_ui.then_some(&DOCS_ROUTE))
+        .chain(config.server.static_dir.is_some().then_some(&STATIC_ROUTE))
+        .chain(config.proxy.is_enabled().then_some(&PROXY_ROUTE))
+        .chain(config.placeholder.enabled.then_some(&PLACEHOLDER_ROUTE))
+        .chain((!config.playlists.is_empty()).then_some(&PLAYLIST_ROUTE))
+}
+
+/// The OpenAPI document describing the routes served with the given configuration
+#[must_use]
+pub fn document(config: &Config) -> Value {
+    let mut generator = SchemaSettings::openapi3().for_serialize().into_generator();
+    let basic_auth = config.server.auth.basic.is_some();
+
+    let mut paths = Map::new();
+    for route in routes(config) {
+        let operation = operation_object(route, basic_auth, &mut generator);
+        paths.insert(route.path.to_string(), json!({ "get": operation }));
+    }
+    for name in config.collections.keys() {
+        for route in ROUTES
+            .iter()
+            .filter(|route| is_collection_route(route.path))
+        {
+            let mut operation = operation_object(route, basic_auth, &mut generator);
+            operation["summary"] = json!(format!("{}, from the {name} collection", route.summary));
+            paths.insert(
+                format!("/{name}{}", route.path),
+                json!({ "get": operation }),
+            );
+        }
+    }
+
+    let mut components = json!({ "schemas": generator.take_definitions(true) });
+    let mut document = json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "random-image-server",
+            "description": env!("CARGO_PKG_DESCRIPTION"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": server_url(config) }],
+        "paths": paths,
+    });
+    if basic_auth {
+        components["securitySchemes"] =
+            json!({ "basicAuth": { "type": "http", "scheme": "basic" } });
+        document["security"] = json!([{ "basicAuth": [] }]);
+    }
+    document["components"] = components;
+    document
+}
+
+/// The operation object of a route
+fn operation_object(route: &Route, basic_auth: bool, generator: &mut SchemaGenerator) -> Value {
+    let mut responses = Map::new();
+    for response in route.responses {
+        responses.insert(
+            response.status.to_string(),
+            response_object(response, generator),
+        );
+    }
+    if basic_auth && !route.public {
+        responses.insert(
+            "401".to_string(),
+            json!({ "description": "The request doesn't have the configured credentials" }),
+        );
+    }
+
+    let mut operation = json!({
+        "summary": route.summary,
+        "description": route.description,
+        "responses": responses,
+    });
+    if !route.parameters.is_empty() {
+        operation["parameters"] = route.parameters.iter().map(parameter_object).collect();
+    }
+    if basic_auth && route.public {
+        operation["security"] = json!([]);
+    }
+    operation
+}
+
+/// The URL the routes are relative to, absolute if `server.public_url` is configured
+fn server_url(config: &Config) -> String {
+    let origin = config
+        .server
+        .public_url
+        .as_ref()
+        .map_or("", |url| url.as_str().trim_end_matches('/'));
+    match format!("{origin}{}", config.server.base_path) {
+        url if url.is_empty() => "/".to_string(),
+        url => url,
+    }
+}
+
+fn response_object(response: &ApiResponse, generator: &mut SchemaGenerator) -> Value {
+    let content = match response.content {
+        Content::None => None,
+        Content::Text => Some(("text/plain", json!({ "type": "string" }))),
+        Content::Html => Some(("text/html", json!({ "type": "string" }))),
+        Content::Image => Some(("image/*", json!({ "type": "string", "format": "binary" }))),
+        Content::File => Some(("*/*", json!({ "type": "string", "format": "binary" }))),
+        Content::Json(media_type, schema) => Some((media_type, schema(generator).to_value())),
+    };
+    let mut object = json!({ "description": response.description });
+    if let Some((media_type, schema)) = content {
+        object["content"] = json!({ media_type: { "schema": schema } });
+    }
+    object
+}
+
+fn parameter_object(parameter: &Parameter) -> Value {
+    let mut schema = json!({ "type": "string" });
+    if !parameter.values.is_empty() {
+        schema["enum"] = json!(parameter.values);
+    }
+    json!({
+        "name": parameter.name,
+        "in": parameter.location,
+        "description": parameter.description,
+        "required": parameter.location == "path",
+        "schema": schema,
+    })
+}
+
+/// The Swagger UI page rendering the OpenAPI document at the given URL, loaded from a CDN
+#[must_use]
+pub fn swagger_ui(openapi_url: &str) -> String {
+    format!(
+        r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>random-image-server API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js" crossorigin></script>
+  <script>
+    window.onload = () => {{
+      window.ui = SwaggerUIBundle({{ url: "{openapi_url}", dom_id: "#swagger-ui" }});
+    }};
+  </script>
+</body>
+</html>
+"##
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HashedBasicAuth;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::root("/", Some("/"))]
+    #[case::random("/random", Some("/random"))]
+    #[case::image("/image/1a2b", Some("/image/{id}"))]
+    #[case::image_info("/image/1a2b/info", Some("/image/{id}/info"))]
+    #[case::playlist("/sequential/lobby", Some("/sequential/{playlist}"))]
+    #[case::static_file("/static/css/site.css", Some("/static/{path}"))]
+    #[case::empty_parameter("/image/", None)]
+    #[case::unknown("/unknown", None)]
+    #[case::trailing_segment("/random/more", None)]
+    fn test_route_path(#[case] path: &str, #[case] expected: Option<&str>) {
+        assert_eq!(route_path(path), expected);
+    }
+
+    #[test]
+    fn test_document() {
+        let document = document(&Config::default());
+
+        assert_eq!(document["openapi"], "3.0.3");
+        assert_eq!(document["servers"], json!([{ "url": "/" }]));
+        let paths = document["paths"].as_object().unwrap();
+        assert_eq!(paths.len(), ROUTES.len());
+        assert!(!paths.contains_key("/docs"));
+        assert_eq!(
+            paths["/random"]["get"]["parameters"][0]["schema"]["enum"],
+            json!(["uniform", "least_served"])
+        );
+        assert_eq!(
+            paths["/image/{id}"]["get"]["parameters"][0]["required"],
+            true
+        );
+        assert_eq!(
+            paths["/list"]["get"]["responses"]["200"]["content"]["application/json"]["schema"],
+            json!({ "$ref": "#/components/schemas/ImageList" })
+        );
+        assert!(document.get("security").is_none());
+
+        // every referenced schema is defined
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+        for name in [
+            "ImageList",
+            "ImageListEntry",
+            "Health",
+            "SourceStatus",
+            "Problem",
+            "BuildInfo",
+        ] {
+            assert!(schemas.contains_key(name), "{name} is not defined");
+        }
+    }
+
+    #[test]
+    fn test_document_with_settings() {
+        let mut config = Config::default();
+        config.server.base_path = "/images".to_string();
+        config.server.public_url = Some("https://example.com/".parse().unwrap());
+        config.server.swagger_ui = true;
+        config.server.auth.basic = Some(HashedBasicAuth::default());
+        let document = document(&config);
+
+        assert_eq!(
+            document["servers"],
+            json!([{ "url": "https://example.com/images" }])
+        );
+        let paths = &document["paths"];
+        assert_eq!(
+            paths["/docs"]["get"]["responses"]["200"]["content"]["text/html"]["schema"]["type"],
+            "string"
+        );
+        assert_eq!(document["security"], json!([{ "basicAuth": [] }]));
+        assert!(paths["/random"]["get"]["responses"].get("401").is_some());
+        assert_eq!(paths["/health"]["get"]["security"], json!([]));
+        assert!(paths["/health"]["get"]["responses"].get("401").is_none());
+    }
+
+    #[test]
+    fn test_document_with_collections() {
+        let mut config = Config::default();
+        config.collections.insert(
+            "cats".to_string(),
+            crate::config::CollectionConfig {
+                sources: vec![crate::config::ImageSource::Path("/cats".into()).into()],
+                cache: None,
+            },
+        );
+        let document = document(&config);
+
+        let paths = document["paths"].as_object().unwrap();
+        assert_eq!(
+            paths["/cats/random"]["get"]["summary"],
+            "A random image, from the cats collection"
+        );
+        assert!(paths.contains_key("/cats/image/{id}"));
+        assert!(paths.contains_key("/cats/list"));
+        assert!(!paths.contains_key("/cats/health"));
+    }
+
+    #[test]
+    fn test_document_with_playlists() {
+        let mut config = Config::default();
+        assert!(
+            !document(&config)["paths"]
+                .as_object()
+                .unwrap()
+                .contains_key("/sequential/{playlist}")
+        );
+
+        config.playlists.insert(
+            "lobby".to_string(),
+            crate::config::PlaylistConfig {
+                items: vec![crate::config::ImageSource::Path("/lobby".into())],
+            },
+        );
+        let document = document(&config);
+        assert_eq!(
+            document["paths"]["/sequential/{playlist}"]["get"]["summary"],
+            "The next image of a playlist"
+        );
+    }
+}
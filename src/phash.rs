@@ -0,0 +1,93 @@
+//! Perceptual hashes of images, used to detect near-duplicates, enabled by the `perceptual-hash` feature.
+//!
+//! The hash is a 64-bit difference hash (dHash): the image is shrunk to 9x8 grayscale pixels,
+//! and each bit records whether a pixel is brighter than its right neighbour. Similar images
+//! have hashes that differ in few bits. Without the feature, no hashes are computed.
+
+/// Compute the perceptual hash of an encoded image
+///
+/// Returns `None` if the image can't be decoded, or the server was built without the `perceptual-hash` feature.
+#[must_use]
+#[cfg_attr(not(feature = "perceptual-hash"), allow(clippy::missing_const_for_fn))]
+pub fn perceptual_hash(data: &[u8]) -> Option<u64> {
+    #[cfg(feature = "perceptual-hash")]
+    {
+        let image = image::load_from_memory(data).ok()?;
+        let pixels = image
+            .resize_exact(9, 8, image::imageops::FilterType::Triangle)
+            .to_luma8();
+        let hash = (0..8)
+            .flat_map(|y| (0..8).map(move |x| (x, y)))
+            .fold(0u64, |hash, (x, y)| {
+                let brighter = pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0];
+                (hash << 1) | u64::from(brighter)
+            });
+        Some(hash)
+    }
+
+    #[cfg(not(feature = "perceptual-hash"))]
+    {
+        let _ = data;
+        None
+    }
+}
+
+/// The number of bits two perceptual hashes differ in, from 0 (identical) to 64
+#[must_use]
+pub const fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Format a perceptual hash as 16 hexadecimal digits
+#[must_use]
+pub fn format_hash(hash: u64) -> String {
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance(0, 0), 0);
+        assert_eq!(distance(0b1010, 0b0110), 2);
+        assert_eq!(distance(0, u64::MAX), 64);
+    }
+
+    #[test]
+    fn test_format_hash() {
+        assert_eq!(format_hash(0xab), "00000000000000ab");
+    }
+
+    #[test]
+    fn test_perceptual_hash_invalid_image() {
+        assert_eq!(perceptual_hash(&[0xFF, 0xD8, 0xFF]), None);
+    }
+
+    #[cfg(feature = "perceptual-hash")]
+    #[test]
+    fn test_perceptual_hash_near_duplicates() {
+        let gradient = |offset: u8| {
+            let image = image::GrayImage::from_fn(64, 64, |x, y| {
+                image::Luma([u8::try_from(x * 2 + y).unwrap().saturating_add(offset)])
+            });
+            let mut data = std::io::Cursor::new(Vec::new());
+            image.write_to(&mut data, image::ImageFormat::Png).unwrap();
+            data.into_inner()
+        };
+        let flipped = {
+            let image = image::load_from_memory(&gradient(0)).unwrap().fliph();
+            let mut data = std::io::Cursor::new(Vec::new());
+            image.write_to(&mut data, image::ImageFormat::Png).unwrap();
+            data.into_inner()
+        };
+
+        let original = perceptual_hash(&gradient(0)).unwrap();
+        let brighter = perceptual_hash(&gradient(10)).unwrap();
+        let flipped = perceptual_hash(&flipped).unwrap();
+        assert!(distance(original, brighter) <= 4);
+        assert!(distance(original, flipped) > 32);
+    }
+}
@@ -0,0 +1,454 @@
+//! Placeholder images of any size, generated on the fly at `/placeholder/{width}x{height}`:
+//! PNGs of a solid color or a gradient, optionally labelled with their dimensions.
+
+use std::{fmt, io::Write as _, str::FromStr};
+
+use anyhow::{Result, anyhow};
+use flate2::{Compression, write::ZlibEncoder};
+
+use crate::{
+    cache::{CacheKey, CacheValue, Validators},
+    config::{CacheBackendType, CacheConfig, EvictionPolicy, PlaceholderConfig},
+    font::{self, Canvas},
+    query::PlaceholderQuery,
+};
+
+/// The most bytes of placeholders the placeholder cache holds, unless `placeholder.cache` is configured
+pub const DEFAULT_CACHE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// The width and height of the placeholder served while no images are available, with `server.on_empty = "placeholder"`
+pub const EMPTY_CACHE_SIZE: (u32, u32) = (640, 480);
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+impl PlaceholderConfig {
+    /// The settings of the cache generated placeholders are stored in
+    ///
+    /// By default, an in-memory cache of up to `DEFAULT_CACHE_BYTES`, evicting the oldest placeholders first,
+    /// since any of them can be generated again.
+    #[must_use]
+    pub fn cache_config(&self) -> CacheConfig {
+        self.cache.clone().unwrap_or_else(|| CacheConfig {
+            backend: CacheBackendType::InMemory,
+            max_bytes: Some(DEFAULT_CACHE_BYTES),
+            eviction: EvictionPolicy::EvictOldest,
+            ..CacheConfig::default()
+        })
+    }
+}
+
+/// A color, as its red, green, and blue components
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color(pub [u8; 3]);
+
+impl Color {
+    pub const BLACK: Self = Self([0, 0, 0]);
+    pub const WHITE: Self = Self([0xff, 0xff, 0xff]);
+    /// The background of placeholders, unless `color` is given
+    pub const GRAY: Self = Self([0xcc, 0xcc, 0xcc]);
+
+    /// Whether dark text is more legible than light text on this color
+    #[must_use]
+    pub fn is_light(self) -> bool {
+        let [r, g, b] = self.0.map(u32::from);
+        299 * r + 587 * g + 114 * b > 128_000
+    }
+
+    /// The color the given step of the way from this color to the other, out of `steps` steps
+    #[must_use]
+    fn mix(self, other: Self, step: usize, steps: usize) -> Self {
+        let last = steps.saturating_sub(1);
+        if last == 0 {
+            return self;
+        }
+        let mut mixed = self.0;
+        for (channel, (from, to)) in mixed.iter_mut().zip(self.0.into_iter().zip(other.0)) {
+            let value = (usize::from(from) * (last - step) + usize::from(to) * step) / last;
+            *channel = u8::try_from(value).unwrap_or(u8::MAX);
+        }
+        Self(mixed)
+    }
+}
+
+impl FromStr for Color {
+    type Err = anyhow::Error;
+
+    /// Parse a hex color, like `ccc` or `#cc0000`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("Invalid color, expected a hex color like `ccc` or `cc0000`: {s}");
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let component = |digits: &str| u8::from_str_radix(digits, 16).map_err(|_| invalid());
+        match hex.len() {
+            3 => Ok(Self([
+                component(&hex[0..1].repeat(2))?,
+                component(&hex[1..2].repeat(2))?,
+                component(&hex[2..3].repeat(2))?,
+            ])),
+            6 => Ok(Self([
+                component(&hex[0..2])?,
+                component(&hex[2..4])?,
+                component(&hex[4..6])?,
+            ])),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b] = self.0;
+        write!(f, "{r:02x}{g:02x}{b:02x}")
+    }
+}
+
+/// Which way the gradient of a placeholder runs
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GradientDirection {
+    /// From the left edge to the right edge
+    #[default]
+    Horizontal,
+    /// From the top edge to the bottom edge
+    Vertical,
+}
+
+impl GradientDirection {
+    /// The value of the `direction` parameter selecting this direction
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Horizontal => "horizontal",
+            Self::Vertical => "vertical",
+        }
+    }
+}
+
+impl FromStr for GradientDirection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "horizontal" => Ok(Self::Horizontal),
+            "vertical" => Ok(Self::Vertical),
+            _ => Err(anyhow!("Unknown direction: {s}")),
+        }
+    }
+}
+
+/// A placeholder image to generate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placeholder {
+    pub width: u32,
+    pub height: u32,
+    /// How it is drawn
+    pub style: PlaceholderQuery,
+}
+
+impl Placeholder {
+    /// The placeholder of the given size, e.g. `640x480`, drawn in the given style
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the size is malformed, empty, or larger than the configured limits.
+    pub fn new(size: &str, style: PlaceholderQuery, config: &PlaceholderConfig) -> Result<Self> {
+        let (width, height) = size
+            .split_once('x')
+            .and_then(|(width, height)| Some((parse_side(width)?, parse_side(height)?)))
+            .ok_or_else(|| anyhow!("Invalid size, expected `{{width}}x{{height}}`: {size}"))?;
+        if width > config.max_width || height > config.max_height {
+            return Err(anyhow!(
+                "The size {size} is larger than the largest of {}x{}",
+                config.max_width,
+                config.max_height
+            ));
+        }
+        Ok(Self {
+            width,
+            height,
+            style,
+        })
+    }
+
+    /// The placeholder served by `/random` and `/sequential` while no images are available,
+    /// with `server.on_empty = "placeholder"`
+    #[must_use]
+    pub fn empty_cache() -> Self {
+        let (width, height) = EMPTY_CACHE_SIZE;
+        Self {
+            width,
+            height,
+            style: PlaceholderQuery::default(),
+        }
+    }
+
+    /// The synthetic key the placeholder is cached under, the same for all requests for it
+    #[must_use]
+    pub fn key(&self) -> CacheKey {
+        CacheKey::Generated(format!(
+            "placeholder/{}x{}?{}",
+            self.width,
+            self.height,
+            self.style.query_string()
+        ))
+    }
+
+    /// Draw the placeholder, as a PNG
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image can't be compressed.
+    pub fn render(&self) -> Result<CacheValue> {
+        let mut pixels = self.background();
+        if self.style.text {
+            self.draw_dimensions(&mut pixels);
+        }
+        Ok(CacheValue {
+            data: encode_png(self.width, self.height, &pixels)?,
+            content_type: "image/png".to_string(),
+            validators: Validators::default(),
+        })
+    }
+
+    /// The color at the middle of the placeholder, that the text is drawn over
+    fn middle_color(&self) -> Color {
+        let to = self.style.to.unwrap_or(self.style.color);
+        self.style.color.mix(to, 1, 3)
+    }
+
+    /// The RGB pixels of the background, row by row
+    fn background(&self) -> Vec<u8> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let from = self.style.color;
+        let to = self.style.to.unwrap_or(from);
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        match self.style.direction {
+            GradientDirection::Horizontal => {
+                let row: Vec<u8> = (0..width).flat_map(|x| from.mix(to, x, width).0).collect();
+                for _ in 0..height {
+                    pixels.extend_from_slice(&row);
+                }
+            }
+            GradientDirection::Vertical => {
+                for y in 0..height {
+                    let color = from.mix(to, y, height).0;
+                    for _ in 0..width {
+                        pixels.extend_from_slice(&color);
+                    }
+                }
+            }
+        }
+        pixels
+    }
+
+    /// Draw the dimensions of the placeholder at its middle, as large as fits comfortably,
+    /// or not at all if the placeholder is too small for them
+    fn draw_dimensions(&self, pixels: &mut [u8]) {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let text = format!("{}x{}", self.width, self.height);
+        let text_width = font::text_width(&text);
+        let scale = (width / 2 / text_width).min(height / 4 / font::GLYPH_HEIGHT);
+        if scale == 0 {
+            return;
+        }
+        let color = if self.middle_color().is_light() {
+            Color::BLACK
+        } else {
+            Color::WHITE
+        };
+        let left = (width - text_width * scale) / 2;
+        let top = (height - font::GLYPH_HEIGHT * scale) / 2;
+        let mut canvas = Canvas {
+            pixels,
+            width,
+            channels: 3,
+        };
+        canvas.draw_text(&text, (left, top), scale, color.0);
+    }
+}
+
+/// Parse the width or height of a placeholder, which must be at least a pixel
+fn parse_side(side: &str) -> Option<u32> {
+    if !side.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    side.parse().ok().filter(|side| *side > 0)
+}
+
+/// Encode RGB pixels, row by row, as an 8-bit truecolor PNG
+fn encode_png(width: u32, height: u32, pixels: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in pixels.chunks_exact(width as usize * 3) {
+        // each row is preceded by its filter type, none
+        encoder.write_all(&[0])?;
+        encoder.write_all(row)?;
+    }
+    let data = encoder.finish()?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // bit depth 8, truecolor, deflate, adaptive filtering, no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut png, *b"IHDR", &header)?;
+    write_chunk(&mut png, *b"IDAT", &data)?;
+    write_chunk(&mut png, *b"IEND", &[])?;
+    Ok(png)
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: [u8; 4], data: &[u8]) -> Result<()> {
+    let mut crc = flate2::Crc::new();
+    crc.update(&kind);
+    crc.update(data);
+    png.extend_from_slice(&u32::try_from(data.len())?.to_be_bytes());
+    png.extend_from_slice(&kind);
+    png.extend_from_slice(data);
+    png.extend_from_slice(&crc.sum().to_be_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::short("ccc", Some([0xcc, 0xcc, 0xcc]))]
+    #[case::long("cc0000", Some([0xcc, 0, 0]))]
+    #[case::hash("#00FF7f", Some([0, 0xff, 0x7f]))]
+    #[case::name("red", None)]
+    #[case::length("cccc", None)]
+    #[case::sign("+c0000", None)]
+    #[case::empty("", None)]
+    fn test_color_parse(#[case] color: &str, #[case] expected: Option<[u8; 3]>) {
+        let parsed = color.parse::<Color>().ok();
+        assert_eq!(parsed, expected.map(Color));
+        if let Some(parsed) = parsed {
+            assert_eq!(parsed.to_string().parse::<Color>().unwrap(), parsed);
+        }
+    }
+
+    #[rstest]
+    #[case::valid("640x480", Some((640, 480)))]
+    #[case::largest("4096x4096", Some((4096, 4096)))]
+    #[case::too_wide("4097x1", None)]
+    #[case::too_tall("1x4097", None)]
+    #[case::empty("0x480", None)]
+    #[case::separator("640*480", None)]
+    #[case::sign("+640x480", None)]
+    #[case::missing("640x", None)]
+    fn test_placeholder_new(#[case] size: &str, #[case] expected: Option<(u32, u32)>) {
+        let placeholder = Placeholder::new(
+            size,
+            PlaceholderQuery::default(),
+            &PlaceholderConfig::default(),
+        );
+        assert_eq!(
+            placeholder
+                .ok()
+                .map(|placeholder| (placeholder.width, placeholder.height)),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_placeholder_key() {
+        let config = PlaceholderConfig::default();
+        let placeholder =
+            Placeholder::new("640x480", PlaceholderQuery::default(), &config).unwrap();
+        assert_eq!(
+            placeholder.key(),
+            CacheKey::Generated(
+                "placeholder/640x480?color=cccccc&direction=horizontal&text=true".to_string()
+            )
+        );
+        let gradient = PlaceholderQuery {
+            to: Some(Color::BLACK),
+            ..PlaceholderQuery::default()
+        };
+        assert_ne!(
+            Placeholder::new("640x480", gradient, &config)
+                .unwrap()
+                .key(),
+            placeholder.key()
+        );
+    }
+
+    const RED: [u8; 3] = [0xff, 0, 0];
+    const PURPLE: [u8; 3] = [0x7f, 0, 0x7f];
+    const BLUE: [u8; 3] = [0, 0, 0xff];
+
+    #[rstest]
+    #[case::horizontal(GradientDirection::Horizontal, [RED, PURPLE, BLUE, RED, PURPLE, BLUE])]
+    #[case::vertical(GradientDirection::Vertical, [RED, RED, RED, BLUE, BLUE, BLUE])]
+    fn test_placeholder_background(
+        #[case] direction: GradientDirection,
+        #[case] expected: [[u8; 3]; 6],
+    ) {
+        let style = PlaceholderQuery {
+            color: Color(RED),
+            to: Some(Color(BLUE)),
+            direction,
+            text: false,
+        };
+        let placeholder = Placeholder::new("3x2", style, &PlaceholderConfig::default()).unwrap();
+        assert_eq!(placeholder.background(), expected.concat());
+    }
+
+    #[test]
+    fn test_placeholder_render() {
+        let config = PlaceholderConfig::default();
+        let placeholder =
+            Placeholder::new("320x200", PlaceholderQuery::default(), &config).unwrap();
+        let image = placeholder.render().unwrap();
+        assert_eq!(image.content_type, "image/png");
+        assert_eq!(
+            imagesize::image_type(&image.data).unwrap(),
+            imagesize::ImageType::Png
+        );
+        let size = imagesize::blob_size(&image.data).unwrap();
+        assert_eq!((size.width, size.height), (320, 200));
+
+        // the dimensions are drawn in black over the light gray background
+        let mut pixels = placeholder.background();
+        placeholder.draw_dimensions(&mut pixels);
+        assert!(pixels.chunks_exact(3).any(|pixel| pixel == Color::BLACK.0));
+        let without_text = PlaceholderQuery {
+            text: false,
+            ..PlaceholderQuery::default()
+        };
+        let plain = Placeholder::new("320x200", without_text, &config).unwrap();
+        assert_ne!(plain.render().unwrap(), image);
+
+        // too small for the dimensions to fit
+        let tiny = Placeholder::new("8x8", PlaceholderQuery::default(), &config).unwrap();
+        let mut pixels = tiny.background();
+        tiny.draw_dimensions(&mut pixels);
+        assert_eq!(pixels, tiny.background());
+    }
+
+    #[cfg(feature = "perceptual-hash")]
+    #[test]
+    fn test_placeholder_render_decodes() {
+        let style = PlaceholderQuery {
+            color: Color(RED),
+            to: Some(Color(BLUE)),
+            direction: GradientDirection::Vertical,
+            text: true,
+        };
+        let placeholder =
+            Placeholder::new("200x100", style, &PlaceholderConfig::default()).unwrap();
+        let image = image::load_from_memory(&placeholder.render().unwrap().data)
+            .unwrap()
+            .into_rgb8();
+        assert_eq!(image.dimensions(), (200, 100));
+        assert_eq!(image.get_pixel(0, 0).0, RED);
+        assert_eq!(image.get_pixel(199, 99).0, BLUE);
+        // the dimensions are drawn in white over the dark purple middle
+        assert!(image.pixels().any(|pixel| pixel.0 == Color::WHITE.0));
+    }
+}
@@ -0,0 +1,121 @@
+//! Accounting for populating the cache with the configured images.
+
+use std::{fmt, time::Duration};
+
+/// The outcome of populating the cache with the configured images, as returned by
+/// [`ImageServer::populate_cache`](crate::ImageServer::populate_cache)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PopulateReport {
+    /// The outcome of loading each configured source, in the order they are configured
+    pub sources: Vec<SourcePopulateReport>,
+    /// How long populating the cache took
+    pub elapsed: Duration,
+}
+
+/// The outcome of loading the images from a single configured source
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourcePopulateReport {
+    /// The configured source
+    pub source: String,
+    /// The number of images loaded into the cache
+    pub images: usize,
+    /// The number of images skipped for being outside the configured limits, or near-duplicates
+    pub skipped: usize,
+    /// The number of errors encountered while loading the source
+    pub errors: usize,
+    /// The most recent error encountered while loading the source
+    pub last_error: Option<String>,
+    /// Whether the source wasn't loaded, because it's remote and the server is offline
+    pub offline: bool,
+}
+
+impl PopulateReport {
+    /// The total number of images loaded into the cache
+    #[must_use]
+    pub fn images(&self) -> usize {
+        self.sources.iter().map(|source| source.images).sum()
+    }
+
+    /// The total number of images skipped for being outside the configured limits, or near-duplicates
+    #[must_use]
+    pub fn skipped(&self) -> usize {
+        self.sources.iter().map(|source| source.skipped).sum()
+    }
+
+    /// The total number of errors encountered while loading the sources
+    #[must_use]
+    pub fn errors(&self) -> usize {
+        self.sources.iter().map(|source| source.errors).sum()
+    }
+
+    /// The number of sources that encountered at least one error
+    #[must_use]
+    pub fn failed_sources(&self) -> usize {
+        self.sources
+            .iter()
+            .filter(|source| source.errors > 0)
+            .count()
+    }
+}
+
+impl fmt::Display for PopulateReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Loaded {} image(s) from {} source(s) in {:.2?}, skipped {}, {} error(s) from {} source(s)",
+            self.images(),
+            self.sources.len(),
+            self.elapsed,
+            self.skipped(),
+            self.errors(),
+            self.failed_sources()
+        )?;
+        let offline = self.sources.iter().filter(|source| source.offline).count();
+        if offline > 0 {
+            write!(f, ", {offline} remote source(s) skipped in offline mode")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_populate_report_totals() {
+        let report = PopulateReport {
+            sources: vec![
+                SourcePopulateReport {
+                    source: "/images".to_string(),
+                    images: 3,
+                    skipped: 1,
+                    ..SourcePopulateReport::default()
+                },
+                SourcePopulateReport {
+                    source: "/broken".to_string(),
+                    images: 1,
+                    errors: 2,
+                    last_error: Some("Failed to read image".to_string()),
+                    ..SourcePopulateReport::default()
+                },
+                SourcePopulateReport {
+                    source: "https://example.com/image.png".to_string(),
+                    offline: true,
+                    ..SourcePopulateReport::default()
+                },
+            ],
+            elapsed: Duration::from_millis(1500),
+        };
+        assert_eq!(report.images(), 4);
+        assert_eq!(report.skipped(), 1);
+        assert_eq!(report.errors(), 2);
+        assert_eq!(report.failed_sources(), 1);
+        assert_eq!(
+            report.to_string(),
+            "Loaded 4 image(s) from 3 source(s) in 1.50s, skipped 1, 2 error(s) from 1 source(s), \
+             1 remote source(s) skipped in offline mode"
+        );
+    }
+}
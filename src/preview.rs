@@ -0,0 +1,247 @@
+//! Previews of images, their dominant color and blurhash, enabled by the `blurhash` feature.
+//!
+//! Frontends can fill the space of an image with its dominant color, or with the blurry rendering of
+//! its [blurhash](https://blurha.sh), while the image itself loads. Both are computed from a thumbnail
+//! of the image when it's loaded. Without the feature, no previews are computed.
+
+use crate::placeholder::Color;
+
+/// The number of horizontal components of blurhashes, enough to render the broad layout of an image
+pub const BLURHASH_X_COMPONENTS: u32 = 4;
+/// The number of vertical components of blurhashes
+pub const BLURHASH_Y_COMPONENTS: u32 = 3;
+
+/// The preview of an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preview {
+    /// The most common color of the image, averaged over the pixels close to it
+    pub dominant_color: Color,
+    /// The blurhash of the image, with [`BLURHASH_X_COMPONENTS`] by [`BLURHASH_Y_COMPONENTS`] components
+    pub blurhash: String,
+}
+
+/// Compute the preview of an encoded image
+///
+/// Returns `None` if the image can't be decoded, is fully transparent, or the server was built
+/// without the `blurhash` feature.
+#[must_use]
+#[cfg_attr(not(feature = "blurhash"), allow(clippy::missing_const_for_fn))]
+pub fn preview(data: &[u8]) -> Option<Preview> {
+    #[cfg(feature = "blurhash")]
+    {
+        // both are computed from a thumbnail, since details don't change either of them
+        let thumbnail = image::load_from_memory(data).ok()?.thumbnail(32, 32);
+        Some(Preview {
+            dominant_color: dominant_color(&thumbnail.to_rgba8())?,
+            blurhash: blurhash(&thumbnail.to_rgb8()),
+        })
+    }
+
+    #[cfg(not(feature = "blurhash"))]
+    {
+        let _ = data;
+        None
+    }
+}
+
+/// The most common color of an image: its opaque pixels are grouped by the 3 most significant bits
+/// of each channel, and the pixels of the largest group are averaged
+#[cfg(feature = "blurhash")]
+fn dominant_color(image: &image::RgbaImage) -> Option<Color> {
+    let mut groups = vec![(0_u32, [0_u32; 3]); 512];
+    for pixel in image.pixels().filter(|pixel| pixel.0[3] >= 0x80) {
+        let [r, g, b, _] = pixel.0;
+        let group =
+            &mut groups[usize::from(r >> 5) << 6 | usize::from(g >> 5) << 3 | usize::from(b >> 5)];
+        group.0 += 1;
+        for (sum, channel) in group.1.iter_mut().zip([r, g, b]) {
+            *sum += u32::from(channel);
+        }
+    }
+    let (count, sums) = groups.into_iter().max_by_key(|(count, _)| *count)?;
+    // the average of a channel fits in one
+    #[allow(clippy::cast_possible_truncation)]
+    (count > 0).then(|| Color(sums.map(|sum| (sum / count) as u8)))
+}
+
+/// The characters of the base 83 encoding of blurhashes
+#[cfg(feature = "blurhash")]
+const BASE83: &[u8; 83] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// Append `value` to a blurhash as `length` base 83 digits
+#[cfg(feature = "blurhash")]
+fn push_base83(hash: &mut String, value: u32, length: u32) {
+    for digit in (0..length).rev() {
+        hash.push(char::from(
+            BASE83[(value / 83_u32.pow(digit) % 83) as usize],
+        ));
+    }
+}
+
+/// Convert a channel of an sRGB color to linear light, from 0 to 1
+#[cfg(feature = "blurhash")]
+fn srgb_to_linear(channel: u8) -> f32 {
+    let value = f32::from(channel) / 255.0;
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert a channel in linear light back to sRGB
+#[cfg(feature = "blurhash")]
+fn linear_to_srgb(value: f32) -> u32 {
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055f32.mul_add(value.powf(1.0 / 2.4), -0.055)
+    };
+    // the value is clamped to the range of a channel
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let srgb = srgb.mul_add(255.0, 0.5) as u32;
+    srgb
+}
+
+/// Quantize an AC component of a blurhash, relative to the largest one, to one of 19 levels
+#[cfg(feature = "blurhash")]
+fn quantize_ac(value: f32, max: f32) -> u32 {
+    let scaled = (value / max).signum() * (value / max).abs().sqrt();
+    // the level is clamped to 0..=18
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let level = scaled.mul_add(9.0, 9.5).floor().clamp(0.0, 18.0) as u32;
+    level
+}
+
+/// Compute the blurhash of an image, as described by <https://github.com/woltapp/blurhash/blob/master/Algorithm.md>
+#[cfg(feature = "blurhash")]
+fn blurhash(image: &image::RgbImage) -> String {
+    use std::f32::consts::PI;
+
+    let (width, height) = image.dimensions();
+    #[allow(clippy::cast_precision_loss)]
+    let (w, h) = (width as f32, height as f32);
+    let factors: Vec<[f32; 3]> = (0..BLURHASH_Y_COMPONENTS)
+        .flat_map(|j| (0..BLURHASH_X_COMPONENTS).map(move |i| (i, j)))
+        .map(|(i, j)| {
+            #[allow(clippy::cast_precision_loss)]
+            let (i, j) = (i as f32, j as f32);
+            let mut factor = [0.0_f32; 3];
+            for (x, y, pixel) in image.enumerate_pixels() {
+                #[allow(clippy::cast_precision_loss)]
+                let basis = (PI * i * x as f32 / w).cos() * (PI * j * y as f32 / h).cos();
+                for (sum, channel) in factor.iter_mut().zip(pixel.0) {
+                    *sum = basis.mul_add(srgb_to_linear(channel), *sum);
+                }
+            }
+            let normalisation = if i == 0.0 && j == 0.0 { 1.0 } else { 2.0 };
+            factor.map(|sum| sum * normalisation / (w * h))
+        })
+        .collect();
+    let (dc, ac) = factors
+        .split_first()
+        .expect("there is at least one component");
+
+    let mut hash = String::new();
+    push_base83(
+        &mut hash,
+        (BLURHASH_X_COMPONENTS - 1) + (BLURHASH_Y_COMPONENTS - 1) * 9,
+        1,
+    );
+    let largest = ac
+        .iter()
+        .flatten()
+        .fold(0.0_f32, |max, value| max.max(value.abs()));
+    // the quantized maximum is clamped to 0..=82
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let quantized_max = largest.mul_add(166.0, -0.5).floor().clamp(0.0, 82.0) as u32;
+    #[allow(clippy::cast_precision_loss)]
+    let max = if ac.is_empty() {
+        1.0
+    } else {
+        (quantized_max + 1) as f32 / 166.0
+    };
+    push_base83(&mut hash, if ac.is_empty() { 0 } else { quantized_max }, 1);
+    let [r, g, b] = dc.map(linear_to_srgb);
+    push_base83(&mut hash, (r << 16) + (g << 8) + b, 4);
+    for [r, g, b] in ac {
+        let [r, g, b] = [r, g, b].map(|value| quantize_ac(*value, max));
+        push_base83(&mut hash, r * 19 * 19 + g * 19 + b, 2);
+    }
+    hash
+}
+
+/// Format a color as a CSS hex color, e.g. `#1a2b3c`
+#[must_use]
+pub fn format_color(color: Color) -> String {
+    format!("#{color}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_format_color() {
+        assert_eq!(format_color(Color([0x1a, 0x2b, 0x3c])), "#1a2b3c");
+    }
+
+    #[test]
+    fn test_preview_invalid_image() {
+        assert_eq!(preview(&[0xFF, 0xD8, 0xFF]), None);
+    }
+
+    #[cfg(feature = "blurhash")]
+    fn encode(image: image::RgbaImage) -> Vec<u8> {
+        let mut data = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut data, image::ImageFormat::Png).unwrap();
+        data.into_inner()
+    }
+
+    #[cfg(feature = "blurhash")]
+    #[test]
+    fn test_preview_solid() {
+        let black = preview(&encode(image::RgbaImage::from_pixel(
+            10,
+            10,
+            image::Rgba([0, 0, 0, 0xff]),
+        )))
+        .unwrap();
+        assert_eq!(black.dominant_color, Color::BLACK);
+        // the well-known blurhash of a black image
+        assert_eq!(black.blurhash, "L00000fQfQfQfQfQfQfQfQfQfQfQ");
+
+        let white = preview(&encode(image::RgbaImage::from_pixel(
+            64,
+            48,
+            image::Rgba([0xff, 0xff, 0xff, 0xff]),
+        )))
+        .unwrap();
+        assert_eq!(white.dominant_color, Color::WHITE);
+        // 4 by 3 components, and an average color of white
+        assert_eq!(&white.blurhash[..1], "L");
+        assert_eq!(&white.blurhash[2..6], "TSUA");
+        assert_eq!(white.blurhash.len(), 28);
+    }
+
+    #[cfg(feature = "blurhash")]
+    #[test]
+    fn test_preview_dominant_color() {
+        // mostly red, with a green stripe and a transparent blue half that doesn't count
+        let image = image::RgbaImage::from_fn(40, 20, |x, _| match x {
+            0..5 => image::Rgba([0, 0xff, 0, 0xff]),
+            5..20 => image::Rgba([0xf0, 0x10, 0x10, 0xff]),
+            _ => image::Rgba([0, 0, 0xff, 0]),
+        });
+        let preview = preview(&encode(image)).unwrap();
+        assert_eq!(preview.dominant_color, Color([0xf0, 0x10, 0x10]));
+        assert_eq!(preview.blurhash.len(), 28);
+        assert_ne!(&preview.blurhash[6..], "fQ".repeat(11));
+
+        let transparent = image::RgbaImage::from_pixel(8, 8, image::Rgba([0, 0, 0, 0]));
+        assert_eq!(super::preview(&encode(transparent)), None);
+    }
+}
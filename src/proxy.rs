@@ -0,0 +1,126 @@
+//! Proxying remote images through a cache of their own, at `/proxy?url=...`, from the configured domains only.
+
+use std::fmt;
+
+use url::Url;
+
+use crate::config::{CacheBackendType, CacheConfig, EvictionPolicy, ProxyConfig};
+
+/// The most bytes of images the proxy cache holds, unless `proxy.cache` is configured
+pub const DEFAULT_CACHE_BYTES: u64 = 256 * 1024 * 1024;
+
+impl ProxyConfig {
+    /// Whether images may be proxied, which is the case once any domain is allowed
+    #[must_use]
+    pub const fn is_enabled(&self) -> bool {
+        !self.allowed_domains.is_empty()
+    }
+
+    /// Whether an image may be proxied from the URL, an `http` or `https` URL on one of the allowed domains
+    #[must_use]
+    pub fn allows(&self, url: &Url) -> bool {
+        matches!(url.scheme(), "http" | "https")
+            && url.host_str().is_some_and(|host| {
+                crate::referer::is_on_domains(&host.to_ascii_lowercase(), &self.allowed_domains)
+            })
+    }
+
+    /// The settings of the cache proxied images are stored in
+    ///
+    /// By default, an in-memory cache of up to `DEFAULT_CACHE_BYTES`, evicting the oldest images first,
+    /// so that proxying arbitrary images can't exhaust the memory of the server.
+    #[must_use]
+    pub fn cache_config(&self) -> CacheConfig {
+        self.cache.clone().unwrap_or_else(|| CacheConfig {
+            backend: CacheBackendType::InMemory,
+            max_bytes: Some(DEFAULT_CACHE_BYTES),
+            eviction: EvictionPolicy::EvictOldest,
+            ..CacheConfig::default()
+        })
+    }
+}
+
+/// Why an image could not be proxied
+#[derive(Debug)]
+pub enum ProxyError {
+    /// No domains are allowed, so the proxy is disabled
+    Disabled,
+    /// The URL is not on one of the allowed domains
+    NotAllowed,
+    /// The image could not be fetched, or isn't an image with one of the allowed extensions
+    Fetch(anyhow::Error),
+    /// The size of the image, in bytes, is outside the configured limits
+    OutsideLimits(u64),
+    /// The fetched image could not be served
+    Response(anyhow::Error),
+}
+
+impl ProxyError {
+    /// The status of the response to a request that failed with this error
+    #[must_use]
+    pub const fn status(&self) -> hyper::StatusCode {
+        match self {
+            Self::Disabled => hyper::StatusCode::NOT_FOUND,
+            Self::NotAllowed => hyper::StatusCode::FORBIDDEN,
+            Self::Fetch(_) | Self::OutsideLimits(_) => hyper::StatusCode::BAD_GATEWAY,
+            Self::Response(_) => hyper::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disabled => f.write_str("the proxy is disabled, no domains are allowed"),
+            Self::NotAllowed => f.write_str("the URL is not on one of the allowed domains"),
+            Self::Fetch(e) => write!(f, "failed to fetch the image: {e}"),
+            Self::OutsideLimits(size) => write!(
+                f,
+                "the file size of {size} bytes is outside the configured limits"
+            ),
+            Self::Response(e) => write!(f, "failed to serve the image: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::domain("https://example.com/image.jpg", true)]
+    #[case::subdomain("https://images.example.com/image.jpg", true)]
+    #[case::http("http://example.com/image.jpg", true)]
+    #[case::case_insensitive("https://EXAMPLE.com/image.jpg", true)]
+    #[case::other_domain("https://evil.example.net/image.jpg", false)]
+    #[case::suffix_only("https://notexample.com/image.jpg", false)]
+    #[case::other_scheme("ftp://example.com/image.jpg", false)]
+    #[case::file("file:///etc/passwd", false)]
+    fn test_allows(#[case] url: &str, #[case] expected: bool) {
+        let config = ProxyConfig {
+            allowed_domains: vec!["example.com".to_string()],
+            cache: None,
+        };
+        assert_eq!(config.allows(&url.parse().unwrap()), expected);
+        assert!(!ProxyConfig::default().is_enabled());
+    }
+
+    #[test]
+    fn test_cache_config() {
+        let mut config = ProxyConfig::default();
+        let cache = config.cache_config();
+        assert_eq!(cache.backend, CacheBackendType::InMemory);
+        assert_eq!(cache.max_bytes, Some(DEFAULT_CACHE_BYTES));
+        assert_eq!(cache.eviction, EvictionPolicy::EvictOldest);
+
+        config.cache = Some(CacheConfig {
+            backend: CacheBackendType::FileSystem,
+            ..CacheConfig::default()
+        });
+        assert_eq!(config.cache_config().backend, CacheBackendType::FileSystem);
+    }
+}
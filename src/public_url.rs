@@ -0,0 +1,154 @@
+use std::net::SocketAddr;
+
+use hyper::{Request, header::HOST};
+use url::Url;
+
+use crate::config::ServerConfig;
+use crate::tls::Secure;
+
+/// The address of the peer that sent a request, attached to requests as an extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// Determine the externally visible base URL (scheme, host, and base path) for a request
+///
+/// The following sources are used, in order of precedence:
+/// 1. `server.public_url`, if configured
+/// 2. the `X-Forwarded-Proto` and `X-Forwarded-Host` headers, if the peer is a trusted proxy
+/// 3. the `Host` header of the request, or its URI
+/// 4. the configured host and port
+///
+/// The scheme is `https` if the request was received over TLS, unless a trusted proxy says otherwise.
+#[must_use]
+pub fn public_base_url<B>(req: &Request<B>, config: &ServerConfig) -> String {
+    let base_path = &config.base_path;
+    if let Some(public_url) = &config.public_url {
+        return format!("{}{base_path}", public_url.as_str().trim_end_matches('/'));
+    }
+
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+
+    let trusted = req
+        .extensions()
+        .get::<RemoteAddr>()
+        .is_some_and(|RemoteAddr(addr)| crate::access::is_trusted_proxy(config, addr.ip()));
+
+    let scheme = trusted
+        .then(|| header("x-forwarded-proto"))
+        .flatten()
+        .filter(|scheme| matches!(*scheme, "http" | "https"))
+        .unwrap_or(if req.extensions().get::<Secure>().is_some() {
+            "https"
+        } else {
+            "http"
+        });
+    // HTTP/2 and HTTP/3 requests may only carry the host in their URI
+    let host = trusted
+        .then(|| header("x-forwarded-host"))
+        .flatten()
+        .or_else(|| header(HOST.as_str()))
+        .or_else(|| {
+            req.uri()
+                .authority()
+                .map(hyper::http::uri::Authority::as_str)
+        })
+        .map_or_else(
+            || format!("{}:{}", config.host, config.port),
+            str::to_string,
+        );
+
+    match Url::parse(&format!("{scheme}://{host}")) {
+        Ok(url) => format!("{}{base_path}", url.as_str().trim_end_matches('/')),
+        Err(e) => {
+            tracing::warn!("Failed to build public URL from host '{host}': {e}");
+            format!("http://{}:{}{base_path}", config.host, config.port)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    fn request(headers: &[(&str, &str)], peer: Option<&str>) -> Request<()> {
+        let mut builder = Request::builder().uri("/list");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let mut req = builder.body(()).unwrap();
+        if let Some(peer) = peer {
+            req.extensions_mut()
+                .insert(RemoteAddr(peer.parse().unwrap()));
+        }
+        req
+    }
+
+    #[rstest]
+    #[case::fallback(&[], None, None, "", "http://127.0.0.1:3000")]
+    #[case::host_header(&[("host", "example.com")], None, None, "", "http://example.com")]
+    #[case::base_path(&[("host", "example.com")], None, None, "/images", "http://example.com/images")]
+    #[case::public_url(&[("host", "example.com")], None, Some("https://images.example.org/"), "/images", "https://images.example.org/images")]
+    #[case::untrusted_proxy(
+        &[("host", "internal:8080"), ("x-forwarded-proto", "https"), ("x-forwarded-host", "example.com")],
+        Some("10.0.0.2:1234"),
+        None,
+        "",
+        "http://internal:8080"
+    )]
+    #[case::trusted_proxy(
+        &[("host", "internal:8080"), ("x-forwarded-proto", "https"), ("x-forwarded-host", "example.com, other.com")],
+        Some("10.0.0.1:1234"),
+        None,
+        "",
+        "https://example.com"
+    )]
+    #[case::trusted_proxy_bad_proto(
+        &[("host", "internal:8080"), ("x-forwarded-proto", "gopher")],
+        Some("10.0.0.1:1234"),
+        None,
+        "",
+        "http://internal:8080"
+    )]
+    fn test_public_base_url(
+        #[case] headers: &[(&str, &str)],
+        #[case] peer: Option<&str>,
+        #[case] public_url: Option<&str>,
+        #[case] base_path: &str,
+        #[case] expected: &str,
+    ) {
+        let config = ServerConfig {
+            public_url: public_url.map(|url| Url::parse(url).unwrap()),
+            trusted_proxies: vec!["10.0.0.1".parse().unwrap()],
+            base_path: base_path.to_string(),
+            ..ServerConfig::default()
+        };
+
+        assert_eq!(public_base_url(&request(headers, peer), &config), expected);
+    }
+
+    #[test]
+    fn test_public_base_url_secure() {
+        let config = ServerConfig::default();
+
+        // requests received over TLS link to https, and HTTP/3 requests carry their host in the URI
+        let mut req = Request::builder()
+            .uri("https://example.com:8443/list")
+            .body(())
+            .unwrap();
+        req.extensions_mut().insert(Secure);
+        assert_eq!(public_base_url(&req, &config), "https://example.com:8443");
+
+        let mut req = request(&[("host", "example.com")], None);
+        req.extensions_mut().insert(Secure);
+        assert_eq!(public_base_url(&req, &config), "https://example.com");
+    }
+}
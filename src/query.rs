@@ -0,0 +1,755 @@
+use std::{str::FromStr, time::Duration};
+
+use anyhow::{Result, anyhow};
+
+use crate::{
+    config::VariantFormat,
+    placeholder::{Color, GradientDirection},
+    transform::{Filter, Flip, Rotation},
+};
+
+/// How `/random` chooses among the cached images
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RandomOrder {
+    /// Every image is equally likely to be chosen
+    #[default]
+    Uniform,
+    /// Only the images that have been served the fewest times are considered
+    LeastServed,
+}
+
+impl FromStr for RandomOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "uniform" => Ok(Self::Uniform),
+            "least_served" => Ok(Self::LeastServed),
+            _ => Err(anyhow!("Unknown order: {s}")),
+        }
+    }
+}
+
+/// The shape of the images `/random` chooses among
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    /// Wider than they are tall
+    Landscape,
+    /// Taller than they are wide
+    Portrait,
+    /// As wide as they are tall
+    Square,
+}
+
+impl Orientation {
+    /// Whether an image of the given dimensions has this orientation
+    #[must_use]
+    pub const fn matches(self, width: usize, height: usize) -> bool {
+        match self {
+            Self::Landscape => width > height,
+            Self::Portrait => width < height,
+            Self::Square => width == height,
+        }
+    }
+}
+
+impl FromStr for Orientation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "landscape" => Ok(Self::Landscape),
+            "portrait" => Ok(Self::Portrait),
+            "square" => Ok(Self::Square),
+            _ => Err(anyhow!("Unknown orientation: {s}")),
+        }
+    }
+}
+
+/// The longest an image may be pinned to a client with `?sticky=`, a week
+pub const MAX_STICKY_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+/// Query parameters accepted by `/random`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RandomQuery {
+    /// `?order=uniform|least_served`
+    pub order: RandomOrder,
+    /// `?sticky={seconds}`, how long the chosen image is pinned to the client, instead of `server.sticky`.
+    /// `?sticky=0` doesn't pin it
+    pub sticky: Option<Duration>,
+    /// `?orientation=landscape|portrait|square`, the shape of the images to choose among
+    pub orientation: Option<Orientation>,
+    /// `?min_width=`, the width in pixels below which images aren't chosen
+    pub min_width: Option<usize>,
+    /// `?min_height=`, the height in pixels below which images aren't chosen
+    pub min_height: Option<usize>,
+    /// `?format=png,webp`, the content types of the images to choose among, any if empty
+    pub formats: Vec<String>,
+    /// How the chosen image is transformed
+    pub transforms: TransformQuery,
+}
+
+impl RandomQuery {
+    /// Parse the query string of a request, ignoring unknown parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a known parameter has an invalid value.
+    pub fn parse(query: Option<&str>) -> Result<Self> {
+        let mut parsed = Self::default();
+        for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            if key == "order" {
+                parsed.order = value.parse()?;
+            } else if key == "sticky" {
+                let seconds = value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|seconds| *seconds <= MAX_STICKY_SECONDS)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Invalid sticky duration {value:?}, it must be at most {MAX_STICKY_SECONDS} seconds"
+                        )
+                    })?;
+                parsed.sticky = Some(Duration::from_secs(seconds));
+            } else if key == "orientation" {
+                parsed.orientation = Some(value.parse()?);
+            } else if key == "min_width" {
+                parsed.min_width = Some(
+                    value
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid minimum width {value:?}"))?,
+                );
+            } else if key == "format" {
+                parsed.formats = parse_formats(&value)?;
+            } else if key == "min_height" {
+                parsed.min_height = Some(
+                    value
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid minimum height {value:?}"))?,
+                );
+            }
+        }
+        parsed.transforms = TransformQuery::parse(query)?;
+        Ok(parsed)
+    }
+
+    /// Whether the images to choose among are filtered by their dimensions
+    #[must_use]
+    pub const fn filters_dimensions(&self) -> bool {
+        self.orientation.is_some() || self.min_width.is_some() || self.min_height.is_some()
+    }
+
+    /// Whether an image with the given dimensions, as `(width, height)`, may be chosen
+    ///
+    /// Images whose dimensions aren't known are only chosen if they aren't filtered by them.
+    #[must_use]
+    pub fn fits(&self, dimensions: Option<(usize, usize)>) -> bool {
+        let Some((width, height)) = dimensions else {
+            return !self.filters_dimensions();
+        };
+        self.orientation
+            .is_none_or(|orientation| orientation.matches(width, height))
+            && self.min_width.is_none_or(|min| width >= min)
+            && self.min_height.is_none_or(|min| height >= min)
+    }
+}
+
+/// Query parameters accepted by `/sequential`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SequentialQuery {
+    /// `?format=png,webp`, the content types of the images to go through, all of them if empty
+    pub formats: Vec<String>,
+    /// How each image is transformed
+    pub transforms: TransformQuery,
+}
+
+impl SequentialQuery {
+    /// Parse the query string of a request, ignoring unknown parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a known parameter has an invalid value.
+    pub fn parse(query: Option<&str>) -> Result<Self> {
+        let mut parsed = Self::default();
+        for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            if key == "format" {
+                parsed.formats = parse_formats(&value)?;
+            }
+        }
+        parsed.transforms = TransformQuery::parse(query)?;
+        Ok(parsed)
+    }
+}
+
+/// Parse a comma-separated list of image formats, given by extension (e.g. `png,webp`)
+/// or content type (e.g. `image/png`), into the content types they're stored as
+///
+/// # Errors
+///
+/// Returns an error if a format isn't known to be an image.
+pub fn parse_formats(formats: &str) -> Result<Vec<String>> {
+    let mut content_types = Vec::new();
+    for format in formats.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        let format = format.to_lowercase();
+        let content_type = if format.contains('/') {
+            Some(format.clone())
+        } else {
+            mime_guess::from_ext(&format)
+                .first()
+                .map(|mime| mime.to_string())
+        }
+        .filter(|content_type| content_type.starts_with("image/"))
+        .ok_or_else(|| anyhow!("Unknown image format: {format}"))?;
+        if !content_types.contains(&content_type) {
+            content_types.push(content_type);
+        }
+    }
+    Ok(content_types)
+}
+
+/// The most characters a caption given with `?caption=` may have
+pub const MAX_CAPTION_LENGTH: usize = 200;
+
+/// Query parameters transforming the image served by `/random`, `/sequential`, and `/image/{id}`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TransformQuery {
+    /// `?caption=`, text drawn across the bottom of the image, at most [`MAX_CAPTION_LENGTH`] characters.
+    /// An empty caption leaves out the caption of the image's source
+    pub caption: Option<String>,
+    /// `?rotate=90|180|270`, how far the image is rotated clockwise, in degrees
+    pub rotate: Option<Rotation>,
+    /// `?flip=h|v`, whether the image is mirrored horizontally or vertically, after it's rotated
+    pub flip: Option<Flip>,
+    /// `?filter=grayscale|blur|blur:{sigma}|sepia`, a filter applied to the colors of the image
+    pub filter: Option<Filter>,
+    /// `?quality=`, the quality from 1 to 100 a JPEG image is re-encoded at.
+    /// Unless it's given, `transforms.quality` is used
+    pub quality: Option<u8>,
+    /// The pre-encoded format a JPEG or PNG image is served in instead, if it has a variant in it.
+    /// Negotiated from the `Accept` header of the request, rather than parsed from the query
+    pub format: Option<VariantFormat>,
+}
+
+impl TransformQuery {
+    /// Parse the query string of a request, ignoring unknown parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a known parameter has an invalid value, or the image would be transformed
+    /// by a server built without the `transforms` feature.
+    pub fn parse(query: Option<&str>) -> Result<Self> {
+        let mut parsed = Self::default();
+        for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            if key == "caption" {
+                if value.chars().count() > MAX_CAPTION_LENGTH {
+                    return Err(anyhow!(
+                        "Invalid caption, it must be at most {MAX_CAPTION_LENGTH} characters"
+                    ));
+                }
+                parsed.caption = Some(value.into_owned());
+            } else if key == "rotate" {
+                parsed.rotate = Some(value.parse()?);
+            } else if key == "flip" {
+                parsed.flip = Some(value.parse()?);
+            } else if key == "filter" {
+                parsed.filter = Some(value.parse()?);
+            } else if key == "quality" {
+                parsed.quality = Some(crate::config::parse_quality(&value)?);
+            }
+        }
+        if !cfg!(feature = "transforms") && !parsed.is_empty() {
+            return Err(anyhow!(
+                "Transforming images requires the server to be built with the `transforms` feature"
+            ));
+        }
+        Ok(parsed)
+    }
+
+    /// Whether the image is served as it is, or in one of its pre-encoded formats
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.caption.as_deref().is_none_or(str::is_empty)
+            && self.rotate.is_none()
+            && self.flip.is_none()
+            && self.filter.is_none()
+            && self.quality.is_none()
+    }
+
+    /// The query string (without the leading `?`) of the same transforms
+    #[must_use]
+    pub fn query_string(&self) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        if let Some(caption) = &self.caption {
+            query.append_pair("caption", caption);
+        }
+        if let Some(rotate) = self.rotate {
+            query.append_pair("rotate", rotate.as_str());
+        }
+        if let Some(flip) = self.flip {
+            query.append_pair("flip", flip.as_str());
+        }
+        if let Some(filter) = self.filter {
+            query.append_pair("filter", &filter.to_string());
+        }
+        if let Some(quality) = self.quality {
+            query.append_pair("quality", &quality.to_string());
+        }
+        query.finish()
+    }
+}
+
+/// The number of images listed per page by `/list`, unless `per_page` is given
+pub const DEFAULT_PER_PAGE: usize = 100;
+/// The most images `/list` lists per page
+pub const MAX_PER_PAGE: usize = 1000;
+
+/// How `/list` sorts the cached images
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ListSort {
+    /// In the order they were added to the cache
+    #[default]
+    Added,
+    /// By their path or URL
+    Name,
+    /// By their size, smallest first
+    Size,
+}
+
+impl ListSort {
+    /// The value of the `sort` parameter selecting this order
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Name => "name",
+            Self::Size => "size",
+        }
+    }
+}
+
+impl FromStr for ListSort {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "added" => Ok(Self::Added),
+            "name" => Ok(Self::Name),
+            "size" => Ok(Self::Size),
+            _ => Err(anyhow!("Unknown sort: {s}")),
+        }
+    }
+}
+
+/// Query parameters accepted by `/list`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListQuery {
+    /// `?page=`, counting from 1
+    pub page: usize,
+    /// `?per_page=`, at most [`MAX_PER_PAGE`]
+    pub per_page: usize,
+    /// `?sort=added|name|size`
+    pub sort: ListSort,
+    /// `?tag=`, to only list the images of sources with the given tag
+    pub tag: Option<String>,
+}
+
+impl Default for ListQuery {
+    fn default() -> Self {
+        Self {
+            page: 1,
+            per_page: DEFAULT_PER_PAGE,
+            sort: ListSort::default(),
+            tag: None,
+        }
+    }
+}
+
+impl ListQuery {
+    /// Parse the query string of a request, ignoring unknown parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a known parameter has an invalid value.
+    pub fn parse(query: Option<&str>) -> Result<Self> {
+        let mut parsed = Self::default();
+        for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            match key.as_ref() {
+                "page" => {
+                    parsed.page = value
+                        .parse()
+                        .ok()
+                        .filter(|page| *page > 0)
+                        .ok_or_else(|| anyhow!("Invalid page: {value}"))?;
+                }
+                "per_page" => {
+                    parsed.per_page = value
+                        .parse()
+                        .ok()
+                        .filter(|per_page| (1..=MAX_PER_PAGE).contains(per_page))
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "Invalid per_page, it must be between 1 and {MAX_PER_PAGE}: {value}"
+                            )
+                        })?;
+                }
+                "sort" => parsed.sort = value.parse()?,
+                "tag" => parsed.tag = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The query string (without the leading `?`) of the given page of the same listing
+    #[must_use]
+    pub fn page_query(&self, page: usize) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query
+            .append_pair("page", &page.to_string())
+            .append_pair("per_page", &self.per_page.to_string())
+            .append_pair("sort", self.sort.as_str());
+        if let Some(tag) = &self.tag {
+            query.append_pair("tag", tag);
+        }
+        query.finish()
+    }
+}
+
+/// Query parameters accepted by `/proxy`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyQuery {
+    /// `?url=`, the URL of the image to proxy
+    pub url: url::Url,
+}
+
+impl ProxyQuery {
+    /// Parse the query string of a request, ignoring unknown parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `url` parameter is missing, or isn't a valid URL.
+    pub fn parse(query: Option<&str>) -> Result<Self> {
+        let url = url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+            .find(|(key, _)| key == "url")
+            .ok_or_else(|| anyhow!("Missing url"))?
+            .1;
+        Ok(Self {
+            url: url.parse().map_err(|e| anyhow!("Invalid url {url}: {e}"))?,
+        })
+    }
+}
+
+/// Query parameters accepted by `/placeholder/{width}x{height}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaceholderQuery {
+    /// `?color=`, the hex color of the background, or where its gradient starts
+    pub color: Color,
+    /// `?to=`, the hex color the gradient of the background ends at, if it is one
+    pub to: Option<Color>,
+    /// `?direction=horizontal|vertical`, which way the gradient runs
+    pub direction: GradientDirection,
+    /// `?text=true|false`, whether the dimensions are drawn at the middle
+    pub text: bool,
+}
+
+impl Default for PlaceholderQuery {
+    fn default() -> Self {
+        Self {
+            color: Color::GRAY,
+            to: None,
+            direction: GradientDirection::default(),
+            text: true,
+        }
+    }
+}
+
+impl PlaceholderQuery {
+    /// Parse the query string of a request, ignoring unknown parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a known parameter has an invalid value.
+    pub fn parse(query: Option<&str>) -> Result<Self> {
+        let mut parsed = Self::default();
+        for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            match key.as_ref() {
+                "color" => parsed.color = value.parse()?,
+                "to" => parsed.to = Some(value.parse()?),
+                "direction" => parsed.direction = value.parse()?,
+                "text" => {
+                    parsed.text = value
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid text, expected true or false: {value}"))?;
+                }
+                _ => {}
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The query string (without the leading `?`) of the same placeholder, with every parameter in order
+    #[must_use]
+    pub fn query_string(&self) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query.append_pair("color", &self.color.to_string());
+        if let Some(to) = self.to {
+            query.append_pair("to", &to.to_string());
+        }
+        query
+            .append_pair("direction", self.direction.as_str())
+            .append_pair("text", &self.text.to_string());
+        query.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::none(None, RandomOrder::Uniform)]
+    #[case::empty(Some(""), RandomOrder::Uniform)]
+    #[case::uniform(Some("order=uniform"), RandomOrder::Uniform)]
+    #[case::least_served(Some("order=least_served"), RandomOrder::LeastServed)]
+    #[case::unknown_param(Some("foo=bar&order=least_served"), RandomOrder::LeastServed)]
+    fn test_random_query_parse(#[case] query: Option<&str>, #[case] expected: RandomOrder) {
+        assert_eq!(RandomQuery::parse(query).unwrap().order, expected);
+    }
+
+    #[test]
+    fn test_transform_query_parse() {
+        assert!(TransformQuery::parse(None).unwrap().is_empty());
+        // an empty caption leaves out the caption of the source, without transforming the image
+        let empty = TransformQuery::parse(Some("caption=")).unwrap();
+        assert_eq!(empty.caption.as_deref(), Some(""));
+        assert!(empty.is_empty());
+
+        let longest = "a".repeat(MAX_CAPTION_LENGTH);
+        assert!(TransformQuery::parse(Some(&format!("caption={longest}a"))).is_err());
+        let parsed = TransformQuery::parse(Some(&format!("caption={longest}")));
+        if cfg!(feature = "transforms") {
+            assert_eq!(parsed.unwrap().caption, Some(longest));
+        } else {
+            assert!(parsed.is_err());
+        }
+    }
+
+    #[test]
+    fn test_transform_query_parse_values() {
+        for query in [
+            "rotate=45",
+            "rotate=-90",
+            "flip=d",
+            "flip=",
+            "filter=invert",
+        ] {
+            assert!(TransformQuery::parse(Some(query)).is_err(), "{query}");
+        }
+        let parsed = TransformQuery::parse(Some("rotate=270&flip=H&quality=70"));
+        if cfg!(feature = "transforms") {
+            let parsed = parsed.unwrap();
+            assert_eq!(parsed.rotate, Some(Rotation::ThreeQuarters));
+            assert_eq!(parsed.flip, Some(Flip::Horizontal));
+            assert_eq!(parsed.quality, Some(70));
+            assert!(!parsed.is_empty());
+        } else {
+            assert!(parsed.is_err());
+        }
+    }
+
+    #[cfg(feature = "transforms")]
+    #[test]
+    fn test_transform_query_string() {
+        let query =
+            TransformQuery::parse(Some("order=least_served&caption=Hello%2C+world%21")).unwrap();
+        assert_eq!(query.caption.as_deref(), Some("Hello, world!"));
+        assert_eq!(query.query_string(), "caption=Hello%2C+world%21");
+        let query = TransformQuery::parse(Some(
+            "quality=1&filter=blur&flip=vertical&rotate=90&caption=Hi",
+        ))
+        .unwrap();
+        assert_eq!(
+            query.query_string(),
+            "caption=Hi&rotate=90&flip=v&filter=blur%3A5&quality=1"
+        );
+        assert_eq!(
+            RandomQuery::parse(Some(&query.query_string()))
+                .unwrap()
+                .transforms,
+            query
+        );
+    }
+
+    #[test]
+    fn test_random_query_parse_invalid() {
+        assert!(RandomQuery::parse(Some("order=most_served")).is_err());
+    }
+
+    #[rstest]
+    #[case::unfiltered("", Some((1, 1)), true)]
+    #[case::unfiltered_unknown("", None, true)]
+    #[case::landscape("orientation=landscape", Some((40, 20)), true)]
+    #[case::not_landscape("orientation=Landscape", Some((20, 40)), false)]
+    #[case::portrait("orientation=portrait", Some((20, 40)), true)]
+    #[case::square("orientation=square", Some((20, 20)), true)]
+    #[case::not_square("orientation=square", Some((20, 21)), false)]
+    #[case::unknown("orientation=square", None, false)]
+    #[case::min_width("min_width=40", Some((40, 20)), true)]
+    #[case::too_narrow("min_width=41", Some((40, 20)), false)]
+    #[case::too_short("min_height=21&orientation=landscape", Some((40, 20)), false)]
+    #[case::all("orientation=landscape&min_width=1920&min_height=1080", Some((2560, 1440)), true)]
+    fn test_random_query_fits(
+        #[case] query: &str,
+        #[case] dimensions: Option<(usize, usize)>,
+        #[case] expected: bool,
+    ) {
+        assert_eq!(
+            RandomQuery::parse(Some(query)).unwrap().fits(dimensions),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::none("", &[])]
+    #[case::extensions("format=png,webp", &["image/png", "image/webp"])]
+    #[case::jpeg("format=jpg,JPEG,%20png", &["image/jpeg", "image/png"])]
+    #[case::content_type("format=image/avif", &["image/avif"])]
+    #[case::empty("format=", &[])]
+    fn test_query_parse_formats(#[case] query: &str, #[case] expected: &[&str]) {
+        assert_eq!(RandomQuery::parse(Some(query)).unwrap().formats, expected);
+        assert_eq!(
+            SequentialQuery::parse(Some(query)).unwrap().formats,
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::unknown("format=png,doc")]
+    #[case::not_an_image("format=txt")]
+    #[case::not_an_image_type("format=text/plain")]
+    fn test_query_parse_formats_invalid(#[case] query: &str) {
+        assert!(RandomQuery::parse(Some(query)).is_err());
+        assert!(SequentialQuery::parse(Some(query)).is_err());
+    }
+
+    #[rstest]
+    #[case::orientation("orientation=wide")]
+    #[case::min_width("min_width=-1")]
+    #[case::min_height("min_height=tall")]
+    fn test_random_query_parse_dimensions_invalid(#[case] query: &str) {
+        assert!(RandomQuery::parse(Some(query)).is_err());
+    }
+
+    #[rstest]
+    #[case::none(None, Ok(None))]
+    #[case::seconds(Some("sticky=300"), Ok(Some(300)))]
+    #[case::disabled(Some("sticky=0"), Ok(Some(0)))]
+    #[case::longest(Some("sticky=604800"), Ok(Some(MAX_STICKY_SECONDS)))]
+    #[case::too_long(Some("sticky=604801"), Err(()))]
+    #[case::negative(Some("sticky=-1"), Err(()))]
+    #[case::unit(Some("sticky=5m"), Err(()))]
+    fn test_random_query_parse_sticky(
+        #[case] query: Option<&str>,
+        #[case] expected: Result<Option<u64>, ()>,
+    ) {
+        assert_eq!(
+            RandomQuery::parse(query)
+                .map(|query| query.sticky)
+                .map_err(|_| ()),
+            expected.map(|seconds| seconds.map(Duration::from_secs))
+        );
+    }
+
+    #[test]
+    fn test_list_query_parse() {
+        assert_eq!(ListQuery::parse(None).unwrap(), ListQuery::default());
+        assert_eq!(
+            ListQuery::parse(Some(
+                "page=3&per_page=20&sort=SIZE&tag=cats%20%26%20dogs&foo=bar"
+            ))
+            .unwrap(),
+            ListQuery {
+                page: 3,
+                per_page: 20,
+                sort: ListSort::Size,
+                tag: Some("cats & dogs".to_string()),
+            }
+        );
+    }
+
+    #[rstest]
+    #[case::zero_page("page=0")]
+    #[case::negative_page("page=-1")]
+    #[case::zero_per_page("per_page=0")]
+    #[case::too_many_per_page("per_page=1001")]
+    #[case::unknown_sort("sort=random")]
+    fn test_list_query_parse_invalid(#[case] query: &str) {
+        assert!(ListQuery::parse(Some(query)).is_err());
+    }
+
+    #[test]
+    fn test_list_query_page_query() {
+        let query = ListQuery::parse(Some("sort=name&tag=cats%20%26%20dogs")).unwrap();
+        assert_eq!(
+            query.page_query(2),
+            "page=2&per_page=100&sort=name&tag=cats+%26+dogs"
+        );
+        // parsing the query string of a page gives back the same listing
+        assert_eq!(
+            ListQuery::parse(Some(&query.page_query(2))).unwrap(),
+            ListQuery { page: 2, ..query }
+        );
+    }
+
+    #[test]
+    fn test_proxy_query_parse() {
+        assert_eq!(
+            ProxyQuery::parse(Some(
+                "foo=bar&url=https%3A%2F%2Fexample.com%2Fa.jpg%3Fw%3D100"
+            ))
+            .unwrap()
+            .url
+            .as_str(),
+            "https://example.com/a.jpg?w=100"
+        );
+        assert!(ProxyQuery::parse(None).is_err());
+        assert!(ProxyQuery::parse(Some("url=not%20a%20url")).is_err());
+    }
+
+    #[rstest]
+    #[case::none(None, PlaceholderQuery::default())]
+    #[case::solid(Some("color=%23c00&text=false"), PlaceholderQuery {
+        color: Color([0xcc, 0, 0]),
+        text: false,
+        ..PlaceholderQuery::default()
+    })]
+    #[case::gradient(Some("color=000&to=ffffff&direction=Vertical&foo=bar"), PlaceholderQuery {
+        color: Color::BLACK,
+        to: Some(Color::WHITE),
+        direction: GradientDirection::Vertical,
+        ..PlaceholderQuery::default()
+    })]
+    fn test_placeholder_query_parse(
+        #[case] query: Option<&str>,
+        #[case] expected: PlaceholderQuery,
+    ) {
+        let parsed = PlaceholderQuery::parse(query).unwrap();
+        assert_eq!(parsed, expected);
+        // parsing the query string of a placeholder gives back the same placeholder
+        assert_eq!(
+            PlaceholderQuery::parse(Some(&parsed.query_string())).unwrap(),
+            parsed
+        );
+    }
+
+    #[rstest]
+    #[case::color("color=red")]
+    #[case::to("to=12345")]
+    #[case::direction("direction=diagonal")]
+    #[case::text("text=yes")]
+    fn test_placeholder_query_parse_invalid(#[case] query: &str) {
+        assert!(PlaceholderQuery::parse(Some(query)).is_err());
+    }
+}
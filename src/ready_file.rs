@@ -0,0 +1,132 @@
+//! The file created once the server is ready, set with `server.ready_file`, for the health checks of
+//! container orchestrators, which can test for a file without an HTTP client in the image.
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+
+use crate::{cache::CacheBackend, state::ServerState};
+
+/// How often the server is checked for readiness, until it's ready
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The ready file of a running server, removed when dropped
+#[derive(Debug)]
+pub struct ReadyFile {
+    path: PathBuf,
+}
+
+impl ReadyFile {
+    /// Remove the ready file left behind by a server that didn't shut down gracefully,
+    /// so it isn't mistaken for a sign that this one is ready
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists, but can't be removed.
+    pub fn clear(path: &Path) -> Result<()> {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(anyhow!(
+                "Failed to remove the ready file {}: {e}",
+                path.display()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Wait until the server is ready, as reported by `/readyz`, then create the file at the given path
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be created.
+    pub async fn create_when_ready<C: CacheBackend>(
+        state: Arc<ServerState<C>>,
+        path: PathBuf,
+    ) -> Result<Self> {
+        while !state.is_ready() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        tokio::fs::write(&path, b"")
+            .await
+            .map_err(|e| anyhow!("Failed to create the ready file {}: {e}", path.display()))?;
+        tracing::info!("Created the ready file {}", path.display());
+        Ok(Self { path })
+    }
+
+    /// The path of the ready file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ReadyFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!(
+                "Failed to remove the ready file {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::{CacheKey, CacheValue, Validators},
+        config::Config,
+    };
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test]
+    async fn test_create_when_ready() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ready");
+        let state = Arc::new(ServerState::with_config(&Config::default()));
+
+        let creating = tokio::spawn(ReadyFile::create_when_ready(state.clone(), path.clone()));
+        tokio::time::sleep(POLL_INTERVAL * 2).await;
+        // populated, but without images
+        state.populated.store(true, Ordering::Release);
+        tokio::time::sleep(POLL_INTERVAL * 2).await;
+        assert!(!path.exists());
+
+        state
+            .cache
+            .set(
+                CacheKey::ImagePath(PathBuf::from("a.jpg")),
+                CacheValue {
+                    data: vec![1, 2, 3],
+                    content_type: "image/jpeg".to_string(),
+                    validators: Validators::default(),
+                },
+            )
+            .unwrap();
+        let ready_file = creating.await.unwrap().unwrap();
+        assert_eq!(ready_file.path(), path);
+        assert!(path.exists());
+
+        drop(ready_file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ready");
+        ReadyFile::clear(&path).unwrap();
+
+        std::fs::write(&path, b"").unwrap();
+        ReadyFile::clear(&path).unwrap();
+        assert!(!path.exists());
+
+        // a directory can't be removed as a file
+        assert!(ReadyFile::clear(dir.path()).is_err());
+    }
+}
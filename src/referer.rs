@@ -0,0 +1,87 @@
+//! Hotlink protection, only serving images to pages on the configured domains.
+
+use hyper::{Request, header};
+use url::Url;
+
+/// Whether a request may be served an image, given the domains images may be embedded on
+///
+/// The `Origin` header is checked if it's sent, and the `Referer` header otherwise. A request is
+/// allowed if the host of that URL is one of the domains, or a subdomain of one, or if it sends
+/// neither header (e.g. when an image is visited directly, or the referrer policy hides it).
+/// Every request is allowed if no domains are configured.
+#[must_use]
+pub fn is_allowed<B>(req: &Request<B>, allowed_referers: &[String]) -> bool {
+    if allowed_referers.is_empty() {
+        return true;
+    }
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    let Some(referer) = header(header::ORIGIN).or_else(|| header(header::REFERER)) else {
+        return true;
+    };
+    let Some(host) = Url::parse(referer)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+    else {
+        return false;
+    };
+    is_on_domains(&host, allowed_referers)
+}
+
+/// Whether a lowercase host is one of the given domains, or a subdomain of one
+///
+/// Domains may be written as `*.example.com`, which is the same as `example.com`.
+#[must_use]
+pub fn is_on_domains(host: &str, domains: &[String]) -> bool {
+    domains.iter().any(|domain| {
+        let domain = domain.trim_start_matches("*.").to_ascii_lowercase();
+        host == domain
+            || host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|subdomain| subdomain.ends_with('.'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::no_domains(&[], Some("https://evil.example.net/page"), None, true)]
+    #[case::no_headers(&["example.com"], None, None, true)]
+    #[case::same_domain(&["example.com"], Some("https://example.com/page"), None, true)]
+    #[case::subdomain(&["example.com"], Some("https://blog.example.com/page"), None, true)]
+    #[case::wildcard(&["*.example.com"], Some("https://blog.example.com/"), None, true)]
+    #[case::case_insensitive(&["Example.com"], Some("https://EXAMPLE.com/"), None, true)]
+    #[case::other_domain(&["example.com"], Some("https://evil.example.net/page"), None, false)]
+    #[case::suffix_only(&["example.com"], Some("https://notexample.com/page"), None, false)]
+    #[case::malformed(&["example.com"], Some("not a url"), None, false)]
+    #[case::origin(&["example.com"], None, Some("https://example.com"), true)]
+    #[case::origin_first(&["example.com"], Some("https://example.com/"), Some("https://evil.example.net"), false)]
+    #[case::null_origin(&["example.com"], None, Some("null"), false)]
+    fn test_is_allowed(
+        #[case] allowed_referers: &[&str],
+        #[case] referer: Option<&str>,
+        #[case] origin: Option<&str>,
+        #[case] expected: bool,
+    ) {
+        let mut req = Request::builder();
+        if let Some(referer) = referer {
+            req = req.header(header::REFERER, referer);
+        }
+        if let Some(origin) = origin {
+            req = req.header(header::ORIGIN, origin);
+        }
+        let req = req.body(()).unwrap();
+        let allowed_referers: Vec<String> =
+            allowed_referers.iter().map(ToString::to_string).collect();
+
+        assert_eq!(is_allowed(&req, &allowed_referers), expected);
+    }
+}
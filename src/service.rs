@@ -0,0 +1,178 @@
+//! Running the server as a Windows service, registered with `install-service` and started by the service
+//! control manager with `--service`.
+//!
+//! Stop and shutdown requests of the service control manager are sent through the termination broadcast
+//! as [`Interrupted::ServiceStop`], so the server shuts down as gracefully as when it's interrupted.
+
+use std::{
+    ffi::OsString,
+    future::Future,
+    path::Path,
+    pin::Pin,
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use tokio::sync::broadcast;
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult},
+    service_dispatcher,
+    service_manager::{ServiceManager, ServiceManagerAccess},
+};
+
+use crate::{
+    StartupError,
+    termination::{Interrupted, Terminator, create_termination},
+};
+
+/// The name the service is registered under
+pub const SERVICE_NAME: &str = "random-image-server";
+
+/// The name the service is shown under in the services console
+const DISPLAY_NAME: &str = "Random Image Server";
+
+/// What the service runs once the service control manager starts it, given the termination broadcast
+pub type ServiceMain = Box<
+    dyn FnOnce(
+            Terminator,
+            broadcast::Receiver<Interrupted>,
+        ) -> Pin<Box<dyn Future<Output = Result<()>> + Send>>
+        + Send,
+>;
+
+/// What the service runs, taken by `service_main` when the dispatcher calls it
+static SERVICE_MAIN: Mutex<Option<ServiceMain>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Register the service with the service control manager, to be started at boot with the given config and env files
+///
+/// # Errors
+///
+/// Returns an error if the service can't be registered, e.g. without administrator rights, or it already is.
+pub fn install(config: Option<&Path>, env_file: Option<&Path>) -> Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+    // the service starts in the system directory, so relative paths wouldn't resolve
+    let mut launch_arguments = vec![OsString::from("--service")];
+    for (flag, path) in [("--config", config), ("--env-file", env_file)] {
+        if let Some(path) = path {
+            launch_arguments.push(OsString::from(flag));
+            launch_arguments.push(std::path::absolute(path)?.into_os_string());
+        }
+    }
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from(DISPLAY_NAME),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments,
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .map_err(|e| anyhow!("Failed to register the {SERVICE_NAME} service: {e}"))?;
+    service.set_description(env!("CARGO_PKG_DESCRIPTION"))?;
+    Ok(())
+}
+
+/// Stop the service if it's running, and remove it from the service control manager
+///
+/// # Errors
+///
+/// Returns an error if the service isn't registered, or can't be stopped or removed.
+pub fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .map_err(|e| anyhow!("Failed to open the {SERVICE_NAME} service: {e}"))?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    service.delete()?;
+    Ok(())
+}
+
+/// Run as the service, until the service control manager stops it
+///
+/// This blocks while the service runs, and must be called from a process started by the service control manager.
+///
+/// # Errors
+///
+/// Returns an error if the process wasn't started by the service control manager.
+pub fn run(main: ServiceMain) -> Result<()> {
+    *SERVICE_MAIN.lock().unwrap_or_else(PoisonError::into_inner) = Some(main);
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .map_err(|e| anyhow!("Failed to start the {SERVICE_NAME} service: {e}"))
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let Some(main) = SERVICE_MAIN
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+    else {
+        return;
+    };
+    if let Err(e) = run_service(main) {
+        tracing::error!("The {SERVICE_NAME} service failed: {e}");
+    }
+}
+
+fn run_service(main: ServiceMain) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let (terminator, interrupt_rx) = create_termination();
+        let mut handler_terminator = terminator.clone();
+        let status =
+            service_control_handler::register(SERVICE_NAME, move |control| match control {
+                ServiceControl::Stop | ServiceControl::Shutdown => {
+                    handler_terminator.terminate(Interrupted::ServiceStop).ok();
+                    ServiceControlHandlerResult::NoError
+                }
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                _ => ServiceControlHandlerResult::NotImplemented,
+            })?;
+        let set_state = |state: ServiceState, exit_code: ServiceExitCode| {
+            status.set_service_status(ServiceStatus {
+                service_type: ServiceType::OWN_PROCESS,
+                current_state: state,
+                controls_accepted: if state == ServiceState::Running {
+                    ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+                } else {
+                    ServiceControlAccept::empty()
+                },
+                exit_code,
+                checkpoint: 0,
+                wait_hint: Duration::default(),
+                process_id: None,
+            })
+        };
+
+        set_state(ServiceState::Running, ServiceExitCode::NO_ERROR)?;
+        let result = main(terminator, interrupt_rx).await;
+        let exit_code = match &result {
+            Ok(()) => ServiceExitCode::NO_ERROR,
+            Err(e) => ServiceExitCode::ServiceSpecific(StartupError::exit_code_of(e).into()),
+        };
+        set_state(ServiceState::Stopped, exit_code)?;
+        result
+    })
+}
@@ -0,0 +1,233 @@
+//! Signed, expiring links to specific images, of the form `/image/{id}?expires=...&sig=...`
+//!
+//! The signature is an HMAC-SHA256 of the image id and expiry time, keyed with `signing.secret`,
+//! so links can be handed out without exposing the secret, and can't be altered to reach other images.
+
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::Config;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Why a request for an image wasn't correctly signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureError {
+    /// Signed links are required, but no `signing.secret` is configured
+    NoSecret,
+    /// The `expires` or `sig` parameter is missing
+    Missing,
+    /// The `expires` or `sig` parameter can't be parsed
+    Malformed,
+    /// The link expired
+    Expired,
+    /// The signature doesn't match the image and expiry time
+    Invalid,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NoSecret => "signed links are required, but no signing secret is configured",
+            Self::Missing => "the link is not signed",
+            Self::Malformed => "the signature or expiry time of the link is malformed",
+            Self::Expired => "the link expired",
+            Self::Invalid => "the signature of the link is invalid",
+        })
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+fn mac(secret: &str, id: &str, expires: u64) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(id.as_bytes());
+    mac.update(b"\n");
+    mac.update(expires.to_string().as_bytes());
+    mac
+}
+
+/// The query string (without the leading `?`) of a link to the image with the given id,
+/// valid until `expires`
+#[must_use]
+pub fn sign(secret: &str, id: &str, expires: SystemTime) -> String {
+    let expires = expires
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let signature = URL_SAFE_NO_PAD.encode(mac(secret, id, expires).finalize().into_bytes());
+    format!("expires={expires}&sig={signature}")
+}
+
+/// The query string of a link to the image with the given id, valid for `ttl` from now
+#[must_use]
+pub fn sign_for(secret: &str, id: &str, ttl: Duration) -> String {
+    sign(secret, id, SystemTime::now() + ttl)
+}
+
+/// A link to the image with the given id, valid for `ttl` from now, signed with the configured secret
+///
+/// The link is absolute if `server.public_url` is configured, and otherwise starts with `server.base_path`.
+///
+/// # Errors
+///
+/// Returns [`SignatureError::NoSecret`] if no `signing.secret` is configured.
+pub fn signed_link(config: &Config, id: &str, ttl: Duration) -> Result<String, SignatureError> {
+    let secret = config
+        .signing
+        .secret
+        .as_deref()
+        .ok_or(SignatureError::NoSecret)?;
+    let origin = config
+        .server
+        .public_url
+        .as_ref()
+        .map_or("", |url| url.as_str().trim_end_matches('/'));
+    Ok(format!(
+        "{origin}{}/image/{id}?{}",
+        config.server.base_path,
+        sign_for(secret, id, ttl)
+    ))
+}
+
+/// Check that the query string of a request for the image with the given id is correctly signed,
+/// and not expired at `now`
+///
+/// # Errors
+///
+/// Returns why the request isn't correctly signed.
+pub fn verify(
+    secret: &str,
+    id: &str,
+    query: Option<&str>,
+    now: SystemTime,
+) -> Result<(), SignatureError> {
+    let (mut expires, mut signature) = (None, None);
+    for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+        match key.as_ref() {
+            "expires" => expires = Some(value.into_owned()),
+            "sig" => signature = Some(value.into_owned()),
+            _ => {}
+        }
+    }
+    let (Some(expires), Some(signature)) = (expires, signature) else {
+        return Err(SignatureError::Missing);
+    };
+    let expires: u64 = expires.parse().map_err(|_| SignatureError::Malformed)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| SignatureError::Malformed)?;
+
+    // check the signature first, so that tampered links aren't reported as merely expired
+    mac(secret, id, expires)
+        .verify_slice(&signature)
+        .map_err(|_| SignatureError::Invalid)?;
+    if UNIX_EPOCH + Duration::from_secs(expires) <= now {
+        return Err(SignatureError::Expired);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const SECRET: &str = "secret";
+
+    #[test]
+    fn test_sign_and_verify() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let query = sign(SECRET, "abc", now + Duration::from_secs(60));
+        assert!(query.starts_with("expires=1060&sig="));
+        assert_eq!(verify(SECRET, "abc", Some(&query), now), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_ignores_other_parameters() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let query = format!(
+            "w=100&{}",
+            sign(SECRET, "abc", now + Duration::from_secs(60))
+        );
+        assert_eq!(verify(SECRET, "abc", Some(&query), now), Ok(()));
+    }
+
+    #[test]
+    fn test_signed_link() {
+        let mut config = Config::default();
+        assert_eq!(
+            signed_link(&config, "abc", Duration::from_secs(60)),
+            Err(SignatureError::NoSecret)
+        );
+
+        config.signing.secret = Some(SECRET.to_string());
+        config.server.base_path = "/images".to_string();
+        config.server.public_url = Some("https://example.com/".parse().unwrap());
+        let link = signed_link(&config, "abc", Duration::from_secs(60)).unwrap();
+        let (path, query) = link.split_once('?').unwrap();
+        assert_eq!(path, "https://example.com/images/image/abc");
+        assert_eq!(
+            verify(SECRET, "abc", Some(query), SystemTime::now()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_verify_expired() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let query = sign(SECRET, "abc", now);
+        assert_eq!(
+            verify(SECRET, "abc", Some(&query), now),
+            Err(SignatureError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_verify_invalid() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let query = sign(SECRET, "abc", now + Duration::from_secs(60));
+        // another image, another secret, or a later expiry time
+        assert_eq!(
+            verify(SECRET, "abd", Some(&query), now),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            verify("other", "abc", Some(&query), now),
+            Err(SignatureError::Invalid)
+        );
+        let extended = query.replace("expires=1060", "expires=9999");
+        assert_eq!(
+            verify(SECRET, "abc", Some(&extended), now),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_verify_missing_or_malformed() {
+        let now = UNIX_EPOCH;
+        assert_eq!(
+            verify(SECRET, "abc", None, now),
+            Err(SignatureError::Missing)
+        );
+        assert_eq!(
+            verify(SECRET, "abc", Some("expires=10"), now),
+            Err(SignatureError::Missing)
+        );
+        assert_eq!(
+            verify(SECRET, "abc", Some("expires=soon&sig=abc"), now),
+            Err(SignatureError::Malformed)
+        );
+        assert_eq!(
+            verify(SECRET, "abc", Some("expires=10&sig=!!"), now),
+            Err(SignatureError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Snapshots of a cache, so that a warmed cache can be shipped to new instances instead of re-fetching every URL.
+//!
+//! A snapshot is a tar archive holding, for each image, a `{n}.json` entry with its key and metadata,
+//! followed by a `{n}` entry with its bytes.
+
+use std::{fs::File, io::Read, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache::{CacheBackend, CacheKey, CacheValue, Validators};
+
+/// The metadata of an image in a snapshot
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotEntry {
+    key: CacheKey,
+    content_type: String,
+    #[serde(default)]
+    validators: Validators,
+}
+
+/// Write every image in the cache to a snapshot archive at the given path, returning how many were written
+///
+/// # Errors
+///
+/// Returns an error if the archive cannot be written.
+pub fn export<C: CacheBackend + ?Sized>(cache: &C, path: &Path) -> Result<usize, String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut archive = tar::Builder::new(file);
+    let mut count = 0;
+    for (key, metadata) in cache.iter() {
+        let Some(image) = cache.get(key.clone()) else {
+            tracing::warn!("Skipping image missing from the cache: {key}");
+            continue;
+        };
+        let entry = serde_json::to_vec(&SnapshotEntry {
+            key: key.clone(),
+            content_type: metadata.content_type,
+            validators: metadata.validators,
+        })
+        .map_err(|e| e.to_string())?;
+        append(&mut archive, &format!("{count}.json"), &entry)?;
+        append(&mut archive, &count.to_string(), &image.data)?;
+        count += 1;
+    }
+    archive
+        .into_inner()
+        .and_then(|file| file.sync_all())
+        .map_err(|e| e.to_string())?;
+    Ok(count)
+}
+
+/// Store every image of the snapshot archive at the given path in the cache, returning how many were stored
+///
+/// # Errors
+///
+/// Returns an error if the archive cannot be read or is malformed.
+/// Images that the cache refuses to store are skipped with a warning.
+pub fn import<C: CacheBackend + ?Sized>(cache: &C, path: &Path) -> Result<usize, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut archive = tar::Archive::new(file);
+    let mut entries = archive.entries().map_err(|e| e.to_string())?;
+    let mut count = 0;
+    while let Some(metadata) = entries.next() {
+        let entry: SnapshotEntry =
+            serde_json::from_slice(&read(metadata)?).map_err(|e| e.to_string())?;
+        let data = entries
+            .next()
+            .ok_or_else(|| format!("Snapshot is missing the image of {}", entry.key))
+            .and_then(read)?;
+        let image = CacheValue {
+            data,
+            content_type: entry.content_type,
+            validators: entry.validators,
+        };
+        match cache.set(entry.key.clone(), image) {
+            Ok(()) => count += 1,
+            Err(e) => tracing::warn!("Failed to import {} into the cache: {e}", entry.key),
+        }
+    }
+    Ok(count)
+}
+
+fn append(archive: &mut tar::Builder<File>, name: &str, data: &[u8]) -> Result<(), String> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    archive
+        .append_data(&mut header, name, data)
+        .map_err(|e| e.to_string())
+}
+
+fn read(entry: std::io::Result<tar::Entry<'_, File>>) -> Result<Vec<u8>, String> {
+    let mut entry = entry.map_err(|e| e.to_string())?;
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data).map_err(|e| e.to_string())?;
+    Ok(data)
+}
@@ -1,22 +1,363 @@
-use std::fmt::Debug;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt::Debug,
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError, RwLock,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::SystemTime,
+};
 
-use crate::{cache::CacheBackend, config::CacheBackendType};
+use arc_swap::{ArcSwap, ArcSwapOption};
+use rand::distr::weighted::WeightedIndex;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{
+    auth::VerifiedCredentials,
+    breaker::CircuitBreakers,
+    cache::{CacheBackend, CacheKey, CacheValue, FileSystemCache, InMemoryCache, TieredCache},
+    config::{
+        CacheBackendType, CacheConfig, Config, EmptyCachePolicy, ImageSource, SourceConfig,
+        TransformsConfig,
+    },
+    metrics::RequestMetrics,
+    preview::Preview,
+    stats::ServeCounters,
+    sticky::StickyImages,
+};
+
+/// The status of a configured image source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceStatus {
+    /// The source has not been loaded yet
+    Pending,
+    /// The source was loaded, and contributed at least one image
+    Loaded,
+    /// The source failed to load any images
+    Failed,
+    /// The source previously failed, and is being loaded again
+    Retrying,
+    /// The source is remote, and was skipped because the server is offline
+    Skipped,
+}
+
+/// Bookkeeping about a configured image source, reported by `/health`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct SourceHealth {
+    /// The source, as a URL or path
+    pub source: String,
+    pub status: SourceStatus,
+    /// The number of images loaded from the source
+    pub images: usize,
+    /// The most recent error encountered while loading the source
+    pub last_error: Option<String>,
+    /// When the source was last loaded, in seconds since the unix epoch
+    pub last_refresh: Option<u64>,
+}
+
+impl SourceHealth {
+    fn new(source: &ImageSource) -> Self {
+        Self {
+            source: source.to_string(),
+            status: SourceStatus::Pending,
+            images: 0,
+            last_error: None,
+            last_refresh: None,
+        }
+    }
+}
+
+/// What is known about an image that was served, passed to the `on_image_served` hooks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServedImage {
+    pub content_type: String,
+    /// The size of the image, in bytes
+    pub bytes: u64,
+    /// The hash of the image, sent as its `ETag`
+    pub hash: String,
+}
+
+/// The images `/random` may choose among, with what they're filtered and weighed by, so that choosing one doesn't
+/// look up what is known about every image
+///
+/// It's taken again whenever the images requests may be served change, e.g. when a reload is published.
+#[derive(Debug)]
+pub(crate) struct RandomSelection {
+    /// The version of the images it was taken of
+    version: u64,
+    /// The number of images in the cache when it was taken, so images set in the cache directly are noticed too
+    cached: usize,
+    pub images: Vec<SelectableImage>,
+    /// The distribution of the images by their weight, unless none of them weighs anything
+    pub weights: Option<WeightedIndex<u32>>,
+}
+
+/// An image `/random` may choose
+#[derive(Debug)]
+pub(crate) struct SelectableImage {
+    pub key: CacheKey,
+    pub content_type: String,
+    pub dimensions: Option<(usize, usize)>,
+    /// How likely the image is to be chosen, relative to other images
+    pub weight: u32,
+}
+
+/// A callback invoked with the key of each image served, and what is known about it
+pub type ImageServedHook = Arc<dyn Fn(&CacheKey, &ServedImage) + Send + Sync>;
+
+/// The callbacks invoked after an image is served, registered with `ImageServer::on_image_served`
+#[derive(Default)]
+pub struct ServeHooks(RwLock<Vec<ImageServedHook>>);
+
+impl Debug for ServeHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hooks = self.0.read().unwrap_or_else(PoisonError::into_inner).len();
+        f.debug_struct("ServeHooks").field("hooks", &hooks).finish()
+    }
+}
+
+impl ServeHooks {
+    /// Register a callback, invoked after the ones already registered
+    pub fn push(&self, hook: impl Fn(&CacheKey, &ServedImage) + Send + Sync + 'static) {
+        self.0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Arc::new(hook));
+    }
+
+    /// Invoke every registered callback, in the order they were registered
+    pub fn run(&self, key: &CacheKey, image: &ServedImage) {
+        for hook in self.0.read().unwrap_or_else(PoisonError::into_inner).iter() {
+            hook(key, image);
+        }
+    }
+}
+
+/// A reload of configured sources, started with [`ServerState::begin_reload`]
+///
+/// Reloads store images in the cache as they are loaded, but the images that weren't cached before are
+/// withheld from requests, and the images no longer found in their source are kept, until the reload is published:
+/// then both changes become visible at once, with a single swap.
+#[derive(Debug)]
+#[must_use = "the images stored by a reload are withheld until it is published"]
+pub struct Reload<'a, C> {
+    state: &'a ServerState<C>,
+    /// The images loaded by the reload
+    loaded: Vec<CacheKey>,
+    /// The images previously loaded from the reloaded sources that are no longer found in them
+    stale: Vec<CacheKey>,
+}
+
+impl<C: CacheBackend> Reload<'_, C> {
+    /// Record the images loaded from the configured source with the given index,
+    /// and so which images previously loaded from it are stale
+    ///
+    /// Sources that failed to load any images keep the images previously loaded from them.
+    pub fn record(&mut self, source_index: usize, keys: &[CacheKey]) {
+        if keys.is_empty() {
+            return;
+        }
+        self.stale
+            .extend(self.state.stale_images(source_index, keys));
+        self.loaded.extend_from_slice(keys);
+    }
+
+    /// Publish the reload: show the images it loaded and hide the stale ones with a single swap,
+    /// then remove the stale images from the cache
+    pub fn publish(mut self) {
+        let (loaded, stale) = (
+            std::mem::take(&mut self.loaded),
+            std::mem::take(&mut self.stale),
+        );
+        self.state.withheld.rcu(|withheld| {
+            let mut withheld = HashSet::clone(withheld);
+            for key in &loaded {
+                withheld.remove(key);
+            }
+            withheld.extend(stale.iter().cloned());
+            withheld
+        });
+        self.state.images_changed();
+        for key in &stale {
+            tracing::info!("Removing image no longer found in its source: {key}");
+            self.state.remove_image(key);
+        }
+        // once no other reload is running, nothing is withheld anymore, including images withheld before they were
+        // loaded that weren't stored
+        let last = self.state.reloads.load(Ordering::Acquire) == 1;
+        self.state.withheld.rcu(|withheld| {
+            if last {
+                return HashSet::new();
+            }
+            let mut withheld = HashSet::clone(withheld);
+            for key in &stale {
+                withheld.remove(key);
+            }
+            withheld
+        });
+    }
+}
+
+impl<C> Drop for Reload<'_, C> {
+    fn drop(&mut self) {
+        self.state.reloads.fetch_sub(1, Ordering::AcqRel);
+    }
+}
 
 /// State for the server
+///
+/// Every part of the state synchronizes itself, so it can be shared between requests and the tasks
+/// populating the cache without a lock around the whole state, and serving images never waits for
+/// images to be loaded.
+///
+/// The state is generic over its cache backend, so that embedders can supply their own backend and
+/// have the handlers monomorphized for it. By default, the backend is chosen at runtime from the configuration.
 #[derive(Debug)]
-pub struct ServerState {
+pub struct ServerState<C = Box<dyn CacheBackend>> {
     /// Cache backend for storing images
-    pub cache: Box<dyn CacheBackend>,
+    pub cache: C,
 
     /// What is the current index (for sequential image serving)
-    pub current_index: usize,
+    ///
+    /// Atomic, so that sequential requests only need to read the state.
+    pub current_index: AtomicUsize,
+
+    /// The position of each configured playlist, served at `/sequential/{name}`
+    pub playlist_indices: BTreeMap<String, AtomicUsize>,
+
+    /// The configuration the server was started with
+    pub config: Config,
+
+    /// Whether the cache has finished being populated from the configured sources
+    pub populated: AtomicBool,
+
+    /// The status of each configured source
+    sources: Mutex<Vec<SourceHealth>>,
+
+    /// How many times each image has been served
+    pub serve_counts: ServeCounters,
+
+    /// The images served most recently, the latest last, as many as `server.no_repeat_window`
+    recently_served: Mutex<VecDeque<CacheKey>>,
+
+    /// The images `/random` pinned to each client, with `?sticky=` or `server.sticky`
+    sticky_images: Mutex<StickyImages>,
+
+    /// The callbacks invoked after an image is served
+    pub serve_hooks: ServeHooks,
+
+    /// The basic authentication credentials verified before, so their password hash isn't checked again
+    pub verified_credentials: VerifiedCredentials,
+
+    /// The image served to requests from domains images may not be embedded on, if configured
+    pub hotlink_placeholder: Option<CacheValue>,
+
+    /// The image served by `/random` and `/sequential` while no images are available, if `server.on_empty = "placeholder"`
+    pub empty_placeholder: Option<CacheValue>,
+
+    /// The icon served at `/favicon.ico`
+    pub favicon: CacheValue,
+
+    /// Counters of the responses sent by the server
+    pub metrics: RequestMetrics,
+
+    /// The images withheld from requests: those stored by reloads that are still running, which weren't cached
+    /// before, and the stale images of finished reloads, until they are removed
+    ///
+    /// Swapped as a whole when a reload is published, so requests see the images of the reloaded sources
+    /// either as they were before the reload, or as they are after it, never half-reloaded or missing.
+    withheld: ArcSwap<HashSet<CacheKey>>,
+    /// The number of reloads that are still running
+    reloads: AtomicUsize,
+    /// Incremented whenever the images requests may be served change
+    images_version: AtomicU64,
+    /// The images `/random` chose among last, taken again when they change
+    random_selection: ArcSwapOption<RandomSelection>,
+
+    /// The index of the configured source each cached image was loaded from
+    image_sources: Mutex<HashMap<CacheKey, usize>>,
+    /// The perceptual hash of each image in the cache, if computed
+    image_hashes: Mutex<HashMap<CacheKey, u64>>,
+    /// The preview of each image in the cache, if computed
+    image_previews: Mutex<HashMap<CacheKey, Preview>>,
+    /// The size in bytes of each image stored from a configured source
+    image_sizes: Mutex<HashMap<CacheKey, u64>>,
+    /// The width and height in pixels of each image stored from a configured source, if they could be determined
+    image_dimensions: Mutex<HashMap<CacheKey, (usize, usize)>>,
+    /// The hash of the content of each image stored from a configured source, its public id
+    image_ids: Mutex<HashMap<CacheKey, String>>,
+    /// The keys of the images stored from a configured source by their public id, and by the id derived from their key,
+    /// so an image is found by its id without visiting every image
+    image_keys: Mutex<HashMap<String, Vec<CacheKey>>>,
+    /// When each image kept after failing to be refreshed from its source became stale, in seconds since the
+    /// Unix epoch
+    image_stale: Mutex<HashMap<CacheKey, u64>>,
+
+    /// The HTTP client shared by every fetch from a remote source
+    #[cfg(feature = "remote-sources")]
+    pub http_client: reqwest::Client,
+
+    /// The HTTP client images proxied at `/proxy` are fetched with, if the proxy is enabled
+    #[cfg(feature = "remote-sources")]
+    pub proxy_client: Option<reqwest::Client>,
+
+    /// The recent fetch failures of remote sources, used to back off from failing URLs and hosts
+    breakers: Mutex<CircuitBreakers>,
+
+    /// The state of each configured collection, served under `/{name}`, with a cache of its own
+    pub collections: BTreeMap<String, Arc<ServerState>>,
+
+    /// The cache images proxied at `/proxy` are stored in, if the proxy is enabled
+    pub proxy_cache: Option<Box<dyn CacheBackend>>,
+
+    /// The cache placeholders generated at `/placeholder/{width}x{height}` are stored in, if they are enabled
+    pub placeholder_cache: Option<Box<dyn CacheBackend>>,
+
+    /// The cache transformed variants of images, e.g. captioned ones, are stored in
+    pub variant_cache: Box<dyn CacheBackend>,
 }
 
 impl Default for ServerState {
     fn default() -> Self {
         Self {
             cache: Box::new(crate::cache::InMemoryCache::new()),
-            current_index: 0,
+            current_index: AtomicUsize::new(0),
+            playlist_indices: BTreeMap::new(),
+            config: Config::default(),
+            populated: AtomicBool::new(false),
+            sources: Mutex::default(),
+            serve_counts: ServeCounters::default(),
+            recently_served: Mutex::default(),
+            sticky_images: Mutex::default(),
+            serve_hooks: ServeHooks::default(),
+            verified_credentials: VerifiedCredentials::default(),
+            hotlink_placeholder: None,
+            empty_placeholder: None,
+            favicon: crate::static_files::default_favicon(),
+            metrics: RequestMetrics::default(),
+            withheld: ArcSwap::default(),
+            reloads: AtomicUsize::new(0),
+            images_version: AtomicU64::new(0),
+            random_selection: ArcSwapOption::empty(),
+            image_sources: Mutex::default(),
+            image_hashes: Mutex::default(),
+            image_previews: Mutex::default(),
+            image_sizes: Mutex::default(),
+            image_dimensions: Mutex::default(),
+            image_ids: Mutex::default(),
+            image_keys: Mutex::default(),
+            image_stale: Mutex::default(),
+            #[cfg(feature = "remote-sources")]
+            http_client: reqwest::Client::default(),
+            #[cfg(feature = "remote-sources")]
+            proxy_client: None,
+            breakers: Mutex::default(),
+            collections: BTreeMap::new(),
+            proxy_cache: None,
+            placeholder_cache: None,
+            variant_cache: TransformsConfig::default().cache_config().create_backend(),
         }
     }
 }
@@ -27,33 +368,724 @@ impl CacheBackendType {
     pub fn create_backend(&self) -> Box<dyn CacheBackend> {
         match self {
             Self::InMemory => Box::new(crate::cache::InMemoryCache::new()),
-            Self::FileSystem => Box::new(crate::cache::FileSystemCache::new()),
+            Self::FileSystem => Box::new(FileSystemCache::new()),
+            Self::Tiered => Box::new(crate::cache::TieredCache::new()),
+            #[cfg(feature = "sled")]
+            Self::Sled => Box::new(crate::cache::SledCache::new()),
+            #[cfg(not(feature = "sled"))]
+            Self::Sled => {
+                tracing::warn!(
+                    "The sled cache backend requires the `sled` feature, falling back to the filesystem backend"
+                );
+                Box::new(FileSystemCache::new())
+            }
+            Self::Custom(_) => CacheConfig {
+                backend: self.clone(),
+                ..CacheConfig::default()
+            }
+            .create_backend(),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Create a new cache backend based on the configuration
+    #[must_use]
+    pub fn create_backend(&self) -> Box<dyn CacheBackend> {
+        match &self.backend {
+            CacheBackendType::FileSystem => Box::new(self.file_system_cache()),
+            CacheBackendType::Tiered => Box::new(TieredCache::with_disk(
+                self.file_system_cache(),
+                self.hot_images,
+            )),
+            #[cfg(feature = "sled")]
+            CacheBackendType::Sled => Box::new(
+                self.sled_path()
+                .and_then(|path| {
+                    crate::cache::SledCache::with_path(&path)
+                        .inspect_err(|e| {
+                            tracing::warn!(
+                                "Failed to open sled database {}, falling back to a temporary database: {e}",
+                                path.display()
+                            );
+                        })
+                        .ok()
+                })
+                    .unwrap_or_else(crate::cache::SledCache::new)
+                    .with_compression(self.compression),
+            ),
+            CacheBackendType::InMemory => Box::new(InMemoryCache::with_max_bytes(
+                self.max_bytes.unwrap_or(u64::MAX),
+                self.eviction,
+            )),
+            #[cfg(not(feature = "sled"))]
+            CacheBackendType::Sled => self.backend.create_backend(),
+            CacheBackendType::Custom(name) => match crate::cache::registered_backend(name) {
+                Some(factory) => factory(self),
+                None => {
+                    tracing::warn!(
+                        "No cache backend is registered as {name:?}, falling back to the in-memory backend"
+                    );
+                    Box::new(InMemoryCache::new())
+                }
+            },
         }
     }
+
+    /// Create a filesystem cache in the configured directory,
+    /// or a temporary directory if it is unset or can't be used
+    fn file_system_cache(&self) -> FileSystemCache {
+        self.directory
+            .as_ref()
+            .and_then(|directory| {
+                FileSystemCache::with_directory(directory)
+                    .inspect_err(|e| {
+                        tracing::warn!(
+                            "Failed to use cache directory {}, falling back to a temporary directory: {e}",
+                            directory.display()
+                        );
+                    })
+                    .ok()
+            })
+            .unwrap_or_else(FileSystemCache::new)
+            .with_hash_algorithm(self.hash)
+            .with_compression(self.compression)
+            .with_mmap(self.mmap)
+    }
+
+    /// Load the persisted serve counters, if the cache is persistent
+    fn load_serve_counts(&self) -> ServeCounters {
+        let Some(path) = self.serve_counts_path().filter(|path| path.exists()) else {
+            return ServeCounters::default();
+        };
+        ServeCounters::load(&path).unwrap_or_else(|e| {
+            tracing::warn!("Failed to load serve counters from {}: {e}", path.display());
+            ServeCounters::default()
+        })
+    }
 }
 
 impl ServerState {
     /// Create a new `ServerState` with a specific configuration
     #[must_use]
-    pub fn with_config(config: &crate::config::Config) -> Self {
+    pub fn with_config(config: &Config) -> Self {
+        Self::with_cache(config, config.cache.create_backend())
+    }
+}
+
+impl<C: CacheBackend> ServerState<C> {
+    /// Create a new `ServerState` with a specific configuration, storing images in the given cache
+    ///
+    /// The cache settings of the configuration are not used to create a backend.
+    #[must_use]
+    pub fn with_cache(config: &Config, cache: C) -> Self {
         Self {
-            cache: config.cache.backend.create_backend(),
-            current_index: 0,
+            cache,
+            current_index: AtomicUsize::new(0),
+            playlist_indices: config
+                .playlists
+                .keys()
+                .map(|name| (name.clone(), AtomicUsize::new(0)))
+                .collect(),
+            config: config.clone(),
+            populated: AtomicBool::new(false),
+            sources: Mutex::new(
+                config
+                    .server
+                    .sources
+                    .iter()
+                    .map(|source| SourceHealth::new(&source.location))
+                    .collect(),
+            ),
+            serve_counts: config.cache.load_serve_counts(),
+            recently_served: Mutex::default(),
+            sticky_images: Mutex::default(),
+            serve_hooks: ServeHooks::default(),
+            verified_credentials: VerifiedCredentials::default(),
+            hotlink_placeholder: config.server.hotlink_placeholder.as_ref().and_then(|path| {
+                crate::read_image_from_path(path)
+                    .inspect_err(|e| {
+                        tracing::error!(
+                            "Failed to read the hotlink placeholder, refusing hotlinks instead: {e}"
+                        );
+                    })
+                    .ok()
+            }),
+            empty_placeholder: (config.server.on_empty == EmptyCachePolicy::Placeholder)
+                .then(|| {
+                    crate::placeholder::Placeholder::empty_cache()
+                        .render()
+                        .inspect_err(|e| {
+                            tracing::error!("Failed to draw the empty cache placeholder: {e}")
+                        })
+                        .ok()
+                })
+                .flatten(),
+            favicon: crate::static_files::load_favicon(&config.server),
+            metrics: RequestMetrics::default(),
+            withheld: ArcSwap::default(),
+            reloads: AtomicUsize::new(0),
+            images_version: AtomicU64::new(0),
+            random_selection: ArcSwapOption::empty(),
+            image_sources: Mutex::default(),
+            image_hashes: Mutex::default(),
+            image_previews: Mutex::default(),
+            image_sizes: Mutex::default(),
+            image_dimensions: Mutex::default(),
+            image_ids: Mutex::default(),
+            image_keys: Mutex::default(),
+            image_stale: Mutex::default(),
+            #[cfg(feature = "remote-sources")]
+            http_client: config.http.build_client().unwrap_or_else(|e| {
+                tracing::error!("Invalid HTTP client settings, using the defaults: {e}");
+                reqwest::Client::default()
+            }),
+            #[cfg(feature = "remote-sources")]
+            proxy_client: config
+                .proxy
+                .is_enabled()
+                .then(|| {
+                    config
+                        .http
+                        .build_proxy_client(&config.proxy)
+                        .inspect_err(|e| {
+                            tracing::error!(
+                                "Invalid HTTP client settings, not proxying images: {e}"
+                            )
+                        })
+                        .ok()
+                })
+                .flatten(),
+            breakers: Mutex::new(CircuitBreakers::new(&config.http)),
+            collections: config
+                .collections
+                .keys()
+                .filter_map(|name| {
+                    let config = config.collection(name)?;
+                    Some((name.clone(), Arc::new(ServerState::with_config(&config))))
+                })
+                .collect(),
+            proxy_cache: config
+                .proxy
+                .is_enabled()
+                .then(|| config.proxy.cache_config().create_backend()),
+            placeholder_cache: config
+                .placeholder
+                .enabled
+                .then(|| config.placeholder.cache_config().create_backend()),
+            variant_cache: config.transforms.cache_config().create_backend(),
+        }
+    }
+
+    /// Persist the per-image serve counters, if the cache is persistent
+    pub fn save_serve_counts(&self) {
+        let Some(path) = self.config.cache.serve_counts_path() else {
+            return;
+        };
+        match self.serve_counts.save(&path) {
+            Ok(()) => tracing::info!("Saved serve counters to {}", path.display()),
+            Err(e) => tracing::error!("Failed to save serve counters to {}: {e}", path.display()),
+        }
+    }
+
+    /// The status of each configured source
+    #[must_use]
+    pub fn sources(&self) -> Vec<SourceHealth> {
+        lock(&self.sources).clone()
+    }
+
+    /// Record that an image was served, and invoke the `on_image_served` hooks
+    pub fn record_served(&self, key: &CacheKey, image: &ServedImage) {
+        self.serve_counts.record(key);
+        if let Some(window) = self.config.server.no_repeat_window {
+            let mut recent = lock(&self.recently_served);
+            recent.push_back(key.clone());
+            while recent.len() > window {
+                recent.pop_front();
+            }
+        }
+        self.serve_hooks.run(key, image);
+    }
+
+    /// The images served most recently, the latest first, as many as `server.no_repeat_window`
+    #[must_use]
+    pub fn recently_served(&self) -> Vec<CacheKey> {
+        lock(&self.recently_served).iter().rev().cloned().collect()
+    }
+
+    /// Update the bookkeeping entry for a source, creating it if it doesn't exist
+    fn update_source_health(&self, source: &ImageSource, update: impl FnOnce(&mut SourceHealth)) {
+        let name = source.to_string();
+        let mut sources = lock(&self.sources);
+        let index = sources
+            .iter()
+            .position(|health| health.source == name)
+            .unwrap_or_else(|| {
+                sources.push(SourceHealth::new(source));
+                sources.len() - 1
+            });
+        update(&mut sources[index]);
+    }
+
+    /// Store an image loaded from the configured source with the given index in the cache
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image cannot be stored in the cache.
+    pub fn store_image(
+        &self,
+        source_index: usize,
+        key: CacheKey,
+        image: CacheValue,
+    ) -> Result<(), String> {
+        let size = image.data.len() as u64;
+        let id = self.config.cache.hash.digest(&image.data);
+        let dimensions = imagesize::blob_size(&image.data)
+            .ok()
+            .map(|dimensions| (dimensions.width, dimensions.height));
+        // images new to the cache only become visible once the reload storing them is published
+        if self.reloads.load(Ordering::Acquire) > 0
+            && !self.is_withheld(&key)
+            && !lock(&self.image_sources).contains_key(&key)
+        {
+            self.withhold(std::slice::from_ref(&key));
+        }
+        // what is known about the images evicted to make room for this one is forgotten along with them
+        for evicted in self.cache.set_evicting(key.clone(), image)? {
+            self.remove_image(&evicted);
+        }
+        lock(&self.image_sizes).insert(key.clone(), size);
+        match dimensions {
+            Some(dimensions) => lock(&self.image_dimensions).insert(key.clone(), dimensions),
+            None => lock(&self.image_dimensions).remove(&key),
+        };
+        let previous = lock(&self.image_ids).insert(key.clone(), id.clone());
+        if let Some(previous) = previous.filter(|previous| *previous != id) {
+            self.forget_id(&previous, &key);
+        }
+        let mut keys = lock(&self.image_keys);
+        let identical = keys.entry(id).or_default();
+        if !identical.contains(&key) {
+            identical.push(key.clone());
+        }
+        keys.insert(key.id(), vec![key.clone()]);
+        drop(keys);
+        lock(&self.image_stale).remove(&key);
+        lock(&self.image_sources).insert(key, source_index);
+        self.images_changed();
+        Ok(())
+    }
+
+    /// The size in bytes of the image with the given key, if it was stored from a configured source
+    #[must_use]
+    pub fn image_size(&self, key: &CacheKey) -> Option<u64> {
+        lock(&self.image_sizes).get(key).copied()
+    }
+
+    /// The width and height in pixels of the image with the given key, if it was stored from a configured
+    /// source and they could be determined
+    #[must_use]
+    pub fn image_dimensions(&self, key: &CacheKey) -> Option<(usize, usize)> {
+        lock(&self.image_dimensions).get(key).copied()
+    }
+
+    /// The public id of the image with the given key
+    ///
+    /// Images stored from a configured source are identified by the hash of their content, so identical
+    /// images have the same id wherever they're loaded from. Other images are identified by their key.
+    #[must_use]
+    pub fn image_id(&self, key: &CacheKey) -> String {
+        lock(&self.image_ids)
+            .get(key)
+            .cloned()
+            .unwrap_or_else(|| key.id())
+    }
+
+    /// The key of the cached image with the given public id
+    ///
+    /// Images are also found by the id derived from their key, so links from before they were
+    /// identified by their content keep working.
+    #[must_use]
+    pub fn find_image(&self, id: &str) -> Option<CacheKey> {
+        lock(&self.image_keys)
+            .get(id)
+            .and_then(|keys| keys.first())
+            .cloned()
+    }
+
+    /// Stop finding the image with the given key by the given id, leaving the other images with the same content
+    fn forget_id(&self, id: &str, key: &CacheKey) {
+        let mut keys = lock(&self.image_keys);
+        if let Some(identical) = keys.get_mut(id) {
+            identical.retain(|other| other != key);
+            if identical.is_empty() {
+                keys.remove(id);
+            }
+        }
+    }
+
+    /// Mark the cached image with the given key as stale: it failed to be refreshed from its source, so the
+    /// copy cached before is served until a refresh succeeds
+    ///
+    /// An image that's already stale keeps the time it became stale.
+    pub fn mark_stale(&self, key: &CacheKey) {
+        lock(&self.image_stale)
+            .entry(key.clone())
+            .or_insert_with(now_secs);
+    }
+
+    /// Mark the cached image with the given key as fresh again, e.g. after its source confirmed it's unchanged
+    pub fn mark_fresh(&self, key: &CacheKey) {
+        lock(&self.image_stale).remove(key);
+    }
+
+    /// When the image with the given key became stale, in seconds since the Unix epoch, if it is
+    #[must_use]
+    pub fn image_stale_since(&self, key: &CacheKey) -> Option<u64> {
+        lock(&self.image_stale).get(key).copied()
+    }
+
+    /// The number of cached images that are stale
+    #[must_use]
+    pub fn stale_count(&self) -> usize {
+        lock(&self.image_stale).len()
+    }
+
+    /// The perceptual hash of the image with the given key, if computed
+    #[must_use]
+    pub fn image_hash(&self, key: &CacheKey) -> Option<u64> {
+        lock(&self.image_hashes).get(key).copied()
+    }
+
+    /// Record the perceptual hash of the image with the given key
+    pub fn set_image_hash(&self, key: CacheKey, hash: u64) {
+        lock(&self.image_hashes).insert(key, hash);
+    }
+
+    /// The preview of the image with the given key, if computed
+    #[must_use]
+    pub fn image_preview(&self, key: &CacheKey) -> Option<Preview> {
+        lock(&self.image_previews).get(key).cloned()
+    }
+
+    /// Record the preview of the image with the given key
+    pub fn set_image_preview(&self, key: CacheKey, preview: Preview) {
+        lock(&self.image_previews).insert(key, preview);
+    }
+
+    /// Another image in the cache whose perceptual hash differs from the given one in at most `threshold` bits
+    #[must_use]
+    pub fn near_duplicate(&self, key: &CacheKey, hash: u64, threshold: u32) -> Option<CacheKey> {
+        lock(&self.image_hashes)
+            .iter()
+            .find(|(other, other_hash)| {
+                *other != key && crate::phash::distance(hash, **other_hash) <= threshold
+            })
+            .map(|(other, _)| other.clone())
+    }
+
+    /// Remove the images of the configured source with the given index that are not in `keys`
+    pub fn remove_stale_images(&self, source_index: usize, keys: &[CacheKey]) {
+        for key in self.stale_images(source_index, keys) {
+            tracing::info!("Removing image no longer found in its source: {key}");
+            self.remove_image(&key);
+        }
+    }
+
+    /// The images of the configured source with the given index that are not in `keys`
+    fn stale_images(&self, source_index: usize, keys: &[CacheKey]) -> Vec<CacheKey> {
+        let keys: HashSet<&CacheKey> = keys.iter().collect();
+        lock(&self.image_sources)
+            .iter()
+            .filter(|(key, index)| **index == source_index && !keys.contains(key))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Start reloading sources, withholding the images it stores that weren't cached before from requests
+    /// until it is published
+    pub fn begin_reload(&self) -> Reload<'_, C> {
+        self.reloads.fetch_add(1, Ordering::AcqRel);
+        Reload {
+            state: self,
+            loaded: Vec::new(),
+            stale: Vec::new(),
+        }
+    }
+
+    /// Withhold the given images from requests
+    fn withhold(&self, keys: &[CacheKey]) {
+        self.withheld.rcu(|withheld| {
+            let mut withheld = HashSet::clone(withheld);
+            withheld.extend(keys.iter().cloned());
+            withheld
+        });
+        self.images_changed();
+    }
+
+    /// Withhold the images with the given keys that weren't stored from a source before from requests, with a single swap,
+    /// if a reload is running
+    ///
+    /// Sources withhold the images they are about to load this way, so that storing each of them doesn't swap
+    /// the withheld images again. Those that end up not being stored are released when the last reload is published.
+    pub fn withhold_new(&self, keys: &[CacheKey]) {
+        if self.reloads.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let sources = lock(&self.image_sources);
+        let new: Vec<CacheKey> = keys
+            .iter()
+            .filter(|key| !sources.contains_key(key))
+            .cloned()
+            .collect();
+        drop(sources);
+        if !new.is_empty() {
+            self.withhold(&new);
+        }
+    }
+
+    /// Whether the image with the given key is withheld from requests until a reload is published
+    #[must_use]
+    pub fn is_withheld(&self, key: &CacheKey) -> bool {
+        self.withheld.load().contains(key)
+    }
+
+    /// Record that the images requests may be served changed
+    fn images_changed(&self) {
+        self.images_version.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// The images `/random` may choose among, taken again if they changed since they were last taken
+    pub(crate) fn random_selection(&self) -> Arc<RandomSelection> {
+        let version = self.images_version.load(Ordering::Acquire);
+        let cached = self.cache.size();
+        if let Some(selection) = self.random_selection.load_full()
+            && selection.version == version
+            && selection.cached == cached
+        {
+            return selection;
+        }
+
+        let withheld = self.withheld.load_full();
+        let dimensions = lock(&self.image_dimensions);
+        let sources = lock(&self.image_sources);
+        let images: Vec<SelectableImage> = self
+            .cache
+            .iter()
+            .filter(|(key, _)| !withheld.contains(key))
+            .map(|(key, metadata)| SelectableImage {
+                dimensions: dimensions.get(&key).copied(),
+                weight: sources
+                    .get(&key)
+                    .and_then(|index| self.config.server.sources.get(*index))
+                    .map_or(1, |source| source.weight),
+                content_type: metadata.content_type,
+                key,
+            })
+            .collect();
+        drop((dimensions, sources));
+        let selection = Arc::new(RandomSelection {
+            version,
+            cached,
+            weights: WeightedIndex::new(images.iter().map(|image| image.weight)).ok(),
+            images,
+        });
+        self.random_selection.store(Some(selection.clone()));
+        selection
+    }
+
+    /// The keys of the cached images requests may be served, in the order of the cache
+    ///
+    /// These are the images of the cache, except those withheld until a reload is published.
+    #[must_use]
+    pub fn image_keys(&self) -> Box<dyn Iterator<Item = CacheKey> + '_> {
+        let withheld = self.withheld.load_full();
+        if withheld.is_empty() {
+            return self.cache.keys();
+        }
+        Box::new(self.cache.keys().filter(move |key| !withheld.contains(key)))
+    }
+
+    /// The keys of the cached images with one of the given content types requests may be served,
+    /// in the order of the cache
+    #[must_use]
+    pub fn image_keys_of_types(&self, content_types: &[String]) -> Vec<CacheKey> {
+        let withheld = self.withheld.load_full();
+        self.cache
+            .keys_of_types(content_types)
+            .filter(|key| !withheld.contains(key))
+            .collect()
+    }
+
+    /// The number of cached images requests may be served
+    #[must_use]
+    pub fn image_count(&self) -> usize {
+        if self.withheld.load().is_empty() {
+            self.cache.size()
+        } else {
+            self.image_keys().count()
+        }
+    }
+
+    /// Whether the cache has finished being populated, and contains at least one image,
+    /// and not every source failed to load
+    #[must_use]
+    pub fn is_ready(&self) -> bool {
+        self.populated.load(Ordering::Acquire) && self.image_count() > 0 && !self.sources_failed()
+    }
+
+    /// Whether every configured source failed to load the last time it was loaded, so the only images cached
+    /// are the stale copies kept from before
+    #[must_use]
+    pub fn sources_failed(&self) -> bool {
+        let sources = lock(&self.sources);
+        !sources.is_empty()
+            && sources.iter().all(|health| {
+                matches!(health.status, SourceStatus::Failed | SourceStatus::Retrying)
+            })
+    }
+
+    /// Remove an image from the cache, along with everything known about it,
+    /// returning the index of the configured source it was loaded from, if known
+    pub fn remove_image(&self, key: &CacheKey) -> Option<usize> {
+        self.cache.remove(key);
+        lock(&self.image_hashes).remove(key);
+        lock(&self.image_previews).remove(key);
+        lock(&self.image_sizes).remove(key);
+        lock(&self.image_dimensions).remove(key);
+        let id = lock(&self.image_ids).remove(key);
+        if let Some(id) = id {
+            self.forget_id(&id, key);
         }
+        lock(&self.image_keys).remove(&key.id());
+        lock(&self.image_stale).remove(key);
+        let source_index = lock(&self.image_sources).remove(key);
+        self.images_changed();
+        source_index
+    }
+
+    /// The index of the configured source the image with the given key was loaded from, if known
+    #[must_use]
+    pub fn image_source_index(&self, key: &CacheKey) -> Option<usize> {
+        lock(&self.image_sources).get(key).copied()
+    }
+
+    /// The configured source the image with the given key was loaded from, if known
+    #[must_use]
+    pub fn image_source(&self, key: &CacheKey) -> Option<&SourceConfig> {
+        self.image_source_index(key)
+            .and_then(|index| self.config.server.sources.get(index))
+    }
+
+    /// How likely the image with the given key is to be chosen at random, relative to other images
+    #[must_use]
+    pub fn image_weight(&self, key: &CacheKey) -> u32 {
+        self.image_source(key).map_or(1, |source| source.weight)
+    }
+
+    /// The cached images of the playlist with the given name, in the order they are played,
+    /// or `None` if no such playlist is configured
+    ///
+    /// An image included by several items of the playlist is played once for each of them.
+    #[must_use]
+    pub fn playlist_images(&self, name: &str) -> Option<Vec<CacheKey>> {
+        let playlist = self.config.playlists.get(name)?;
+        let keys: Vec<(CacheKey, Option<&SourceConfig>)> = self
+            .image_keys()
+            .map(|key| {
+                let source = self.image_source(&key);
+                (key, source)
+            })
+            .collect();
+        Some(
+            playlist
+                .items
+                .iter()
+                .flat_map(|item| {
+                    keys.iter()
+                        .filter(|(key, source)| {
+                            item.includes(key, source.map(|source| &source.location))
+                        })
+                        .map(|(key, _)| key.clone())
+                })
+                .collect(),
+        )
+    }
+
+    /// The images pinned to each client
+    pub fn sticky_images(&self) -> MutexGuard<'_, StickyImages> {
+        lock(&self.sticky_images)
+    }
+
+    /// The recent fetch failures of remote sources
+    pub fn breakers(&self) -> MutexGuard<'_, CircuitBreakers> {
+        lock(&self.breakers)
     }
+
+    /// Mark a source as about to be (re)loaded
+    pub fn mark_source_refreshing(&self, source: &ImageSource) {
+        self.update_source_health(source, |health| {
+            if health.status == SourceStatus::Failed {
+                health.status = SourceStatus::Retrying;
+            }
+        });
+    }
+
+    /// Record that a remote source was skipped, because the server is offline
+    pub fn mark_source_skipped(&self, source: &ImageSource) {
+        self.update_source_health(source, |health| {
+            health.status = SourceStatus::Skipped;
+            health.images = 0;
+            health.last_error = None;
+        });
+    }
+
+    /// Record the outcome of loading a source
+    pub fn record_source_outcome(
+        &self,
+        source: &ImageSource,
+        images: usize,
+        last_error: Option<String>,
+    ) {
+        self.update_source_health(source, |health| {
+            health.status = if images > 0 {
+                SourceStatus::Loaded
+            } else {
+                SourceStatus::Failed
+            };
+            health.images = images;
+            health.last_error = last_error;
+            health.last_refresh = Some(now_secs());
+        });
+    }
+}
+
+/// The current time, in seconds since the Unix epoch
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Acquire a lock, even if another thread panicked while holding it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{CacheBackendType, CacheConfig, Config};
+    use crate::cache::Validators;
+    use crate::config::{CacheBackendType, CacheConfig};
     use pretty_assertions::assert_eq;
+    use std::sync::atomic::Ordering;
 
     #[test]
     fn test_server_state_default() {
         let state = ServerState::default();
-        assert_eq!(state.current_index, 0);
+        assert_eq!(state.current_index.load(Ordering::Relaxed), 0);
         assert!(state.cache.is_empty());
+        assert!(!state.populated.load(Ordering::Relaxed));
     }
 
     #[test]
@@ -61,12 +1093,13 @@ mod tests {
         let config = Config {
             cache: CacheConfig {
                 backend: CacheBackendType::InMemory,
+                ..CacheConfig::default()
             },
             ..Config::default()
         };
         let state = ServerState::with_config(&config);
         assert_eq!(state.cache.backend_type(), "InMemory");
-        assert_eq!(state.current_index, 0);
+        assert_eq!(state.current_index.load(Ordering::Relaxed), 0);
         assert!(state.cache.is_empty());
     }
 
@@ -75,15 +1108,373 @@ mod tests {
         let config = Config {
             cache: CacheConfig {
                 backend: CacheBackendType::FileSystem,
+                ..CacheConfig::default()
             },
             ..Config::default()
         };
         let state = ServerState::with_config(&config);
         assert_eq!(state.cache.backend_type(), "FileSystem");
-        assert_eq!(state.current_index, 0);
+        assert_eq!(state.current_index.load(Ordering::Relaxed), 0);
         assert!(state.cache.is_empty());
     }
 
+    #[test]
+    fn test_server_state_source_bookkeeping() {
+        let source = ImageSource::Path("/test/images".into());
+        let config = Config {
+            server: crate::config::ServerConfig {
+                sources: vec![source.clone().into()],
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+        let state = ServerState::with_config(&config);
+        assert_eq!(state.sources().len(), 1);
+        assert_eq!(state.sources()[0].status, SourceStatus::Pending);
+
+        state.record_source_outcome(&source, 0, Some("boom".to_string()));
+        assert_eq!(state.sources()[0].status, SourceStatus::Failed);
+        assert_eq!(state.sources()[0].last_error.as_deref(), Some("boom"));
+        assert!(state.sources()[0].last_refresh.is_some());
+
+        state.mark_source_refreshing(&source);
+        assert_eq!(state.sources()[0].status, SourceStatus::Retrying);
+
+        state.record_source_outcome(&source, 3, None);
+        assert_eq!(state.sources()[0].status, SourceStatus::Loaded);
+        assert_eq!(state.sources()[0].images, 3);
+        assert_eq!(state.sources()[0].last_error, None);
+
+        // sources that weren't configured up front are tracked too
+        let other = ImageSource::Path("/test/other".into());
+        state.record_source_outcome(&other, 1, None);
+        assert_eq!(state.sources().len(), 2);
+    }
+
+    #[test]
+    fn test_server_state_remove_stale_images() {
+        let config = Config {
+            server: crate::config::ServerConfig {
+                sources: vec![
+                    ImageSource::Path("/test/a".into()).into(),
+                    ImageSource::Path("/test/b".into()).into(),
+                ],
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+        let state = ServerState::with_config(&config);
+        let keys = ["/test/a/1.jpg", "/test/a/2.jpg", "/test/b/1.jpg"]
+            .map(|path| CacheKey::ImagePath(path.into()));
+        for (index, key) in [0, 0, 1].into_iter().zip(&keys) {
+            let value = CacheValue {
+                data: vec![1, 2, 3],
+                content_type: "image/jpeg".to_string(),
+                validators: Validators::default(),
+            };
+            state.store_image(index, key.clone(), value).unwrap();
+        }
+
+        state.remove_stale_images(0, &keys[..1]);
+        assert_eq!(state.cache.size(), 2);
+        assert!(state.cache.get(keys[0].clone()).is_some());
+        assert!(state.cache.get(keys[1].clone()).is_none());
+        assert_eq!(
+            state.image_source(&keys[2]),
+            Some(&config.server.sources[1])
+        );
+        assert_eq!(state.image_source(&keys[1]), None);
+    }
+
+    #[test]
+    fn test_server_state_reload() {
+        let state = ServerState::with_config(&Config::default());
+        let image = || CacheValue {
+            data: vec![1, 2, 3],
+            content_type: "image/jpeg".to_string(),
+            validators: Validators::default(),
+        };
+        let keys = [
+            "/test/a/1.jpg",
+            "/test/a/2.jpg",
+            "/test/a/3.jpg",
+            "/test/b/1.jpg",
+        ]
+        .map(|path| CacheKey::ImagePath(path.into()));
+        state.store_image(0, keys[0].clone(), image()).unwrap();
+        state.store_image(0, keys[1].clone(), image()).unwrap();
+        state.store_image(1, keys[3].clone(), image()).unwrap();
+
+        let mut reload = state.begin_reload();
+        // the new image is stored, but only served once the reload is published,
+        // and the image no longer found is served until then
+        state.store_image(0, keys[0].clone(), image()).unwrap();
+        state.store_image(0, keys[2].clone(), image()).unwrap();
+        reload.record(0, &[keys[0].clone(), keys[2].clone()]);
+        // sources that failed to load keep their images
+        reload.record(1, &[]);
+        assert_eq!(state.cache.size(), 4);
+        assert_eq!(
+            state.image_keys().collect::<HashSet<_>>(),
+            HashSet::from([keys[0].clone(), keys[1].clone(), keys[3].clone()])
+        );
+        assert_eq!(state.image_count(), 3);
+        assert_eq!(
+            state.image_keys_of_types(&["image/jpeg".to_string()]).len(),
+            3
+        );
+
+        reload.publish();
+        assert_eq!(
+            state.image_keys().collect::<HashSet<_>>(),
+            HashSet::from([keys[0].clone(), keys[2].clone(), keys[3].clone()])
+        );
+        assert_eq!(state.cache.size(), 3);
+        assert_eq!(state.image_source_index(&keys[1]), None);
+
+        // images stored outside of reloads are served right away
+        let key = CacheKey::ImagePath("/test/c/1.jpg".into());
+        state.store_image(2, key.clone(), image()).unwrap();
+        assert!(state.image_keys().any(|other| other == key));
+    }
+
+    #[test]
+    fn test_server_state_store_image_eviction() {
+        let config = Config {
+            cache: CacheConfig {
+                max_bytes: Some(4),
+                eviction: crate::config::EvictionPolicy::EvictOldest,
+                ..CacheConfig::default()
+            },
+            ..Config::default()
+        };
+        let state = ServerState::with_config(&config);
+        let keys = ["/test/1.jpg", "/test/2.jpg"].map(|path| CacheKey::ImagePath(path.into()));
+        for (key, data) in keys.iter().zip([vec![1, 2, 3], vec![4, 5, 6]]) {
+            let value = CacheValue {
+                data,
+                content_type: "image/jpeg".to_string(),
+                validators: Validators::default(),
+            };
+            state.store_image(0, key.clone(), value).unwrap();
+        }
+
+        // the evicted image is forgotten entirely
+        let id = state.config.cache.hash.digest(&[1, 2, 3]);
+        assert_eq!(state.cache.keys().collect::<Vec<_>>(), [keys[1].clone()]);
+        assert_eq!(state.image_size(&keys[0]), None);
+        assert_eq!(state.image_source_index(&keys[0]), None);
+        assert_eq!(state.image_id(&keys[0]), keys[0].id());
+        assert_eq!(state.find_image(&id), None);
+        assert_eq!(state.image_size(&keys[1]), Some(3));
+    }
+
+    #[test]
+    fn test_server_state_reload_withhold_new() {
+        let state = ServerState::with_config(&Config::default());
+        let image = || CacheValue {
+            data: vec![1, 2, 3],
+            content_type: "image/jpeg".to_string(),
+            validators: Validators::default(),
+        };
+        let keys = ["/test/1.jpg", "/test/2.jpg", "/test/3.jpg"]
+            .map(|path| CacheKey::ImagePath(path.into()));
+        state.store_image(0, keys[0].clone(), image()).unwrap();
+
+        // outside of reloads, nothing is withheld
+        state.withhold_new(&keys);
+        assert!(!state.is_withheld(&keys[1]));
+
+        let mut reload = state.begin_reload();
+        state.withhold_new(&keys);
+        assert!(!state.is_withheld(&keys[0]));
+        assert!(state.is_withheld(&keys[1]) && state.is_withheld(&keys[2]));
+        // the third image fails to load, so only the second is stored
+        state.store_image(0, keys[1].clone(), image()).unwrap();
+        assert_eq!(state.image_count(), 1);
+        reload.record(0, &keys[..2]);
+        reload.publish();
+
+        assert_eq!(state.image_count(), 2);
+        assert!(!state.is_withheld(&keys[2]));
+        state.store_image(0, keys[2].clone(), image()).unwrap();
+        assert_eq!(state.image_count(), 3);
+    }
+
+    #[test]
+    fn test_server_state_stale_images() {
+        let state = ServerState::with_config(&Config::default());
+        let image = || CacheValue {
+            data: vec![1, 2, 3],
+            content_type: "image/jpeg".to_string(),
+            validators: Validators::default(),
+        };
+        let key = CacheKey::ImagePath("/test/a/1.jpg".into());
+        state.store_image(0, key.clone(), image()).unwrap();
+        assert_eq!(state.image_stale_since(&key), None);
+
+        state.mark_stale(&key);
+        let since = state.image_stale_since(&key).unwrap();
+        assert_eq!(state.stale_count(), 1);
+
+        // storing a fresh copy clears the mark, as does removing the image
+        state.store_image(0, key.clone(), image()).unwrap();
+        assert_eq!(state.image_stale_since(&key), None);
+        state.mark_stale(&key);
+        assert!(state.image_stale_since(&key).unwrap() >= since);
+        state.remove_image(&key);
+        assert_eq!(state.stale_count(), 0);
+    }
+
+    #[test]
+    fn test_server_state_image_ids() {
+        let state = ServerState::with_config(&Config::default());
+        let keys = ["/test/a/1.jpg", "/test/b/1.jpg", "/test/b/2.jpg"]
+            .map(|path| CacheKey::ImagePath(path.into()));
+        for (key, data) in keys.iter().zip([vec![1, 2, 3], vec![1, 2, 3], vec![4]]) {
+            let value = CacheValue {
+                data,
+                content_type: "image/jpeg".to_string(),
+                validators: Validators::default(),
+            };
+            state.store_image(0, key.clone(), value).unwrap();
+        }
+
+        // identical images share the id of their content
+        let id = state.config.cache.hash.digest(&[1, 2, 3]);
+        assert_eq!(state.image_id(&keys[0]), id);
+        assert_eq!(state.image_id(&keys[1]), id);
+        assert_ne!(state.image_id(&keys[2]), id);
+        assert!(
+            state
+                .find_image(&id)
+                .is_some_and(|key| keys[..2].contains(&key))
+        );
+        // the ids derived from keys still find their images
+        assert_eq!(state.find_image(&keys[2].id()), Some(keys[2].clone()));
+        assert_eq!(state.find_image("unknown"), None);
+
+        state.remove_stale_images(0, &keys[..1]);
+        assert_eq!(state.find_image(&id), Some(keys[0].clone()));
+        assert_eq!(state.find_image(&keys[1].id()), None);
+        assert_eq!(
+            state.find_image(&state.config.cache.hash.digest(&[4])),
+            None
+        );
+        // images that weren't stored from a source are identified by their key
+        let key = CacheKey::ImagePath("/test/c/1.jpg".into());
+        assert_eq!(state.image_id(&key), key.id());
+    }
+
+    #[test]
+    fn test_server_state_random_selection() {
+        let mut config = Config::default();
+        config.server.sources = vec![SourceConfig {
+            weight: 3,
+            ..ImageSource::Path("/test".into()).into()
+        }];
+        let state = ServerState::with_config(&config);
+        let value = CacheValue {
+            data: vec![1, 2, 3],
+            content_type: "image/jpeg".to_string(),
+            validators: Validators::default(),
+        };
+        let key = CacheKey::ImagePath("/test/1.jpg".into());
+        state.store_image(0, key.clone(), value.clone()).unwrap();
+
+        // the selection is only taken again once the images change
+        let selection = state.random_selection();
+        assert!(Arc::ptr_eq(&selection, &state.random_selection()));
+        assert_eq!(selection.images.len(), 1);
+        assert_eq!(selection.images[0].weight, 3);
+
+        let other = CacheKey::ImagePath("/test/2.jpg".into());
+        state.cache.set(other.clone(), value).unwrap();
+        let selection = state.random_selection();
+        assert_eq!(selection.images.len(), 2);
+        assert_eq!(selection.images[1].weight, 1);
+
+        state.remove_image(&key);
+        let selection = state.random_selection();
+        assert_eq!(
+            selection
+                .images
+                .iter()
+                .map(|image| &image.key)
+                .collect::<Vec<_>>(),
+            [&other]
+        );
+    }
+
+    #[test]
+    fn test_server_state_recently_served() {
+        let mut config = Config::default();
+        let keys = ["/test/1.jpg", "/test/2.jpg", "/test/3.jpg"]
+            .map(|path| CacheKey::ImagePath(path.into()));
+        let served = ServedImage {
+            content_type: "image/jpeg".to_string(),
+            bytes: 3,
+            hash: String::new(),
+        };
+
+        // nothing is tracked without a window
+        let state = ServerState::with_config(&config);
+        state.record_served(&keys[0], &served);
+        assert_eq!(state.recently_served(), vec![]);
+
+        config.server.no_repeat_window = Some(2);
+        let state = ServerState::with_config(&config);
+        for key in &keys {
+            state.record_served(key, &served);
+        }
+        assert_eq!(
+            state.recently_served(),
+            vec![keys[2].clone(), keys[1].clone()]
+        );
+        assert_eq!(state.serve_counts.get(&keys[0]), 1);
+    }
+
+    #[test]
+    fn test_server_state_near_duplicate() {
+        let state = ServerState::default();
+        let original = CacheKey::ImagePath("/test/original.jpg".into());
+        let copy = CacheKey::ImagePath("/test/copy.jpg".into());
+        state.set_image_hash(original.clone(), 0b1111);
+
+        assert_eq!(
+            state.near_duplicate(&copy, 0b0111, 1),
+            Some(original.clone())
+        );
+        assert_eq!(state.near_duplicate(&copy, 0b0001, 1), None);
+        // an image is not a duplicate of itself
+        assert_eq!(state.near_duplicate(&original, 0b1111, 0), None);
+    }
+
+    #[test]
+    fn test_server_state_with_config_persistent_serve_counts() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = Config {
+            cache: CacheConfig {
+                backend: CacheBackendType::FileSystem,
+                directory: Some(temp_dir.path().to_path_buf()),
+                ..CacheConfig::default()
+            },
+            ..Config::default()
+        };
+        let key = crate::cache::CacheKey::ImagePath("/test/image.jpg".into());
+
+        let state = ServerState::with_config(&config);
+        state.serve_counts.record(&key);
+        state
+            .serve_counts
+            .save(&config.cache.serve_counts_path().unwrap())
+            .unwrap();
+        drop(state);
+
+        let state = ServerState::with_config(&config);
+        assert_eq!(state.serve_counts.get(&key), 1);
+    }
+
     #[test]
     fn test_cache_backend_type_create_backend_in_memory() {
         let backend = CacheBackendType::InMemory.create_backend();
@@ -97,4 +1488,62 @@ mod tests {
         assert_eq!(backend.size(), 0);
         assert!(backend.is_empty());
     }
+
+    #[test]
+    fn test_cache_config_create_backend_tiered() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = CacheConfig {
+            backend: CacheBackendType::Tiered,
+            directory: Some(temp_dir.path().to_path_buf()),
+            ..CacheConfig::default()
+        };
+        let backend = config.create_backend();
+        assert_eq!(backend.backend_type(), "Tiered");
+        assert!(backend.is_empty());
+        assert_eq!(
+            config.serve_counts_path(),
+            Some(temp_dir.path().join("serve_counts.json"))
+        );
+    }
+
+    #[test]
+    fn test_cache_config_create_backend_sled() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = CacheConfig {
+            backend: CacheBackendType::Sled,
+            directory: Some(temp_dir.path().to_path_buf()),
+            ..CacheConfig::default()
+        };
+        assert_eq!(
+            config.sled_path(),
+            Some(temp_dir.path().join("images.sled"))
+        );
+        let backend = config.create_backend();
+        #[cfg(feature = "sled")]
+        assert_eq!(backend.backend_type(), "Sled");
+        #[cfg(not(feature = "sled"))]
+        assert_eq!(backend.backend_type(), "FileSystem");
+        assert!(backend.is_empty());
+    }
+
+    #[test]
+    fn test_cache_config_create_backend_custom() {
+        crate::cache::register_backend("tiered_in_temp_dir", |config: &CacheConfig| {
+            Box::new(TieredCache::with_disk(
+                FileSystemCache::new(),
+                config.hot_images,
+            ))
+        });
+        let config = CacheConfig {
+            backend: CacheBackendType::Custom("tiered_in_temp_dir".to_string()),
+            ..CacheConfig::default()
+        };
+        assert_eq!(config.create_backend().backend_type(), "Tiered");
+        assert_eq!(config.backend.create_backend().backend_type(), "Tiered");
+        assert_eq!(config.serve_counts_path(), None);
+
+        // unregistered backends fall back to the in-memory backend
+        let backend = CacheBackendType::Custom("unregistered".to_string()).create_backend();
+        assert_eq!(backend.backend_type(), "InMemory");
+    }
 }
@@ -0,0 +1,145 @@
+//! The favicon, and the static files (e.g. the CSS and scripts of a gallery) served alongside the images.
+
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow};
+
+use crate::{cache::CacheValue, config::ServerConfig};
+
+/// The icon served at `/favicon.ico`, unless `server.favicon` is configured
+pub const DEFAULT_FAVICON: &[u8] = include_bytes!("../static/favicon.ico");
+
+const ICON_CONTENT_TYPE: &str = "image/x-icon";
+
+/// The built-in favicon
+#[must_use]
+pub fn default_favicon() -> CacheValue {
+    CacheValue {
+        data: DEFAULT_FAVICON.to_vec(),
+        content_type: ICON_CONTENT_TYPE.to_string(),
+        validators: crate::cache::Validators::default(),
+    }
+}
+
+/// The configured favicon, or the built-in one if none is configured or it can't be read
+#[must_use]
+pub fn load_favicon(config: &ServerConfig) -> CacheValue {
+    let Some(path) = &config.favicon else {
+        return default_favicon();
+    };
+    read_favicon(path).unwrap_or_else(|e| {
+        tracing::error!("Failed to read the favicon, serving the built-in one instead: {e}");
+        default_favicon()
+    })
+}
+
+fn read_favicon(path: &Path) -> Result<CacheValue> {
+    let data = fs::read(path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+    Ok(CacheValue {
+        data,
+        content_type: mime_guess::from_path(path)
+            .first()
+            .map_or_else(|| ICON_CONTENT_TYPE.to_string(), |mime| mime.to_string()),
+        validators: crate::cache::Validators::default(),
+    })
+}
+
+/// The file in the static directory at the given path, relative to it
+///
+/// Paths that aren't plain relative paths (e.g. containing `..`), or that lead out of the directory
+/// through symbolic links, are refused.
+#[must_use]
+pub fn resolve(dir: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path);
+    if path.is_empty()
+        || !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    let dir = dir.canonicalize().ok()?;
+    let file = dir.join(relative).canonicalize().ok()?;
+    (file.starts_with(&dir) && file.is_file()).then_some(file)
+}
+
+/// The content type a static file is served with, guessed from its extension
+#[must_use]
+pub fn content_type(path: &Path) -> String {
+    mime_guess::from_path(path)
+        .first_or_octet_stream()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_default_favicon() {
+        let favicon = default_favicon();
+        assert_eq!(favicon.content_type, "image/x-icon");
+        assert_eq!(
+            imagesize::image_type(&favicon.data).unwrap(),
+            imagesize::ImageType::Ico
+        );
+    }
+
+    #[test]
+    fn test_load_favicon() {
+        let mut config = ServerConfig {
+            favicon: Some(PathBuf::from("assets/blank.jpg")),
+            ..ServerConfig::default()
+        };
+        let favicon = load_favicon(&config);
+        assert_eq!(favicon.content_type, "image/jpeg");
+        assert_eq!(favicon.data, fs::read("assets/blank.jpg").unwrap());
+
+        config.favicon = Some(PathBuf::from("/nonexistent/favicon.ico"));
+        assert_eq!(load_favicon(&config), default_favicon());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("css")).unwrap();
+        fs::write(dir.path().join("css/gallery.css"), "body {}").unwrap();
+
+        assert_eq!(
+            resolve(dir.path(), "css/gallery.css"),
+            Some(dir.path().join("css/gallery.css").canonicalize().unwrap())
+        );
+        for path in [
+            "",
+            "css",
+            "css/missing.css",
+            "../secret",
+            "css/../../secret",
+            "/etc/passwd",
+        ] {
+            assert_eq!(resolve(dir.path(), path), None, "{path}");
+        }
+        assert_eq!(
+            content_type(Path::new("css/gallery.css")),
+            "text/css".to_string()
+        );
+        assert_eq!(
+            content_type(Path::new("data.unknown")),
+            "application/octet-stream".to_string()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_refuses_symlinks_out_of_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::NamedTempFile::new().unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("link")).unwrap();
+
+        assert_eq!(resolve(dir.path(), "link"), None);
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::cache::CacheKey;
+
+/// Counts of how many times each image has been served
+#[derive(Debug, Default)]
+pub struct ServeCounters {
+    counts: Mutex<HashMap<CacheKey, u64>>,
+}
+
+/// A single entry of a persisted `ServeCounters`
+#[derive(Debug, Serialize, Deserialize)]
+struct ServeCount {
+    key: CacheKey,
+    count: u64,
+}
+
+impl ServeCounters {
+    /// Record that the image with the given key was served
+    pub fn record(&self, key: &CacheKey) {
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        *counts.entry(key.clone()).or_default() += 1;
+    }
+
+    /// Get the number of times the image with the given key has been served
+    #[must_use]
+    pub fn get(&self, key: &CacheKey) -> u64 {
+        self.counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(key)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Get the total number of images served
+    #[must_use]
+    pub fn total(&self) -> u64 {
+        self.counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .sum()
+    }
+
+    /// Load counters previously persisted with `save`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read(path)?;
+        let counts: Vec<ServeCount> = serde_json::from_slice(&content)?;
+        Ok(Self {
+            counts: Mutex::new(
+                counts
+                    .into_iter()
+                    .map(|ServeCount { key, count }| (key, count))
+                    .collect(),
+            ),
+        })
+    }
+
+    /// Persist the counters to the given file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let counts: Vec<ServeCount> = self
+            .counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(key, count)| ServeCount {
+                key: key.clone(),
+                count: *count,
+            })
+            .collect();
+        std::fs::write(path, serde_json::to_vec(&counts)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_serve_counters_record() {
+        let counters = ServeCounters::default();
+        let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
+        assert_eq!(counters.get(&key), 0);
+
+        counters.record(&key);
+        counters.record(&key);
+        assert_eq!(counters.get(&key), 2);
+        assert_eq!(counters.total(), 2);
+    }
+
+    #[test]
+    fn test_serve_counters_save_and_load() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("serve_counts.json");
+        let key1 = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
+        let key2 = CacheKey::ImageUrl("https://example.com/image.jpg".parse().unwrap());
+
+        let counters = ServeCounters::default();
+        counters.record(&key1);
+        counters.record(&key2);
+        counters.record(&key2);
+        counters.save(&path).unwrap();
+
+        let loaded = ServeCounters::load(&path).unwrap();
+        assert_eq!(loaded.get(&key1), 1);
+        assert_eq!(loaded.get(&key2), 2);
+    }
+
+    #[test]
+    fn test_serve_counters_load_missing_file() {
+        assert!(ServeCounters::load(Path::new("/nonexistent/serve_counts.json")).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+//! Sticky random images, pinned to each client for a while with `?sticky=` or `server.sticky`,
+//! so that a dashboard refreshing every few seconds doesn't flicker between images.
+//!
+//! Clients are identified by a cookie, set by their first sticky request. Clients that don't keep
+//! cookies, e.g. scripts and kiosks, are identified by their address instead.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use hyper::{Request, header::HeaderValue};
+
+use crate::{cache::CacheKey, config::ServerConfig, query::MAX_STICKY_SECONDS};
+
+/// The name of the cookie identifying clients
+pub const COOKIE: &str = "random_image_client";
+
+/// The longest client id accepted from a cookie
+const MAX_ID_LENGTH: usize = 64;
+
+/// The most pins kept, beyond which the pins expiring soonest are forgotten first
+const MAX_PINS: usize = 100_000;
+
+/// How often the pins that have expired are forgotten
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The client that sent a request, as far as sticky images are concerned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Client {
+    /// The id of the client, from its cookie, or a new one if it has none
+    pub id: String,
+    /// Whether the id is new, and so must be set as the cookie of the client
+    pub new: bool,
+    /// The address of the client, if known
+    pub ip: Option<IpAddr>,
+}
+
+impl Client {
+    /// Identify the client that sent a request, by its cookie, or a new id if it has none
+    #[must_use]
+    pub fn identify<B>(req: &Request<B>, config: &ServerConfig) -> Self {
+        let ip = crate::access::client_ip(req, config);
+        let cookie = req
+            .headers()
+            .get_all(hyper::header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, _)| *name == COOKIE)
+            .map(|(_, id)| id)
+            .filter(|id| {
+                !id.is_empty()
+                    && id.len() <= MAX_ID_LENGTH
+                    && id
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+            });
+        match cookie {
+            Some(id) => Self {
+                id: id.to_string(),
+                new: false,
+                ip,
+            },
+            None => Self {
+                id: uuid::Uuid::new_v4().to_string(),
+                new: true,
+                ip,
+            },
+        }
+    }
+
+    /// The `Set-Cookie` header keeping the id of a new client for as long as its image is pinned,
+    /// scoped to the base path of the server
+    #[must_use]
+    pub fn set_cookie(&self, base_path: &str, duration: Duration) -> Option<HeaderValue> {
+        let path = if base_path.is_empty() { "/" } else { base_path };
+        self.new
+            .then(|| {
+                HeaderValue::from_str(&format!(
+                    "{COOKIE}={}; Path={path}; Max-Age={}; HttpOnly; SameSite=Lax",
+                    self.id,
+                    duration.as_secs()
+                ))
+                .ok()
+            })
+            .flatten()
+    }
+
+    /// The key of the pin of the client's cookie
+    fn cookie_key(&self) -> String {
+        format!("cookie:{}", self.id)
+    }
+
+    /// The key the image of the client is looked up by: its cookie if it has one, and its address otherwise
+    fn lookup_key(&self) -> Option<String> {
+        if self.new {
+            self.ip.map(|ip| format!("ip:{ip}"))
+        } else {
+            Some(self.cookie_key())
+        }
+    }
+
+    /// The keys the image of the client is pinned under: its cookie, and its address if the cookie is new,
+    /// in case the client doesn't keep it
+    fn pin_keys(&self) -> impl Iterator<Item = String> {
+        std::iter::once(self.cookie_key())
+            .chain(self.ip.filter(|_| self.new).map(|ip| format!("ip:{ip}")))
+    }
+}
+
+/// An image pinned to a client
+#[derive(Debug, Clone)]
+struct Pin {
+    key: CacheKey,
+    until: Instant,
+}
+
+/// The images pinned to each client
+#[derive(Debug, Default)]
+pub struct StickyImages {
+    pins: HashMap<String, Pin>,
+    /// The keys of the pins, in the order they expire
+    expiry: BTreeSet<(Instant, String)>,
+    /// When the pins that had expired were last forgotten
+    pruned: Option<Instant>,
+}
+
+impl StickyImages {
+    /// The image pinned to the client, unless its pin has expired
+    ///
+    /// The image pinned to the address of a new client is pinned to its new cookie too,
+    /// so that it keeps its image once it sends the cookie.
+    pub fn get(&mut self, client: &Client, now: Instant) -> Option<CacheKey> {
+        let pin = self
+            .pins
+            .get(&client.lookup_key()?)
+            .filter(|pin| pin.until > now)?
+            .clone();
+        if client.new {
+            self.insert(client.cookie_key(), pin.clone(), now);
+        }
+        Some(pin.key)
+    }
+
+    /// Pin an image to the client for the given duration, of at most `MAX_STICKY_SECONDS`
+    pub fn pin(&mut self, client: &Client, key: &CacheKey, duration: Duration, now: Instant) {
+        let until = now + duration.min(Duration::from_secs(MAX_STICKY_SECONDS));
+        for pin_key in client.pin_keys() {
+            let pin = Pin {
+                key: key.clone(),
+                until,
+            };
+            self.insert(pin_key, pin, now);
+        }
+    }
+
+    /// Keep a pin, replacing the one under the same key
+    ///
+    /// The pins that have expired are forgotten every `PRUNE_INTERVAL`, and the pin expiring soonest
+    /// if there are already `MAX_PINS`.
+    fn insert(&mut self, pin_key: String, pin: Pin, now: Instant) {
+        if let Some(previous) = self.pins.remove(&pin_key) {
+            self.expiry.remove(&(previous.until, pin_key.clone()));
+        }
+        if self
+            .pruned
+            .is_none_or(|pruned| now.saturating_duration_since(pruned) >= PRUNE_INTERVAL)
+        {
+            while let Some((until, _)) = self.expiry.first()
+                && *until <= now
+            {
+                self.forget_first();
+            }
+            self.pruned = Some(now);
+        }
+        if self.pins.len() >= MAX_PINS {
+            self.forget_first();
+        }
+        self.expiry.insert((pin.until, pin_key.clone()));
+        self.pins.insert(pin_key, pin);
+    }
+
+    /// Forget the pin expiring soonest
+    fn forget_first(&mut self) {
+        if let Some((_, pin_key)) = self.expiry.pop_first() {
+            self.pins.remove(&pin_key);
+        }
+    }
+
+    /// The number of clients with an image pinned to them, including expired pins
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.pins.len()
+    }
+
+    /// Whether no images are pinned
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::public_url::RemoteAddr;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    fn request(cookie: Option<&str>) -> Request<()> {
+        let mut builder = Request::builder().uri("/random");
+        if let Some(cookie) = cookie {
+            builder = builder.header(hyper::header::COOKIE, cookie);
+        }
+        let mut req = builder.body(()).unwrap();
+        req.extensions_mut()
+            .insert(RemoteAddr("10.0.0.1:1234".parse().unwrap()));
+        req
+    }
+
+    #[rstest]
+    #[case::none(None, None)]
+    #[case::only(Some("random_image_client=abc-123"), Some("abc-123"))]
+    #[case::among_others(Some("theme=dark; random_image_client=abc_123"), Some("abc_123"))]
+    #[case::empty(Some("random_image_client="), None)]
+    #[case::invalid(Some("random_image_client=<script>"), None)]
+    #[case::other_name(Some("random_image=abc"), None)]
+    fn test_client_identify(#[case] cookie: Option<&str>, #[case] expected: Option<&str>) {
+        let client = Client::identify(&request(cookie), &ServerConfig::default());
+        assert_eq!(client.ip, Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(client.new, expected.is_none());
+        if let Some(expected) = expected {
+            assert_eq!(client.id, expected);
+        }
+    }
+
+    #[test]
+    fn test_client_set_cookie() {
+        let client = Client::identify(&request(None), &ServerConfig::default());
+        assert_eq!(
+            client
+                .set_cookie("/images", Duration::from_secs(300))
+                .unwrap(),
+            format!(
+                "random_image_client={}; Path=/images; Max-Age=300; HttpOnly; SameSite=Lax",
+                client.id
+            )
+            .as_str()
+        );
+
+        let client = Client::identify(
+            &request(Some("random_image_client=abc")),
+            &ServerConfig::default(),
+        );
+        assert_eq!(client.set_cookie("", Duration::from_secs(300)), None);
+    }
+
+    #[test]
+    fn test_sticky_images() {
+        let now = Instant::now();
+        let key = CacheKey::ImagePath("/test/image.jpg".into());
+        let mut sticky = StickyImages::default();
+        let new = Client::identify(&request(None), &ServerConfig::default());
+        assert_eq!(sticky.get(&new, now), None);
+
+        sticky.pin(&new, &key, Duration::from_secs(60), now);
+        // pinned under both the new cookie and the address
+        assert_eq!(sticky.len(), 2);
+        assert_eq!(sticky.get(&new, now), Some(key.clone()));
+        assert_eq!(sticky.len(), 2);
+        let returning = Client {
+            new: false,
+            ..new.clone()
+        };
+        assert_eq!(sticky.get(&returning, now), Some(key.clone()));
+        // clients without the cookie, from the same address
+        let other = Client::identify(&request(None), &ServerConfig::default());
+        assert_eq!(sticky.get(&other, now), Some(key.clone()));
+        // which keep it once they send their cookie
+        let other = Client {
+            new: false,
+            ..other
+        };
+        assert_eq!(sticky.get(&other, now), Some(key.clone()));
+
+        let later = now + Duration::from_secs(61);
+        assert_eq!(sticky.get(&returning, later), None);
+        sticky.pin(
+            &returning,
+            &CacheKey::ImagePath("/test/other.jpg".into()),
+            Duration::from_secs(60),
+            later,
+        );
+        assert_eq!(sticky.len(), 1);
+    }
+
+    #[test]
+    fn test_sticky_images_max_pins() {
+        let now = Instant::now();
+        let key = CacheKey::ImagePath("/test/image.jpg".into());
+        let mut sticky = StickyImages::default();
+        let client = |id: usize| Client {
+            id: id.to_string(),
+            new: false,
+            ip: None,
+        };
+        // the first client's pin expires soonest
+        for id in 0..=MAX_PINS {
+            let duration = Duration::from_secs(60 + id as u64);
+            sticky.pin(&client(id), &key, duration, now);
+        }
+        assert_eq!(sticky.len(), MAX_PINS);
+        assert_eq!(sticky.get(&client(0), now), None);
+        assert_eq!(sticky.get(&client(1), now), Some(key.clone()));
+        assert_eq!(sticky.get(&client(MAX_PINS), now), Some(key));
+    }
+
+    #[test]
+    fn test_sticky_images_longest_pin() {
+        let now = Instant::now();
+        let key = CacheKey::ImagePath("/test/image.jpg".into());
+        let mut sticky = StickyImages::default();
+        let client = Client::identify(&request(None), &ServerConfig::default());
+        // doesn't overflow
+        sticky.pin(&client, &key, Duration::MAX, now);
+
+        let longest = Duration::from_secs(MAX_STICKY_SECONDS);
+        assert_eq!(
+            sticky.get(&client, now + longest - Duration::from_secs(1)),
+            Some(key)
+        );
+        assert_eq!(sticky.get(&client, now + longest), None);
+    }
+}
@@ -1,3 +1,4 @@
+#[cfg(unix)]
 use tokio::signal::unix::signal;
 use tokio::sync::broadcast;
 
@@ -7,6 +8,8 @@ pub enum Interrupted {
     OsSigQuit,
     OsSigTerm,
     UserInt,
+    /// The service control manager stopped the Windows service
+    ServiceStop,
 }
 
 #[derive(Debug, Clone)]
@@ -32,6 +35,7 @@ impl Terminator {
     }
 }
 
+#[cfg(unix)]
 async fn terminate_by_unix_signal(mut terminator: Terminator) {
     let mut interrupt_signal = signal(tokio::signal::unix::SignalKind::interrupt())
         .expect("failed to create interrupt signal stream");
@@ -59,6 +63,15 @@ async fn terminate_by_unix_signal(mut terminator: Terminator) {
     }
 }
 
+#[cfg(not(unix))]
+async fn terminate_by_ctrl_c(mut terminator: Terminator) {
+    if tokio::signal::ctrl_c().await.is_ok() {
+        terminator
+            .terminate(Interrupted::OsSigInt)
+            .expect("failed to send interrupt signal");
+    }
+}
+
 // create a broadcast channel for retrieving the application kill signal
 #[allow(clippy::module_name_repetitions)]
 #[must_use]
@@ -66,7 +79,10 @@ pub fn create_termination() -> (Terminator, broadcast::Receiver<Interrupted>) {
     let (tx, rx) = broadcast::channel(1);
     let terminator = Terminator::new(tx);
 
+    #[cfg(unix)]
     tokio::spawn(terminate_by_unix_signal(terminator.clone()));
+    #[cfg(not(unix))]
+    tokio::spawn(terminate_by_ctrl_c(terminator.clone()));
 
     (terminator, rx)
 }
@@ -0,0 +1,407 @@
+//! Serving HTTPS, and HTTP/3 over QUIC with the `http3` feature.
+
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path, sync::Arc};
+
+use anyhow::{Context, Result, anyhow};
+use hyper::header::HeaderValue;
+use rustls::{
+    ConfigBuilder,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert, WantsServerCert, WebPkiClientVerifier},
+    sign::CertifiedKey,
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::TlsConfig;
+
+/// The protocols negotiated over TCP with ALPN, preferring HTTP/2
+const TCP_ALPN_PROTOCOLS: [&[u8]; 2] = [b"h2", b"http/1.1"];
+
+/// Attached to requests received over TLS as an extension, so that links to the server use `https`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Secure;
+
+/// The certificate a client authenticated with, attached to its requests as an extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    /// The common name of the subject of the certificate, if it has one
+    pub common_name: Option<String>,
+}
+
+impl ClientCertificate {
+    /// The certificate the peer of a TLS connection authenticated with, given its certificate chain
+    #[must_use]
+    pub fn from_chain(chain: Option<&[CertificateDer<'_>]>) -> Option<Self> {
+        let cert = chain?.first()?;
+        let common_name = x509_parser::parse_x509_certificate(cert)
+            .ok()
+            .and_then(|(_, cert)| {
+                cert.subject()
+                    .iter_common_name()
+                    .next()
+                    .and_then(|name| name.as_str().ok())
+                    .map(ToString::to_string)
+            });
+        Some(Self { common_name })
+    }
+}
+
+/// Build the TLS configuration of the server, from the certificates and keys configured in `[tls]`
+///
+/// Returns `None` if no certificate is configured, as HTTPS isn't served then.
+/// The configuration doesn't negotiate any protocol with ALPN, the listener using it sets them.
+///
+/// # Errors
+///
+/// Returns an error if only one of `tls.cert` and `tls.key` is set, if certificates are configured
+/// along with `tls.acme`, or if they can't be loaded.
+pub fn server_config(config: &TlsConfig) -> Result<Option<rustls::ServerConfig>> {
+    if config.acme.is_some()
+        && (config.cert.is_some() || config.key.is_some() || !config.certificates.is_empty())
+    {
+        return Err(anyhow!(
+            "tls.cert, tls.key, and tls.certificates can't be set along with tls.acme, which obtains the certificate"
+        ));
+    }
+    let default = match (&config.cert, &config.key) {
+        (None, None) => None,
+        (Some(cert), Some(key)) => Some(load_certified_key(cert, key)?),
+        _ => {
+            return Err(anyhow!(
+                "Both tls.cert and tls.key must be set to serve HTTPS"
+            ));
+        }
+    };
+    if default.is_none() && config.certificates.is_empty() {
+        if config.client_ca.is_some() && config.acme.is_none() {
+            return Err(anyhow!(
+                "tls.client_ca requires HTTPS to be served, with tls.cert and tls.key or tls.acme"
+            ));
+        }
+        return Ok(None);
+    }
+
+    let mut resolver = SniResolver {
+        default,
+        by_hostname: HashMap::new(),
+    };
+    for (hostname, files) in &config.certificates {
+        let certified = load_certified_key(&files.cert, &files.key)
+            .with_context(|| format!("Failed to load the certificate of {hostname}"))?;
+        resolver
+            .by_hostname
+            .insert(hostname.to_ascii_lowercase(), certified);
+    }
+    Ok(Some(
+        config_builder(config, false)?.with_cert_resolver(Arc::new(resolver)),
+    ))
+}
+
+/// Chooses the certificate of each TLS handshake by the hostname the client asked for with SNI,
+/// falling back to the default certificate
+#[derive(Debug)]
+struct SniResolver {
+    default: Option<Arc<CertifiedKey>>,
+    by_hostname: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl SniResolver {
+    /// The certificate of the given hostname, keyed by it or by a wildcard matching it
+    fn certificate(&self, hostname: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let hostname = hostname.map(str::to_ascii_lowercase);
+        hostname
+            .as_deref()
+            .and_then(|hostname| {
+                self.by_hostname.get(hostname).or_else(|| {
+                    let (_, parent) = hostname.split_once('.')?;
+                    self.by_hostname.get(&format!("*.{parent}"))
+                })
+            })
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.certificate(client_hello.server_name())
+    }
+}
+
+/// Load a certificate chain and its private key, checking that they match
+fn load_certified_key(cert: &Path, key: &Path) -> Result<Arc<CertifiedKey>> {
+    let certified = CertifiedKey::from_der(
+        load_certs(cert)?,
+        load_key(key)?,
+        &rustls::crypto::ring::default_provider(),
+    )
+    .context("The TLS certificate doesn't match its private key")?;
+    Ok(Arc::new(certified))
+}
+
+/// Start building the TLS configuration of the server, verifying client certificates if `tls.client_ca` is set
+///
+/// If `allow_unauthenticated` is set, clients without a certificate complete the handshake anyway,
+/// and their requests are refused instead.
+///
+/// # Errors
+///
+/// Returns an error if the certificate authorities of `tls.client_ca` can't be loaded.
+pub(crate) fn config_builder(
+    config: &TlsConfig,
+    allow_unauthenticated: bool,
+) -> Result<ConfigBuilder<rustls::ServerConfig, WantsServerCert>> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let Some(client_ca) = &config.client_ca else {
+        return Ok(builder.with_no_client_auth());
+    };
+
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in load_certs(client_ca)? {
+        roots
+            .add(cert)
+            .with_context(|| format!("Invalid client CA certificate in {}", client_ca.display()))?;
+    }
+    let mut verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+    if allow_unauthenticated {
+        verifier = verifier.allow_unauthenticated();
+    }
+    Ok(builder.with_client_cert_verifier(verifier.build()?))
+}
+
+/// The acceptor HTTPS connections are made with over TCP, negotiating HTTP/2 or HTTP/1.1,
+/// along with the protocols already set in the configuration
+#[must_use]
+pub fn tcp_acceptor(mut config: rustls::ServerConfig) -> TlsAcceptor {
+    config
+        .alpn_protocols
+        .extend(TCP_ALPN_PROTOCOLS.iter().map(|p| p.to_vec()));
+    TlsAcceptor::from(Arc::new(config))
+}
+
+/// The `Alt-Svc` header advertising HTTP/3 on the given port to clients of the TCP listener
+#[must_use]
+pub fn alt_svc(port: u16) -> HeaderValue {
+    HeaderValue::from_str(&format!("h3=\":{port}\"; ma=86400"))
+        .expect("the Alt-Svc header is always valid")
+}
+
+/// Load the certificate chain in the given PEM file
+pub(crate) fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open TLS certificate {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to read TLS certificate {}", path.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificate found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+/// Load the first private key in the given PEM file
+pub(crate) fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open TLS private key {}", path.display()))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to read TLS private key {}", path.display()))?
+        .ok_or_else(|| anyhow!("No private key found in {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::config::CertificateFiles;
+
+    /// Write a self-signed certificate for `localhost` and its key to the given directory
+    fn self_signed(dir: &Path) -> (PathBuf, PathBuf) {
+        let rcgen::CertifiedKey { cert, signing_key } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert_path, cert.pem()).unwrap();
+        std::fs::write(&key_path, signing_key.serialize_pem()).unwrap();
+        (cert_path, key_path)
+    }
+
+    #[test]
+    fn test_server_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = self_signed(dir.path());
+
+        assert!(server_config(&TlsConfig::default()).unwrap().is_none());
+        let config = TlsConfig {
+            cert: Some(cert.clone()),
+            key: Some(key.clone()),
+            ..TlsConfig::default()
+        };
+        let acceptor = tcp_acceptor(server_config(&config).unwrap().unwrap());
+        assert_eq!(
+            acceptor.config().alpn_protocols,
+            [b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+
+        // both halves are required
+        let config = TlsConfig {
+            cert: Some(cert.clone()),
+            ..TlsConfig::default()
+        };
+        assert!(server_config(&config).is_err());
+
+        // a certificate can't be both loaded and obtained with ACME
+        let config = TlsConfig {
+            cert: Some(cert.clone()),
+            key: Some(key.clone()),
+            acme: Some(crate::config::AcmeConfig::default()),
+            ..TlsConfig::default()
+        };
+        assert!(server_config(&config).is_err());
+
+        // the key file doesn't hold a key
+        let config = TlsConfig {
+            cert: Some(cert.clone()),
+            key: Some(cert),
+            ..TlsConfig::default()
+        };
+        let error = server_config(&config).unwrap_err().to_string();
+        assert!(error.starts_with("No private key found"), "{error}");
+
+        // the certificate file doesn't hold a certificate
+        let config = TlsConfig {
+            cert: Some(key.clone()),
+            key: Some(key),
+            ..TlsConfig::default()
+        };
+        let error = server_config(&config).unwrap_err().to_string();
+        assert!(error.starts_with("No certificate found"), "{error}");
+    }
+
+    #[test]
+    fn test_sni_certificates() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = self_signed(dir.path());
+        let files = CertificateFiles {
+            cert: cert.clone(),
+            key: key.clone(),
+        };
+
+        // certificates keyed by hostname are enough to serve HTTPS, but not along with ACME
+        let mut config = TlsConfig {
+            certificates: [("images.example.com".to_string(), files.clone())].into(),
+            ..TlsConfig::default()
+        };
+        assert!(server_config(&config).unwrap().is_some());
+        config.acme = Some(crate::config::AcmeConfig::default());
+        assert!(server_config(&config).is_err());
+
+        // a certificate that doesn't match its key
+        let other = tempfile::tempdir().unwrap();
+        let (_, other_key) = self_signed(other.path());
+        let config = TlsConfig {
+            certificates: [(
+                "images.example.com".to_string(),
+                CertificateFiles {
+                    cert,
+                    key: other_key,
+                },
+            )]
+            .into(),
+            ..TlsConfig::default()
+        };
+        let error = format!("{:#}", server_config(&config).unwrap_err());
+        assert!(
+            error.starts_with("Failed to load the certificate of images.example.com"),
+            "{error}"
+        );
+
+        let certified = || load_certified_key(&files.cert, &files.key).unwrap();
+        let (default, exact, wildcard) = (certified(), certified(), certified());
+        let resolver = SniResolver {
+            default: Some(default.clone()),
+            by_hostname: [
+                ("images.example.com".to_string(), exact.clone()),
+                ("*.example.org".to_string(), wildcard.clone()),
+            ]
+            .into(),
+        };
+        let resolves_to = |hostname: Option<&str>, expected: &Arc<CertifiedKey>| {
+            assert!(
+                Arc::ptr_eq(&resolver.certificate(hostname).unwrap(), expected),
+                "{hostname:?}"
+            );
+        };
+        resolves_to(Some("images.example.com"), &exact);
+        resolves_to(Some("Images.Example.com"), &exact);
+        resolves_to(Some("cdn.example.org"), &wildcard);
+        // wildcards only match a single label
+        resolves_to(Some("a.cdn.example.org"), &default);
+        resolves_to(Some("example.org"), &default);
+        resolves_to(None, &default);
+
+        let resolver = SniResolver {
+            default: None,
+            ..resolver
+        };
+        assert!(resolver.certificate(Some("other.example.com")).is_none());
+    }
+
+    #[test]
+    fn test_client_ca() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = self_signed(dir.path());
+
+        // client certificates are only verified over HTTPS
+        let config = TlsConfig {
+            client_ca: Some(cert.clone()),
+            ..TlsConfig::default()
+        };
+        assert!(server_config(&config).is_err());
+        let config = TlsConfig {
+            cert: Some(cert.clone()),
+            key: Some(key.clone()),
+            client_ca: Some(cert),
+            ..TlsConfig::default()
+        };
+        assert!(server_config(&config).unwrap().is_some());
+        let config = TlsConfig {
+            client_ca: Some(key),
+            ..config
+        };
+        assert!(server_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_client_certificate() {
+        let mut params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "photo-frame");
+        let key = rcgen::KeyPair::generate().unwrap();
+        let named = params.self_signed(&key).unwrap();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        let anonymous = params.self_signed(&key).unwrap();
+
+        assert_eq!(ClientCertificate::from_chain(None), None);
+        assert_eq!(ClientCertificate::from_chain(Some(&[])), None);
+        assert_eq!(
+            ClientCertificate::from_chain(Some(&[named.der().clone()])),
+            Some(ClientCertificate {
+                common_name: Some("photo-frame".to_string())
+            })
+        );
+        assert_eq!(
+            ClientCertificate::from_chain(Some(&[anonymous.der().clone()])),
+            Some(ClientCertificate { common_name: None })
+        );
+    }
+
+    #[test]
+    fn test_alt_svc() {
+        assert_eq!(alt_svc(443), "h3=\":443\"; ma=86400");
+    }
+}
@@ -0,0 +1,771 @@
+//! Transforms of served images, e.g. rotating, filtering, or drawing captions onto them, enabled by the `transforms` feature.
+//!
+//! Transformed images are variants of the cached ones, stored in a cache of their own under synthetic keys
+//! derived from the original image and its transforms, so each variant is only rendered once.
+//! Without the feature, images can't be transformed.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Result, anyhow};
+
+use crate::{
+    cache::{CacheKey, CacheValue},
+    config::{CacheBackendType, CacheConfig, EvictionPolicy, TransformsConfig},
+    query::TransformQuery,
+};
+
+/// The most bytes of variants the variant cache holds, unless `transforms.cache` is configured
+pub const DEFAULT_CACHE_BYTES: u64 = 128 * 1024 * 1024;
+
+impl TransformsConfig {
+    /// The settings of the cache transformed variants of images are stored in
+    ///
+    /// By default, an in-memory cache of up to `DEFAULT_CACHE_BYTES`, evicting the oldest variants first,
+    /// since any of them can be rendered again.
+    #[must_use]
+    pub fn cache_config(&self) -> CacheConfig {
+        self.cache.clone().unwrap_or_else(|| CacheConfig {
+            backend: CacheBackendType::InMemory,
+            max_bytes: Some(DEFAULT_CACHE_BYTES),
+            eviction: EvictionPolicy::EvictOldest,
+            ..CacheConfig::default()
+        })
+    }
+}
+
+/// How far an image is rotated clockwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    /// A quarter turn
+    Quarter,
+    /// A half turn
+    Half,
+    /// Three quarters of a turn
+    ThreeQuarters,
+}
+
+impl Rotation {
+    /// The value of the `rotate` parameter selecting this rotation, in degrees
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Quarter => "90",
+            Self::Half => "180",
+            Self::ThreeQuarters => "270",
+        }
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "90" => Ok(Self::Quarter),
+            "180" => Ok(Self::Half),
+            "270" => Ok(Self::ThreeQuarters),
+            _ => Err(anyhow!("Unknown rotation, expected 90, 180, or 270: {s}")),
+        }
+    }
+}
+
+/// Which way an image is mirrored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flip {
+    /// Left to right
+    Horizontal,
+    /// Top to bottom
+    Vertical,
+}
+
+impl Flip {
+    /// The value of the `flip` parameter selecting this flip
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Horizontal => "h",
+            Self::Vertical => "v",
+        }
+    }
+}
+
+impl FromStr for Flip {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "h" | "horizontal" => Ok(Self::Horizontal),
+            "v" | "vertical" => Ok(Self::Vertical),
+            _ => Err(anyhow!("Unknown flip, expected h or v: {s}")),
+        }
+    }
+}
+
+/// How strongly `?filter=blur` blurs an image, unless it's given as `blur:{sigma}`
+pub const DEFAULT_BLUR: u8 = 5;
+/// The strongest blur `?filter=blur:{sigma}` may apply
+pub const MAX_BLUR: u8 = 50;
+
+/// A filter applied to the colors of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Shades of gray
+    Grayscale,
+    /// A gaussian blur, with the given standard deviation in pixels
+    Blur(u8),
+    /// The brownish tones of an old photograph
+    Sepia,
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Grayscale => write!(f, "grayscale"),
+            Self::Blur(sigma) => write!(f, "blur:{sigma}"),
+            Self::Sepia => write!(f, "sepia"),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, argument) = s
+            .split_once(':')
+            .map_or((s, None), |(name, argument)| (name, Some(argument)));
+        match (name.to_lowercase().as_str(), argument) {
+            ("grayscale" | "greyscale", None) => Ok(Self::Grayscale),
+            ("sepia", None) => Ok(Self::Sepia),
+            ("blur", None) => Ok(Self::Blur(DEFAULT_BLUR)),
+            ("blur", Some(sigma)) => match sigma.parse() {
+                Ok(sigma @ 1..=MAX_BLUR) => Ok(Self::Blur(sigma)),
+                _ => Err(anyhow!(
+                    "Invalid blur, expected a whole number from 1 to {MAX_BLUR}: {sigma}"
+                )),
+            },
+            _ => Err(anyhow!(
+                "Unknown filter, expected grayscale, blur, blur:{{sigma}}, or sepia: {s}"
+            )),
+        }
+    }
+}
+
+/// The synthetic key the variant of the image with the given key and content hash is cached under
+///
+/// The hash is part of the key, so an image that changed when its source was refreshed isn't served
+/// with the variants of what it was before.
+#[must_use]
+pub fn variant_key(key: &CacheKey, hash: &str, transforms: &TransformQuery) -> CacheKey {
+    CacheKey::Generated(format!("{key}#{hash}?{}", transforms.query_string()))
+}
+
+/// Transform an image, encoding the result in the same format if it can be, and as a PNG otherwise
+///
+/// The image is rotated, flipped, and filtered, and then captioned, so the caption is always upright and legible.
+/// JPEGs are encoded at the quality of the transforms, if it's set.
+/// Only the first frame of animated images is kept.
+///
+/// # Errors
+///
+/// Returns an error if the image can't be decoded or encoded, or the server was built without
+/// the `transforms` feature.
+#[cfg_attr(not(feature = "transforms"), allow(clippy::missing_const_for_fn))]
+pub fn apply(image: &CacheValue, transforms: &TransformQuery) -> Result<CacheValue> {
+    #[cfg(feature = "transforms")]
+    {
+        let format = image::ImageFormat::from_mime_type(&image.content_type)
+            .or_else(|| image::guess_format(&image.data).ok())
+            .ok_or_else(|| anyhow!("Unknown image format: {}", image.content_type))?;
+        let mut decoded = image::load_from_memory_with_format(&image.data, format)?;
+        decoded = match transforms.rotate {
+            Some(Rotation::Quarter) => decoded.rotate90(),
+            Some(Rotation::Half) => decoded.rotate180(),
+            Some(Rotation::ThreeQuarters) => decoded.rotate270(),
+            None => decoded,
+        };
+        decoded = match transforms.flip {
+            Some(Flip::Horizontal) => decoded.fliph(),
+            Some(Flip::Vertical) => decoded.flipv(),
+            None => decoded,
+        };
+        decoded = match transforms.filter {
+            Some(Filter::Grayscale) => decoded.grayscale(),
+            Some(Filter::Blur(sigma)) => decoded.fast_blur(f32::from(sigma)),
+            Some(Filter::Sepia) => sepia(decoded),
+            None => decoded,
+        };
+        if let Some(caption) = transforms.caption.as_deref().filter(|c| !c.is_empty()) {
+            decoded = draw_caption(decoded, caption);
+        }
+        encode(decoded, format, transforms.quality)
+    }
+
+    #[cfg(not(feature = "transforms"))]
+    {
+        let _ = (image, transforms);
+        Err(anyhow!(
+            "Transforming images requires the server to be built with the `transforms` feature"
+        ))
+    }
+}
+
+/// The quality JPEGs rotated upright by [`auto_orient`] are re-encoded at, high enough not to lose visible detail
+pub const AUTO_ORIENT_QUALITY: u8 = 92;
+
+/// Rotate a JPEG upright according to its EXIF orientation, re-encoding it without the orientation tag
+///
+/// Returns `None` for other images, and JPEGs that are already upright.
+///
+/// # Errors
+///
+/// Returns an error if the image can't be decoded or encoded, or the server was built without
+/// the `transforms` feature.
+#[cfg_attr(not(feature = "transforms"), allow(clippy::missing_const_for_fn))]
+pub fn auto_orient(image: &CacheValue) -> Result<Option<CacheValue>> {
+    #[cfg(feature = "transforms")]
+    {
+        use image::{ImageDecoder, metadata::Orientation};
+
+        if image.content_type != "image/jpeg" {
+            return Ok(None);
+        }
+        let mut decoder = image::codecs::jpeg::JpegDecoder::new(std::io::Cursor::new(&image.data))?;
+        let orientation = decoder.orientation()?;
+        if orientation == Orientation::NoTransforms {
+            return Ok(None);
+        }
+        let mut decoded = image::DynamicImage::from_decoder(decoder)?;
+        decoded.apply_orientation(orientation);
+        Ok(Some(CacheValue {
+            validators: image.validators.clone(),
+            ..encode(decoded, image::ImageFormat::Jpeg, Some(AUTO_ORIENT_QUALITY))?
+        }))
+    }
+
+    #[cfg(not(feature = "transforms"))]
+    {
+        let _ = image;
+        Err(anyhow!(
+            "Orienting images requires the server to be built with the `transforms` feature"
+        ))
+    }
+}
+
+/// Encode an image in the given format, or as a PNG if it can't be written in it,
+/// at the given quality if it's a JPEG
+#[cfg(feature = "transforms")]
+fn encode(
+    image: image::DynamicImage,
+    format: image::ImageFormat,
+    quality: Option<u8>,
+) -> Result<CacheValue> {
+    use image::{DynamicImage, ImageFormat, codecs::jpeg::JpegEncoder};
+
+    let format = if format.writing_enabled() {
+        format
+    } else {
+        ImageFormat::Png
+    };
+    let mut data = Vec::new();
+    match (format, quality) {
+        // JPEGs have no alpha channel
+        (ImageFormat::Jpeg, Some(quality)) => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut data, quality))?,
+        (ImageFormat::Jpeg, None) => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_to(&mut std::io::Cursor::new(&mut data), format)?,
+        _ => image.write_to(&mut std::io::Cursor::new(&mut data), format)?,
+    }
+    Ok(CacheValue {
+        data,
+        content_type: format.to_mime_type().to_string(),
+        validators: crate::cache::Validators::default(),
+    })
+}
+
+/// Tint an image in the tones of an old photograph
+#[cfg(feature = "transforms")]
+fn sepia(image: image::DynamicImage) -> image::DynamicImage {
+    let mut pixels = image.into_rgba8();
+    for pixel in pixels.pixels_mut() {
+        let [r, g, b, _] = pixel.0.map(f32::from);
+        let tone = |red: f32, green: f32, blue: f32| {
+            // truncation is intended, the tone is clamped to the range of a channel
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let tone = red.mul_add(r, green.mul_add(g, blue * b)).min(255.0) as u8;
+            tone
+        };
+        pixel.0[..3].copy_from_slice(&[
+            tone(0.393, 0.769, 0.189),
+            tone(0.349, 0.686, 0.168),
+            tone(0.272, 0.534, 0.131),
+        ]);
+    }
+    image::DynamicImage::ImageRgba8(pixels)
+}
+
+/// Draw a caption in white across the bottom of an image, over a translucent black banner,
+/// wrapped into as many lines as fit in half of the image
+#[cfg(feature = "transforms")]
+fn draw_caption(image: image::DynamicImage, caption: &str) -> image::DynamicImage {
+    use crate::font::{self, Canvas};
+
+    let mut pixels = image.into_rgba8();
+    let (width, height) = (pixels.width() as usize, pixels.height() as usize);
+    // the text is about a twentieth of the height of the image
+    let scale = (height / 20 / font::GLYPH_HEIGHT).max(1);
+    let padding = 2 * scale;
+    let line_height = (font::GLYPH_HEIGHT + 2) * scale;
+    let max_chars = font::max_chars(width.saturating_sub(2 * padding) / scale);
+    let max_lines = (height / 2).saturating_sub(2 * padding) / line_height;
+    if max_chars == 0 || max_lines == 0 {
+        return image::DynamicImage::ImageRgba8(pixels);
+    }
+    let mut lines = wrap(caption, max_chars);
+    lines.truncate(max_lines);
+
+    let banner_height = lines.len() * line_height + 2 * scale;
+    let banner_top = height.saturating_sub(banner_height);
+    let mut canvas = Canvas {
+        pixels: &mut pixels,
+        width,
+        channels: 4,
+    };
+    canvas.blend((0, banner_top), (width, banner_height), [0, 0, 0], 0x99);
+    for (index, line) in lines.iter().enumerate() {
+        let left = width.saturating_sub(font::text_width(line) * scale) / 2;
+        let top = banner_top + padding + index * line_height;
+        canvas.draw_text(line, (left, top), scale, [0xff; 3]);
+    }
+    image::DynamicImage::ImageRgba8(pixels)
+}
+
+/// Break text into lines of at most `max_chars` characters, between words where possible
+#[cfg(feature = "transforms")]
+fn wrap(text: &str, max_chars: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_chars = 0;
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        // words too long for a line of their own are broken up
+        while word.len() > max_chars {
+            if line_chars > 0 {
+                lines.push(std::mem::take(&mut line));
+                line_chars = 0;
+            }
+            lines.push(word.drain(..max_chars).collect());
+        }
+        if word.is_empty() {
+            continue;
+        }
+        if line_chars > 0 && line_chars + 1 + word.len() > max_chars {
+            lines.push(std::mem::take(&mut line));
+            line_chars = 0;
+        }
+        if line_chars > 0 {
+            line.push(' ');
+            line_chars += 1;
+        }
+        line_chars += word.len();
+        line.extend(word);
+    }
+    if line_chars > 0 {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    #[cfg(feature = "transforms")]
+    use rstest::rstest;
+
+    fn caption(caption: &str) -> TransformQuery {
+        TransformQuery {
+            caption: Some(caption.to_string()),
+            ..TransformQuery::default()
+        }
+    }
+
+    #[test]
+    fn test_variant_key() {
+        let key = CacheKey::ImagePath("/images/cat.jpg".into());
+        assert_eq!(
+            variant_key(&key, "abc", &caption("Hello, world!")),
+            CacheKey::Generated("/images/cat.jpg#abc?caption=Hello%2C+world%21".to_string())
+        );
+        assert_ne!(
+            variant_key(&key, "abc", &caption("Hello")),
+            variant_key(
+                &key,
+                "abc",
+                &TransformQuery {
+                    rotate: Some(Rotation::Half),
+                    ..caption("Hello")
+                }
+            )
+        );
+        assert_ne!(
+            variant_key(&key, "abc", &caption("Hello")),
+            variant_key(&key, "def", &caption("Hello"))
+        );
+    }
+
+    #[test]
+    fn test_cache_config() {
+        let cache = TransformsConfig::default().cache_config();
+        assert_eq!(cache.backend, CacheBackendType::InMemory);
+        assert_eq!(cache.max_bytes, Some(DEFAULT_CACHE_BYTES));
+        assert_eq!(cache.eviction, EvictionPolicy::EvictOldest);
+    }
+
+    #[cfg(not(feature = "transforms"))]
+    #[test]
+    fn test_apply_without_feature() {
+        let image = crate::read_image_from_path(&"assets/blank.jpg".into()).unwrap();
+        assert!(apply(&image, &caption("Hello")).is_err());
+    }
+
+    #[cfg(feature = "transforms")]
+    #[rstest]
+    #[case::fits("one two three", 20, vec!["one two three"])]
+    #[case::words("one two three", 7, vec!["one two", "three"])]
+    #[case::exact("one two", 7, vec!["one two"])]
+    #[case::long_word("a abcdefghij b", 4, vec!["a", "abcd", "efgh", "ij b"])]
+    #[case::whitespace("  one \n two  ", 20, vec!["one two"])]
+    #[case::empty("", 20, vec![])]
+    fn test_wrap(#[case] text: &str, #[case] max_chars: usize, #[case] expected: Vec<&str>) {
+        assert_eq!(wrap(text, max_chars), expected);
+    }
+
+    #[cfg(feature = "transforms")]
+    #[rstest]
+    #[case::jpeg("image/jpeg", image::ImageFormat::Jpeg)]
+    #[case::png("image/png", image::ImageFormat::Png)]
+    fn test_apply_caption(#[case] content_type: &str, #[case] format: image::ImageFormat) {
+        let mut original = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            200,
+            100,
+            image::Rgb([0xff, 0xff, 0xff]),
+        ))
+        .write_to(&mut std::io::Cursor::new(&mut original), format)
+        .unwrap();
+        let original = CacheValue {
+            data: original,
+            content_type: content_type.to_string(),
+            validators: crate::cache::Validators::default(),
+        };
+
+        let captioned = apply(&original, &caption("Hello")).unwrap();
+        assert_eq!(captioned.content_type, content_type);
+        let decoded = image::load_from_memory(&captioned.data)
+            .unwrap()
+            .into_rgb8();
+        assert_eq!(decoded.dimensions(), (200, 100));
+        // the top of the image is untouched, and the bottom is darkened by the banner
+        assert!(
+            decoded
+                .get_pixel(100, 10)
+                .0
+                .iter()
+                .all(|channel| *channel > 0xf0)
+        );
+        assert!(
+            decoded
+                .get_pixel(0, 99)
+                .0
+                .iter()
+                .all(|channel| *channel < 0x80)
+        );
+
+        // without a caption, the image is re-encoded as it is
+        let plain = apply(&original, &caption("")).unwrap();
+        let decoded = image::load_from_memory(&plain.data).unwrap().into_rgb8();
+        assert!(
+            decoded
+                .get_pixel(0, 99)
+                .0
+                .iter()
+                .all(|channel| *channel > 0xf0)
+        );
+    }
+
+    #[cfg(feature = "transforms")]
+    #[rstest]
+    #[case::none(None, None, (4, 2), (0, 0))]
+    #[case::quarter(Some(Rotation::Quarter), None, (2, 4), (1, 0))]
+    #[case::half(Some(Rotation::Half), None, (4, 2), (3, 1))]
+    #[case::three_quarters(Some(Rotation::ThreeQuarters), None, (2, 4), (0, 3))]
+    #[case::flip_h(None, Some(Flip::Horizontal), (4, 2), (3, 0))]
+    #[case::flip_v(None, Some(Flip::Vertical), (4, 2), (0, 1))]
+    #[case::rotate_then_flip(Some(Rotation::Quarter), Some(Flip::Vertical), (2, 4), (1, 3))]
+    fn test_apply_orientation(
+        #[case] rotate: Option<Rotation>,
+        #[case] flip: Option<Flip>,
+        #[case] dimensions: (u32, u32),
+        #[case] marked: (u32, u32),
+    ) {
+        // a 4x2 image with its top left pixel marked
+        let mut pixels = image::RgbImage::from_pixel(4, 2, image::Rgb([0, 0, 0]));
+        pixels.put_pixel(0, 0, image::Rgb([0xff, 0xff, 0xff]));
+        let mut original = Vec::new();
+        image::DynamicImage::ImageRgb8(pixels)
+            .write_to(
+                &mut std::io::Cursor::new(&mut original),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+        let original = CacheValue {
+            data: original,
+            content_type: "image/png".to_string(),
+            validators: crate::cache::Validators::default(),
+        };
+
+        let transformed = apply(
+            &original,
+            &TransformQuery {
+                rotate,
+                flip,
+                ..TransformQuery::default()
+            },
+        )
+        .unwrap();
+        let decoded = image::load_from_memory(&transformed.data)
+            .unwrap()
+            .into_rgb8();
+        assert_eq!(decoded.dimensions(), dimensions);
+        let marked_pixels: Vec<_> = decoded
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel.0 == [0xff; 3])
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert_eq!(marked_pixels, vec![marked]);
+    }
+
+    #[test]
+    fn test_filter_parse() {
+        for filter in [Filter::Grayscale, Filter::Blur(12), Filter::Sepia] {
+            assert_eq!(filter.to_string().parse::<Filter>().unwrap(), filter);
+        }
+        assert_eq!(
+            "blur".parse::<Filter>().unwrap(),
+            Filter::Blur(DEFAULT_BLUR)
+        );
+        assert_eq!("GreyScale".parse::<Filter>().unwrap(), Filter::Grayscale);
+        for invalid in [
+            "blur:0", "blur:51", "blur:2.5", "blur:", "sepia:3", "invert", "",
+        ] {
+            assert!(invalid.parse::<Filter>().is_err(), "{invalid}");
+        }
+    }
+
+    #[cfg(feature = "transforms")]
+    #[rstest]
+    #[case::grayscale(Filter::Grayscale, [94, 94, 94])]
+    #[case::sepia(Filter::Sepia, [127, 113, 88])]
+    fn test_apply_color_filter(#[case] filter: Filter, #[case] expected: [u8; 3]) {
+        let mut original = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            4,
+            4,
+            image::Rgb([0x33, 0x66, 0x99]),
+        ))
+        .write_to(
+            &mut std::io::Cursor::new(&mut original),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+        let original = CacheValue {
+            data: original,
+            content_type: "image/png".to_string(),
+            validators: crate::cache::Validators::default(),
+        };
+
+        let filtered = apply(
+            &original,
+            &TransformQuery {
+                filter: Some(filter),
+                ..TransformQuery::default()
+            },
+        )
+        .unwrap();
+        let decoded = image::load_from_memory(&filtered.data).unwrap().into_rgb8();
+        assert_eq!(decoded.dimensions(), (4, 4));
+        assert_eq!(decoded.get_pixel(2, 2).0, expected);
+    }
+
+    #[cfg(feature = "transforms")]
+    #[test]
+    fn test_apply_blur() {
+        // a black image with a white stripe down its middle, which blurring spreads out
+        let pixels = image::RgbImage::from_fn(21, 5, |x, _| {
+            image::Rgb(if x == 10 { [0xff; 3] } else { [0; 3] })
+        });
+        let mut original = Vec::new();
+        image::DynamicImage::ImageRgb8(pixels)
+            .write_to(
+                &mut std::io::Cursor::new(&mut original),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+        let original = CacheValue {
+            data: original,
+            content_type: "image/png".to_string(),
+            validators: crate::cache::Validators::default(),
+        };
+
+        let blurred = apply(
+            &original,
+            &TransformQuery {
+                filter: Some(Filter::Blur(2)),
+                ..TransformQuery::default()
+            },
+        )
+        .unwrap();
+        let decoded = image::load_from_memory(&blurred.data).unwrap().into_rgb8();
+        let stripe = decoded.get_pixel(10, 2).0[0];
+        let beside = decoded.get_pixel(11, 2).0[0];
+        assert!(stripe < 0xff, "{stripe}");
+        assert!(beside > 0, "{beside}");
+        assert_eq!(decoded.get_pixel(0, 2).0[0], 0);
+    }
+
+    #[test]
+    fn test_orientation_roundtrip() {
+        for rotation in [Rotation::Quarter, Rotation::Half, Rotation::ThreeQuarters] {
+            assert_eq!(rotation.as_str().parse::<Rotation>().unwrap(), rotation);
+        }
+        for flip in [Flip::Horizontal, Flip::Vertical] {
+            assert_eq!(flip.as_str().parse::<Flip>().unwrap(), flip);
+        }
+        assert_eq!("vertical".parse::<Flip>().unwrap(), Flip::Vertical);
+        assert!("0".parse::<Rotation>().is_err());
+    }
+
+    #[cfg(feature = "transforms")]
+    #[test]
+    fn test_apply_quality() {
+        // noise, so that the quality makes a difference to the size
+        let mut seed = 1_u32;
+        let pixels = image::RgbImage::from_fn(64, 64, |_, _| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            image::Rgb(seed.to_be_bytes()[..3].try_into().unwrap())
+        });
+        let mut original = Vec::new();
+        image::DynamicImage::ImageRgb8(pixels)
+            .write_to(
+                &mut std::io::Cursor::new(&mut original),
+                image::ImageFormat::Jpeg,
+            )
+            .unwrap();
+        let original = CacheValue {
+            data: original,
+            content_type: "image/jpeg".to_string(),
+            validators: crate::cache::Validators::default(),
+        };
+
+        let quality = |quality| {
+            apply(
+                &original,
+                &TransformQuery {
+                    quality: Some(quality),
+                    ..TransformQuery::default()
+                },
+            )
+            .unwrap()
+        };
+        let (low, high) = (quality(10), quality(95));
+        assert_eq!(low.content_type, "image/jpeg");
+        assert!(low.data.len() < high.data.len());
+        assert_eq!(
+            image::load_from_memory(&low.data)
+                .unwrap()
+                .into_rgb8()
+                .dimensions(),
+            (64, 64)
+        );
+    }
+
+    /// A 40x20 JPEG, black with a white left half, tagged with the given EXIF orientation
+    #[cfg(feature = "transforms")]
+    fn oriented_jpeg(orientation: Option<u16>) -> CacheValue {
+        let pixels = image::RgbImage::from_fn(40, 20, |x, _| {
+            image::Rgb(if x < 20 { [0xff; 3] } else { [0; 3] })
+        });
+        let mut data = Vec::new();
+        pixels
+            .write_to(
+                &mut std::io::Cursor::new(&mut data),
+                image::ImageFormat::Jpeg,
+            )
+            .unwrap();
+        if let Some(orientation) = orientation {
+            // an APP1 segment, right after the start of image marker, with a single IFD entry
+            let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+            exif.extend(orientation.to_be_bytes());
+            exif.extend([0; 6]);
+            let mut segment = vec![0xff, 0xe1];
+            segment.extend(u16::try_from(exif.len() + 2).unwrap().to_be_bytes());
+            segment.extend(exif);
+            data.splice(2..2, segment);
+        }
+        CacheValue {
+            data,
+            content_type: "image/jpeg".to_string(),
+            validators: crate::cache::Validators::default(),
+        }
+    }
+
+    #[cfg(feature = "transforms")]
+    #[rstest]
+    // rotated a quarter turn clockwise, so the white half ends up on top
+    #[case::rotate_90(6, (20, 40), (10, 5), (10, 35))]
+    // rotated a quarter turn counter-clockwise, so the white half ends up at the bottom
+    #[case::rotate_270(8, (20, 40), (10, 35), (10, 5))]
+    // upside down, so the white half ends up on the right
+    #[case::rotate_180(3, (40, 20), (35, 10), (5, 10))]
+    fn test_auto_orient(
+        #[case] orientation: u16,
+        #[case] dimensions: (u32, u32),
+        #[case] white: (u32, u32),
+        #[case] black: (u32, u32),
+    ) {
+        let oriented = auto_orient(&oriented_jpeg(Some(orientation)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(oriented.content_type, "image/jpeg");
+        let decoded = image::load_from_memory(&oriented.data).unwrap().into_rgb8();
+        assert_eq!(decoded.dimensions(), dimensions);
+        assert!(decoded.get_pixel(white.0, white.1).0[0] > 0xf0);
+        assert!(decoded.get_pixel(black.0, black.1).0[0] < 0x10);
+        // the orientation is applied, so it's not applied again
+        assert_eq!(auto_orient(&oriented).unwrap(), None);
+    }
+
+    #[cfg(feature = "transforms")]
+    #[test]
+    fn test_auto_orient_upright() {
+        assert_eq!(auto_orient(&oriented_jpeg(None)).unwrap(), None);
+        assert_eq!(auto_orient(&oriented_jpeg(Some(1))).unwrap(), None);
+        let png = CacheValue {
+            content_type: "image/png".to_string(),
+            ..oriented_jpeg(Some(6))
+        };
+        assert_eq!(auto_orient(&png).unwrap(), None);
+    }
+
+    #[cfg(feature = "transforms")]
+    #[test]
+    fn test_apply_unknown_format() {
+        let image = CacheValue {
+            data: b"<svg></svg>".to_vec(),
+            content_type: "image/svg+xml".to_string(),
+            validators: crate::cache::Validators::default(),
+        };
+        assert!(apply(&image, &caption("Hello")).is_err());
+    }
+}
@@ -0,0 +1,351 @@
+//! Dry-run validation of a configuration, without starting the server.
+
+#[cfg(feature = "remote-sources")]
+use std::collections::BTreeMap;
+use std::{
+    fmt,
+    io::{Read, Seek},
+    ops::ControlFlow,
+    path::Path,
+};
+
+use anyhow::{Result, anyhow};
+#[cfg(feature = "remote-sources")]
+use url::Url;
+
+use crate::{
+    archive,
+    config::{Config, ImageSource, ServerConfig, SourceConfig},
+    file_limits_violation,
+    filter::FileFilter,
+    has_allowed_extension, image_files_in_directory, image_limits_violation,
+};
+#[cfg(feature = "remote-sources")]
+use crate::{feed::fetch_feed, has_allowed_content_type, manifest::fetch_manifest};
+
+/// The result of validating a single image source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceReport {
+    /// The source, as a URL or path
+    pub source: String,
+    /// The number of images that would be served from the source
+    pub images: usize,
+    /// Why the source would not serve any images
+    pub error: Option<String>,
+    /// Whether the source would be skipped, because it is remote and offline mode is enabled
+    pub skipped: bool,
+}
+
+/// The result of validating a configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Problems with the configuration that would prevent the server from starting
+    pub errors: Vec<String>,
+    /// The result of validating each configured source
+    pub sources: Vec<SourceReport>,
+}
+
+impl ValidationReport {
+    /// The total number of images that would be served
+    #[must_use]
+    pub fn images(&self) -> usize {
+        self.sources.iter().map(|source| source.images).sum()
+    }
+
+    /// Whether the configuration is free of fatal problems
+    ///
+    /// A configuration is invalid if the server would fail to start, or any source would not serve any images.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+            && self.images() > 0
+            && self.sources.iter().all(|source| source.error.is_none())
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for error in &self.errors {
+            writeln!(f, "error: {error}")?;
+        }
+        for source in &self.sources {
+            match &source.error {
+                None if source.skipped => writeln!(f, "SKIP {}: offline mode", source.source)?,
+                Some(error) => writeln!(f, "FAIL {}: {error}", source.source)?,
+                None => writeln!(f, "OK   {}: {} image(s)", source.source, source.images)?,
+            }
+        }
+        write!(
+            f,
+            "{} image(s) would be served from {} source(s): {}",
+            self.images(),
+            self.sources.len(),
+            if self.is_valid() { "valid" } else { "invalid" }
+        )
+    }
+}
+
+/// Validate a configuration, resolving every source without loading any images
+///
+/// Paths are checked for supported image files, and URLs are checked with a `HEAD` request.
+/// Manifests and feeds are fetched, and the images they list are counted without being checked.
+/// In offline mode, remote sources are skipped without being checked.
+pub async fn validate_config(config: &Config) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    if let Err(e) = config.socket_addr() {
+        report.errors.push(format!(
+            "Invalid listen address {}:{}: {e}",
+            config.server.host, config.server.port
+        ));
+    }
+    if let (Some(min), Some(max)) = (config.server.min_file_size, config.server.max_file_size)
+        && min > max
+    {
+        report.errors.push(format!(
+            "min_file_size ({min}) is greater than max_file_size ({max}), no images would be loaded"
+        ));
+    }
+    if let Err(e) = config.server.check_offline() {
+        report.errors.push(e.to_string());
+    }
+    if let Some(basic) = &config.server.auth.basic
+        && let Err(e) = crate::auth::validate_password_hash(&basic.password_hash)
+    {
+        report
+            .errors
+            .push(format!("Invalid server.auth.basic.password_hash: {e}"));
+    }
+    if config.signing.required && config.signing.secret.is_none() {
+        report.errors.push(
+            "signing.required is set without a signing.secret, no images would be served"
+                .to_string(),
+        );
+    }
+    if config.server.sources.is_empty() {
+        report
+            .errors
+            .push("No image sources configured".to_string());
+    }
+
+    let filter = config.server.file_filter().unwrap_or_else(|e| {
+        report.errors.push(e.to_string());
+        FileFilter::default()
+    });
+
+    #[cfg(feature = "remote-sources")]
+    let client = config.http.build_client().unwrap_or_else(|e| {
+        report.errors.push(e.to_string());
+        reqwest::Client::default()
+    });
+
+    let collection_sources = config.collections.iter().flat_map(|(name, collection)| {
+        collection
+            .sources
+            .iter()
+            .map(move |source| (format!("{name}: {source}"), source))
+    });
+    // collected, so the future stays `Send` across the awaits below
+    let sources: Vec<(String, &SourceConfig)> = config
+        .server
+        .sources
+        .iter()
+        .map(|source| (source.to_string(), source))
+        .chain(collection_sources)
+        .collect();
+    for (name, source) in sources {
+        if config.server.offline && source.location.is_remote() {
+            report.sources.push(SourceReport {
+                source: name,
+                images: 0,
+                error: None,
+                skipped: true,
+            });
+            continue;
+        }
+        let images = if source.location.is_remote() {
+            #[cfg(feature = "remote-sources")]
+            let images = count_remote_images(&client, source, config, &filter).await;
+            #[cfg(not(feature = "remote-sources"))]
+            let images = Err(anyhow!(crate::REMOTE_SOURCES_DISABLED));
+            images
+        } else {
+            count_local_images(source, &config.server, &filter)
+        };
+        let (images, error) = match images {
+            Ok(images) => (images, None),
+            Err(e) => (0, Some(e.to_string())),
+        };
+        report.sources.push(SourceReport {
+            source: name,
+            images,
+            error,
+            skipped: false,
+        });
+    }
+
+    report
+}
+
+/// Count the images that would be served from a remote source
+#[cfg(feature = "remote-sources")]
+async fn count_remote_images(
+    client: &reqwest::Client,
+    source: &SourceConfig,
+    config: &Config,
+    filter: &FileFilter,
+) -> Result<usize> {
+    let server_config = &config.server;
+    let allowed_extensions = &server_config.allowed_extensions;
+    match &source.location {
+        ImageSource::Url(url) if source.location.archive_format().is_some() => {
+            let data = archive::fetch_archive(
+                client,
+                url,
+                &source.request_headers(),
+                config.http.max_archive_size,
+            )
+            .await?;
+            count_archive_images(std::io::Cursor::new(data), source, server_config, filter)
+        }
+        ImageSource::Url(url) => {
+            check_url(client, url, &source.request_headers(), allowed_extensions)
+                .await
+                .map(|()| 1)
+        }
+        ImageSource::Feed(feed) => fetch_feed(client, feed, &source.request_headers())
+            .await
+            .and_then(
+                |urls| match urls.len().min(source.max_images.unwrap_or(usize::MAX)) {
+                    0 => Err(anyhow!("No images found in feed")),
+                    images => Ok(images),
+                },
+            ),
+        ImageSource::Manifest(manifest) => {
+            fetch_manifest(client, manifest, &source.request_headers())
+                .await
+                .and_then(
+                    |urls| match urls.len().min(source.max_images.unwrap_or(usize::MAX)) {
+                        0 => Err(anyhow!("No images listed in manifest")),
+                        images => Ok(images),
+                    },
+                )
+        }
+        ImageSource::Path(_) => count_local_images(source, server_config, filter),
+    }
+}
+
+/// Count the images that would be served from a local source
+fn count_local_images(
+    source: &SourceConfig,
+    server_config: &ServerConfig,
+    filter: &FileFilter,
+) -> Result<usize> {
+    let allowed_extensions = &server_config.allowed_extensions;
+    let within_limits = |path: &Path| file_limits_violation(server_config, source, path).is_none();
+    match &source.location {
+        ImageSource::Path(path) if source.location.archive_format().is_some() => {
+            let file =
+                std::fs::File::open(path).map_err(|e| anyhow!("Failed to open archive: {e}"))?;
+            count_archive_images(std::io::BufReader::new(file), source, server_config, filter)
+        }
+        ImageSource::Path(path) if path.is_file() => {
+            if !has_allowed_extension(path, allowed_extensions) {
+                Err(anyhow!("Unsupported image file extension"))
+            } else if let Some(reason) = file_limits_violation(server_config, source, path) {
+                Err(anyhow!("Image {reason}"))
+            } else {
+                Ok(1)
+            }
+        }
+        ImageSource::Path(path) if path.is_dir() => {
+            match image_files_in_directory(path, source, filter, allowed_extensions)
+                .filter(|path| within_limits(path))
+                .take(source.max_images.unwrap_or(usize::MAX))
+                .count()
+            {
+                0 => Err(anyhow!("No images found in directory")),
+                images => Ok(images),
+            }
+        }
+        ImageSource::Path(_) => Err(anyhow!(
+            "Path does not exist, or is not a file or directory"
+        )),
+        ImageSource::Url(_) | ImageSource::Manifest(_) | ImageSource::Feed(_) => {
+            Err(anyhow!("Not a local source"))
+        }
+    }
+}
+
+/// Count the images in an archive source that would be loaded
+fn count_archive_images(
+    reader: impl Read + Seek,
+    source: &SourceConfig,
+    server_config: &ServerConfig,
+    filter: &FileFilter,
+) -> Result<usize> {
+    let Some(format) = source.location.archive_format() else {
+        return Err(anyhow!("Not an archive"));
+    };
+    let max_images = source.max_images.unwrap_or(usize::MAX);
+    let mut images = 0;
+    archive::for_each_image(
+        reader,
+        format,
+        filter,
+        &server_config.allowed_extensions,
+        |path, size, reader| {
+            if let Ok(image) = archive::read_image(path, size, server_config.max_file_size, reader)
+                && image_limits_violation(server_config, source, &image.data).is_none()
+            {
+                images += 1;
+            }
+            if images >= max_images {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        },
+    )?;
+    match images {
+        0 => Err(anyhow!("No images found in archive")),
+        images => Ok(images),
+    }
+}
+
+/// Check that a URL serves an image with one of the allowed extensions, using a `HEAD` request with the given headers
+///
+/// # Errors
+///
+/// Returns an error if the request fails, or the response is not a supported image.
+#[cfg(feature = "remote-sources")]
+pub async fn check_url(
+    client: &reqwest::Client,
+    url: &Url,
+    headers: &BTreeMap<String, String>,
+    allowed_extensions: &[impl AsRef<str> + Sync],
+) -> Result<()> {
+    let response = headers
+        .iter()
+        .fold(client.head(url.as_str()), |request, (name, value)| {
+            request.header(name, value)
+        })
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to reach URL: {e}"))?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!("Unexpected status: {}", response.status()));
+    }
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if !has_allowed_content_type(content_type, allowed_extensions) {
+        return Err(anyhow!("Unsupported image content type: {content_type}"));
+    }
+
+    Ok(())
+}
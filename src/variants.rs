@@ -0,0 +1,280 @@
+//! Pre-encoded WebP and AVIF variants of JPEG and PNG images, enabled by the `variants` feature.
+//!
+//! Variants are encoded as images are loaded, and stored in the variant cache under synthetic keys derived
+//! from the original image and its format, next to the transformed variants of images. They're served
+//! instead of the original to clients whose `Accept` header lists their format.
+//! Without the feature, images are always served in the format they were loaded in.
+
+use anyhow::Result;
+#[cfg(not(feature = "variants"))]
+use anyhow::anyhow;
+use hyper::HeaderMap;
+
+use crate::{
+    cache::{CacheKey, CacheValue},
+    config::VariantFormat,
+};
+
+/// The quality, from 1 to 100, AVIF variants are encoded at
+pub const AVIF_QUALITY: u8 = 70;
+
+/// The speed, from 1 (slowest, smallest) to 10 (fastest), AVIF variants are encoded at
+pub const AVIF_SPEED: u8 = 6;
+
+impl VariantFormat {
+    /// The media type of images in the format
+    #[must_use]
+    pub const fn mime_type(self) -> &'static str {
+        match self {
+            Self::Avif => "image/avif",
+            Self::Webp => "image/webp",
+        }
+    }
+
+    /// The usual file extension of images in the format
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Avif => "avif",
+            Self::Webp => "webp",
+        }
+    }
+}
+
+/// The synthetic key the variant of the image with the given key and content hash is cached under in the given format
+///
+/// The hash is part of the key, so an image that changed when its source was refreshed isn't served
+/// with the variants of what it was before.
+#[must_use]
+pub fn variant_key(key: &CacheKey, hash: &str, format: VariantFormat) -> CacheKey {
+    CacheKey::Generated(format!("{key}#{hash}.{}", format.extension()))
+}
+
+/// The format of the given ones a request with the given headers accepts best, if it accepts any
+///
+/// Only media types listed explicitly count, since clients sending `image/*` or `*/*` may not decode
+/// newer formats. Formats accepted with the same quality value are chosen in the given order of preference.
+#[must_use]
+pub fn negotiate(headers: &HeaderMap, formats: &[VariantFormat]) -> Option<VariantFormat> {
+    let ranges: Vec<(&str, f32)> = headers
+        .get_all(hyper::header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next().filter(|media_type| !media_type.is_empty())?;
+            let q = params
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            Some((media_type, q))
+        })
+        .collect();
+
+    let mut best: Option<(VariantFormat, f32)> = None;
+    for &format in formats {
+        let q = ranges
+            .iter()
+            .filter(|(media_type, _)| media_type.eq_ignore_ascii_case(format.mime_type()))
+            .map(|&(_, q)| q)
+            .reduce(f32::max);
+        if let Some(q) = q.filter(|&q| q > 0.0)
+            && best.is_none_or(|(_, best_q)| q > best_q)
+        {
+            best = Some((format, q));
+        }
+    }
+    best.map(|(format, _)| format)
+}
+
+/// Encode a JPEG or PNG image in the given format
+///
+/// Returns `None` for images in other formats, animated PNGs, and images the variant wouldn't be smaller than,
+/// which are better served as they are.
+///
+/// # Errors
+///
+/// Returns an error if the image can't be decoded or encoded, or the server was built without
+/// the `variants` feature.
+#[cfg_attr(not(feature = "variants"), allow(clippy::missing_const_for_fn))]
+pub fn encode(image: &CacheValue, format: VariantFormat) -> Result<Option<CacheValue>> {
+    #[cfg(feature = "variants")]
+    {
+        use image::{
+            DynamicImage,
+            codecs::{avif::AvifEncoder, png::PngDecoder, webp::WebPEncoder},
+        };
+
+        let decoded = match image.content_type.as_str() {
+            "image/jpeg" => {
+                image::load_from_memory_with_format(&image.data, image::ImageFormat::Jpeg)?
+            }
+            "image/png" => {
+                let decoder = PngDecoder::new(std::io::Cursor::new(&image.data))?;
+                if decoder.is_apng()? {
+                    return Ok(None);
+                }
+                DynamicImage::from_decoder(decoder)?
+            }
+            _ => return Ok(None),
+        };
+        // the encoders only take 8-bit channels
+        let decoded = if decoded.color().has_alpha() {
+            DynamicImage::ImageRgba8(decoded.into_rgba8())
+        } else {
+            DynamicImage::ImageRgb8(decoded.into_rgb8())
+        };
+
+        let mut data = Vec::new();
+        match format {
+            VariantFormat::Avif => decoded.write_with_encoder(
+                AvifEncoder::new_with_speed_quality(&mut data, AVIF_SPEED, AVIF_QUALITY),
+            )?,
+            VariantFormat::Webp => {
+                decoded.write_with_encoder(WebPEncoder::new_lossless(&mut data))?
+            }
+        }
+        if data.len() >= image.data.len() {
+            return Ok(None);
+        }
+        Ok(Some(CacheValue {
+            data,
+            content_type: format.mime_type().to_string(),
+            validators: crate::cache::Validators::default(),
+        }))
+    }
+
+    #[cfg(not(feature = "variants"))]
+    {
+        let _ = (image, format);
+        Err(anyhow!(
+            "Encoding variants of images requires the server to be built with the `variants` feature"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[test]
+    fn test_variant_key() {
+        let key = CacheKey::ImagePath("/images/cat.jpg".into());
+        assert_eq!(
+            variant_key(&key, "abc", VariantFormat::Avif),
+            CacheKey::Generated("/images/cat.jpg#abc.avif".to_string())
+        );
+        assert_ne!(
+            variant_key(&key, "abc", VariantFormat::Avif),
+            variant_key(&key, "abc", VariantFormat::Webp)
+        );
+        assert_ne!(
+            variant_key(&key, "abc", VariantFormat::Avif),
+            variant_key(&key, "def", VariantFormat::Avif)
+        );
+    }
+
+    #[rstest]
+    #[case::none(&[], None)]
+    #[case::wildcards(&["image/*,*/*;q=0.8"], None)]
+    #[case::browser(&["image/avif,image/webp,image/apng,*/*;q=0.8"], Some(VariantFormat::Avif))]
+    #[case::webp_only(&["image/webp,*/*"], Some(VariantFormat::Webp))]
+    #[case::quality(&["image/avif;q=0.5, image/webp"], Some(VariantFormat::Webp))]
+    #[case::refused(&["image/avif;q=0, image/webp;q=0"], None)]
+    #[case::case_insensitive(&["Image/AVIF"], Some(VariantFormat::Avif))]
+    #[case::several_headers(&["text/html", "image/webp"], Some(VariantFormat::Webp))]
+    fn test_negotiate(#[case] accept: &[&str], #[case] expected: Option<VariantFormat>) {
+        let mut headers = HeaderMap::new();
+        for value in accept {
+            headers.append(hyper::header::ACCEPT, value.parse().unwrap());
+        }
+        assert_eq!(
+            negotiate(&headers, &[VariantFormat::Avif, VariantFormat::Webp]),
+            expected
+        );
+        // formats that aren't configured are never chosen
+        assert_eq!(negotiate(&headers, &[]), None);
+    }
+
+    #[test]
+    fn test_negotiate_preference() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            hyper::header::ACCEPT,
+            "image/avif,image/webp".parse().unwrap(),
+        );
+        assert_eq!(
+            negotiate(&headers, &[VariantFormat::Webp, VariantFormat::Avif]),
+            Some(VariantFormat::Webp)
+        );
+        assert_eq!(
+            negotiate(&headers, &[VariantFormat::Avif]),
+            Some(VariantFormat::Avif)
+        );
+    }
+
+    #[cfg(feature = "variants")]
+    fn png(width: u32, height: u32) -> CacheValue {
+        let image = image::RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x * y) as u8, ((x ^ y) * 4) as u8, ((x + y) * 2) as u8])
+        });
+        let mut data = Vec::new();
+        image
+            .write_to(
+                &mut std::io::Cursor::new(&mut data),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+        CacheValue {
+            data,
+            content_type: "image/png".to_string(),
+            validators: crate::cache::Validators::default(),
+        }
+    }
+
+    #[cfg(feature = "variants")]
+    #[rstest]
+    #[case::avif(VariantFormat::Avif, image::ImageFormat::Avif)]
+    #[case::webp(VariantFormat::Webp, image::ImageFormat::WebP)]
+    fn test_encode(#[case] format: VariantFormat, #[case] expected: image::ImageFormat) {
+        let original = png(64, 64);
+        let variant = encode(&original, format).unwrap().unwrap();
+        assert_eq!(variant.content_type, format.mime_type());
+        assert_eq!(image::guess_format(&variant.data).unwrap(), expected);
+        assert!(variant.data.len() < original.data.len());
+        if format == VariantFormat::Webp {
+            // lossless
+            let decoded = image::load_from_memory(&variant.data).unwrap();
+            assert_eq!(
+                decoded.to_rgb8(),
+                image::load_from_memory(&original.data).unwrap().to_rgb8()
+            );
+        }
+    }
+
+    #[cfg(feature = "variants")]
+    #[test]
+    fn test_encode_skipped() {
+        let gif = CacheValue {
+            content_type: "image/gif".to_string(),
+            ..png(32, 32)
+        };
+        assert_eq!(encode(&gif, VariantFormat::Webp).unwrap(), None);
+
+        // the variant of an image that's already tiny wouldn't be any smaller
+        assert_eq!(encode(&png(1, 1), VariantFormat::Avif).unwrap(), None);
+    }
+
+    #[cfg(not(feature = "variants"))]
+    #[test]
+    fn test_encode_without_feature() {
+        let image = CacheValue {
+            data: std::fs::read("assets/blank.jpg").unwrap(),
+            content_type: "image/jpeg".to_string(),
+            validators: crate::cache::Validators::default(),
+        };
+        assert!(encode(&image, VariantFormat::Webp).is_err());
+    }
+}
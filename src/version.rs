@@ -0,0 +1,44 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+
+/// Metadata about the running build, embedded at compile time by the build script
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct BuildInfo {
+    /// The version of the crate
+    pub version: &'static str,
+    /// The git commit the binary was built from, or `unknown`
+    pub git_commit: &'static str,
+    /// The version of the compiler used to build the binary, or `unknown`
+    pub rustc_version: &'static str,
+    /// The cargo features enabled at build time
+    pub features: Vec<&'static str>,
+}
+
+/// Get the build metadata of the running binary
+#[must_use]
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("RANDOM_IMAGE_SERVER_GIT_COMMIT"),
+        rustc_version: env!("RANDOM_IMAGE_SERVER_RUSTC_VERSION"),
+        features: env!("RANDOM_IMAGE_SERVER_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_build_info() {
+        let info = build_info();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.git_commit.is_empty());
+        assert!(!info.rustc_version.is_empty());
+        assert!(info.features.iter().all(|feature| !feature.is_empty()));
+    }
+}
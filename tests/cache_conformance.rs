@@ -0,0 +1,58 @@
+use random_image_server::{
+    cache::{
+        CacheBackend, FileSystemCache, InMemoryCache, TieredCache,
+        testing::{cache_backend_conformance, check_eviction, check_rejection},
+    },
+    config::{Compression, EvictionPolicy},
+};
+use rstest::rstest;
+
+mod in_memory_cache {
+    use super::*;
+
+    random_image_server::cache_backend_tests!(InMemoryCache::new);
+}
+
+mod file_system_cache {
+    use super::*;
+
+    random_image_server::cache_backend_tests!(FileSystemCache::new);
+}
+
+mod tiered_cache {
+    use super::*;
+
+    random_image_server::cache_backend_tests!(TieredCache::new);
+}
+
+#[cfg(feature = "sled")]
+mod sled_cache {
+    use super::*;
+
+    random_image_server::cache_backend_tests!(random_image_server::cache::SledCache::new);
+}
+
+#[test]
+fn test_compressed_file_system_cache() {
+    cache_backend_conformance(|| FileSystemCache::new().with_compression(Compression::Zstd));
+}
+
+#[rstest]
+#[case::even(10)]
+#[case::odd(11)]
+fn test_in_memory_cache_eviction(#[case] max_bytes: u64) {
+    check_eviction(
+        &InMemoryCache::with_max_bytes(max_bytes, EvictionPolicy::EvictOldest),
+        max_bytes,
+    );
+}
+
+#[rstest]
+#[case::even(10)]
+#[case::odd(11)]
+fn test_in_memory_cache_rejection(#[case] max_bytes: u64) {
+    check_rejection(
+        &InMemoryCache::with_max_bytes(max_bytes, EvictionPolicy::Reject),
+        max_bytes,
+    );
+}
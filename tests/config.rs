@@ -1,9 +1,20 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 use pretty_assertions::{assert_eq, assert_str_eq};
 use random_image_server::{
-    config::{CacheBackendType, CacheConfig, Config, ImageSource, ServerConfig},
+    access::Cidr,
+    config::{
+        AccessConfig, AcmeConfig, AspectRatio, AuthConfig, BasicAuth, CacheBackendType,
+        CacheConfig, CertificateFiles, CollectionConfig, Compression, Config, ConfigFormat,
+        EmptyCachePolicy, EvictionPolicy, HashAlgorithm, HashedBasicAuth, HttpConfig, ImageSource,
+        LimitsConfig, LogRotation, MetricsConfig, NotificationsConfig, ObservabilityConfig,
+        PlaceholderConfig, PlaylistConfig, ProxyConfig, ServerConfig, SigningConfig, SourceConfig,
+        TlsConfig, TransformsConfig, VariantFormat, format_duration, parse_duration,
+    },
     env::{EnvBackend, MockEnvBackend},
 };
 use rstest::rstest;
@@ -30,8 +41,14 @@ fn test_deserialize_config() {
         url::Host::Domain("localhost".to_string())
     );
     assert_eq!(config.server.sources.len(), 2);
-    assert!(matches!(config.server.sources[0], ImageSource::Path(_)));
-    assert!(matches!(config.server.sources[1], ImageSource::Url(_)));
+    assert!(matches!(
+        config.server.sources[0].location,
+        ImageSource::Path(_)
+    ));
+    assert!(matches!(
+        config.server.sources[1].location,
+        ImageSource::Url(_)
+    ));
 
     assert_eq!(config.cache.backend, CacheBackendType::FileSystem);
 }
@@ -66,24 +83,139 @@ fn test_socket_addr() {
 
 #[rstest]
 #[case::full(
-    "[server]\nport = 9090\nhost = \"0.0.0.0\"\nlog_level = \"debug\"\nsources = [\"./assets/blank.jpg\"]\n[cache]\nbackend = \"file_system\"", 
+    "[server]\nport = 9090\nhost = \"0.0.0.0\"\nlog_level = \"debug\"\nlog_file = \"/var/log/random-image-server.log\"\nlog_rotation = \"size\"\nlog_max_size = 1024\nsources = [\"./assets/blank.jpg\"]\nallowed_referers = [\"example.com\"]\naccess = { allow = [\"10.0.0.0/8\"] }\nlimits = { max_uri_length = 4096 }\nexclude = [\"*_thumb.jpg\", \".*\"]\nallowed_extensions = [\"jpg\", \".HEIC\", \"tiff\"]\nmin_file_size = 1024\ndedup_threshold = 4\nauto_orient = true\nexpose_sources = true\nno_repeat_window = 10\nsticky = \"5m\"\nrescan_interval = \"10m\"\nstart_before_populate = true\non_empty = \"placeholder\"\n[cache]\nbackend = \"file_system\"\ndirectory = \"/var/cache/random-image-server\"\n[observability]\nsentry_dsn = \"https://key@sentry.example.com/1\"\n[metrics]\nstatsd_host = \"localhost\"\nstatsd_prefix = \"images\"\n[http]\nproxy = \"http://proxy.example.com:8080\"\ntimeout = 10\ntls_verify = false\n[tls]\ncert = \"/etc/ssl/server.pem\"\nkey = \"/etc/ssl/server.key\"\ncertificates = { \"cdn.example.org\" = { cert = \"/etc/ssl/cdn.pem\", key = \"/etc/ssl/cdn.key\" } }\nacme = { domains = [\"images.example.com\"], cache_dir = \"/var/lib/acme\" }\n[notifications]\nwebhook_url = \"https://hooks.example.com/events\"\n[proxy]\nallowed_domains = [\"example.com\"]\n[placeholder]\nenabled = true\nmax_width = 1024\n[transforms]\ncache = { backend = \"in_memory\", max_bytes = 2048 }\nquality = 80\nformats = [\"avif\", \"webp\"]\n[collections.cats]\nsources = [\"./assets\"]\ncache = { backend = \"in_memory\", max_bytes = 1024 }\n[playlists.lobby]\nitems = [\"./assets/blank.jpg\", \"https://example.com/image.jpg\"]", 
     Config {
         server: ServerConfig {
             port: 9090,
             host: url::Host::Ipv4(std::net::Ipv4Addr::new(0, 0, 0, 0)),
             log_level: Level::DEBUG,
-            sources: vec![ImageSource::Path(PathBuf::from("./assets/blank.jpg").canonicalize().unwrap())],
+            log_file: Some(PathBuf::from("/var/log/random-image-server.log")),
+            log_rotation: LogRotation::Size,
+            log_max_size: 1024,
+            sources: vec![ImageSource::Path(PathBuf::from("./assets/blank.jpg").canonicalize().unwrap()).into()],
+            base_path: String::new(),
+            public_url: None,
+            trusted_proxies: vec![],
+            allowed_referers: vec!["example.com".to_string()],
+            hotlink_placeholder: None,
+            access: AccessConfig {
+                allow: vec!["10.0.0.0/8".parse().unwrap()],
+                deny: vec![],
+            },
+            auth: AuthConfig::default(),
+            limits: LimitsConfig {
+                max_uri_length: 4096,
+                ..LimitsConfig::default()
+            },
+            env_file: None,
+            include: vec![],
+            exclude: vec!["*_thumb.jpg".to_string(), ".*".to_string()],
+            allowed_extensions: vec!["jpg".to_string(), "heic".to_string(), "tiff".to_string()],
+            min_file_size: Some(1024),
+            max_file_size: None,
+            dedup_threshold: Some(4),
+            auto_orient: true,
+            rescan_interval: Some(Duration::from_secs(600)),
+            offline: false,
+            start_before_populate: true,
+            on_empty: EmptyCachePolicy::Placeholder,
+            swagger_ui: false,
+            expose_sources: true,
+            no_repeat_window: Some(10),
+            sticky: Some(Duration::from_secs(300)),
+            favicon: None,
+            static_dir: None,
+            ready_file: None,
         },
         cache: CacheConfig {
             backend: CacheBackendType::FileSystem,
+            directory: Some(PathBuf::from("/var/cache/random-image-server")),
+            ..CacheConfig::default()
+        },
+        observability: ObservabilityConfig {
+            sentry_dsn: Some("https://key@sentry.example.com/1".to_string()),
+            sentry_environment: None,
+        },
+        metrics: MetricsConfig {
+            statsd_host: Some("localhost".to_string()),
+            statsd_prefix: "images".to_string(),
+            ..MetricsConfig::default()
+        },
+        http: HttpConfig {
+            proxy: Some(Url::parse("http://proxy.example.com:8080").unwrap()),
+            timeout: 10,
+            tls_verify: false,
+            ..HttpConfig::default()
+        },
+        signing: SigningConfig::default(),
+        tls: TlsConfig {
+            cert: Some(PathBuf::from("/etc/ssl/server.pem")),
+            key: Some(PathBuf::from("/etc/ssl/server.key")),
+            certificates: [(
+                "cdn.example.org".to_string(),
+                CertificateFiles {
+                    cert: PathBuf::from("/etc/ssl/cdn.pem"),
+                    key: PathBuf::from("/etc/ssl/cdn.key"),
+                },
+            )]
+            .into(),
+            http3: false,
+            client_ca: None,
+            acme: Some(AcmeConfig {
+                domains: vec!["images.example.com".to_string()],
+                cache_dir: PathBuf::from("/var/lib/acme"),
+                ..AcmeConfig::default()
+            }),
         },
+        notifications: NotificationsConfig {
+            webhook_url: Some(Url::parse("https://hooks.example.com/events").unwrap()),
+        },
+        proxy: ProxyConfig {
+            allowed_domains: vec!["example.com".to_string()],
+            cache: None,
+        },
+        placeholder: PlaceholderConfig {
+            enabled: true,
+            max_width: 1024,
+            max_height: 4096,
+            cache: None,
+        },
+        transforms: TransformsConfig {
+            cache: Some(CacheConfig {
+                backend: CacheBackendType::InMemory,
+                max_bytes: Some(2048),
+                ..CacheConfig::default()
+            }),
+            quality: Some(80),
+            formats: vec![VariantFormat::Avif, VariantFormat::Webp],
+        },
+        collections: BTreeMap::from([(
+            "cats".to_string(),
+            CollectionConfig {
+                sources: vec![ImageSource::Path(PathBuf::from("./assets").canonicalize().unwrap()).into()],
+                cache: Some(CacheConfig {
+                    backend: CacheBackendType::InMemory,
+                    max_bytes: Some(1024),
+                    ..CacheConfig::default()
+                }),
+            },
+        )]),
+        playlists: BTreeMap::from([(
+            "lobby".to_string(),
+            PlaylistConfig {
+                items: vec![
+                    ImageSource::Path(PathBuf::from("./assets/blank.jpg").canonicalize().unwrap()),
+                    ImageSource::Url(Url::parse("https://example.com/image.jpg").unwrap()),
+                ],
+            },
+        )]),
     }
 )]
 #[case::minimal(
     "[server]\nsources = [\"https://example.com/image.jpg\"]",
     Config {
         server: ServerConfig {
-            sources: vec![ImageSource::Url(Url::parse("https://example.com/image.jpg").unwrap())],
+            sources: vec![ImageSource::Url(Url::parse("https://example.com/image.jpg").unwrap()).into()],
             ..ServerConfig::default()
         },
         ..Config::default()
@@ -99,6 +231,71 @@ fn test_from_file(#[case] content: &str, #[case] expected: Config) {
     assert_eq!(config, expected);
 }
 
+#[rstest]
+#[case::yaml(
+    "test.yaml",
+    "server:\n  port: 9090\n  log_level: debug\n  sources:\n    - https://example.com/image.jpg\ncache:\n  backend: file_system\n"
+)]
+#[case::yml(
+    "test.yml",
+    "server:\n  port: 9090\n  log_level: debug\n  sources: [\"https://example.com/image.jpg\"]\ncache:\n  backend: file_system\n"
+)]
+#[case::json(
+    "test.json",
+    r#"{"server": {"port": 9090, "log_level": "debug", "sources": ["https://example.com/image.jpg"]}, "cache": {"backend": "file_system"}}"#
+)]
+fn test_from_file_formats(#[case] file_name: &str, #[case] content: &str) {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join(file_name);
+
+    fs::write(&config_path, content).unwrap();
+
+    let config = Config::from_file(config_path.to_str().unwrap()).unwrap();
+    assert_eq!(
+        config,
+        Config {
+            server: ServerConfig {
+                port: 9090,
+                log_level: Level::DEBUG,
+                sources: vec![
+                    ImageSource::Url(Url::parse("https://example.com/image.jpg").unwrap()).into()
+                ],
+                ..ServerConfig::default()
+            },
+            cache: CacheConfig {
+                backend: CacheBackendType::FileSystem,
+                ..CacheConfig::default()
+            },
+            ..Config::default()
+        }
+    );
+}
+
+#[rstest]
+#[case::toml("config.toml", Some(ConfigFormat::Toml))]
+#[case::yaml("config.yaml", Some(ConfigFormat::Yaml))]
+#[case::yml("/etc/config.YML", Some(ConfigFormat::Yaml))]
+#[case::json("config.json", Some(ConfigFormat::Json))]
+#[case::unsupported("config.ini", None)]
+#[case::no_extension("config", None)]
+fn test_config_format_from_path(#[case] path: &str, #[case] expected: Option<ConfigFormat>) {
+    assert_eq!(
+        ConfigFormat::from_path(std::path::Path::new(path)),
+        expected
+    );
+}
+
+#[test]
+fn test_from_file_unsupported_extension() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.ini");
+
+    fs::write(&config_path, "[server]\nsources = [\"./assets/blank.jpg\"]").unwrap();
+
+    let result = Config::from_file(config_path.to_str().unwrap());
+    assert!(result.is_err());
+}
+
 #[test]
 fn test_from_file_not_found() {
     let result = Config::from_file("nonexistent.toml");
@@ -138,9 +335,30 @@ fn test_log_level_deserialization(#[case] level: &str, #[case] expected: Level)
     assert_eq!(config.server.log_level, expected);
 }
 
+#[rstest]
+#[case("daily", LogRotation::Daily)]
+#[case("hourly", LogRotation::Hourly)]
+#[case("size", LogRotation::Size)]
+fn test_log_rotation_deserialization(#[case] rotation: &str, #[case] expected: LogRotation) {
+    let config_toml = &format!(
+        r#"
+            [server]
+            log_file = "server.log"
+            log_rotation = "{rotation}"
+            sources = ["https://example.com/image.jpg"]
+            "#
+    );
+    let config: Config = toml::from_str(config_toml).unwrap();
+    assert_eq!(config.server.log_file, Some(PathBuf::from("server.log")));
+    assert_eq!(config.server.log_rotation, expected);
+}
+
 #[rstest]
 #[case("in_memory", CacheBackendType::InMemory)]
 #[case("file_system", CacheBackendType::FileSystem)]
+#[case("tiered", CacheBackendType::Tiered)]
+#[case("sled", CacheBackendType::Sled)]
+#[case("my_backend", CacheBackendType::Custom("my_backend".to_string()))]
 fn test_cache_backend_deserialization(#[case] backend: &str, #[case] expected: CacheBackendType) {
     let in_memory_toml = &format!(
         r#"
@@ -160,6 +378,30 @@ fn test_cache_backend_deserialization(#[case] backend: &str, #[case] expected: C
     assert_eq!(config.cache.backend, expected);
 }
 
+#[rstest]
+#[case::in_memory(CacheBackendType::InMemory, Some("/var/cache"), None, false)]
+#[case::file_system(CacheBackendType::FileSystem, Some("/var/cache"), None, true)]
+#[case::temporary_file_system(CacheBackendType::FileSystem, None, None, false)]
+#[case::tiered(CacheBackendType::Tiered, Some("/var/cache"), None, true)]
+#[case::sled_directory(CacheBackendType::Sled, Some("/var/cache"), None, true)]
+#[case::sled_path(CacheBackendType::Sled, None, Some("/var/images.sled"), true)]
+#[case::temporary_sled(CacheBackendType::Sled, None, None, false)]
+#[case::custom(CacheBackendType::Custom("my_backend".to_string()), None, None, true)]
+fn test_cache_is_persistent(
+    #[case] backend: CacheBackendType,
+    #[case] directory: Option<&str>,
+    #[case] sled_path: Option<&str>,
+    #[case] expected: bool,
+) {
+    let config = CacheConfig {
+        backend,
+        directory: directory.map(PathBuf::from),
+        sled_path: sled_path.map(PathBuf::from),
+        ..CacheConfig::default()
+    };
+    assert_eq!(config.is_persistent(), expected);
+}
+
 #[test]
 fn test_sources_deserialization_path() {
     // Create a temporary file for testing
@@ -180,12 +422,15 @@ fn test_sources_deserialization_path() {
 
     let config: Config = toml::from_str(&config_toml).unwrap();
     assert_eq!(config.server.sources.len(), 1);
-    assert!(matches!(config.server.sources[0], ImageSource::Path(_)));
+    assert!(matches!(
+        config.server.sources[0].location,
+        ImageSource::Path(_)
+    ));
 }
 
 #[rstest]
-#[case::path(r#"["./assets/blank.jpg"]"#, Ok(vec![ImageSource::Path(PathBuf::from("./assets/blank.jpg").canonicalize().unwrap())]))]
-#[case::url(r#"["https://example.com/image.jpg"]"#, Ok(vec![ImageSource::Url(Url::parse("https://example.com/image.jpg").unwrap())]))]
+#[case::path(r#"["./assets/blank.jpg"]"#, Ok(vec![ImageSource::Path(PathBuf::from("./assets/blank.jpg").canonicalize().unwrap()).into()]))]
+#[case::url(r#"["https://example.com/image.jpg"]"#, Ok(vec![ImageSource::Url(Url::parse("https://example.com/image.jpg").unwrap()).into()]))]
 #[case::empty(r#"[]"#, Err("No valid image sources found"))]
 #[case::invalid(
     r#"["/nonexistent/path.jpg", "not-a-url"]"#,
@@ -197,7 +442,7 @@ fn test_sources_deserialization_path() {
 )]
 fn test_sources_deserialization(
     #[case] sources: &str,
-    #[case] expected: Result<Vec<ImageSource>, &str>,
+    #[case] expected: Result<Vec<SourceConfig>, &str>,
 ) {
     let config_toml = format!(
         r#"
@@ -221,6 +466,52 @@ fn test_sources_deserialization(
     }
 }
 
+#[rstest]
+#[case::empty("", Ok(""))]
+#[case::root("/", Ok(""))]
+#[case::simple("/images", Ok("/images"))]
+#[case::trailing_slash("/images/", Ok("/images"))]
+#[case::no_leading_slash("images", Ok("/images"))]
+#[case::nested("/api/images/", Ok("/api/images"))]
+#[case::query("/images?x=1", Err("Invalid base path: /images?x=1"))]
+#[case::whitespace("/my images", Err("Invalid base path: /my images"))]
+fn test_base_path_deserialization(#[case] base_path: &str, #[case] expected: Result<&str, &str>) {
+    let config_toml = format!(
+        r#"
+            [server]
+            base_path = "{base_path}"
+            sources = ["https://example.com/image.jpg"]
+            "#,
+    );
+
+    match (toml::from_str::<Config>(&config_toml), expected) {
+        (Ok(config), Ok(expected)) => assert_eq!(config.server.base_path, expected),
+        (Ok(_), Err(e)) => panic!("Expected an error but got a valid config, expected: {e:?}"),
+        (Err(e), Ok(_)) => panic!("Failed to parse config when it should succeed: {e}"),
+        (Err(err), Err(message)) => assert_str_eq!(err.message(), message),
+    }
+}
+
+#[test]
+fn test_public_url_deserialization() {
+    let config_toml = r#"
+            [server]
+            public_url = "https://images.example.com"
+            trusted_proxies = ["10.0.0.1", "::1", "172.16.0.0/12"]
+            sources = ["https://example.com/image.jpg"]
+        "#;
+
+    let config: Config = toml::from_str(config_toml).unwrap();
+    assert_eq!(
+        config.server.public_url,
+        Some(Url::parse("https://images.example.com").unwrap())
+    );
+    assert_eq!(
+        config.server.trusted_proxies,
+        ["10.0.0.1/32", "::1/128", "172.16.0.0/12"].map(|range| range.parse::<Cidr>().unwrap())
+    );
+}
+
 #[test]
 fn test_invalid_host_deserialization() {
     let config_toml = r#"
@@ -261,16 +552,38 @@ fn test_invalid_host_deserialization() {
 #[case::sources(&[("RANDOM_IMAGE_SERVER_SOURCES", "https://example.com/image.jpg,./assets/blank.jpg")], Config {
         server: ServerConfig {
             sources: vec![
-                ImageSource::Url(Url::parse("https://example.com/image.jpg").unwrap()),
-                ImageSource::Path(PathBuf::from("./assets/blank.jpg").canonicalize().unwrap()),
+                ImageSource::Url(Url::parse("https://example.com/image.jpg").unwrap()).into(),
+                ImageSource::Path(PathBuf::from("./assets/blank.jpg").canonicalize().unwrap()).into(),
             ],
             ..Config::default().server
         },
         ..Config::default()
     })]
+#[case::base_path(&[("RANDOM_IMAGE_SERVER_BASE_PATH", "/images")], Config {
+        server: ServerConfig {
+            base_path: "/images".to_string(),
+            ..Config::default().server
+        },
+        ..Config::default()
+    })]
+#[case::public_url(&[("RANDOM_IMAGE_SERVER_PUBLIC_URL", "https://images.example.com/")], Config {
+        server: ServerConfig {
+            public_url: Some(Url::parse("https://images.example.com/").unwrap()),
+            ..Config::default().server
+        },
+        ..Config::default()
+    })]
+#[case::trusted_proxies(&[("RANDOM_IMAGE_SERVER_TRUSTED_PROXIES", "10.0.0.1,192.168.1.1")], Config {
+        server: ServerConfig {
+            trusted_proxies: vec!["10.0.0.1".parse().unwrap(), "192.168.1.1".parse().unwrap()],
+            ..Config::default().server
+        },
+        ..Config::default()
+    })]
 #[case::cache_backend(&[("RANDOM_IMAGE_SERVER_CACHE_BACKEND", "file_system")], Config {
         cache: CacheConfig {
             backend: CacheBackendType::FileSystem,
+            ..CacheConfig::default()
         },
         ..Config::default()
     })]
@@ -279,22 +592,210 @@ fn test_invalid_host_deserialization() {
             ("RANDOM_IMAGE_SERVER_PORT", "8080"),
             ("RANDOM_IMAGE_SERVER_HOST", "example.com"),
             ("RANDOM_IMAGE_SERVER_LOG_LEVEL", "debug"),
+            ("RANDOM_IMAGE_SERVER_LOG_FILE", "/tmp/server.log"),
+            ("RANDOM_IMAGE_SERVER_LOG_ROTATION", "hourly"),
+            ("RANDOM_IMAGE_SERVER_LOG_MAX_SIZE", "2048"),
             ("RANDOM_IMAGE_SERVER_SOURCES", "https://example.com/image.jpg,./assets/blank.jpg"),
-            ("RANDOM_IMAGE_SERVER_CACHE_BACKEND", "file_system")
+            ("RANDOM_IMAGE_SERVER_CACHE_BACKEND", "file_system"),
+            ("RANDOM_IMAGE_SERVER_CACHE_DIRECTORY", "/tmp/cache"),
+            ("RANDOM_IMAGE_SERVER_CACHE_MAX_BYTES", "1048576"),
+            ("RANDOM_IMAGE_SERVER_CACHE_EVICTION", "evict_oldest"),
+            ("RANDOM_IMAGE_SERVER_CACHE_HOT_IMAGES", "10"),
+            ("RANDOM_IMAGE_SERVER_CACHE_SLED_PATH", "/tmp/cache/images.sled"),
+            ("RANDOM_IMAGE_SERVER_CACHE_HASH", "xxh3"),
+            ("RANDOM_IMAGE_SERVER_CACHE_COMPRESSION", "zstd"),
+            ("RANDOM_IMAGE_SERVER_CACHE_MMAP", "false"),
+            ("RANDOM_IMAGE_SERVER_CACHE_REVALIDATE_INTERVAL", "2h"),
+            ("RANDOM_IMAGE_SERVER_CACHE_REVALIDATE_RATE", "5"),
+            ("RANDOM_IMAGE_SERVER_SENTRY_DSN", "https://key@sentry.example.com/1"),
+            ("RANDOM_IMAGE_SERVER_SENTRY_ENVIRONMENT", "production"),
+            ("RANDOM_IMAGE_SERVER_STATSD_HOST", "statsd.example.com"),
+            ("RANDOM_IMAGE_SERVER_STATSD_PORT", "9125"),
+            ("RANDOM_IMAGE_SERVER_STATSD_PREFIX", "images"),
+            ("RANDOM_IMAGE_SERVER_STATSD_FLUSH_INTERVAL", "30"),
+            ("RANDOM_IMAGE_SERVER_BASE_PATH", "images/"),
+            ("RANDOM_IMAGE_SERVER_PUBLIC_URL", "https://images.example.com"),
+            ("RANDOM_IMAGE_SERVER_TRUSTED_PROXIES", "10.0.0.1, ::1"),
+            ("RANDOM_IMAGE_SERVER_ALLOWED_REFERERS", "example.com, images.example.org"),
+            ("RANDOM_IMAGE_SERVER_HOTLINK_PLACEHOLDER", "/srv/placeholder.png"),
+            ("RANDOM_IMAGE_SERVER_ACCESS_ALLOW", "10.0.0.0/8, 2001:db8::/32"),
+            ("RANDOM_IMAGE_SERVER_ACCESS_DENY", "10.0.0.1"),
+            ("RANDOM_IMAGE_SERVER_AUTH_USERNAME", "admin"),
+            ("RANDOM_IMAGE_SERVER_AUTH_PASSWORD_HASH", "$2b$12$hash"),
+            ("RANDOM_IMAGE_SERVER_LIMITS_MAX_URI_LENGTH", "2048"),
+            ("RANDOM_IMAGE_SERVER_LIMITS_MAX_HEADER_SIZE", "65536"),
+            ("RANDOM_IMAGE_SERVER_LIMITS_MAX_HEADERS", "20"),
+            ("RANDOM_IMAGE_SERVER_INCLUDE", "*.jpg, *.png"),
+            ("RANDOM_IMAGE_SERVER_EXCLUDE", ""),
+            ("RANDOM_IMAGE_SERVER_ALLOWED_EXTENSIONS", "bmp, tif"),
+            ("RANDOM_IMAGE_SERVER_MAX_FILE_SIZE", "10000000"),
+            ("RANDOM_IMAGE_SERVER_DEDUP_THRESHOLD", "2"),
+            ("RANDOM_IMAGE_SERVER_AUTO_ORIENT", "true"),
+            ("RANDOM_IMAGE_SERVER_RESCAN_INTERVAL", "1h30m"),
+            ("RANDOM_IMAGE_SERVER_OFFLINE", "true"),
+            ("RANDOM_IMAGE_SERVER_START_BEFORE_POPULATE", "true"),
+            ("RANDOM_IMAGE_SERVER_ON_EMPTY", "wait"),
+            ("RANDOM_IMAGE_SERVER_SWAGGER_UI", "true"),
+            ("RANDOM_IMAGE_SERVER_EXPOSE_SOURCES", "true"),
+            ("RANDOM_IMAGE_SERVER_NO_REPEAT_WINDOW", "3"),
+            ("RANDOM_IMAGE_SERVER_STICKY", "30s"),
+            ("RANDOM_IMAGE_SERVER_FAVICON", "/srv/favicon.ico"),
+            ("RANDOM_IMAGE_SERVER_STATIC_DIR", "/srv/static"),
+            ("RANDOM_IMAGE_SERVER_READY_FILE", "/tmp/ready"),
+            ("RANDOM_IMAGE_SERVER_HTTP_PROXY", "socks5://127.0.0.1:1080"),
+            ("RANDOM_IMAGE_SERVER_HTTP_TIMEOUT", "60"),
+            ("RANDOM_IMAGE_SERVER_HTTP_MAX_REDIRECTS", "0"),
+            ("RANDOM_IMAGE_SERVER_HTTP_USER_AGENT", "my-images/1.0"),
+            ("RANDOM_IMAGE_SERVER_HTTP_CA_BUNDLE", "/etc/ssl/internal.pem"),
+            ("RANDOM_IMAGE_SERVER_HTTP_TLS_VERIFY", "false"),
+            ("RANDOM_IMAGE_SERVER_HTTP_FAILURE_THRESHOLD", "0"),
+            ("RANDOM_IMAGE_SERVER_HTTP_MAX_BACKOFF", "60"),
+            ("RANDOM_IMAGE_SERVER_HTTP_MAX_ARCHIVE_SIZE", "1048576"),
+            ("RANDOM_IMAGE_SERVER_SIGNING_SECRET", "secret"),
+            ("RANDOM_IMAGE_SERVER_SIGNING_REQUIRED", "true"),
+            ("RANDOM_IMAGE_SERVER_TLS_CERT", "/etc/ssl/server.pem"),
+            ("RANDOM_IMAGE_SERVER_TLS_KEY", "/etc/ssl/server.key"),
+            ("RANDOM_IMAGE_SERVER_TLS_HTTP3", "true"),
+            ("RANDOM_IMAGE_SERVER_TLS_CLIENT_CA", "/etc/ssl/clients.pem"),
+            ("RANDOM_IMAGE_SERVER_TLS_ACME_DOMAINS", "images.example.com, cdn.example.com"),
+            ("RANDOM_IMAGE_SERVER_TLS_ACME_EMAIL", "admin@example.com"),
+            ("RANDOM_IMAGE_SERVER_TLS_ACME_CACHE_DIR", "/var/lib/acme"),
+            ("RANDOM_IMAGE_SERVER_TLS_ACME_DIRECTORY", "https://acme-staging-v02.api.letsencrypt.org/directory"),
+            ("RANDOM_IMAGE_SERVER_WEBHOOK_URL", "https://hooks.example.com/events"),
+            ("RANDOM_IMAGE_SERVER_PROXY_ALLOWED_DOMAINS", "example.com, cdn.example.net"),
+            ("RANDOM_IMAGE_SERVER_PLACEHOLDER_ENABLED", "true"),
+            ("RANDOM_IMAGE_SERVER_PLACEHOLDER_MAX_WIDTH", "800"),
+            ("RANDOM_IMAGE_SERVER_PLACEHOLDER_MAX_HEIGHT", "600"),
+            ("RANDOM_IMAGE_SERVER_TRANSFORMS_QUALITY", "60"),
+            ("RANDOM_IMAGE_SERVER_TRANSFORMS_FORMATS", "webp"),
         ],
         Config {
             server: ServerConfig {
                 port: 8080,
                 host: url::Host::Domain("example.com".to_string()),
                 log_level: Level::DEBUG,
+                log_file: Some(PathBuf::from("/tmp/server.log")),
+                log_rotation: LogRotation::Hourly,
+                log_max_size: 2048,
                 sources: vec![
-                    ImageSource::Url(Url::parse("https://example.com/image.jpg").unwrap()),
-                    ImageSource::Path(PathBuf::from("./assets/blank.jpg").canonicalize().unwrap()),
+                    ImageSource::Url(Url::parse("https://example.com/image.jpg").unwrap()).into(),
+                    ImageSource::Path(PathBuf::from("./assets/blank.jpg").canonicalize().unwrap()).into(),
                 ],
+                base_path: "/images".to_string(),
+                public_url: Some(Url::parse("https://images.example.com").unwrap()),
+                trusted_proxies: vec!["10.0.0.1".parse().unwrap(), "::1".parse().unwrap()],
+                allowed_referers: vec!["example.com".to_string(), "images.example.org".to_string()],
+                hotlink_placeholder: Some(PathBuf::from("/srv/placeholder.png")),
+                access: AccessConfig {
+                    allow: vec!["10.0.0.0/8".parse().unwrap(), "2001:db8::/32".parse().unwrap()],
+                    deny: vec!["10.0.0.1".parse().unwrap()],
+                },
+                auth: AuthConfig {
+                    basic: Some(HashedBasicAuth {
+                        username: "admin".to_string(),
+                        password_hash: "$2b$12$hash".to_string(),
+                    }),
+                },
+                limits: LimitsConfig {
+                    max_uri_length: 2048,
+                    max_header_size: 65536,
+                    max_headers: 20,
+                },
+                env_file: None,
+                include: vec!["*.jpg".to_string(), "*.png".to_string()],
+                exclude: vec![],
+                allowed_extensions: vec!["bmp".to_string(), "tif".to_string()],
+                min_file_size: None,
+                max_file_size: Some(10_000_000),
+                dedup_threshold: Some(2),
+                auto_orient: true,
+                rescan_interval: Some(Duration::from_secs(5400)),
+                offline: true,
+                start_before_populate: true,
+                on_empty: EmptyCachePolicy::Wait,
+                swagger_ui: true,
+                expose_sources: true,
+                no_repeat_window: Some(3),
+                sticky: Some(Duration::from_secs(30)),
+                favicon: Some(PathBuf::from("/srv/favicon.ico")),
+                static_dir: Some(PathBuf::from("/srv/static")),
+                ready_file: Some(PathBuf::from("/tmp/ready")),
             },
             cache: CacheConfig {
                 backend: CacheBackendType::FileSystem,
+                directory: Some(PathBuf::from("/tmp/cache")),
+                max_bytes: Some(1_048_576),
+                eviction: EvictionPolicy::EvictOldest,
+                hot_images: 10,
+                sled_path: Some(PathBuf::from("/tmp/cache/images.sled")),
+                hash: HashAlgorithm::Xxh3,
+                compression: Compression::Zstd,
+                mmap: false,
+                revalidate_interval: Some(Duration::from_secs(7200)),
+                revalidate_rate: 5,
+            },
+            observability: ObservabilityConfig {
+                sentry_dsn: Some("https://key@sentry.example.com/1".to_string()),
+                sentry_environment: Some("production".to_string()),
+            },
+            metrics: MetricsConfig {
+                statsd_host: Some("statsd.example.com".to_string()),
+                statsd_port: 9125,
+                statsd_prefix: "images".to_string(),
+                statsd_flush_interval: 30,
             },
+            http: HttpConfig {
+                proxy: Some(Url::parse("socks5://127.0.0.1:1080").unwrap()),
+                timeout: 60,
+                max_redirects: 0,
+                user_agent: "my-images/1.0".to_string(),
+                ca_bundle: Some(PathBuf::from("/etc/ssl/internal.pem")),
+                tls_verify: false,
+                failure_threshold: 0,
+                max_backoff: 60,
+                max_archive_size: 1_048_576,
+            },
+            signing: SigningConfig {
+                secret: Some("secret".to_string()),
+                required: true,
+            },
+            tls: TlsConfig {
+                cert: Some(PathBuf::from("/etc/ssl/server.pem")),
+                key: Some(PathBuf::from("/etc/ssl/server.key")),
+                certificates: BTreeMap::new(),
+                http3: true,
+                client_ca: Some(PathBuf::from("/etc/ssl/clients.pem")),
+                acme: Some(AcmeConfig {
+                    domains: vec![
+                        "images.example.com".to_string(),
+                        "cdn.example.com".to_string()
+                    ],
+                    email: Some("admin@example.com".to_string()),
+                    cache_dir: PathBuf::from("/var/lib/acme"),
+                    directory: Url::parse("https://acme-staging-v02.api.letsencrypt.org/directory")
+                        .unwrap(),
+                }),
+            },
+            notifications: NotificationsConfig {
+                webhook_url: Some(Url::parse("https://hooks.example.com/events").unwrap()),
+            },
+            proxy: ProxyConfig {
+                allowed_domains: vec!["example.com".to_string(), "cdn.example.net".to_string()],
+                cache: None,
+            },
+            placeholder: PlaceholderConfig {
+                enabled: true,
+                max_width: 800,
+                max_height: 600,
+                cache: None,
+            },
+            transforms: TransformsConfig {
+                cache: None,
+                quality: Some(60),
+                formats: vec![VariantFormat::Webp],
+            },
+            collections: BTreeMap::new(),
+            playlists: BTreeMap::new(),
         }
     )]
 fn test_update_config_from_env(#[case] env_vars: &[(&str, &str)], #[case] expected: Config) {
@@ -309,3 +810,575 @@ fn test_update_config_from_env(#[case] env_vars: &[(&str, &str)], #[case] expect
 
     assert_eq!(config, expected);
 }
+
+#[test]
+fn test_update_config_from_env_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let env_file = temp_dir.path().join("server.env");
+    fs::write(
+        &env_file,
+        "RANDOM_IMAGE_SERVER_PORT=8080\nRANDOM_IMAGE_SERVER_LOG_LEVEL=debug\n",
+    )
+    .unwrap();
+
+    let mut mock_env = MockEnvBackend::default();
+    mock_env.set_var("RANDOM_IMAGE_SERVER_PORT", "9090");
+
+    let config = Config {
+        server: ServerConfig {
+            env_file: Some(env_file),
+            ..ServerConfig::default()
+        },
+        ..Config::default()
+    }
+    .with_env_backend(&mock_env)
+    .unwrap();
+
+    // variables in the environment take precedence over the env file
+    assert_eq!(config.server.port, 9090);
+    assert_eq!(config.server.log_level, Level::DEBUG);
+}
+
+#[test]
+fn test_update_config_from_missing_env_file() {
+    let config = Config {
+        server: ServerConfig {
+            env_file: Some(PathBuf::from("/nonexistent/server.env")),
+            ..ServerConfig::default()
+        },
+        ..Config::default()
+    };
+
+    assert!(config.with_env_backend(&MockEnvBackend::default()).is_err());
+}
+
+#[test]
+fn test_json_schema() {
+    let schema = Config::json_schema();
+
+    assert_eq!(schema["required"], serde_json::json!(["server"]));
+    let properties = schema["properties"].as_object().unwrap();
+    assert_eq!(
+        properties.keys().collect::<Vec<_>>(),
+        vec![
+            "cache",
+            "collections",
+            "http",
+            "metrics",
+            "notifications",
+            "observability",
+            "placeholder",
+            "playlists",
+            "proxy",
+            "server",
+            "signing",
+            "tls",
+            "transforms"
+        ]
+    );
+    let server = &schema["$defs"]["ServerConfig"];
+    assert_eq!(server["required"], serde_json::json!(["sources"]));
+    assert_eq!(server["properties"]["port"]["default"], 3000);
+    assert_eq!(server["properties"]["host"]["type"], "string");
+    assert_eq!(
+        schema["$defs"]["LogRotation"]["oneOf"][2]["const"],
+        serde_json::json!("size")
+    );
+}
+
+#[rstest]
+#[case::valid("cats", true)]
+#[case::dashes_and_underscores("cute-cats_2", true)]
+#[case::empty("", false)]
+#[case::slash("cats/dogs", false)]
+#[case::dot("cats.json", false)]
+#[case::route("image", false)]
+#[case::route_prefix("stats", false)]
+#[case::static_route("static", false)]
+fn test_deserialize_collection_names(#[case] name: &str, #[case] valid: bool) {
+    let config_toml = format!(
+        "[server]\nsources = [\"./assets\"]\n[collections.\"{name}\"]\nsources = [\"./assets\"]"
+    );
+    assert_eq!(toml::from_str::<Config>(&config_toml).is_ok(), valid);
+}
+
+#[rstest]
+#[case::valid(
+    "[playlists.lobby]\nitems = [\"./assets/blank.jpg\", \"./assets\"]",
+    true
+)]
+#[case::invalid_name("[playlists.\"lobby/main\"]\nitems = [\"./assets\"]", false)]
+#[case::empty("[playlists.lobby]\nitems = []", false)]
+#[case::missing_item("[playlists.lobby]\nitems = [\"./assets/missing.jpg\"]", false)]
+fn test_deserialize_playlists(#[case] playlists: &str, #[case] valid: bool) {
+    let config_toml = format!("[server]\nsources = [\"./assets\"]\n{playlists}");
+    assert_eq!(toml::from_str::<Config>(&config_toml).is_ok(), valid);
+}
+
+#[test]
+fn test_collection_config() {
+    let config_toml = r#"
+            [server]
+            sources = ["./assets"]
+            base_path = "/images"
+
+            [cache]
+            backend = "sled"
+            directory = "/var/cache/images"
+            sled_path = "/var/lib/images.sled"
+
+            [collections.cats]
+            sources = ["./assets/blank.jpg"]
+
+            [collections.featured]
+            sources = ["./assets/blank.jpg"]
+            cache = { backend = "in_memory", max_bytes = 1048576 }
+        "#;
+    let config: Config = toml::from_str(config_toml).unwrap();
+    assert_eq!(config.collection("dogs"), None);
+
+    let cats = config.collection("cats").unwrap();
+    assert_eq!(cats.server.sources, config.collections["cats"].sources);
+    assert_eq!(cats.server.base_path, "/images/cats");
+    assert!(cats.collections.is_empty());
+    assert_eq!(
+        cats.cache.directory,
+        Some(PathBuf::from("/var/cache/images/collections/cats"))
+    );
+    assert_eq!(
+        cats.cache.sled_path,
+        Some(PathBuf::from("/var/lib/images.sled-cats"))
+    );
+    assert_eq!(cats.server.port, config.server.port);
+
+    // collections with cache settings of their own use them as they are
+    let featured = config.collection("featured").unwrap();
+    assert_eq!(
+        featured.cache,
+        CacheConfig {
+            backend: CacheBackendType::InMemory,
+            max_bytes: Some(1_048_576),
+            ..CacheConfig::default()
+        }
+    );
+}
+
+#[test]
+fn test_deserialize_source_tables() {
+    let config_toml = r#"
+            [server]
+            sources = [
+                "./assets/blank.jpg",
+                { url = "https://example.com/image.jpg", weight = 3, headers = { Authorization = "Bearer token" } },
+            ]
+        "#;
+    let config: Config = toml::from_str(config_toml).expect("Failed to parse config");
+
+    assert_eq!(config.server.sources.len(), 2);
+    assert_eq!(
+        config.server.sources[0],
+        ImageSource::Path(PathBuf::from("./assets/blank.jpg").canonicalize().unwrap()).into()
+    );
+    assert_eq!(
+        config.server.sources[1],
+        SourceConfig {
+            weight: 3,
+            headers: [("Authorization".to_string(), "Bearer token".to_string())].into(),
+            ..ImageSource::Url(Url::parse("https://example.com/image.jpg").unwrap()).into()
+        }
+    );
+
+    let config_toml = r#"
+            [[server.source]]
+            path = "./assets"
+            tags = ["blank"]
+            caption = "Nothing to see here"
+            refresh_interval = 60
+            recursive = false
+            max_depth = 2
+            follow_symlinks = true
+            max_images = 10
+            min_width = 1920
+            min_aspect_ratio = "16:9"
+        "#;
+    let config: Config = toml::from_str(config_toml).expect("Failed to parse config");
+    assert_eq!(
+        config.server.sources,
+        vec![SourceConfig {
+            tags: vec!["blank".to_string()],
+            caption: Some("Nothing to see here".to_string()),
+            refresh_interval: Some(60),
+            recursive: false,
+            max_depth: Some(2),
+            follow_symlinks: true,
+            min_width: Some(1920),
+            min_aspect_ratio: Some(AspectRatio {
+                width: 16,
+                height: 9
+            }),
+            max_images: Some(10),
+            ..ImageSource::Path(PathBuf::from("./assets").canonicalize().unwrap()).into()
+        }]
+    );
+}
+
+#[rstest]
+#[case::both(r#"{ path = "./assets", url = "https://example.com/image.jpg" }"#)]
+#[case::neither("{ weight = 2 }")]
+#[case::missing_path(r#"{ path = "./does/not/exist" }"#)]
+#[case::both_auth(
+    r#"{ url = "https://example.com/a.jpg", bearer_token = "t", basic_auth = { username = "u" } }"#
+)]
+fn test_deserialize_invalid_source_table(#[case] source: &str) {
+    let config_toml = format!(
+        r#"
+            [server]
+            sources = ["./assets/blank.jpg", {source}]
+        "#
+    );
+    let config: Config = toml::from_str(&config_toml).expect("Failed to parse config");
+    assert_eq!(
+        config.server.sources,
+        vec![ImageSource::Path(PathBuf::from("./assets/blank.jpg").canonicalize().unwrap()).into()]
+    );
+}
+
+#[test]
+fn test_deserialize_unknown_source_field() {
+    let config_toml = r#"
+            [server]
+            sources = [{ path = "./assets", wieght = 2 }]
+        "#;
+    assert!(toml::from_str::<Config>(config_toml).is_err());
+}
+
+#[test]
+fn test_serialize_source_tables_roundtrip() {
+    let config = Config {
+        server: ServerConfig {
+            sources: vec![
+                ImageSource::Path(PathBuf::from("./assets/blank.jpg").canonicalize().unwrap())
+                    .into(),
+                SourceConfig {
+                    weight: 2,
+                    recursive: false,
+                    follow_symlinks: true,
+                    max_aspect_ratio: Some(AspectRatio {
+                        width: 4,
+                        height: 3,
+                    }),
+                    ..ImageSource::Path(PathBuf::from("./assets").canonicalize().unwrap()).into()
+                },
+            ],
+            ..ServerConfig::default()
+        },
+        ..Config::default()
+    };
+
+    let serialized = toml::to_string(&config).unwrap();
+    let deserialized: Config = toml::from_str(&serialized).unwrap();
+    assert_eq!(deserialized, config);
+}
+
+#[test]
+fn test_deserialize_invalid_glob() {
+    let config_toml = r#"
+            [server]
+            sources = ["./assets/blank.jpg"]
+            exclude = ["[a-"]
+        "#;
+    assert!(toml::from_str::<Config>(config_toml).is_err());
+}
+
+#[rstest]
+#[case::not_an_image("[\"txt\"]")]
+#[case::unknown("[\"notanextension\"]")]
+#[case::empty("[]")]
+fn test_deserialize_invalid_allowed_extensions(#[case] extensions: &str) {
+    let config_toml = format!(
+        r#"
+            [server]
+            sources = ["./assets/blank.jpg"]
+            allowed_extensions = {extensions}
+        "#
+    );
+    assert!(toml::from_str::<Config>(&config_toml).is_err());
+}
+
+#[rstest]
+#[case::zero("0")]
+#[case::too_high("101")]
+#[case::negative("-1")]
+#[case::not_a_number("\"high\"")]
+fn test_deserialize_invalid_quality(#[case] quality: &str) {
+    let config_toml = format!(
+        r#"
+            [server]
+            sources = ["./assets/blank.jpg"]
+            [transforms]
+            quality = {quality}
+        "#
+    );
+    assert!(toml::from_str::<Config>(&config_toml).is_err());
+}
+
+#[rstest]
+#[case::min(8192, true)]
+#[case::too_small(4096, false)]
+fn test_deserialize_max_header_size(#[case] size: usize, #[case] valid: bool) {
+    let config_toml = format!(
+        r#"
+            [server]
+            sources = ["./assets/blank.jpg"]
+            limits = {{ max_header_size = {size} }}
+        "#
+    );
+    match toml::from_str::<Config>(&config_toml) {
+        Ok(config) => {
+            assert!(valid);
+            assert_eq!(config.server.limits.max_header_size, size);
+        }
+        Err(err) => assert!(!valid, "{err}"),
+    }
+}
+
+#[rstest]
+#[case::no_limits(None, None, 100, true)]
+#[case::too_small(Some(200), None, 100, false)]
+#[case::min_inclusive(Some(100), None, 100, true)]
+#[case::too_large(None, Some(50), 100, false)]
+#[case::max_inclusive(Some(10), Some(100), 100, true)]
+fn test_allows_file_size(
+    #[case] min_file_size: Option<u64>,
+    #[case] max_file_size: Option<u64>,
+    #[case] size: u64,
+    #[case] expected: bool,
+) {
+    let config = ServerConfig {
+        min_file_size,
+        max_file_size,
+        ..ServerConfig::default()
+    };
+    assert_eq!(config.allows_file_size(size), expected);
+}
+
+#[rstest]
+#[case::seconds("45", Some(45))]
+#[case::minutes("10m", Some(600))]
+#[case::combined("1h30m", Some(5400))]
+#[case::days(" 2d ", Some(172_800))]
+#[case::zero("0s", None)]
+#[case::empty("", None)]
+#[case::unknown_unit("10w", None)]
+#[case::missing_unit("1h30", None)]
+#[case::negative("-5m", None)]
+fn test_parse_duration(#[case] input: &str, #[case] expected: Option<u64>) {
+    assert_eq!(
+        parse_duration(input).ok(),
+        expected.map(Duration::from_secs)
+    );
+}
+
+#[rstest]
+#[case::blake3(
+    "blake3",
+    HashAlgorithm::Blake3,
+    "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+)]
+#[case::xxh3("XXH3", HashAlgorithm::Xxh3, "99aa06d3014798d86001c324468d497f")]
+#[case::md5("md5", HashAlgorithm::Md5, "d41d8cd98f00b204e9800998ecf8427e")]
+fn test_hash_algorithm(#[case] name: &str, #[case] expected: HashAlgorithm, #[case] digest: &str) {
+    let algorithm = HashAlgorithm::from_str(name).unwrap();
+    assert_eq!(algorithm, expected);
+    assert_str_eq!(algorithm.digest(b""), digest);
+}
+
+#[rstest]
+#[case::none(Compression::None)]
+#[case::zstd(Compression::Zstd)]
+fn test_compression_roundtrip(#[case] compression: Compression) {
+    let data = vec![7; 1024];
+    let compressed = compression.compress(&data).unwrap().into_owned();
+    assert_eq!(compression.decompress(compressed).unwrap(), data);
+}
+
+#[rstest]
+#[case::seconds(45, "45s")]
+#[case::minutes(600, "10m")]
+#[case::combined(5430, "1h30m30s")]
+#[case::days(90_000, "1d1h")]
+fn test_format_duration(#[case] seconds: u64, #[case] expected: &str) {
+    assert_str_eq!(format_duration(Duration::from_secs(seconds)), expected);
+    assert_eq!(
+        parse_duration(expected).unwrap(),
+        Duration::from_secs(seconds)
+    );
+}
+
+#[rstest]
+#[case::landscape("16:9", Some(AspectRatio { width: 16, height: 9 }))]
+#[case::spaces(" 4 : 3 ", Some(AspectRatio { width: 4, height: 3 }))]
+#[case::decimal("1.5", None)]
+#[case::zero("16:0", None)]
+#[case::missing_height("16:", None)]
+fn test_parse_aspect_ratio(#[case] input: &str, #[case] expected: Option<AspectRatio>) {
+    assert_eq!(input.parse::<AspectRatio>().ok(), expected);
+}
+
+#[rstest]
+#[case::no_limits(
+    SourceConfig::from(ImageSource::Path(PathBuf::from("."))),
+    10,
+    10,
+    true
+)]
+#[case::too_narrow(SourceConfig { min_width: Some(1920), ..ImageSource::Path(PathBuf::from(".")).into() }, 1280, 1080, false)]
+#[case::too_short(SourceConfig { min_height: Some(1080), ..ImageSource::Path(PathBuf::from(".")).into() }, 1920, 720, false)]
+#[case::large_enough(SourceConfig { min_width: Some(1920), min_height: Some(1080), ..ImageSource::Path(PathBuf::from(".")).into() }, 1920, 1080, true)]
+#[case::landscape(SourceConfig { min_aspect_ratio: Some(AspectRatio { width: 1, height: 1 }), ..ImageSource::Path(PathBuf::from(".")).into() }, 1920, 1080, true)]
+#[case::not_landscape(SourceConfig { min_aspect_ratio: Some(AspectRatio { width: 1, height: 1 }), ..ImageSource::Path(PathBuf::from(".")).into() }, 1080, 1920, false)]
+#[case::too_wide(SourceConfig { max_aspect_ratio: Some(AspectRatio { width: 16, height: 9 }), ..ImageSource::Path(PathBuf::from(".")).into() }, 3440, 1440, false)]
+#[case::max_inclusive(SourceConfig { max_aspect_ratio: Some(AspectRatio { width: 16, height: 9 }), ..ImageSource::Path(PathBuf::from(".")).into() }, 1920, 1080, true)]
+fn test_allows_dimensions(
+    #[case] source: SourceConfig,
+    #[case] width: u64,
+    #[case] height: u64,
+    #[case] expected: bool,
+) {
+    assert_eq!(source.allows_dimensions(width, height), expected);
+}
+
+#[test]
+fn test_deserialize_manifest_source() {
+    let config_toml = r#"
+            [server]
+            sources = [
+                "manifest:https://example.com/images.txt",
+                { manifest = "https://example.com/images.json", max_images = 100 },
+            ]
+        "#;
+    let config: Config = toml::from_str(config_toml).expect("Failed to parse config");
+
+    assert_eq!(
+        config.server.sources,
+        vec![
+            ImageSource::Manifest(Url::parse("https://example.com/images.txt").unwrap()).into(),
+            SourceConfig {
+                max_images: Some(100),
+                ..ImageSource::Manifest(Url::parse("https://example.com/images.json").unwrap())
+                    .into()
+            },
+        ]
+    );
+    assert_eq!(
+        config.server.sources[0].to_string(),
+        "manifest:https://example.com/images.txt"
+    );
+
+    let serialized = toml::to_string(&config).unwrap();
+    let deserialized: Config = toml::from_str(&serialized).unwrap();
+    assert_eq!(deserialized, config);
+}
+
+#[test]
+fn test_deserialize_feed_source() {
+    let config_toml = r#"
+            [server]
+            sources = [
+                "feed:https://example.com/potd.rss",
+                { feed = "https://example.com/potd.atom", max_images = 1, refresh_interval = 86400 },
+            ]
+        "#;
+    let config: Config = toml::from_str(config_toml).expect("Failed to parse config");
+
+    assert_eq!(
+        config.server.sources,
+        vec![
+            ImageSource::Feed(Url::parse("https://example.com/potd.rss").unwrap()).into(),
+            SourceConfig {
+                max_images: Some(1),
+                refresh_interval: Some(86400),
+                ..ImageSource::Feed(Url::parse("https://example.com/potd.atom").unwrap()).into()
+            },
+        ]
+    );
+    assert_eq!(
+        config.server.sources[0].to_string(),
+        "feed:https://example.com/potd.rss"
+    );
+
+    let serialized = toml::to_string(&config).unwrap();
+    let deserialized: Config = toml::from_str(&serialized).unwrap();
+    assert_eq!(deserialized, config);
+}
+
+#[test]
+fn test_source_credentials() {
+    let config_toml = r#"
+            [server]
+            sources = [
+                { url = "https://example.com/a.jpg", basic_auth = { username = "user", password = "secret" } },
+                { url = "https://example.com/b.jpg", bearer_token = "token", headers = { X-Api-Key = "key" } },
+            ]
+        "#;
+    let config: Config = toml::from_str(config_toml).expect("Failed to parse config");
+
+    assert_eq!(
+        config.server.sources[0].basic_auth,
+        Some(BasicAuth {
+            username: "user".to_string(),
+            password: Some("secret".to_string()),
+        })
+    );
+    assert_eq!(
+        config.server.sources[0].request_headers(),
+        BTreeMap::from([(
+            "Authorization".to_string(),
+            "Basic dXNlcjpzZWNyZXQ=".to_string()
+        )])
+    );
+    assert_eq!(
+        config.server.sources[1].request_headers(),
+        BTreeMap::from([
+            ("Authorization".to_string(), "Bearer token".to_string()),
+            ("X-Api-Key".to_string(), "key".to_string()),
+        ])
+    );
+
+    let serialized = toml::to_string(&config).unwrap();
+    let deserialized: Config = toml::from_str(&serialized).unwrap();
+    assert_eq!(deserialized, config);
+}
+
+#[test]
+fn test_source_credentials_from_env() {
+    let mut config = Config::default();
+    config.server.sources = vec![
+        ImageSource::Url(Url::parse("https://example.com/a.jpg").unwrap()).into(),
+        SourceConfig {
+            bearer_token: Some("from-config".to_string()),
+            ..ImageSource::Url(Url::parse("https://example.com/b.jpg").unwrap()).into()
+        },
+    ];
+    let mut mock_env = MockEnvBackend::default();
+    mock_env.set_var("RANDOM_IMAGE_SERVER_SOURCE_0_USERNAME", "user");
+    mock_env.set_var("RANDOM_IMAGE_SERVER_SOURCE_0_PASSWORD", "secret");
+    mock_env.set_var("RANDOM_IMAGE_SERVER_SOURCE_1_BEARER_TOKEN", "from-env");
+
+    let config = config.with_env_backend(&mock_env).unwrap();
+    assert_eq!(
+        config.server.sources[0].basic_auth,
+        Some(BasicAuth {
+            username: "user".to_string(),
+            password: Some("secret".to_string()),
+        })
+    );
+    assert_eq!(
+        config.server.sources[1].bearer_token.as_deref(),
+        Some("from-env")
+    );
+
+    let mut mock_env = MockEnvBackend::default();
+    mock_env.set_var("RANDOM_IMAGE_SERVER_SOURCE_1_PASSWORD", "secret");
+    assert!(config.with_env_backend(&mock_env).is_err());
+}
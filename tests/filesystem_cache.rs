@@ -1,7 +1,11 @@
 use std::path::PathBuf;
 
 use pretty_assertions::assert_eq;
-use random_image_server::cache::{CacheBackend, CacheKey, CacheValue, FileSystemCache};
+use random_image_server::{
+    cache::{CacheBackend, CacheKey, CacheMetadata, CacheValue, FileSystemCache, Validators},
+    config::{Compression, HashAlgorithm},
+};
+use rstest::rstest;
 use url::Url;
 
 #[test]
@@ -9,16 +13,17 @@ fn test_new_cache() {
     let cache = FileSystemCache::new();
     assert_eq!(cache.size(), 0);
     assert!(cache.is_empty());
-    assert!(cache.keys().is_empty());
+    assert_eq!(cache.keys().count(), 0);
 }
 
 #[test]
 fn test_set_and_get() {
-    let mut cache = FileSystemCache::new();
+    let cache = FileSystemCache::new();
     let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
 
     assert!(cache.set(key.clone(), value.clone()).is_ok());
@@ -36,20 +41,22 @@ fn test_get_nonexistent() {
 
 #[test]
 fn test_remove() {
-    let mut cache = FileSystemCache::new();
+    let cache = FileSystemCache::new();
     let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
 
-    cache.set(key.clone(), value).unwrap();
+    cache.set(key.clone(), value.clone()).unwrap();
     assert_eq!(cache.size(), 1);
+    let path = cache.entry(&key).unwrap().path;
 
-    let _removed = cache.remove(&key);
-    // Note: FileSystemCache.remove() implementation has a bug - it reads after removing
-    // So we just test that it doesn't panic and the cache is updated
+    assert_eq!(cache.remove(&key), Some(value));
     assert_eq!(cache.size(), 0);
+    assert!(!path.exists());
+    assert_eq!(cache.remove(&key), None);
 }
 
 #[test]
@@ -60,24 +67,26 @@ fn test_get_random_empty_cache() {
 
 #[test]
 fn test_get_random_single_item() {
-    let mut cache = FileSystemCache::new();
+    let cache = FileSystemCache::new();
     let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
 
-    cache.set(key, value.clone()).unwrap();
-    assert_eq!(cache.get_random(), Some(value));
+    cache.set(key.clone(), value.clone()).unwrap();
+    assert_eq!(cache.get_random(), Some((key, value)));
 }
 
 #[test]
 fn test_clear() {
-    let mut cache = FileSystemCache::new();
+    let cache = FileSystemCache::new();
     let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
 
     cache.set(key, value).unwrap();
@@ -90,41 +99,288 @@ fn test_clear() {
 
 #[test]
 fn test_keys() {
-    let mut cache = FileSystemCache::new();
+    let cache = FileSystemCache::new();
     let k1 = CacheKey::ImagePath(PathBuf::from("/test/image1.jpg"));
     let k2 = CacheKey::ImageUrl(Url::parse("https://example.com/image.jpg").unwrap());
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
 
     cache.set(k1.clone(), value.clone()).unwrap();
     cache.set(k2.clone(), value).unwrap();
 
-    let keys = cache.keys();
-    assert_eq!(keys.len(), 2);
-    assert!(keys.contains(&k1));
-    assert!(keys.contains(&k2));
+    // keys are iterated in the order they were stored
+    assert_eq!(cache.keys().collect::<Vec<_>>(), [k1.clone(), k2.clone()]);
+}
+
+#[test]
+fn test_iter() {
+    let cache = FileSystemCache::new();
+    let key = CacheKey::ImageUrl(Url::parse("https://example.com/image.png").unwrap());
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/png".to_string(),
+        validators: Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+        },
+    };
+    cache.set(key.clone(), value.clone()).unwrap();
+
+    let entries: Vec<(CacheKey, CacheMetadata)> = cache.iter().collect();
+    assert_eq!(
+        entries,
+        [(
+            key,
+            CacheMetadata {
+                content_type: value.content_type,
+                validators: value.validators,
+            }
+        )]
+    );
 }
 
 // ensure that if a file is modified after being cached, it will be invalidated
 #[test]
 fn test_hash_validation() {
-    let mut cache = FileSystemCache::new();
+    let cache = FileSystemCache::new();
     let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
 
     cache.set(key.clone(), value).unwrap();
 
     // Corrupt the file to test hash validation
-    if let Some(fs_value) = cache.cache.get(&key) {
+    if let Some(fs_value) = cache.entry(&key) {
         std::fs::write(&fs_value.path, vec![9, 9, 9, 9]).unwrap();
         // Get should return None due to hash mismatch
         assert_eq!(cache.get(key), None);
         // and the cache file should be deleted
         assert!(!fs_value.path.exists());
+        assert_eq!(cache.stats().validation_failures, 1);
+        assert_eq!(cache.stats().misses, 1);
+    }
+}
+
+// ensure that opening a file to stream it checks its hash the first time
+#[rstest]
+#[case::mmap(true)]
+#[case::read(false)]
+fn test_open_hash_validation(#[case] mmap: bool) {
+    let cache = FileSystemCache::new().with_mmap(mmap);
+    let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    };
+    cache.set(key.clone(), value).unwrap();
+    let path = cache.entry(&key).unwrap().path;
+    std::fs::write(&path, vec![9, 9, 9, 9]).unwrap();
+
+    assert!(cache.open(&key).is_none());
+    assert!(!path.exists());
+    assert_eq!(cache.stats().validation_failures, 1);
+    assert_eq!(cache.stats().misses, 1);
+}
+
+// ensure that revalidation removes corrupted files from the cache, without counting as a lookup
+#[test]
+fn test_revalidate() {
+    let cache = FileSystemCache::new();
+    let intact = CacheKey::ImagePath(PathBuf::from("/test/intact.jpg"));
+    let corrupted = CacheKey::ImagePath(PathBuf::from("/test/corrupted.jpg"));
+    for key in [&intact, &corrupted] {
+        let value = CacheValue {
+            data: vec![1, 2, 3, 4],
+            content_type: "image/jpeg".to_string(),
+            validators: Validators::default(),
+        };
+        cache.set(key.clone(), value).unwrap();
+    }
+    let path = cache.entry(&corrupted).unwrap().path;
+    std::fs::write(&path, vec![9, 9, 9, 9]).unwrap();
+
+    assert!(cache.revalidate(&intact));
+    assert!(!cache.revalidate(&corrupted));
+    assert!(!path.exists());
+    assert_eq!(cache.keys().collect::<Vec<_>>(), [intact]);
+    assert_eq!(cache.stats().validation_failures, 1);
+    assert_eq!(cache.stats().hits + cache.stats().misses, 0);
+    // images that aren't in the cache have nothing to revalidate
+    assert!(cache.revalidate(&corrupted));
+}
+
+#[rstest]
+#[case::blake3(HashAlgorithm::Blake3)]
+#[case::xxh3(HashAlgorithm::Xxh3)]
+#[case::md5(HashAlgorithm::Md5)]
+fn test_hash_algorithm(#[case] algorithm: HashAlgorithm) {
+    let cache = FileSystemCache::new().with_hash_algorithm(algorithm);
+    let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    };
+
+    cache.set(key.clone(), value.clone()).unwrap();
+    assert_eq!(
+        cache.entry(&key).unwrap().hash,
+        algorithm.digest(&value.data)
+    );
+    assert_eq!(cache.get(key.clone()), Some(value));
+
+    std::fs::write(cache.entry(&key).unwrap().path, vec![9, 9, 9, 9]).unwrap();
+    assert_eq!(cache.get(key), None);
+}
+
+#[test]
+fn test_validators() {
+    let cache = FileSystemCache::new();
+    let key = CacheKey::ImageUrl(Url::parse("https://example.com/image.jpg").unwrap());
+    let validators = Validators {
+        etag: Some("\"v1\"".to_string()),
+        last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+    };
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: validators.clone(),
+    };
+
+    assert_eq!(cache.validators(&key), None);
+    cache.set(key.clone(), value.clone()).unwrap();
+    assert_eq!(cache.validators(&key), Some(validators));
+    assert_eq!(cache.get(key), Some(value));
+}
+
+#[test]
+fn test_spool_outlives_cache() {
+    let directory = tempfile::TempDir::new().unwrap();
+    let url_key = CacheKey::ImageUrl(Url::parse("https://example.com/image.jpg").unwrap());
+    let path_key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+        },
+    };
+
+    let cache = FileSystemCache::with_directory(directory.path()).unwrap();
+    cache.set(url_key.clone(), value.clone()).unwrap();
+    cache.set(path_key.clone(), value.clone()).unwrap();
+    drop(cache);
+
+    // images fetched from URLs can be loaded again after a restart, but aren't in the cache
+    let cache = FileSystemCache::with_directory(directory.path()).unwrap();
+    assert!(cache.is_empty());
+    assert_eq!(cache.spooled(&url_key), Some(value.clone()));
+    assert_eq!(cache.spooled(&path_key), None);
+
+    // images removed from the cache are removed from the spool
+    cache.set(url_key.clone(), value).unwrap();
+    cache.remove(&url_key);
+    drop(cache);
+    let cache = FileSystemCache::with_directory(directory.path()).unwrap();
+    assert_eq!(cache.spooled(&url_key), None);
+}
+
+#[test]
+fn test_spool_temporary_cache() {
+    let cache = FileSystemCache::new();
+    let key = CacheKey::ImageUrl(Url::parse("https://example.com/image.jpg").unwrap());
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    };
+    cache.set(key.clone(), value).unwrap();
+    assert_eq!(cache.spooled(&key), None);
+}
+
+#[test]
+fn test_compression() {
+    let directory = tempfile::TempDir::new().unwrap();
+    let key = CacheKey::ImagePath(PathBuf::from("/test/image.png"));
+    let url_key = CacheKey::ImageUrl(Url::parse("https://example.com/image.png").unwrap());
+    let value = CacheValue {
+        data: vec![0; 4096],
+        content_type: "image/png".to_string(),
+        validators: Validators::default(),
+    };
+
+    let cache = FileSystemCache::with_directory(directory.path())
+        .unwrap()
+        .with_compression(Compression::Zstd);
+    cache.set(key.clone(), value.clone()).unwrap();
+    cache.set(url_key.clone(), value.clone()).unwrap();
+
+    // images are compressed at rest, and decompressed when they are read
+    assert!(cache.bytes() < 4096);
+    assert_eq!(cache.get(key), Some(value.clone()));
+    assert_eq!(cache.get(url_key.clone()), Some(value.clone()));
+    drop(cache);
+
+    // spooled images remember their compression, even if it has since been disabled
+    let cache = FileSystemCache::with_directory(directory.path()).unwrap();
+    assert_eq!(cache.spooled(&url_key), Some(value));
+}
+
+#[rstest]
+#[case::uncompressed(Compression::None, true)]
+#[case::zstd(Compression::Zstd, false)]
+fn test_open(#[case] compression: Compression, #[case] streamable: bool) {
+    let cache = FileSystemCache::new().with_compression(compression);
+    let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    };
+    cache.set(key.clone(), value.clone()).unwrap();
+
+    let file = cache.open(&key);
+    assert_eq!(file.is_some(), streamable);
+    if let Some(file) = file {
+        assert_eq!(file.len, 4);
+        assert_eq!(file.content_type, "image/jpeg");
+        assert_eq!(file.hash, HashAlgorithm::default().digest(&value.data));
+        // files are mapped into memory by default
+        assert_eq!(file.map.as_deref(), Some(value.data.as_slice()));
+    }
+    assert!(
+        cache
+            .open(&CacheKey::ImagePath(PathBuf::from("/nonexistent.jpg")))
+            .is_none()
+    );
+}
+
+#[rstest]
+#[case::uncompressed(Compression::None)]
+#[case::zstd(Compression::Zstd)]
+fn test_without_mmap(#[case] compression: Compression) {
+    let cache = FileSystemCache::new()
+        .with_compression(compression)
+        .with_mmap(false);
+    let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    };
+    cache.set(key.clone(), value.clone()).unwrap();
+
+    // files are read into memory instead, and streamed rather than mapped
+    assert_eq!(cache.get(key.clone()), Some(value));
+    if let Some(file) = cache.open(&key) {
+        assert!(file.map.is_none());
     }
 }
@@ -1,7 +1,12 @@
 use std::path::PathBuf;
 
 use pretty_assertions::assert_eq;
-use random_image_server::cache::{CacheBackend, CacheKey, CacheValue, InMemoryCache};
+use random_image_server::{
+    cache::{
+        CacheBackend, CacheKey, CacheMetadata, CacheStats, CacheValue, InMemoryCache, Validators,
+    },
+    config::EvictionPolicy,
+};
 use url::Url;
 
 #[test]
@@ -9,16 +14,17 @@ fn test_new_cache() {
     let cache = InMemoryCache::new();
     assert_eq!(cache.size(), 0);
     assert!(cache.is_empty());
-    assert!(cache.keys().is_empty());
+    assert_eq!(cache.keys().count(), 0);
 }
 
 #[test]
 fn test_set_and_get() {
-    let mut cache = InMemoryCache::new();
+    let cache = InMemoryCache::new();
     let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
 
     assert!(cache.set(key.clone(), value.clone()).is_ok());
@@ -32,15 +38,23 @@ fn test_get_nonexistent() {
     let cache = InMemoryCache::new();
     let key = CacheKey::ImagePath(PathBuf::from("/nonexistent.jpg"));
     assert_eq!(cache.get(key), None);
+    assert_eq!(
+        cache.stats(),
+        CacheStats {
+            misses: 1,
+            ..CacheStats::default()
+        }
+    );
 }
 
 #[test]
 fn test_remove() {
-    let mut cache = InMemoryCache::new();
+    let cache = InMemoryCache::new();
     let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
 
     cache.set(key.clone(), value.clone()).unwrap();
@@ -54,7 +68,7 @@ fn test_remove() {
 
 #[test]
 fn test_remove_nonexistent() {
-    let mut cache = InMemoryCache::new();
+    let cache = InMemoryCache::new();
     let key = CacheKey::ImagePath(PathBuf::from("/nonexistent.jpg"));
     assert_eq!(cache.remove(&key), None);
 }
@@ -67,46 +81,50 @@ fn test_get_random_empty_cache() {
 
 #[test]
 fn test_get_random_single_item() {
-    let mut cache = InMemoryCache::new();
+    let cache = InMemoryCache::new();
     let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
 
-    cache.set(key, value.clone()).unwrap();
-    assert_eq!(cache.get_random(), Some(value));
+    cache.set(key.clone(), value.clone()).unwrap();
+    assert_eq!(cache.get_random(), Some((key, value)));
 }
 
 #[test]
 fn test_get_random_multiple_items() {
-    let mut cache = InMemoryCache::new();
+    let cache = InMemoryCache::new();
     let key1 = CacheKey::ImagePath(PathBuf::from("/test/image1.jpg"));
     let key2 = CacheKey::ImagePath(PathBuf::from("/test/image2.jpg"));
     let value1 = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
     let value2 = CacheValue {
         data: vec![5, 6, 7, 8],
         content_type: "image/png".to_string(),
+        validators: Validators::default(),
     };
 
-    cache.set(key1, value1.clone()).unwrap();
-    cache.set(key2, value2.clone()).unwrap();
+    cache.set(key1.clone(), value1.clone()).unwrap();
+    cache.set(key2.clone(), value2.clone()).unwrap();
 
-    // Test that get_random returns one of the values
-    let random_value = cache.get_random().unwrap();
-    assert!(random_value == value1 || random_value == value2);
+    // Test that get_random returns one of the values, with its key
+    let random = cache.get_random().unwrap();
+    assert!(random == (key1, value1) || random == (key2, value2));
 }
 
 #[test]
 fn test_clear() {
-    let mut cache = InMemoryCache::new();
+    let cache = InMemoryCache::new();
     let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
 
     cache.set(key, value).unwrap();
@@ -119,34 +137,62 @@ fn test_clear() {
 
 #[test]
 fn test_keys() {
-    let mut cache = InMemoryCache::new();
+    let cache = InMemoryCache::new();
     let k1 = CacheKey::ImagePath(PathBuf::from("/test/image1.jpg"));
     let k2 = CacheKey::ImageUrl(Url::parse("https://example.com/image.jpg").unwrap());
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
 
     cache.set(k1.clone(), value.clone()).unwrap();
     cache.set(k2.clone(), value).unwrap();
 
-    let keys = cache.keys();
-    assert_eq!(keys.len(), 2);
-    assert!(keys.contains(&k1));
-    assert!(keys.contains(&k2));
+    // keys are iterated in the order they were stored
+    assert_eq!(cache.keys().collect::<Vec<_>>(), [k1.clone(), k2.clone()]);
+}
+
+#[test]
+fn test_iter() {
+    let cache = InMemoryCache::new();
+    let key = CacheKey::ImageUrl(Url::parse("https://example.com/image.png").unwrap());
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/png".to_string(),
+        validators: Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+        },
+    };
+    cache.set(key.clone(), value.clone()).unwrap();
+
+    let entries: Vec<(CacheKey, CacheMetadata)> = cache.iter().collect();
+    assert_eq!(
+        entries,
+        [(
+            key,
+            CacheMetadata {
+                content_type: value.content_type,
+                validators: value.validators,
+            }
+        )]
+    );
 }
 
 #[test]
 fn test_set_duplicate_key() {
-    let mut cache = InMemoryCache::new();
+    let cache = InMemoryCache::new();
     let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
     let value1 = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
     let value2 = CacheValue {
         data: vec![5, 6, 7, 8],
         content_type: "image/png".to_string(),
+        validators: Validators::default(),
     };
 
     cache.set(key.clone(), value1).unwrap();
@@ -157,3 +203,91 @@ fn test_set_duplicate_key() {
     assert_eq!(cache.size(), 1);
     assert_eq!(cache.get(key), Some(value2));
 }
+
+#[test]
+fn test_validators() {
+    let cache = InMemoryCache::new();
+    let key = CacheKey::ImageUrl(Url::parse("https://example.com/image.jpg").unwrap());
+    let validators = Validators {
+        etag: Some("\"v1\"".to_string()),
+        last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+    };
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: validators.clone(),
+    };
+
+    assert_eq!(cache.validators(&key), None);
+    cache.set(key.clone(), value.clone()).unwrap();
+    assert_eq!(cache.validators(&key), Some(validators));
+    assert_eq!(cache.get(key), Some(value));
+}
+
+fn image(size: usize) -> CacheValue {
+    CacheValue {
+        data: vec![0; size],
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    }
+}
+
+#[test]
+fn test_bytes() {
+    let cache = InMemoryCache::new();
+    let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
+    cache.set(key.clone(), image(10)).unwrap();
+    cache
+        .set(
+            CacheKey::ImagePath(PathBuf::from("/test/other.jpg")),
+            image(5),
+        )
+        .unwrap();
+    assert_eq!(cache.bytes(), 15);
+
+    // replacing an image only counts its new size
+    cache.set(key.clone(), image(3)).unwrap();
+    assert_eq!(cache.bytes(), 8);
+    cache.remove(&key);
+    assert_eq!(cache.bytes(), 5);
+}
+
+#[test]
+fn test_max_bytes_reject() {
+    let cache = InMemoryCache::with_max_bytes(10, EvictionPolicy::Reject);
+    let first = CacheKey::ImagePath(PathBuf::from("/test/first.jpg"));
+    let second = CacheKey::ImagePath(PathBuf::from("/test/second.jpg"));
+
+    cache.set(first.clone(), image(6)).unwrap();
+    assert!(cache.set(second.clone(), image(6)).is_err());
+    assert!(cache.set(second.clone(), image(11)).is_err());
+    assert_eq!(
+        cache.keys().collect::<Vec<_>>(),
+        std::slice::from_ref(&first)
+    );
+    assert_eq!(cache.bytes(), 6);
+
+    // an image can be replaced by a bigger one, as long as the cache stays within budget
+    cache.set(first, image(10)).unwrap();
+    assert_eq!(cache.bytes(), 10);
+}
+
+#[test]
+fn test_max_bytes_evict_oldest() {
+    let cache = InMemoryCache::with_max_bytes(10, EvictionPolicy::EvictOldest);
+    let keys: Vec<CacheKey> = (0..3)
+        .map(|i| CacheKey::ImagePath(PathBuf::from(format!("/test/{i}.jpg"))))
+        .collect();
+
+    cache.set(keys[0].clone(), image(4)).unwrap();
+    cache.set(keys[1].clone(), image(4)).unwrap();
+    cache.set(keys[2].clone(), image(4)).unwrap();
+    assert_eq!(cache.keys().collect::<Vec<_>>(), keys[1..]);
+    assert_eq!(cache.bytes(), 8);
+
+    // images bigger than the whole budget are rejected without evicting anything
+    let huge = CacheKey::ImagePath(PathBuf::from("/test/huge.jpg"));
+    assert!(cache.set(huge, image(11)).is_err());
+    assert_eq!(cache.size(), 2);
+    assert_eq!(cache.stats().evictions, 1);
+}
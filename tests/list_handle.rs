@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use http_body_util::BodyExt;
+use hyper::Request;
+use pretty_assertions::assert_eq;
+use random_image_server::{
+    cache::{CacheKey, CacheValue, Validators},
+    config::{Config, ImageSource, SourceConfig},
+    handle_list_images,
+    query::ListQuery,
+    state::ServerState,
+};
+use rstest::rstest;
+
+/// A state with images `c`, `a`, and `b` (added in that order, 3, 1, and 2 bytes long) from a source tagged
+/// `letters`, and image `d` from an untagged source
+fn state() -> ServerState {
+    let mut config = Config::default();
+    config.server.sources = vec![
+        SourceConfig {
+            tags: vec!["letters".to_string()],
+            ..ImageSource::Path(PathBuf::from("/test/letters")).into()
+        },
+        ImageSource::Path(PathBuf::from("/test/other")).into(),
+    ];
+    let state = ServerState::with_config(&config);
+    for (index, name, size) in [(0, "c", 3), (0, "a", 1), (0, "b", 2), (1, "d", 4)] {
+        let directory = if index == 0 { "letters" } else { "other" };
+        let key = CacheKey::ImagePath(PathBuf::from(format!("/test/{directory}/{name}.jpg")));
+        let value = CacheValue {
+            data: vec![0; size],
+            content_type: "image/jpeg".to_string(),
+            validators: Validators::default(),
+        };
+        state.store_image(index, key, value).unwrap();
+    }
+    state
+}
+
+async fn list(state: &ServerState, query: &str) -> serde_json::Value {
+    let req = Request::builder()
+        .uri(format!("http://example.com/list?{query}"))
+        .header("Host", "example.com")
+        .body(())
+        .unwrap();
+    let query = ListQuery::parse(req.uri().query()).unwrap();
+    let response = handle_list_images(&req, state, &query).unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&body).unwrap()
+}
+
+fn sizes(list: &serde_json::Value) -> Vec<u64> {
+    list["images"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|image| image["bytes"].as_u64().unwrap())
+        .collect()
+}
+
+#[rstest]
+#[case::added("", vec![3, 1, 2, 4])]
+#[case::name("sort=name", vec![1, 2, 3, 4])]
+#[case::size("sort=size", vec![1, 2, 3, 4])]
+#[case::tag("tag=letters&sort=size", vec![1, 2, 3])]
+#[case::unknown_tag("tag=numbers", vec![])]
+#[tokio::test]
+async fn test_handle_list_images_sort_and_filter(#[case] query: &str, #[case] expected: Vec<u64>) {
+    let list = list(&state(), query).await;
+
+    assert_eq!(sizes(&list), expected);
+    assert_eq!(list["total"], expected.len());
+}
+
+#[tokio::test]
+async fn test_handle_list_images_pages() {
+    let state = state();
+
+    let first = list(&state, "per_page=3&sort=size").await;
+    assert_eq!(sizes(&first), [1, 2, 3]);
+    assert_eq!(first["total"], 4);
+    assert_eq!(first["page"], 1);
+    assert_eq!(first["per_page"], 3);
+    assert_eq!(first["previous"], serde_json::Value::Null);
+    let next = first["next"].as_str().unwrap();
+    assert_eq!(next, "http://example.com/list?page=2&per_page=3&sort=size");
+
+    let second = list(&state, next.split_once('?').unwrap().1).await;
+    assert_eq!(sizes(&second), [4]);
+    assert_eq!(second["next"], serde_json::Value::Null);
+    assert_eq!(
+        second["previous"],
+        "http://example.com/list?page=1&per_page=3&sort=size"
+    );
+
+    // past the last page, the previous page is the last one
+    let past = list(&state, "page=5&per_page=3&sort=size").await;
+    assert_eq!(sizes(&past), [] as [u64; 0]);
+    assert_eq!(past["next"], serde_json::Value::Null);
+    assert_eq!(
+        past["previous"],
+        "http://example.com/list?page=2&per_page=3&sort=size"
+    );
+}
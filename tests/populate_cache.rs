@@ -1,11 +1,37 @@
-use std::fs;
+// most of the HTTP helpers and imports are only used by the tests of remote sources
+#![cfg_attr(not(feature = "remote-sources"), allow(unused_imports, dead_code))]
+
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+use http_body_util::Full;
+use hyper::{Response, body::Bytes, service::service_fn};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
 
 use pretty_assertions::assert_eq;
 use random_image_server::{
     ImageServer,
-    config::{Config, ImageSource},
+    breaker::BreakerState,
+    cache::CacheKey,
+    config::{
+        AspectRatio, BasicAuth, CacheBackendType, Config, EvictionPolicy, ImageSource, SourceConfig,
+    },
+    state::SourceStatus,
 };
+use rstest::rstest;
 use tempfile::TempDir;
+use tokio::net::TcpListener;
+use url::Url;
 
 #[tokio::test]
 async fn test_image_server_populate_cache_no_sources() {
@@ -15,7 +41,7 @@ async fn test_image_server_populate_cache_no_sources() {
     // Should not panic even with no sources
     server.populate_cache().await;
 
-    assert_eq!(server.state.read().await.cache.size(), 0);
+    assert_eq!(server.state.cache.size(), 0);
 }
 
 #[tokio::test]
@@ -26,12 +52,12 @@ async fn test_image_server_populate_cache_with_file() {
     fs::write(&image_path, &test_data).unwrap();
 
     let mut config = Config::default();
-    config.server.sources = vec![ImageSource::Path(image_path)];
+    config.server.sources = vec![ImageSource::Path(image_path).into()];
 
     let server = ImageServer::with_config(config);
     server.populate_cache().await;
 
-    assert_eq!(server.state.read().await.cache.size(), 1);
+    assert_eq!(server.state.cache.size(), 1);
 }
 
 #[tokio::test]
@@ -46,13 +72,36 @@ async fn test_image_server_populate_cache_with_directory() {
     fs::write(&text_file_path, "not an image").unwrap();
 
     let mut config = Config::default();
-    config.server.sources = vec![ImageSource::Path(temp_dir.path().to_path_buf())];
+    config.server.sources = vec![ImageSource::Path(temp_dir.path().to_path_buf()).into()];
 
     let server = ImageServer::with_config(config);
     server.populate_cache().await;
 
     // Should only load image files, not text files
-    assert_eq!(server.state.read().await.cache.size(), 2);
+    assert_eq!(server.state.cache.size(), 2);
+}
+
+#[tokio::test]
+async fn test_image_server_populate_cache_large_directory() {
+    // more images than are read at once, so they are loaded over several batches
+    let temp_dir = TempDir::new().unwrap();
+    for i in 0..150 {
+        fs::write(
+            temp_dir.path().join(format!("test{i}.jpg")),
+            vec![0xFF, 0xD8, 0xFF],
+        )
+        .unwrap();
+    }
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(temp_dir.path().to_path_buf()).into()];
+
+    let server = ImageServer::with_config(config);
+    let report = server.populate_cache().await;
+
+    assert_eq!(server.state.cache.size(), 150);
+    assert_eq!(report.images(), 150);
+    assert_eq!(report.errors(), 0);
 }
 
 #[tokio::test]
@@ -62,11 +111,915 @@ async fn test_image_server_populate_cache_invalid_file() {
     fs::write(&text_path, "not an image").unwrap();
 
     let mut config = Config::default();
-    config.server.sources = vec![ImageSource::Path(text_path)];
+    config.server.sources = vec![ImageSource::Path(text_path).into()];
 
     let server = ImageServer::with_config(config);
     server.populate_cache().await;
 
     // Should not load non-image files
-    assert_eq!(server.state.read().await.cache.size(), 0);
+    assert_eq!(server.state.cache.size(), 0);
+}
+
+#[tokio::test]
+async fn test_image_server_populate_cache_records_source_status() {
+    let temp_dir = TempDir::new().unwrap();
+    let image_path = temp_dir.path().join("test.jpg");
+    fs::write(&image_path, vec![0xFF, 0xD8, 0xFF]).unwrap();
+    let text_path = temp_dir.path().join("test.txt");
+    fs::write(&text_path, "not an image").unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![
+        ImageSource::Path(image_path).into(),
+        ImageSource::Path(text_path).into(),
+    ];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    assert!(state.populated.load(Ordering::Relaxed));
+    assert_eq!(state.sources().len(), 2);
+    assert_eq!(state.sources()[0].status, SourceStatus::Loaded);
+    assert_eq!(state.sources()[0].images, 1);
+    assert_eq!(state.sources()[1].status, SourceStatus::Failed);
+    assert_eq!(state.sources()[1].images, 0);
+    assert!(state.sources()[1].last_error.is_some());
+}
+
+#[tokio::test]
+async fn test_image_server_populate_cache_report() {
+    let temp_dir = TempDir::new().unwrap();
+    for (name, size) in [("icon.jpg", 10), ("photo.jpg", 100)] {
+        fs::write(temp_dir.path().join(name), vec![0xFF; size]).unwrap();
+    }
+    let text_path = temp_dir.path().join("test.txt");
+    fs::write(&text_path, "not an image").unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![
+        ImageSource::Path(temp_dir.path().to_path_buf()).into(),
+        ImageSource::Path(text_path).into(),
+    ];
+    config.server.min_file_size = Some(50);
+
+    let server = ImageServer::with_config(config);
+    let report = server.populate_cache().await;
+
+    assert_eq!(report.sources.len(), 2);
+    assert_eq!(report.sources[0].images, 1);
+    assert_eq!(report.sources[0].skipped, 1);
+    assert_eq!(report.sources[0].errors, 0);
+    assert_eq!(report.sources[1].images, 0);
+    assert_eq!(report.sources[1].errors, 1);
+    assert!(report.sources[1].last_error.is_some());
+    assert_eq!(report.images(), server.state.cache.size());
+    assert_eq!(report.skipped(), 1);
+    assert_eq!(report.failed_sources(), 1);
+}
+
+/// Create a directory with two images at the top level, and one in a subdirectory
+fn nested_image_directory() -> TempDir {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("nested")).unwrap();
+    for path in ["test1.jpg", "test2.jpg", "nested/test3.jpg"] {
+        fs::write(temp_dir.path().join(path), vec![0xFF, 0xD8, 0xFF]).unwrap();
+    }
+    temp_dir
+}
+
+#[rstest]
+#[case::recursive(true, None, None, 3)]
+#[case::not_recursive(false, None, None, 2)]
+#[case::max_depth_0(true, Some(0), None, 2)]
+#[case::max_depth_1(true, Some(1), None, 3)]
+#[case::not_recursive_max_depth(false, Some(1), None, 2)]
+#[case::max_images(true, None, Some(1), 1)]
+#[tokio::test]
+async fn test_image_server_populate_cache_source_settings(
+    #[case] recursive: bool,
+    #[case] max_depth: Option<usize>,
+    #[case] max_images: Option<usize>,
+    #[case] expected: usize,
+) {
+    let temp_dir = nested_image_directory();
+
+    let mut config = Config::default();
+    config.server.sources = vec![SourceConfig {
+        recursive,
+        max_depth,
+        max_images,
+        ..ImageSource::Path(temp_dir.path().to_path_buf()).into()
+    }];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    assert_eq!(state.cache.size(), expected);
+    assert_eq!(state.sources()[0].images, expected);
+    for key in state.cache.keys() {
+        assert_eq!(state.image_source_index(&key), Some(0));
+    }
+}
+
+#[tokio::test]
+async fn test_image_server_populate_cache_include_exclude() {
+    let temp_dir = nested_image_directory();
+    fs::write(
+        temp_dir.path().join("test1_thumb.jpg"),
+        vec![0xFF, 0xD8, 0xFF],
+    )
+    .unwrap();
+    fs::create_dir(temp_dir.path().join(".hidden")).unwrap();
+    fs::write(
+        temp_dir.path().join(".hidden/test4.jpg"),
+        vec![0xFF, 0xD8, 0xFF],
+    )
+    .unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(temp_dir.path().to_path_buf()).into()];
+    config.server.include = vec!["test[12]*".to_string(), "nested".to_string()];
+    config.server.exclude = vec!["*_thumb.jpg".to_string(), ".*".to_string()];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    let mut names: Vec<String> = state
+        .cache
+        .keys()
+        .map(|key| match key {
+            CacheKey::ImagePath(path) => path.file_name().unwrap().to_string_lossy().to_string(),
+            other => other.to_string(),
+        })
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["test1.jpg", "test2.jpg", "test3.jpg"]);
+}
+
+#[cfg(unix)]
+#[rstest]
+#[case::skip_symlinks(false, 1)]
+#[case::follow_symlinks(true, 3)]
+#[tokio::test]
+async fn test_image_server_populate_cache_symlinks(
+    #[case] follow_symlinks: bool,
+    #[case] expected: usize,
+) {
+    let outside = nested_image_directory();
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("test.jpg"), vec![0xFF, 0xD8, 0xFF]).unwrap();
+    std::os::unix::fs::symlink(
+        outside.path().join("test1.jpg"),
+        temp_dir.path().join("linked.jpg"),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink(
+        outside.path().join("nested"),
+        temp_dir.path().join("linked"),
+    )
+    .unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![SourceConfig {
+        follow_symlinks,
+        ..ImageSource::Path(temp_dir.path().to_path_buf()).into()
+    }];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    assert_eq!(server.state.cache.size(), expected);
+}
+
+#[tokio::test]
+async fn test_image_server_populate_cache_allowed_extensions() {
+    let temp_dir = TempDir::new().unwrap();
+    for name in ["test.jpg", "test.heic", "test.bmp"] {
+        fs::write(temp_dir.path().join(name), vec![0xFF, 0xD8, 0xFF]).unwrap();
+    }
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(temp_dir.path().to_path_buf()).into()];
+    config.server.allowed_extensions = vec!["heic".to_string(), "bmp".to_string()];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    let mut content_types: Vec<String> = state
+        .cache
+        .iter()
+        .map(|(_, metadata)| metadata.content_type)
+        .collect();
+    content_types.sort();
+    assert_eq!(content_types, vec!["image/bmp", "image/heic"]);
+}
+
+#[tokio::test]
+async fn test_image_server_populate_cache_file_size_limits() {
+    let temp_dir = TempDir::new().unwrap();
+    for (name, size) in [("icon.jpg", 10), ("photo.jpg", 100), ("scan.jpg", 1000)] {
+        fs::write(temp_dir.path().join(name), vec![0xFF; size]).unwrap();
+    }
+
+    let mut config = Config::default();
+    config.server.sources = vec![
+        ImageSource::Path(temp_dir.path().to_path_buf()).into(),
+        ImageSource::Path(temp_dir.path().join("scan.jpg")).into(),
+    ];
+    config.server.min_file_size = Some(50);
+    config.server.max_file_size = Some(500);
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    assert_eq!(state.cache.size(), 1);
+    assert!(
+        state
+            .cache
+            .get(CacheKey::ImagePath(temp_dir.path().join("photo.jpg")))
+            .is_some()
+    );
+    assert_eq!(state.sources()[0].images, 1);
+    assert_eq!(state.sources()[1].images, 0);
+}
+
+#[rstest]
+#[case::no_limits(SourceConfig::from(ImageSource::Path(PathBuf::new())), 2)]
+#[case::wide_enough(SourceConfig { min_width: Some(400), ..ImageSource::Path(PathBuf::new()).into() }, 1)]
+#[case::too_narrow(SourceConfig { min_width: Some(1920), ..ImageSource::Path(PathBuf::new()).into() }, 0)]
+#[case::not_landscape(SourceConfig { min_aspect_ratio: Some(AspectRatio { width: 16, height: 9 }), ..ImageSource::Path(PathBuf::new()).into() }, 0)]
+#[tokio::test]
+async fn test_image_server_populate_cache_dimension_limits(
+    #[case] source: SourceConfig,
+    #[case] expected: usize,
+) {
+    // blank.jpg is 474x474, and invalid.jpg has no dimensions to check
+    let temp_dir = TempDir::new().unwrap();
+    fs::copy("assets/blank.jpg", temp_dir.path().join("blank.jpg")).unwrap();
+    fs::write(temp_dir.path().join("invalid.jpg"), vec![0xFF, 0xD8, 0xFF]).unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![SourceConfig {
+        location: ImageSource::Path(temp_dir.path().to_path_buf()),
+        ..source
+    }];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    assert_eq!(server.state.cache.size(), expected);
+}
+
+#[cfg(feature = "perceptual-hash")]
+#[rstest]
+#[case::no_dedup(None, 2)]
+#[case::dedup(Some(4), 1)]
+#[tokio::test]
+async fn test_image_server_populate_cache_dedup(
+    #[case] dedup_threshold: Option<u32>,
+    #[case] expected: usize,
+) {
+    let temp_dir = TempDir::new().unwrap();
+    fs::copy("assets/blank.jpg", temp_dir.path().join("blank.jpg")).unwrap();
+    fs::copy("assets/blank.jpg", temp_dir.path().join("copy.jpg")).unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(temp_dir.path().to_path_buf()).into()];
+    config.server.dedup_threshold = dedup_threshold;
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    assert_eq!(state.cache.size(), expected);
+    assert_eq!(
+        state
+            .cache
+            .keys()
+            .filter(|key| state.image_hash(key).is_some())
+            .count(),
+        expected
+    );
+}
+
+#[cfg(feature = "transforms")]
+#[rstest]
+#[case::as_is(false, (40, 20))]
+#[case::auto_orient(true, (20, 40))]
+#[tokio::test]
+async fn test_image_server_populate_cache_auto_orient(
+    #[case] auto_orient: bool,
+    #[case] expected: (usize, usize),
+) {
+    // a landscape JPEG, tagged as taken with the camera turned a quarter turn
+    let mut data = Vec::new();
+    image::RgbImage::new(40, 20)
+        .write_to(
+            &mut std::io::Cursor::new(&mut data),
+            image::ImageFormat::Jpeg,
+        )
+        .unwrap();
+    let exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0";
+    let mut segment = vec![0xff, 0xe1, 0, u8::try_from(exif.len() + 2).unwrap()];
+    segment.extend(exif);
+    data.splice(2..2, segment);
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("photo.jpg"), data).unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(temp_dir.path().to_path_buf()).into()];
+    config.server.auto_orient = auto_orient;
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let key = server.state.cache.keys().next().unwrap();
+    let image = server.state.cache.get(key).unwrap();
+    let size = imagesize::blob_size(&image.data).unwrap();
+    assert_eq!((size.width, size.height), expected);
+}
+
+/// Serve a manifest listing two images (and a missing one) at `/manifest.txt`, and the images themselves
+async fn serve_manifest() -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let archive = Bytes::from(tar_archive(&["a.jpg", "b.png", "notes.txt"]));
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let archive = archive.clone();
+            let service = service_fn(move |req: hyper::Request<hyper::body::Incoming>| {
+                let archive = archive.clone();
+                async move {
+                    let (status, content_type, body) = match req.uri().path() {
+                        "/manifest.txt" => (
+                            200,
+                            "text/plain",
+                            Bytes::from("# images\na.jpg\n/b.jpg\nmissing.jpg\n"),
+                        ),
+                        "/a.jpg" | "/b.jpg" => (200, "image/jpeg", Bytes::from("image")),
+                        "/images.tar" => (200, "application/x-tar", archive),
+                        "/private.jpg"
+                            if req
+                                .headers()
+                                .get("Authorization")
+                                .is_some_and(|value| value == "Basic dXNlcjpzZWNyZXQ=") =>
+                        {
+                            (200, "image/jpeg", Bytes::from("image"))
+                        }
+                        "/private.jpg" => (401, "text/plain", Bytes::from("Unauthorized")),
+                        "/agent.jpg"
+                            if req
+                                .headers()
+                                .get("User-Agent")
+                                .is_some_and(|value| value == "test-agent") =>
+                        {
+                            (200, "image/jpeg", Bytes::from("image"))
+                        }
+                        "/feed.xml" => (
+                            200,
+                            "application/rss+xml",
+                            Bytes::from(concat!(
+                                r#"<rss version="2.0"><channel><title>Images</title>"#,
+                                r#"<item><enclosure url="a.jpg" type="image/jpeg"/></item>"#,
+                                r#"<item><enclosure url="/b.jpg" type="image/jpeg"/></item>"#,
+                                r#"<item><enclosure url="c.mp3" type="audio/mpeg"/></item>"#,
+                                "</channel></rss>"
+                            )),
+                        ),
+                        _ => (404, "text/plain", Bytes::from("Not Found")),
+                    };
+                    Response::builder()
+                        .status(status)
+                        .header("Content-Type", content_type)
+                        .body(Full::new(body))
+                }
+            });
+            tokio::spawn(async move {
+                let _ = auto::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    Url::parse(&format!("http://{addr}/manifest.txt")).unwrap()
+}
+
+#[cfg(feature = "remote-sources")]
+#[rstest]
+#[case::all(None, 2)]
+#[case::max_images(Some(1), 1)]
+#[tokio::test]
+async fn test_image_server_populate_cache_manifest(
+    #[case] max_images: Option<usize>,
+    #[case] expected: usize,
+) {
+    let manifest = serve_manifest().await;
+
+    let mut config = Config::default();
+    config.server.sources = vec![SourceConfig {
+        max_images,
+        ..ImageSource::Manifest(manifest.clone()).into()
+    }];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    assert_eq!(state.cache.size(), expected);
+    assert!(
+        state
+            .cache
+            .get(CacheKey::ImageUrl(manifest.join("a.jpg").unwrap()))
+            .is_some()
+    );
+    assert_eq!(state.sources()[0].images, expected);
+}
+
+#[cfg(feature = "remote-sources")]
+#[rstest]
+#[case::all(None, 2)]
+#[case::latest(Some(1), 1)]
+#[tokio::test]
+async fn test_image_server_populate_cache_feed(
+    #[case] max_images: Option<usize>,
+    #[case] expected: usize,
+) {
+    let feed = serve_manifest().await.join("feed.xml").unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![SourceConfig {
+        max_images,
+        ..ImageSource::Feed(feed.clone()).into()
+    }];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    assert_eq!(state.cache.size(), expected);
+    assert!(
+        state
+            .cache
+            .get(CacheKey::ImageUrl(feed.join("a.jpg").unwrap()))
+            .is_some()
+    );
+    assert_eq!(state.sources()[0].images, expected);
+}
+
+#[tokio::test]
+async fn test_image_server_populate_cache_offline() {
+    let manifest = serve_manifest().await;
+
+    let mut config = Config::default();
+    config.server.offline = true;
+    config.server.sources = vec![
+        ImageSource::Manifest(manifest.clone()).into(),
+        ImageSource::Url(manifest.join("a.jpg").unwrap()).into(),
+        ImageSource::Path(PathBuf::from("assets/blank.jpg").canonicalize().unwrap()).into(),
+    ];
+
+    let server = ImageServer::with_config(config);
+    let report = server.populate_cache().await;
+
+    let state = &server.state;
+    assert_eq!(state.cache.size(), 1);
+    assert!(report.sources[0].offline && report.sources[1].offline);
+    assert!(!report.sources[2].offline);
+    assert_eq!(state.sources()[0].status, SourceStatus::Skipped);
+    assert_eq!(state.sources()[1].status, SourceStatus::Skipped);
+    assert_eq!(state.sources()[2].status, SourceStatus::Loaded);
+}
+
+#[cfg(not(feature = "remote-sources"))]
+#[tokio::test]
+async fn test_image_server_populate_cache_remote_sources_disabled() {
+    let manifest = serve_manifest().await;
+
+    let mut config = Config::default();
+    config.server.sources = vec![
+        ImageSource::Manifest(manifest.clone()).into(),
+        ImageSource::Url(manifest.join("images.tar").unwrap()).into(),
+        ImageSource::Path(PathBuf::from("assets/blank.jpg").canonicalize().unwrap()).into(),
+    ];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    assert_eq!(state.cache.size(), 1);
+    for source in &state.sources()[..2] {
+        assert_eq!(source.status, SourceStatus::Failed);
+        assert_eq!(
+            source.last_error.as_deref(),
+            Some(random_image_server::REMOTE_SOURCES_DISABLED)
+        );
+    }
+    assert_eq!(state.sources()[2].status, SourceStatus::Loaded);
+}
+
+#[tokio::test]
+async fn test_image_server_populate_cache_missing_manifest() {
+    let manifest = serve_manifest().await.join("missing.txt").unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Manifest(manifest).into()];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    assert_eq!(state.cache.size(), 0);
+    assert_eq!(state.sources()[0].status, SourceStatus::Failed);
+}
+
+fn tar_archive(names: &[&str]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for name in names {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, name, &[0xFF, 0xD8, 0xFF][..])
+            .unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+fn zip_archive(names: &[&str]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for name in names {
+        writer
+            .start_file(*name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(&[0xFF, 0xD8, 0xFF]).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+fn tar_gz_archive(names: &[&str]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&tar_archive(names)).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[rstest]
+#[case::zip("images.zip", zip_archive(&["a.jpg", "cats/b.png", "notes.txt"]), None, 2)]
+#[case::tar_gz("images.tar.gz", tar_gz_archive(&["a.jpg", "cats/b.png", "notes.txt"]), None, 2)]
+#[case::max_images("images.tgz", tar_gz_archive(&["a.jpg", "cats/b.png"]), Some(1), 1)]
+#[tokio::test]
+async fn test_image_server_populate_cache_archive(
+    #[case] name: &str,
+    #[case] archive: Vec<u8>,
+    #[case] max_images: Option<usize>,
+    #[case] expected: usize,
+) {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().canonicalize().unwrap().join(name);
+    fs::write(&archive_path, archive).unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![SourceConfig {
+        max_images,
+        ..ImageSource::Path(archive_path.clone()).into()
+    }];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    assert_eq!(state.cache.size(), expected);
+    assert!(
+        state
+            .cache
+            .get(CacheKey::ImagePath(archive_path.join("a.jpg")))
+            .is_some()
+    );
+    assert_eq!(state.sources()[0].images, expected);
+}
+
+#[cfg(feature = "remote-sources")]
+#[tokio::test]
+async fn test_image_server_populate_cache_remote_archive() {
+    let archive = serve_manifest().await.join("images.tar").unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Url(archive.clone()).into()];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    assert_eq!(state.cache.size(), 2);
+    let mut key = archive;
+    key.set_fragment(Some("b.png"));
+    assert_eq!(
+        state
+            .cache
+            .get(CacheKey::ImageUrl(key))
+            .unwrap()
+            .content_type,
+        "image/png"
+    );
+}
+
+#[cfg(feature = "remote-sources")]
+#[tokio::test]
+async fn test_image_server_populate_cache_remote_archive_too_large() {
+    let archive = serve_manifest().await.join("images.tar").unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Url(archive).into()];
+    config.http.max_archive_size = 1024;
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    assert_eq!(server.state.cache.size(), 0);
+    assert!(
+        server.state.sources()[0]
+            .last_error
+            .as_ref()
+            .is_some_and(|e| e.contains("larger than the limit"))
+    );
+}
+
+#[tokio::test]
+async fn test_image_server_populate_cache_invalid_archive() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("images.zip");
+    fs::write(&archive_path, "not an archive").unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(archive_path).into()];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    assert_eq!(state.cache.size(), 0);
+    assert_eq!(state.sources()[0].status, SourceStatus::Failed);
+}
+
+#[cfg(feature = "remote-sources")]
+#[rstest]
+#[case::authorized(Some("secret"), 1)]
+#[case::unauthorized(Some("wrong"), 0)]
+#[case::anonymous(None, 0)]
+#[tokio::test]
+async fn test_image_server_populate_cache_basic_auth(
+    #[case] password: Option<&str>,
+    #[case] expected: usize,
+) {
+    let url = serve_manifest().await.join("private.jpg").unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![SourceConfig {
+        basic_auth: password.map(|password| BasicAuth {
+            username: "user".to_string(),
+            password: Some(password.to_string()),
+        }),
+        ..ImageSource::Url(url).into()
+    }];
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    assert_eq!(server.state.cache.size(), expected);
+}
+
+#[cfg(feature = "remote-sources")]
+#[rstest]
+#[case::configured_user_agent("test-agent", 1)]
+#[case::other_user_agent("other-agent", 0)]
+#[tokio::test]
+async fn test_image_server_populate_cache_http_client_settings(
+    #[case] user_agent: &str,
+    #[case] expected: usize,
+) {
+    let url = serve_manifest().await.join("agent.jpg").unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Url(url).into()];
+    config.http.user_agent = user_agent.to_string();
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    assert_eq!(server.state.cache.size(), expected);
+}
+
+/// Serve an image with an `ETag`, counting how many times it was downloaded in full
+async fn serve_with_etag(downloads: Arc<AtomicUsize>) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let downloads = downloads.clone();
+            let service = service_fn(move |req: hyper::Request<hyper::body::Incoming>| {
+                let downloads = downloads.clone();
+                async move {
+                    let response = Response::builder().header("ETag", "\"v1\"");
+                    if req
+                        .headers()
+                        .get("If-None-Match")
+                        .is_some_and(|value| value == "\"v1\"")
+                    {
+                        return response.status(304).body(Full::new(Bytes::new()));
+                    }
+                    downloads.fetch_add(1, Ordering::SeqCst);
+                    response
+                        .header("Content-Type", "image/jpeg")
+                        .body(Full::new(Bytes::from("image")))
+                }
+            });
+            tokio::spawn(async move {
+                let _ = auto::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    Url::parse(&format!("http://{addr}/image.jpg")).unwrap()
+}
+
+#[cfg(feature = "remote-sources")]
+#[rstest]
+#[case::in_memory(CacheBackendType::InMemory)]
+#[case::file_system(CacheBackendType::FileSystem)]
+#[tokio::test]
+async fn test_image_server_populate_cache_conditional_refresh(#[case] backend: CacheBackendType) {
+    let downloads = Arc::new(AtomicUsize::new(0));
+    let url = serve_with_etag(downloads.clone()).await;
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Url(url.clone()).into()];
+    config.cache.backend = backend;
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+    // loading the source again only checks whether the image changed
+    server.populate_cache().await;
+
+    assert_eq!(downloads.load(Ordering::SeqCst), 1);
+    let state = &server.state;
+    let image = state.cache.get(CacheKey::ImageUrl(url)).unwrap();
+    assert_eq!(image.data, b"image");
+    assert_eq!(image.validators.etag.as_deref(), Some("\"v1\""));
+    assert_eq!(state.sources()[0].images, 1);
+    assert_eq!(state.sources()[0].status, SourceStatus::Loaded);
+}
+
+/// Serve an image, or an error while `failing` is set
+async fn serve_flaky(failing: Arc<AtomicBool>) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let failing = failing.clone();
+            let service = service_fn(move |_: hyper::Request<hyper::body::Incoming>| {
+                let failing = failing.clone();
+                async move {
+                    if failing.load(Ordering::SeqCst) {
+                        return Response::builder()
+                            .status(503)
+                            .body(Full::new(Bytes::from("Service Unavailable")));
+                    }
+                    Response::builder()
+                        .header("Content-Type", "image/jpeg")
+                        .body(Full::new(Bytes::from("image")))
+                }
+            });
+            tokio::spawn(async move {
+                let _ = auto::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    Url::parse(&format!("http://{addr}/image.jpg")).unwrap()
+}
+
+#[cfg(feature = "remote-sources")]
+#[tokio::test]
+async fn test_image_server_populate_cache_serve_stale() {
+    let failing = Arc::new(AtomicBool::new(false));
+    let url = serve_flaky(failing.clone()).await;
+    let key = CacheKey::ImageUrl(url.clone());
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Url(url).into()];
+    // retry the failed URL right away
+    config.http.max_backoff = 0;
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+    let state = &server.state;
+    assert_eq!(state.image_stale_since(&key), None);
+
+    // the refresh fails, so the cached copy is kept, and marked stale
+    failing.store(true, Ordering::SeqCst);
+    server.populate_cache().await;
+    assert_eq!(state.image_count(), 1);
+    assert_eq!(state.cache.get(key.clone()).unwrap().data, b"image");
+    assert!(state.image_stale_since(&key).is_some());
+    assert_eq!(state.stale_count(), 1);
+    assert_eq!(state.sources()[0].status, SourceStatus::Loaded);
+    assert!(state.sources()[0].last_error.is_some());
+
+    // until a refresh succeeds
+    failing.store(false, Ordering::SeqCst);
+    server.populate_cache().await;
+    assert_eq!(state.image_stale_since(&key), None);
+    assert_eq!(state.stale_count(), 0);
+}
+
+#[cfg(feature = "remote-sources")]
+#[tokio::test]
+async fn test_image_server_populate_cache_spool() {
+    let directory = TempDir::new().unwrap();
+    let image = serve_manifest().await.join("a.jpg").unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Url(image.clone()).into()];
+    config.cache.backend = CacheBackendType::FileSystem;
+    config.cache.directory = Some(directory.path().to_path_buf());
+    let server = ImageServer::with_config(config.clone());
+    server.populate_cache().await;
+    drop(server);
+
+    // restart without network: the image can no longer be fetched, so it is loaded from the spool
+    config.http.proxy = Some(Url::parse("http://127.0.0.1:9").unwrap());
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    let state = &server.state;
+    assert_eq!(state.cache.size(), 1);
+    assert!(state.cache.get(CacheKey::ImageUrl(image)).is_some());
+    assert_eq!(state.sources()[0].status, SourceStatus::Loaded);
+}
+
+#[cfg(feature = "remote-sources")]
+#[tokio::test]
+async fn test_image_server_populate_cache_circuit_breaker() {
+    let mut config = Config::default();
+    config.server.sources = vec![
+        ImageSource::Url(Url::parse("http://127.0.0.1:9/a.jpg").unwrap()).into(),
+        ImageSource::Url(Url::parse("http://127.0.0.1:9/b.jpg").unwrap()).into(),
+    ];
+    config.http.failure_threshold = 1;
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    // the host is not contacted again once its circuit is open
+    let state = &server.state;
+    assert_eq!(state.sources()[1].status, SourceStatus::Failed);
+    assert!(
+        state.sources()[1]
+            .last_error
+            .as_deref()
+            .is_some_and(|error| error.contains("Circuit open for http://127.0.0.1:9"))
+    );
+    let hosts = state.breakers().hosts(std::time::Instant::now());
+    assert_eq!(hosts.len(), 1);
+    assert_eq!(hosts[0].state, BreakerState::Open);
+    assert_eq!(hosts[0].consecutive_failures, 1);
+}
+
+#[rstest]
+#[case::reject(EvictionPolicy::Reject)]
+#[case::evict_oldest(EvictionPolicy::EvictOldest)]
+#[tokio::test]
+async fn test_image_server_populate_cache_max_bytes(#[case] eviction: EvictionPolicy) {
+    let dir = TempDir::new().unwrap();
+    for name in ["a.jpg", "b.jpg"] {
+        fs::copy("assets/blank.jpg", dir.path().join(name)).unwrap();
+    }
+    let size = fs::metadata("assets/blank.jpg").unwrap().len();
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(dir.path().to_path_buf()).into()];
+    config.cache.max_bytes = Some(size + size / 2);
+    config.cache.eviction = eviction;
+
+    let server = ImageServer::with_config(config);
+    server.populate_cache().await;
+
+    // only one of the images fits in the budget
+    let state = &server.state;
+    assert_eq!(state.cache.size(), 1);
+    assert_eq!(state.cache.bytes(), size);
 }
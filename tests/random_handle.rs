@@ -1,34 +1,387 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 
+use http_body_util::BodyExt;
 use pretty_assertions::assert_eq;
 use random_image_server::{
-    cache::{CacheKey, CacheValue},
-    handle_random_image,
-    state::ServerState,
+    ImageServer,
+    cache::{CacheKey, CacheValue, Validators},
+    config::{CacheBackendType, Config, ImageSource, SourceConfig},
+    handle_random_image, handle_random_image_json,
+    query::{RandomOrder, RandomQuery},
+    state::{ServedImage, ServerState},
 };
-use tokio::sync::RwLock;
+use rstest::rstest;
+
+/// A request for a random image, from a client without a cookie or address
+fn random_request() -> hyper::Request<()> {
+    hyper::Request::builder().uri("/random").body(()).unwrap()
+}
 
 #[tokio::test]
 async fn test_handle_random_image_empty_cache() {
-    let state = Arc::new(RwLock::new(ServerState::default()));
-    let result = handle_random_image(state).await;
+    let state = ServerState::default();
+    let result = handle_random_image(&random_request(), &state, &RandomQuery::default()).await;
     assert!(result.is_err());
 }
 
 #[tokio::test]
 async fn test_handle_random_image_with_cache() {
-    let mut server_state = ServerState::default();
+    let state = ServerState::default();
     let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
-    server_state.cache.set(key, value).unwrap();
-
-    let state = Arc::new(RwLock::new(server_state));
-    let result = handle_random_image(state).await;
+    state.cache.set(key, value).unwrap();
+    let result = handle_random_image(&random_request(), &state, &RandomQuery::default()).await;
     assert!(result.is_ok());
 
     let response = result.unwrap();
     assert_eq!(response.status(), hyper::StatusCode::OK);
 }
+
+#[tokio::test]
+async fn test_handle_random_image_streams_from_file_system_cache() {
+    let mut config = Config::default();
+    config.cache.backend = CacheBackendType::FileSystem;
+    let state = ServerState::with_config(&config);
+    let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    };
+    state.cache.set(key, value).unwrap();
+
+    let response = handle_random_image(&random_request(), &state, &RandomQuery::default())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        response.headers().get(hyper::header::ETAG).unwrap(),
+        &format!("\"{}\"", config.cache.hash.digest(&[1, 2, 3, 4]))
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body.as_ref(), [1, 2, 3, 4]);
+}
+
+#[rstest]
+#[case::in_memory(CacheBackendType::InMemory)]
+#[case::file_system(CacheBackendType::FileSystem)]
+#[tokio::test]
+async fn test_handle_random_image_json(#[case] backend: CacheBackendType) {
+    let mut config = Config::default();
+    config.cache.backend = backend;
+    let state = ServerState::with_config(&config);
+    let key = CacheKey::ImagePath(PathBuf::from("assets/blank.jpg"));
+    let data = std::fs::read("assets/blank.jpg").unwrap();
+    let dimensions = imagesize::blob_size(&data).unwrap();
+    let value = CacheValue {
+        data: data.clone(),
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    };
+    state.cache.set(key.clone(), value).unwrap();
+
+    let req = hyper::Request::builder()
+        .uri("/random.json")
+        .header(hyper::header::HOST, "example.com")
+        .body(())
+        .unwrap();
+    let response = handle_random_image_json(&req, &state, &RandomQuery::default()).unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        response.headers().get(hyper::header::CONTENT_TYPE).unwrap(),
+        "application/json"
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let image: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        image,
+        serde_json::json!({
+            "id": key.id(),
+            "url": format!("http://example.com/image/{}", key.id()),
+            "content_type": "image/jpeg",
+            "width": dimensions.width,
+            "height": dimensions.height,
+            "size": data.len(),
+        })
+    );
+    // the image is only served once it's retrieved from its link
+    assert_eq!(state.serve_counts.get(&key), 0);
+}
+
+#[test]
+fn test_handle_random_image_json_empty_cache() {
+    let state = ServerState::default();
+    let req = hyper::Request::builder()
+        .uri("/random.json")
+        .body(())
+        .unwrap();
+    assert!(handle_random_image_json(&req, &state, &RandomQuery::default()).is_err());
+}
+
+#[tokio::test]
+async fn test_handle_random_image_records_serve_count() {
+    let state = ServerState::default();
+    let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    };
+    state.cache.set(key.clone(), value).unwrap();
+    handle_random_image(&random_request(), &state, &RandomQuery::default())
+        .await
+        .unwrap();
+    handle_random_image(&random_request(), &state, &RandomQuery::default())
+        .await
+        .unwrap();
+
+    assert_eq!(state.serve_counts.get(&key), 2);
+}
+
+#[tokio::test]
+async fn test_handle_random_image_runs_serve_hooks() {
+    let served = Arc::new(Mutex::new(Vec::new()));
+    let server = ImageServer::new().on_image_served({
+        let served = served.clone();
+        move |key, image| served.lock().unwrap().push((key.clone(), image.clone()))
+    });
+    let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    };
+    server.state.cache.set(key.clone(), value).unwrap();
+    handle_random_image(&random_request(), &server.state, &RandomQuery::default())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        *served.lock().unwrap(),
+        [(
+            key,
+            ServedImage {
+                content_type: "image/jpeg".to_string(),
+                bytes: 4,
+                hash: server.config.cache.hash.digest(&[1, 2, 3, 4]),
+            }
+        )]
+    );
+}
+
+#[tokio::test]
+async fn test_handle_random_image_least_served() {
+    let state = ServerState::default();
+    let keys: Vec<CacheKey> = (0..3)
+        .map(|i| CacheKey::ImagePath(PathBuf::from(format!("/test/image{i}.jpg"))))
+        .collect();
+    for key in &keys {
+        let value = CacheValue {
+            data: vec![1, 2, 3, 4],
+            content_type: "image/jpeg".to_string(),
+            validators: Validators::default(),
+        };
+        state.cache.set(key.clone(), value).unwrap();
+    }
+    let query = RandomQuery {
+        order: RandomOrder::LeastServed,
+        ..RandomQuery::default()
+    };
+
+    // serving least served images first means every image is served once before any is repeated
+    for _ in 0..keys.len() {
+        handle_random_image(&random_request(), &state, &query)
+            .await
+            .unwrap();
+    }
+
+    for key in &keys {
+        assert_eq!(state.serve_counts.get(key), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_handle_random_image_source_weight() {
+    let mut config = Config::default();
+    config.server.sources = vec![
+        ImageSource::Path(PathBuf::from("/test/weighted")).into(),
+        SourceConfig {
+            weight: 0,
+            ..ImageSource::Path(PathBuf::from("/test/ignored")).into()
+        },
+    ];
+    let state = ServerState::with_config(&config);
+    let weighted = CacheKey::ImagePath(PathBuf::from("/test/weighted/image.jpg"));
+    let ignored = CacheKey::ImagePath(PathBuf::from("/test/ignored/image.jpg"));
+    for (index, key) in [&weighted, &ignored].into_iter().enumerate() {
+        let value = CacheValue {
+            data: vec![1, 2, 3, 4],
+            content_type: "image/jpeg".to_string(),
+            validators: Validators::default(),
+        };
+        state.store_image(index, key.clone(), value).unwrap();
+    }
+    assert_eq!(state.image_weight(&weighted), 1);
+    assert_eq!(state.image_weight(&ignored), 0);
+    for _ in 0..10 {
+        handle_random_image(&random_request(), &state, &RandomQuery::default())
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(state.serve_counts.get(&weighted), 10);
+    assert_eq!(state.serve_counts.get(&ignored), 0);
+}
+
+#[tokio::test]
+async fn test_handle_random_image_no_repeat_window_larger_than_cache() {
+    let mut config = Config::default();
+    config.server.no_repeat_window = Some(5);
+    let state = ServerState::with_config(&config);
+    for name in ["a", "b"] {
+        let value = CacheValue {
+            data: name.as_bytes().to_vec(),
+            content_type: "image/jpeg".to_string(),
+            validators: Validators::default(),
+        };
+        let key = CacheKey::ImagePath(PathBuf::from(format!("/test/{name}.jpg")));
+        state.store_image(0, key, value).unwrap();
+    }
+
+    // once every image was served recently, the one served least recently is chosen
+    let mut served = Vec::new();
+    for _ in 0..6 {
+        let response = handle_random_image(&random_request(), &state, &RandomQuery::default())
+            .await
+            .unwrap();
+        served.push(response.headers()["x-image-id"].clone());
+    }
+    for window in served.windows(2) {
+        assert_ne!(window[0], window[1]);
+    }
+}
+
+/// The start of a PNG of the given dimensions, enough for them to be read
+fn png_header(width: u32, height: u32) -> Vec<u8> {
+    let mut data = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+    data.extend(width.to_be_bytes());
+    data.extend(height.to_be_bytes());
+    data.extend([8, 6, 0, 0, 0]);
+    data
+}
+
+#[rstest]
+#[case::landscape("orientation=landscape", &["landscape"])]
+#[case::portrait("orientation=portrait", &["portrait"])]
+#[case::square("orientation=square", &["square"])]
+#[case::min_width("min_width=30", &["landscape", "square"])]
+#[case::min_height("min_height=30", &["portrait", "square"])]
+#[case::both("min_width=30&min_height=30", &["square"])]
+#[case::unfiltered("", &["landscape", "portrait", "square", "unknown"])]
+#[tokio::test]
+async fn test_handle_random_image_dimensions(#[case] query: &str, #[case] expected: &[&str]) {
+    let state = ServerState::default();
+    for (name, data) in [
+        ("landscape", png_header(40, 20)),
+        ("portrait", png_header(20, 40)),
+        ("square", png_header(30, 30)),
+        ("unknown", vec![1, 2, 3, 4]),
+    ] {
+        let value = CacheValue {
+            data,
+            content_type: "image/png".to_string(),
+            validators: Validators::default(),
+        };
+        let key = CacheKey::ImagePath(PathBuf::from(format!("/test/{name}.png")));
+        state.store_image(0, key, value).unwrap();
+    }
+    let query = RandomQuery::parse(Some(query)).unwrap();
+
+    for _ in 0..20 {
+        handle_random_image(&random_request(), &state, &query)
+            .await
+            .unwrap();
+    }
+    let served: Vec<String> = state
+        .cache
+        .keys()
+        .filter(|key| state.serve_counts.get(key) > 0)
+        .map(|key| {
+            key.to_string()
+                .trim_start_matches("/test/")
+                .trim_end_matches(".png")
+                .to_string()
+        })
+        .collect();
+    for name in &served {
+        assert!(expected.contains(&name.as_str()), "{name}");
+    }
+    if expected.len() == 1 {
+        assert_eq!(served, expected);
+    }
+}
+
+#[tokio::test]
+async fn test_handle_random_image_dimensions_none_fit() {
+    let state = ServerState::default();
+    let value = CacheValue {
+        data: png_header(40, 20),
+        content_type: "image/png".to_string(),
+        validators: Validators::default(),
+    };
+    let key = CacheKey::ImagePath(PathBuf::from("/test/landscape.png"));
+    state.store_image(0, key, value).unwrap();
+
+    let query = RandomQuery::parse(Some("orientation=portrait")).unwrap();
+    assert!(
+        handle_random_image(&random_request(), &state, &query)
+            .await
+            .is_err()
+    );
+    let query = RandomQuery::parse(Some("min_width=41")).unwrap();
+    assert!(handle_random_image_json(&random_request(), &state, &query).is_err());
+}
+
+#[rstest]
+#[case::png("format=png", &["image/png"])]
+#[case::several("format=gif,jpg", &["image/gif", "image/jpeg"])]
+#[case::with_dimensions("format=png,gif&orientation=portrait", &["image/gif"])]
+#[tokio::test]
+async fn test_handle_random_image_format(#[case] query: &str, #[case] expected: &[&str]) {
+    let state = ServerState::default();
+    for (name, data, content_type) in [
+        ("a.png", png_header(40, 20), "image/png"),
+        ("b.jpg", vec![1, 2, 3, 4], "image/jpeg"),
+        ("c.gif", png_header(20, 40), "image/gif"),
+    ] {
+        let value = CacheValue {
+            data,
+            content_type: content_type.to_string(),
+            validators: Validators::default(),
+        };
+        let key = CacheKey::ImagePath(PathBuf::from(format!("/test/{name}")));
+        state.store_image(0, key, value).unwrap();
+    }
+    let query = RandomQuery::parse(Some(query)).unwrap();
+
+    for _ in 0..10 {
+        let response = handle_random_image(&random_request(), &state, &query)
+            .await
+            .unwrap();
+        let content_type = response.headers().get(hyper::header::CONTENT_TYPE).unwrap();
+        assert!(expected.contains(&content_type.to_str().unwrap()));
+    }
+    let query = RandomQuery::parse(Some("format=webp")).unwrap();
+    assert!(
+        handle_random_image(&random_request(), &state, &query)
+            .await
+            .is_err()
+    );
+}
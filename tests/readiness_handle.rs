@@ -0,0 +1,93 @@
+use std::{path::PathBuf, sync::atomic::Ordering};
+
+use http_body_util::BodyExt;
+use pretty_assertions::assert_eq;
+use random_image_server::{
+    cache::{CacheKey, CacheValue, Validators},
+    config::{Config, ImageSource},
+    handle_readiness,
+    state::ServerState,
+};
+
+#[tokio::test]
+async fn test_handle_readiness_not_populated() {
+    let state = ServerState::default();
+    let response = handle_readiness(&state).unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/problem+json"
+    );
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(problem["status"], 503);
+    assert_eq!(problem["title"], "Service Unavailable");
+}
+
+#[test]
+fn test_handle_readiness_empty_cache() {
+    let state = ServerState::default();
+    state.populated.store(true, Ordering::Relaxed);
+    let response = handle_readiness(&state).unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[test]
+fn test_handle_readiness_ready() {
+    let state = ServerState::default();
+    let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    };
+    state.cache.set(key, value).unwrap();
+    state.populated.store(true, Ordering::Relaxed);
+    let response = handle_readiness(&state).unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_handle_readiness_sources_failed() {
+    let sources = [
+        ImageSource::Path(PathBuf::from("/test/a")),
+        ImageSource::Path(PathBuf::from("/test/b")),
+    ];
+    let mut config = Config::default();
+    config.server.sources = sources.iter().cloned().map(Into::into).collect();
+    let state = ServerState::with_config(&config);
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    };
+    state
+        .store_image(
+            0,
+            CacheKey::ImagePath(PathBuf::from("/test/a/1.jpg")),
+            value,
+        )
+        .unwrap();
+    state.populated.store(true, Ordering::Relaxed);
+    state.record_source_outcome(&sources[0], 1, None);
+    state.record_source_outcome(&sources[1], 0, Some("boom".to_string()));
+    assert_eq!(
+        handle_readiness(&state).unwrap().status(),
+        hyper::StatusCode::OK
+    );
+
+    // the images kept from before are still cached, but no source could be loaded
+    state.record_source_outcome(&sources[0], 0, Some("boom".to_string()));
+    let response = handle_readiness(&state).unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::SERVICE_UNAVAILABLE);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(problem["detail"], "Every image source failed to load");
+    assert!(!state.is_ready());
+
+    state.mark_source_refreshing(&sources[0]);
+    assert!(!state.is_ready());
+    state.record_source_outcome(&sources[0], 1, None);
+    assert!(state.is_ready());
+}
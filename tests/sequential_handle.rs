@@ -1,32 +1,36 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, atomic::Ordering},
+};
 
 use pretty_assertions::assert_eq;
 use random_image_server::{
-    cache::{CacheKey, CacheValue},
-    handle_sequential_image,
+    cache::{CacheKey, CacheValue, Validators},
+    config::{Config, ImageSource, PlaylistConfig},
+    handle_playlist_image, handle_sequential_image,
+    query::SequentialQuery,
     state::ServerState,
 };
-use tokio::sync::RwLock;
 
 #[tokio::test]
 async fn test_handle_sequential_image_empty_cache() {
-    let state = Arc::new(RwLock::new(ServerState::default()));
-    let result = handle_sequential_image(state).await;
+    let state = ServerState::default();
+    let result = handle_sequential_image(&state, &SequentialQuery::default()).await;
     assert!(result.is_err());
 }
 
 #[tokio::test]
 async fn test_handle_sequential_image_with_cache() {
-    let mut server_state = ServerState::default();
+    let state = ServerState::default();
     let key = CacheKey::ImagePath(PathBuf::from("/test/image.jpg"));
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
-    server_state.cache.set(key, value).unwrap();
-
-    let state = Arc::new(RwLock::new(server_state));
-    let result = handle_sequential_image(state).await;
+    state.cache.set(key, value).unwrap();
+    let result = handle_sequential_image(&state, &SequentialQuery::default()).await;
     assert!(result.is_ok());
 
     let response = result.unwrap();
@@ -35,29 +39,251 @@ async fn test_handle_sequential_image_with_cache() {
 
 #[tokio::test]
 async fn test_handle_sequential_image_index_increment() {
-    let mut server_state = ServerState::default();
+    let state = ServerState::default();
     let key1 = CacheKey::ImagePath(PathBuf::from("/test/image1.jpg"));
     let key2 = CacheKey::ImagePath(PathBuf::from("/test/image2.jpg"));
     let value = CacheValue {
         data: vec![1, 2, 3, 4],
         content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
     };
-    server_state.cache.set(key1, value.clone()).unwrap();
-    server_state.cache.set(key2, value).unwrap();
-
-    let state = Arc::new(RwLock::new(server_state));
+    state.cache.set(key1, value.clone()).unwrap();
+    state.cache.set(key2, value).unwrap();
 
     // First call should use index 0
-    let _result1 = handle_sequential_image(state.clone()).await.unwrap();
+    let _result1 = handle_sequential_image(&state, &SequentialQuery::default())
+        .await
+        .unwrap();
 
     // Check that index has incremented
-    let current_index = state.read().await.current_index;
+    let current_index = state.current_index.load(Ordering::Relaxed);
     assert_eq!(current_index, 1);
 
     // Second call should use index 1
-    let _result2 = handle_sequential_image(state.clone()).await.unwrap();
+    let _result2 = handle_sequential_image(&state, &SequentialQuery::default())
+        .await
+        .unwrap();
 
     // Check that index wraps back to 0
-    let current_index = state.read().await.current_index;
+    let current_index = state.current_index.load(Ordering::Relaxed);
     assert_eq!(current_index, 0);
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_handle_sequential_image_concurrent() {
+    let state = Arc::new(ServerState::default());
+    let keys: Vec<CacheKey> = (0..4)
+        .map(|i| CacheKey::ImagePath(PathBuf::from(format!("/test/image{i}.jpg"))))
+        .collect();
+    for key in &keys {
+        let value = CacheValue {
+            data: vec![1, 2, 3, 4],
+            content_type: "image/jpeg".to_string(),
+            validators: Validators::default(),
+        };
+        state.cache.set(key.clone(), value).unwrap();
+    }
+
+    // requests only share the state, so they can be served from many threads at once
+    let requests: Vec<_> = (0..8)
+        .map(|_| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                handle_sequential_image(&state, &SequentialQuery::default()).await
+            })
+        })
+        .collect();
+    for request in requests {
+        assert_eq!(
+            request.await.unwrap().unwrap().status(),
+            hyper::StatusCode::OK
+        );
+    }
+
+    // every image was served exactly twice
+    for key in &keys {
+        assert_eq!(state.serve_counts.get(key), 2);
+    }
+    assert_eq!(state.current_index.load(Ordering::Relaxed), 0);
+}
+
+#[tokio::test]
+async fn test_handle_sequential_image_format() {
+    let state = ServerState::default();
+    for (name, content_type) in [
+        ("a.jpg", "image/jpeg"),
+        ("b.png", "image/png"),
+        ("c.webp", "image/webp"),
+        ("d.png", "image/png"),
+    ] {
+        let value = CacheValue {
+            data: name.as_bytes().to_vec(),
+            content_type: content_type.to_string(),
+            validators: Validators::default(),
+        };
+        state
+            .cache
+            .set(CacheKey::ImagePath(PathBuf::from(name)), value)
+            .unwrap();
+    }
+
+    // only the PNGs and WebPs are gone through, in the order of their formats
+    let query = SequentialQuery::parse(Some("format=png,webp")).unwrap();
+    let mut served = Vec::new();
+    for _ in 0..6 {
+        let response = handle_sequential_image(&state, &query).await.unwrap();
+        served.push(
+            response
+                .headers()
+                .get(hyper::header::CONTENT_TYPE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+        );
+    }
+    assert_eq!(
+        served,
+        [
+            "image/png",
+            "image/png",
+            "image/webp",
+            "image/png",
+            "image/png",
+            "image/webp"
+        ]
+    );
+
+    let query = SequentialQuery::parse(Some("format=gif")).unwrap();
+    assert!(handle_sequential_image(&state, &query).await.is_err());
+}
+
+#[cfg(feature = "transforms")]
+#[tokio::test]
+async fn test_failed_transform_keeps_image() {
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(PathBuf::from("/test")).into()];
+    config.playlists = BTreeMap::from([(
+        "lobby".to_string(),
+        PlaylistConfig {
+            items: vec![ImageSource::Path(PathBuf::from("/test/a.jpg"))],
+        },
+    )]);
+    let state = ServerState::with_config(&config);
+    let key = CacheKey::ImagePath(PathBuf::from("/test/a.jpg"));
+    // not a JPEG the transforms can decode
+    let value = CacheValue {
+        data: vec![1, 2, 3, 4],
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    };
+    state.store_image(0, key.clone(), value).unwrap();
+
+    let query = SequentialQuery::parse(Some("rotate=90")).unwrap();
+    assert!(handle_sequential_image(&state, &query).await.is_err());
+    assert!(
+        handle_playlist_image(&state, "lobby", &query)
+            .await
+            .is_err()
+    );
+    assert!(state.cache.get(key).is_some());
+    assert!(
+        handle_sequential_image(&state, &SequentialQuery::default())
+            .await
+            .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_handle_playlist_image() {
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(PathBuf::from("/test")).into()];
+    config.server.expose_sources = true;
+    config.playlists = BTreeMap::from([
+        (
+            "lobby".to_string(),
+            PlaylistConfig {
+                items: vec![
+                    ImageSource::Path(PathBuf::from("/test/dir/d.png")),
+                    ImageSource::Path(PathBuf::from("/test/dir")),
+                    ImageSource::Path(PathBuf::from("/test/a.jpg")),
+                    ImageSource::Path(PathBuf::from("/test/missing.jpg")),
+                ],
+            },
+        ),
+        (
+            "everything".to_string(),
+            PlaylistConfig {
+                items: vec![ImageSource::Path(PathBuf::from("/test"))],
+            },
+        ),
+    ]);
+    let state = ServerState::with_config(&config);
+    for (name, content_type) in [
+        ("/test/a.jpg", "image/jpeg"),
+        ("/test/b.jpg", "image/jpeg"),
+        ("/test/dir/c.jpg", "image/jpeg"),
+        ("/test/dir/d.png", "image/png"),
+    ] {
+        let value = CacheValue {
+            data: name.as_bytes().to_vec(),
+            content_type: content_type.to_string(),
+            validators: Validators::default(),
+        };
+        state
+            .store_image(0, CacheKey::ImagePath(PathBuf::from(name)), value)
+            .unwrap();
+    }
+    let play = async |name: &str, query: &SequentialQuery, count: usize| -> Vec<String> {
+        let mut played = Vec::new();
+        for _ in 0..count {
+            let response = handle_playlist_image(&state, name, query).await.unwrap();
+            played.push(
+                response.headers()["x-image-source"]
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            );
+        }
+        played
+    };
+
+    // the items are played in order, and the images of each item in the order they were loaded
+    assert_eq!(
+        play("lobby", &SequentialQuery::default(), 5).await,
+        [
+            "/test/dir/d.png",
+            "/test/dir/c.jpg",
+            "/test/dir/d.png",
+            "/test/a.jpg",
+            "/test/dir/d.png"
+        ]
+    );
+    // each playlist has a position of its own, independent of `/sequential`
+    assert_eq!(
+        play("everything", &SequentialQuery::default(), 2).await,
+        ["/test/a.jpg", "/test/b.jpg"]
+    );
+    assert_eq!(state.current_index.load(Ordering::Relaxed), 0);
+    assert_eq!(
+        play("lobby", &SequentialQuery::default(), 1).await,
+        ["/test/dir/c.jpg"]
+    );
+
+    let query = SequentialQuery::parse(Some("format=png")).unwrap();
+    assert_eq!(
+        play("lobby", &query, 2).await,
+        ["/test/dir/d.png", "/test/dir/d.png"]
+    );
+    let query = SequentialQuery::parse(Some("format=gif")).unwrap();
+    assert!(
+        handle_playlist_image(&state, "lobby", &query)
+            .await
+            .is_err()
+    );
+    assert!(
+        handle_playlist_image(&state, "missing", &SequentialQuery::default())
+            .await
+            .is_err()
+    );
+}
@@ -6,7 +6,14 @@ use hyper_util::{
     server::conn::auto,
 };
 use pretty_assertions::{assert_eq, assert_ne};
-use random_image_server::{ImageServer, config::ImageSource, handle_request};
+use random_image_server::{
+    ImageServer,
+    cache::{CacheBackend, CacheKey, TieredCache},
+    config::{
+        CacheBackendType, CacheConfig, CollectionConfig, Config, HashedBasicAuth, ImageSource,
+    },
+    handle_request, signing,
+};
 use rstest::{fixture, rstest};
 use tokio::net::TcpListener;
 
@@ -21,12 +28,21 @@ struct TestState {
 
 impl TestState {
     async fn new(requests_to_handle: usize) -> Self {
-        let mut server = ImageServer::default();
-        server.config.server.sources = vec![ImageSource::Path(PathBuf::from("assets"))];
+        Self::with_config(requests_to_handle, Config::default()).await
+    }
+
+    async fn with_config(requests_to_handle: usize, mut config: Config) -> Self {
+        config.server.sources = vec![ImageSource::Path(PathBuf::from("assets")).into()];
+        Self::with_server(requests_to_handle, ImageServer::with_config(config)).await
+    }
 
+    async fn with_server<C: CacheBackend + 'static>(
+        requests_to_handle: usize,
+        server: ImageServer<C>,
+    ) -> Self {
         // Populate the cache with images from configured sources
         server.populate_cache().await;
-        assert_ne!(server.state.read().await.cache.size(), 0);
+        assert_ne!(server.state.cache.size(), 0);
 
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listener.local_addr().unwrap();
@@ -90,11 +106,134 @@ async fn test_handle_request_health(#[future] test_one_request: TestState) {
 
     let response = reqwest::get(format!("http://{addr}/health")).await.unwrap();
     assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/json"
+    );
+
+    let health: serde_json::Value =
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["images"], 1);
+    let sources = health["sources"].as_array().unwrap();
+    assert_eq!(sources.len(), 1);
+    assert_eq!(sources[0]["status"], "loaded");
+    assert_eq!(sources[0]["images"], 1);
+    assert!(sources[0]["last_error"].is_null());
+    assert!(sources[0]["last_refresh"].is_u64());
+
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_livez(#[future] test_one_request: TestState) {
+    let TestState { addr, join_handle } = test_one_request.await;
+
+    let response = reqwest::get(format!("http://{addr}/livez")).await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
     assert_eq!(response.text().await.unwrap(), "OK");
 
     join_handle.await.unwrap();
 }
 
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_readyz(#[future] test_one_request: TestState) {
+    let TestState { addr, join_handle } = test_one_request.await;
+
+    let response = reqwest::get(format!("http://{addr}/readyz")).await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let readiness: serde_json::Value =
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(readiness["status"], "ready");
+    assert_eq!(readiness["images"], 1);
+
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_version(#[future] test_one_request: TestState) {
+    let TestState { addr, join_handle } = test_one_request.await;
+
+    let response = reqwest::get(format!("http://{addr}/version"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/json"
+    );
+
+    let version: serde_json::Value =
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    assert!(version["git_commit"].is_string());
+    assert!(version["rustc_version"].is_string());
+    assert!(version["features"].is_array());
+
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_config_schema(#[future] test_one_request: TestState) {
+    let TestState { addr, join_handle } = test_one_request.await;
+
+    let response = reqwest::get(format!("http://{addr}/admin/config/schema"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/json"
+    );
+    let schema: serde_json::Value =
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(schema, Config::json_schema());
+
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_generates_request_id(#[future] test_one_request: TestState) {
+    let TestState { addr, join_handle } = test_one_request.await;
+
+    let response = reqwest::get(format!("http://{addr}/livez")).await.unwrap();
+    let request_id = response.headers().get("X-Request-Id").unwrap();
+    assert!(uuid::Uuid::parse_str(request_id.to_str().unwrap()).is_ok());
+    assert!(!response.bytes().await.unwrap().is_empty());
+
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[case::honored("abc-123", "abc-123")]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_propagates_request_id(#[case] incoming: &str, #[case] expected: &str) {
+    let TestState { addr, join_handle } = TestState::new(1).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("http://{addr}/unknown"))
+        .header("X-Request-Id", incoming)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+    assert_eq!(response.headers().get("X-Request-Id").unwrap(), expected);
+    assert!(!response.bytes().await.unwrap().is_empty());
+
+    join_handle.await.unwrap();
+}
+
 #[rstest]
 #[timeout(Duration::from_secs(2))]
 #[tokio::test]
@@ -106,7 +245,14 @@ async fn test_handle_request_not_found(#[future] test_one_request: TestState) {
         .unwrap();
 
     assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
-    assert_eq!(response.text().await.unwrap(), "Not Found");
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/problem+json"
+    );
+    let problem: serde_json::Value =
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(problem["status"], 404);
+    assert_eq!(problem["title"], "Not Found");
     join_handle.await.unwrap();
 }
 
@@ -129,6 +275,152 @@ async fn test_handle_request_random_image(#[future] test_one_request: TestState)
     join_handle.await.unwrap();
 }
 
+#[rstest]
+#[case::uniform("/random?order=uniform", hyper::StatusCode::OK)]
+#[case::least_served("/random?order=least_served", hyper::StatusCode::OK)]
+#[case::invalid("/random?order=most_served", hyper::StatusCode::BAD_REQUEST)]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_random_image_order(
+    #[case] path: &str,
+    #[case] expected: hyper::StatusCode,
+) {
+    let TestState { addr, join_handle } = TestState::new(1).await;
+
+    let response = reqwest::get(format!("http://{addr}{path}")).await.unwrap();
+    assert_eq!(response.status(), expected);
+    assert!(!response.bytes().await.unwrap().is_empty());
+
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_image_stats() {
+    let TestState { addr, join_handle } = TestState::new(1).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{addr}/random"))
+        .send()
+        .await
+        .unwrap();
+    assert!(!response.bytes().await.unwrap().is_empty());
+
+    let response = client
+        .get(format!("http://{addr}/stats/images"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let stats: serde_json::Value =
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    let images = stats["images"].as_array().unwrap();
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0]["served"], 1);
+    assert!(images[0]["source"].as_str().unwrap().ends_with("blank.jpg"));
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_cache_stats() {
+    let mut config = Config::default();
+    config.cache.backend = CacheBackendType::FileSystem;
+    let TestState { addr, join_handle } = TestState::with_config(1, config).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{addr}/random"))
+        .send()
+        .await
+        .unwrap();
+    assert!(!response.bytes().await.unwrap().is_empty());
+
+    let response = client
+        .get(format!("http://{addr}/stats/cache"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let stats: serde_json::Value =
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(stats["backend"], "FileSystem");
+    assert_eq!(stats["images"], 1);
+    assert_eq!(stats["hits"], 1);
+    assert_eq!(stats["misses"], 0);
+    assert_eq!(stats["validation_failures"], 0);
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_typed_cache() {
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(PathBuf::from("assets")).into()];
+    let server = ImageServer::with_cache(config, TieredCache::new());
+    let TestState { addr, join_handle } = TestState::with_server(1, server).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{addr}/stats/cache"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let stats: serde_json::Value =
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(stats["backend"], "Tiered");
+    assert_eq!(stats["images"], 1);
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_metrics() {
+    let TestState { addr, join_handle } = TestState::new(1).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{addr}/random"))
+        .send()
+        .await
+        .unwrap();
+    assert!(!response.bytes().await.unwrap().is_empty());
+    let response = client
+        .get(format!("http://{addr}/nonexistent"))
+        .send()
+        .await
+        .unwrap();
+    assert!(!response.bytes().await.unwrap().is_empty());
+
+    let response = client
+        .get(format!("http://{addr}/metrics"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let body = response.text().await.unwrap();
+    assert!(body.contains("random_image_server_responses_total{status=\"2xx\"} 1\n"));
+    assert!(body.contains("random_image_server_responses_total{status=\"4xx\"} 1\n"));
+    assert!(body.contains("random_image_server_images_served_total 1\n"));
+    assert!(body.contains("random_image_server_cache_images 1\n"));
+    assert!(body.contains("random_image_server_cache_hits_total{backend=\"InMemory\"} 1\n"));
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
 #[rstest]
 #[timeout(Duration::from_secs(2))]
 #[tokio::test]
@@ -148,3 +440,1051 @@ async fn test_handle_request_sequential_image(#[future] test_one_request: TestSt
     assert!(!response.bytes().await.unwrap().is_empty());
     join_handle.await.unwrap();
 }
+
+#[rstest]
+#[case::random("/random", false)]
+#[case::sequential("/sequential", false)]
+#[case::random_exposed("/random", true)]
+#[case::sequential_exposed("/sequential", true)]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_image_headers(#[case] path: &str, #[case] expose_sources: bool) {
+    let mut config = Config::default();
+    config.server.expose_sources = expose_sources;
+    let content_id = config
+        .cache
+        .hash
+        .digest(&std::fs::read("assets/blank.jpg").unwrap());
+    let TestState { addr, join_handle } = TestState::with_config(1, config).await;
+
+    let response = reqwest::get(format!("http://{addr}{path}")).await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(response.headers().get("X-Image-Id").unwrap(), &content_id);
+    let source = PathBuf::from("assets/blank.jpg").canonicalize().unwrap();
+    assert_eq!(
+        response
+            .headers()
+            .get("X-Image-Source")
+            .map(|source| source.to_str().unwrap().to_string()),
+        expose_sources.then(|| source.display().to_string())
+    );
+    assert!(!response.bytes().await.unwrap().is_empty());
+
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_random_no_repeat_window() {
+    // three images, which differ by a trailing byte
+    let dir = tempfile::tempdir().unwrap();
+    let data = std::fs::read("assets/blank.jpg").unwrap();
+    for byte in 0..3 {
+        let mut image = data.clone();
+        image.push(byte);
+        std::fs::write(dir.path().join(format!("{byte}.jpg")), image).unwrap();
+    }
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(dir.path().to_path_buf()).into()];
+    config.server.no_repeat_window = Some(2);
+    let TestState { addr, join_handle } =
+        TestState::with_server(1, ImageServer::with_config(config)).await;
+
+    let client = reqwest::Client::new();
+    let mut served = Vec::new();
+    for _ in 0..9 {
+        let response = client
+            .get(format!("http://{addr}/random"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        let id = response.headers().get("X-Image-Id").unwrap().clone();
+        response.bytes().await.unwrap();
+        served.push(id);
+    }
+    // the two images served last are never chosen, leaving only one to choose from
+    for window in served.windows(3) {
+        assert_ne!(window[0], window[1]);
+        assert_ne!(window[0], window[2]);
+        assert_ne!(window[1], window[2]);
+    }
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[case::query("/random?sticky=300", None)]
+#[case::config("/random", Some(Duration::from_secs(300)))]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_random_sticky(#[case] path: &str, #[case] sticky: Option<Duration>) {
+    // three images, which differ by a trailing byte
+    let dir = tempfile::tempdir().unwrap();
+    let data = std::fs::read("assets/blank.jpg").unwrap();
+    for byte in 0..3 {
+        let mut image = data.clone();
+        image.push(byte);
+        std::fs::write(dir.path().join(format!("{byte}.jpg")), image).unwrap();
+    }
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(dir.path().to_path_buf()).into()];
+    config.server.sticky = sticky;
+    // without the pin, the image served next would always be another one
+    config.server.no_repeat_window = Some(2);
+    let TestState { addr, join_handle } =
+        TestState::with_server(1, ImageServer::with_config(config)).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{addr}{path}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let id = response.headers().get("X-Image-Id").unwrap().clone();
+    let set_cookie = response.headers().get("Set-Cookie").unwrap();
+    assert!(
+        set_cookie
+            .to_str()
+            .unwrap()
+            .ends_with("; Path=/; Max-Age=300; HttpOnly; SameSite=Lax")
+    );
+    let cookie = set_cookie
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string();
+    response.bytes().await.unwrap();
+
+    for _ in 0..5 {
+        let response = client
+            .get(format!("http://{addr}{path}"))
+            .header("Cookie", &cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers().get("X-Image-Id").unwrap(), &id);
+        assert!(response.headers().get("Set-Cookie").is_none());
+        response.bytes().await.unwrap();
+    }
+
+    // the image isn't pinned to other clients, or with `?sticky=0`
+    for (path, cookie) in [
+        (path, "random_image_client=someone-else"),
+        ("/random?sticky=0", cookie.as_str()),
+    ] {
+        let response = client
+            .get(format!("http://{addr}{path}"))
+            .header("Cookie", cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_ne!(response.headers().get("X-Image-Id").unwrap(), &id);
+        response.bytes().await.unwrap();
+    }
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[case::root("/images", hyper::StatusCode::OK)]
+#[case::root_slash("/images/", hyper::StatusCode::OK)]
+#[case::health("/images/health", hyper::StatusCode::OK)]
+#[case::random("/images/random", hyper::StatusCode::OK)]
+#[case::unprefixed("/random", hyper::StatusCode::NOT_FOUND)]
+#[case::partial_prefix("/imagesrandom", hyper::StatusCode::NOT_FOUND)]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_base_path(#[case] path: &str, #[case] expected: hyper::StatusCode) {
+    let mut config = Config::default();
+    config.server.base_path = "/images".to_string();
+    let TestState { addr, join_handle } = TestState::with_config(1, config).await;
+
+    let response = reqwest::get(format!("http://{addr}{path}")).await.unwrap();
+    assert_eq!(response.status(), expected);
+    assert!(!response.bytes().await.unwrap().is_empty());
+
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_list_and_image_by_id() {
+    let mut config = Config::default();
+    config.server.base_path = "/images".to_string();
+    let TestState { addr, join_handle } = TestState::with_config(1, config).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{addr}/images/list"))
+        .header("X-Forwarded-Host", "untrusted.example.com")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/json"
+    );
+
+    let list: serde_json::Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    let images = list["images"].as_array().unwrap();
+    assert_eq!(images.len(), 1);
+    let id = images[0]["id"].as_str().unwrap();
+    let url = images[0]["url"].as_str().unwrap();
+    assert_eq!(url, format!("http://{addr}/images/image/{id}"));
+
+    // the connection is kept alive, so the image request is handled by the same connection
+    let response = client.get(url).send().await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "image/jpeg"
+    );
+    assert!(!response.bytes().await.unwrap().is_empty());
+
+    let response = client
+        .get(format!("http://{addr}/images/image/unknown"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+    let problem: serde_json::Value =
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(problem["detail"], "No image with id unknown");
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_signed_image() {
+    let mut config = Config::default();
+    config.signing.secret = Some("secret".to_string());
+    config.signing.required = true;
+    let key = CacheKey::ImagePath(PathBuf::from("assets/blank.jpg").canonicalize().unwrap());
+    let link = signing::signed_link(&config, &key.id(), Duration::from_secs(60)).unwrap();
+    let TestState { addr, join_handle } = TestState::with_config(1, config).await;
+
+    let client = reqwest::Client::new();
+    let get = |path: String| client.get(format!("http://{addr}{path}")).send();
+    // every body is read, so that the connection is reused by the next request
+    let response = get(link).await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert!(!response.bytes().await.unwrap().is_empty());
+    for path in [
+        format!("/image/{}", key.id()),
+        format!("/image/{}?expires=1&sig=AAAA", key.id()),
+        "/random".to_string(),
+        "/list".to_string(),
+    ] {
+        let response = get(path).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::FORBIDDEN);
+        assert_eq!(response.text().await.unwrap(), "Forbidden");
+    }
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[case::allowed(
+    Some("https://blog.example.com/post"),
+    None,
+    hyper::StatusCode::OK,
+    Some("image/jpeg")
+)]
+#[case::direct(None, None, hyper::StatusCode::OK, Some("image/jpeg"))]
+#[case::refused(
+    Some("https://evil.example.net/"),
+    None,
+    hyper::StatusCode::FORBIDDEN,
+    None
+)]
+#[case::placeholder(
+    Some("https://evil.example.net/"),
+    Some("assets/blank.jpg"),
+    hyper::StatusCode::OK,
+    Some("image/jpeg")
+)]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_hotlink(
+    #[case] referer: Option<&str>,
+    #[case] placeholder: Option<&str>,
+    #[case] status: hyper::StatusCode,
+    #[case] content_type: Option<&str>,
+) {
+    let mut config = Config::default();
+    config.server.allowed_referers = vec!["example.com".to_string()];
+    config.server.hotlink_placeholder = placeholder.map(PathBuf::from);
+    let TestState { addr, join_handle } = TestState::with_config(1, config).await;
+
+    let client = reqwest::Client::new();
+    let mut request = client.get(format!("http://{addr}/random"));
+    if let Some(referer) = referer {
+        request = request.header("Referer", referer);
+    }
+    let response = request.send().await.unwrap();
+    assert_eq!(response.status(), status);
+    assert_eq!(
+        response
+            .headers()
+            .get("Content-Type")
+            .map(|value| value.to_str().unwrap()),
+        content_type
+    );
+    // the placeholder must not be cached in place of the image
+    assert_eq!(
+        response
+            .headers()
+            .get("Cache-Control")
+            .is_some_and(|value| value == "no-store"),
+        placeholder.is_some()
+    );
+    assert!(!response.bytes().await.unwrap().is_empty());
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(5))]
+#[tokio::test]
+async fn test_handle_request_basic_auth() {
+    let mut config = Config::default();
+    config.server.auth.basic = Some(HashedBasicAuth {
+        username: "admin".to_string(),
+        password_hash: bcrypt::hash("secret", 4).unwrap(),
+    });
+    let TestState { addr, join_handle } = TestState::with_config(1, config).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("http://{addr}/random"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::UNAUTHORIZED);
+    assert!(
+        response.headers()["WWW-Authenticate"]
+            .to_str()
+            .unwrap()
+            .starts_with("Basic realm=")
+    );
+    assert_eq!(response.text().await.unwrap(), "Unauthorized");
+
+    let response = client
+        .get(format!("http://{addr}/random"))
+        .basic_auth("admin", Some("wrong"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::UNAUTHORIZED);
+    assert_eq!(response.text().await.unwrap(), "Unauthorized");
+
+    let response = client
+        .get(format!("http://{addr}/random"))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert!(!response.bytes().await.unwrap().is_empty());
+
+    // health checks don't require authentication
+    let response = client
+        .get(format!("http://{addr}/livez"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "OK");
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_etag(#[future] test_one_request: TestState) {
+    let TestState { addr, join_handle } = test_one_request.await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("http://{addr}/random"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let etag = response.headers().get("ETag").unwrap().clone();
+    let body = response.bytes().await.unwrap();
+    assert_eq!(
+        etag,
+        format!("\"{}\"", blake3::hash(&body).to_hex()).as_str()
+    );
+
+    // the connection is kept alive, so the conditional request is handled by the same connection
+    let response = client
+        .get(format!("http://{addr}/random"))
+        .header("If-None-Match", etag.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers().get("ETag").unwrap(), &etag);
+    assert!(response.bytes().await.unwrap().is_empty());
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_favicon_and_static_files() {
+    let static_dir = tempfile::tempdir().unwrap();
+    std::fs::write(static_dir.path().join("gallery.css"), "body {}").unwrap();
+    let mut config = Config::default();
+    config.server.static_dir = Some(static_dir.path().to_path_buf());
+    let TestState { addr, join_handle } = TestState::with_config(1, config).await;
+
+    let client = reqwest::Client::new();
+    let get = |path: &str| client.get(format!("http://{addr}{path}")).send();
+    // every body is read, so that the connection is reused by the next request
+    let response = get("/favicon.ico").await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "image/x-icon"
+    );
+    assert!(response.headers().contains_key("ETag"));
+    assert_eq!(
+        response.bytes().await.unwrap().as_ref(),
+        random_image_server::static_files::DEFAULT_FAVICON
+    );
+
+    let response = get("/static/gallery.css").await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(response.headers().get("Content-Type").unwrap(), "text/css");
+    assert_eq!(response.text().await.unwrap(), "body {}");
+
+    for path in ["/static/missing.css", "/static/../Cargo.toml"] {
+        let response = get(path).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND, "{path}");
+        assert!(!response.bytes().await.unwrap().is_empty());
+    }
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_collections() {
+    let cats = tempfile::tempdir().unwrap();
+    // a trailing byte sets the cat apart from the image of the server, since ids are hashes of content
+    let mut cat = std::fs::read("assets/blank.jpg").unwrap();
+    cat.push(0);
+    std::fs::write(cats.path().join("cat.jpg"), &cat).unwrap();
+    let mut config = Config::default();
+    config.collections.insert(
+        "cats".to_string(),
+        CollectionConfig {
+            sources: vec![ImageSource::Path(cats.path().canonicalize().unwrap()).into()],
+            cache: Some(CacheConfig {
+                backend: CacheBackendType::FileSystem,
+                ..CacheConfig::default()
+            }),
+        },
+    );
+    let TestState { addr, join_handle } = TestState::with_config(1, config).await;
+
+    let client = reqwest::Client::new();
+    let get = |path: &str| client.get(format!("http://{addr}{path}")).send();
+    // every body is read, so that the connection is reused by the next request
+    let list: serde_json::Value =
+        serde_json::from_slice(&get("/cats/list").await.unwrap().bytes().await.unwrap()).unwrap();
+    assert_eq!(list["total"], 1);
+    let url = list["images"][0]["url"].as_str().unwrap();
+    assert!(
+        url.starts_with(&format!("http://{addr}/cats/image/")),
+        "{url}"
+    );
+
+    let response = client.get(url).send().await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(response.bytes().await.unwrap().as_ref(), cat);
+
+    let response = get("/cats/random").await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "image/jpeg"
+    );
+    response.bytes().await.unwrap();
+
+    // the images of a collection are only served under its path
+    let id = url.rsplit('/').next().unwrap();
+    for path in [
+        format!("/image/{id}"),
+        "/cats/health".to_string(),
+        "/dogs/random".to_string(),
+    ] {
+        let response = get(&path).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND, "{path}");
+        response.bytes().await.unwrap();
+    }
+
+    let list: serde_json::Value =
+        serde_json::from_slice(&get("/list").await.unwrap().bytes().await.unwrap()).unwrap();
+    assert_eq!(list["total"], 1);
+    assert_ne!(list["images"][0]["id"], id);
+
+    // the collection is stored in the backend it configures, rather than the one of the server
+    for (path, backend) in [
+        ("/stats/cache", "InMemory"),
+        ("/cats/stats/cache", "FileSystem"),
+    ] {
+        let stats: serde_json::Value =
+            serde_json::from_slice(&get(path).await.unwrap().bytes().await.unwrap()).unwrap();
+        assert_eq!(stats["backend"], backend, "{path}");
+        assert_eq!(stats["images"], 1, "{path}");
+    }
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_placeholder() {
+    let mut config = Config::default();
+    config.placeholder.enabled = true;
+    config.placeholder.max_width = 1000;
+    let TestState { addr, join_handle } = TestState::with_config(1, config).await;
+
+    let client = reqwest::Client::new();
+    let get = |path: &str| client.get(format!("http://{addr}{path}")).send();
+    // every body is read, so that the connection is reused by the next request
+    let mut etags = Vec::new();
+    for _ in 0..2 {
+        let response = get("/placeholder/64x32?color=c00&to=00c").await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(response.headers().get("Content-Type").unwrap(), "image/png");
+        etags.push(response.headers().get("ETag").unwrap().clone());
+        let size = imagesize::blob_size(&response.bytes().await.unwrap()).unwrap();
+        assert_eq!((size.width, size.height), (64, 32));
+    }
+    // the second request is served from the placeholder cache
+    assert_eq!(etags[0], etags[1]);
+
+    for path in [
+        "/placeholder/64x0",
+        "/placeholder/1001x32",
+        "/placeholder/64",
+        "/placeholder/64x32?color=red",
+        "/placeholder/64x32?direction=diagonal",
+    ] {
+        let response = get(path).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST, "{path}");
+        response.bytes().await.unwrap();
+    }
+
+    // placeholders are kept apart from the configured images
+    let response = get("/list").await.unwrap();
+    let list: serde_json::Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(list["total"], 1);
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_placeholder_disabled() {
+    let TestState { addr, join_handle } = TestState::with_config(1, Config::default()).await;
+
+    let response = reqwest::get(format!("http://{addr}/placeholder/64x32"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_image_info() {
+    let TestState { addr, join_handle } = TestState::new(1).await;
+
+    let client = reqwest::Client::new();
+    let get = |path: &str| client.get(format!("http://{addr}{path}")).send();
+    let response = get("/list").await.unwrap();
+    let list: serde_json::Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    let entry = &list["images"][0];
+    let id = entry["id"].as_str().unwrap();
+
+    let response = get(&format!("/image/{id}/info")).await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let info: serde_json::Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(info["id"], id);
+    assert_eq!(info["url"], format!("http://{addr}/image/{id}"));
+    assert_eq!(info["content_type"], "image/jpeg");
+    assert_eq!(
+        (info["width"].clone(), info["height"].clone()),
+        (474.into(), 474.into())
+    );
+    assert_eq!(
+        info["size"],
+        std::fs::metadata("assets/blank.jpg").unwrap().len()
+    );
+    if cfg!(feature = "blurhash") {
+        // the blank image is a flat gray
+        assert_eq!(info["dominant_color"], "#b9b9b9");
+        assert_eq!(info["blurhash"].as_str().unwrap().len(), 28);
+    } else {
+        assert!(info.get("dominant_color").is_none());
+        assert!(info.get("blurhash").is_none());
+    }
+    // the list shows the same preview
+    assert_eq!(entry["dominant_color"], info["dominant_color"]);
+    assert_eq!(entry["blurhash"], info["blurhash"]);
+
+    let response = get("/image/unknown/info").await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+    response.bytes().await.unwrap();
+
+    // describing an image doesn't count it as served
+    let response = get("/stats/images").await.unwrap();
+    let stats: serde_json::Value =
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(stats["images"][0]["served"], 0);
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_content_addressed_id() {
+    // the same image, in two sources
+    let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+    let data = std::fs::read("assets/blank.jpg").unwrap();
+    for dir in &dirs {
+        std::fs::write(dir.path().join("blank.jpg"), &data).unwrap();
+    }
+    let mut config = Config::default();
+    config.server.sources = dirs
+        .iter()
+        .map(|dir| ImageSource::Path(dir.path().to_path_buf()).into())
+        .collect();
+    let content_id = config.cache.hash.digest(&data);
+    let TestState { addr, join_handle } =
+        TestState::with_server(1, ImageServer::with_config(config)).await;
+
+    let client = reqwest::Client::new();
+    let get = |path: String| client.get(format!("http://{addr}{path}")).send();
+    let response = get("/list".to_string()).await.unwrap();
+    let list: serde_json::Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    let images = list["images"].as_array().unwrap();
+    assert_eq!(images.len(), 2);
+    for image in images {
+        assert_eq!(image["id"], content_id);
+    }
+
+    let response = get(format!("/image/{content_id}")).await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Cache-Control").unwrap(),
+        "public, max-age=31536000, immutable"
+    );
+    assert_eq!(response.bytes().await.unwrap().as_ref(), data);
+
+    // the id derived from the path of an image still finds it, but isn't immutable
+    let key = CacheKey::ImagePath(dirs[0].path().join("blank.jpg").canonicalize().unwrap());
+    let response = get(format!("/image/{}", key.id())).await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert!(response.headers().get("Cache-Control").is_none());
+    assert_eq!(response.bytes().await.unwrap().as_ref(), data);
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[cfg(feature = "transforms")]
+#[rstest]
+#[timeout(Duration::from_secs(5))]
+#[tokio::test]
+async fn test_handle_request_caption() {
+    let mut config = Config::default();
+    config.server.sources = vec![random_image_server::config::SourceConfig {
+        caption: Some("Nothing to see here".to_string()),
+        ..ImageSource::Path(PathBuf::from("assets")).into()
+    }];
+    let TestState { addr, join_handle } =
+        TestState::with_server(1, ImageServer::with_config(config)).await;
+
+    let client = reqwest::Client::new();
+    let get = |path: &str| client.get(format!("http://{addr}{path}")).send();
+    // every body is read, so that the connection is reused by the next request
+    let original = std::fs::read("assets/blank.jpg").unwrap();
+    let original_size = imagesize::blob_size(&original).unwrap();
+    let mut etags = Vec::new();
+    for path in [
+        "/random?caption=Hello",
+        "/random?caption=Hello",
+        "/sequential?caption=Hello",
+        "/random",
+        "/random?caption=",
+    ] {
+        let response = get(path).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK, "{path}");
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "image/jpeg",
+            "{path}"
+        );
+        etags.push(response.headers().get("ETag").unwrap().clone());
+        let body = response.bytes().await.unwrap();
+        let size = imagesize::blob_size(&body).unwrap();
+        assert_eq!(size, original_size, "{path}");
+        if path.ends_with("caption=") {
+            // an empty caption serves the image without the caption of its source
+            assert_eq!(body, original);
+        } else {
+            assert_ne!(body, original, "{path}");
+        }
+    }
+    // captioned images are rendered once, then served from the variant cache
+    assert_eq!(etags[0], etags[1]);
+    assert_eq!(etags[0], etags[2]);
+    // the caption of the source is drawn unless the request sets its own
+    assert_ne!(etags[0], etags[3]);
+    assert_ne!(etags[3], etags[4]);
+
+    let response = get(&format!("/random?caption={}", "a".repeat(201)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+    response.bytes().await.unwrap();
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[cfg(feature = "transforms")]
+#[rstest]
+#[timeout(Duration::from_secs(5))]
+#[tokio::test]
+async fn test_handle_request_rotate_and_flip() {
+    // a landscape image, so rotating it swaps its sides
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("wide.png");
+    image::RgbImage::new(40, 20).save(&path).unwrap();
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(path.clone()).into()];
+    let TestState { addr, join_handle } =
+        TestState::with_server(1, ImageServer::with_config(config)).await;
+
+    let client = reqwest::Client::new();
+    let get = |path: &str| client.get(format!("http://{addr}{path}")).send();
+    let original = imagesize::blob_size(&std::fs::read(&path).unwrap()).unwrap();
+    for (path, rotated) in [
+        ("/random?rotate=90", true),
+        ("/sequential?rotate=270&flip=h", true),
+        ("/random?rotate=180&flip=v", false),
+        ("/random?flip=h", false),
+    ] {
+        let response = get(path).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK, "{path}");
+        let size = imagesize::blob_size(&response.bytes().await.unwrap()).unwrap();
+        if rotated {
+            assert_eq!((size.width, size.height), (original.height, original.width));
+        } else {
+            assert_eq!(size, original, "{path}");
+        }
+    }
+
+    for path in ["/random?rotate=45", "/sequential?flip=diagonal"] {
+        let response = get(path).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST, "{path}");
+        response.bytes().await.unwrap();
+    }
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[cfg(feature = "transforms")]
+#[rstest]
+#[timeout(Duration::from_secs(5))]
+#[tokio::test]
+async fn test_handle_request_filter() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("blue.png");
+    image::RgbImage::from_pixel(8, 8, image::Rgb([0x33, 0x66, 0x99]))
+        .save(&path)
+        .unwrap();
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(path).into()];
+    let TestState { addr, join_handle } =
+        TestState::with_server(1, ImageServer::with_config(config)).await;
+
+    let client = reqwest::Client::new();
+    let get = |path: &str| client.get(format!("http://{addr}{path}")).send();
+    let mut etags = Vec::new();
+    for path in [
+        "/random?filter=grayscale",
+        "/random?filter=sepia",
+        "/random?filter=blur:3",
+    ] {
+        let response = get(path).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK, "{path}");
+        etags.push(response.headers().get("ETag").unwrap().clone());
+        let body = response.bytes().await.unwrap();
+        let [r, g, b] = image::load_from_memory(&body)
+            .unwrap()
+            .into_rgb8()
+            .get_pixel(4, 4)
+            .0;
+        match path {
+            "/random?filter=grayscale" => assert!(r == g && g == b, "{path}"),
+            "/random?filter=sepia" => assert!(r > g && g > b, "{path}"),
+            // blurring a single color leaves it as it is
+            _ => assert_eq!([r, g, b], [0x33, 0x66, 0x99]),
+        }
+    }
+    // each filter is a variant of its own
+    assert_ne!(etags[0], etags[1]);
+    assert_ne!(etags[1], etags[2]);
+
+    for path in ["/random?filter=invert", "/random?filter=blur:51"] {
+        let response = get(path).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST, "{path}");
+        response.bytes().await.unwrap();
+    }
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[cfg(feature = "transforms")]
+#[rstest]
+#[timeout(Duration::from_secs(5))]
+#[tokio::test]
+async fn test_handle_request_quality() {
+    // a noisy photo, so that its quality makes a difference to its size
+    let dir = tempfile::tempdir().unwrap();
+    let mut seed = 1_u32;
+    let photo = image::RgbImage::from_fn(64, 64, |_, _| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        image::Rgb(seed.to_be_bytes()[..3].try_into().unwrap())
+    });
+    let mut jpeg = Vec::new();
+    photo
+        .write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(
+            &mut jpeg, 100,
+        ))
+        .unwrap();
+    std::fs::write(dir.path().join("photo.jpg"), &jpeg).unwrap();
+    photo.save(dir.path().join("icon.png")).unwrap();
+    let png = std::fs::read(dir.path().join("icon.png")).unwrap();
+
+    let mut config = Config::default();
+    config.server.sources = vec![ImageSource::Path(dir.path().to_path_buf()).into()];
+    config.transforms.quality = Some(10);
+    let TestState { addr, join_handle } =
+        TestState::with_server(1, ImageServer::with_config(config)).await;
+
+    let client = reqwest::Client::new();
+    let get = |path: &str| client.get(format!("http://{addr}{path}")).send();
+    let response = get("/list?sort=name").await.unwrap();
+    let list: serde_json::Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    let [icon, photo] =
+        [0, 1].map(|index| format!("/image/{}", list["images"][index]["id"].as_str().unwrap()));
+
+    // the configured quality applies to JPEGs, and the quality of other images is left as it is
+    let response = get(&icon).await.unwrap();
+    assert_eq!(response.headers().get("Content-Type").unwrap(), "image/png");
+    assert_eq!(response.bytes().await.unwrap(), png);
+    let response = get(&photo).await.unwrap();
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "image/jpeg"
+    );
+    let low = response.bytes().await.unwrap();
+    assert!(low.len() < jpeg.len());
+
+    // the quality of the request takes precedence
+    let response = get(&format!("{photo}?quality=95")).await.unwrap();
+    let high = response.bytes().await.unwrap();
+    assert!(low.len() < high.len());
+
+    let response = get(&format!("{photo}?quality=0")).await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+    response.bytes().await.unwrap();
+
+    drop(client);
+    join_handle.await.unwrap();
+}
+
+#[cfg(not(feature = "transforms"))]
+#[rstest]
+#[timeout(Duration::from_secs(2))]
+#[tokio::test]
+async fn test_handle_request_caption_without_feature() {
+    let TestState { addr, join_handle } = TestState::new(1).await;
+
+    let response = reqwest::get(format!("http://{addr}/random?caption=Hello"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+
+    join_handle.await.unwrap();
+}
+
+/// Serve `/image.jpg` over HTTP, counting the requests for it, `/redirect` redirecting to it from `localhost`,
+/// and a 2 MiB `/large.jpg`
+#[cfg(feature = "remote-sources")]
+async fn serve_upstream_image() -> (SocketAddr, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    use std::sync::{Arc, atomic::AtomicUsize, atomic::Ordering};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let counter = counter.clone();
+            let service = service_fn(move |req: hyper::Request<hyper::body::Incoming>| {
+                let counter = counter.clone();
+                async move {
+                    let (status, content_type, body) = match req.uri().path() {
+                        "/image.jpg" => {
+                            counter.fetch_add(1, Ordering::SeqCst);
+                            (
+                                200,
+                                "image/jpeg",
+                                std::fs::read("assets/blank.jpg").unwrap(),
+                            )
+                        }
+                        "/page.html" => (200, "text/html", b"<html></html>".to_vec()),
+                        "/large.jpg" => (200, "image/jpeg", vec![0; 2 * 1024 * 1024]),
+                        "/redirect" => (302, "text/plain", Vec::new()),
+                        _ => (404, "text/plain", b"Not Found".to_vec()),
+                    };
+                    let mut response = hyper::Response::builder()
+                        .status(status)
+                        .header("Content-Type", content_type);
+                    if status == 302 {
+                        let location = format!("http://localhost:{}/image.jpg", addr.port());
+                        response = response.header("Location", location);
+                    }
+                    response.body(http_body_util::Full::new(hyper::body::Bytes::from(body)))
+                }
+            });
+            tokio::spawn(async move {
+                let _ = auto::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    (addr, requests)
+}
+
+#[cfg(feature = "remote-sources")]
+#[rstest]
+#[timeout(Duration::from_secs(5))]
+#[tokio::test]
+async fn test_handle_request_proxy() {
+    let (upstream, requests) = serve_upstream_image().await;
+    let mut config = Config::default();
+    config.proxy.allowed_domains = vec!["127.0.0.1".to_string()];
+    config.server.max_file_size = Some(1024 * 1024);
+    let TestState { addr, join_handle } = TestState::with_config(1, config).await;
+
+    let client = reqwest::Client::new();
+    let proxy = |url: &str| {
+        client
+            .get(format!("http://{addr}/proxy"))
+            .query(&[("url", url)])
+            .send()
+    };
+    // every body is read, so that the connection is reused by the next request
+    for _ in 0..2 {
+        let response = proxy(&format!("http://{upstream}/image.jpg"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "image/jpeg"
+        );
+        assert_eq!(
+            response.bytes().await.unwrap().as_ref(),
+            std::fs::read("assets/blank.jpg").unwrap()
+        );
+    }
+    // the second request is served from the proxy cache
+    assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    for (url, status) in [
+        (
+            "https://example.com/image.jpg",
+            hyper::StatusCode::FORBIDDEN,
+        ),
+        ("not a url", hyper::StatusCode::BAD_REQUEST),
+        (
+            &format!("http://{upstream}/page.html"),
+            hyper::StatusCode::BAD_GATEWAY,
+        ),
+        (
+            &format!("http://{upstream}/missing.jpg"),
+            hyper::StatusCode::BAD_GATEWAY,
+        ),
+        // redirected off the allowed domains
+        (
+            &format!("http://{upstream}/redirect"),
+            hyper::StatusCode::BAD_GATEWAY,
+        ),
+        // larger than `server.max_file_size`
+        (
+            &format!("http://{upstream}/large.jpg"),
+            hyper::StatusCode::BAD_GATEWAY,
+        ),
+    ] {
+        let response = proxy(url).await.unwrap();
+        assert_eq!(response.status(), status, "{url}");
+        response.bytes().await.unwrap();
+    }
+
+    // proxied images are kept apart from the configured ones
+    let response = client
+        .get(format!("http://{addr}/list"))
+        .send()
+        .await
+        .unwrap();
+    let list: serde_json::Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!(list["total"], 1);
+
+    drop(client);
+    join_handle.await.unwrap();
+}
@@ -0,0 +1,92 @@
+#![cfg(feature = "sled")]
+
+use std::path::PathBuf;
+
+use pretty_assertions::assert_eq;
+use random_image_server::{
+    cache::{CacheBackend, CacheKey, CacheValue, SledCache, Validators},
+    config::Compression,
+};
+use url::Url;
+
+fn image(data: &[u8]) -> CacheValue {
+    CacheValue {
+        data: data.to_vec(),
+        content_type: "image/jpeg".to_string(),
+        validators: Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+        },
+    }
+}
+
+fn key(name: &str) -> CacheKey {
+    CacheKey::ImagePath(PathBuf::from(format!("/test/{name}.jpg")))
+}
+
+#[test]
+fn test_new_cache() {
+    let cache = SledCache::new();
+    assert_eq!(cache.backend_type(), "Sled");
+    assert_eq!(cache.size(), 0);
+    assert!(cache.is_empty());
+    assert_eq!(cache.get_random(), None);
+}
+
+#[test]
+fn test_set_get_and_remove() {
+    let cache = SledCache::new();
+    cache.set(key("a"), image(b"a")).unwrap();
+    cache.set(key("b"), image(b"bb")).unwrap();
+    cache.set(key("b"), image(b"bbb")).unwrap();
+
+    assert_eq!(cache.size(), 2);
+    assert_eq!(cache.keys().collect::<Vec<_>>(), [key("a"), key("b")]);
+    assert_eq!(cache.get(key("a")), Some(image(b"a")));
+    assert_eq!(cache.get(key("b")), Some(image(b"bbb")));
+    assert_eq!(cache.get(key("missing")), None);
+    assert_eq!(cache.validators(&key("a")), Some(image(b"a").validators));
+    assert_eq!(cache.bytes(), 4);
+    assert!(cache.get_random().is_some());
+
+    assert_eq!(cache.remove(&key("a")), Some(image(b"a")));
+    assert_eq!(cache.remove(&key("a")), None);
+    assert_eq!(cache.get(key("a")), None);
+    assert_eq!(cache.size(), 1);
+
+    cache.clear().unwrap();
+    assert!(cache.is_empty());
+    assert_eq!(cache.get(key("b")), None);
+}
+
+#[test]
+fn test_reopen_keeps_only_url_images() {
+    let directory = tempfile::TempDir::new().unwrap();
+    let path = directory.path().join("images.sled");
+    let url_key = CacheKey::ImageUrl(Url::parse("https://example.com/image.jpg").unwrap());
+
+    let cache = SledCache::with_path(&path).unwrap();
+    cache.set(url_key.clone(), image(b"url")).unwrap();
+    cache.set(key("a"), image(b"a")).unwrap();
+    drop(cache);
+
+    // images fetched from URLs can be loaded again after a restart, but aren't in the cache
+    let cache = SledCache::with_path(&path).unwrap();
+    assert!(cache.is_empty());
+    assert_eq!(cache.get(url_key.clone()), None);
+    assert_eq!(cache.spooled(&url_key), Some(image(b"url")));
+    assert_eq!(cache.spooled(&key("a")), None);
+}
+
+#[test]
+fn test_compression() {
+    let cache = SledCache::new().with_compression(Compression::Zstd);
+    let value = CacheValue {
+        data: vec![0; 4096],
+        ..image(b"")
+    };
+    cache.set(key("a"), value.clone()).unwrap();
+
+    assert!(cache.bytes() < 4096);
+    assert_eq!(cache.get(key("a")), Some(value));
+}
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use pretty_assertions::assert_eq;
+use random_image_server::cache::{
+    CacheBackend, CacheKey, CacheValue, FileSystemCache, InMemoryCache, Validators,
+};
+use url::Url;
+
+fn images() -> Vec<(CacheKey, CacheValue)> {
+    vec![
+        (
+            CacheKey::ImagePath(PathBuf::from("/test/image.jpg")),
+            CacheValue {
+                data: vec![1, 2, 3, 4],
+                content_type: "image/jpeg".to_string(),
+                validators: Validators::default(),
+            },
+        ),
+        (
+            CacheKey::ImageUrl(Url::parse("https://example.com/image.png").unwrap()),
+            CacheValue {
+                data: vec![5, 6, 7],
+                content_type: "image/png".to_string(),
+                validators: Validators {
+                    etag: Some("\"v1\"".to_string()),
+                    last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+                },
+            },
+        ),
+    ]
+}
+
+#[test]
+fn test_export_import_roundtrip() {
+    let directory = tempfile::TempDir::new().unwrap();
+    let archive = directory.path().join("cache.tar");
+
+    let cache = InMemoryCache::new();
+    for (key, image) in images() {
+        cache.set(key, image).unwrap();
+    }
+    assert_eq!(cache.export(&archive).unwrap(), 2);
+
+    // snapshots can be imported into any backend
+    let imported = FileSystemCache::new();
+    assert_eq!(imported.import(&archive).unwrap(), 2);
+    assert_eq!(imported.size(), 2);
+    for (key, image) in images() {
+        assert_eq!(imported.get(key), Some(image));
+    }
+}
+
+#[test]
+fn test_export_empty_cache() {
+    let directory = tempfile::TempDir::new().unwrap();
+    let archive = directory.path().join("cache.tar");
+
+    assert_eq!(InMemoryCache::new().export(&archive).unwrap(), 0);
+    let imported = InMemoryCache::new();
+    assert_eq!(imported.import(&archive).unwrap(), 0);
+    assert!(imported.is_empty());
+}
+
+#[test]
+fn test_import_invalid_snapshot() {
+    let directory = tempfile::TempDir::new().unwrap();
+    let archive = directory.path().join("cache.tar");
+    std::fs::write(&archive, b"not a snapshot").unwrap();
+
+    assert!(InMemoryCache::new().import(&archive).is_err());
+    assert!(
+        InMemoryCache::new()
+            .import(&directory.path().join("missing.tar"))
+            .is_err()
+    );
+}
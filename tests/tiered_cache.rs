@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+
+use pretty_assertions::assert_eq;
+use random_image_server::cache::{
+    CacheBackend, CacheKey, CacheValue, FileSystemCache, TieredCache, Validators,
+};
+
+fn image(data: &[u8]) -> CacheValue {
+    CacheValue {
+        data: data.to_vec(),
+        content_type: "image/jpeg".to_string(),
+        validators: Validators::default(),
+    }
+}
+
+fn key(name: &str) -> CacheKey {
+    CacheKey::ImagePath(PathBuf::from(format!("/test/{name}.jpg")))
+}
+
+#[test]
+fn test_new_cache() {
+    let cache = TieredCache::new();
+    assert_eq!(cache.backend_type(), "Tiered");
+    assert_eq!(cache.size(), 0);
+    assert!(cache.is_empty());
+    assert_eq!(cache.hot_size(), 0);
+}
+
+#[test]
+fn test_set_and_get() {
+    let cache = TieredCache::new();
+    cache.set(key("a"), image(b"a")).unwrap();
+
+    // images are only kept in memory once they are served
+    assert_eq!(cache.size(), 1);
+    assert_eq!(cache.hot_size(), 0);
+    assert_eq!(cache.get(key("a")), Some(image(b"a")));
+    assert_eq!(cache.hot_size(), 1);
+    assert_eq!(cache.get(key("a")), Some(image(b"a")));
+    assert_eq!(cache.get(key("missing")), None);
+    assert_eq!(cache.bytes(), 1);
+}
+
+#[test]
+fn test_least_recently_served_are_evicted_from_memory() {
+    let cache = TieredCache::with_disk(FileSystemCache::new(), 2);
+    for name in ["a", "b", "c"] {
+        cache.set(key(name), image(name.as_bytes())).unwrap();
+    }
+
+    cache.get(key("a"));
+    cache.get(key("b"));
+    cache.get(key("a"));
+    cache.get(key("c"));
+
+    // "b" was served least recently, so only "a" and "c" are in memory
+    assert_eq!(cache.hot_size(), 2);
+    assert_eq!(cache.size(), 3);
+    // every image can still be served from disk
+    assert_eq!(cache.get(key("b")), Some(image(b"b")));
+}
+
+#[test]
+fn test_set_replaces_hot_image() {
+    let cache = TieredCache::new();
+    cache.set(key("a"), image(b"old")).unwrap();
+    cache.get(key("a"));
+
+    cache.set(key("a"), image(b"new")).unwrap();
+    assert_eq!(cache.get(key("a")), Some(image(b"new")));
+}
+
+#[test]
+fn test_remove() {
+    let cache = TieredCache::new();
+    cache.set(key("a"), image(b"a")).unwrap();
+    cache.get(key("a"));
+
+    cache.remove(&key("a"));
+    assert_eq!(cache.get(key("a")), None);
+    assert_eq!(cache.hot_size(), 0);
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_no_hot_images() {
+    let cache = TieredCache::with_disk(FileSystemCache::new(), 0);
+    cache.set(key("a"), image(b"a")).unwrap();
+    assert_eq!(cache.get(key("a")), Some(image(b"a")));
+    assert_eq!(cache.hot_size(), 0);
+}
+
+#[test]
+fn test_get_random() {
+    let cache = TieredCache::new();
+    assert_eq!(cache.get_random(), None);
+    cache.set(key("a"), image(b"a")).unwrap();
+    assert_eq!(cache.get_random(), Some((key("a"), image(b"a"))));
+}
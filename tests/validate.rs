@@ -0,0 +1,299 @@
+use std::{io::Write, path::PathBuf};
+
+use pretty_assertions::assert_eq;
+use random_image_server::{
+    config::{Config, HashedBasicAuth, ImageSource, ServerConfig},
+    validate::{SourceReport, validate_config},
+};
+use url::Url;
+
+fn config(sources: Vec<ImageSource>) -> Config {
+    Config {
+        server: ServerConfig {
+            sources: sources.into_iter().map(Into::into).collect(),
+            ..ServerConfig::default()
+        },
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn test_validate_config_valid() {
+    let report = validate_config(&config(vec![
+        ImageSource::Path(PathBuf::from("assets")),
+        ImageSource::Path(PathBuf::from("assets/blank.jpg")),
+    ]))
+    .await;
+
+    assert!(report.is_valid());
+    assert_eq!(report.images(), 2);
+    assert!(
+        report
+            .to_string()
+            .ends_with("2 image(s) would be served from 2 source(s): valid")
+    );
+}
+
+#[tokio::test]
+async fn test_validate_config_no_sources() {
+    let report = validate_config(&config(vec![])).await;
+
+    assert!(!report.is_valid());
+    assert_eq!(
+        report.errors,
+        vec!["No image sources configured".to_string()]
+    );
+}
+
+#[tokio::test]
+async fn test_validate_config_file_size_limits() {
+    let mut config = config(vec![ImageSource::Path(PathBuf::from("assets/blank.jpg"))]);
+    config.server.min_file_size = Some(u64::MAX);
+    config.server.max_file_size = Some(1);
+    let report = validate_config(&config).await;
+
+    assert!(!report.is_valid());
+    assert_eq!(report.errors.len(), 1);
+    assert_eq!(
+        report.sources[0].error.as_deref(),
+        Some("Image file size of 42762 bytes is outside the configured limits")
+    );
+}
+
+#[tokio::test]
+async fn test_validate_config_signing_without_secret() {
+    let mut config = config(vec![ImageSource::Path(PathBuf::from("assets/blank.jpg"))]);
+    config.signing.required = true;
+    let report = validate_config(&config).await;
+
+    assert!(!report.is_valid());
+    assert_eq!(
+        report.errors,
+        vec!["signing.required is set without a signing.secret, no images would be served"]
+    );
+}
+
+#[tokio::test]
+async fn test_validate_config_invalid_password_hash() {
+    let mut config = config(vec![ImageSource::Path(PathBuf::from("assets/blank.jpg"))]);
+    config.server.auth.basic = Some(HashedBasicAuth {
+        username: "admin".to_string(),
+        password_hash: "secret".to_string(),
+    });
+    let report = validate_config(&config).await;
+
+    assert!(!report.is_valid());
+    assert_eq!(report.errors.len(), 1);
+    assert!(report.errors[0].starts_with("Invalid server.auth.basic.password_hash"));
+}
+
+#[tokio::test]
+async fn test_validate_config_offline() {
+    let url = Url::parse("http://127.0.0.1:9/image.jpg").unwrap();
+    let mut config = config(vec![
+        ImageSource::Url(url.clone()),
+        ImageSource::Path(PathBuf::from("assets/blank.jpg")),
+    ]);
+    config.server.offline = true;
+    let report = validate_config(&config).await;
+
+    assert!(report.is_valid());
+    assert_eq!(report.images(), 1);
+    assert_eq!(
+        report.sources[0],
+        SourceReport {
+            source: url.to_string(),
+            images: 0,
+            error: None,
+            skipped: true,
+        }
+    );
+    assert!(
+        report
+            .to_string()
+            .starts_with(&format!("SKIP {url}: offline mode\n"))
+    );
+
+    config.server.sources.pop();
+    let report = validate_config(&config).await;
+    assert!(!report.is_valid());
+    assert_eq!(
+        report.errors,
+        vec!["Offline mode is enabled, but only remote sources are configured".to_string()]
+    );
+}
+
+#[tokio::test]
+async fn test_validate_config_archive() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("images.zip");
+    let mut writer = zip::ZipWriter::new(std::fs::File::create(&archive_path).unwrap());
+    for name in ["a.jpg", "b.png", "notes.txt"] {
+        writer
+            .start_file(name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"image").unwrap();
+    }
+    writer.finish().unwrap();
+    let empty_path = temp_dir.path().join("empty.tar");
+    std::fs::write(
+        &empty_path,
+        tar::Builder::new(Vec::new()).into_inner().unwrap(),
+    )
+    .unwrap();
+
+    let report = validate_config(&config(vec![
+        ImageSource::Path(archive_path),
+        ImageSource::Path(empty_path),
+    ]))
+    .await;
+
+    assert_eq!(report.sources[0].images, 2);
+    assert_eq!(report.sources[0].error, None);
+    assert_eq!(
+        report.sources[1].error.as_deref(),
+        Some("No images found in archive")
+    );
+}
+
+#[tokio::test]
+async fn test_validate_config_invalid_sources() {
+    let report = validate_config(&config(vec![
+        ImageSource::Path(PathBuf::from("assets")),
+        ImageSource::Path(PathBuf::from("Cargo.toml")),
+        ImageSource::Path(PathBuf::from("src")),
+        ImageSource::Path(PathBuf::from("nonexistent")),
+    ]))
+    .await;
+
+    assert!(!report.is_valid());
+    assert_eq!(report.images(), 1);
+    assert_eq!(
+        report.sources[1],
+        SourceReport {
+            source: "Cargo.toml".to_string(),
+            images: 0,
+            error: Some("Unsupported image file extension".to_string()),
+            skipped: false,
+        }
+    );
+    assert_eq!(
+        report.sources[2].error.as_deref(),
+        Some("No images found in directory")
+    );
+    assert!(report.sources[3].error.is_some());
+}
+
+#[cfg(not(feature = "remote-sources"))]
+#[tokio::test]
+async fn test_validate_config_remote_sources_disabled() {
+    let url = Url::parse("http://127.0.0.1:9/image.jpg").unwrap();
+    let report = validate_config(&config(vec![ImageSource::Url(url)])).await;
+
+    assert!(!report.is_valid());
+    assert_eq!(
+        report.sources[0].error.as_deref(),
+        Some(random_image_server::REMOTE_SOURCES_DISABLED)
+    );
+}
+
+#[cfg(feature = "remote-sources")]
+mod check_url {
+    use std::collections::BTreeMap;
+
+    use http_body_util::Full;
+    use hyper::{Response, body::Bytes, service::service_fn};
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto,
+    };
+    use random_image_server::{ALLOWED_IMAGE_EXTENSIONS, validate::check_url};
+    use tokio::net::TcpListener;
+    use url::Url;
+
+    /// Serve a single request, responding with the given content type
+    ///
+    /// If `authorization` is set, requests without that `Authorization` header are rejected.
+    async fn serve_once(content_type: &'static str, authorization: Option<&'static str>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(move |req: hyper::Request<hyper::body::Incoming>| {
+                let authorized = authorization.is_none_or(|expected| {
+                    req.headers()
+                        .get("Authorization")
+                        .is_some_and(|value| value == expected)
+                });
+                async move {
+                    Response::builder()
+                        .status(if authorized { 200 } else { 401 })
+                        .header("Content-Type", content_type)
+                        .body(Full::new(Bytes::new()))
+                }
+            });
+            let _ = auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+
+        Url::parse(&format!("http://{addr}/image.jpg")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_check_url() {
+        let url = serve_once("image/jpeg", None).await;
+        assert!(
+            check_url(
+                &reqwest::Client::new(),
+                &url,
+                &BTreeMap::new(),
+                ALLOWED_IMAGE_EXTENSIONS
+            )
+            .await
+            .is_ok()
+        );
+
+        let url = serve_once("text/html", None).await;
+        assert!(
+            check_url(
+                &reqwest::Client::new(),
+                &url,
+                &BTreeMap::new(),
+                ALLOWED_IMAGE_EXTENSIONS
+            )
+            .await
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_url_headers() {
+        let headers = BTreeMap::from([("Authorization".to_string(), "Bearer token".to_string())]);
+
+        let url = serve_once("image/jpeg", Some("Bearer token")).await;
+        assert!(
+            check_url(
+                &reqwest::Client::new(),
+                &url,
+                &headers,
+                ALLOWED_IMAGE_EXTENSIONS
+            )
+            .await
+            .is_ok()
+        );
+
+        let url = serve_once("image/jpeg", Some("Bearer token")).await;
+        assert!(
+            check_url(
+                &reqwest::Client::new(),
+                &url,
+                &BTreeMap::new(),
+                ALLOWED_IMAGE_EXTENSIONS
+            )
+            .await
+            .is_err()
+        );
+    }
+}